@@ -0,0 +1,190 @@
+//! Measures streaming summaries made as fast as the summary task can make them to a client
+//! over gRPC, one a message by `WatchSummary` and in batches by `WatchSummaryBatches`: the
+//! summaries received, the messages they came in and the CPU time the process took.
+//!
+//! ```sh
+//! cargo bench --bench batching
+//! ```
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt};
+use orderbook_agg::{
+    backfill::Backfill,
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, Level, WatchSummaryRequest,
+    },
+    core::order_book::BookLevels,
+    service::{spawn_summary_with_feeds, OrderbookSummary, SummaryFeeds},
+    Exchange, Symbol,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+
+const SUMMARIES: u64 = 20_000;
+const LEVELS: usize = 10;
+
+fn book_levels(id: u64) -> Arc<BookLevels> {
+    let offset = (id % 50) as f64;
+    let level = |price: f64| Level {
+        exchange: Exchange::BITSTAMP.to_string(),
+        price,
+        quantity: 1.0,
+        updated_at: None,
+        market_type: Exchange::BITSTAMP.market_type().into(),
+    };
+    Arc::new(BookLevels {
+        exchange: Exchange::BITSTAMP,
+        symbol: Symbol::BTCUSDT,
+        last_update_id: id,
+        bids: (0..LEVELS)
+            .map(|i| level(29950.0 + offset - i as f64))
+            .collect(),
+        asks: (0..LEVELS)
+            .map(|i| level(30050.0 + offset + i as f64))
+            .collect(),
+        depth: None,
+        restored: false,
+    })
+}
+
+/// The user and system CPU time the process has taken, from /proc, so linux only
+fn cpu_time() -> Duration {
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    // The command is in parentheses and may hold spaces, so fields are counted after it.
+    let fields = stat
+        .rsplit_once(')')
+        .map_or("", |(_, fields)| fields)
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let ticks = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    // utime and stime, in ticks of 10ms.
+    Duration::from_millis((ticks(11).unwrap_or(0) + ticks(12).unwrap_or(0)) * 10)
+}
+
+/// Serves the summaries of the levels sent on the returned sender, at the returned url.
+async fn serve() -> (mpsc::Sender<Arc<BookLevels>>, String) {
+    let (tx_levels, rx_levels) = mpsc::channel(100);
+    let (tx_closed, rx_closed) = mpsc::channel(10);
+    let backfill = Backfill::default();
+    let tx_summary = spawn_summary_with_feeds(
+        Symbol::BTCUSDT,
+        vec![Exchange::BITSTAMP],
+        rx_levels,
+        rx_closed,
+        watch::channel(true).0,
+        SummaryFeeds {
+            backfill: backfill.clone(),
+            ..Default::default()
+        },
+    );
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+        .with_exchanges(vec![Exchange::BITSTAMP])
+        .with_backfill(backfill);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let _open = (tx_closed, tx_shutdown);
+        let incoming =
+            tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tonic::transport::Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .serve_with_incoming(incoming)
+            .await
+            .unwrap();
+    });
+    (tx_levels, url)
+}
+
+struct Run {
+    elapsed: Duration,
+    cpu: Duration,
+    summaries: usize,
+    messages: usize,
+}
+
+/// Streams every summary made from [SUMMARIES] levels, in batches of at most `max_batch`
+/// when set.
+async fn run(max_batch: Option<u32>) -> Run {
+    let (tx_levels, url) = serve().await;
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let request = WatchSummaryRequest {
+        max_batch: max_batch.unwrap_or(0),
+        ..Default::default()
+    };
+    // The sequence numbers of the summaries in each message.
+    let mut messages: Pin<Box<dyn Stream<Item = Vec<u64>> + Send>> = match max_batch {
+        None => Box::pin(
+            client
+                .watch_summary(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|summary| vec![summary.unwrap().sequence]),
+        ),
+        Some(_) => Box::pin(
+            client
+                .watch_summary_batches(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|batch| {
+                    let batch = batch.unwrap();
+                    batch.summaries.iter().map(|s| s.sequence).collect()
+                }),
+        ),
+    };
+
+    let cpu = cpu_time();
+    let start = Instant::now();
+    let feed = tokio::spawn(async move {
+        for id in 1..=SUMMARIES {
+            tx_levels.send(book_levels(id)).await.unwrap();
+        }
+        tx_levels
+    });
+    let (mut summaries, mut count) = (0, 0);
+    while let Some(sequences) = messages.next().await {
+        count += 1;
+        summaries += sequences.len();
+        if sequences.last().is_some_and(|last| *last >= SUMMARIES) {
+            break;
+        }
+    }
+    let run = Run {
+        elapsed: start.elapsed(),
+        cpu: cpu_time().saturating_sub(cpu),
+        summaries,
+        messages: count,
+    };
+    drop(feed.await.unwrap());
+    run
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    println!("{} summaries of {} levels a side", SUMMARIES, LEVELS);
+    for max_batch in [None, Some(10), Some(100), Some(1000)] {
+        let run = run(max_batch).await;
+        let mode = match max_batch {
+            None => "WatchSummary".to_string(),
+            Some(max_batch) => format!("WatchSummaryBatches {}", max_batch),
+        };
+        println!(
+            "{:<24} {:>8.2?}  {:>6} summaries in {:>6} messages  {:>8.0} summaries/s  cpu {:>8.2?}",
+            mode,
+            run.elapsed,
+            run.summaries,
+            run.messages,
+            run.summaries as f64 / run.elapsed.as_secs_f64(),
+            run.cpu
+        );
+    }
+}
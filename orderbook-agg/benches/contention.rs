@@ -0,0 +1,125 @@
+//! Measures how long it takes to apply a stream of updates to an order book while readers
+//! repeatedly take the top levels, with the book behind a [Mutex] and behind a [RwLock].
+//!
+//! ```sh
+//! cargo bench --bench contention
+//! ```
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use orderbook_agg::{
+    core::order_book::OrderBook, exchanges::bitstamp::data::Snapshot, Exchange, Symbol,
+};
+use rust_decimal::Decimal;
+use tokio::sync::{Mutex, RwLock};
+
+const UPDATES: u64 = 20_000;
+const LEVELS: u32 = 15;
+
+fn orderbook() -> OrderBook {
+    let mut orderbook = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 29000, 31000, 0, 8);
+    let mut snapshot = Snapshot {
+        last_update_id: 1,
+        bids: (29000..30000)
+            .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+            .collect(),
+        asks: (30001..31000)
+            .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+            .collect(),
+    };
+    orderbook.update(&mut snapshot).unwrap();
+    orderbook
+}
+
+fn update(id: u64) -> Snapshot {
+    let offset = (id % 500) as i64;
+    Snapshot {
+        last_update_id: id,
+        bids: vec![[
+            Decimal::new(29500 + offset, 0),
+            Decimal::new(id as i64 % 7, 0),
+        ]],
+        asks: vec![[
+            Decimal::new(30500 - offset, 0),
+            Decimal::new(id as i64 % 5, 0),
+        ]],
+    }
+}
+
+/// The two ways the order book can be shared between the update loop and readers.
+#[derive(Clone)]
+enum Shared {
+    Mutex(Arc<Mutex<OrderBook>>),
+    RwLock(Arc<RwLock<OrderBook>>),
+}
+
+impl Shared {
+    async fn read_levels(&self) {
+        let levels = match self {
+            Shared::Mutex(ob) => ob.lock().await.get_book_levels(LEVELS),
+            Shared::RwLock(ob) => ob.read().await.get_book_levels(LEVELS),
+        };
+        assert!(levels.is_some());
+    }
+
+    async fn apply(&self, mut update: Snapshot) {
+        match self {
+            Shared::Mutex(ob) => ob.lock().await.update(&mut update).unwrap(),
+            Shared::RwLock(ob) => ob.write().await.update(&mut update).unwrap(),
+        };
+    }
+}
+
+/// Returns the time taken to apply all the updates and the number of reads made meanwhile.
+async fn run(shared: Shared, readers: usize) -> (Duration, u64) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handles = (0..readers)
+        .map(|_| {
+            let shared = shared.clone();
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let mut reads = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    shared.read_levels().await;
+                    reads += 1;
+                    tokio::task::yield_now().await;
+                }
+                reads
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    for id in 2..UPDATES + 2 {
+        shared.apply(update(id)).await;
+        tokio::task::yield_now().await;
+    }
+    let elapsed = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    let mut reads = 0;
+    for handle in handles {
+        reads += handle.await.unwrap();
+    }
+    (elapsed, reads)
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    println!("{} updates, {} levels per read", UPDATES, LEVELS);
+    for readers in [0, 4, 16] {
+        let (mutex_elapsed, mutex_reads) =
+            run(Shared::Mutex(Arc::new(Mutex::new(orderbook()))), readers).await;
+        let (rwlock_elapsed, rwlock_reads) =
+            run(Shared::RwLock(Arc::new(RwLock::new(orderbook()))), readers).await;
+        println!(
+            "{:>2} readers  mutex: {:>8.2?} ({:>7} reads)  rwlock: {:>8.2?} ({:>7} reads)",
+            readers, mutex_elapsed, mutex_reads, rwlock_elapsed, rwlock_reads
+        );
+    }
+}
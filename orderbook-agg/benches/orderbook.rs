@@ -0,0 +1,178 @@
+//! Criterion benchmarks for the order book hot paths. Updates come from a deterministic
+//! generator shaped like the Binance and Bitstamp depth streams, and the snapshot from the
+//! committed fixture, so results are reproducible and no network access is needed.
+//!
+//! ```sh
+//! cargo bench --bench orderbook
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use orderbook_agg::{
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        order_book::{OrderBook, Update},
+    },
+    exchanges::{binance, bitstamp},
+    make_summary, Exchange, Symbol,
+};
+
+const SNAPSHOT: &[u8] = include_bytes!("../../tests/fixtures/snapshot_bitstamp.json");
+const PRICE_MIN: u64 = 29301;
+const PRICE_MAX: u64 = 32304;
+const BURST: u64 = 10_000;
+
+fn snapshot() -> bitstamp::data::Snapshot {
+    serde_json::from_slice(SNAPSHOT).unwrap()
+}
+
+fn empty_book(exchange: Exchange) -> OrderBook {
+    match exchange {
+        Exchange::BINANCE => OrderBook::new(
+            exchange,
+            Symbol::BTCUSDT,
+            PRICE_MIN * 100,
+            PRICE_MAX * 100,
+            2,
+            8,
+        ),
+        _ => OrderBook::new(exchange, Symbol::BTCUSDT, PRICE_MIN, PRICE_MAX, 0, 8),
+    }
+}
+
+fn seeded_book(exchange: Exchange) -> OrderBook {
+    let mut orderbook = empty_book(exchange);
+    let mut snapshot = snapshot();
+    if exchange == Exchange::BINANCE {
+        // Binance sends prices with 8 decimals where Bitstamp sends whole numbers.
+        for level in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+            level[0].rescale(8);
+        }
+    }
+    orderbook.update(&mut snapshot).unwrap();
+    orderbook
+}
+
+/// Generates a burst of raw depth update messages alternating between Binance and
+/// Bitstamp. Prices random walk around the top of the snapshot with a quarter of the
+/// levels removed, like the live streams.
+fn burst(count: u64) -> Vec<(Exchange, Vec<u8>)> {
+    let mut seed: u64 = 7;
+    let mut next = move |max: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % max
+    };
+    let last_update_id = snapshot().last_update_id;
+    (0..count)
+        .map(|i| {
+            let bid = 30700 + next(67);
+            let ask = 30769 + next(67);
+            let quantity = |n: u64| {
+                if n.is_multiple_of(4) {
+                    "0.00000000".to_string()
+                } else {
+                    format!("0.{:08}", n * 1_234_567 % 100_000_000)
+                }
+            };
+            let (bid_qty, ask_qty) = (quantity(next(100)), quantity(next(100)));
+            if i % 2 == 0 {
+                let id = last_update_id + 1 + i / 2;
+                let message = serde_json::json!({
+                    "e": "depthUpdate",
+                    "E": 1688515101262u64 + i,
+                    "s": "BTCUSDT",
+                    "U": id,
+                    "u": id,
+                    "b": [[format!("{}.{:02}", bid, next(100)), bid_qty]],
+                    "a": [[format!("{}.{:02}", ask, next(100)), ask_qty]],
+                });
+                (Exchange::BINANCE, message.to_string().into_bytes())
+            } else {
+                let message = serde_json::json!({
+                    "data": {
+                        "timestamp": "1688515101",
+                        "microtimestamp": (last_update_id + i).to_string(),
+                        "bids": [[bid.to_string(), bid_qty]],
+                        "asks": [[ask.to_string(), ask_qty]],
+                    },
+                    "channel": "diff_order_book_btcusdt",
+                    "event": "data",
+                });
+                (Exchange::BITSTAMP, message.to_string().into_bytes())
+            }
+        })
+        .collect()
+}
+
+fn apply<U: FromMessage + Update + std::fmt::Debug>(orderbook: &mut OrderBook, data: &[u8]) {
+    if let StreamMessage::Update(mut update) = U::from_message(data).unwrap() {
+        orderbook.update(&mut update).unwrap();
+        black_box(orderbook.cached_book_levels(15));
+    }
+}
+
+fn bench_updates(c: &mut Criterion) {
+    let messages = burst(BURST);
+    c.bench_function("apply 10k binance and bitstamp updates", |b| {
+        b.iter_batched_ref(
+            || {
+                (
+                    seeded_book(Exchange::BINANCE),
+                    seeded_book(Exchange::BITSTAMP),
+                )
+            },
+            |(binance_book, bitstamp_book)| {
+                for (exchange, data) in messages.iter() {
+                    match exchange {
+                        Exchange::BINANCE => apply::<binance::data::BookUpdate>(binance_book, data),
+                        _ => apply::<bitstamp::data::BookUpdate>(bitstamp_book, data),
+                    }
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_levels(c: &mut Criterion) {
+    let orderbook = seeded_book(Exchange::BITSTAMP);
+    let mut group = c.benchmark_group("book levels");
+    for levels in [10, 50, 500] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &levels,
+            |b, &levels| b.iter(|| orderbook.get_book_levels(black_box(levels))),
+        );
+    }
+    group.finish();
+}
+
+fn bench_snapshot(c: &mut Criterion) {
+    c.bench_function("apply bitstamp snapshot", |b| {
+        b.iter_batched_ref(
+            || (empty_book(Exchange::BITSTAMP), snapshot()),
+            |(orderbook, snapshot)| orderbook.update(snapshot).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let book_levels = [
+        seeded_book(Exchange::BINANCE).get_book_levels(15).unwrap(),
+        seeded_book(Exchange::BITSTAMP).get_book_levels(15).unwrap(),
+    ];
+    c.bench_function("merge 15 levels from two exchanges", |b| {
+        let book_levels = book_levels.iter().collect::<Vec<_>>();
+        b.iter(|| make_summary(&book_levels, Symbol::BTCUSDT).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_updates,
+    bench_levels,
+    bench_snapshot,
+    bench_merge
+);
+criterion_main!(benches);
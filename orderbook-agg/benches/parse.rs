@@ -0,0 +1,118 @@
+//! Compares parsing websocket frames into updates with [FromMessage], which deserializes
+//! the levels straight from borrowed strings, against going through a [Value] first.
+//! The corpus is the committed update fixtures plus frames generated in the same shape
+//! with as many levels as the depth streams send during volatile markets.
+//!
+//! ```sh
+//! cargo bench --bench parse
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use orderbook_agg::{
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        num_types::{str_vec_to_num_vec, DisplayAmount},
+    },
+    exchanges::{binance, bitstamp},
+};
+use serde_json::Value;
+
+const BINANCE_UPDATE: &[u8] = include_bytes!("../../tests/fixtures/update_binance.json");
+const BITSTAMP_UPDATE: &[u8] = include_bytes!("../../tests/fixtures/update_bitstamp.json");
+
+/// Frames with `levels` bids and asks each, starting with the fixture for the exchange.
+fn corpus(levels: u64) -> Vec<(&'static str, Vec<Vec<u8>>)> {
+    let pairs = |start: u64, decimals: bool| {
+        (0..levels)
+            .map(|i| {
+                let price = if decimals {
+                    format!("{}.{:08}", start + i, i * 1_234_567 % 100_000_000)
+                } else {
+                    (start + i).to_string()
+                };
+                [price, format!("0.{:08}", (i + 1) * 7_654_321 % 100_000_000)]
+            })
+            .collect::<Vec<_>>()
+    };
+    let binance = (0..100u64)
+        .map(|id| {
+            serde_json::json!({
+                "e": "depthUpdate",
+                "E": 1688515101262u64 + id,
+                "s": "BTCUSDT",
+                "U": 157 + id,
+                "u": 157 + id,
+                "b": pairs(30700 - levels, true),
+                "a": pairs(30770, true),
+            })
+            .to_string()
+            .into_bytes()
+        })
+        .chain(std::iter::once(BINANCE_UPDATE.to_vec()))
+        .collect();
+    let bitstamp = (0..100u64)
+        .map(|id| {
+            serde_json::json!({
+                "data": {
+                    "timestamp": "1688515101",
+                    "microtimestamp": (1688515101262376 + id).to_string(),
+                    "bids": pairs(30700 - levels, false),
+                    "asks": pairs(30770, false),
+                },
+                "channel": "diff_order_book_btcusdt",
+                "event": "data",
+            })
+            .to_string()
+            .into_bytes()
+        })
+        .chain(std::iter::once(BITSTAMP_UPDATE.to_vec()))
+        .collect();
+    vec![("binance", binance), ("bitstamp", bitstamp)]
+}
+
+fn parse<U: FromMessage>(frames: &[Vec<u8>]) {
+    for frame in frames {
+        match U::from_message(frame).unwrap() {
+            StreamMessage::Update(update) => {
+                black_box(update);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Parses each frame into a [Value] and picks the levels out of it.
+fn parse_value(frames: &[Vec<u8>], exchange: &str) {
+    for frame in frames {
+        let value = serde_json::from_slice::<Value>(frame).unwrap();
+        let (bids, asks) = match exchange {
+            "binance" => (&value["b"], &value["a"]),
+            _ => (&value["data"]["bids"], &value["data"]["asks"]),
+        };
+        black_box(str_vec_to_num_vec::<DisplayAmount>(bids).unwrap());
+        black_box(str_vec_to_num_vec::<DisplayAmount>(asks).unwrap());
+    }
+}
+
+fn bench_parse(c: &mut Criterion) {
+    for levels in [1, 20] {
+        let mut group = c.benchmark_group(format!("parse frames with {} levels", levels));
+        for (exchange, frames) in corpus(levels) {
+            group.throughput(Throughput::Elements(frames.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new("borrowed", exchange),
+                &frames,
+                |b, frames| match exchange {
+                    "binance" => b.iter(|| parse::<binance::data::BookUpdate>(frames)),
+                    _ => b.iter(|| parse::<bitstamp::data::BookUpdate>(frames)),
+                },
+            );
+            group.bench_with_input(BenchmarkId::new("value", exchange), &frames, |b, frames| {
+                b.iter(|| parse_value(frames, exchange))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
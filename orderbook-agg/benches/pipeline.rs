@@ -0,0 +1,92 @@
+//! Measures the throughput of the update pipeline, from raw websocket messages to the
+//! book levels received by the summary task, with two exchanges streaming at once.
+//!
+//! ```sh
+//! cargo bench --bench pipeline
+//! ```
+use std::{sync::Arc, time::Instant};
+
+use futures::SinkExt;
+use orderbook_agg::{
+    core::{
+        exchange_book::process_updates,
+        order_book::{BookLevels, OrderBook},
+        stats::ExchangeStats,
+    },
+    exchanges::bitstamp::data::{BookUpdate, Snapshot},
+    Exchange, Symbol,
+};
+use rust_decimal::Decimal;
+use tokio::sync::{mpsc, RwLock};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+const MESSAGES: u64 = 50_000;
+const LEVELS: u32 = 15;
+
+fn messages() -> Vec<Message> {
+    (2..MESSAGES + 2)
+        .map(|id| {
+            let offset = id % 500;
+            let update = serde_json::json!({
+                "data": {
+                    "microtimestamp": id.to_string(),
+                    "bids": [[(29500 + offset).to_string(), (id % 7).to_string()]],
+                    "asks": [[(30500 - offset).to_string(), (id % 5).to_string()]],
+                }
+            });
+            Message::Text(update.to_string())
+        })
+        .collect()
+}
+
+fn snapshot() -> Snapshot {
+    Snapshot {
+        last_update_id: 1,
+        bids: (29000..30000)
+            .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+            .collect(),
+        asks: (30001..31000)
+            .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+            .collect(),
+    }
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let (tx_levels, mut rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+    let streams = [Exchange::BITSTAMP, Exchange::BINANCE].map(|exchange| {
+        let orderbook = OrderBook::new(exchange, Symbol::BTCUSDT, 29000, 31000, 0, 8);
+        (exchange, orderbook, messages())
+    });
+
+    let start = Instant::now();
+    for (_, orderbook, messages) in streams {
+        let sink = futures::sink::drain().sink_map_err(|never| match never {});
+        tokio::spawn(process_updates::<Snapshot, BookUpdate, _, _>(
+            Arc::new(RwLock::new(orderbook)),
+            Arc::new(ExchangeStats::default()),
+            LEVELS,
+            snapshot(),
+            None,
+            futures::stream::iter(messages.into_iter().map(Ok::<_, WsError>)),
+            sink,
+            tx_levels.clone(),
+        ));
+    }
+    drop(tx_levels);
+
+    // Only updates that change the top levels are sent on.
+    let mut received = 0;
+    while rx_levels.recv().await.is_some() {
+        received += 1;
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} messages from 2 exchanges in {:.2?}, {:.0} messages/s, {} book levels received",
+        2 * MESSAGES,
+        elapsed,
+        (2 * MESSAGES) as f64 / elapsed.as_secs_f64(),
+        received
+    );
+}
@@ -1,5 +1,42 @@
+use std::{path::Path, process::Command, time::SystemTime};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // tonic_build::compile_protos("proto/orderbook.proto")?;
-    tonic_build::compile_protos("proto/booksummary.proto")?;
+    // Messages serialize with their proto field names, e.g. for the cli's json output.
+    tonic_build::configure()
+        .type_attribute(
+            ".booksummary",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .message_attribute(".booksummary", "#[serde(default)]")
+        .enum_attribute(".booksummary", "#[serde(rename_all = \"snake_case\")]")
+        .compile(&["proto/booksummary.proto"], &["proto"])?;
+
+    // Build info reported by GetServerInfo
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ORDERBOOK_GIT_COMMIT={}", commit);
+    let built = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    println!(
+        "cargo:rustc-env=ORDERBOOK_BUILD_TIMESTAMP={}",
+        built.as_secs()
+    );
+    let mut features = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=ORDERBOOK_FEATURES={}", features.join(","));
+    // Rebuilt on a new commit rather than left reporting the one it was first built at.
+    for path in ["../.git/HEAD", "../.git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,39 @@
+//! Prints the spread and best levels of each summary from a running server, using
+//! [OrderbookClient] to reconnect when the server restarts.
+//!
+//! ```sh
+//! cargo run --bin server
+//! cargo run --example watch_summaries -- 127.0.0.1:9001 btcusdt
+//! ```
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio_stream::StreamExt;
+
+use orderbook_agg::client::{OrderbookClient, SummaryOptions};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let mut args = std::env::args().skip(1);
+    let endpoint = args.next().unwrap_or_else(|| "127.0.0.1:9001".to_string());
+    let symbol = args.next().unwrap_or_else(|| "btcusdt".to_string());
+
+    let client = OrderbookClient::connect(&endpoint).await?;
+    println!("{} serves {}", endpoint, client.symbols().await?.join(", "));
+
+    let options = SummaryOptions::new()
+        .with_levels(1)
+        .with_min_interval(Duration::from_millis(500));
+    let mut summaries = client.watch(&symbol, options);
+    while let Some(summary) = summaries.next().await {
+        let summary = summary?;
+        if let (Some(bid), Some(ask)) = (summary.bids.first(), summary.asks.first()) {
+            println!(
+                "{} spread {:.2} bid {} {} ask {} {}",
+                summary.symbol, summary.spread, bid.price, bid.exchange, ask.price, ask.exchange
+            );
+        }
+    }
+    Ok(())
+}
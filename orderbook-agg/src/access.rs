@@ -0,0 +1,214 @@
+//! Restricts the client addresses the server answers, set with `--allow-peers` and
+//! `--deny-peers`.
+//!
+//! [PeerFilter] is an interceptor layered over every service the server runs, so each
+//! call is checked against the peer address of its connection. Denied ranges take
+//! precedence over allowed ones, and without any allowed ranges every address not denied
+//! is served. Calls that don't come over TCP, like those over in-process pipes, have no
+//! address and are served or refused by `--unknown-peers`. Refused calls fail with
+//! [Status::permission_denied] and are logged with the address.
+//!
+//! The ranges are reloaded along with the config file.
+use anyhow::{bail, Context, Result};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+use tonic::{Request, Status};
+
+/// Whether calls without a peer address are served
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownPeers {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl std::str::FromStr for UnknownPeers {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "allow" => Ok(UnknownPeers::Allow),
+            "deny" => Ok(UnknownPeers::Deny),
+            _ => bail!("expected allow or deny, got {}", name),
+        }
+    }
+}
+
+/// The ranges of addresses served and refused
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerRules {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+    pub unknown: UnknownPeers,
+}
+
+impl PeerRules {
+    /// Whether a call from `addr` is served
+    pub fn permits(&self, addr: Option<IpAddr>) -> bool {
+        let Some(addr) = addr else {
+            return self.unknown == UnknownPeers::Allow;
+        };
+        // Clients of a server listening on an IPv6 socket reach it with mapped IPv4
+        // addresses, which the IPv4 ranges are for.
+        let addr = addr.to_canonical();
+        !self.deny.iter().any(|net| net.contains(&addr))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&addr)))
+    }
+}
+
+impl std::fmt::Display for PeerRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let nets = |nets: &[IpNet]| match nets.is_empty() {
+            true => "none".to_string(),
+            false => nets
+                .iter()
+                .map(|net| net.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        let unknown = match self.unknown {
+            UnknownPeers::Allow => "allowed",
+            UnknownPeers::Deny => "denied",
+        };
+        write!(
+            f,
+            "allow {}, deny {}, unknown {}",
+            nets(&self.allow),
+            nets(&self.deny),
+            unknown
+        )
+    }
+}
+
+/// Parses CIDR ranges given to the server, e.g. 10.0.0.0/8 or fd00::/8. A bare address is
+/// a range of just itself.
+pub fn parse_nets<S: AsRef<str>>(ranges: &[S]) -> Result<Vec<IpNet>> {
+    ranges
+        .iter()
+        .map(|range| {
+            let range = range.as_ref().trim();
+            range
+                .parse::<IpNet>()
+                .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                .with_context(|| format!("expected a CIDR range, got {}", range))
+        })
+        .collect()
+}
+
+/// Checks the peer address of each call against the rules. Clones share the same rules.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFilter {
+    rules: Arc<RwLock<PeerRules>>,
+}
+
+impl PeerFilter {
+    pub fn new(rules: PeerRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn rules(&self) -> PeerRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Checks calls against `rules` from now on
+    pub fn set(&self, rules: PeerRules) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    /// Fails with [Status::permission_denied] unless the rules serve `addr`
+    #[allow(clippy::result_large_err)]
+    pub fn check(&self, addr: Option<SocketAddr>) -> Result<(), Status> {
+        if self
+            .rules
+            .read()
+            .unwrap()
+            .permits(addr.map(|addr| addr.ip()))
+        {
+            return Ok(());
+        }
+        match addr {
+            Some(addr) => tracing::warn!("Refused a call from {}", addr),
+            None => tracing::warn!("Refused a call without a peer address"),
+        }
+        Err(Status::permission_denied("peer address not allowed"))
+    }
+}
+
+impl tonic::service::Interceptor for PeerFilter {
+    #[allow(clippy::result_large_err)] // The signature is tonic's.
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        self.check(crate::tls::remote_addr(&request))?;
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str], unknown: UnknownPeers) -> PeerRules {
+        PeerRules {
+            allow: parse_nets(allow).unwrap(),
+            deny: parse_nets(deny).unwrap(),
+            unknown,
+        }
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn it_denies_before_it_allows() {
+        let rules = rules(
+            &["10.0.0.0/8", "fd00::/8"],
+            &["10.1.0.0/16", "fd00::1"],
+            UnknownPeers::Allow,
+        );
+        assert!(rules.permits(ip("10.2.3.4")));
+        assert!(!rules.permits(ip("10.1.2.3")));
+        assert!(!rules.permits(ip("192.168.1.1")));
+        assert!(rules.permits(ip("fd00::2")));
+        assert!(!rules.permits(ip("fd00::1")));
+        assert!(!rules.permits(ip("2001:db8::1")));
+        // Mapped IPv4 addresses match the IPv4 ranges.
+        assert!(rules.permits(ip("::ffff:10.2.3.4")));
+        assert!(!rules.permits(ip("::ffff:10.1.2.3")));
+
+        let open = self::rules(&[], &["192.168.0.0/16"], UnknownPeers::Allow);
+        assert!(open.permits(ip("8.8.8.8")));
+        assert!(!open.permits(ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn it_serves_unknown_peers_by_the_policy() {
+        assert!(rules(&["10.0.0.0/8"], &[], UnknownPeers::Allow).permits(None));
+        assert!(!rules(&[], &[], UnknownPeers::Deny).permits(None));
+
+        let filter = PeerFilter::new(rules(&[], &[], UnknownPeers::Allow));
+        assert!(filter.check(None).is_ok());
+        filter.clone().set(rules(&[], &[], UnknownPeers::Deny));
+        let status = filter.check(None).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn it_parses_ranges_and_policies() {
+        assert_eq!(
+            parse_nets(&["10.0.0.0/8", " ::1 "]).unwrap(),
+            [
+                "10.0.0.0/8".parse::<IpNet>().unwrap(),
+                "::1/128".parse().unwrap()
+            ]
+        );
+        assert!(parse_nets(&["10.0.0.0/33"]).is_err());
+        assert!(parse_nets(&["localhost"]).is_err());
+        assert_eq!("deny".parse::<UnknownPeers>().unwrap(), UnknownPeers::Deny);
+        assert!("block".parse::<UnknownPeers>().is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! [OrderbookAdmin] gRPC service for operators, served alongside the aggregator when the
+//! server is started with `--admin`. Every call needs the admin bearer token.
+use futures::Stream;
+use std::{pin::Pin, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{service::interceptor::InterceptedService, Request, Response, Status};
+
+use crate::{
+    book_summary::{
+        orderbook_admin_server::{OrderbookAdmin, OrderbookAdminServer},
+        DisconnectStreamRequest, Empty, ReloadConfigResponse, SetExchangeEnabledRequest,
+        SetExchangeEnabledResponse, Streams, TapUpdatesRequest, TappedUpdate,
+    },
+    metrics::{self, PipelineStats},
+    reload::Reloader,
+    service::ExchangeSwitches,
+    streams::StreamRegistry,
+    Exchange, Symbol,
+};
+
+/// Lists and ends the streams in the registry shared with the aggregator service,
+/// switches exchanges off and on, reloads the config file and taps the updates applied to
+/// the order books.
+#[derive(Debug, Clone)]
+pub struct AdminService {
+    streams: StreamRegistry,
+    switches: ExchangeSwitches,
+    reloader: Option<Arc<Reloader>>,
+    stats: PipelineStats,
+}
+
+/// Checks requests carry `authorization: Bearer <token>`.
+#[derive(Debug, Clone)]
+pub struct AdminAuth {
+    expected: String,
+}
+
+impl AdminService {
+    pub fn new(streams: StreamRegistry) -> Self {
+        Self {
+            streams,
+            switches: ExchangeSwitches::default(),
+            reloader: None,
+            stats: PipelineStats::new(),
+        }
+    }
+
+    /// Sets the switches of the exchanges the summaries are aggregated from
+    pub fn with_switches(mut self, switches: ExchangeSwitches) -> Self {
+        self.switches = switches;
+        self
+    }
+
+    /// Sets the reloader of the config file the server was started with
+    pub fn with_reloader(mut self, reloader: Arc<Reloader>) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    /// Sets the stats of the order books whose updates are tapped, the ones the aggregator
+    /// service counts in
+    pub fn with_stats(mut self, stats: PipelineStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// The service wrapped in a check for the admin `token`
+    pub fn with_token(
+        self,
+        token: &str,
+    ) -> InterceptedService<OrderbookAdminServer<Self>, AdminAuth> {
+        OrderbookAdminServer::with_interceptor(
+            self,
+            AdminAuth {
+                expected: format!("Bearer {}", token),
+            },
+        )
+    }
+}
+
+impl tonic::service::Interceptor for AdminAuth {
+    #[allow(clippy::result_large_err)] // The signature is tonic's.
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let given = request
+            .metadata()
+            .get("authorization")
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        if constant_time_eq(given, self.expected.as_bytes()) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("invalid admin token"))
+        }
+    }
+}
+
+/// Compares without returning early, so the time taken doesn't give away how much of a
+/// guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[async_trait::async_trait]
+impl OrderbookAdmin for AdminService {
+    async fn list_streams(&self, _: Request<Empty>) -> Result<Response<Streams>, Status> {
+        Ok(Response::new(Streams {
+            streams: self.streams.list(),
+        }))
+    }
+
+    async fn disconnect_stream(
+        &self,
+        request: Request<DisconnectStreamRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let id = request.into_inner().id;
+        if !self.streams.cancel(id) {
+            return Err(Status::not_found(format!("no stream {}", id)));
+        }
+        tracing::info!("disconnecting stream {}", id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn set_exchange_enabled(
+        &self,
+        request: Request<SetExchangeEnabledRequest>,
+    ) -> Result<Response<SetExchangeEnabledResponse>, Status> {
+        let request = request.into_inner();
+        let exchange = request
+            .exchange
+            .parse::<Exchange>()
+            .map_err(|err| Status::invalid_argument(format!("{:#}", err)))?;
+        let changed = self
+            .switches
+            .set(exchange, request.enabled)
+            .map_err(|err| Status::failed_precondition(format!("{:#}", err)))?;
+        Ok(Response::new(SetExchangeEnabledResponse {
+            changed,
+            changes: self.switches.changes(),
+        }))
+    }
+
+    async fn reload_config(
+        &self,
+        _: Request<Empty>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        let reloader = self.reloader.as_ref().ok_or_else(|| {
+            Status::failed_precondition("the server wasn't started with a config file")
+        })?;
+        let changes = reloader
+            .reload()
+            .map_err(|err| Status::failed_precondition(format!("{:#}", err)))?;
+        Ok(Response::new(ReloadConfigResponse { changes }))
+    }
+
+    type TapUpdatesStream = Pin<Box<dyn Stream<Item = Result<TappedUpdate, Status>> + Send>>;
+    async fn tap_updates(
+        &self,
+        request: Request<TapUpdatesRequest>,
+    ) -> Result<Response<Self::TapUpdatesStream>, Status> {
+        let request = request.into_inner();
+        let symbol = request
+            .symbol
+            .parse::<Symbol>()
+            .map_err(|err| Status::invalid_argument(format!("{:#}", err)))?;
+        let exchanges = request
+            .exchanges
+            .iter()
+            .map(|name| name.parse::<Exchange>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Status::invalid_argument(format!("{:#}", err)))?;
+        tracing::info!("tapping the {} updates", symbol);
+
+        // Subscribed before the stream is returned, so the tap has every update from then.
+        let tap = self.stats.tap(symbol);
+        let (tx, rx) = mpsc::channel(100);
+        tokio::spawn(metrics::tap_updates(
+            self.stats.clone(),
+            tap,
+            symbol,
+            exchanges,
+            tx,
+        ));
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::TapUpdatesStream
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::service::Interceptor;
+
+    #[test]
+    fn it_checks_the_admin_token() {
+        let mut auth = AdminAuth {
+            expected: "Bearer secret".to_string(),
+        };
+        let request = |token: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(token) = token {
+                request
+                    .metadata_mut()
+                    .insert("authorization", token.parse().unwrap());
+            }
+            request
+        };
+        assert!(auth.call(request(Some("Bearer secret"))).is_ok());
+        for token in [
+            None,
+            Some("Bearer secre"),
+            Some("Bearer secrets"),
+            Some("secret"),
+        ] {
+            let status = auth.call(request(token)).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated, "{:?}", token);
+        }
+    }
+}
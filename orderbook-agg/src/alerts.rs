@@ -0,0 +1,437 @@
+//! Rules checked against each summary published for a symbol, raising an [Alert] when one
+//! starts or stops holding.
+//!
+//! A rule compares one value of the summary with a threshold. Once triggered it only
+//! clears after the value has gone back past the threshold by the rule's hysteresis, and
+//! either change has to last for the rule's debounce before it's sent, so a value
+//! flickering around the threshold raises a single alert.
+use anyhow::{ensure, Context, Result};
+use std::{collections::HashSet, time::Duration};
+use tokio::{select, sync::mpsc, time::Instant};
+use tonic::Status;
+
+use crate::{
+    book_summary::{alert_rule::Condition, Alert, AlertRule, Summary},
+    service::SummaryReceiver,
+    spread_stats::best_size,
+};
+
+/// The values of a summary rules are checked against, unset while they're unknown
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Values {
+    /// The spread in basis points of the mid price
+    pub spread_bps: Option<f64>,
+    pub bid_size: Option<f64>,
+    pub ask_size: Option<f64>,
+    /// The exchanges quoting in the summary
+    pub live_exchanges: u32,
+}
+
+impl Values {
+    /// The values of `summary`, or those of an unavailable summary, with no exchanges
+    /// live, when it's `None`.
+    pub fn new(summary: Option<&Summary>) -> Self {
+        let Some(summary) = summary else {
+            return Self::default();
+        };
+        let spread_bps = match (summary.bids.first(), summary.asks.first()) {
+            (Some(bid), Some(ask)) => {
+                let mid = (bid.price + ask.price) / 2.0;
+                (mid > 0.0).then(|| (ask.price - bid.price) / mid * 10_000.0)
+            }
+            _ => None,
+        };
+        let live = summary
+            .bids
+            .iter()
+            .chain(summary.asks.iter())
+            .map(|level| level.exchange.as_str())
+            .collect::<HashSet<_>>();
+        Self {
+            spread_bps,
+            bid_size: best_size(&summary.bids),
+            ask_size: best_size(&summary.asks),
+            live_exchanges: live.len() as u32,
+        }
+    }
+
+    /// The smaller of the sizes at the best bid and ask
+    fn top_size(&self) -> Option<f64> {
+        Some(self.bid_size?.min(self.ask_size?))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Threshold {
+    Above(f64),
+    Below(f64),
+}
+
+/// A rule of a [WatchAlertsRequest](crate::book_summary::WatchAlertsRequest) and whether
+/// it's triggered
+#[derive(Debug)]
+pub struct Rule {
+    id: String,
+    value: fn(&Values) -> Option<f64>,
+    threshold: Threshold,
+    /// Milliseconds
+    debounce: u64,
+    hysteresis: f64,
+    triggered: bool,
+    /// When the value first went the other way, in milliseconds
+    pending: Option<u64>,
+}
+
+impl Rule {
+    pub fn new(rule: AlertRule) -> Result<Self> {
+        ensure!(!rule.id.is_empty(), "rules need an id");
+        let id = rule.id;
+        let (value, threshold): (fn(&Values) -> Option<f64>, _) = match rule
+            .condition
+            .with_context(|| format!("rule {} has no condition", id))?
+        {
+            Condition::SpreadBpsAbove(bps) => {
+                ensure!(
+                    bps.is_finite(),
+                    "rule {} needs a finite spread_bps_above",
+                    id
+                );
+                (|values| values.spread_bps, Threshold::Above(bps))
+            }
+            Condition::TopSizeBelow(size) => {
+                ensure!(
+                    size.is_finite() && size > 0.0,
+                    "rule {} needs a top_size_below above 0",
+                    id
+                );
+                (Values::top_size, Threshold::Below(size))
+            }
+            Condition::LiveExchangesBelow(count) => {
+                ensure!(
+                    count > 0,
+                    "rule {} needs a live_exchanges_below above 0",
+                    id
+                );
+                (
+                    |values| Some(values.live_exchanges as f64),
+                    Threshold::Below(count as f64),
+                )
+            }
+            // The hysteresis of a crossed book is how far it has to uncross, in bps.
+            Condition::Crossed(_) => (|values| values.spread_bps, Threshold::Below(0.0)),
+        };
+        ensure!(
+            rule.hysteresis.is_finite() && rule.hysteresis >= 0.0,
+            "rule {} needs a hysteresis of 0 or more",
+            id
+        );
+        Ok(Self {
+            id,
+            value,
+            threshold,
+            debounce: rule.debounce_ms as u64,
+            hysteresis: rule.hysteresis,
+            triggered: false,
+            pending: None,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the rule holds for `value`, given whether it's triggered now
+    fn holds(&self, value: f64) -> bool {
+        match (self.threshold, self.triggered) {
+            (Threshold::Above(threshold), false) => value > threshold,
+            (Threshold::Above(threshold), true) => value > threshold - self.hysteresis,
+            (Threshold::Below(threshold), false) => value < threshold,
+            (Threshold::Below(threshold), true) => value < threshold + self.hysteresis,
+        }
+    }
+
+    /// Checks the rule against `values` at `now` milliseconds, returning whether it
+    /// triggered or cleared if it changed. Unknown values leave the rule as it was.
+    pub fn check(&mut self, values: &Values, now: u64) -> Option<bool> {
+        let holds = (self.value)(values).map_or(self.triggered, |value| self.holds(value));
+        if holds == self.triggered {
+            self.pending = None;
+            return None;
+        }
+        let since = *self.pending.get_or_insert(now);
+        if now.saturating_sub(since) < self.debounce {
+            return None;
+        }
+        self.triggered = holds;
+        self.pending = None;
+        Some(holds)
+    }
+
+    /// When a change waiting out the debounce is due to be checked again, in milliseconds
+    pub fn deadline(&self) -> Option<u64> {
+        self.pending.map(|since| since + self.debounce)
+    }
+
+    fn alert(&self, values: &Values, sequence: u64) -> Alert {
+        Alert {
+            rule_id: self.id.clone(),
+            triggered: self.triggered,
+            value: (self.value)(values).unwrap_or_default(),
+            spread_bps: values.spread_bps.unwrap_or_default(),
+            bid_size: values.bid_size.unwrap_or_default(),
+            ask_size: values.ask_size.unwrap_or_default(),
+            live_exchanges: values.live_exchanges,
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+            sequence,
+        }
+    }
+}
+
+/// The rules of a request, which need at least one and unique ids
+pub fn parse_rules(rules: Vec<AlertRule>) -> Result<Vec<Rule>> {
+    ensure!(!rules.is_empty(), "at least one rule is needed");
+    let mut ids = HashSet::new();
+    let rules = rules
+        .into_iter()
+        .map(Rule::new)
+        .collect::<Result<Vec<_>>>()?;
+    for rule in rules.iter() {
+        ensure!(
+            ids.insert(rule.id()),
+            "rule {} is given more than once",
+            rule.id()
+        );
+    }
+    Ok(rules)
+}
+
+/// Checks `rules` against each summary published on `rx_summary`, and again when a
+/// change has waited out its debounce, sending an alert for each rule that changes.
+/// Returns once the summaries end or the client goes away.
+pub async fn watch_alerts(
+    mut rx_summary: SummaryReceiver,
+    mut rules: Vec<Rule>,
+    tx: mpsc::Sender<Result<Alert, Status>>,
+) {
+    let start = Instant::now();
+    let mut latest = None;
+    let mut sequence = 0;
+    loop {
+        match &*rx_summary.borrow_and_update() {
+            // The placeholder published before the first summary isn't checked.
+            Ok(summary) if summary.sequence == 0 => {}
+            Ok(summary) => {
+                latest = Some(Values::new(Some(summary)));
+                sequence = summary.sequence;
+            }
+            Err(_) => latest = Some(Values::new(None)),
+        }
+        loop {
+            if let Some(values) = latest {
+                let now = start.elapsed().as_millis() as u64;
+                for rule in rules.iter_mut() {
+                    if rule.check(&values, now).is_some()
+                        && tx.send(Ok(rule.alert(&values, sequence))).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+            let deadline = rules.iter().filter_map(Rule::deadline).min();
+            let due = async {
+                match deadline {
+                    Some(at) => tokio::time::sleep_until(start + Duration::from_millis(at)).await,
+                    None => std::future::pending().await,
+                }
+            };
+            select! {
+                changed = rx_summary.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    break;
+                }
+                _ = due => {}
+                _ = tx.closed() => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::{Level, MarketType};
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    fn rule(id: &str, condition: Condition, debounce_ms: u32, hysteresis: f64) -> Rule {
+        Rule::new(AlertRule {
+            id: id.to_string(),
+            condition: Some(condition),
+            debounce_ms,
+            hysteresis,
+        })
+        .unwrap()
+    }
+
+    fn spread(bps: f64) -> Values {
+        Values {
+            spread_bps: Some(bps),
+            ..Default::default()
+        }
+    }
+
+    /// The changes of `rule` when checked against each value a millisecond apart
+    fn changes(rule: &mut Rule, values: &[Values]) -> Vec<(usize, bool)> {
+        values
+            .iter()
+            .enumerate()
+            .filter_map(|(at, values)| Some((at, rule.check(values, at as u64)?)))
+            .collect()
+    }
+
+    #[test]
+    fn it_triggers_and_clears_once_per_excursion_with_hysteresis() {
+        let mut wide = rule("wide", Condition::SpreadBpsAbove(10.0), 0, 2.0);
+        // Flickering around the threshold while above it only clears once it's below 8.
+        let values = [5.0, 11.0, 9.0, 12.0, 8.5, 10.5, 7.0, 9.5, 5.0, 11.0, 3.0].map(spread);
+        assert_eq!(
+            changes(&mut wide, &values),
+            [(1, true), (6, false), (9, true), (10, false)]
+        );
+
+        let mut thin = rule("thin", Condition::TopSizeBelow(1.0), 0, 0.5);
+        let sizes = |bid: f64, ask: f64| Values {
+            bid_size: Some(bid),
+            ask_size: Some(ask),
+            ..Default::default()
+        };
+        let values = [
+            sizes(2.0, 2.0),
+            sizes(2.0, 0.5),
+            sizes(1.2, 2.0),
+            sizes(1.5, 1.6),
+        ];
+        assert_eq!(changes(&mut thin, &values), [(1, true), (3, false)]);
+    }
+
+    #[test]
+    fn it_waits_out_the_debounce_before_changing() {
+        let mut wide = rule("wide", Condition::SpreadBpsAbove(10.0), 3, 0.0);
+        // A blip shorter than the debounce is ignored.
+        let mut values = vec![spread(11.0), spread(11.0), spread(5.0)];
+        values.extend([spread(11.0); 5]);
+        values.extend([spread(5.0); 5]);
+        assert_eq!(changes(&mut wide, &values), [(6, true), (11, false)]);
+        assert_eq!(wide.deadline(), None);
+
+        wide.check(&spread(11.0), 100);
+        assert_eq!(wide.deadline(), Some(103));
+    }
+
+    #[test]
+    fn it_leaves_rules_as_they_were_while_values_are_unknown() {
+        let mut crossed = rule("crossed", Condition::Crossed(true), 0, 1.0);
+        let values = [spread(-1.0), Values::default(), spread(0.5), spread(1.0)];
+        assert_eq!(changes(&mut crossed, &values), [(0, true), (3, false)]);
+
+        let mut live = rule("live", Condition::LiveExchangesBelow(2), 0, 0.0);
+        assert_eq!(live.check(&Values::new(None), 0), Some(true));
+    }
+
+    #[test]
+    fn it_validates_rules() {
+        let rule = |id: &str, condition| AlertRule {
+            id: id.to_string(),
+            condition,
+            ..Default::default()
+        };
+        let error = |rules| parse_rules(rules).unwrap_err().to_string();
+        assert_eq!(error(vec![]), "at least one rule is needed");
+        assert_eq!(
+            error(vec![rule("", Some(Condition::Crossed(true)))]),
+            "rules need an id"
+        );
+        assert_eq!(error(vec![rule("a", None)]), "rule a has no condition");
+        assert_eq!(
+            error(vec![rule("a", Some(Condition::TopSizeBelow(0.0)))]),
+            "rule a needs a top_size_below above 0"
+        );
+        assert_eq!(
+            error(vec![rule("a", Some(Condition::SpreadBpsAbove(f64::NAN)))]),
+            "rule a needs a finite spread_bps_above"
+        );
+        assert_eq!(
+            error(vec![
+                rule("a", Some(Condition::Crossed(true))),
+                rule("a", Some(Condition::LiveExchangesBelow(1)))
+            ]),
+            "rule a is given more than once"
+        );
+    }
+
+    #[test]
+    fn it_takes_the_values_from_a_summary() {
+        let level = |exchange: &str, price, quantity| Level {
+            exchange: exchange.to_string(),
+            price,
+            quantity,
+            updated_at: None,
+            market_type: MarketType::Spot.into(),
+        };
+        let summary = Summary {
+            bids: vec![level("BINANCE", 99.0, 1.0), level("BITSTAMP", 99.0, 2.0)],
+            asks: vec![level("BINANCE", 101.0, 0.5)],
+            ..Default::default()
+        };
+        assert_eq!(
+            Values::new(Some(&summary)),
+            Values {
+                spread_bps: Some(200.0),
+                bid_size: Some(3.0),
+                ask_size: Some(0.5),
+                live_exchanges: 2,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_sends_an_alert_once_a_change_outlasts_the_debounce() {
+        let summary = |sequence, ask: f64| {
+            let level = |price| Level {
+                exchange: "BINANCE".to_string(),
+                price,
+                quantity: 1.0,
+                updated_at: None,
+                market_type: MarketType::Spot.into(),
+            };
+            Arc::new(Summary {
+                bids: vec![level(100.0)],
+                asks: vec![level(ask)],
+                sequence,
+                ..Default::default()
+            })
+        };
+        let (tx_summary, rx_summary) = watch::channel(Ok(Arc::new(Summary::default())));
+        let rules = vec![rule("wide", Condition::SpreadBpsAbove(50.0), 1000, 0.0)];
+        let (tx, mut rx) = mpsc::channel(10);
+        let handle = tokio::spawn(watch_alerts(rx_summary, rules, tx));
+
+        let _ = tx_summary.send_replace(Ok(summary(1, 101.0)));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(rx.try_recv().is_err());
+        // Stays wide with no more summaries, so the alert comes from the debounce running out.
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let alert = rx.try_recv().unwrap().unwrap();
+        assert_eq!(
+            (alert.rule_id.as_str(), alert.triggered, alert.sequence),
+            ("wide", true, 1)
+        );
+        assert!((alert.value - 99.5).abs() < 0.1, "{}", alert.value);
+
+        drop(tx_summary);
+        handle.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! The latest summaries of each symbol, kept so `WatchSummary` streams asking for a
+//! `backfill` start with the summaries made before they connected.
+//!
+//! The summary task adds each summary here before publishing it, so every summary
+//! numbered before the first one a stream is sent live is already here. The stream sends
+//! those first, so none is sent twice and none is missed where the backfill ends and the
+//! live summaries start.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{book_summary::Summary, Symbol};
+
+/// Summaries kept of each symbol unless set otherwise
+pub const DEFAULT_CAPACITY: usize = 300;
+
+/// The latest summaries of each symbol, at most the capacity of each. Clones share the
+/// same summaries.
+#[derive(Debug, Clone)]
+pub struct Backfill {
+    capacity: usize,
+    summaries: Arc<Mutex<HashMap<Symbol, VecDeque<Arc<Summary>>>>>,
+}
+
+impl Default for Backfill {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Backfill {
+    /// Keeps the latest `capacity` summaries of each symbol, none when 0
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            summaries: Arc::default(),
+        }
+    }
+
+    /// Adds the summary just made for `symbol`, dropping the oldest past the capacity. A
+    /// summary numbered before the latest is from a new summary task, which starts over.
+    pub fn push(&self, symbol: Symbol, summary: Arc<Summary>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut summaries = self.summaries.lock().unwrap();
+        let kept = summaries.entry(symbol).or_default();
+        if kept
+            .back()
+            .is_some_and(|last| last.sequence >= summary.sequence)
+        {
+            kept.clear();
+        }
+        if kept.len() == self.capacity {
+            kept.pop_front();
+        }
+        kept.push_back(summary);
+    }
+
+    /// The latest `count` summaries of `symbol` numbered before `sequence`, oldest first
+    pub fn before(&self, symbol: Symbol, sequence: u64, count: usize) -> Vec<Arc<Summary>> {
+        let summaries = self.summaries.lock().unwrap();
+        let mut before = summaries
+            .get(&symbol)
+            .into_iter()
+            .flatten()
+            .rev()
+            .filter(|summary| summary.sequence < sequence)
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+        before.reverse();
+        before
+    }
+
+    /// Drops the summaries of `symbol` once its summary task ends
+    pub fn remove(&self, symbol: Symbol) {
+        self.summaries.lock().unwrap().remove(&symbol);
+    }
+
+    /// The summaries kept across every symbol
+    pub fn kept(&self) -> usize {
+        let summaries = self.summaries.lock().unwrap();
+        summaries.values().map(|kept| kept.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(sequence: u64) -> Arc<Summary> {
+        Arc::new(Summary {
+            sequence,
+            ..Default::default()
+        })
+    }
+
+    fn sequences(summaries: &[Arc<Summary>]) -> Vec<u64> {
+        summaries.iter().map(|summary| summary.sequence).collect()
+    }
+
+    #[test]
+    fn it_keeps_the_latest_summaries_of_each_symbol() {
+        let backfill = Backfill::new(3);
+        for sequence in 1..=5 {
+            backfill.push(Symbol::BTCUSDT, summary(sequence));
+        }
+        backfill.push(Symbol::ETHBTC, summary(1));
+        assert_eq!(
+            sequences(&backfill.before(Symbol::BTCUSDT, 6, 10)),
+            [3, 4, 5]
+        );
+        assert_eq!(sequences(&backfill.before(Symbol::BTCUSDT, 6, 2)), [4, 5]);
+        assert_eq!(sequences(&backfill.before(Symbol::BTCUSDT, 5, 10)), [3, 4]);
+        assert!(backfill.before(Symbol::BTCUSDT, 3, 10).is_empty());
+        assert_eq!(backfill.kept(), 4);
+
+        // A new summary task numbers its summaries from 1 again.
+        backfill.push(Symbol::BTCUSDT, summary(1));
+        assert_eq!(sequences(&backfill.before(Symbol::BTCUSDT, 2, 10)), [1]);
+        backfill.remove(Symbol::ETHBTC);
+        assert!(backfill.before(Symbol::ETHBTC, 2, 10).is_empty());
+        assert_eq!(backfill.kept(), 1);
+
+        let none = Backfill::new(0);
+        none.push(Symbol::BTCUSDT, summary(1));
+        assert_eq!(none.kept(), 0);
+    }
+}
@@ -0,0 +1,146 @@
+//! Summaries grouped into [SummaryBatch]es for `WatchSummaryBatches`, so clients of busy
+//! symbols get one message for many small summaries.
+//!
+//! A batch only holds summaries with contiguous sequence numbers. It's sent once it has
+//! the most summaries asked for, or once its first summary has waited the longest delay
+//! asked for, so batching never holds a summary back for longer than that.
+use anyhow::{ensure, Result};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::book_summary::{Summary, SummaryBatch};
+
+/// Summaries in a batch when the request doesn't say
+pub const DEFAULT_MAX_BATCH: u32 = 100;
+/// The most summaries a request can ask for in a batch
+pub const MAX_BATCH: u32 = 1000;
+/// The longest a summary waits for others to join its batch when the request doesn't say
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(20);
+/// The longest delay a request can ask for
+pub const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// The batch being filled for a stream
+#[derive(Debug)]
+pub struct Batcher {
+    max_batch: usize,
+    max_delay: Duration,
+    summaries: Vec<Summary>,
+    deadline: Option<Instant>,
+}
+
+impl Batcher {
+    /// Batches of at most `max_batch` summaries, each sent within `max_delay` of its first
+    /// summary being added. 0 takes the defaults.
+    pub fn new(max_batch: u32, max_delay_ms: u32) -> Result<Self> {
+        let max_delay = Duration::from_millis(max_delay_ms as u64);
+        ensure!(
+            max_batch <= MAX_BATCH,
+            "max_batch must be at most {}",
+            MAX_BATCH
+        );
+        ensure!(
+            max_delay <= MAX_DELAY,
+            "max_batch_delay_ms must be at most {}",
+            MAX_DELAY.as_millis()
+        );
+        Ok(Self {
+            max_batch: match max_batch {
+                0 => DEFAULT_MAX_BATCH,
+                max_batch => max_batch,
+            } as usize,
+            max_delay: match max_delay.is_zero() {
+                true => DEFAULT_MAX_DELAY,
+                false => max_delay,
+            },
+            summaries: Vec::new(),
+            deadline: None,
+        })
+    }
+
+    /// Adds `summary` at `now`, returning the batches it closes: the one before it when its
+    /// sequence number doesn't follow on from the last one's, and its own once it's full.
+    pub fn push(&mut self, summary: Summary, now: Instant) -> Vec<SummaryBatch> {
+        let mut closed = Vec::new();
+        let follows = self
+            .summaries
+            .last()
+            .is_none_or(|last| last.sequence + 1 == summary.sequence);
+        if !follows {
+            closed.extend(self.take());
+        }
+        self.deadline.get_or_insert(now + self.max_delay);
+        self.summaries.push(summary);
+        if self.summaries.len() >= self.max_batch {
+            closed.extend(self.take());
+        }
+        closed
+    }
+
+    /// When the batch being filled has to be sent by, `None` while it's empty
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// The batch being filled, unless it's empty, starting the next one
+    pub fn take(&mut self) -> Option<SummaryBatch> {
+        self.deadline = None;
+        match self.summaries.is_empty() {
+            true => None,
+            false => Some(SummaryBatch {
+                summaries: std::mem::take(&mut self.summaries),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(sequence: u64) -> Summary {
+        Summary {
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    fn sequences(batches: &[SummaryBatch]) -> Vec<Vec<u64>> {
+        batches
+            .iter()
+            .map(|batch| batch.summaries.iter().map(|s| s.sequence).collect())
+            .collect()
+    }
+
+    #[test]
+    fn it_closes_batches_when_full_or_broken() {
+        let now = Instant::now();
+        let mut batcher = Batcher::new(3, 0).unwrap();
+        let mut batches = Vec::new();
+        for sequence in [1, 2, 3, 4, 5, 7, 8] {
+            batches.extend(batcher.push(summary(sequence), now));
+        }
+        batches.extend(batcher.take());
+        assert_eq!(sequences(&batches), [vec![1, 2, 3], vec![4, 5], vec![7, 8]]);
+        assert!(batcher.take().is_none());
+    }
+
+    #[test]
+    fn it_sends_each_batch_within_the_delay_of_its_first_summary() {
+        let now = Instant::now();
+        let mut batcher = Batcher::new(0, 0).unwrap();
+        assert_eq!(batcher.deadline(), None);
+        batcher.push(summary(1), now);
+        batcher.push(summary(2), now + Duration::from_millis(15));
+        assert_eq!(batcher.deadline(), Some(now + DEFAULT_MAX_DELAY));
+        batcher.take();
+        assert_eq!(batcher.deadline(), None);
+        batcher.push(summary(3), now + Duration::from_millis(30));
+        assert_eq!(
+            batcher.deadline(),
+            Some(now + Duration::from_millis(30) + DEFAULT_MAX_DELAY)
+        );
+
+        assert!(Batcher::new(MAX_BATCH + 1, 0).is_err());
+        assert!(Batcher::new(0, 1001).is_err());
+    }
+}
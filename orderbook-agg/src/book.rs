@@ -0,0 +1,538 @@
+//! Every level of each exchange's book within its [Depth](crate::core::order_book::Depth),
+//! served by `WatchBook` as the whole book and then the changes to it.
+//!
+//! The summary task publishes each exchange's latest levels on [Books]. Each client's
+//! stream works out the changes from what it last sent to the latest publication, so
+//! publications it's too slow for are folded into the next update rather than queued.
+//! Depth is taken along with the top levels, so changes further out reach clients along
+//! with the next change at the top.
+//!
+//! An exchange's book rebuilt from a new snapshot is sent as a whole again, flagged as a
+//! snapshot, so clients never carry levels over from before the rebuild. Clients keep
+//! their copy of the book with [LocalBook].
+use anyhow::{bail, ensure, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+use tonic::Status;
+
+use crate::{
+    book_summary::{book_change::Kind, BookChange, BookUpdate, Level, MarketType},
+    core::order_book::BookLevels,
+    Exchange, Symbol,
+};
+
+/// The latest levels of an exchange's book
+#[derive(Debug, Clone)]
+pub struct PublishedBook {
+    /// Goes up each time the exchange's connection closes, so a book rebuilt from a new
+    /// snapshot can be told apart from changes to the last one
+    pub connection: u64,
+    pub levels: Arc<BookLevels>,
+}
+
+pub type PublishedBooks = Arc<HashMap<Exchange, PublishedBook>>;
+
+pub type BooksReceiver = watch::Receiver<PublishedBooks>;
+
+type BooksSender = watch::Sender<PublishedBooks>;
+
+/// Where the summary task publishes the books of each exchange with levels, by symbol.
+/// Clones share the same books.
+#[derive(Debug, Clone, Default)]
+pub struct Books {
+    senders: Arc<Mutex<HashMap<Symbol, BooksSender>>>,
+}
+
+impl Books {
+    /// Replaces the books of `symbol`
+    pub fn publish(&self, symbol: Symbol, books: HashMap<Exchange, PublishedBook>) {
+        let mut senders = self.senders.lock().unwrap();
+        let _ = Self::sender(&mut senders, symbol).send_replace(Arc::new(books));
+    }
+
+    /// Drops the books of `symbol`, ending the streams of their receivers
+    pub fn remove(&self, symbol: Symbol) {
+        self.senders.lock().unwrap().remove(&symbol);
+    }
+
+    /// A receiver of the books of `symbol`, which are empty until they're first published
+    pub fn subscribe(&self, symbol: Symbol) -> BooksReceiver {
+        let mut senders = self.senders.lock().unwrap();
+        Self::sender(&mut senders, symbol).subscribe()
+    }
+
+    fn sender(senders: &mut HashMap<Symbol, BooksSender>, symbol: Symbol) -> &BooksSender {
+        senders
+            .entry(symbol)
+            .or_insert_with(|| watch::channel(Arc::default()).0)
+    }
+}
+
+/// The price and quantity of each bid and ask of `book_levels`, best first. Books without
+/// depth only have their top levels.
+fn levels(book_levels: &BookLevels) -> [Vec<[f64; 2]>; 2] {
+    match &book_levels.depth {
+        Some(depth) => [depth.bids().collect(), depth.asks().collect()],
+        None => {
+            let amounts = |levels: &[Level]| {
+                levels
+                    .iter()
+                    .map(|level| [level.price, level.quantity])
+                    .collect()
+            };
+            [amounts(&book_levels.bids), amounts(&book_levels.asks)]
+        }
+    }
+}
+
+fn change(kind: Kind, exchange: Exchange, bid: bool, [price, quantity]: [f64; 2]) -> BookChange {
+    BookChange {
+        kind: kind as i32,
+        exchange: exchange.to_string(),
+        bid,
+        price,
+        quantity,
+    }
+}
+
+/// Adds the changes taking one side of an exchange's book from `before` to `after` to
+/// `changes`, both best first.
+fn diff(
+    exchange: Exchange,
+    bid: bool,
+    before: &[[f64; 2]],
+    after: &[[f64; 2]],
+    changes: &mut Vec<BookChange>,
+) {
+    let ahead = |a: f64, b: f64| if bid { a > b } else { a < b };
+    let (mut i, mut j) = (0, 0);
+    loop {
+        match (before.get(i), after.get(j)) {
+            (Some(old), Some(new)) if old[0] == new[0] => {
+                if old[1] != new[1] {
+                    changes.push(change(Kind::Change, exchange, bid, *new));
+                }
+                i += 1;
+                j += 1;
+            }
+            (Some(old), Some(new)) if ahead(new[0], old[0]) => {
+                changes.push(change(Kind::Add, exchange, bid, *new));
+                j += 1;
+            }
+            (None, Some(new)) => {
+                changes.push(change(Kind::Add, exchange, bid, *new));
+                j += 1;
+            }
+            (Some(&[price, _]), _) => {
+                changes.push(change(Kind::Delete, exchange, bid, [price, 0.0]));
+                i += 1;
+            }
+            (None, None) => return,
+        }
+    }
+}
+
+/// The levels last sent of an exchange's book
+#[derive(Debug)]
+struct Sent {
+    bids: Vec<[f64; 2]>,
+    asks: Vec<[f64; 2]>,
+}
+
+/// The updates of one client's stream, made by comparing each publication of the books to
+/// what the client was last sent
+#[derive(Debug)]
+pub struct BookChanges {
+    symbol: Symbol,
+    exchanges: Vec<Exchange>,
+    sequence: u64,
+    sent: HashMap<Exchange, Sent>,
+    /// The last connection seen of each exchange, kept after its book goes
+    connections: HashMap<Exchange, u64>,
+}
+
+impl BookChanges {
+    /// Updates of the books of `exchanges`, or of every exchange when empty
+    pub fn new(symbol: Symbol, exchanges: Vec<Exchange>) -> Self {
+        Self {
+            symbol,
+            exchanges,
+            sequence: 0,
+            sent: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    /// The update taking the client's book to `books`, or `None` when nothing changed.
+    /// The first update, and the first after an exchange's book is rebuilt, is a snapshot.
+    pub fn update(&mut self, books: &HashMap<Exchange, PublishedBook>) -> Option<BookUpdate> {
+        let mut books = books
+            .iter()
+            .filter(|(exchange, _)| self.exchanges.is_empty() || self.exchanges.contains(exchange))
+            .collect::<Vec<_>>();
+        books.sort_by_key(|(exchange, _)| exchange.to_string());
+
+        let mut snapshot = self.sequence == 0;
+        for (exchange, book) in books.iter() {
+            let last = self.connections.insert(**exchange, book.connection);
+            snapshot |= last.is_some_and(|last| last != book.connection);
+        }
+
+        let mut changes = Vec::new();
+        if snapshot {
+            self.sent.clear();
+        } else {
+            let mut gone = self
+                .sent
+                .keys()
+                .filter(|exchange| books.iter().all(|(e, _)| e != exchange))
+                .copied()
+                .collect::<Vec<_>>();
+            gone.sort_by_key(|exchange| exchange.to_string());
+            for exchange in gone {
+                let sent = self.sent.remove(&exchange).unwrap();
+                diff(exchange, true, &sent.bids, &[], &mut changes);
+                diff(exchange, false, &sent.asks, &[], &mut changes);
+            }
+        }
+        for (&exchange, book) in books {
+            let [bids, asks] = levels(&book.levels);
+            let sent = self.sent.get(&exchange);
+            diff(
+                exchange,
+                true,
+                sent.map_or(&[], |sent| &sent.bids),
+                &bids,
+                &mut changes,
+            );
+            diff(
+                exchange,
+                false,
+                sent.map_or(&[], |sent| &sent.asks),
+                &asks,
+                &mut changes,
+            );
+            self.sent.insert(exchange, Sent { bids, asks });
+        }
+
+        if !snapshot && changes.is_empty() {
+            return None;
+        }
+        self.sequence += 1;
+        Some(BookUpdate {
+            symbol: self.symbol.to_string(),
+            sequence: self.sequence,
+            snapshot,
+            changes,
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+        })
+    }
+}
+
+/// Sends the updates of `changes` made from the books on `rx_books`. Returns once the
+/// books end or the client goes away.
+pub async fn watch_book(
+    mut rx_books: BooksReceiver,
+    mut changes: BookChanges,
+    tx: mpsc::Sender<Result<BookUpdate, Status>>,
+) {
+    loop {
+        let books = rx_books.borrow_and_update().clone();
+        if let Some(update) = changes.update(&books) {
+            if tx.send(Ok(update)).await.is_err() {
+                return;
+            }
+        }
+        select! {
+            changed = rx_books.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// A client's copy of the book streamed by `WatchBook`, kept by applying each update in
+/// turn
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LocalBook {
+    sequence: u64,
+    // Keyed by the bits of each price, which sort like the prices do as they're all
+    // positive, and the exchange quoting it.
+    bids: BTreeMap<(u64, String), f64>,
+    asks: BTreeMap<(u64, String), f64>,
+}
+
+impl LocalBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sequence of the last update applied
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Applies `update`, failing if it doesn't follow the last one or changes levels the
+    /// book doesn't have, after which the book can only be recovered by a snapshot. A
+    /// snapshot replaces the book whatever came before, so a book can be carried over to a
+    /// new stream.
+    pub fn apply(&mut self, update: &BookUpdate) -> Result<()> {
+        if update.snapshot {
+            self.bids.clear();
+            self.asks.clear();
+        } else {
+            ensure!(
+                self.sequence > 0 && update.sequence == self.sequence + 1,
+                "book update {} doesn't follow {}",
+                update.sequence,
+                self.sequence
+            );
+        }
+        for change in update.changes.iter() {
+            let levels = if change.bid {
+                &mut self.bids
+            } else {
+                &mut self.asks
+            };
+            let key = (change.price.to_bits(), change.exchange.clone());
+            let held = levels.contains_key(&key);
+            match change.kind() {
+                Kind::Add if held => bail!(
+                    "{} {} is already in the book",
+                    change.exchange,
+                    change.price
+                ),
+                Kind::Change | Kind::Delete if !held => {
+                    bail!("{} {} isn't in the book", change.exchange, change.price)
+                }
+                Kind::Add | Kind::Change => {
+                    levels.insert(key, change.quantity);
+                }
+                Kind::Delete => {
+                    levels.remove(&key);
+                }
+            }
+        }
+        self.sequence = update.sequence;
+        Ok(())
+    }
+
+    /// The bids best first, the exchanges at the same price in alphabetical order
+    pub fn bids(&self) -> Vec<Level> {
+        let mut bids = to_levels(&self.bids);
+        bids.sort_by(|a, b| {
+            b.price
+                .total_cmp(&a.price)
+                .then_with(|| a.exchange.cmp(&b.exchange))
+        });
+        bids
+    }
+
+    /// The asks best first, the exchanges at the same price in alphabetical order
+    pub fn asks(&self) -> Vec<Level> {
+        to_levels(&self.asks)
+    }
+}
+
+fn to_levels(levels: &BTreeMap<(u64, String), f64>) -> Vec<Level> {
+    levels
+        .iter()
+        .map(|((price, exchange), &quantity)| Level {
+            exchange: exchange.clone(),
+            price: f64::from_bits(*price),
+            quantity,
+            updated_at: None,
+            market_type: exchange
+                .parse::<Exchange>()
+                .map_or(MarketType::Spot, |exchange| exchange.market_type())
+                .into(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(
+        exchange: Exchange,
+        connection: u64,
+        bids: &[[f64; 2]],
+        asks: &[[f64; 2]],
+    ) -> PublishedBook {
+        let levels = |levels: &[[f64; 2]]| {
+            levels
+                .iter()
+                .map(|&[price, quantity]| Level {
+                    exchange: exchange.to_string(),
+                    price,
+                    quantity,
+                    updated_at: None,
+                    market_type: exchange.market_type().into(),
+                })
+                .collect()
+        };
+        PublishedBook {
+            connection,
+            levels: Arc::new(BookLevels {
+                exchange,
+                symbol: Symbol::BTCUSDT,
+                last_update_id: 0,
+                bids: levels(bids),
+                asks: levels(asks),
+                depth: None,
+                restored: false,
+            }),
+        }
+    }
+
+    fn books(books: Vec<PublishedBook>) -> HashMap<Exchange, PublishedBook> {
+        books
+            .into_iter()
+            .map(|book| (book.levels.exchange, book))
+            .collect()
+    }
+
+    /// The kind, side, price and quantity of each change
+    fn ops(update: &BookUpdate) -> Vec<(Kind, bool, f64, f64)> {
+        update
+            .changes
+            .iter()
+            .map(|c| (c.kind(), c.bid, c.price, c.quantity))
+            .collect()
+    }
+
+    #[test]
+    fn it_sends_the_changes_since_the_last_update() {
+        let mut changes = BookChanges::new(Symbol::BTCUSDT, vec![]);
+        let first = changes
+            .update(&books(vec![book(
+                Exchange::BITSTAMP,
+                0,
+                &[[100.0, 1.0], [99.0, 2.0]],
+                &[[101.0, 1.0]],
+            )]))
+            .unwrap();
+        assert!(first.snapshot);
+        assert_eq!(first.sequence, 1);
+        assert_eq!(
+            ops(&first),
+            [
+                (Kind::Add, true, 100.0, 1.0),
+                (Kind::Add, true, 99.0, 2.0),
+                (Kind::Add, false, 101.0, 1.0),
+            ]
+        );
+
+        // 100 goes, 99 changes, 98 and 99.5 arrive and the ask is the same.
+        let second = changes
+            .update(&books(vec![book(
+                Exchange::BITSTAMP,
+                0,
+                &[[99.5, 1.0], [99.0, 3.0], [98.0, 1.0]],
+                &[[101.0, 1.0]],
+            )]))
+            .unwrap();
+        assert!(!second.snapshot);
+        assert_eq!(second.sequence, 2);
+        assert_eq!(
+            ops(&second),
+            [
+                (Kind::Delete, true, 100.0, 0.0),
+                (Kind::Add, true, 99.5, 1.0),
+                (Kind::Change, true, 99.0, 3.0),
+                (Kind::Add, true, 98.0, 1.0),
+            ]
+        );
+
+        // Nothing changed, nothing's sent.
+        assert_eq!(
+            changes.update(&books(vec![book(
+                Exchange::BITSTAMP,
+                0,
+                &[[99.5, 1.0], [99.0, 3.0], [98.0, 1.0]],
+                &[[101.0, 1.0]],
+            )])),
+            None
+        );
+    }
+
+    #[test]
+    fn it_sends_a_snapshot_once_a_book_is_rebuilt() {
+        let mut changes = BookChanges::new(Symbol::BTCUSDT, vec![Exchange::BITSTAMP]);
+        let mut local = LocalBook::new();
+        let bitstamp = book(Exchange::BITSTAMP, 0, &[[100.0, 1.0]], &[[101.0, 1.0]]);
+        // Binance isn't asked for.
+        let binance = book(Exchange::BINANCE, 0, &[[100.5, 1.0]], &[[100.6, 1.0]]);
+        local
+            .apply(
+                &changes
+                    .update(&books(vec![bitstamp, binance.clone()]))
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(local.bids().len(), 1);
+
+        // Bitstamp's connection closes, taking its levels away.
+        let update = changes.update(&books(vec![binance.clone()])).unwrap();
+        assert!(!update.snapshot);
+        local.apply(&update).unwrap();
+        assert_eq!((local.bids(), local.asks()), (vec![], vec![]));
+
+        // The book rebuilt after it reconnects is a snapshot.
+        let rebuilt = book(Exchange::BITSTAMP, 1, &[[99.0, 2.0]], &[[102.0, 2.0]]);
+        let update = changes.update(&books(vec![rebuilt, binance])).unwrap();
+        assert!(update.snapshot);
+        assert_eq!(update.sequence, 3);
+        local.apply(&update).unwrap();
+        assert_eq!(
+            local.bids(),
+            [Level {
+                exchange: "BITSTAMP".to_string(),
+                price: 99.0,
+                quantity: 2.0,
+                updated_at: None,
+                market_type: MarketType::Spot.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn it_rejects_updates_that_dont_follow_the_book() {
+        let mut local = LocalBook::new();
+        let update = |sequence, snapshot, kind: Kind, price| BookUpdate {
+            sequence,
+            snapshot,
+            changes: vec![change(kind, Exchange::BINANCE, true, [price, 1.0])],
+            ..Default::default()
+        };
+        let err = local
+            .apply(&update(1, false, Kind::Add, 100.0))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "book update 1 doesn't follow 0");
+        local.apply(&update(1, true, Kind::Add, 100.0)).unwrap();
+        let err = local.apply(&update(3, false, Kind::Add, 99.0)).unwrap_err();
+        assert_eq!(err.to_string(), "book update 3 doesn't follow 1");
+        let err = local
+            .apply(&update(2, false, Kind::Add, 100.0))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "BINANCE 100 is already in the book");
+        let err = local
+            .apply(&update(2, false, Kind::Delete, 99.0))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "BINANCE 99 isn't in the book");
+        // A snapshot starts the book over.
+        local.apply(&update(7, true, Kind::Add, 98.0)).unwrap();
+        assert_eq!(local.sequence(), 7);
+        assert_eq!(local.bids()[0].price, 98.0);
+    }
+}
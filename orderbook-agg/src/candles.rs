@@ -0,0 +1,333 @@
+//! Candles of the mid price of a symbol's summaries, built for each `WatchCandles` client
+//! from the summaries the summary task publishes.
+//!
+//! Intervals start on multiples of their length since the unix epoch, so candles line up
+//! with the wall clock whenever a client starts watching. An interval without summaries
+//! still gets a candle, flagged empty and carrying the last close forward.
+use anyhow::{ensure, Result};
+use std::time::Duration;
+use tokio::{
+    select,
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+use tonic::Status;
+
+use crate::{
+    book_summary::{Candle, Summary},
+    service::SummaryReceiver,
+    Symbol,
+};
+
+/// The intervals candles can be built for, in milliseconds
+pub const INTERVALS_MS: [u32; 3] = [1000, 5000, 60_000];
+
+/// The candle of the interval in progress, and the intervals closed since
+#[derive(Debug)]
+pub struct CandleBuilder {
+    symbol: Symbol,
+    interval_ms: u32,
+    current: Option<Candle>,
+}
+
+impl CandleBuilder {
+    pub fn new(symbol: Symbol, interval_ms: u32) -> Result<Self> {
+        ensure!(
+            INTERVALS_MS.contains(&interval_ms),
+            "interval_ms must be 1000, 5000 or 60000"
+        );
+        Ok(Self {
+            symbol,
+            interval_ms,
+            current: None,
+        })
+    }
+
+    /// When the interval in progress closes, in milliseconds since the unix epoch. There's
+    /// no interval in progress until the first update.
+    pub fn closes_at(&self) -> Option<u64> {
+        self.current
+            .as_ref()
+            .map(|candle| candle.open_time_ms + self.interval_ms as u64)
+    }
+
+    /// Closes the intervals that ended by `now_ms`, returning their candles finalized. The
+    /// intervals after the last update get empty candles at its close.
+    pub fn advance(&mut self, now_ms: u64) -> Vec<Candle> {
+        let open_time_ms = self.open_time(now_ms);
+        let mut closed = Vec::new();
+        let Some(current) = self.current.as_mut() else {
+            return closed;
+        };
+        while current.open_time_ms < open_time_ms {
+            closed.push(Candle {
+                finalized: true,
+                ..current.clone()
+            });
+            *current = Candle {
+                open_time_ms: current.open_time_ms + self.interval_ms as u64,
+                open: current.close,
+                high: current.close,
+                low: current.close,
+                spread_open: current.spread_close,
+                spread_high: current.spread_close,
+                spread_low: current.spread_close,
+                updates: 0,
+                empty: true,
+                ..current.clone()
+            };
+        }
+        closed
+    }
+
+    /// Adds a summary's `mid` and `spread` at `now_ms`, returning the candles of the
+    /// intervals closed since the last update and then the candle in progress.
+    pub fn update(&mut self, now_ms: u64, mid: f64, spread: f64) -> (Vec<Candle>, Candle) {
+        let closed = self.advance(now_ms);
+        let open_time_ms = self.open_time(now_ms);
+        let candle = self.current.get_or_insert_with(|| Candle {
+            symbol: self.symbol.to_string(),
+            interval_ms: self.interval_ms,
+            open_time_ms,
+            empty: true,
+            ..Default::default()
+        });
+        if candle.empty {
+            candle.open = mid;
+            candle.high = mid;
+            candle.low = mid;
+            candle.spread_open = spread;
+            candle.spread_high = spread;
+            candle.spread_low = spread;
+            candle.empty = false;
+        }
+        candle.high = candle.high.max(mid);
+        candle.low = candle.low.min(mid);
+        candle.close = mid;
+        candle.spread_high = candle.spread_high.max(spread);
+        candle.spread_low = candle.spread_low.min(spread);
+        candle.spread_close = spread;
+        candle.updates += 1;
+        (closed, candle.clone())
+    }
+
+    fn open_time(&self, now_ms: u64) -> u64 {
+        now_ms - now_ms % self.interval_ms as u64
+    }
+}
+
+/// The mid price and spread of `summary`, unless one side is empty
+fn mid_and_spread(summary: &Summary) -> Option<(f64, f64)> {
+    let bid = summary.bids.first()?;
+    let ask = summary.asks.first()?;
+    Some(((bid.price + ask.price) / 2.0, summary.spread))
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// Sends the candles of `builder` made from the summaries on `rx_summary`: the one in
+/// progress with each summary, or at most every `throttle` when it's more than zero, and
+/// each one as its interval closes. Returns once the summaries end or the client goes
+/// away.
+pub async fn watch_candles(
+    mut rx_summary: SummaryReceiver,
+    mut builder: CandleBuilder,
+    throttle: Duration,
+    tx: mpsc::Sender<Result<Candle, Status>>,
+) {
+    let mut fresh = true;
+    let mut last_sent: Option<Instant> = None;
+    let mut pending = None;
+    loop {
+        if fresh {
+            let update = match &*rx_summary.borrow_and_update() {
+                // The placeholder published before the first summary has no prices.
+                Ok(summary) if summary.sequence == 0 => None,
+                Ok(summary) => mid_and_spread(summary),
+                // Lost feeds leave the intervals empty until they're back.
+                Err(_) => None,
+            };
+            if let Some((mid, spread)) = update {
+                let (closed, candle) = builder.update(now_ms(), mid, spread);
+                for candle in closed {
+                    if tx.send(Ok(candle)).await.is_err() {
+                        return;
+                    }
+                }
+                let due = last_sent.is_none_or(|sent| sent.elapsed() >= throttle);
+                if due {
+                    if tx.send(Ok(candle)).await.is_err() {
+                        return;
+                    }
+                    last_sent = Some(Instant::now());
+                    pending = None;
+                } else {
+                    pending = Some(candle);
+                }
+            }
+            fresh = false;
+        }
+
+        let closes_at = builder.closes_at();
+        let closes = async {
+            match closes_at {
+                Some(at) => {
+                    let wait = Duration::from_millis(at.saturating_sub(now_ms()));
+                    sleep_until(Instant::now() + wait).await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let throttled_until = last_sent.filter(|_| pending.is_some());
+        let throttled = async {
+            match throttled_until {
+                Some(sent) => sleep_until(sent + throttle).await,
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            changed = rx_summary.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                fresh = true;
+            }
+            _ = closes => {
+                // The candle held back by the throttle is sent finalized instead.
+                pending = None;
+                for candle in builder.advance(now_ms()) {
+                    if tx.send(Ok(candle)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = throttled => {
+                if let Some(candle) = pending.take() {
+                    if tx.send(Ok(candle)).await.is_err() {
+                        return;
+                    }
+                }
+                last_sent = Some(Instant::now());
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The open time, prices and flags of each candle
+    fn ohlc(candles: &[Candle]) -> Vec<(u64, [f64; 4], bool, bool)> {
+        candles
+            .iter()
+            .map(|c| {
+                (
+                    c.open_time_ms,
+                    [c.open, c.high, c.low, c.close],
+                    c.finalized,
+                    c.empty,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_buckets_mids_into_intervals_aligned_to_the_clock() {
+        let mut builder = CandleBuilder::new(Symbol::BTCUSDT, 1000).unwrap();
+        assert_eq!(builder.closes_at(), None);
+        let mut closed = Vec::new();
+        let mut latest = None;
+        for (now_ms, mid) in [
+            (10_250, 100.0),
+            (10_500, 103.0),
+            (10_999, 99.0),
+            // The first mid of the next interval opens a new candle.
+            (11_000, 101.0),
+            (11_700, 102.0),
+        ] {
+            let (done, candle) = builder.update(now_ms, mid, 0.5);
+            closed.extend(done);
+            latest = Some(candle);
+        }
+        assert_eq!(
+            ohlc(&closed),
+            [(10_000, [100.0, 103.0, 99.0, 99.0], true, false)]
+        );
+        assert_eq!(closed[0].updates, 3);
+        let latest = latest.unwrap();
+        assert_eq!(
+            ohlc(std::slice::from_ref(&latest)),
+            [(11_000, [101.0, 102.0, 101.0, 102.0], false, false)]
+        );
+        assert_eq!(latest.symbol, "BTCUSDT");
+        assert_eq!(builder.closes_at(), Some(12_000));
+    }
+
+    #[test]
+    fn it_carries_the_close_through_empty_intervals() {
+        let mut builder = CandleBuilder::new(Symbol::BTCUSDT, 5000).unwrap();
+        builder.update(3_000, 100.0, 1.0);
+        builder.update(4_000, 104.0, 2.0);
+        // Nothing closes until the interval has ended.
+        assert!(builder.advance(4_999).is_empty());
+
+        // The next mid is two intervals later, straddling the empty one.
+        let (closed, candle) = builder.update(12_000, 98.0, 1.5);
+        assert_eq!(
+            ohlc(&closed),
+            [
+                (0, [100.0, 104.0, 100.0, 104.0], true, false),
+                (5_000, [104.0; 4], true, true),
+            ]
+        );
+        assert_eq!(closed[1].spread_close, 2.0);
+        assert_eq!(closed[1].updates, 0);
+        // The candle after a gap opens at its first mid rather than the carried close.
+        assert_eq!(
+            ohlc(&[candle]),
+            [(10_000, [98.0, 98.0, 98.0, 98.0], false, false)]
+        );
+
+        // Closing on the clock alone leaves empty candles behind too.
+        let closed = builder.advance(21_000);
+        assert_eq!(
+            ohlc(&closed),
+            [
+                (10_000, [98.0; 4], true, false),
+                (15_000, [98.0; 4], true, true),
+            ]
+        );
+        assert_eq!(builder.closes_at(), Some(25_000));
+    }
+
+    #[test]
+    fn it_tracks_the_range_of_the_spread() {
+        let mut builder = CandleBuilder::new(Symbol::BTCUSDT, 60_000).unwrap();
+        for spread in [2.0, 0.5, 3.0, 1.0] {
+            builder.update(120_000, 100.0, spread);
+        }
+        let (_, candle) = builder.update(179_999, 100.0, 1.5);
+        assert_eq!(
+            [
+                candle.spread_open,
+                candle.spread_high,
+                candle.spread_low,
+                candle.spread_close
+            ],
+            [2.0, 3.0, 0.5, 1.5]
+        );
+        assert_eq!(candle.updates, 5);
+    }
+
+    #[test]
+    fn it_only_builds_the_intervals_supported() {
+        for interval_ms in [0, 500, 2000, 3_600_000] {
+            let err = CandleBuilder::new(Symbol::BTCUSDT, interval_ms).unwrap_err();
+            assert_eq!(err.to_string(), "interval_ms must be 1000, 5000 or 60000");
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! The checksum on each summary the server sends, for clients to check the levels they
+//! received are the ones it sent.
+//!
+//! The checksum is the CRC-32 (IEEE, as in zlib and gzip) of the summary's levels written
+//! out as text: each level as `price:quantity`, the bids joined with `,`, then `|`, then
+//! the asks joined with `,`. Prices and quantities are written as the shortest decimal that
+//! reads back as the same double, with no exponent and no trailing zeros or point, so a
+//! quantity of 2.0 is `2` and one of 1e-8 is `0.00000001`. A summary with one bid and no
+//! asks is written `30000:1.5|`.
+//!
+//! Clients get the doubles on the wire, so the text has to be made from those rather than
+//! from any rounding of them for display, or the checksum won't match.
+use anyhow::{ensure, Result};
+use std::fmt::Write;
+
+use crate::book_summary::{Level, Summary};
+
+/// The text of the levels of `summary` the checksum is taken over
+pub fn canonical(summary: &Summary) -> String {
+    let mut text = String::new();
+    let mut side = |levels: &[Level]| {
+        for (i, level) in levels.iter().enumerate() {
+            if i > 0 {
+                text.push(',');
+            }
+            // Display of f64 is the shortest round trip without an exponent.
+            let _ = write!(text, "{}:{}", level.price, level.quantity);
+        }
+    };
+    side(&summary.bids);
+    text.push('|');
+    side(&summary.asks);
+    text
+}
+
+/// The checksum of the levels of `summary`
+pub fn checksum(summary: &Summary) -> u32 {
+    crc32(canonical(summary).as_bytes())
+}
+
+/// Sets the checksum of `summary` to that of its levels, once they're the ones to send
+pub fn seal(summary: &mut Summary) {
+    summary.checksum = checksum(summary);
+}
+
+/// Checks the levels of a summary received match its checksum
+pub fn verify(summary: &Summary) -> Result<()> {
+    let expected = checksum(summary);
+    ensure!(
+        summary.checksum == expected,
+        "checksum {:08x} of summary {} doesn't match its levels, expected {:08x}",
+        summary.checksum,
+        summary.sequence,
+        expected
+    );
+    Ok(())
+}
+
+/// The reflected CRC-32 of `bytes` with polynomial 0xedb88320
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::MarketType;
+
+    fn summary(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Summary {
+        let levels = |levels: &[(f64, f64)]| {
+            levels
+                .iter()
+                .map(|&(price, quantity)| Level {
+                    exchange: "BINANCE".to_string(),
+                    price,
+                    quantity,
+                    updated_at: None,
+                    market_type: MarketType::Spot.into(),
+                })
+                .collect()
+        };
+        Summary {
+            bids: levels(bids),
+            asks: levels(asks),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_takes_the_crc32_of_the_check_string() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn it_matches_the_golden_checksums() {
+        let cases = [
+            (
+                summary(&[(30000.25, 1.5), (30000.0, 0.125)], &[(30000.75, 2.0)]),
+                "30000.25:1.5,30000:0.125|30000.75:2",
+                2593596605,
+            ),
+            (summary(&[], &[]), "|", 2343686810),
+            (
+                summary(&[(0.00000001, 100000000.0)], &[]),
+                "0.00000001:100000000|",
+                1189394749,
+            ),
+        ];
+        for (summary, text, expected) in cases {
+            assert_eq!(canonical(&summary), text);
+            assert_eq!(checksum(&summary), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn it_fails_summaries_whose_levels_changed() {
+        let mut summary = summary(&[(30000.25, 1.5)], &[(30000.75, 2.0)]);
+        seal(&mut summary);
+        verify(&summary).unwrap();
+        // The exchange isn't part of the checksum, only the prices and quantities.
+        summary.bids[0].exchange = "BITSTAMP".to_string();
+        verify(&summary).unwrap();
+        summary.bids[0].quantity = 1.25;
+        assert!(verify(&summary).is_err());
+        summary.bids[0].quantity = 1.5;
+        summary.asks.push(summary.bids[0].clone());
+        assert!(verify(&summary).is_err());
+    }
+}
@@ -1,54 +1,627 @@
-use anyhow::Result;
-use clap::Parser;
-use tokio_stream::StreamExt;
+//! A client for other Rust programs, connecting to a server and streaming its summaries.
+//! Streams reconnect and subscribe again when the server goes away, see
+//! [OrderbookClient::watch].
+//!
+//! ```no_run
+//! use orderbook_agg::client::{OrderbookClient, SummaryOptions};
+//! use tokio_stream::StreamExt;
+//!
+//! # async fn run() -> Result<(), orderbook_agg::client::ClientError> {
+//! let client = OrderbookClient::connect("127.0.0.1:9001").await?;
+//! let mut summaries = client.watch("btcusdt", SummaryOptions::new().with_levels(5));
+//! while let Some(summary) = summaries.next().await {
+//!     println!("{}", summary?.spread);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{collections::HashMap, error::Error, fmt, time::Duration};
 
-use orderbook_agg::book_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, Empty};
+use tokio::{sync::mpsc, time::Instant};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{Channel, Endpoint},
+    Code, Request, Status, Streaming,
+};
 
-#[derive(Debug, Parser)]
-struct Options {
-    #[clap(subcommand)]
-    command: Command,
+use crate::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient, Empty, ServerInfo, Summary,
+        WatchSummaryRequest,
+    },
+    exchanges::options::DepthSpeed,
+    service::Backoff,
+    Exchange, Symbol,
+};
+
+pub use crate::book::LocalBook;
+
+/// What went wrong talking to the server, from the status it returned where there was one
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    /// The server couldn't be reached or went away, or has no summary while every
+    /// exchange is down
+    Unavailable(String),
+    /// The server rejected the options, e.g. a negative weight
+    InvalidArgument(String),
+    /// The server doesn't serve the symbol, or an exchange asked for
+    NotServed(String),
+    /// The server didn't accept the token
+    Unauthenticated(String),
+    /// The endpoint or token couldn't be used
+    InvalidConfig(String),
+    /// Any other status
+    Status { code: Code, message: String },
+}
+
+impl ClientError {
+    /// Whether trying again could succeed without changing anything
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Unavailable(_))
+    }
 }
 
-#[derive(Debug, Parser)]
-enum Command {
-    WatchSummary,
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        match status.code() {
+            Code::Unavailable => Self::Unavailable(message),
+            // Connections that drop part way through a stream end with an unknown status.
+            Code::Unknown if status.source().is_some() => Self::Unavailable(describe(&status)),
+            Code::InvalidArgument => Self::InvalidArgument(message),
+            Code::NotFound | Code::FailedPrecondition => Self::NotServed(message),
+            Code::Unauthenticated | Code::PermissionDenied => Self::Unauthenticated(message),
+            code => Self::Status { code, message },
+        }
+    }
 }
 
-#[derive(Debug, Parser)]
-struct SummaryOptions {
-    #[clap(long)]
-    symbol: String,
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unavailable(message) => write!(f, "unavailable: {}", message),
+            Self::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            Self::NotServed(message) => write!(f, "not served: {}", message),
+            Self::Unauthenticated(message) => write!(f, "unauthenticated: {}", message),
+            Self::InvalidConfig(message) => write!(f, "invalid config: {}", message),
+            Self::Status { code, message } => write!(f, "{:?}: {}", code, message),
+        }
+    }
 }
 
-async fn watch_summary(
-    mut client: OrderbookAggregatorClient<tonic::transport::Channel>,
-) -> Result<()> {
-    let request = tonic::Request::new(Empty {});
+impl Error for ClientError {}
 
-    let mut stream = client.watch_summary(request).await?.into_inner();
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(summary) => println!("\n{:#?}", summary),
-            Err(err) => {
-                return Err(err.into());
-            }
+/// An error and its sources, which transport errors keep the useful part of.
+fn describe(err: &dyn Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        description.push_str(": ");
+        description.push_str(&err.to_string());
+        source = err.source();
+    }
+    description
+}
+
+/// What to receive in each summary. Anything not set is left to the server, so every
+/// level of every exchange at the rate summaries are published.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    levels: u32,
+    exchanges: Vec<Exchange>,
+    weights: HashMap<Exchange, f64>,
+    min_interval: Duration,
+    update_speed: Option<DepthSpeed>,
+    liquidity_bps: Vec<f64>,
+    depth_notional: f64,
+    symbol: Option<Symbol>,
+    smoothing_half_life: Duration,
+    backfill: u32,
+    max_batch: u32,
+    max_batch_delay: Duration,
+    bid_levels: Option<u32>,
+    ask_levels: Option<u32>,
+}
+
+impl SummaryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bid and ask levels to receive
+    pub fn with_levels(mut self, levels: u32) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    /// Number of bid levels to receive in place of those of [Self::with_levels], 0 for none
+    pub fn with_bid_levels(mut self, levels: u32) -> Self {
+        self.bid_levels = Some(levels);
+        self
+    }
+
+    /// Number of ask levels to receive in place of those of [Self::with_levels], 0 for none
+    pub fn with_ask_levels(mut self, levels: u32) -> Self {
+        self.ask_levels = Some(levels);
+        self
+    }
+
+    /// Only receive levels from these exchanges
+    pub fn with_exchanges(mut self, exchanges: impl IntoIterator<Item = Exchange>) -> Self {
+        self.exchanges = exchanges.into_iter().collect();
+        self
+    }
+
+    /// Multiplies an exchange's quantities by `weight`, 0 leaves it out
+    pub fn with_weight(mut self, exchange: Exchange, weight: f64) -> Self {
+        self.weights.insert(exchange, weight);
+        self
+    }
+
+    /// Receive at most one summary each `min_interval`, to the millisecond
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Binance depth update speed wanted
+    pub fn with_update_speed(mut self, update_speed: DepthSpeed) -> Self {
+        self.update_speed = Some(update_speed);
+        self
+    }
+
+    /// Basis points from the mid to receive the liquidity within
+    pub fn with_liquidity_bps(mut self, bps: impl IntoIterator<Item = f64>) -> Self {
+        self.liquidity_bps = bps.into_iter().collect();
+        self
+    }
+
+    /// Only receive the levels of each side it takes for their price times quantity to add
+    /// up to `notional`, in the quote currency
+    pub fn with_depth_notional(mut self, notional: f64) -> Self {
+        self.depth_notional = notional;
+        self
+    }
+
+    /// Watch `symbol` rather than the server's own, one of the symbols it keeps warm
+    pub fn with_symbol(mut self, symbol: Symbol) -> Self {
+        self.symbol = Some(symbol);
+        self
+    }
+
+    /// Receive the summaries' metrics smoothed with `half_life` along with the raw ones
+    pub fn with_smoothing_half_life(mut self, half_life: Duration) -> Self {
+        self.smoothing_half_life = half_life;
+        self
+    }
+
+    /// Receive up to `count` of the summaries the server kept from before the stream
+    /// started first, flagged as backfill
+    pub fn with_backfill(mut self, count: u32) -> Self {
+        self.backfill = count;
+        self
+    }
+
+    /// Receive at most `count` summaries in each batch of `WatchSummaryBatches`
+    pub fn with_max_batch(mut self, count: u32) -> Self {
+        self.max_batch = count;
+        self
+    }
+
+    /// Wait at most `delay` for summaries to join a batch of `WatchSummaryBatches`, to the
+    /// millisecond
+    pub fn with_max_batch_delay(mut self, delay: Duration) -> Self {
+        self.max_batch_delay = delay;
+        self
+    }
+
+    pub fn request(&self) -> WatchSummaryRequest {
+        WatchSummaryRequest {
+            levels: self.levels,
+            exchanges: self.exchanges.iter().map(|e| e.to_string()).collect(),
+            min_interval_ms: self.min_interval.as_millis().try_into().unwrap_or(u32::MAX),
+            update_speed_ms: self.update_speed.map_or(0, DepthSpeed::millis),
+            exchange_weights: self
+                .weights
+                .iter()
+                .map(|(exchange, weight)| (exchange.to_string(), *weight))
+                .collect(),
+            liquidity_bps: self.liquidity_bps.clone(),
+            depth_notional: self.depth_notional,
+            symbol: self
+                .symbol
+                .map(|symbol| symbol.to_string())
+                .unwrap_or_default(),
+            smoothing_half_life_ms: self
+                .smoothing_half_life
+                .as_millis()
+                .try_into()
+                .unwrap_or(u32::MAX),
+            backfill: self.backfill,
+            max_batch: self.max_batch,
+            max_batch_delay_ms: self
+                .max_batch_delay
+                .as_millis()
+                .try_into()
+                .unwrap_or(u32::MAX),
+            bid_levels: self.bid_levels,
+            ask_levels: self.ask_levels,
+        }
+    }
+}
+
+/// A connection to a server, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct OrderbookClient {
+    endpoint: Endpoint,
+    client: OrderbookAggregatorClient<Channel>,
+    token: Option<MetadataValue<Ascii>>,
+    reconnect: Option<Backoff>,
+}
+
+impl OrderbookClient {
+    /// Connects to `endpoint`, e.g. `127.0.0.1:9001` or `http://127.0.0.1:9001`.
+    pub async fn connect(endpoint: &str) -> Result<Self, ClientError> {
+        let endpoint = if endpoint.contains("://") {
+            endpoint.to_string()
+        } else {
+            format!("http://{}", endpoint)
         };
+        let endpoint = Endpoint::from_shared(endpoint.clone())
+            .map_err(|err| ClientError::InvalidConfig(format!("{}: {}", endpoint, err)))?;
+        let channel = connect(&endpoint).await?;
+        Ok(Self {
+            endpoint,
+            client: OrderbookAggregatorClient::new(channel),
+            token: None,
+            reconnect: Some(Backoff::default()),
+        })
     }
-    println!("stream closed");
 
-    Ok(())
+    /// Sends `token` as a bearer token with each request.
+    pub fn with_token(mut self, token: &str) -> Result<Self, ClientError> {
+        let token = format!("Bearer {}", token)
+            .parse()
+            .map_err(|_| ClientError::InvalidConfig("invalid token".to_string()))?;
+        self.token = Some(token);
+        Ok(self)
+    }
+
+    /// How [watch](Self::watch) streams reconnect after a [transient](ClientError::is_transient)
+    /// error, giving up once the server has been unavailable for the backoff's budget.
+    /// Streams end at the first error when `None`.
+    pub fn with_reconnect(mut self, reconnect: Option<Backoff>) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        request
+    }
+
+    pub async fn symbols(&self) -> Result<Vec<String>, ClientError> {
+        let request = self.request(Empty {});
+        let symbols = self.client.clone().get_symbols(request).await?;
+        Ok(symbols.into_inner().symbols)
+    }
+
+    pub async fn exchanges(&self) -> Result<Vec<String>, ClientError> {
+        let request = self.request(Empty {});
+        let exchanges = self.client.clone().get_exchanges(request).await?;
+        Ok(exchanges.into_inner().exchanges)
+    }
+
+    pub async fn server_info(&self) -> Result<ServerInfo, ClientError> {
+        let request = self.request(Empty {});
+        let info = self.client.clone().get_server_info(request).await?;
+        Ok(info.into_inner())
+    }
+
+    /// The latest summary published for `symbol`
+    pub async fn summary(
+        &self,
+        symbol: &str,
+        options: &SummaryOptions,
+    ) -> Result<Summary, ClientError> {
+        let mut stream = self.subscribe(symbol, options.request()).await?;
+        while let Some(summary) = stream.next().await {
+            let summary = summary?;
+            // The summary before the first one is published has nothing in it.
+            if summary.sequence > 0 {
+                return Ok(summary);
+            }
+        }
+        Err(ClientError::Unavailable(
+            "server ended the stream before sending a summary".to_string(),
+        ))
+    }
+
+    /// Streams summaries for `symbol`. When the connection drops or the server ends the
+    /// stream, the client reconnects and subscribes again with the same options, carrying
+    /// on from the server's latest summary. The stream ends after yielding an error that
+    /// isn't transient, or once reconnecting has taken longer than allowed, see
+    /// [with_reconnect](Self::with_reconnect).
+    pub fn watch(
+        &self,
+        symbol: &str,
+        options: SummaryOptions,
+    ) -> impl Stream<Item = Result<Summary, ClientError>> + Send + 'static {
+        let (tx, rx) = mpsc::channel(16);
+        let client = self.clone();
+        let symbol = symbol.to_string();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = client.forward(&symbol, options.request(), &tx) => {}
+                _ = tx.closed() => {}
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    /// Subscribes to `symbol`, failing first if the server doesn't serve it so the error
+    /// names the symbols it does.
+    async fn subscribe(
+        &self,
+        symbol: &str,
+        request: WatchSummaryRequest,
+    ) -> Result<Streaming<Summary>, ClientError> {
+        let symbols = self.symbols().await?;
+        if !symbols.iter().any(|s| s.eq_ignore_ascii_case(symbol)) {
+            return Err(ClientError::NotServed(format!(
+                "{} is not served, the server serves {}",
+                symbol,
+                symbols.join(", ")
+            )));
+        }
+        let request = self.request(request);
+        Ok(self
+            .client
+            .clone()
+            .watch_summary(request)
+            .await?
+            .into_inner())
+    }
+
+    async fn forward(
+        mut self,
+        symbol: &str,
+        request: WatchSummaryRequest,
+        tx: &mpsc::Sender<Result<Summary, ClientError>>,
+    ) {
+        let mut delay = self.reconnect.map(|backoff| backoff.initial);
+        let mut down_since = None;
+        loop {
+            let subscribed_at = Instant::now();
+            let err = match self.subscribe(symbol, request.clone()).await {
+                Ok(mut stream) => loop {
+                    match stream.next().await {
+                        Some(Ok(summary)) if summary.sequence == 0 => {}
+                        Some(Ok(summary)) => {
+                            down_since = None;
+                            if tx.send(Ok(summary)).await.is_err() {
+                                return;
+                            }
+                        }
+                        Some(Err(status)) => break ClientError::from(status),
+                        None => {
+                            break ClientError::Unavailable("server ended the stream".to_string())
+                        }
+                    }
+                },
+                Err(err) => err,
+            };
+
+            let (backoff, delay) = match (self.reconnect, delay.as_mut()) {
+                (Some(backoff), Some(delay)) if err.is_transient() => (backoff, delay),
+                _ => {
+                    let _ = tx.send(Err(err)).await;
+                    return;
+                }
+            };
+            let since = *down_since.get_or_insert(subscribed_at);
+            if since.elapsed() > backoff.budget {
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+            if subscribed_at.elapsed() > backoff.max {
+                *delay = backoff.initial;
+            }
+            tracing::warn!("{}, resubscribing to {} in {:?}", err, symbol, delay);
+            tokio::time::sleep(*delay).await;
+            *delay = (*delay * 2).min(backoff.max);
+            if let Ok(channel) = connect(&self.endpoint).await {
+                self.client = OrderbookAggregatorClient::new(channel);
+            }
+        }
+    }
+}
+
+async fn connect(endpoint: &Endpoint) -> Result<Channel, ClientError> {
+    endpoint
+        .connect()
+        .await
+        .map_err(|err| ClientError::Unavailable(format!("{}: {}", endpoint.uri(), describe(&err))))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let client = OrderbookAggregatorClient::connect("http://127.0.0.1:9001").await?;
-    let opts = Options::parse();
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
 
-    use Command::*;
-    match opts.command {
-        WatchSummary => watch_summary(client).await?,
+    use tokio::{net::TcpListener, sync::watch, task::JoinHandle, time::timeout};
+
+    use super::*;
+    use crate::{
+        book_summary::{orderbook_aggregator_server::OrderbookAggregatorServer, Level},
+        core::order_book::BookLevels,
+        service::{spawn_summary, OrderbookSummary},
+        Symbol,
     };
 
-    Ok(())
+    /// Serves Bitstamp levels priced at `price` on `addr` until `tx_shutdown` is sent.
+    async fn serve(addr: SocketAddr, price: f64) -> (watch::Sender<bool>, JoinHandle<()>) {
+        let (tx_levels, rx_levels) = mpsc::channel(100);
+        let (tx_closed, rx_closed) = mpsc::channel(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown.clone())
+            .with_exchanges(vec![Exchange::BITSTAMP]);
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server = tokio::spawn(async move {
+            let incoming =
+                tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+            let mut rx_shutdown = rx_shutdown;
+            tonic::transport::Server::builder()
+                .add_service(OrderbookAggregatorServer::new(service))
+                .serve_with_incoming_shutdown(incoming, async move {
+                    let _ = rx_shutdown.wait_for(|stop| *stop).await;
+                })
+                .await
+                .unwrap();
+            drop(tx_closed);
+        });
+        tokio::spawn(async move {
+            for id in 1.. {
+                let level = Level {
+                    exchange: Exchange::BITSTAMP.to_string(),
+                    price,
+                    quantity: 1.0,
+                    updated_at: None,
+                    market_type: Exchange::BITSTAMP.market_type().into(),
+                };
+                let levels = BookLevels {
+                    exchange: Exchange::BITSTAMP,
+                    symbol: Symbol::BTCUSDT,
+                    last_update_id: id,
+                    bids: vec![level.clone()],
+                    asks: vec![Level {
+                        price: price + 1.0,
+                        ..level
+                    }],
+                    depth: None,
+                    restored: false,
+                };
+                if tx_levels.send(Arc::new(levels)).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        (tx_shutdown, server)
+    }
+
+    fn quick_reconnect() -> Option<Backoff> {
+        Some(Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_millis(200),
+            budget: Duration::from_secs(10),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_resubscribes_when_the_server_restarts() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx_shutdown, server) = serve(addr, 30000.0).await;
+        let client = OrderbookClient::connect(&addr.to_string())
+            .await
+            .unwrap()
+            .with_reconnect(quick_reconnect());
+
+        let options = SummaryOptions::new().with_levels(1);
+        let summary = client.summary("btcusdt", &options).await.unwrap();
+        assert_eq!(summary.bids[0].price, 30000.0);
+
+        let mut summaries = client.watch("btcusdt", options);
+        let summary = summaries.next().await.unwrap().unwrap();
+        assert_eq!(summary.bids[0].price, 30000.0);
+
+        tx_shutdown.send(true).unwrap();
+        server.await.unwrap();
+        let _restarted = serve(addr, 31000.0).await;
+
+        let restarted = timeout(Duration::from_secs(10), async {
+            loop {
+                let summary = summaries.next().await.unwrap().unwrap();
+                if summary.bids[0].price == 31000.0 {
+                    break summary;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(restarted.asks[0].price, 31001.0);
+    }
+
+    #[tokio::test]
+    async fn it_ends_streams_at_errors_that_are_not_transient() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let _server = serve(addr, 30000.0).await;
+        let client = OrderbookClient::connect(&addr.to_string()).await.unwrap();
+
+        let err = client
+            .summary("ethbtc", &SummaryOptions::new())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ClientError::NotServed("ethbtc is not served, the server serves BTCUSDT".to_string())
+        );
+
+        let options = SummaryOptions::new().with_weight(Exchange::BITSTAMP, -1.0);
+        let mut summaries = client.watch("btcusdt", options);
+        let err = summaries.next().await.unwrap().unwrap_err();
+        assert!(matches!(err, ClientError::InvalidArgument(_)), "{}", err);
+        assert!(!err.is_transient());
+        assert!(summaries.next().await.is_none());
+
+        // Binance isn't served.
+        let options = SummaryOptions::new().with_exchanges([Exchange::BINANCE]);
+        let err = client.summary("btcusdt", &options).await.unwrap_err();
+        assert!(matches!(err, ClientError::NotServed(_)), "{}", err);
+
+        let err = OrderbookClient::connect("127.0.0.1:1").await.unwrap_err();
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn it_builds_requests_from_options() {
+        let request = SummaryOptions::new()
+            .with_levels(5)
+            .with_exchanges([Exchange::BINANCE])
+            .with_weight(Exchange::BINANCE, 0.5)
+            .with_min_interval(Duration::from_millis(250))
+            .with_update_speed(DepthSpeed::Ms1000)
+            .with_ask_levels(0)
+            .request();
+        assert_eq!(request.levels, 5);
+        assert_eq!((request.bid_levels, request.ask_levels), (None, Some(0)));
+        assert_eq!(request.exchanges, ["BINANCE"]);
+        assert_eq!(request.exchange_weights["BINANCE"], 0.5);
+        assert_eq!(request.min_interval_ms, 250);
+        assert_eq!(request.update_speed_ms, 1000);
+        assert_eq!(
+            SummaryOptions::new().request(),
+            WatchSummaryRequest::default()
+        );
+    }
 }
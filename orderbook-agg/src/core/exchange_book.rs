@@ -1,47 +1,334 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
-use std::sync::Arc;
+use futures::{
+    future::BoxFuture,
+    stream::{SplitSink, SplitStream},
+    Sink, SinkExt, Stream, StreamExt,
+};
+use num_traits::ToPrimitive;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
     net::TcpStream,
-    sync::{mpsc, Mutex},
+    sync::{mpsc, RwLock},
     task::JoinHandle,
 };
-use tokio_stream::StreamExt;
-use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::Instrument;
 use url::Url;
 
+use super::http::HttpClient;
 use super::order_book::{BookLevels, OrderBook, OrderBookArgs, Update};
-use crate::{core::num_types::*, Exchange, Symbol};
+use super::recorder::{Recorder, Recording};
+use super::stats::{ConnectionState, ExchangeStats};
+use crate::{
+    book_summary::{TappedLevel, TappedUpdate, UpdateOutcome},
+    core::num_types::*,
+    Exchange, Symbol,
+};
+
+/// The half of an update stream messages are sent to the exchange on
+pub type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// A message received on an exchange's update stream
+#[derive(Debug)]
+pub enum StreamMessage<U> {
+    /// An order book update
+    Update(U),
+    /// The exchange acknowledged the subscription
+    Subscribed,
+    /// The exchange rejected the subscription or reported an error
+    Error(String),
+    /// The exchange asked the client to reconnect
+    Reconnect,
+}
+
+/// Parses the raw message payloads received on an exchange's update stream.
+pub trait FromMessage: Sized {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>>;
+
+    /// Decodes the payload of a binary frame into the same format as a text frame.
+    /// Exchanges that compress their messages override this, the default passes the
+    /// payload through unchanged.
+    fn decode_binary(data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+/// Base urls of an exchange's REST and websocket APIs, e.g. to point an exchange at its
+/// testnet instead of production.
+///
+/// The websocket url can be followed by fallbacks to fail over to when it can't be
+/// connected to. Clones share which url last connected, so a feed reconnecting with a new
+/// order book carries on from the url that worked, see [Endpoints::connect_wss].
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub https: Url,
+    /// The preferred websocket url
+    pub wss: Url,
+    failover: Arc<WssFailover>,
+}
+
+impl PartialEq for Endpoints {
+    fn eq(&self, other: &Self) -> bool {
+        self.https == other.https && self.wss_urls() == other.wss_urls()
+    }
+}
+
+/// How long connections stay failed over before the preferred websocket url is tried
+/// again, unless set with [Endpoints::with_probe_after]
+pub const DEFAULT_PROBE_AFTER: Duration = Duration::from_secs(300);
+
+/// The websocket urls in order of preference and which of them last connected
+#[derive(Debug)]
+struct WssFailover {
+    urls: Vec<Url>,
+    probe_after: Duration,
+    state: std::sync::Mutex<FailoverState>,
+}
+
+#[derive(Debug, Default)]
+struct FailoverState {
+    /// Index of the url that last connected
+    current: usize,
+    /// When connections last moved off the preferred url, or stayed off it after trying it
+    failed_over_at: Option<Instant>,
+    failovers: u64,
+}
+
+impl WssFailover {
+    fn new(urls: Vec<Url>, probe_after: Duration) -> Self {
+        Self {
+            urls,
+            probe_after,
+            state: Default::default(),
+        }
+    }
+
+    fn probe_due(&self, state: &FailoverState) -> bool {
+        state
+            .failed_over_at
+            .is_some_and(|at| at.elapsed() >= self.probe_after)
+    }
+
+    /// Indexes of the urls to try, from the one that last connected, or from the preferred
+    /// one once it's time to probe it again.
+    fn order(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+        let start = if self.probe_due(&state) {
+            0
+        } else {
+            state.current
+        };
+        (start..self.urls.len()).chain(0..start).collect()
+    }
+
+    fn connected(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        if index != state.current {
+            if index == 0 {
+                tracing::info!("back on preferred websocket url {}", self.urls[index]);
+            } else {
+                tracing::warn!("failed over to websocket url {}", self.urls[index]);
+                state.failovers += 1;
+            }
+        }
+        if index == 0 {
+            state.failed_over_at = None;
+        } else if index != state.current || self.probe_due(&state) {
+            state.failed_over_at = Some(Instant::now());
+        }
+        state.current = index;
+    }
+}
+
+impl Endpoints {
+    /// Parses the base urls, adding the trailing slash paths are joined onto if it's missing.
+    pub fn new(https: &str, wss: &str) -> Result<Self> {
+        let wss = parse_base_url(wss)?;
+        Ok(Self {
+            https: parse_base_url(https)?,
+            failover: Arc::new(WssFailover::new(vec![wss.clone()], DEFAULT_PROBE_AFTER)),
+            wss,
+        })
+    }
+
+    /// Websocket urls to fail over to in order when the preferred one can't be connected to
+    pub fn with_wss_fallbacks<S: AsRef<str>>(mut self, fallbacks: &[S]) -> Result<Self> {
+        let mut urls = vec![self.wss.clone()];
+        for url in fallbacks {
+            urls.push(parse_base_url(url.as_ref())?);
+        }
+        self.failover = Arc::new(WssFailover::new(urls, self.failover.probe_after));
+        Ok(self)
+    }
+
+    /// How long connections stay failed over before the preferred websocket url is tried
+    /// again. It's only tried when connecting, a working connection is never dropped for it.
+    pub fn with_probe_after(mut self, probe_after: Duration) -> Self {
+        self.failover = Arc::new(WssFailover::new(self.failover.urls.clone(), probe_after));
+        self
+    }
+
+    /// The preferred websocket url followed by the fallbacks
+    pub fn wss_urls(&self) -> &[Url] {
+        &self.failover.urls
+    }
+
+    /// Times connections have moved off the url that last connected to a fallback
+    pub fn failovers(&self) -> u64 {
+        self.failover.state.lock().unwrap().failovers
+    }
+
+    /// Connects to `path` on the websocket url that last connected, or on the preferred
+    /// url once connections have been failed over for longer than the probe interval, and
+    /// then on each of the others in turn until one connects. Returns the index of the url
+    /// connected to in [Endpoints::wss_urls] with the stream.
+    pub async fn connect_wss(
+        &self,
+        http: &HttpClient,
+        path: &str,
+    ) -> Result<(usize, WebSocketStream<MaybeTlsStream<TcpStream>>)> {
+        let mut last_err = None;
+        for index in self.failover.order() {
+            let url = self.failover.urls[index]
+                .join(path)
+                .with_context(|| format!("Invalid websocket path: {}", path))?;
+            match http.connect_websocket(&url).await {
+                Ok(stream) => {
+                    self.failover.connected(index);
+                    return Ok((index, stream));
+                }
+                Err(err) => {
+                    tracing::warn!("failed to connect to {}: {:#}", url, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.expect("there's always a websocket url"))
+            .context("Failed to connect to any of the websocket urls")
+    }
+}
+
+fn parse_base_url(url: &str) -> Result<Url> {
+    let url = if url.ends_with('/') {
+        url.to_string()
+    } else {
+        format!("{}/", url)
+    };
+    Url::parse(&url).with_context(|| format!("Invalid base url: {}", url))
+}
+
+/// How many levels of each side the REST snapshots ask for. Exchanges clamp the depth
+/// to what they allow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotDepth {
+    /// Each exchange's usual depth
+    #[default]
+    Default,
+    /// Just the levels being served
+    Levels,
+    /// The deepest snapshot each exchange gives
+    Max,
+    /// This many levels
+    Limit(u32),
+}
+
+impl SnapshotDepth {
+    /// The depth to ask for when serving `levels`, none for the exchange's default.
+    pub fn resolve(self, levels: u32) -> Option<u32> {
+        match self {
+            Self::Default => None,
+            Self::Levels => Some(levels),
+            Self::Max => Some(u32::MAX),
+            Self::Limit(limit) => Some(limit),
+        }
+    }
+}
+
+impl std::str::FromStr for SnapshotDepth {
+    type Err = anyhow::Error;
+
+    /// Parses `default`, `levels`, `max` or a number of levels.
+    fn from_str(depth: &str) -> Result<Self> {
+        match depth {
+            "default" => Ok(Self::Default),
+            "levels" => Ok(Self::Levels),
+            "max" => Ok(Self::Max),
+            _ => match depth.parse() {
+                Ok(0) | Err(_) => bail!(
+                    "snapshot depth must be default, levels, max or a number of levels, got {}",
+                    depth
+                ),
+                Ok(limit) => Ok(Self::Limit(limit)),
+            },
+        }
+    }
+}
 
 #[async_trait]
 pub trait ExchangeBook<
     S: Update + Send,
-    U: std::fmt::Debug
-        + Update
-        + From<S>
-        + TryFrom<Message, Error = anyhow::Error>
-        + Send
-        + Sync
-        + 'static,
+    U: std::fmt::Debug + Update + From<S> + FromMessage + Send + Sync + 'static,
     Error = anyhow::Error,
 >
 {
+    /// Production urls, used unless others are configured
     const BASE_URL_HTTPS: &'static str;
     const BASE_URL_WSS: &'static str;
+    /// Production websocket urls to fail over to, in order
+    const FALLBACK_URLS_WSS: &'static [&'static str] = &[];
 
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>>;
-    fn base_url_https() -> Url {
-        Url::parse(&Self::BASE_URL_HTTPS).unwrap()
-    }
-    fn base_url_wss() -> Url {
-        Url::parse(&Self::BASE_URL_WSS).unwrap()
+    fn orderbook(&self) -> Arc<RwLock<OrderBook>>;
+    fn default_endpoints() -> Endpoints {
+        Endpoints::new(Self::BASE_URL_HTTPS, Self::BASE_URL_WSS)
+            .and_then(|endpoints| endpoints.with_wss_fallbacks(Self::FALLBACK_URLS_WSS))
+            .unwrap()
     }
     // fn tx_levels(&self) -> Arc<Mutex<watch::Sender<Option<BookLevels>>>>;
 
-    async fn new(symbol: Symbol, price_range: u8) -> Result<Self>
+    /// The exchange's own name for `symbol`
+    fn native_symbol(symbol: Symbol) -> String
     where
         Self: Sized;
-    async fn new_orderbook(exchange: Exchange, symbol: Symbol, price_range: u8) -> Result<OrderBook>
+
+    async fn new(
+        http: HttpClient,
+        endpoints: Endpoints,
+        symbol: Symbol,
+        price_range: u8,
+    ) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let native = Self::native_symbol(symbol);
+        Self::new_native(http, endpoints, symbol, native, price_range).await
+    }
+
+    /// Makes the book of `symbol` from the exchange's listing named `native` rather than
+    /// its [usual name](Self::native_symbol), for exchanges listing it under another.
+    async fn new_native(
+        http: HttpClient,
+        endpoints: Endpoints,
+        symbol: Symbol,
+        native: String,
+        price_range: u8,
+    ) -> Result<Self>
+    where
+        Self: Sized;
+    async fn new_orderbook(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        exchange: Exchange,
+        symbol: Symbol,
+        native: &str,
+        price_range: u8,
+    ) -> Result<OrderBook>
     where
         Self: Sized,
     {
@@ -50,7 +337,7 @@ pub trait ExchangeBook<
             storage_price_max,
             scale_price,
             scale_quantity,
-        } = Self::fetch_orderbook_args(&symbol, price_range).await?;
+        } = Self::fetch_orderbook_args(http, endpoints, native, price_range).await?;
 
         let orderbook = OrderBook::new(
             exchange,
@@ -72,11 +359,23 @@ pub trait ExchangeBook<
         );
         Ok(orderbook)
     }
-    /// Fetches the best bid and ask from the exchange
-    async fn fetch_prices(symbol: &Symbol) -> Result<(DisplayAmount, DisplayAmount)>;
+    /// Fetches the best bid and ask of the `native` listing from the exchange
+    async fn fetch_prices(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+    ) -> Result<(DisplayAmount, DisplayAmount)>;
+
+    /// Whether the exchange still lists `native`, see [listings](crate::listings)
+    async fn fetch_listed(http: &HttpClient, endpoints: &Endpoints, native: &str) -> Result<bool>;
 
     /// Fetches precious data from the exchange
-    async fn fetch_orderbook_args(symbol: &Symbol, price_range: u8) -> Result<OrderBookArgs>;
+    async fn fetch_orderbook_args(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+        price_range: u8,
+    ) -> Result<OrderBookArgs>;
 
     /// Fetches the initial snapshot from the exchange
     async fn fetch_snapshot(&self) -> Result<S>;
@@ -84,49 +383,169 @@ pub trait ExchangeBook<
     /// Fetches the update stream from the exchange
     async fn fetch_update_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 
-    async fn start(&self, levels: u32, tx_summary: mpsc::Sender<BookLevels>) -> Result<()> {
-        let (tx_update, mut rx_update) = mpsc::channel::<U>(100);
-
-        let mut stream = self.fetch_update_stream().await?;
-        let snapshot = self.fetch_snapshot().await?;
-        let snapshot_update = U::from(snapshot);
-
-        let fetcher: JoinHandle<std::result::Result<(), anyhow::Error>> =
-            tokio::spawn(async move {
-                tx_update
-                    .send(snapshot_update)
-                    .await
-                    .context("failed to send snapshot")?;
-                while let Some(response) = stream.next().await {
-                    match response {
-                        Ok(message) => match U::try_from(message) {
-                            Ok(mut update) => {
-                                tracing::debug!(
-                                    "sending update with {} bids and {} asks",
-                                    update.bids_mut().len(),
-                                    update.asks_mut().len()
-                                );
-                                tx_update
-                                    .send(update)
-                                    .await
-                                    .context("failed to send update")?;
-                            }
-                            Err(_) => {
-                                tracing::error!("failed to get update from message");
-                            }
-                        },
-                        Err(e) => {
-                            tracing::error!("failed to get message, {:?}", e);
-                            continue;
-                        }
+    fn stats(&self) -> Arc<ExchangeStats>;
+
+    /// The client shared by all the exchanges' REST calls
+    fn http(&self) -> &HttpClient;
+
+    /// The urls the order book was created with
+    fn endpoints(&self) -> &Endpoints;
+
+    /// Where frames received on the update stream are recorded, if anywhere
+    fn recorder(&self) -> Option<&Recorder> {
+        None
+    }
+
+    /// Connects to `path` on the exchange's websocket urls with
+    /// [Endpoints::connect_wss], recording the url connected to in the stats.
+    async fn connect_wss(&self, path: &str) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let (index, stream) = self.endpoints().connect_wss(self.http(), path).await?;
+        self.stats().set_wss_endpoint(index);
+        Ok(stream)
+    }
+
+    /// Splits a connected update stream, recording the frames received on it when there's
+    /// a [recorder](Self::recorder).
+    async fn split_stream(
+        &self,
+        stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ) -> (
+        WsSink,
+        Recording<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
+    ) {
+        let (sink, stream) = stream.split();
+        let (exchange, symbol) = {
+            let orderbook = self.orderbook();
+            let ob = orderbook.read().await;
+            (ob.exchange, ob.symbol)
+        };
+        let stream = Recording::new(stream, self.recorder().cloned(), exchange, symbol);
+        (sink, stream)
+    }
+
+    async fn start(&self, levels: u32, tx_summary: mpsc::Sender<Arc<BookLevels>>) -> Result<()> {
+        let (sink, stream) = self.split_stream(self.fetch_update_stream().await?).await;
+        let snapshot = self
+            .fetch_snapshot()
+            .instrument(tracing::info_span!("fetch_snapshot"))
+            .await?;
+        process_updates::<S, U, _, _>(
+            self.orderbook(),
+            self.stats(),
+            levels,
+            snapshot,
+            Some(Box::new(|| self.fetch_snapshot())),
+            stream,
+            sink,
+            tx_summary,
+        )
+        .await
+    }
+}
+
+/// Applies the snapshot and then each update received on the stream to the order book,
+/// sending the book levels after each update that changes them.
+///
+/// Frames are read and parsed on their own task with [forward_messages], which hands
+/// updates in order to the task applying them over a bounded channel. Each exchange runs
+/// its own pair of tasks, so a slow parse or a burst of updates on one exchange never
+/// holds up applying another's, and backpressure stays on that exchange's connection.
+/// See the pipeline benchmark for the throughput.
+///
+/// Updates that can't be applied are logged and counted in [ExchangeStats] without ending
+/// the stream. An update that doesn't follow on from the book's last one means updates
+/// were missed, so with `fetch_snapshot` the book is cleared and rebuilt from a fresh
+/// snapshot, then the update and those buffered behind it are applied on top, skipping
+/// any the snapshot already holds. Without it the update is dropped. Returns once the
+/// stream ends or is closed by the exchange, the exchange asks for a reconnect or the
+/// summary receiver is dropped, and errors if the exchange reports a subscription error
+/// or a resync snapshot can't be fetched.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_updates<S, U, St, Si>(
+    orderbook: Arc<RwLock<OrderBook>>,
+    stats: Arc<ExchangeStats>,
+    levels: u32,
+    snapshot: S,
+    mut fetch_snapshot: Option<FetchSnapshot<'_, S>>,
+    stream: St,
+    sink: Si,
+    tx_summary: mpsc::Sender<Arc<BookLevels>>,
+) -> Result<()>
+where
+    S: Update + Send,
+    U: std::fmt::Debug + Update + From<S> + FromMessage,
+    U: Send + Sync + 'static,
+    St: Stream<Item = Result<Message, WsError>> + Unpin + Send + 'static,
+    Si: Sink<Message, Error = WsError> + Unpin + Send + 'static,
+{
+    stats.counters.connections.incr();
+    let (tx_update, mut rx_update) = mpsc::channel::<U>(100);
+    let snapshot_update = U::from(snapshot);
+    let exchange = orderbook.read().await.exchange;
+
+    let fetcher_stats = stats.clone();
+    // Aborted if this future is dropped too, so the websocket closes along with it.
+    let mut fetcher: AbortOnDrop<Result<()>> = AbortOnDrop(tokio::spawn(
+        async move {
+            tx_update
+                .send(snapshot_update)
+                .await
+                .context("failed to send snapshot")?;
+            forward_messages(exchange, fetcher_stats, stream, sink, tx_update).await
+        }
+        .in_current_span(),
+    ));
+
+    // The first update is the snapshot, which replaces the book.
+    let mut resyncing = true;
+    while let Some(mut update) = rx_update.recv().await {
+        if let Some(fetch_snapshot) = fetch_snapshot.as_mut() {
+            let gap = {
+                let ob = orderbook.read().await;
+                match update.precedes(ob.last_update_id) {
+                    true => None,
+                    false => ob.check_sequence(&update).err(),
+                }
+            };
+            if let Some(gap) = gap {
+                stats.incr_resyncs();
+                tracing::warn!("{} resyncing after a gap: {:#}", exchange, gap);
+                let mut snapshot = U::from(
+                    fetch_snapshot()
+                        .instrument(tracing::info_span!("fetch_snapshot"))
+                        .await
+                        .context("failed to fetch snapshot to resync from")?,
+                );
+                let top_levels = {
+                    let mut ob = orderbook.write().await;
+                    ob.reset();
+                    let tapped = stats.tapping().then(|| tap_update(&ob, &mut snapshot));
+                    let applied = ob.update(&mut snapshot);
+                    if let Some(mut tapped) = tapped {
+                        tapped.set_outcome(UpdateOutcome::Resync);
+                        tapped.reason = match &applied {
+                            Ok(_) => format!("{:#}", gap),
+                            Err(err) => format!("{:#}", err),
+                        };
+                        stats.tap(tapped);
+                    }
+                    applied.context("failed to apply snapshot to resync from")?;
+                    stats.applied(ob.bid_levels(), ob.ask_levels());
+                    ob.take_top_levels(levels)
+                };
+                if let Some(book_levels) = top_levels.and_then(|top| top.to_book_levels()) {
+                    if tx_summary.send(Arc::new(book_levels)).await.is_err() {
+                        tracing::info!("summary receiver dropped: {}", exchange);
+                        break;
                     }
                 }
-                Ok(())
-            });
+            }
+        }
 
-        let orderbook = self.orderbook();
-        while let Some(mut update) = rx_update.recv().await {
-            let mut ob = orderbook.lock().await;
+        // The write lock is only held while the update is applied and the top storage
+        // amounts copied out. The levels are built and sent after it's released.
+        let top_levels = {
+            let mut ob = orderbook.write().await;
             let exchange = ob.exchange;
             let symbol = ob.symbol;
             tracing::debug!(
@@ -135,21 +554,420 @@ pub trait ExchangeBook<
                 symbol,
                 update.last_update_id()
             );
-            if let Err(err) = ob.update(&mut update) {
-                tracing::error!(
-                    "failed to update orderbook: {} {} {}",
+            let resync = std::mem::take(&mut resyncing) || update.replaces_book();
+            let tapped = stats.tapping().then(|| tap_update(&ob, &mut update));
+            // Updates buffered while the snapshot was fetched hold nothing new.
+            if update.precedes(ob.last_update_id) {
+                tracing::debug!(
+                    "skipping stale update: {} {} {}",
                     exchange,
                     symbol,
-                    err
+                    update.last_update_id()
                 );
-            } else {
-                if let Some(book_levels) = ob.get_book_levels(levels) {
-                    tx_summary.send(book_levels.clone()).await.unwrap();
+                if let Some(mut tapped) = tapped {
+                    tapped.set_outcome(UpdateOutcome::OutOfOrder);
+                    tapped.reason = "already applied".to_string();
+                    stats.tap(tapped);
                 }
+                continue;
+            }
+            let in_sequence = ob.check_sequence(&update).is_ok();
+            let applied = ob.update(&mut update);
+            if let Some(mut tapped) = tapped {
+                let outcome = match &applied {
+                    Ok(_) if resync => UpdateOutcome::Resync,
+                    Ok(_) => UpdateOutcome::Accepted,
+                    Err(_) if !in_sequence => UpdateOutcome::OutOfOrder,
+                    Err(_) => UpdateOutcome::Rejected,
+                };
+                tapped.set_outcome(outcome);
+                if let Err(err) = &applied {
+                    tapped.reason = format!("{:#}", err);
+                }
+                stats.tap(tapped);
+            }
+            match applied {
+                Ok(changed) => {
+                    stats.applied(ob.bid_levels(), ob.ask_levels());
+                    // Nothing within the top levels changed so there's nothing new to send.
+                    if !changed {
+                        continue;
+                    }
+                    ob.take_top_levels(levels)
+                }
+                Err(err) => {
+                    stats.incr_update_errors();
+                    tracing::error!(
+                        "failed to update orderbook: {} {} {}",
+                        exchange,
+                        symbol,
+                        err
+                    );
+                    continue;
+                }
+            }
+        };
+        if let Some(book_levels) = top_levels.and_then(|top| top.to_book_levels()) {
+            if tx_summary.send(Arc::new(book_levels)).await.is_err() {
+                tracing::info!("summary receiver dropped: {}", exchange);
+                break;
             }
         }
+    }
 
-        let _ = fetcher.await?;
-        Ok(())
+    // The fetcher has already finished unless the summary receiver was dropped.
+    fetcher.0.abort();
+    match (&mut fetcher.0).await {
+        Ok(result) => result,
+        Err(err) if err.is_cancelled() => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Fetches a fresh snapshot for [process_updates] to rebuild a book from after a gap in
+/// its updates
+pub type FetchSnapshot<'a, S> = Box<dyn FnMut() -> BoxFuture<'a, Result<S>> + Send + 'a>;
+
+/// `update` as it's tapped, before it's applied to `ob`
+fn tap_update<U: Update>(ob: &OrderBook, update: &mut U) -> TappedUpdate {
+    let level = |&[price, quantity]: &[DisplayAmount; 2]| TappedLevel {
+        price: price.to_f64().unwrap_or_default(),
+        quantity: quantity.to_f64().unwrap_or_default(),
+    };
+    TappedUpdate {
+        exchange: ob.exchange.to_string(),
+        symbol: ob.symbol.to_string(),
+        update_id: update.last_update_id(),
+        previous_update_id: ob.last_update_id,
+        applied_at_ms: chrono::Utc::now().timestamp_millis() as u64,
+        bids: update.bids_mut().iter().map(level).collect(),
+        asks: update.asks_mut().iter().map(level).collect(),
+        ..Default::default()
+    }
+}
+
+/// Reads the frames of an exchange's stream, sending each message parsed as `U` on
+/// `tx_update`. Pings are answered on the sink and binary frames are decoded with
+/// [FromMessage::decode_binary] before parsing. Messages that can't be parsed are logged
+/// and counted in [ExchangeStats] without ending the stream, and subscription
+/// acknowledgements and updates move the connection state along. Returns once the stream ends,
+/// fails or is closed by the exchange or the exchange asks for a reconnect, and errors if the
+/// exchange reports a subscription error or the receiver is dropped.
+pub async fn forward_messages<U, St, Si>(
+    exchange: Exchange,
+    stats: Arc<ExchangeStats>,
+    mut stream: St,
+    mut sink: Si,
+    tx_update: mpsc::Sender<U>,
+) -> Result<()>
+where
+    U: FromMessage + Send + 'static,
+    St: Stream<Item = Result<Message, WsError>> + Unpin + Send + 'static,
+    Si: Sink<Message, Error = WsError> + Unpin + Send + 'static,
+{
+    while let Some(response) = stream.next().await {
+        match response {
+            Ok(message) => {
+                if matches!(message, Message::Text(_) | Message::Binary(_)) {
+                    stats.received();
+                }
+                let data = match message {
+                    Message::Text(text) => text.into_bytes(),
+                    Message::Binary(data) => match U::decode_binary(data) {
+                        Ok(data) => data,
+                        Err(err) => {
+                            stats.incr_parse_errors();
+                            tracing::warn!("failed to decode binary message: {:#}", err);
+                            continue;
+                        }
+                    },
+                    Message::Ping(payload) => {
+                        if let Err(err) = sink.send(Message::Pong(payload)).await {
+                            tracing::warn!("{} failed to answer ping: {}", exchange, err);
+                        }
+                        continue;
+                    }
+                    Message::Pong(_) | Message::Frame(_) => continue,
+                    Message::Close(frame) => {
+                        match frame {
+                            Some(frame) => tracing::info!(
+                                "{} closed the stream: {} {}",
+                                exchange,
+                                frame.code,
+                                frame.reason
+                            ),
+                            None => tracing::info!("{} closed the stream", exchange),
+                        }
+                        break;
+                    }
+                };
+                match U::from_message(data.as_slice()) {
+                    Ok(StreamMessage::Subscribed) => {
+                        tracing::info!("{} subscription acknowledged", exchange);
+                        stats.set_state(ConnectionState::Subscribed);
+                    }
+                    Ok(StreamMessage::Error(message)) => {
+                        stats.set_state(ConnectionState::Disconnected);
+                        bail!("{} subscription error: {}", exchange, message);
+                    }
+                    Ok(StreamMessage::Reconnect) => {
+                        tracing::info!("{} requested reconnect", exchange);
+                        stats.set_state(ConnectionState::Disconnected);
+                        return Ok(());
+                    }
+                    Ok(StreamMessage::Update(update)) => {
+                        stats.receiving();
+                        if tx_update.send(update).await.is_err() {
+                            bail!("failed to send update");
+                        }
+                    }
+                    Err(err) => {
+                        stats.incr_parse_errors();
+                        tracing::warn!("failed to get update from message: {:#}", err);
+                        tracing::debug!("raw message: {}", String::from_utf8_lossy(&data));
+                    }
+                }
+            }
+            Err(e) => {
+                // the connection is gone, so end the stream and let the caller reconnect
+                tracing::error!("{} failed to get message, {:?}", exchange, e);
+                break;
+            }
+        }
+    }
+    stats.set_state(ConnectionState::Disconnected);
+    Ok(())
+}
+
+/// Aborts the task when dropped
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(all(test, feature = "exchange-bitstamp"))]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+
+    use super::*;
+    use crate::core::http::HttpConfig;
+    use crate::exchanges::bitstamp::data::{BookUpdate, Snapshot};
+
+    fn update_message(id: u64, bid: &str, ask: &str) -> Message {
+        let update = serde_json::json!({
+            "data": {
+                "microtimestamp": id.to_string(),
+                "bids": [[bid, "1.0"]],
+                "asks": [[ask, "1.0"]],
+            }
+        });
+        Message::Text(update.to_string())
+    }
+
+    fn orderbook_setup() -> (Arc<RwLock<OrderBook>>, Snapshot) {
+        let orderbook = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 29000, 31000, 0, 8);
+        let snapshot = Snapshot {
+            last_update_id: 1,
+            bids: vec![[Decimal::new(29990, 0), Decimal::ONE]],
+            asks: vec![[Decimal::new(30010, 0), Decimal::ONE]],
+        };
+        (Arc::new(RwLock::new(orderbook)), snapshot)
+    }
+
+    #[test]
+    fn it_parses_snapshot_depths() {
+        for (depth, resolved) in [
+            ("default", None),
+            ("levels", Some(15)),
+            ("max", Some(u32::MAX)),
+            ("100", Some(100)),
+        ] {
+            let depth: SnapshotDepth = depth.parse().unwrap();
+            assert_eq!(depth.resolve(15), resolved);
+        }
+        assert!("0".parse::<SnapshotDepth>().is_err());
+        assert!("deep".parse::<SnapshotDepth>().is_err());
+    }
+
+    /// Accepts websocket connections on `listener` and holds them open.
+    fn accept_websockets(listener: tokio::net::TcpListener) {
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let _ws = tokio_tungstenite::accept_async(socket).await;
+                    futures::future::pending::<()>().await;
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn it_fails_over_and_probes_back_to_the_preferred_url() {
+        // The preferred url refuses connections until it's listened on again below.
+        let preferred = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let preferred_addr = preferred.local_addr().unwrap();
+        drop(preferred);
+        let fallback = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fallback_url = format!("ws://{}/ws", fallback.local_addr().unwrap());
+        accept_websockets(fallback);
+
+        let probe_after = Duration::from_secs(1);
+        let endpoints = Endpoints::new(
+            "http://127.0.0.1:1/",
+            &format!("ws://{}/ws", preferred_addr),
+        )
+        .unwrap()
+        .with_wss_fallbacks(&[fallback_url])
+        .unwrap()
+        .with_probe_after(probe_after);
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+
+        let (index, _) = endpoints.connect_wss(&http, "stream").await.unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(endpoints.failovers(), 1);
+        // Reconnects with a clone, as feeds do with each new book, stay on the fallback.
+        let (index, _) = endpoints
+            .clone()
+            .connect_wss(&http, "stream")
+            .await
+            .unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(endpoints.failovers(), 1);
+
+        accept_websockets(tokio::net::TcpListener::bind(preferred_addr).await.unwrap());
+        let (index, _) = endpoints.connect_wss(&http, "stream").await.unwrap();
+        assert_eq!(index, 1);
+        tokio::time::sleep(probe_after).await;
+        let (index, _) = endpoints.connect_wss(&http, "stream").await.unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(endpoints.failovers(), 1);
+
+        let err = Endpoints::new("http://127.0.0.1:1/", "ws://127.0.0.1:1/ws")
+            .unwrap()
+            .connect_wss(&http, "stream")
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).contains("any of the websocket urls"));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_updating_through_malformed_messages() {
+        let (orderbook, snapshot) = orderbook_setup();
+        let stats = Arc::new(ExchangeStats::default());
+        let messages = vec![
+            update_message(2, "29991", "30009"),
+            Message::Text("not json".to_string()),
+            update_message(3, "29992", "30008"),
+            Message::Text(r#"{"data": {"bids": "garbage"}}"#.to_string()),
+            update_message(4, "29993", "30007"),
+        ];
+
+        let (tx, mut rx) = mpsc::channel(100);
+        process_updates::<Snapshot, BookUpdate, _, _>(
+            orderbook,
+            stats.clone(),
+            5,
+            snapshot,
+            None,
+            futures::stream::iter(messages.into_iter().map(Ok::<_, WsError>)),
+            futures::sink::drain().sink_map_err(|never| match never {}),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        let mut best_bids = Vec::new();
+        while let Some(book_levels) = rx.recv().await {
+            best_bids.push(book_levels.bids[0].price);
+        }
+        assert_eq!(best_bids, vec![29990.0, 29991.0, 29992.0, 29993.0]);
+        assert_eq!(stats.parse_errors(), 2);
+        assert_eq!(stats.update_errors(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_handles_each_frame_type() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            ws.send(Message::Ping(b"ping".to_vec())).await.unwrap();
+            let pong = loop {
+                match ws.next().await {
+                    Some(Ok(Message::Pong(payload))) => break payload,
+                    Some(Ok(_)) => continue,
+                    other => panic!("expected pong, got {:?}", other),
+                }
+            };
+            ws.send(update_message(2, "29991", "30009")).await.unwrap();
+            let binary = update_message(3, "29992", "30008").into_data();
+            ws.send(Message::Binary(binary)).await.unwrap();
+            ws.send(Message::Pong(Vec::new())).await.unwrap();
+            ws.close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "maintenance".into(),
+            }))
+            .await
+            .unwrap();
+            pong
+        });
+
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        let (sink, stream) = ws.split();
+        let (orderbook, snapshot) = orderbook_setup();
+        let stats = Arc::new(ExchangeStats::default());
+        let (tx, mut rx) = mpsc::channel(100);
+        process_updates::<Snapshot, BookUpdate, _, _>(
+            orderbook,
+            stats.clone(),
+            5,
+            snapshot,
+            None,
+            stream,
+            sink,
+            tx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(server.await.unwrap(), b"ping".to_vec());
+        let mut best_bids = Vec::new();
+        while let Some(book_levels) = rx.recv().await {
+            best_bids.push(book_levels.bids[0].price);
+        }
+        assert_eq!(best_bids, vec![29990.0, 29991.0, 29992.0]);
+        assert_eq!(stats.parse_errors(), 0);
+        assert_eq!(stats.state(), ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn it_stops_forwarding_when_the_stream_fails() {
+        let stream = futures::stream::iter(vec![
+            Ok(update_message(2, "29991", "30009")),
+            Err(WsError::ConnectionClosed),
+            Ok(update_message(3, "29992", "30008")),
+        ]);
+        let sink = futures::sink::drain().sink_map_err(|never| match never {});
+        let stats = Arc::new(ExchangeStats::default());
+        let (tx, mut rx) = mpsc::channel(100);
+        forward_messages::<BookUpdate, _, _>(Exchange::BITSTAMP, stats.clone(), stream, sink, tx)
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        while let Some(update) = rx.recv().await {
+            ids.push(update.last_update_id());
+        }
+        assert_eq!(ids, vec![2]);
+        assert_eq!(stats.state(), ConnectionState::Disconnected);
     }
 }
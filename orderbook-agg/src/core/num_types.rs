@@ -1,6 +1,11 @@
 use anyhow::{ensure, Context, Result};
 use rust_decimal::Decimal;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserializer,
+};
 use serde_json::Value;
+use std::fmt;
 
 /// Converts a pair of strings to a pair of numbers of type T.
 pub fn str_pair_to_num<T>(pair: &Value) -> Result<[T; 2]>
@@ -77,3 +82,94 @@ pub trait ToDisplay {
 pub trait ToStorage {
     fn to_storage(&self, scale: u32) -> Result<StorageAmount>;
 }
+
+/// Deserializes an array of `[price, quantity]` string pairs straight into decimals,
+/// borrowing the strings from the input. Pairs that don't parse are skipped.
+pub fn deserialize_levels<'de, D>(deserializer: D) -> Result<Vec<[Decimal; 2]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct LevelsVisitor;
+
+    impl<'de> Visitor<'de> for LevelsVisitor {
+        type Value = Vec<[Decimal; 2]>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an array of [price, quantity] string pairs")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut levels = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some([price, quantity]) = seq.next_element::<[&'de str; 2]>()? {
+                if let (Ok(price), Ok(quantity)) = (price.parse(), quantity.parse()) {
+                    levels.push([price, quantity]);
+                }
+            }
+            Ok(levels)
+        }
+    }
+
+    deserializer.deserialize_seq(LevelsVisitor)
+}
+
+/// Deserializes an array of `[price, quantity, order id]` orders into levels, summing the
+/// quantities of the orders at each price. Orders at the same price must be adjacent, as
+/// they are in a sorted book.
+pub fn deserialize_orders<'de, D>(deserializer: D) -> Result<Vec<[Decimal; 2]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct OrdersVisitor;
+
+    impl<'de> Visitor<'de> for OrdersVisitor {
+        type Value = Vec<[Decimal; 2]>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an array of [price, quantity, order id] orders")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut levels: Vec<[Decimal; 2]> = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some((price, quantity, de::IgnoredAny)) =
+                seq.next_element::<(&'de str, &'de str, de::IgnoredAny)>()?
+            {
+                let (Ok(price), Ok(quantity)) = (price.parse(), quantity.parse::<Decimal>()) else {
+                    continue;
+                };
+                match levels.last_mut() {
+                    Some(level) if level[0] == price => level[1] += quantity,
+                    _ => levels.push([price, quantity]),
+                }
+            }
+            Ok(levels)
+        }
+    }
+
+    deserializer.deserialize_seq(OrdersVisitor)
+}
+
+/// Deserializes a u64 sent either as a number or as a string, without allocating.
+pub fn deserialize_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct U64Visitor;
+
+    impl<'de> Visitor<'de> for U64Visitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an unsigned integer or a string containing one")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+            value.parse().map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(U64Visitor)
+}
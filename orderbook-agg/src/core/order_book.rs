@@ -1,6 +1,7 @@
 use anyhow::Result;
 use num_traits::ToPrimitive;
 use rust_decimal::Decimal;
+use std::sync::Arc;
 use tracing::instrument;
 
 use crate::{book_summary::Level, core::num_types::*, Exchange, Symbol};
@@ -18,14 +19,159 @@ pub struct BookLevels {
     pub last_update_id: u64,
     pub bids: Vec<Level>,
     pub asks: Vec<Level>,
+    /// Every level near the best prices, for books that report them
+    pub depth: Option<Arc<Depth>>,
+    /// Set on levels restored from the books a past server saved rather than sent by the
+    /// exchange, see [warm](crate::warm)
+    pub restored: bool,
+}
+
+/// How far from its best bid and ask a book reports its [Depth], in basis points
+pub const DEPTH_BAND_BPS: u64 = 100;
+
+/// The storage prices and quantities of every level within [DEPTH_BAND_BPS] of a book's
+/// best bid and ask, best first. The levels are complete down to `bid_floor` and up to
+/// `ask_ceiling`, which stop short of the band where the book's price range ends first.
+///
+/// Depth is taken along with the top levels, which are retaken whenever an update touches a
+/// price within the band as well as within the levels.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Depth {
+    pub scale_price: u32,
+    pub scale_quantity: u32,
+    pub bids: Vec<[StorageAmount; 2]>,
+    pub asks: Vec<[StorageAmount; 2]>,
+    pub bid_floor: StorageAmount,
+    pub ask_ceiling: StorageAmount,
+}
+
+impl Depth {
+    /// The display price and quantity of each bid, best first
+    pub fn bids(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.bids.iter().map(|&level| self.to_f64(level))
+    }
+
+    /// The display price and quantity of each ask, best first
+    pub fn asks(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.asks.iter().map(|&level| self.to_f64(level))
+    }
+
+    pub fn bid_floor(&self) -> f64 {
+        self.to_f64([self.bid_floor, 0])[0]
+    }
+
+    pub fn ask_ceiling(&self) -> f64 {
+        self.to_f64([self.ask_ceiling, 0])[0]
+    }
+
+    fn to_f64(&self, [price, quantity]: [StorageAmount; 2]) -> [f64; 2] {
+        [
+            price as f64 / 10u64.pow(self.scale_price) as f64,
+            quantity as f64 / 10u64.pow(self.scale_quantity) as f64,
+        ]
+    }
+}
+
+/// The storage prices and quantities of an order book's best levels, copied out under the
+/// lock so the [Level]s can be built from them after it's released.
+#[derive(Debug, Clone)]
+pub struct TopLevels {
+    pub exchange: Exchange,
+    pub symbol: Symbol,
+    pub last_update_id: u64,
+    pub scale_price: u32,
+    pub scale_quantity: u32,
+    pub bids: Vec<[StorageAmount; 2]>,
+    pub asks: Vec<[StorageAmount; 2]>,
+    /// When each of the bids and asks was set, see [OrderBook::update_at]
+    pub bid_times: Vec<u64>,
+    pub ask_times: Vec<u64>,
+    pub depth: Arc<Depth>,
+}
+
+impl TopLevels {
+    /// Same as [OrderBook::get_book_levels] for the book the levels were taken from.
+    pub fn to_book_levels(&self) -> Option<BookLevels> {
+        if self.bids.is_empty() && self.asks.is_empty() {
+            return None;
+        }
+        let to_levels = |levels: &[[StorageAmount; 2]], times: &[u64]| {
+            levels
+                .iter()
+                .zip(times)
+                .map(|(&level, &updated_at)| {
+                    display_level(
+                        self.exchange,
+                        self.scale_price,
+                        self.scale_quantity,
+                        level,
+                        updated_at,
+                    )
+                })
+                .collect::<Result<Vec<Level>>>()
+        };
+        Some(BookLevels {
+            exchange: self.exchange,
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: to_levels(&self.bids, &self.bid_times).ok()?,
+            asks: to_levels(&self.asks, &self.ask_times).ok()?,
+            depth: Some(self.depth.clone()),
+            restored: false,
+        })
+    }
+}
+
+/// The [Level] of a storage price and quantity set at `updated_at`, in milliseconds since
+/// the unix epoch, left unset when 0
+fn display_level(
+    exchange: Exchange,
+    scale_price: u32,
+    scale_quantity: u32,
+    storage_level: [StorageAmount; 2],
+    updated_at: u64,
+) -> Result<Level> {
+    let price = (storage_level[0].to_display(scale_price)?.to_f64().unwrap()
+        * 10u32.pow(scale_price) as f64)
+        .round()
+        / 10u32.pow(scale_price) as f64;
+    let quantity = (storage_level[1]
+        .to_display(scale_quantity)?
+        .to_f64()
+        .unwrap()
+        * 10u32.pow(scale_quantity) as f64)
+        .round()
+        / 10u32.pow(scale_quantity) as f64;
+    Ok(Level {
+        exchange: exchange.to_string(),
+        price,
+        quantity,
+        updated_at: (updated_at > 0).then_some(updated_at),
+        market_type: exchange.market_type().into(),
+    })
 }
 
 /// Updates from all exchanges should implement this trait
 pub trait Update {
     fn validate(&self, last_id: u64) -> Result<()>;
+    /// Checks the first update after a snapshot at `snapshot_id` overlaps it, which is all
+    /// some exchanges promise of it. Checked with [validate](Self::validate) by default.
+    fn validate_first(&self, snapshot_id: u64) -> Result<()> {
+        self.validate(snapshot_id)
+    }
+    /// Whether the update only holds changes the book already has up to `last_id`, like the
+    /// updates buffered while a snapshot was fetched, so it's skipped rather than applied.
+    fn precedes(&self, _last_id: u64) -> bool {
+        false
+    }
     fn last_update_id(&self) -> u64;
     fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]>;
     fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]>;
+    /// Whether the update holds the whole book rather than changes to it, in which case
+    /// every level not in it is removed.
+    fn replaces_book(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Default)]
@@ -53,6 +199,33 @@ impl OrderBookArgs {
     }
 }
 
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// `count` after a level's quantity changes from `previous` to `quantity`
+fn level_count(count: usize, previous: StorageAmount, quantity: StorageAmount) -> usize {
+    match (previous > 0, quantity > 0) {
+        (false, true) => count + 1,
+        (true, false) => count - 1,
+        _ => count,
+    }
+}
+
+/// The top levels from the last rebuild, reused until an update touches a price inside
+/// them or their [Depth]. `bid_floor` and `ask_ceiling` are the worst visible prices or the
+/// edges of the depth band, whichever is further out, or the edges of the price range while
+/// there are fewer levels than asked for.
+#[derive(Debug)]
+struct LevelsCache {
+    levels: u32,
+    book_levels: Option<Arc<BookLevels>>,
+    bid_floor: StorageAmount,
+    ask_ceiling: StorageAmount,
+    dirty: bool,
+    hits: u64,
+}
+
 #[derive(Debug)]
 pub struct OrderBook {
     pub exchange: Exchange,
@@ -66,6 +239,19 @@ pub struct OrderBook {
     pub bids: Vec<StorageAmount>,
     pub asks: Vec<StorageAmount>,
     pub last_update_id: u64,
+    /// Whether the last update applied was a snapshot, which the update after it only has
+    /// to [overlap](Update::validate_first)
+    after_snapshot: bool,
+    /// When the update that set the quantity at each price was applied, in milliseconds
+    /// since the unix epoch, 0 where none has been
+    bid_times: Vec<u64>,
+    ask_times: Vec<u64>,
+    /// Prices with a quantity on each side, kept as levels are added and removed
+    bid_count: usize,
+    ask_count: usize,
+    /// The most levels of each side kept, see [set_max_depth](Self::set_max_depth)
+    max_depth: Option<u32>,
+    cache: LevelsCache,
 }
 
 impl OrderBook {
@@ -99,6 +285,20 @@ impl OrderBook {
             bids,
             asks,
             last_update_id: u64::MIN,
+            after_snapshot: false,
+            bid_times: vec![0; capacity],
+            ask_times: vec![0; capacity],
+            bid_count: 0,
+            ask_count: 0,
+            max_depth: None,
+            cache: LevelsCache {
+                levels: 0,
+                book_levels: None,
+                bid_floor: storage_price_min,
+                ask_ceiling: storage_price_max,
+                dirty: true,
+                hits: 0,
+            },
         }
     }
 
@@ -114,22 +314,18 @@ impl OrderBook {
     fn storage_quantity(&self, quantity: DisplayAmount) -> Result<StorageAmount> {
         quantity.to_storage(self.scale_quantity)
     }
-    fn storage_level_to_display_level(&self, storage_level: [StorageAmount; 2]) -> Result<Level> {
-        let price = (self.display_price(storage_level[0])?.to_f64().unwrap()
-            * 10u32.pow(self.scale_price) as f64)
-            .round()
-            / 10u32.pow(self.scale_price) as f64;
-        let quantity = (self.display_quantity(storage_level[1])?.to_f64().unwrap()
-            * 10u32.pow(self.scale_quantity) as f64)
-            .round()
-            / 10u32.pow(self.scale_quantity) as f64;
-        let level = Level {
-            exchange: self.exchange.to_string(),
-            price,
-            quantity,
-        };
-
-        Ok(level)
+    fn storage_level_to_display_level(
+        &self,
+        storage_level: [StorageAmount; 2],
+        updated_at: u64,
+    ) -> Result<Level> {
+        display_level(
+            self.exchange,
+            self.scale_price,
+            self.scale_quantity,
+            storage_level,
+            updated_at,
+        )
     }
     fn bids(&self) -> &Vec<StorageAmount> {
         &self.bids
@@ -147,8 +343,17 @@ impl OrderBook {
         (storage_price - self.storage_price_min) as usize
     }
     /// Adds, modifies or removes a bid from the order book.
-    #[instrument(level = "debug", skip(self))]
     pub fn add_bid(&mut self, level: [Decimal; 2]) -> Result<()> {
+        self.set_bid(level, now_ms())
+    }
+    /// Adds, modifies or removes an ask from the order book.
+    pub fn add_ask(&mut self, level: [Decimal; 2]) -> Result<()> {
+        self.set_ask(level, now_ms())
+    }
+    /// [add_bid](Self::add_bid) from an update applied at `now_ms`, which the bid is
+    /// stamped with unless it keeps its quantity
+    #[instrument(level = "debug", skip(self))]
+    fn set_bid(&mut self, level: [Decimal; 2], now_ms: u64) -> Result<()> {
         let mut storage_price = self.storage_price(level[0])?;
         if storage_price > self.storage_price_max || storage_price < self.storage_price_min {
             return Ok(());
@@ -164,7 +369,14 @@ impl OrderBook {
         let mut idx = self.idx(storage_price);
 
         let bids = self.bids_mut();
-        bids[idx] = storage_quantity;
+        let previous = std::mem::replace(&mut bids[idx], storage_quantity);
+        self.bid_count = level_count(self.bid_count, previous, storage_quantity);
+        if previous != storage_quantity || self.bid_times[idx] == 0 {
+            self.bid_times[idx] = now_ms;
+        }
+        if storage_price >= self.cache.bid_floor {
+            self.cache.dirty = true;
+        }
 
         if storage_quantity > 0 {
             if storage_price > self.storage_bid_max {
@@ -174,7 +386,7 @@ impl OrderBook {
             if storage_price == self.storage_bid_max && storage_quantity == 0 {
                 while storage_price > self.storage_price_min {
                     storage_price -= 1;
-                    idx = self.idx(storage_price).clone();
+                    idx = self.idx(storage_price);
                     if self.bids[idx] > 0 {
                         self.storage_bid_max = storage_price;
                         break;
@@ -184,9 +396,10 @@ impl OrderBook {
         }
         Ok(())
     }
-    /// Adds, modifies or removes a bid from the order book.
+    /// [add_ask](Self::add_ask) from an update applied at `now_ms`, which the ask is
+    /// stamped with unless it keeps its quantity
     #[instrument(level = "debug", skip(self))]
-    pub fn add_ask(&mut self, level: [Decimal; 2]) -> Result<()> {
+    fn set_ask(&mut self, level: [Decimal; 2], now_ms: u64) -> Result<()> {
         let mut storage_price = self.storage_price(level[0])?;
         if storage_price > self.storage_price_max || storage_price < self.storage_price_min {
             return Ok(());
@@ -202,7 +415,14 @@ impl OrderBook {
         let mut idx = self.idx(storage_price);
 
         let asks = self.asks_mut();
-        asks[idx] = storage_quantity;
+        let previous = std::mem::replace(&mut asks[idx], storage_quantity);
+        self.ask_count = level_count(self.ask_count, previous, storage_quantity);
+        if previous != storage_quantity || self.ask_times[idx] == 0 {
+            self.ask_times[idx] = now_ms;
+        }
+        if storage_price <= self.cache.ask_ceiling {
+            self.cache.dirty = true;
+        }
 
         if storage_quantity > 0 {
             if storage_price < self.storage_ask_min {
@@ -222,80 +442,353 @@ impl OrderBook {
         }
         Ok(())
     }
-    pub fn get_bids_levels(&self, mut levels: u32) -> Result<Vec<Level>> {
-        let bids = self.bids();
-        let summary_bids = if bids.is_empty() {
-            Vec::new()
-        } else {
-            let mut bid_max = self.storage_bid_max;
-            let mut summary_bids = Vec::<Level>::with_capacity(levels as usize);
-            while levels > 0 && bid_max >= self.storage_price_min {
-                let idx = self.idx(bid_max);
-                if bids[idx] > 0 {
-                    let level = self.storage_level_to_display_level([bid_max, bids[idx]])?;
-                    summary_bids.push(level);
-                    levels -= 1;
+    /// Removes every level from the order book, along with when each was set.
+    pub fn clear(&mut self) {
+        self.bids.fill(0);
+        self.asks.fill(0);
+        self.bid_times.fill(0);
+        self.ask_times.fill(0);
+        self.storage_bid_max = StorageAmount::MIN;
+        self.storage_ask_min = StorageAmount::MAX;
+        self.bid_count = 0;
+        self.ask_count = 0;
+        self.cache.dirty = true;
+    }
+    /// Empties the book to be rebuilt from a fresh snapshot, which it then takes like the
+    /// first one it started from.
+    pub fn reset(&mut self) {
+        self.clear();
+        self.last_update_id = 0;
+    }
+    /// Keeps at most `depth` levels of each side from the next update on, dropping the
+    /// worst ones past it, or every level within the price range without a depth.
+    pub fn set_max_depth(&mut self, depth: Option<u32>) {
+        self.max_depth = depth.map(|depth| depth.max(1));
+    }
+    pub fn max_depth(&self) -> Option<u32> {
+        self.max_depth
+    }
+    /// Drops the worst levels of each side past [max_depth](Self::set_max_depth), along
+    /// with the times they were last updated.
+    fn trim(&mut self) {
+        let Some(max) = self.max_depth.map(|max| max as usize) else {
+            return;
+        };
+        if self.bid_count > max {
+            let mut kept = 0;
+            for price in (self.storage_price_min..=self.storage_bid_max).rev() {
+                let idx = self.idx(price);
+                if self.bids[idx] == 0 {
+                    continue;
+                }
+                if kept < max {
+                    kept += 1;
+                    continue;
+                }
+                self.bids[idx] = 0;
+                self.bid_times[idx] = 0;
+                self.bid_count -= 1;
+                if self.bid_count == max {
+                    break;
                 }
-                bid_max -= 1;
             }
-            summary_bids
-        };
-        Ok(summary_bids)
+            self.cache.dirty = true;
+        }
+        if self.ask_count > max {
+            let mut kept = 0;
+            for price in self.storage_ask_min..=self.storage_price_max {
+                let idx = self.idx(price);
+                if self.asks[idx] == 0 {
+                    continue;
+                }
+                if kept < max {
+                    kept += 1;
+                    continue;
+                }
+                self.asks[idx] = 0;
+                self.ask_times[idx] = 0;
+                self.ask_count -= 1;
+                if self.ask_count == max {
+                    break;
+                }
+            }
+            self.cache.dirty = true;
+        }
     }
-    pub fn get_asks_levels(&self, mut levels: u32) -> Result<Vec<Level>> {
+    /// Prices with a quantity on the bid side
+    pub fn bid_levels(&self) -> usize {
+        self.bid_count
+    }
+    /// Prices with a quantity on the ask side
+    pub fn ask_levels(&self) -> usize {
+        self.ask_count
+    }
+    /// Storage price and quantity of the best `levels` bids, best first
+    fn top_bids(&self, mut levels: u32) -> Vec<[StorageAmount; 2]> {
+        let bids = self.bids();
+        let mut top_bids = Vec::with_capacity(levels as usize);
+        let mut bid_max = self.storage_bid_max;
+        while levels > 0 && bid_max >= self.storage_price_min {
+            let idx = self.idx(bid_max);
+            if bids[idx] > 0 {
+                top_bids.push([bid_max, bids[idx]]);
+                levels -= 1;
+            }
+            bid_max -= 1;
+        }
+        top_bids
+    }
+    /// Storage price and quantity of the best `levels` asks, best first
+    fn top_asks(&self, mut levels: u32) -> Vec<[StorageAmount; 2]> {
         let asks = self.asks();
-        let summary_asks = if asks.is_empty() {
-            Vec::new()
-        } else {
-            let mut ask_min = self.storage_ask_min;
-            let mut summary_asks = Vec::<Level>::with_capacity(levels as usize);
-            while levels > 0 && ask_min <= self.storage_price_max {
-                let idx = self.idx(ask_min);
-                if asks[idx] > 0 {
-                    let level = self.storage_level_to_display_level([ask_min, asks[idx]])?;
-                    summary_asks.push(level);
-                    levels -= 1;
-                }
-                ask_min += 1;
+        let mut top_asks = Vec::with_capacity(levels as usize);
+        let mut ask_min = self.storage_ask_min;
+        while levels > 0 && ask_min <= self.storage_price_max {
+            let idx = self.idx(ask_min);
+            if asks[idx] > 0 {
+                top_asks.push([ask_min, asks[idx]]);
+                levels -= 1;
             }
-            summary_asks
+            ask_min += 1;
+        }
+        top_asks
+    }
+    /// When each of `levels` on the side with `times` was set
+    fn times(&self, levels: &[[StorageAmount; 2]], times: &[u64]) -> Vec<u64> {
+        levels
+            .iter()
+            .map(|&[price, _]| times[self.idx(price)])
+            .collect()
+    }
+    pub fn get_bids_levels(&self, levels: u32) -> Result<Vec<Level>> {
+        self.top_bids(levels)
+            .into_iter()
+            .map(|level| {
+                let updated_at = self.bid_times[self.idx(level[0])];
+                self.storage_level_to_display_level(level, updated_at)
+            })
+            .collect()
+    }
+    pub fn get_asks_levels(&self, levels: u32) -> Result<Vec<Level>> {
+        self.top_asks(levels)
+            .into_iter()
+            .map(|level| {
+                let updated_at = self.ask_times[self.idx(level[0])];
+                self.storage_level_to_display_level(level, updated_at)
+            })
+            .collect()
+    }
+    /// Storage price and quantity of every level within [DEPTH_BAND_BPS] of the best bid
+    /// and ask, best first
+    fn depth(&self) -> Depth {
+        let band = |price: StorageAmount| price.saturating_mul(DEPTH_BAND_BPS) / 10_000;
+        // Sized up front so the levels are copied into a single allocation.
+        let capacity = |count: usize, from: StorageAmount, to: StorageAmount| {
+            count.min((to - from) as usize + 1)
+        };
+        let mut depth = Depth {
+            scale_price: self.scale_price,
+            scale_quantity: self.scale_quantity,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            bid_floor: self.storage_price_min,
+            ask_ceiling: self.storage_price_max,
         };
-        Ok(summary_asks)
+        if self.storage_bid_max >= self.storage_price_min {
+            depth.bid_floor =
+                (self.storage_bid_max - band(self.storage_bid_max)).max(self.storage_price_min);
+            depth.bids = Vec::with_capacity(capacity(
+                self.bid_count,
+                depth.bid_floor,
+                self.storage_bid_max,
+            ));
+            for price in (depth.bid_floor..=self.storage_bid_max).rev() {
+                let quantity = self.bids[self.idx(price)];
+                if quantity > 0 {
+                    depth.bids.push([price, quantity]);
+                }
+            }
+            // Levels past the ones a full book keeps aren't known, so the band ends with
+            // its worst level.
+            if self.is_full(self.bid_count) && depth.bids.len() == self.bid_count {
+                depth.bid_floor = depth.bids[depth.bids.len() - 1][0];
+            }
+        }
+        if self.storage_ask_min <= self.storage_price_max {
+            depth.ask_ceiling =
+                (self.storage_ask_min + band(self.storage_ask_min)).min(self.storage_price_max);
+            depth.asks = Vec::with_capacity(capacity(
+                self.ask_count,
+                self.storage_ask_min,
+                depth.ask_ceiling,
+            ));
+            for price in self.storage_ask_min..=depth.ask_ceiling {
+                let quantity = self.asks[self.idx(price)];
+                if quantity > 0 {
+                    depth.asks.push([price, quantity]);
+                }
+            }
+            if self.is_full(self.ask_count) && depth.asks.len() == self.ask_count {
+                depth.ask_ceiling = depth.asks[depth.asks.len() - 1][0];
+            }
+        }
+        depth
+    }
+    /// Whether a side with `count` levels holds all [max_depth](Self::set_max_depth) of them
+    fn is_full(&self, count: usize) -> bool {
+        self.max_depth.is_some_and(|max| count >= max as usize)
+    }
+    fn top_levels(&self, levels: u32) -> TopLevels {
+        let bids = self.top_bids(levels);
+        let asks = self.top_asks(levels);
+        TopLevels {
+            exchange: self.exchange,
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            scale_price: self.scale_price,
+            scale_quantity: self.scale_quantity,
+            bid_times: self.times(&bids, &self.bid_times),
+            ask_times: self.times(&asks, &self.ask_times),
+            bids,
+            asks,
+            depth: Arc::new(self.depth()),
+        }
     }
     pub fn get_book_levels(&self, levels: u32) -> Option<BookLevels> {
-        // levels come out here with the best bid and ask at the end of the vector
-        let bids = self.get_bids_levels(levels).ok()?;
-        let asks = self.get_asks_levels(levels).ok()?;
-        if bids.is_empty() && asks.is_empty() {
-            None
+        self.top_levels(levels).to_book_levels()
+    }
+    /// Copies out the best `levels` bids and asks if an update has touched a visible price
+    /// since they were last taken, or `None` if they haven't changed. Only the storage
+    /// amounts are copied, see [TopLevels::to_book_levels].
+    pub fn take_top_levels(&mut self, levels: u32) -> Option<TopLevels> {
+        if !self.cache.dirty && self.cache.levels == levels {
+            return None;
+        }
+        let top_levels = self.top_levels(levels);
+        self.cache.bid_floor = match top_levels.bids.last() {
+            Some(&[price, _]) if top_levels.bids.len() == levels as usize => price,
+            _ => self.storage_price_min,
+        };
+        self.cache.ask_ceiling = match top_levels.asks.last() {
+            Some(&[price, _]) if top_levels.asks.len() == levels as usize => price,
+            _ => self.storage_price_max,
+        };
+        // The liquidity bands are read from the depth, so it has to follow every change
+        // within them too.
+        self.cache.bid_floor = self.cache.bid_floor.min(top_levels.depth.bid_floor);
+        self.cache.ask_ceiling = self.cache.ask_ceiling.max(top_levels.depth.ask_ceiling);
+        self.cache.book_levels = None;
+        self.cache.levels = levels;
+        self.cache.dirty = false;
+        Some(top_levels)
+    }
+    /// Returns the same levels as [get_book_levels](Self::get_book_levels), only rebuilding
+    /// them when an update has touched a visible price since the last call. The levels are
+    /// shared with the cache rather than copied.
+    pub fn cached_book_levels(&mut self, levels: u32) -> Option<Arc<BookLevels>> {
+        if let Some(top_levels) = self.take_top_levels(levels) {
+            self.cache.book_levels = top_levels.to_book_levels().map(Arc::new);
+        } else if self.cache.book_levels.is_none() {
+            // The levels were taken with take_top_levels and not kept.
+            self.cache.book_levels = self.get_book_levels(levels).map(Arc::new);
         } else {
-            Some(BookLevels {
-                exchange: self.exchange,
-                symbol: self.symbol,
-                last_update_id: self.last_update_id,
-                bids,
-                asks,
-            })
+            self.cache.hits += 1;
+            if let Some(book_levels) = self.cache.book_levels.as_mut() {
+                Arc::make_mut(book_levels).last_update_id = self.last_update_id;
+            }
+            #[cfg(debug_assertions)]
+            if self.cache.hits.is_multiple_of(64) {
+                self.assert_cache_matches_rebuild();
+            }
         }
+        self.cache.book_levels.clone()
     }
-    pub fn update<U: Update + std::fmt::Debug>(&mut self, update: &mut U) -> Result<()> {
+    /// Panics if the cached levels differ from the levels built from scratch.
+    pub(crate) fn assert_cache_matches_rebuild(&self) {
+        let rebuilt = self.get_book_levels(self.cache.levels);
+        let cached = self.cache.book_levels.as_deref();
+        assert_eq!(
+            cached.map(|l| (&l.bids, &l.asks)),
+            rebuilt.as_ref().map(|l| (&l.bids, &l.asks)),
+            "cached levels differ from rebuilt levels for {} {}",
+            self.exchange,
+            self.symbol
+        );
+    }
+    /// Checks `update` follows on from the last update applied, or only that it overlaps
+    /// the snapshot when it's the first update after one.
+    pub fn check_sequence<U: Update>(&self, update: &U) -> Result<()> {
+        if self.after_snapshot {
+            update.validate_first(self.last_update_id)
+        } else {
+            update.validate(self.last_update_id)
+        }
+    }
+    /// Applies the update and returns whether the levels returned by
+    /// [take_top_levels](Self::take_top_levels) and
+    /// [cached_book_levels](Self::cached_book_levels) need rebuilding, which is the case when
+    /// the update touched a price within them.
+    pub fn update<U: Update + std::fmt::Debug>(&mut self, update: &mut U) -> Result<bool> {
+        self.update_at(update, now_ms())
+    }
+    /// [update](Self::update) applied at `now_ms`, in milliseconds since the unix epoch.
+    ///
+    /// Each level the update changes the quantity of is stamped with `now_ms`, which its
+    /// [Level]s report as `updated_at`. Levels it leaves as they were keep their time, even
+    /// when an update replacing the book carries them again. Prices the book's tick rounds
+    /// to the same level take the time of the latest of them.
+    pub fn update_at<U: Update + std::fmt::Debug>(
+        &mut self,
+        update: &mut U,
+        now_ms: u64,
+    ) -> Result<bool> {
         tracing::debug!("update {:#?}", update);
 
-        update.validate(self.last_update_id)?;
+        self.check_sequence(update)?;
+        let replaced = update.replaces_book().then(|| {
+            let levels = [
+                self.top_bids(self.bid_count as u32),
+                self.top_asks(self.ask_count as u32),
+            ];
+            let times = [
+                self.times(&levels[0], &self.bid_times),
+                self.times(&levels[1], &self.ask_times),
+            ];
+            self.clear();
+            (levels, times)
+        });
 
         // this is set up this way to be able to consume the update without copying it
-        for bid in update.bids_mut().into_iter() {
+        for bid in update.bids_mut().iter_mut() {
             tracing::debug!("adding bid: {:?}", bid);
-            self.add_bid(*bid)?
+            self.set_bid(*bid, now_ms)?
         }
-        for ask in update.asks_mut().into_iter() {
+        for ask in update.asks_mut().iter_mut() {
             tracing::debug!("adding ask: {:?}", ask);
-            self.add_ask(*ask)?
+            self.set_ask(*ask, now_ms)?
         }
+        // Levels the replacement carries with the same quantity keep the time they were set.
+        if let Some(([bids, asks], [bid_times, ask_times])) = replaced {
+            for ([price, quantity], time) in bids.into_iter().zip(bid_times) {
+                let idx = self.idx(price);
+                if self.bids[idx] == quantity {
+                    self.bid_times[idx] = time;
+                }
+            }
+            for ([price, quantity], time) in asks.into_iter().zip(ask_times) {
+                let idx = self.idx(price);
+                if self.asks[idx] == quantity {
+                    self.ask_times[idx] = time;
+                }
+            }
+        }
+        self.trim();
 
+        // Books start from a snapshot, which is applied to an empty book.
+        self.after_snapshot = self.last_update_id == 0;
         self.last_update_id = update.last_update_id();
 
-        Ok(())
+        Ok(self.cache.dirty)
     }
 }
 
@@ -367,4 +860,294 @@ mod tests {
         );
         assert_eq!(ob.display_quantity(1).unwrap().to_string(), "0.00000001");
     }
+
+    fn level(price: i64, quantity: i64) -> [Decimal; 2] {
+        [Decimal::new(price, 0), Decimal::new(quantity, 0)]
+    }
+
+    /// Minimal update for driving the book directly.
+    #[derive(Debug)]
+    struct TestUpdate {
+        id: u64,
+        bids: Vec<[Decimal; 2]>,
+        asks: Vec<[Decimal; 2]>,
+    }
+
+    impl Update for TestUpdate {
+        fn validate(&self, _: u64) -> Result<()> {
+            Ok(())
+        }
+        fn last_update_id(&self) -> u64 {
+            self.id
+        }
+        fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.bids
+        }
+        fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.asks
+        }
+    }
+
+    fn seeded_book() -> OrderBook {
+        let mut ob = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 900, 1100, 0, 0);
+        let mut snapshot = TestUpdate {
+            id: 1,
+            bids: (950..1000).map(|p| level(p, 1)).collect(),
+            asks: (1001..1050).map(|p| level(p, 1)).collect(),
+        };
+        assert!(ob.update(&mut snapshot).unwrap());
+        ob.cached_book_levels(5).unwrap();
+        ob
+    }
+
+    #[test]
+    fn it_reports_whether_updates_touch_the_visible_levels() {
+        let mut ob = seeded_book();
+        let update = |ob: &mut OrderBook, bids, asks| {
+            let changed = ob.update(&mut TestUpdate { id: 2, bids, asks }).unwrap();
+            ob.cached_book_levels(5);
+            changed
+        };
+
+        // Top five bids are 995..=999 and asks 1001..=1005.
+        assert!(!update(&mut ob, vec![level(960, 3)], vec![]));
+        assert!(!update(&mut ob, vec![], vec![level(1040, 0)]));
+        assert!(update(&mut ob, vec![level(995, 2)], vec![]));
+        assert!(update(&mut ob, vec![], vec![level(1005, 0)]));
+        assert!(update(&mut ob, vec![level(1000, 1)], vec![]));
+        let book_levels = ob.cached_book_levels(5).unwrap();
+        assert_eq!(book_levels.bids[0].price, 1000.0);
+        assert_eq!(book_levels.asks[4].price, 1006.0);
+        assert_eq!(book_levels.last_update_id, 2);
+    }
+
+    #[test]
+    fn it_retakes_the_levels_when_an_update_lands_in_the_depth_band() {
+        let mut ob = seeded_book();
+        // Past the top five bids at 995..=999, but within the band down to 990.
+        assert!(ob
+            .update(&mut TestUpdate {
+                id: 2,
+                bids: vec![level(992, 7)],
+                asks: vec![],
+            })
+            .unwrap());
+        let top_levels = ob.take_top_levels(5).unwrap();
+        assert!(top_levels.depth.bids.contains(&[992, 7]));
+        assert!(ob.take_top_levels(5).is_none());
+    }
+
+    #[test]
+    fn it_takes_top_levels_only_when_they_change() {
+        let mut ob = seeded_book();
+        assert!(ob.take_top_levels(5).is_none());
+
+        ob.update(&mut TestUpdate {
+            id: 2,
+            bids: vec![level(998, 4)],
+            asks: vec![],
+        })
+        .unwrap();
+        let top_levels = ob.take_top_levels(5).unwrap();
+        assert_eq!(top_levels.bids[1], [998, 4]);
+        let book_levels = top_levels.to_book_levels().unwrap();
+        let rebuilt = ob.get_book_levels(5).unwrap();
+        assert_eq!(book_levels.bids, rebuilt.bids);
+        assert_eq!(book_levels.asks, rebuilt.asks);
+        assert_eq!(book_levels.last_update_id, 2);
+        assert!(ob.take_top_levels(5).is_none());
+
+        // The cache rebuilds levels that were taken rather than returning stale ones.
+        assert_eq!(ob.cached_book_levels(5).unwrap().bids, rebuilt.bids);
+    }
+
+    /// Update carrying the whole top of the book, like a partial depth stream sends.
+    #[derive(Debug)]
+    struct TestReplacement(TestUpdate);
+
+    impl Update for TestReplacement {
+        fn validate(&self, _: u64) -> Result<()> {
+            Ok(())
+        }
+        fn last_update_id(&self) -> u64 {
+            self.0.id
+        }
+        fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.0.bids
+        }
+        fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.0.asks
+        }
+        fn replaces_book(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn it_replaces_the_book_with_updates_that_carry_all_of_it() {
+        let mut ob = seeded_book();
+        ob.update(&mut TestReplacement(TestUpdate {
+            id: 2,
+            bids: vec![level(970, 2), level(960, 1)],
+            asks: vec![level(1020, 3)],
+        }))
+        .unwrap();
+        let book_levels = ob.cached_book_levels(5).unwrap();
+        let prices = |levels: &[Level]| levels.iter().map(|l| l.price).collect::<Vec<_>>();
+        assert_eq!(prices(&book_levels.bids), [970.0, 960.0]);
+        assert_eq!(prices(&book_levels.asks), [1020.0]);
+        assert_eq!(ob.storage_bid_max, 970);
+        assert_eq!(ob.storage_ask_min, 1020);
+        assert_eq!((ob.bid_levels(), ob.ask_levels()), (2, 1));
+    }
+
+    #[test]
+    fn it_stamps_each_level_with_the_update_that_last_changed_it() {
+        let mut ob = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 900, 1100, 0, 0);
+        let ages = |ob: &OrderBook| {
+            let ages = |levels: Vec<Level>| {
+                levels
+                    .iter()
+                    .map(|l| (l.price, l.updated_at))
+                    .collect::<Vec<_>>()
+            };
+            (
+                ages(ob.get_bids_levels(3).unwrap()),
+                ages(ob.get_asks_levels(3).unwrap()),
+            )
+        };
+        let update = |id, bids, asks| TestUpdate { id, bids, asks };
+        ob.update_at(
+            &mut update(
+                1,
+                (997..1000).map(|p| level(p, 1)).collect(),
+                (1001..1004).map(|p| level(p, 1)).collect(),
+            ),
+            1000,
+        )
+        .unwrap();
+
+        // A changed quantity is stamped and the same one sent again keeps its time.
+        ob.update_at(
+            &mut update(2, vec![level(998, 2), level(999, 1)], vec![level(1001, 0)]),
+            2000,
+        )
+        .unwrap();
+        ob.update_at(&mut update(3, vec![], vec![level(1004, 1)]), 3000)
+            .unwrap();
+        assert_eq!(
+            ages(&ob),
+            (
+                vec![
+                    (999.0, Some(1000)),
+                    (998.0, Some(2000)),
+                    (997.0, Some(1000))
+                ],
+                vec![
+                    (1002.0, Some(1000)),
+                    (1003.0, Some(1000)),
+                    (1004.0, Some(3000))
+                ],
+            )
+        );
+
+        // A replacement keeps the times of the levels it carries unchanged.
+        ob.update_at(
+            &mut TestReplacement(update(
+                4,
+                vec![level(999, 1), level(998, 3)],
+                vec![level(1002, 1), level(1003, 1), level(1004, 1)],
+            )),
+            4000,
+        )
+        .unwrap();
+        assert_eq!(
+            ages(&ob),
+            (
+                vec![(999.0, Some(1000)), (998.0, Some(4000))],
+                vec![
+                    (1002.0, Some(1000)),
+                    (1003.0, Some(1000)),
+                    (1004.0, Some(3000))
+                ],
+            )
+        );
+
+        // Levels set after a resync start over.
+        ob.clear();
+        ob.update_at(&mut update(5, vec![level(999, 1)], vec![]), 5000)
+            .unwrap();
+        assert_eq!(ages(&ob).0, [(999.0, Some(5000))]);
+    }
+
+    #[test]
+    fn it_keeps_only_the_best_levels_up_to_its_depth() {
+        let mut ob = seeded_book();
+        ob.set_max_depth(Some(3));
+        ob.update(&mut TestUpdate {
+            id: 2,
+            bids: vec![level(990, 2)],
+            asks: vec![],
+        })
+        .unwrap();
+        assert_eq!((ob.bid_levels(), ob.ask_levels()), (3, 3));
+        let prices = |levels: &[Level]| levels.iter().map(|l| l.price).collect::<Vec<_>>();
+        let book_levels = ob.cached_book_levels(5).unwrap();
+        assert_eq!(prices(&book_levels.bids), [999.0, 998.0, 997.0]);
+        assert_eq!(prices(&book_levels.asks), [1001.0, 1002.0, 1003.0]);
+
+        // A better level pushes the worst one out.
+        ob.update(&mut TestUpdate {
+            id: 3,
+            bids: vec![],
+            asks: vec![level(1000, 1)],
+        })
+        .unwrap();
+        let book_levels = ob.cached_book_levels(5).unwrap();
+        assert_eq!(prices(&book_levels.asks), [1000.0, 1001.0, 1002.0]);
+        assert_eq!(ob.asks[ob.idx(1003)], 0);
+        assert_eq!(ob.ask_times[ob.idx(1003)], 0);
+        assert_eq!(ob.bid_times[ob.idx(990)], 0);
+
+        // The depth reported ends with the last level kept rather than the band's edge.
+        let depth = ob.depth();
+        assert_eq!(depth.bid_floor, 997);
+        assert_eq!(depth.ask_ceiling, 1002);
+    }
+
+    #[test]
+    fn it_keeps_cached_levels_in_line_with_a_rebuild() {
+        let mut ob = seeded_book();
+        // Deterministic pseudo random walk over prices near the top of the book.
+        let mut seed: u64 = 42;
+        let mut next = |max: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % max
+        };
+        for id in 2..5000 {
+            let bid = level(940 + next(70) as i64, next(3) as i64);
+            let ask = level(995 + next(70) as i64, next(3) as i64);
+            let mut update = TestUpdate {
+                id,
+                bids: vec![bid],
+                asks: vec![ask],
+            };
+            ob.update(&mut update).unwrap();
+            let cached = ob.cached_book_levels(5);
+            let rebuilt = ob.get_book_levels(5);
+            assert_eq!(
+                cached.map(|l| (l.bids.clone(), l.asks.clone())),
+                rebuilt.map(|l| (l.bids, l.asks)),
+                "update {}",
+                id
+            );
+            ob.assert_cache_matches_rebuild();
+            let count = |side: &[StorageAmount]| side.iter().filter(|&&q| q > 0).count();
+            assert_eq!(ob.bid_levels(), count(&ob.bids), "update {}", id);
+            assert_eq!(ob.ask_levels(), count(&ob.asks), "update {}", id);
+        }
+        assert!(ob.cache.hits > 0);
+    }
 }
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+/// How an exchange reports the request weight used against its per minute limit
+#[derive(Debug, Clone, Copy)]
+pub struct WeightLimits {
+    /// Response header with the weight used in the current minute
+    pub used_header: &'static str,
+    /// Weight allowed per minute
+    pub limit: u32,
+    /// Cool down after a ban when the exchange doesn't say how long it lasts
+    pub ban_cool_down: Duration,
+}
+
+/// Returned while an exchange has banned this IP for going over its rate limits.
+#[derive(Debug)]
+pub struct Banned {
+    pub host: String,
+    pub remaining: Duration,
+}
+
+impl std::fmt::Display for Banned {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} has banned this IP for exceeding its rate limits, {}s remaining",
+            self.host,
+            self.remaining.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for Banned {}
+
+#[derive(Debug, Default)]
+struct State {
+    used: u32,
+    blocked_until: Option<Instant>,
+    banned: bool,
+}
+
+/// Tracks the weight an exchange reports as used this minute so requests wait for the
+/// next minute instead of going over the limit. Requests also wait out the `Retry-After`
+/// of a 429, and fail with [Banned] until a 418 ban has passed. Shared by every request
+/// to the same host through [HttpClient](super::http::HttpClient).
+#[derive(Debug)]
+pub struct RateLimiter {
+    host: String,
+    limits: WeightLimits,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    pub fn new(host: String, limits: WeightLimits) -> Self {
+        Self {
+            host,
+            limits,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Waits until a request of `weight` can be sent.
+    pub async fn acquire(&self, weight: u32) -> Result<()> {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                match state.blocked_until {
+                    Some(until) if until > now => {
+                        if state.banned {
+                            return Err(Banned {
+                                host: self.host.clone(),
+                                remaining: until - now,
+                            }
+                            .into());
+                        }
+                        until - now
+                    }
+                    _ => {
+                        state.blocked_until = None;
+                        state.banned = false;
+                        if state.used + weight <= self.limits.limit {
+                            state.used += weight;
+                            return Ok(());
+                        }
+                        // The used weight resets at the start of each minute.
+                        state.used = 0;
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                        Duration::from_secs(60) - Duration::from_secs(now.as_secs() % 60)
+                    }
+                }
+            };
+            tracing::warn!(
+                "waiting {:?} for the {} rate limit, weight {}",
+                wait,
+                self.host,
+                weight
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Updates the used weight and any cool down from a response.
+    pub fn record(&self, status: StatusCode, headers: &HeaderMap) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(used) = headers
+            .get(self.limits.used_header)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+        {
+            state.used = used;
+        }
+        let retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => {
+                let wait = retry_after.unwrap_or(Duration::from_secs(60));
+                tracing::warn!("{} rate limit hit, waiting {:?}", self.host, wait);
+                state.blocked_until = Some(Instant::now() + wait);
+            }
+            StatusCode::IM_A_TEAPOT => {
+                let wait = retry_after.unwrap_or(self.limits.ban_cool_down);
+                tracing::error!(
+                    "{} has BANNED this IP for exceeding its rate limits, no requests for {:?}",
+                    self.host,
+                    wait
+                );
+                state.blocked_until = Some(Instant::now() + wait);
+                state.banned = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    const LIMITS: WeightLimits = WeightLimits {
+        used_header: "x-mbx-used-weight-1m",
+        limit: 100,
+        ban_cool_down: Duration::from_secs(600),
+    };
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_waits_for_the_next_minute_near_the_limit() {
+        let limiter = RateLimiter::new("binance".to_string(), LIMITS);
+        limiter.acquire(10).await.unwrap();
+        limiter.record(StatusCode::OK, &headers(&[("x-mbx-used-weight-1m", "95")]));
+
+        let started = Instant::now();
+        limiter.acquire(5).await.unwrap();
+        assert!(started.elapsed().is_zero());
+        limiter.acquire(10).await.unwrap();
+        let waited = started.elapsed();
+        assert!(waited > Duration::ZERO && waited <= Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_backs_off_on_429_and_fails_while_banned() {
+        let limiter = RateLimiter::new("binance".to_string(), LIMITS);
+        limiter.record(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[("retry-after", "7")]),
+        );
+        let started = Instant::now();
+        limiter.acquire(1).await.unwrap();
+        assert_eq!(started.elapsed().as_secs(), 7);
+
+        limiter.record(StatusCode::IM_A_TEAPOT, &HeaderMap::new());
+        let err = limiter.acquire(1).await.err().unwrap();
+        let banned = err.downcast_ref::<Banned>().unwrap();
+        assert_eq!(banned.remaining, Duration::from_secs(600));
+
+        tokio::time::advance(Duration::from_secs(600)).await;
+        limiter.acquire(1).await.unwrap();
+    }
+}
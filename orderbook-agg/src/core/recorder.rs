@@ -0,0 +1,517 @@
+//! Records the raw frames received from each exchange to disk, for finding out later what
+//! sequence of messages led to a bad book.
+//!
+//! ## Format, version 1
+//! Each exchange and symbol is recorded to its own files, named
+//! `{EXCHANGE}-{SYMBOL}-{index}.obrec` with the index counting up from 0 as files are
+//! rotated. A file starts with the 5 bytes `OBREC` and a version byte, then records:
+//!
+//! | bytes  | field                                                              |
+//! |--------|--------------------------------------------------------------------|
+//! | 4      | length of the rest of the record, u32 little endian                |
+//! | 8      | when the frame was received, microseconds since the unix epoch, u64 little endian |
+//! | 1      | exchange, see [exchange_code]                                      |
+//! | 1      | frame, 0 for text and 1 for binary                                 |
+//! | length - 10 | the frame's payload as received, before binary frames are decoded |
+//!
+//! Only text and binary frames are recorded, pings and closes aren't. Any change to the
+//! layout bumps the version, and readers refuse versions newer than theirs.
+use anyhow::{bail, ensure, Context, Result};
+use futures::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context as TaskContext, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::{Exchange, Symbol};
+
+pub const MAGIC: &[u8; 5] = b"OBREC";
+pub const VERSION: u8 = 1;
+const EXTENSION: &str = "obrec";
+/// Bytes of a record after the length and before the payload
+const RECORD_HEADER: usize = 10;
+
+/// The byte an exchange is recorded as. Codes are never reused.
+pub fn exchange_code(exchange: Exchange) -> u8 {
+    match exchange {
+        Exchange::BITSTAMP => 1,
+        Exchange::BINANCE => 2,
+        Exchange::BINANCE_FUTURES => 3,
+    }
+}
+
+fn exchange_from_code(code: u8) -> Result<Exchange> {
+    Ok(match code {
+        1 => Exchange::BITSTAMP,
+        2 => Exchange::BINANCE,
+        3 => Exchange::BINANCE_FUTURES,
+        _ => bail!("unknown exchange code {}", code),
+    })
+}
+
+/// A frame received from an exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Microseconds since the unix epoch
+    pub received: u64,
+    pub exchange: Exchange,
+    /// Text frames are [Message::Text] and binary ones [Message::Binary]
+    pub message: Message,
+}
+
+impl Record {
+    fn write_to(&self, out: &mut impl Write) -> io::Result<usize> {
+        let (frame, payload) = match &self.message {
+            Message::Text(text) => (0, text.as_bytes()),
+            Message::Binary(data) => (1, data.as_slice()),
+            _ => return Ok(0),
+        };
+        let length = RECORD_HEADER + payload.len();
+        out.write_all(&(length as u32).to_le_bytes())?;
+        out.write_all(&self.received.to_le_bytes())?;
+        out.write_all(&[exchange_code(self.exchange), frame])?;
+        out.write_all(payload)?;
+        Ok(4 + length)
+    }
+}
+
+/// Reads the records of a recorded file in the order they were received.
+pub struct RecordReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut input: R) -> Result<Self> {
+        let mut header = [0; 6];
+        input
+            .read_exact(&mut header)
+            .context("not a recording, too short")?;
+        ensure!(&header[..5] == MAGIC, "not a recording");
+        ensure!(
+            header[5] <= VERSION,
+            "recording version {} is newer than {}",
+            header[5],
+            VERSION
+        );
+        Ok(Self { input })
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>> {
+        let mut length = [0; 4];
+        match self.input.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let length = u32::from_le_bytes(length) as usize;
+        ensure!(length >= RECORD_HEADER, "record too short");
+        let mut record = vec![0; length];
+        // A record cut short was being written when the recording stopped.
+        self.input
+            .read_exact(&mut record)
+            .context("recording ends part way through a record")?;
+        let received = u64::from_le_bytes(record[..8].try_into().unwrap());
+        let exchange = exchange_from_code(record[8])?;
+        let payload = record[RECORD_HEADER..].to_vec();
+        let message = match record[9] {
+            0 => Message::Text(String::from_utf8(payload).context("text frame isn't utf-8")?),
+            1 => Message::Binary(payload),
+            frame => bail!("unknown frame type {}", frame),
+        };
+        Ok(Some(Record {
+            received,
+            exchange,
+            message,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Where and how much to record
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    /// Files are rotated once they're bigger than this
+    pub max_file_bytes: u64,
+    /// The oldest files are removed once the recordings in `dir` add up to more than this
+    pub max_total_bytes: u64,
+    /// Frames waiting to be written before more are dropped
+    pub capacity: usize,
+}
+
+impl RecorderConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_file_bytes: 64 * 1024 * 1024,
+            max_total_bytes: 1024 * 1024 * 1024,
+            capacity: 10_000,
+        }
+    }
+}
+
+/// Hands frames to a writer on its own thread. Recording never waits, frames that arrive
+/// while the writer is behind by [RecorderConfig::capacity] are dropped and counted.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<(Symbol, Record)>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Starts the writer, creating `config.dir` if needed.
+    pub fn spawn(config: RecorderConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("failed to create {}", config.dir.display()))?;
+        let mut writer = Writer::new(config.clone())?;
+        let (tx, mut rx) = mpsc::channel(config.capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = dropped.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut reported = 0;
+            while let Some(received) = rx.blocking_recv() {
+                // Everything waiting is written before flushing.
+                let mut next = Some(received);
+                while let Some((symbol, record)) = next {
+                    if let Err(err) = writer.write(symbol, &record) {
+                        tracing::error!("failed to record {} frame: {}", record.exchange, err);
+                    }
+                    next = rx.try_recv().ok();
+                }
+                writer.flush();
+                let dropped = writer_dropped.load(Ordering::Relaxed);
+                if dropped > reported {
+                    tracing::warn!("recorder fell behind, {} frames dropped", dropped);
+                    reported = dropped;
+                }
+            }
+            writer.flush();
+        });
+        Ok(Self { tx, dropped })
+    }
+
+    pub fn record(&self, exchange: Exchange, symbol: Symbol, message: &Message) {
+        if !matches!(message, Message::Text(_) | Message::Binary(_)) {
+            return;
+        }
+        let received = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let record = Record {
+            received,
+            exchange,
+            message: message.clone(),
+        };
+        if self.tx.try_send((symbol, record)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Frames dropped because the writer was behind or had stopped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct OpenFile {
+    out: BufWriter<File>,
+    path: PathBuf,
+    index: u64,
+    bytes: u64,
+}
+
+/// Writes records to the file for their exchange and symbol, rotating files and removing
+/// the oldest ones as it goes.
+struct Writer {
+    config: RecorderConfig,
+    open: HashMap<(Exchange, Symbol), OpenFile>,
+    /// Every recording in the directory and its size, oldest first
+    files: VecDeque<(PathBuf, u64)>,
+    total_bytes: u64,
+}
+
+impl Writer {
+    fn new(config: RecorderConfig) -> Result<Self> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&config.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                let metadata = entry.metadata()?;
+                files.push((metadata.modified()?, path, metadata.len()));
+            }
+        }
+        files.sort();
+        let total_bytes = files.iter().map(|(_, _, bytes)| bytes).sum();
+        Ok(Self {
+            config,
+            open: HashMap::new(),
+            files: files
+                .into_iter()
+                .map(|(_, path, bytes)| (path, bytes))
+                .collect(),
+            total_bytes,
+        })
+    }
+
+    fn write(&mut self, symbol: Symbol, record: &Record) -> Result<()> {
+        let key = (record.exchange, symbol);
+        let rotate = self
+            .open
+            .get(&key)
+            .map(|file| (file.bytes >= self.config.max_file_bytes).then_some(file.index + 1));
+        let index = match rotate {
+            None => Some(self.next_index(record.exchange, symbol)),
+            Some(index) => index,
+        };
+        if let Some(index) = index {
+            if let Some(mut file) = self.open.remove(&key) {
+                file.out.flush()?;
+            }
+            let file = self.create(record.exchange, symbol, index)?;
+            self.open.insert(key, file);
+        }
+
+        let file = self.open.get_mut(&key).unwrap();
+        let bytes = record.write_to(&mut file.out)? as u64;
+        file.bytes += bytes;
+        let path = file.path.clone();
+        if let Some((_, size)) = self.files.iter_mut().rev().find(|(p, _)| *p == path) {
+            *size += bytes;
+        }
+        self.total_bytes += bytes;
+        self.remove_oldest();
+        Ok(())
+    }
+
+    fn file_name(exchange: Exchange, symbol: Symbol, index: u64) -> String {
+        format!("{}-{}-{:06}.{}", exchange, symbol, index, EXTENSION)
+    }
+
+    /// The index after any recordings already in the directory for the exchange and symbol
+    fn next_index(&self, exchange: Exchange, symbol: Symbol) -> u64 {
+        let prefix = format!("{}-{}-", exchange, symbol);
+        self.files
+            .iter()
+            .filter_map(|(path, _)| {
+                let name = path.file_stem()?.to_str()?;
+                name.strip_prefix(&prefix)?.parse::<u64>().ok()
+            })
+            .max()
+            .map_or(0, |index| index + 1)
+    }
+
+    fn create(&mut self, exchange: Exchange, symbol: Symbol, index: u64) -> Result<OpenFile> {
+        let path = self
+            .config
+            .dir
+            .join(Self::file_name(exchange, symbol, index));
+        let mut out = BufWriter::new(
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?,
+        );
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        let bytes = (MAGIC.len() + 1) as u64;
+        self.files.push_back((path.clone(), bytes));
+        self.total_bytes += bytes;
+        Ok(OpenFile {
+            out,
+            path,
+            index,
+            bytes,
+        })
+    }
+
+    /// Removes the oldest files until the total is under the cap, never one being written.
+    fn remove_oldest(&mut self) {
+        while self.total_bytes > self.config.max_total_bytes {
+            let open = |path: &Path| self.open.values().any(|file| file.path == path);
+            let Some(oldest) = self.files.iter().position(|(path, _)| !open(path)) else {
+                break;
+            };
+            let (path, bytes) = self.files.remove(oldest).unwrap();
+            if let Err(err) = fs::remove_file(&path) {
+                tracing::warn!("failed to remove {}: {}", path.display(), err);
+            }
+            self.total_bytes -= bytes;
+        }
+    }
+
+    fn flush(&mut self) {
+        for file in self.open.values_mut() {
+            if let Err(err) = file.out.flush() {
+                tracing::error!("failed to flush {}: {}", file.path.display(), err);
+            }
+        }
+    }
+}
+
+/// Passes an exchange's update stream through, recording each frame on the way when
+/// there's a recorder.
+pub struct Recording<St> {
+    stream: St,
+    recorder: Option<Recorder>,
+    exchange: Exchange,
+    symbol: Symbol,
+}
+
+impl<St> Recording<St> {
+    pub fn new(stream: St, recorder: Option<Recorder>, exchange: Exchange, symbol: Symbol) -> Self {
+        Self {
+            stream,
+            recorder,
+            exchange,
+            symbol,
+        }
+    }
+}
+
+impl<St> Stream for Recording<St>
+where
+    St: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    type Item = St::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.stream).poll_next(cx);
+        if let (Poll::Ready(Some(Ok(message))), Some(recorder)) = (&polled, &self.recorder) {
+            recorder.record(self.exchange, self.symbol, message);
+        }
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn recordings(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn read(path: PathBuf) -> Vec<Record> {
+        RecordReader::new(File::open(path).unwrap())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_records_frames_to_a_file_for_each_exchange_and_symbol() {
+        let dir = std::env::temp_dir().join(format!("obrec-{}-frames", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recorder = Recorder::spawn(RecorderConfig::new(&dir)).unwrap();
+
+        let frames = futures::stream::iter([
+            Ok(Message::Text(r#"{"u":1}"#.to_string())),
+            Ok(Message::Ping(Vec::new())),
+            Ok(Message::Binary(vec![0, 159, 146, 150])),
+        ]);
+        let recording = Recording::new(
+            frames,
+            Some(recorder.clone()),
+            Exchange::BINANCE,
+            Symbol::BTCUSDT,
+        );
+        // Frames pass through unchanged.
+        assert_eq!(recording.collect::<Vec<_>>().await.len(), 3);
+        recorder.record(
+            Exchange::BITSTAMP,
+            Symbol::BTCUSDT,
+            &Message::Text("{}".to_string()),
+        );
+        drop(recorder);
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(
+            recordings(&dir),
+            [
+                "BINANCE-BTCUSDT-000000.obrec",
+                "BITSTAMP-BTCUSDT-000000.obrec"
+            ]
+        );
+        let records = read(dir.join("BINANCE-BTCUSDT-000000.obrec"));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].exchange, Exchange::BINANCE);
+        assert_eq!(records[0].message, Message::Text(r#"{"u":1}"#.to_string()));
+        assert_eq!(records[1].message, Message::Binary(vec![0, 159, 146, 150]));
+        assert!(records[0].received > 0 && records[0].received <= records[1].received);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn it_rotates_files_and_removes_the_oldest_over_the_cap() {
+        let dir = std::env::temp_dir().join(format!("obrec-{}-rotate", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = RecorderConfig {
+            max_file_bytes: 100,
+            max_total_bytes: 300,
+            ..RecorderConfig::new(&dir)
+        };
+        let mut writer = Writer::new(config.clone()).unwrap();
+        // Each record is 4 + 10 + 50 bytes, so every file holds two.
+        let record = Record {
+            received: 1,
+            exchange: Exchange::BITSTAMP,
+            message: Message::Text("x".repeat(50)),
+        };
+        for _ in 0..8 {
+            writer.write(Symbol::BTCUSDT, &record).unwrap();
+        }
+        writer.flush();
+        assert_eq!(
+            recordings(&dir),
+            [
+                "BITSTAMP-BTCUSDT-000002.obrec",
+                "BITSTAMP-BTCUSDT-000003.obrec"
+            ]
+        );
+        assert_eq!(
+            read(dir.join("BITSTAMP-BTCUSDT-000003.obrec")),
+            vec![record.clone(); 2]
+        );
+
+        // A new writer carries on from the files already there.
+        let mut writer = Writer::new(config).unwrap();
+        writer.write(Symbol::BTCUSDT, &record).unwrap();
+        writer.flush();
+        assert!(recordings(&dir).contains(&"BITSTAMP-BTCUSDT-000004.obrec".to_string()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn it_rejects_files_that_are_not_recordings() {
+        assert!(RecordReader::new(&b"OBREX\x01"[..]).is_err());
+        assert!(RecordReader::new(&b"OBREC\x02"[..]).is_err());
+        let mut reader = RecordReader::new(&b"OBREC\x01\x0c\x00\x00\x00"[..]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+}
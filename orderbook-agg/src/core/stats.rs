@@ -0,0 +1,347 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+pub use crate::book_summary::ConnectionState;
+use crate::{
+    book_summary::{ExchangeStatus, TappedUpdate},
+    Exchange, Symbol,
+};
+
+/// How long a receiving connection can go without a message before it's reported stale
+pub const STALE_AFTER: Duration = Duration::from_secs(10);
+
+/// A count, or a gauge, that any task can update without locking
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn decr(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+    pub fn set(&self, n: u64) {
+        self.0.store(n, Ordering::Relaxed);
+    }
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether a value only goes up, or goes up and down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterKind {
+    Counter,
+    Gauge,
+}
+
+impl CounterKind {
+    /// The Prometheus type of the metric
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CounterKind::Counter => "counter",
+            CounterKind::Gauge => "gauge",
+        }
+    }
+}
+
+/// The value of a [Counter] as it was read, with its description for the metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterValue {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: CounterKind,
+    pub value: u64,
+}
+
+/// Declares a struct of [Counter]s, each a line of its doc comment, whether it's a
+/// `counter` or a `gauge` and its name, with a `values` method reading each of them.
+macro_rules! counters {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $(#[doc = $help:literal] $kind:ident $field:ident,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Default)]
+        pub struct $name {
+            $(#[doc = $help] pub $field: $crate::core::stats::Counter,)*
+        }
+
+        impl $name {
+            /// Reads each counter, none of them locking.
+            pub fn values(&self) -> Vec<$crate::core::stats::CounterValue> {
+                vec![$($crate::core::stats::CounterValue {
+                    name: stringify!($field),
+                    help: $help.trim(),
+                    kind: counters!(@kind $kind),
+                    value: self.$field.get(),
+                },)*]
+            }
+        }
+    };
+    (@kind counter) => { $crate::core::stats::CounterKind::Counter };
+    (@kind gauge) => { $crate::core::stats::CounterKind::Gauge };
+}
+pub(crate) use counters;
+
+counters! {
+    /// Counted for an exchange and symbol across each of its connections
+    pub struct FeedCounters {
+        /// Websocket messages received
+        counter messages,
+        /// Messages that could not be parsed into an update
+        counter parse_errors,
+        /// Updates that were parsed but could not be applied to the order book
+        counter update_errors,
+        /// Updates applied to the order book
+        counter updates_applied,
+        /// Times the order book was rebuilt from a fresh snapshot after a gap in its updates
+        counter resyncs,
+        /// Connections made to the update stream, one more than the reconnects
+        counter connections,
+        /// Milliseconds since the unix epoch the last message was received at
+        gauge last_message_ms,
+        /// Bid levels in the order book
+        gauge bid_levels,
+        /// Ask levels in the order book
+        gauge ask_levels,
+        /// Times the connection changed state
+        counter state_changes,
+        /// Attempts to reconnect since the connection was last receiving
+        gauge reconnect_attempt,
+    }
+}
+
+/// The name of `state` as it's reported in the stats and logs
+pub fn state_name(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Subscribed => "subscribed",
+        ConnectionState::Receiving => "receiving",
+        ConnectionState::Stale => "stale",
+        ConnectionState::Reconnecting => "reconnecting",
+        ConnectionState::Disconnected => "disconnected",
+        ConnectionState::Disabled => "disabled",
+    }
+}
+
+/// Counters for an exchange's order book. Shared between the task that reads messages
+/// from the exchange and the task that applies them to the order book, and kept across
+/// reconnects when the book is made with the stats of a
+/// [PipelineStats](crate::metrics::PipelineStats).
+#[derive(Debug, Default)]
+pub struct ExchangeStats {
+    pub counters: FeedCounters,
+    /// Current [ConnectionState] of the update stream
+    pub state: AtomicU8,
+    /// Number of levels in each message when streaming partial books, 0 when streaming
+    /// changes to a full book
+    pub partial_depth: AtomicU8,
+    /// Index of the websocket url connected to in
+    /// [Endpoints::wss_urls](crate::core::exchange_book::Endpoints::wss_urls), 0 for the
+    /// preferred one
+    pub wss_endpoint: AtomicU8,
+    /// The connection's latest status, its `sequence` 0 until the state first changes
+    status: Mutex<ExchangeStatus>,
+    /// Where each change of state is published besides the logs and counters
+    transitions: Option<broadcast::Sender<ExchangeStatus>>,
+    /// Where the updates applied to the order book are published while anything taps them
+    taps: Option<broadcast::Sender<Arc<TappedUpdate>>>,
+    /// How many taps want the updates of the book's symbol
+    tappers: Arc<AtomicUsize>,
+}
+
+impl ExchangeStats {
+    /// Stats for the connection of `exchange` for `symbol`, named in its status and logs
+    pub fn new(exchange: Exchange, symbol: Symbol) -> Self {
+        Self {
+            status: Mutex::new(ExchangeStatus {
+                exchange: exchange.to_string(),
+                symbol: symbol.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Publishes each change of the connection's state on `transitions`.
+    pub fn with_transitions(mut self, transitions: broadcast::Sender<ExchangeStatus>) -> Self {
+        self.transitions = Some(transitions);
+        self
+    }
+
+    /// Publishes the updates applied to the order book on `taps` while `tappers`, shared
+    /// by the feeds of the book's symbol, counts any taps of the symbol.
+    pub fn with_taps(
+        mut self,
+        taps: broadcast::Sender<Arc<TappedUpdate>>,
+        tappers: Arc<AtomicUsize>,
+    ) -> Self {
+        self.taps = Some(taps);
+        self.tappers = tappers;
+        self
+    }
+
+    pub fn incr_parse_errors(&self) {
+        self.counters.parse_errors.incr();
+    }
+    pub fn incr_update_errors(&self) {
+        self.counters.update_errors.incr();
+    }
+    pub fn incr_resyncs(&self) {
+        self.counters.resyncs.incr();
+    }
+    pub fn parse_errors(&self) -> u64 {
+        self.counters.parse_errors.get()
+    }
+    pub fn update_errors(&self) -> u64 {
+        self.counters.update_errors.get()
+    }
+    pub fn resyncs(&self) -> u64 {
+        self.counters.resyncs.get()
+    }
+    /// Counts a data message received from the exchange just now.
+    pub fn received(&self) {
+        self.counters.messages.incr();
+        self.counters.last_message_ms.set(now_ms());
+    }
+    /// Counts an update applied to the order book, which has the levels given after it.
+    pub fn applied(&self, bid_levels: usize, ask_levels: usize) {
+        self.counters.updates_applied.incr();
+        self.counters.bid_levels.set(bid_levels as u64);
+        self.counters.ask_levels.set(ask_levels as u64);
+    }
+    /// Moves the connection to `state`, doing nothing if it's already in it.
+    pub fn set_state(&self, state: ConnectionState) {
+        self.transition(state, 0, 0, String::new());
+    }
+    pub fn state(&self) -> ConnectionState {
+        ConnectionState::from_i32(self.state.load(Ordering::Relaxed) as i32)
+            .unwrap_or(ConnectionState::Connecting)
+    }
+    /// Counts an update received on the connection, which is subscribed if the exchange
+    /// never acknowledged it, and receiving again if it was stale.
+    pub fn receiving(&self) {
+        match self.state() {
+            ConnectionState::Receiving => {}
+            ConnectionState::Subscribed | ConnectionState::Stale => {
+                self.set_state(ConnectionState::Receiving)
+            }
+            _ => {
+                self.set_state(ConnectionState::Subscribed);
+                self.set_state(ConnectionState::Receiving);
+            }
+        }
+    }
+    /// Marks a receiving connection stale once nothing has arrived on it for [STALE_AFTER].
+    pub fn check_stale(&self) {
+        let quiet = now_ms().saturating_sub(self.counters.last_message_ms.get());
+        if self.state() == ConnectionState::Receiving && quiet >= STALE_AFTER.as_millis() as u64 {
+            self.set_state(ConnectionState::Stale);
+        }
+    }
+    /// Reports the connection closed for `reason`, with the `attempt` to reconnect it
+    /// made at `next_retry_ms` milliseconds since the unix epoch.
+    pub fn reconnecting(&self, attempt: u32, next_retry_ms: u64, reason: String) {
+        self.transition(
+            ConnectionState::Reconnecting,
+            attempt,
+            next_retry_ms,
+            reason,
+        );
+    }
+    /// The connection's latest status
+    pub fn status(&self) -> ExchangeStatus {
+        self.status.lock().unwrap().clone()
+    }
+    /// Whether anything taps the updates of the book's symbol, which are only copied out
+    /// for [tap](Self::tap) when something does, so taps of other symbols cost nothing.
+    pub fn tapping(&self) -> bool {
+        self.taps.is_some() && self.tappers.load(Ordering::Relaxed) > 0
+    }
+    /// Publishes an update to the taps, never waiting on them. Taps that are behind lose
+    /// their oldest updates.
+    pub fn tap(&self, update: TappedUpdate) {
+        if let Some(taps) = &self.taps {
+            // The tap may have ended since it was checked for.
+            let _ = taps.send(Arc::new(update));
+        }
+    }
+
+    /// Changes the state, logging the change, counting it and publishing it, so the logs,
+    /// the counters and the `WatchExchangeStatus` streams agree. Only reconnecting again
+    /// is a change without a new state, the attempt having gone up.
+    fn transition(&self, state: ConnectionState, attempt: u32, next_retry_ms: u64, reason: String) {
+        let mut status = self.status.lock().unwrap();
+        if status.sequence > 0 && status.state() == state && state != ConnectionState::Reconnecting
+        {
+            return;
+        }
+        self.state.store(state as u8, Ordering::Relaxed);
+        self.counters.state_changes.incr();
+        match state {
+            ConnectionState::Reconnecting => self.counters.reconnect_attempt.set(attempt as u64),
+            ConnectionState::Receiving => self.counters.reconnect_attempt.set(0),
+            _ => {}
+        }
+        status.set_state(state);
+        status.attempt = attempt;
+        status.next_retry_ms = next_retry_ms;
+        status.reason = reason;
+        status.changed_at_ms = now_ms();
+        status.sequence += 1;
+        match state {
+            ConnectionState::Reconnecting => tracing::info!(
+                "reconnecting to {} {} in {}ms, attempt {}: {}",
+                status.exchange,
+                status.symbol,
+                next_retry_ms.saturating_sub(status.changed_at_ms),
+                attempt,
+                status.reason
+            ),
+            _ => tracing::info!(
+                "{} {} {}",
+                status.exchange,
+                status.symbol,
+                state_name(state)
+            ),
+        }
+        if let Some(transitions) = &self.transitions {
+            // Nothing is watching while there are no receivers.
+            let _ = transitions.send(status.clone());
+        }
+    }
+    pub fn set_partial_depth(&self, levels: Option<u8>) {
+        self.partial_depth
+            .store(levels.unwrap_or_default(), Ordering::Relaxed);
+    }
+    pub fn partial_depth(&self) -> Option<u8> {
+        Some(self.partial_depth.load(Ordering::Relaxed)).filter(|levels| *levels > 0)
+    }
+    pub fn set_wss_endpoint(&self, index: usize) {
+        self.wss_endpoint
+            .store(index.min(u8::MAX as usize) as u8, Ordering::Relaxed);
+    }
+    pub fn wss_endpoint(&self) -> usize {
+        self.wss_endpoint.load(Ordering::Relaxed) as usize
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
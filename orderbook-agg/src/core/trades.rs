@@ -0,0 +1,156 @@
+//! Trades printed on each exchange, read from their trade streams over the same websocket
+//! handling as the order books but never touching them.
+use anyhow::Result;
+use futures::{Sink, Stream};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use super::{
+    exchange_book::{forward_messages, FromMessage},
+    stats::ExchangeStats,
+};
+use crate::{book_summary::Trade, Exchange, Symbol};
+
+/// The side the taker of a trade was on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+}
+
+/// A message of an exchange's trade stream
+pub trait IntoTrade: FromMessage {
+    /// The trade, leaving the exchange and symbol to be filled in
+    fn into_trade(self) -> Trade;
+}
+
+/// A trade of `quantity` at `price`, `timestamp_ms` being when the exchange made it
+pub fn trade(
+    id: u64,
+    price: Decimal,
+    quantity: Decimal,
+    side: Option<Side>,
+    timestamp_ms: u64,
+) -> Trade {
+    Trade {
+        price: price.to_f64().unwrap_or_default(),
+        quantity: quantity.to_f64().unwrap_or_default(),
+        side: side.map(Side::as_str).unwrap_or_default().to_string(),
+        exchange_timestamp_ms: timestamp_ms,
+        trade_id: id,
+        ..Default::default()
+    }
+}
+
+/// Sends each trade parsed as `T` from an exchange's trade stream on `tx_trades`, tagged
+/// with the exchange and symbol. Frames are read with [forward_messages], and it returns
+/// when that does.
+pub async fn process_trades<T, St, Si>(
+    exchange: Exchange,
+    symbol: Symbol,
+    stats: Arc<ExchangeStats>,
+    stream: St,
+    sink: Si,
+    tx_trades: mpsc::Sender<Trade>,
+) -> Result<()>
+where
+    T: IntoTrade + Send + 'static,
+    St: Stream<Item = Result<Message, WsError>> + Unpin + Send + 'static,
+    Si: Sink<Message, Error = WsError> + Unpin + Send + 'static,
+{
+    stats.counters.connections.incr();
+    let (tx_message, mut rx_message) = mpsc::channel::<T>(100);
+    let send_trades = async move {
+        while let Some(message) = rx_message.recv().await {
+            let trade = Trade {
+                exchange: exchange.to_string(),
+                symbol: symbol.to_string(),
+                ..message.into_trade()
+            };
+            if tx_trades.send(trade).await.is_err() {
+                tracing::info!("trade receiver dropped: {}", exchange);
+                break;
+            }
+        }
+    };
+    let (result, ()) = tokio::join!(
+        forward_messages(exchange, stats, stream, sink, tx_message),
+        send_trades
+    );
+    result
+}
+
+#[cfg(all(test, feature = "exchange-bitstamp"))]
+mod tests {
+    use futures::SinkExt;
+
+    use super::*;
+    use crate::exchanges::bitstamp::data::LiveTrade;
+
+    #[tokio::test]
+    async fn it_tags_each_trade_with_the_exchange_and_symbol() {
+        let trade = |id: u64, side: u8| {
+            let message = serde_json::json!({
+                "data": {
+                    "id": id,
+                    "amount_str": "0.5",
+                    "price_str": "30000.5",
+                    "type": side,
+                    "microtimestamp": "1688515101262376",
+                },
+                "channel": "live_trades_btcusdt",
+                "event": "trade",
+            });
+            Message::Text(message.to_string())
+        };
+        let messages = vec![
+            trade(1, 0),
+            Message::Text("not json".to_string()),
+            trade(2, 1),
+        ];
+        let stats = Arc::new(ExchangeStats::default());
+        let (tx, mut rx) = mpsc::channel(10);
+        process_trades::<LiveTrade, _, _>(
+            Exchange::BITSTAMP,
+            Symbol::BTCUSDT,
+            stats.clone(),
+            futures::stream::iter(messages.into_iter().map(Ok::<_, WsError>)),
+            futures::sink::drain().sink_map_err(|never| match never {}),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        let mut trades = Vec::new();
+        while let Some(trade) = rx.recv().await {
+            trades.push(trade);
+        }
+        assert_eq!(
+            trades[0],
+            Trade {
+                exchange: "BITSTAMP".to_string(),
+                symbol: "BTCUSDT".to_string(),
+                price: 30000.5,
+                quantity: 0.5,
+                side: "buy".to_string(),
+                exchange_timestamp_ms: 1688515101262,
+                trade_id: 1,
+                sequence: 0,
+            }
+        );
+        assert_eq!(trades[1].side, "sell");
+        assert_eq!(trades.len(), 2);
+        assert_eq!(stats.parse_errors(), 1);
+    }
+}
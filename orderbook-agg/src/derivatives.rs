@@ -0,0 +1,140 @@
+//! Mark prices and funding of the derivative exchanges, added to `GetSummary` responses
+//! asking for them with `SummaryRequest.derivatives`.
+//!
+//! Nothing is subscribed to until a client first asks, so servers with only spot clients
+//! open no extra connections. From then on each derivative exchange's mark price stream of
+//! the symbol runs next to its order book, reconnecting with [spawn_exchange_with_stats]
+//! like the books do, and the latest of it is served to every client. An exchange's mark price is
+//! forgotten while its stream is down, rather than served stale. Spot exchanges have none.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+
+use crate::{
+    book_summary::{DerivativeInfo, MarketType},
+    core::{http::HttpClient, stats::ExchangeStats},
+    exchanges::{connector, StreamArgs},
+    service::{spawn_exchange_with_stats, Backoff, ExchangeClosed, ExchangeOptions},
+    Exchange, Symbol,
+};
+
+/// How long the first mark price of a stream has to arrive when it's started for a call
+const FIRST_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Latest = watch::Receiver<Option<DerivativeInfo>>;
+
+/// Starts each derivative exchange's mark price stream of a symbol the first time it's
+/// asked for. Clones share the same streams.
+#[derive(Debug, Clone)]
+pub struct Derivatives {
+    http: HttpClient,
+    options: ExchangeOptions,
+    streams: Arc<Mutex<HashMap<(Exchange, Symbol), Latest>>>,
+}
+
+impl Derivatives {
+    /// Connects with the urls and switches of `options`
+    pub fn new(http: HttpClient, options: &ExchangeOptions) -> Self {
+        Self {
+            http,
+            options: options.clone(),
+            streams: Arc::default(),
+        }
+    }
+
+    /// The latest mark price and funding of each derivative exchange of `exchanges`, by
+    /// exchange name. Streams started by the call are waited on for up to [FIRST_TIMEOUT],
+    /// exchanges with none yet are left out.
+    pub async fn latest(
+        &self,
+        symbol: Symbol,
+        exchanges: &[Exchange],
+    ) -> HashMap<String, DerivativeInfo> {
+        let mut latest = HashMap::new();
+        for &exchange in exchanges {
+            let Some((mut rx_info, started)) = self.subscribe(exchange, symbol) else {
+                continue;
+            };
+            let info = if started {
+                match tokio::time::timeout(FIRST_TIMEOUT, rx_info.wait_for(Option::is_some)).await {
+                    Ok(Ok(info)) => info.clone(),
+                    _ => None,
+                }
+            } else {
+                rx_info.borrow().clone()
+            };
+            match info {
+                Some(info) => {
+                    latest.insert(exchange.to_string(), info);
+                }
+                None => tracing::debug!("no mark price from {} {} yet", exchange, symbol),
+            }
+        }
+        latest
+    }
+
+    /// The latest info of `exchange`'s stream of `symbol`, and whether it was started by
+    /// this call. None for spot exchanges.
+    fn subscribe(&self, exchange: Exchange, symbol: Symbol) -> Option<(Latest, bool)> {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(latest) = streams.get(&(exchange, symbol)) {
+            return Some((latest.clone(), false));
+        }
+        if exchange.market_type() != MarketType::Futures {
+            return None;
+        }
+        let connector = connector(exchange).ok()?;
+        let (tx_info, mut rx_info) = mpsc::channel::<DerivativeInfo>(10);
+        let (tx_closed, mut rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let http = self.http.clone();
+        let endpoints = self
+            .options
+            .endpoints
+            .get(&exchange)
+            .cloned()
+            .unwrap_or_else(|| connector.default_endpoints());
+        let rx_enabled = self.options.switches.subscribe(exchange);
+        // Counted apart from the book's stats, which report the book's connection.
+        let stats = Arc::new(ExchangeStats::new(exchange, symbol));
+        spawn_exchange_with_stats(
+            exchange,
+            symbol,
+            Backoff::default(),
+            rx_enabled,
+            tx_closed,
+            stats.clone(),
+            move || {
+                let args = StreamArgs {
+                    http: http.clone(),
+                    endpoints: endpoints.clone(),
+                    symbol,
+                    stats: stats.clone(),
+                };
+                connector.stream_mark_prices(args, tx_info.clone())
+            },
+        );
+        tracing::info!("Streaming {} {} mark prices", exchange, symbol);
+        let (tx_latest, rx_latest) = watch::channel(None);
+        tokio::spawn(async move {
+            loop {
+                select! {
+                    Some(info) = rx_info.recv() => {
+                        tx_latest.send_replace(Some(info));
+                    }
+                    Some(_) = rx_closed.recv() => {
+                        tx_latest.send_replace(None);
+                    }
+                    else => break,
+                }
+            }
+        });
+        streams.insert((exchange, symbol), rx_latest.clone());
+        Some((rx_latest, true))
+    }
+}
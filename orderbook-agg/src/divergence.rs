@@ -0,0 +1,322 @@
+//! How far apart the exchanges quote a symbol, from the best bid and ask of each
+//! exchange's book rather than the merged levels of its summaries.
+//!
+//! The summary task publishes each exchange's [Quote] on [Quotes] along with every
+//! summary. A divergence stream compares the mid prices of each pair of exchanges, sending
+//! an event when a pair moves further apart than the threshold and again once it's back
+//! under by the hysteresis.
+use anyhow::{ensure, Result};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::{select, sync::mpsc, sync::watch};
+use tonic::Status;
+
+use crate::{book_summary::Divergence, core::order_book::BookLevels, Exchange, Symbol};
+
+/// The best bid and ask of an exchange's book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub exchange: Exchange,
+    pub bid: f64,
+    pub bid_quantity: f64,
+    pub ask: f64,
+    pub ask_quantity: f64,
+}
+
+impl Quote {
+    /// The quote of `levels`, unless one side of the book is empty
+    pub fn new(levels: &BookLevels) -> Option<Self> {
+        let bid = levels.bids.first()?;
+        let ask = levels.asks.first()?;
+        Some(Self {
+            exchange: levels.exchange,
+            bid: bid.price,
+            bid_quantity: bid.quantity,
+            ask: ask.price,
+            ask_quantity: ask.quantity,
+        })
+    }
+
+    fn mid(&self) -> f64 {
+        (self.bid + self.ask) / 2.0
+    }
+}
+
+/// Receives the latest quote of each exchange with levels for a symbol
+pub type QuotesReceiver = watch::Receiver<Arc<Vec<Quote>>>;
+
+type QuotesSender = watch::Sender<Arc<Vec<Quote>>>;
+
+/// The latest quotes of each symbol. Clones share the same quotes.
+#[derive(Debug, Clone, Default)]
+pub struct Quotes {
+    senders: Arc<Mutex<HashMap<Symbol, QuotesSender>>>,
+}
+
+impl Quotes {
+    /// Replaces the quotes of `symbol`
+    pub fn publish(&self, symbol: Symbol, quotes: Vec<Quote>) {
+        let mut senders = self.senders.lock().unwrap();
+        let _ = Self::sender(&mut senders, symbol).send_replace(Arc::new(quotes));
+    }
+
+    /// Drops the quotes of `symbol`, ending the streams of their receivers
+    pub fn remove(&self, symbol: Symbol) {
+        self.senders.lock().unwrap().remove(&symbol);
+    }
+
+    /// A receiver of the quotes of `symbol`, which are empty until they're first published
+    pub fn subscribe(&self, symbol: Symbol) -> QuotesReceiver {
+        let mut senders = self.senders.lock().unwrap();
+        Self::sender(&mut senders, symbol).subscribe()
+    }
+
+    fn sender(senders: &mut HashMap<Symbol, QuotesSender>, symbol: Symbol) -> &QuotesSender {
+        senders
+            .entry(symbol)
+            .or_insert_with(|| watch::channel(Arc::new(Vec::new())).0)
+    }
+}
+
+/// The pairs of exchanges diverged now, each with its last event
+#[derive(Debug)]
+pub struct Divergences {
+    threshold_bps: f64,
+    hysteresis_bps: f64,
+    diverged: HashMap<(Exchange, Exchange), Divergence>,
+}
+
+impl Divergences {
+    /// Pairs diverge once their mids are more than `threshold_bps` apart, and clear once
+    /// they're back within `threshold_bps` less `hysteresis_bps`.
+    pub fn new(threshold_bps: f64, hysteresis_bps: f64) -> Result<Self> {
+        ensure!(
+            threshold_bps.is_finite() && threshold_bps > 0.0,
+            "threshold_bps must be above 0"
+        );
+        ensure!(
+            hysteresis_bps.is_finite() && (0.0..threshold_bps).contains(&hysteresis_bps),
+            "hysteresis_bps must be 0 or more and less than threshold_bps"
+        );
+        Ok(Self {
+            threshold_bps,
+            hysteresis_bps,
+            diverged: HashMap::new(),
+        })
+    }
+
+    /// The events for the pairs that diverged or cleared with `quotes`. A diverged pair
+    /// is cleared when one of its exchanges stops quoting.
+    pub fn update(&mut self, quotes: &[Quote]) -> Vec<Divergence> {
+        let mut quotes = quotes.to_vec();
+        quotes.sort_by_key(|quote| quote.exchange.to_string());
+        let timestamp_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let mut events = Vec::new();
+        let mut quoted = Vec::new();
+        for (i, a) in quotes.iter().enumerate() {
+            for b in quotes[i + 1..].iter() {
+                let pair = (a.exchange, b.exchange);
+                quoted.push(pair);
+                let event = Divergence {
+                    exchange_a: a.exchange.to_string(),
+                    exchange_b: b.exchange.to_string(),
+                    diverged: true,
+                    difference_bps: (b.mid() - a.mid()) / ((a.mid() + b.mid()) / 2.0) * 10_000.0,
+                    crossed_quantity: crossed_quantity(a, b),
+                    timestamp_ms,
+                };
+                let apart = event.difference_bps.abs();
+                if !self.diverged.contains_key(&pair) {
+                    if apart > self.threshold_bps {
+                        events.push(event.clone());
+                        self.diverged.insert(pair, event);
+                    }
+                } else if apart <= self.threshold_bps - self.hysteresis_bps {
+                    self.diverged.remove(&pair);
+                    events.push(Divergence {
+                        diverged: false,
+                        ..event
+                    });
+                } else {
+                    self.diverged.insert(pair, event);
+                }
+            }
+        }
+        let gone = self
+            .diverged
+            .keys()
+            .filter(|pair| !quoted.contains(pair))
+            .copied()
+            .collect::<Vec<_>>();
+        for pair in gone {
+            let last = self.diverged.remove(&pair).unwrap();
+            events.push(Divergence {
+                diverged: false,
+                timestamp_ms,
+                ..last
+            });
+        }
+        events
+    }
+}
+
+/// The quantity that could be bought on one exchange and sold on the other at their best
+/// prices, 0 unless one's bid is above the other's ask
+fn crossed_quantity(a: &Quote, b: &Quote) -> f64 {
+    if a.bid > b.ask {
+        a.bid_quantity.min(b.ask_quantity)
+    } else if b.bid > a.ask {
+        b.bid_quantity.min(a.ask_quantity)
+    } else {
+        0.0
+    }
+}
+
+/// Sends the events of `divergences` each time the quotes on `rx_quotes` change. Returns
+/// once the quotes end or the client goes away.
+pub async fn watch_divergence(
+    mut rx_quotes: QuotesReceiver,
+    mut divergences: Divergences,
+    tx: mpsc::Sender<Result<Divergence, Status>>,
+) {
+    loop {
+        let quotes = rx_quotes.borrow_and_update().clone();
+        for event in divergences.update(&quotes) {
+            if tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+        select! {
+            changed = rx_quotes.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(exchange: Exchange, bid: f64, ask: f64) -> Quote {
+        Quote {
+            exchange,
+            bid,
+            bid_quantity: 2.0,
+            ask,
+            ask_quantity: 0.5,
+        }
+    }
+
+    /// The pair, whether it diverged and the rounded difference of each event
+    fn events_of(divergences: &mut Divergences, quotes: &[Quote]) -> Vec<(String, bool, f64)> {
+        divergences
+            .update(quotes)
+            .into_iter()
+            .map(|event| {
+                (
+                    format!("{}-{}", event.exchange_a, event.exchange_b),
+                    event.diverged,
+                    event.difference_bps.round(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_diverges_and_clears_a_pair_once_per_excursion() {
+        let mut divergences = Divergences::new(10.0, 2.0).unwrap();
+        let binance = quote(Exchange::BINANCE, 9999.0, 10001.0);
+        let bitstamp = |mid: f64| quote(Exchange::BITSTAMP, mid - 1.0, mid + 1.0);
+        // 5bps apart, then 15, 9 and 12 while within the hysteresis, and back to 7.
+        let mut seen = Vec::new();
+        for mid in [10005.0, 10015.0, 10009.0, 10012.0, 10007.0, 10005.0] {
+            seen.extend(events_of(&mut divergences, &[bitstamp(mid), binance]));
+        }
+        assert_eq!(
+            seen,
+            [
+                ("BINANCE-BITSTAMP".to_string(), true, 15.0),
+                ("BINANCE-BITSTAMP".to_string(), false, 7.0),
+            ]
+        );
+
+        // Bitstamp's mid below binance's gives a negative difference.
+        let events = events_of(&mut divergences, &[binance, bitstamp(9980.0)]);
+        assert_eq!(events, [("BINANCE-BITSTAMP".to_string(), true, -20.0)]);
+    }
+
+    #[test]
+    fn it_only_reports_the_pair_that_diverges() {
+        let mut divergences = Divergences::new(10.0, 0.0).unwrap();
+        // Bitstamp sits halfway between the two binance books, 6bps from each.
+        let quotes = |futures_mid: f64| {
+            [
+                quote(Exchange::BINANCE, 9999.0, 10001.0),
+                quote(Exchange::BITSTAMP, 10005.0, 10007.0),
+                quote(
+                    Exchange::BINANCE_FUTURES,
+                    futures_mid - 1.0,
+                    futures_mid + 1.0,
+                ),
+            ]
+        };
+        let events = divergences.update(&quotes(10012.0));
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].exchange_a.as_str(), events[0].exchange_b.as_str()),
+            ("BINANCE", "BINANCE_FUTURES")
+        );
+        assert!(events[0].diverged);
+        // The books cross, the futures bid being above the spot ask.
+        assert_eq!(events[0].crossed_quantity, 0.5);
+
+        assert!(divergences.update(&quotes(10011.0)).is_empty());
+        assert_eq!(
+            events_of(&mut divergences, &quotes(10008.0)),
+            [("BINANCE-BINANCE_FUTURES".to_string(), false, 8.0)]
+        );
+    }
+
+    #[test]
+    fn it_clears_pairs_when_an_exchange_stops_quoting() {
+        let mut divergences = Divergences::new(10.0, 0.0).unwrap();
+        let binance = quote(Exchange::BINANCE, 9999.0, 10001.0);
+        let bitstamp = quote(Exchange::BITSTAMP, 10049.0, 10051.0);
+        assert_eq!(divergences.update(&[binance, bitstamp]).len(), 1);
+        let events = divergences.update(&[binance]);
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].diverged);
+        assert!(divergences.update(&[binance]).is_empty());
+    }
+
+    #[test]
+    fn it_validates_the_thresholds() {
+        let error = |threshold, hysteresis| {
+            Divergences::new(threshold, hysteresis)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error(0.0, 0.0), "threshold_bps must be above 0");
+        assert_eq!(
+            error(10.0, 10.0),
+            "hysteresis_bps must be 0 or more and less than threshold_bps"
+        );
+        assert!(Divergences::new(10.0, 9.0).is_ok());
+    }
+
+    #[test]
+    fn it_shares_the_quotes_published() {
+        let quotes = Quotes::default();
+        let rx = quotes.subscribe(Symbol::BTCUSDT);
+        assert!(rx.borrow().is_empty());
+        quotes.publish(Symbol::BTCUSDT, vec![quote(Exchange::BINANCE, 1.0, 2.0)]);
+        assert_eq!(rx.borrow().len(), 1);
+        assert!(quotes.subscribe(Symbol::ETHBTC).borrow().is_empty());
+    }
+}
@@ -1,34 +1,41 @@
 use anyhow::{ensure, Context, Result};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use std::str::FromStr;
-use tokio_tungstenite::tungstenite::Message;
+use std::time::Duration;
 use url::Url;
 
 use crate::{
-    core::{num_types::DisplayAmount, order_book::Update},
-    Symbol,
+    book_summary::Trade,
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        http::HttpClient,
+        num_types::{deserialize_levels, DisplayAmount},
+        order_book::Update,
+        rate_limit::WeightLimits,
+        trades::{trade, IntoTrade, Side},
+    },
 };
 
-fn from_str<'de, D>(deserializer: D) -> Result<Vec<[Decimal; 2]>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let v: Vec<[&str; 2]> = Deserialize::deserialize(deserializer)?;
-    Ok(v.into_iter()
-        .map(|s| (s[0].parse::<Decimal>(), s[1].parse::<Decimal>()))
-        .filter_map(|p| Some([p.0.ok()?, p.1.ok()?]))
-        .collect::<Vec<[Decimal; 2]>>())
-}
+/// The REST limits for an IP, from the spot api docs
+const LIMITS: WeightLimits = WeightLimits {
+    used_header: "x-mbx-used-weight-1m",
+    limit: 1200,
+    ban_cool_down: Duration::from_secs(600),
+};
+
+/// Request weights from the spot api docs
+const BOOK_TICKER_WEIGHT: u32 = 2;
+const EXCHANGE_INFO_WEIGHT: u32 = 20;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Snapshot {
     pub last_update_id: u64,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub bids: Vec<[Decimal; 2]>,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub asks: Vec<[Decimal; 2]>,
 }
 
@@ -48,11 +55,40 @@ impl Update for Snapshot {
     }
 }
 
+/// Levels of each side in a snapshot unless another depth is asked for
+const DEFAULT_DEPTH: u32 = 1000;
+/// The deepest snapshot the api gives
+pub const MAX_DEPTH: u32 = 5000;
+
+/// The `limit` to ask for a snapshot `depth` with, clamped to what the api allows.
+pub(super) fn snapshot_limit(depth: Option<u32>) -> u32 {
+    match depth {
+        None => DEFAULT_DEPTH,
+        Some(depth) if depth > MAX_DEPTH => {
+            tracing::debug!("snapshot depth {} clamped to {}", depth, MAX_DEPTH);
+            MAX_DEPTH
+        }
+        Some(depth) => depth.max(1),
+    }
+}
+
+/// Deeper snapshots weigh more.
+fn depth_weight(limit: u32) -> u32 {
+    match limit {
+        0..=100 => 5,
+        101..=500 => 25,
+        501..=1000 => 50,
+        _ => 250,
+    }
+}
+
 impl Snapshot {
-    pub(crate) async fn fetch(url: Url) -> Result<Self> {
-        let snapshot = reqwest::get(url)
+    pub(crate) async fn fetch(http: &HttpClient, url: Url, limit: u32) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let snapshot = http
+            .get_weighted(url, depth_weight(limit), LIMITS)
             .await
-            .context("Failed to get snapshot")?
+            .with_context(|| format!("Failed to get snapshot from {}", host))?
             .json::<Self>()
             .await
             .context("Failed to deserialize snapshot")?;
@@ -67,9 +103,9 @@ pub struct BookUpdate {
     pub first_update_id: u64,
     #[serde(alias = "u")]
     pub last_update_id: u64,
-    #[serde(alias = "b", deserialize_with = "from_str")]
+    #[serde(alias = "b", deserialize_with = "deserialize_levels")]
     pub bids: Vec<[DisplayAmount; 2]>,
-    #[serde(alias = "a", deserialize_with = "from_str")]
+    #[serde(alias = "a", deserialize_with = "deserialize_levels")]
     pub asks: Vec<[DisplayAmount; 2]>,
 }
 
@@ -89,6 +125,22 @@ impl Update for BookUpdate {
         );
         Ok(())
     }
+    /// The first update after the snapshot has to hold the update right after it.
+    fn validate_first(&self, snapshot_id: u64) -> Result<()> {
+        let (first, last) = (self.first_update_id, self.last_update_id);
+        if snapshot_id == 0 {
+            return Ok(());
+        }
+        ensure!(
+            first <= snapshot_id + 1 && snapshot_id < last,
+            "failed to validate: {first}..={last} doesn't hold the update after the snapshot at \
+             {snapshot_id}"
+        );
+        Ok(())
+    }
+    fn precedes(&self, last_id: u64) -> bool {
+        self.last_update_id <= last_id
+    }
     fn last_update_id(&self) -> u64 {
         self.last_update_id
     }
@@ -101,10 +153,40 @@ impl Update for BookUpdate {
     }
 }
 
-impl TryFrom<Message> for BookUpdate {
-    type Error = anyhow::Error;
-    fn try_from(item: Message) -> Result<Self> {
-        serde_json::from_slice::<Self>(&item.into_data()).context("Failed to deserialize update")
+/// Response to a `SUBSCRIBE` request, either `{"result": null, "id": 1}` or an error like
+/// `{"code": 2, "msg": "Invalid request"}`.
+#[derive(Debug, Deserialize)]
+struct ControlMessage {
+    id: Option<u64>,
+    code: Option<i64>,
+    msg: Option<String>,
+}
+
+/// Parses an update of type `U`, falling back to the responses to a `SUBSCRIBE` request,
+/// which are the same on every Binance stream.
+pub(crate) fn from_message<U: DeserializeOwned>(data: &[u8]) -> Result<StreamMessage<U>> {
+    let update_err = match serde_json::from_slice::<U>(data) {
+        Ok(update) => return Ok(StreamMessage::Update(update)),
+        Err(err) => err,
+    };
+    match serde_json::from_slice::<ControlMessage>(data) {
+        Ok(ControlMessage {
+            code: Some(code),
+            msg,
+            ..
+        }) => Ok(StreamMessage::Error(format!(
+            "{}: {}",
+            code,
+            msg.unwrap_or_default()
+        ))),
+        Ok(ControlMessage { id: Some(_), .. }) => Ok(StreamMessage::Subscribed),
+        _ => Err(update_err).context("Failed to deserialize update"),
+    }
+}
+
+impl FromMessage for BookUpdate {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
     }
 }
 
@@ -119,9 +201,103 @@ impl From<Snapshot> for BookUpdate {
     }
 }
 
+/// Message from a partial book depth stream, holding the top levels of the whole book
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialDepth {
+    pub last_update_id: u64,
+    #[serde(deserialize_with = "deserialize_levels")]
+    pub bids: Vec<[DisplayAmount; 2]>,
+    #[serde(deserialize_with = "deserialize_levels")]
+    pub asks: Vec<[DisplayAmount; 2]>,
+}
+
+impl Update for PartialDepth {
+    // Each message stands on its own, so there's no sequence to check.
+    fn validate(&self, _: u64) -> Result<()> {
+        Ok(())
+    }
+    fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+    fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.bids
+    }
+    fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.asks
+    }
+    fn replaces_book(&self) -> bool {
+        true
+    }
+}
+
+impl FromMessage for PartialDepth {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl From<Snapshot> for PartialDepth {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            last_update_id: snapshot.last_update_id,
+            bids: snapshot.bids,
+            asks: snapshot.asks,
+        }
+    }
+}
+
+/// Message from a trade or aggregate trade stream, the aggregate ones summing the fills
+/// of a taker order at one price
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeEvent {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "t")]
+    pub trade_id: Option<u64>,
+    #[serde(rename = "a")]
+    pub aggregate_id: Option<u64>,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub quantity: Decimal,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    #[serde(rename = "m")]
+    pub buyer_is_maker: bool,
+}
+
+impl FromMessage for TradeEvent {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl IntoTrade for TradeEvent {
+    fn into_trade(self) -> Trade {
+        // The "a" of a raw trade is the seller's order id rather than a trade id.
+        let id = match self.event.as_str() {
+            "aggTrade" => self.aggregate_id,
+            _ => self.trade_id,
+        };
+        let side = if self.buyer_is_maker {
+            Side::Sell
+        } else {
+            Side::Buy
+        };
+        trade(
+            id.unwrap_or_default(),
+            self.price,
+            self.quantity,
+            Some(side),
+            self.trade_time,
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(super) struct BestPrice {
+pub(crate) struct BestPrice {
     pub symbol: String,
     pub bid_price: Decimal,
     pub bid_qty: Decimal,
@@ -130,10 +306,12 @@ pub(super) struct BestPrice {
 }
 
 impl BestPrice {
-    pub(super) async fn fetch(url: Url) -> Result<Self> {
-        let price = reqwest::get(url)
+    pub(super) async fn fetch(http: &HttpClient, url: Url) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let price = http
+            .get_weighted(url, BOOK_TICKER_WEIGHT, LIMITS)
             .await
-            .context("Failed to get price")?
+            .with_context(|| format!("Failed to get price from {}", host))?
             .json::<Self>()
             .await
             .context("Failed to deserialize binance best price")?;
@@ -161,22 +339,42 @@ impl ExchangeInfoBinance {
         Ok(symbol)
     }
 
-    pub async fn fetch(url: Url, symbol: &Symbol) -> Result<Self> {
+    pub async fn fetch(http: &HttpClient, url: Url, native: &str) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
         let mut endpoint = url.join("exchangeInfo").unwrap();
         endpoint
             .query_pairs_mut()
-            .append_pair("symbol", &symbol.to_string())
+            .append_pair("symbol", native)
             .finish();
 
-        let exchange_info = reqwest::get(endpoint)
+        let exchange_info = http
+            .get_weighted(endpoint, EXCHANGE_INFO_WEIGHT, LIMITS)
             .await
-            .context("Failed to get exchange info")?
+            .with_context(|| format!("Failed to get exchange info from {}", host))?
             .json::<Self>()
             .await
             .context("Failed to deserialize exchange info to json")?;
         Ok(exchange_info)
     }
 
+    /// Whether `native` is still listed. Binance answers symbols it doesn't list with a 400.
+    pub async fn fetch_listed(http: &HttpClient, url: Url, native: &str) -> Result<bool> {
+        match Self::fetch(http, url, native).await {
+            Ok(info) => Ok(info.symbols.iter().any(|s| s.symbol == native)),
+            Err(err)
+                if err.chain().any(|cause| {
+                    cause
+                        .downcast_ref::<reqwest::Error>()
+                        .and_then(|err| err.status())
+                        == Some(reqwest::StatusCode::BAD_REQUEST)
+                }) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     fn scale_price(&self) -> Result<u32> {
         let tick_sizes = self
             .symbol()?
@@ -212,10 +410,100 @@ impl ExchangeInfoBinance {
         Ok(scale_quantity)
     }
 
-    pub async fn fetch_scales(url: Url, symbol: &Symbol) -> Result<(u32, u32)> {
-        let exinfo = Self::fetch(url, symbol).await?;
+    pub async fn fetch_scales(http: &HttpClient, url: Url, native: &str) -> Result<(u32, u32)> {
+        let exinfo = Self::fetch(http, url, native).await?;
         let scale_price = exinfo.scale_price()?;
         let scale_quantity = exinfo.scale_quantity()?;
         Ok((scale_price, scale_quantity))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("../tests/fixtures/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn it_parses_control_messages() {
+        let message = BookUpdate::from_message(&fixture("control_binance_subscribed.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+
+        match BookUpdate::from_message(&fixture("control_binance_error.json")) {
+            Ok(StreamMessage::Error(message)) => assert!(message.starts_with("2: Invalid request")),
+            other => panic!("expected error message, got {:?}", other),
+        }
+
+        match BookUpdate::from_message(&fixture("update_binance.json")) {
+            Ok(StreamMessage::Update(update)) => {
+                assert_eq!(update.first_update_id, 157);
+                assert_eq!(update.last_update_id, 160);
+                assert_eq!(update.bids.len(), 1);
+                assert_eq!(update.asks.len(), 1);
+            }
+            other => panic!("expected update, got {:?}", other),
+        }
+
+        assert!(BookUpdate::from_message(b"{\"unexpected\": true}").is_err());
+    }
+
+    #[test]
+    fn it_validates_the_first_update_after_a_snapshot_by_overlap() {
+        let update = |first_update_id, last_update_id| BookUpdate {
+            first_update_id,
+            last_update_id,
+            ..Default::default()
+        };
+        // Updates buffered while the snapshot at 100 was fetched
+        assert!(update(95, 100).precedes(100));
+        assert!(!update(95, 101).precedes(100));
+        assert!(update(95, 105).validate_first(100).is_ok());
+        assert!(update(101, 101).validate_first(100).is_ok());
+        assert!(update(102, 105).validate_first(100).is_err());
+        // Only the first update can overlap, every one after has to follow on.
+        assert!(update(95, 105).validate(100).is_err());
+        assert!(update(101, 105).validate(100).is_ok());
+    }
+
+    #[test]
+    fn it_parses_partial_depth_messages() {
+        match PartialDepth::from_message(&fixture("partial_depth_binance.json")) {
+            Ok(StreamMessage::Update(depth)) => {
+                assert_eq!(depth.last_update_id, 160);
+                assert_eq!(depth.bids.len(), 5);
+                assert_eq!(depth.asks.len(), 5);
+                assert!(depth.replaces_book());
+            }
+            other => panic!("expected partial depth, got {:?}", other),
+        }
+        let message = PartialDepth::from_message(&fixture("control_binance_subscribed.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        assert!(PartialDepth::from_message(&fixture("update_binance.json")).is_err());
+    }
+
+    #[test]
+    fn it_parses_trade_messages() {
+        let trade = |name| match TradeEvent::from_message(&fixture(name)) {
+            Ok(StreamMessage::Update(event)) => event.into_trade(),
+            other => panic!("expected trade, got {:?}", other),
+        };
+        let raw = trade("trade_binance.json");
+        assert_eq!(raw.trade_id, 12345);
+        assert_eq!(raw.price, 30766.01);
+        assert_eq!(raw.quantity, 0.001);
+        // The buyer made the market, so the taker sold.
+        assert_eq!(raw.side, "sell");
+        assert_eq!(raw.exchange_timestamp_ms, 1688515101260);
+
+        let aggregate = trade("agg_trade_binance.json");
+        assert_eq!(aggregate.trade_id, 26129);
+        assert_eq!(aggregate.quantity, 0.25);
+        assert_eq!(aggregate.side, "buy");
+
+        let message = TradeEvent::from_message(&fixture("control_binance_subscribed.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        assert!(TradeEvent::from_message(&fixture("update_binance.json")).is_err());
+    }
+}
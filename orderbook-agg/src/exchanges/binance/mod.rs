@@ -1,25 +1,90 @@
 use std::sync::Arc;
 
 use crate::{
+    book_summary::Trade,
     core::{
-        exchange_book::ExchangeBook,
+        exchange_book::{process_updates, Endpoints, ExchangeBook},
+        http::HttpClient,
         num_types::DisplayAmount,
-        order_book::{OrderBook, OrderBookArgs},
+        order_book::{BookLevels, OrderBook, OrderBookArgs},
+        recorder::Recorder,
+        stats::ExchangeStats,
+        trades::process_trades,
     },
+    exchanges::registry::{BookArgs, ExchangeConnector, StreamArgs},
+    replay::{feed, replay_exchange, Frames, Replay},
+    service::ExchangeOptions,
     Exchange, Symbol,
 };
-use anyhow::{Context, Result};
+use anyhow::Result;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio::sync::{mpsc, RwLock};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::Instrument;
 
-use self::data::{BestPrice, BookUpdate, ExchangeInfoBinance, Snapshot};
+use futures::StreamExt;
+
+use self::data::{BestPrice, BookUpdate, ExchangeInfoBinance, PartialDepth, Snapshot, TradeEvent};
 
 pub mod data;
 
+pub use super::options::{DepthMode, DepthSpeed, TradeStream};
+
 pub struct BinanceOrderBook {
-    pub orderbook: Arc<Mutex<OrderBook>>,
+    pub orderbook: Arc<RwLock<OrderBook>>,
+    pub stats: Arc<ExchangeStats>,
+    http: HttpClient,
+    endpoints: Endpoints,
+    depth_speed: DepthSpeed,
+    partial_depth: bool,
+    snapshot_depth: Option<u32>,
+    recorder: Option<Recorder>,
+    /// The listing streamed, see [ExchangeBook::new_native]
+    native: String,
+}
+
+impl BinanceOrderBook {
+    pub fn with_depth_speed(mut self, depth_speed: DepthSpeed) -> Self {
+        self.depth_speed = depth_speed;
+        self
+    }
+
+    /// Allows streaming partial books when few enough levels are wanted, or the book is
+    /// kept to few enough with [OrderBook::set_max_depth], see [DepthMode::select].
+    pub fn with_partial_depth(mut self, partial_depth: bool) -> Self {
+        self.partial_depth = partial_depth;
+        self
+    }
+
+    /// Levels of each side to ask for in snapshots, 1000 when not set and at most 5000.
+    pub fn with_snapshot_depth(mut self, snapshot_depth: Option<u32>) -> Self {
+        self.snapshot_depth = snapshot_depth;
+        self
+    }
+
+    /// Records the frames received on the update stream
+    pub fn with_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Counts into `stats`, which outlive the book when shared with the next one
+    pub fn with_stats(mut self, stats: Arc<ExchangeStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    async fn connect_stream(
+        &self,
+        mode: DepthMode,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let endpoint = self
+            .depth_speed
+            .stream_name(&self.native.to_lowercase(), mode);
+        self.connect_wss(&endpoint).await
+    }
 }
 
 #[async_trait]
@@ -28,28 +93,67 @@ impl ExchangeBook<Snapshot, BookUpdate> for BinanceOrderBook {
     const BASE_URL_HTTPS: &'static str = "https://www.binance.us/api/v3/";
     const BASE_URL_WSS: &'static str = "wss://stream.binance.us:9443/ws/";
 
-    async fn new(symbol: Symbol, price_range: u8) -> Result<Self>
+    fn native_symbol(symbol: Symbol) -> String {
+        symbol.to_string()
+    }
+
+    async fn new_native(
+        http: HttpClient,
+        endpoints: Endpoints,
+        symbol: Symbol,
+        native: String,
+        price_range: u8,
+    ) -> Result<Self>
     where
         Self: Sized,
     {
         let exchange = Exchange::BINANCE;
-        let orderbook = Self::new_orderbook(exchange, symbol, price_range).await?;
+        let orderbook =
+            Self::new_orderbook(&http, &endpoints, exchange, symbol, &native, price_range).await?;
         let exchange_orderbook = Self {
-            orderbook: Arc::new(Mutex::new(orderbook)),
+            orderbook: Arc::new(RwLock::new(orderbook)),
+            stats: Arc::new(ExchangeStats::default()),
+            http,
+            endpoints,
+            depth_speed: DepthSpeed::default(),
+            partial_depth: false,
+            snapshot_depth: None,
+            recorder: None,
+            native,
         };
         Ok(exchange_orderbook)
     }
 
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>> {
+    fn orderbook(&self) -> Arc<RwLock<OrderBook>> {
         self.orderbook.clone()
     }
 
-    async fn fetch_orderbook_args(symbol: &Symbol, price_range: u8) -> Result<OrderBookArgs> {
-        let (best_price, _) = Self::fetch_prices(symbol).await?;
+    fn stats(&self) -> Arc<ExchangeStats> {
+        self.stats.clone()
+    }
+
+    fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
+    fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    async fn fetch_orderbook_args(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+        price_range: u8,
+    ) -> Result<OrderBookArgs> {
+        let (best_price, _) = Self::fetch_prices(http, endpoints, native).await?;
 
-        println!("base_url_https: {}", Self::base_url_https());
         let (scale_price, scale_quantity) =
-            ExchangeInfoBinance::fetch_scales(Self::base_url_https(), symbol).await?;
+            ExchangeInfoBinance::fetch_scales(http, endpoints.https.clone(), native).await?;
         let (storage_price_min, storage_price_max) =
             OrderBookArgs::get_min_max(best_price, price_range, scale_price)?;
 
@@ -65,38 +169,501 @@ impl ExchangeBook<Snapshot, BookUpdate> for BinanceOrderBook {
         Ok(args)
     }
 
-    async fn fetch_prices(symbol: &Symbol) -> Result<(DisplayAmount, DisplayAmount)> {
-        let mut url = Self::base_url_https().join("ticker/bookTicker").unwrap();
-        url.query_pairs_mut()
-            .append_pair("symbol", &symbol.to_string())
-            .finish();
-        let price = BestPrice::fetch(url).await?;
+    async fn fetch_listed(http: &HttpClient, endpoints: &Endpoints, native: &str) -> Result<bool> {
+        ExchangeInfoBinance::fetch_listed(http, endpoints.https.clone(), native).await
+    }
+
+    async fn fetch_prices(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+    ) -> Result<(DisplayAmount, DisplayAmount)> {
+        let mut url = endpoints.https.join("ticker/bookTicker").unwrap();
+        url.query_pairs_mut().append_pair("symbol", native).finish();
+        let price = BestPrice::fetch(http, url).await?;
         Ok((price.bid_price, price.ask_price))
     }
 
     async fn fetch_snapshot(&self) -> Result<Snapshot> {
-        let symbol = self.orderbook().lock().await.symbol;
-        let mut url = Self::base_url_https().join("depth").unwrap();
+        let mut url = self.endpoints().https.join("depth").unwrap();
+        let limit = data::snapshot_limit(self.snapshot_depth);
         url.query_pairs_mut()
-            .append_pair("symbol", &symbol.to_string())
-            .append_pair("limit", "1000")
+            .append_pair("symbol", &self.native)
+            .append_pair("limit", &limit.to_string())
             .finish();
-        Snapshot::fetch(url).await
+        Snapshot::fetch(self.http(), url, limit).await
     }
 
     async fn fetch_update_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
-        let symbol = self
-            .orderbook()
-            .lock()
+        self.connect_stream(DepthMode::Diff).await
+    }
+
+    async fn start(&self, levels: u32, tx_summary: mpsc::Sender<Arc<BookLevels>>) -> Result<()> {
+        let (symbol, max_depth) = {
+            let orderbook = self.orderbook();
+            let orderbook = orderbook.read().await;
+            (orderbook.symbol, orderbook.max_depth())
+        };
+        // A book kept shallower than the levels served fits in a smaller partial book. Both
+        // are read again on each reconnect, which subscribes to the stream they now fit.
+        let depth = max_depth.map_or(levels, |max_depth| max_depth.min(levels));
+        let mode = DepthMode::select(depth, self.partial_depth);
+        tracing::info!(
+            "BINANCE {} streaming {:?} for {} levels",
+            symbol,
+            mode,
+            levels
+        );
+        match mode {
+            DepthMode::Diff => {
+                self.stats().set_partial_depth(None);
+                let (sink, stream) = self.split_stream(self.fetch_update_stream().await?).await;
+                let snapshot = self
+                    .fetch_snapshot()
+                    .instrument(tracing::info_span!("fetch_snapshot"))
+                    .await?;
+                process_updates::<Snapshot, BookUpdate, _, _>(
+                    self.orderbook(),
+                    self.stats(),
+                    levels,
+                    snapshot,
+                    Some(Box::new(|| self.fetch_snapshot())),
+                    stream,
+                    sink,
+                    tx_summary,
+                )
+                .await
+            }
+            // Each message holds the whole top of the book, so there's no snapshot to
+            // start from.
+            DepthMode::Partial(depth) => {
+                self.stats().set_partial_depth(Some(depth));
+                let (sink, stream) = self.split_stream(self.connect_stream(mode).await?).await;
+                process_updates::<Snapshot, PartialDepth, _, _>(
+                    self.orderbook(),
+                    self.stats(),
+                    levels,
+                    Snapshot::default(),
+                    None,
+                    stream,
+                    sink,
+                    tx_summary,
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Sends the trades of `symbol` from `trade_stream` on `tx_trades` until the stream ends,
+/// leaving the order books alone.
+pub async fn stream_trades(
+    http: HttpClient,
+    endpoints: Endpoints,
+    symbol: Symbol,
+    trade_stream: TradeStream,
+    stats: Arc<ExchangeStats>,
+    tx_trades: mpsc::Sender<Trade>,
+) -> Result<()> {
+    let path = format!(
+        "{}@{}",
+        symbol.to_string().to_lowercase(),
+        trade_stream.name()
+    );
+    let (index, stream) = endpoints.connect_wss(&http, &path).await?;
+    stats.set_wss_endpoint(index);
+    let (sink, stream) = stream.split();
+    process_trades::<TradeEvent, _, _>(Exchange::BINANCE, symbol, stats, stream, sink, tx_trades)
+        .await
+}
+
+/// Binance's entry in the [registry](crate::exchanges::registry)
+pub struct BinanceConnector;
+
+impl ExchangeConnector for BinanceConnector {
+    fn default_endpoints(&self) -> Endpoints {
+        BinanceOrderBook::default_endpoints()
+    }
+
+    fn native_symbol(&self, symbol: Symbol) -> String {
+        BinanceOrderBook::native_symbol(symbol)
+    }
+
+    fn max_depth(&self, _options: &ExchangeOptions) -> Option<u32> {
+        Some(data::MAX_DEPTH)
+    }
+
+    fn fetch_listed<'a>(
+        &self,
+        http: &'a HttpClient,
+        endpoints: &'a Endpoints,
+        native: &'a str,
+    ) -> BoxFuture<'a, Result<bool>> {
+        BinanceOrderBook::fetch_listed(http, endpoints, native)
+    }
+
+    fn book(
+        &self,
+        args: BookArgs,
+        options: &ExchangeOptions,
+    ) -> BoxFuture<'static, Result<BoxFuture<'static, Result<()>>>> {
+        let depth_speed = options.binance_depth_speed;
+        let partial_depth = options.binance_partial_depth;
+        Box::pin(async move {
+            let native = args
+                .native
+                .unwrap_or_else(|| BinanceOrderBook::native_symbol(args.symbol));
+            let mut book = BinanceOrderBook::new_native(
+                args.http,
+                args.endpoints,
+                args.symbol,
+                native,
+                args.price_range,
+            )
+            .await?
+            .with_depth_speed(depth_speed)
+            .with_partial_depth(partial_depth)
+            .with_snapshot_depth(args.snapshot_depth)
+            .with_recorder(args.recorder);
+            if let Some(stats) = args.stats {
+                book = book.with_stats(stats);
+            }
+            book.orderbook().write().await.set_max_depth(args.depth);
+            let (levels, tx_levels) = (args.levels, args.tx_levels);
+            let started: BoxFuture<'static, Result<()>> =
+                Box::pin(async move { book.start(levels, tx_levels).await });
+            Ok(started)
+        })
+    }
+
+    fn stream_trades(
+        &self,
+        args: StreamArgs,
+        options: &ExchangeOptions,
+        tx_trades: mpsc::Sender<Trade>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(stream_trades(
+            args.http,
+            args.endpoints,
+            args.symbol,
+            options.binance_trade_stream,
+            args.stats,
+            tx_trades,
+        ))
+    }
+
+    fn replay(&self, replay: Replay, options: &ExchangeOptions) -> BoxFuture<'static, Result<()>> {
+        match DepthMode::select(replay.levels, options.binance_partial_depth) {
+            DepthMode::Diff => Box::pin(replay_exchange::<Snapshot, BookUpdate>(replay)),
+            DepthMode::Partial(_) => Box::pin(replay_exchange::<Snapshot, PartialDepth>(replay)),
+        }
+    }
+
+    fn feed_diffs(
+        &self,
+        orderbook: OrderBook,
+        stats: Arc<ExchangeStats>,
+        levels: u32,
+        frames: Frames,
+        tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(feed::<Snapshot, BookUpdate>(
+            orderbook, stats, levels, frames, tx_levels,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+    use crate::core::http::HttpConfig;
+
+    /// Answers the REST calls for BTCUSDT only, like a testnet that lists fewer symbols,
+    /// and returns the base url.
+    async fn mock_rest() -> String {
+        mock_rest_recording().await.0
+    }
+
+    /// [mock_rest] that also returns the paths requested from it.
+    async fn mock_rest_recording() -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let paths = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = paths.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let paths = paths.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0; 4096];
+                    while let Ok(n) = socket.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let request = String::from_utf8_lossy(&buf[..n]);
+                        let path = request.split(' ').nth(1).unwrap_or_default();
+                        paths.lock().unwrap().push(path.to_string());
+                        let (status, body) = if !path.contains("symbol=BTCUSDT") {
+                            (
+                                "400 Bad Request",
+                                r#"{"code":-1121,"msg":"Invalid symbol."}"#,
+                            )
+                        } else if path.starts_with("/api/v3/ticker/bookTicker") {
+                            (
+                                "200 OK",
+                                r#"{"symbol":"BTCUSDT","bidPrice":"30000.00000000","bidQty":"1.00000000","askPrice":"30001.00000000","askQty":"1.00000000"}"#,
+                            )
+                        } else if path.starts_with("/api/v3/exchangeInfo") {
+                            (
+                                "200 OK",
+                                r#"{"symbols":[{"symbol":"BTCUSDT","baseAssetPrecision":8,"quoteAssetPrecision":8,"filters":[{"filterType":"PRICE_FILTER","tickSize":"0.01000000"}]}]}"#,
+                            )
+                        } else if path.starts_with("/api/v3/depth") {
+                            (
+                                "200 OK",
+                                r#"{"lastUpdateId":100,"bids":[["30000.00000000","1.00000000"]],"asks":[["30001.00000000","2.00000000"]]}"#,
+                            )
+                        } else {
+                            ("404 Not Found", "{}")
+                        };
+                        let response = format!(
+                            "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (format!("http://{}/api/v3", addr), recorded)
+    }
+
+    /// Sends one depth update following the mock snapshot to each client and returns the
+    /// base url.
+    async fn mock_stream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    let update = serde_json::json!({
+                        "e": "depthUpdate",
+                        "E": 1688515101262u64,
+                        "s": "BTCUSDT",
+                        "U": 101,
+                        "u": 101,
+                        "b": [["30000.50000000", "3.00000000"]],
+                        "a": [],
+                    });
+                    ws.send(Message::Text(update.to_string())).await.unwrap();
+                    futures::future::pending::<()>().await;
+                });
+            }
+        });
+        format!("ws://{}/ws", addr)
+    }
+
+    /// Accepts one websocket connection, returning its url and the path it asked for.
+    // The handshake callback's error type is set by tungstenite.
+    #[allow(clippy::result_large_err)]
+    async fn capture_path() -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut path = String::new();
+            let _ws = tokio_tungstenite::accept_hdr_async(
+                socket,
+                |request: &tokio_tungstenite::tungstenite::handshake::server::Request, response| {
+                    path = request.uri().path().to_string();
+                    Ok(response)
+                },
+            )
             .await
-            .symbol
-            .to_string()
-            .to_lowercase();
-        let endpoint = format!("{}@depth@100ms", symbol);
-        let url = Self::base_url_wss().join(&endpoint).unwrap();
-        let (stream, _) = connect_async(url)
+            .unwrap();
+            path
+        });
+        (format!("ws://{}/ws", addr), server)
+    }
+
+    /// Accepts every websocket connection, closing each once it's made, and returns the url
+    /// and the paths they asked for.
+    #[allow(clippy::result_large_err)]
+    async fn capture_paths() -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx_paths, rx_paths) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let mut path = String::new();
+                let _ws = tokio_tungstenite::accept_hdr_async(
+                    socket,
+                    |request: &tokio_tungstenite::tungstenite::handshake::server::Request,
+                     response| {
+                        path = request.uri().path().to_string();
+                        Ok(response)
+                    },
+                )
+                .await;
+                if tx_paths.send(path).is_err() {
+                    break;
+                }
+            }
+        });
+        (format!("ws://{}/ws", addr), rx_paths)
+    }
+
+    #[tokio::test]
+    async fn it_subscribes_to_the_stream_for_each_depth_speed() {
+        let rest = mock_rest().await;
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        for (speed, path) in [
+            (DepthSpeed::Ms100, "/ws/btcusdt@depth@100ms"),
+            (DepthSpeed::Ms1000, "/ws/btcusdt@depth"),
+        ] {
+            let (wss, server) = capture_path().await;
+            let endpoints = Endpoints::new(&rest, &wss).unwrap();
+            let book = BinanceOrderBook::new(http.clone(), endpoints, Symbol::BTCUSDT, 10)
+                .await
+                .unwrap()
+                .with_depth_speed(speed);
+            let _stream = book.fetch_update_stream().await.unwrap();
+            assert_eq!(server.await.unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn it_selects_the_smallest_partial_book_holding_the_levels() {
+        assert_eq!(DepthMode::select(1, true), DepthMode::Partial(5));
+        assert_eq!(DepthMode::select(5, true), DepthMode::Partial(5));
+        assert_eq!(DepthMode::select(6, true), DepthMode::Partial(10));
+        assert_eq!(DepthMode::select(20, true), DepthMode::Partial(20));
+        assert_eq!(DepthMode::select(21, true), DepthMode::Diff);
+        assert_eq!(DepthMode::select(5, false), DepthMode::Diff);
+    }
+
+    #[tokio::test]
+    async fn it_subscribes_to_the_partial_stream_only_for_few_enough_levels() {
+        let rest = mock_rest().await;
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        for (levels, speed, path, partial_depth) in [
+            (15, DepthSpeed::Ms100, "/ws/btcusdt@depth20@100ms", Some(20)),
+            (15, DepthSpeed::Ms1000, "/ws/btcusdt@depth20", Some(20)),
+            (21, DepthSpeed::Ms100, "/ws/btcusdt@depth@100ms", None),
+        ] {
+            let (wss, server) = capture_path().await;
+            let endpoints = Endpoints::new(&rest, &wss).unwrap();
+            let book = BinanceOrderBook::new(http.clone(), endpoints, Symbol::BTCUSDT, 10)
+                .await
+                .unwrap()
+                .with_depth_speed(speed)
+                .with_partial_depth(true);
+            let stats = book.stats();
+            let (tx, _rx) = mpsc::channel(10);
+            tokio::spawn(async move { book.start(levels, tx).await });
+            assert_eq!(server.await.unwrap(), path);
+            assert_eq!(stats.partial_depth(), partial_depth);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_picks_the_stream_again_when_reconnecting_across_20_levels() {
+        let rest = mock_rest().await;
+        let (wss, mut rx_paths) = capture_paths().await;
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        let endpoints = Endpoints::new(&rest, &wss).unwrap();
+        let book = BinanceOrderBook::new(http, endpoints, Symbol::BTCUSDT, 10)
+            .await
+            .unwrap()
+            .with_partial_depth(true);
+        // The same book, kept to a different depth each time its stream is closed.
+        for (max_depth, path, partial_depth) in [
+            (Some(25), "/ws/btcusdt@depth@100ms", None),
+            (Some(10), "/ws/btcusdt@depth10@100ms", Some(10)),
+            (Some(20), "/ws/btcusdt@depth20@100ms", Some(20)),
+            (None, "/ws/btcusdt@depth@100ms", None),
+        ] {
+            book.orderbook().write().await.set_max_depth(max_depth);
+            let (tx, _rx) = mpsc::channel(10);
+            let _ = tokio::time::timeout(Duration::from_secs(5), book.start(30, tx)).await;
+            assert_eq!(rx_paths.recv().await.unwrap(), path);
+            assert_eq!(book.stats().partial_depth(), partial_depth);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_uses_the_configured_endpoints() {
+        let endpoints = Endpoints::new(&mock_rest().await, &mock_stream().await).unwrap();
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+
+        let book = BinanceOrderBook::new(http.clone(), endpoints.clone(), Symbol::BTCUSDT, 10)
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        tokio::spawn(async move { book.start(5, tx).await });
+        let snapshot_levels = rx.recv().await.unwrap();
+        assert_eq!(snapshot_levels.bids[0].price, 30000.0);
+        assert_eq!(snapshot_levels.asks[0].price, 30001.0);
+        let update_levels = rx.recv().await.unwrap();
+        assert_eq!(update_levels.last_update_id, 101);
+        assert_eq!(update_levels.bids[0].price, 30000.5);
+        assert_eq!(update_levels.bids[0].quantity, 3.0);
+
+        // A symbol the configured host doesn't list fails naming the host.
+        let err = BinanceOrderBook::new(http, endpoints.clone(), Symbol::ETHBTC, 10)
+            .await
+            .err()
+            .unwrap();
+        let host = endpoints.https.origin().ascii_serialization();
+        assert!(format!("{:#}", err).contains(&host), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn it_fails_over_to_the_next_websocket_url() {
+        // Nothing listens on the preferred url.
+        let endpoints = Endpoints::new(&mock_rest().await, "ws://127.0.0.1:1/ws")
+            .unwrap()
+            .with_wss_fallbacks(&[mock_stream().await])
+            .unwrap();
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+
+        let book = BinanceOrderBook::new(http, endpoints.clone(), Symbol::BTCUSDT, 10)
+            .await
+            .unwrap();
+        let stats = book.stats();
+        let (tx, mut rx) = mpsc::channel(10);
+        tokio::spawn(async move { book.start(5, tx).await });
+        rx.recv().await.unwrap();
+        let update_levels = rx.recv().await.unwrap();
+        assert_eq!(update_levels.last_update_id, 101);
+        assert_eq!(stats.wss_endpoint(), 1);
+        assert_eq!(endpoints.failovers(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_asks_for_the_configured_snapshot_depth() {
+        let (rest, paths) = mock_rest_recording().await;
+        let endpoints = Endpoints::new(&rest, "ws://127.0.0.1:1/ws").unwrap();
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        let mut book = BinanceOrderBook::new(http, endpoints, Symbol::BTCUSDT, 10)
             .await
-            .context("Failed to connect to wss endpoint")?;
-        Ok(stream)
+            .unwrap();
+        for (depth, limit) in [(None, 1000), (Some(10), 10), (Some(9000), 5000)] {
+            book = book.with_snapshot_depth(depth);
+            book.fetch_snapshot().await.unwrap();
+            let path = paths.lock().unwrap().last().cloned().unwrap();
+            assert!(path.ends_with(&format!("limit={}", limit)), "{}", path);
+        }
     }
 }
@@ -0,0 +1,350 @@
+use anyhow::{ensure, Context, Result};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+use crate::{
+    book_summary::DerivativeInfo,
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        http::HttpClient,
+        num_types::{deserialize_levels, DisplayAmount},
+        order_book::Update,
+        rate_limit::WeightLimits,
+    },
+    exchanges::binance::data::{from_message, BestPrice, Snapshot},
+};
+
+/// The REST limits for an IP, from the USDⓈ-M futures api docs
+const LIMITS: WeightLimits = WeightLimits {
+    used_header: "x-mbx-used-weight-1m",
+    limit: 2400,
+    ban_cool_down: Duration::from_secs(600),
+};
+
+/// Request weights from the USDⓈ-M futures api docs
+const BOOK_TICKER_WEIGHT: u32 = 2;
+const EXCHANGE_INFO_WEIGHT: u32 = 1;
+
+/// The only snapshot `limit`s the api takes, with their weights
+const DEPTH_LIMITS: [(u32, u32); 7] = [
+    (5, 2),
+    (10, 2),
+    (20, 2),
+    (50, 2),
+    (100, 5),
+    (500, 10),
+    (1000, 20),
+];
+
+/// The deepest snapshot the api gives
+pub const MAX_DEPTH: u32 = DEPTH_LIMITS[DEPTH_LIMITS.len() - 1].0;
+
+/// The smallest `limit` holding a snapshot `depth`, the deepest one by default.
+pub(super) fn snapshot_limit(depth: Option<u32>) -> u32 {
+    let deepest = MAX_DEPTH;
+    let Some(depth) = depth else {
+        return deepest;
+    };
+    match DEPTH_LIMITS.iter().find(|(limit, _)| *limit >= depth) {
+        Some((limit, _)) => *limit,
+        None => {
+            tracing::debug!("snapshot depth {} clamped to {}", depth, deepest);
+            deepest
+        }
+    }
+}
+
+pub(super) async fn fetch_snapshot(http: &HttpClient, url: Url, limit: u32) -> Result<Snapshot> {
+    let host = url.origin().ascii_serialization();
+    let weight = DEPTH_LIMITS
+        .iter()
+        .find(|(depth, _)| *depth == limit)
+        .map_or(DEPTH_LIMITS[DEPTH_LIMITS.len() - 1].1, |(_, weight)| {
+            *weight
+        });
+    let snapshot = http
+        .get_weighted(url, weight, LIMITS)
+        .await
+        .with_context(|| format!("Failed to get snapshot from {}", host))?
+        .json::<Snapshot>()
+        .await
+        .context("Failed to deserialize snapshot")?;
+    Ok(snapshot)
+}
+
+pub(super) async fn fetch_best_price(http: &HttpClient, url: Url) -> Result<BestPrice> {
+    let host = url.origin().ascii_serialization();
+    let price = http
+        .get_weighted(url, BOOK_TICKER_WEIGHT, LIMITS)
+        .await
+        .with_context(|| format!("Failed to get price from {}", host))?
+        .json::<BestPrice>()
+        .await
+        .context("Failed to deserialize binance futures best price")?;
+    Ok(price)
+}
+
+/// Diff depth update, the same as on spot plus the final update id of the previous
+/// update, `pu`, which each update follows on from.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BookUpdate {
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub last_update_id: u64,
+    #[serde(rename = "pu")]
+    pub previous_update_id: u64,
+    #[serde(rename = "b", deserialize_with = "deserialize_levels")]
+    pub bids: Vec<[DisplayAmount; 2]>,
+    #[serde(rename = "a", deserialize_with = "deserialize_levels")]
+    pub asks: Vec<[DisplayAmount; 2]>,
+}
+
+impl Update for BookUpdate {
+    /// Each update has to follow on from the update before by `pu`.
+    fn validate(&self, last_id: u64) -> Result<()> {
+        let previous = self.previous_update_id;
+        if last_id == 0 {
+            return Ok(());
+        }
+        ensure!(
+            previous == last_id,
+            "failed to validate: previous_update_id: {previous} != last_id: {last_id}"
+        );
+        Ok(())
+    }
+    /// The first update after the snapshot only has to span the snapshot's id.
+    fn validate_first(&self, snapshot_id: u64) -> Result<()> {
+        let (first, last) = (self.first_update_id, self.last_update_id);
+        if first <= snapshot_id && snapshot_id <= last {
+            return Ok(());
+        }
+        self.validate(snapshot_id)
+            .with_context(|| format!("{first}..={last} doesn't span the snapshot at {snapshot_id}"))
+    }
+    fn precedes(&self, last_id: u64) -> bool {
+        self.last_update_id < last_id
+    }
+    fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+    fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.bids
+    }
+    fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.asks
+    }
+}
+
+impl FromMessage for BookUpdate {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl From<Snapshot> for BookUpdate {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            first_update_id: 1,
+            last_update_id: snapshot.last_update_id,
+            previous_update_id: 0,
+            bids: snapshot.bids,
+            asks: snapshot.asks,
+        }
+    }
+}
+
+/// Update from the mark price stream, sent every second or three
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkPriceUpdate {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "p")]
+    pub mark_price: Decimal,
+    #[serde(rename = "r")]
+    pub funding_rate: Decimal,
+    /// Milliseconds since the unix epoch
+    #[serde(rename = "T")]
+    pub next_funding_time: u64,
+}
+
+impl FromMessage for MarkPriceUpdate {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl MarkPriceUpdate {
+    pub fn to_info(&self) -> DerivativeInfo {
+        DerivativeInfo {
+            mark_price: self.mark_price.to_f64().unwrap_or_default(),
+            funding_rate: self.funding_rate.to_f64().unwrap_or_default(),
+            next_funding_time_ms: self.next_funding_time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct SymbolData {
+    pub symbol: String,
+    pub contract_type: String,
+    pub quantity_precision: u32,
+    pub filters: Vec<Value>,
+}
+
+/// Futures list their own symbols, and the exchange info lists all of them at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ExchangeInfoFutures {
+    pub symbols: Vec<SymbolData>,
+}
+
+impl ExchangeInfoFutures {
+    pub async fn fetch(http: &HttpClient, url: Url) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let endpoint = url.join("exchangeInfo").unwrap();
+        let exchange_info = http
+            .get_weighted(endpoint, EXCHANGE_INFO_WEIGHT, LIMITS)
+            .await
+            .with_context(|| format!("Failed to get exchange info from {}", host))?
+            .json::<Self>()
+            .await
+            .context("Failed to deserialize exchange info to json")?;
+        Ok(exchange_info)
+    }
+
+    /// The perpetual contract listed as `native`, the only kind streamed.
+    fn perpetual(&self, native: &str) -> Option<&SymbolData> {
+        self.symbols
+            .iter()
+            .find(|s| s.symbol == native && s.contract_type == "PERPETUAL")
+    }
+
+    fn scales(&self, native: &str) -> Result<(u32, u32)> {
+        let data = self
+            .perpetual(native)
+            .with_context(|| format!("{} has no perpetual contract", native))?;
+        let tick_size = data
+            .filters
+            .iter()
+            .find(|filter| filter["filterType"].as_str() == Some("PRICE_FILTER"))
+            .and_then(|filter| filter["tickSize"].as_str())
+            .context("Failed to get tick size")?;
+        let scale_price = Decimal::from_str(tick_size)
+            .context("Failed to parse tick size")?
+            .normalize()
+            .scale();
+        Ok((scale_price, data.quantity_precision.min(8)))
+    }
+
+    /// Whether `native` still has a perpetual contract listed
+    pub async fn fetch_listed(http: &HttpClient, url: Url, native: &str) -> Result<bool> {
+        Ok(Self::fetch(http, url).await?.perpetual(native).is_some())
+    }
+
+    pub async fn fetch_scales(http: &HttpClient, url: Url, native: &str) -> Result<(u32, u32)> {
+        let host = url.origin().ascii_serialization();
+        Self::fetch(http, url)
+            .await?
+            .scales(native)
+            .with_context(|| format!("{} is not listed on {}", native, host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("../tests/fixtures/{}", name)).unwrap()
+    }
+
+    fn update(first: u64, last: u64, previous: u64) -> BookUpdate {
+        BookUpdate {
+            first_update_id: first,
+            last_update_id: last,
+            previous_update_id: previous,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_parses_futures_depth_updates() {
+        match BookUpdate::from_message(&fixture("update_binance_futures.json")) {
+            Ok(StreamMessage::Update(update)) => {
+                assert_eq!(update.first_update_id, 3002526128010);
+                assert_eq!(update.last_update_id, 3002526131649);
+                assert_eq!(update.previous_update_id, 3002526127975);
+                assert_eq!(
+                    update.bids[0],
+                    [Decimal::new(3076510, 2), Decimal::new(1234, 3)]
+                );
+                assert_eq!(update.bids.len(), 2);
+                assert_eq!(update.asks.len(), 1);
+            }
+            other => panic!("expected update, got {:?}", other),
+        }
+        let message = BookUpdate::from_message(&fixture("control_binance_subscribed.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        // Spot updates have no pu.
+        assert!(BookUpdate::from_message(&fixture("update_binance.json")).is_err());
+    }
+
+    #[test]
+    fn it_validates_updates_by_the_previous_update_id() {
+        // The first update spans the snapshot at 100, the rest follow on by pu.
+        assert!(update(95, 105, 90).validate_first(100).is_ok());
+        assert!(update(100, 100, 99).validate_first(100).is_ok());
+        assert!(update(106, 110, 105).validate(105).is_ok());
+        // Update ids on futures aren't contiguous, only pu has to match.
+        assert!(update(120, 130, 105).validate(105).is_ok());
+
+        let err = update(111, 115, 108).validate(110).unwrap_err();
+        assert!(
+            err.to_string().contains("previous_update_id: 108"),
+            "{}",
+            err
+        );
+        assert!(update(90, 99, 85).validate_first(100).is_err());
+        // Only the first update after the snapshot can get by with spanning it.
+        assert!(update(100, 110, 95).validate(105).is_err());
+        let err = update(106, 110, 104).validate_first(100).unwrap_err();
+        assert!(format!("{:#}", err).contains("doesn't span the snapshot at 100"));
+
+        // Updates buffered from before the snapshot are skipped.
+        assert!(update(90, 99, 85).precedes(100));
+        assert!(!update(95, 100, 90).precedes(100));
+    }
+
+    #[test]
+    fn it_parses_mark_price_updates() {
+        match MarkPriceUpdate::from_message(&fixture("mark_price_binance_futures.json")) {
+            Ok(StreamMessage::Update(update)) => assert_eq!(
+                update.to_info(),
+                DerivativeInfo {
+                    mark_price: 30768.41234567,
+                    funding_rate: 0.0001,
+                    next_funding_time_ms: 1688515200000,
+                }
+            ),
+            other => panic!("expected update, got {:?}", other),
+        }
+        let message = MarkPriceUpdate::from_message(&fixture("control_binance_subscribed.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        assert!(MarkPriceUpdate::from_message(&fixture("update_binance_futures.json")).is_err());
+    }
+
+    #[test]
+    fn it_reads_scales_for_perpetuals_only() {
+        let info: ExchangeInfoFutures =
+            serde_json::from_slice(&fixture("exchange_info_binance_futures.json")).unwrap();
+        assert_eq!(info.scales("BTCUSDT").unwrap(), (1, 3));
+        assert!(info.scales("ETHBTC").is_err());
+        assert!(info.symbols.iter().any(|s| s.symbol == "1000PEPEUSDT"));
+    }
+}
@@ -0,0 +1,501 @@
+use std::sync::Arc;
+
+use crate::{
+    book_summary::{DerivativeInfo, Trade},
+    core::{
+        exchange_book::{forward_messages, Endpoints, ExchangeBook},
+        http::HttpClient,
+        num_types::DisplayAmount,
+        order_book::{BookLevels, OrderBook, OrderBookArgs},
+        recorder::Recorder,
+        stats::ExchangeStats,
+        trades::process_trades,
+    },
+    exchanges::{
+        binance::data::{Snapshot, TradeEvent},
+        registry::{BookArgs, ExchangeConnector, StreamArgs},
+    },
+    replay::{feed, replay_exchange, Frames, Replay},
+    service::ExchangeOptions,
+    Exchange, Symbol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{future::BoxFuture, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, RwLock};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use self::data::{BookUpdate, ExchangeInfoFutures, MarkPriceUpdate};
+
+pub mod data;
+
+/// Binance USDⓈ-M perpetual futures. The api mirrors spot apart from the symbols listed
+/// and the `pu` field that depth updates are checked by, see [BookUpdate].
+pub struct BinanceFuturesOrderBook {
+    pub orderbook: Arc<RwLock<OrderBook>>,
+    pub stats: Arc<ExchangeStats>,
+    http: HttpClient,
+    endpoints: Endpoints,
+    snapshot_depth: Option<u32>,
+    recorder: Option<Recorder>,
+    /// The listing streamed, see [ExchangeBook::new_native]
+    native: String,
+}
+
+impl BinanceFuturesOrderBook {
+    /// Levels of each side to ask for in snapshots, rounded up to a depth the api takes
+    /// and at most 1000, the default.
+    pub fn with_snapshot_depth(mut self, snapshot_depth: Option<u32>) -> Self {
+        self.snapshot_depth = snapshot_depth;
+        self
+    }
+
+    /// Records the frames received on the update stream
+    pub fn with_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Counts into `stats`, which outlive the book when shared with the next one
+    pub fn with_stats(mut self, stats: Arc<ExchangeStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+}
+
+#[async_trait]
+impl ExchangeBook<Snapshot, BookUpdate> for BinanceFuturesOrderBook {
+    // make sure these have trailing slashes
+    const BASE_URL_HTTPS: &'static str = "https://fapi.binance.com/fapi/v1/";
+    const BASE_URL_WSS: &'static str = "wss://fstream.binance.com/ws/";
+
+    fn native_symbol(symbol: Symbol) -> String {
+        symbol.to_string()
+    }
+
+    async fn new_native(
+        http: HttpClient,
+        endpoints: Endpoints,
+        symbol: Symbol,
+        native: String,
+        price_range: u8,
+    ) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let exchange = Exchange::BINANCE_FUTURES;
+        let orderbook =
+            Self::new_orderbook(&http, &endpoints, exchange, symbol, &native, price_range).await?;
+        let exchange_orderbook = Self {
+            orderbook: Arc::new(RwLock::new(orderbook)),
+            stats: Arc::new(ExchangeStats::default()),
+            http,
+            endpoints,
+            snapshot_depth: None,
+            recorder: None,
+            native,
+        };
+        Ok(exchange_orderbook)
+    }
+
+    fn orderbook(&self) -> Arc<RwLock<OrderBook>> {
+        self.orderbook.clone()
+    }
+
+    fn stats(&self) -> Arc<ExchangeStats> {
+        self.stats.clone()
+    }
+
+    fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
+    fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    async fn fetch_orderbook_args(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+        price_range: u8,
+    ) -> Result<OrderBookArgs> {
+        // The exchange info comes first so symbols without a perpetual fail naming it.
+        let (scale_price, scale_quantity) =
+            ExchangeInfoFutures::fetch_scales(http, endpoints.https.clone(), native).await?;
+        let (best_price, _) = Self::fetch_prices(http, endpoints, native).await?;
+        let (storage_price_min, storage_price_max) =
+            OrderBookArgs::get_min_max(best_price, price_range, scale_price)?;
+
+        let args = OrderBookArgs {
+            storage_price_min,
+            storage_price_max,
+            scale_price,
+            scale_quantity,
+        };
+
+        tracing::debug!("orderbook args: {:#?}", args);
+
+        Ok(args)
+    }
+
+    async fn fetch_listed(http: &HttpClient, endpoints: &Endpoints, native: &str) -> Result<bool> {
+        ExchangeInfoFutures::fetch_listed(http, endpoints.https.clone(), native).await
+    }
+
+    async fn fetch_prices(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+    ) -> Result<(DisplayAmount, DisplayAmount)> {
+        let mut url = endpoints.https.join("ticker/bookTicker").unwrap();
+        url.query_pairs_mut().append_pair("symbol", native).finish();
+        let price = data::fetch_best_price(http, url).await?;
+        Ok((price.bid_price, price.ask_price))
+    }
+
+    async fn fetch_snapshot(&self) -> Result<Snapshot> {
+        let mut url = self.endpoints().https.join("depth").unwrap();
+        let limit = data::snapshot_limit(self.snapshot_depth);
+        url.query_pairs_mut()
+            .append_pair("symbol", &self.native)
+            .append_pair("limit", &limit.to_string())
+            .finish();
+        data::fetch_snapshot(self.http(), url, limit).await
+    }
+
+    async fn fetch_update_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let endpoint = format!("{}@depth@100ms", self.native.to_lowercase());
+        self.connect_wss(&endpoint).await
+    }
+}
+
+/// Sends the trades of `symbol` from the aggregate trade stream on `tx_trades` until the
+/// stream ends, leaving the order books alone. The messages are the same as spot's.
+pub async fn stream_trades(
+    http: HttpClient,
+    endpoints: Endpoints,
+    symbol: Symbol,
+    stats: Arc<ExchangeStats>,
+    tx_trades: mpsc::Sender<Trade>,
+) -> Result<()> {
+    let path = format!("{}@aggTrade", symbol.to_string().to_lowercase());
+    let (index, stream) = endpoints.connect_wss(&http, &path).await?;
+    stats.set_wss_endpoint(index);
+    let (sink, stream) = stream.split();
+    process_trades::<TradeEvent, _, _>(
+        Exchange::BINANCE_FUTURES,
+        symbol,
+        stats,
+        stream,
+        sink,
+        tx_trades,
+    )
+    .await
+}
+
+/// Sends the mark price and funding of `symbol` from the mark price stream on `tx_info`
+/// each second until the stream ends, leaving the order books alone.
+pub async fn stream_mark_prices(
+    http: HttpClient,
+    endpoints: Endpoints,
+    symbol: Symbol,
+    stats: Arc<ExchangeStats>,
+    tx_info: mpsc::Sender<DerivativeInfo>,
+) -> Result<()> {
+    let path = format!("{}@markPrice@1s", symbol.to_string().to_lowercase());
+    let (index, stream) = endpoints.connect_wss(&http, &path).await?;
+    stats.set_wss_endpoint(index);
+    stats.counters.connections.incr();
+    let (sink, stream) = stream.split();
+    let (tx_message, mut rx_message) = mpsc::channel::<MarkPriceUpdate>(10);
+    let send_info = async move {
+        while let Some(update) = rx_message.recv().await {
+            if tx_info.send(update.to_info()).await.is_err() {
+                break;
+            }
+        }
+    };
+    let (result, ()) = tokio::join!(
+        forward_messages(Exchange::BINANCE_FUTURES, stats, stream, sink, tx_message),
+        send_info
+    );
+    result
+}
+
+/// Binance futures' entry in the [registry](crate::exchanges::registry)
+pub struct BinanceFuturesConnector;
+
+impl ExchangeConnector for BinanceFuturesConnector {
+    fn default_endpoints(&self) -> Endpoints {
+        BinanceFuturesOrderBook::default_endpoints()
+    }
+
+    fn native_symbol(&self, symbol: Symbol) -> String {
+        BinanceFuturesOrderBook::native_symbol(symbol)
+    }
+
+    fn max_depth(&self, _options: &ExchangeOptions) -> Option<u32> {
+        Some(data::MAX_DEPTH)
+    }
+
+    fn fetch_listed<'a>(
+        &self,
+        http: &'a HttpClient,
+        endpoints: &'a Endpoints,
+        native: &'a str,
+    ) -> BoxFuture<'a, Result<bool>> {
+        BinanceFuturesOrderBook::fetch_listed(http, endpoints, native)
+    }
+
+    fn book(
+        &self,
+        args: BookArgs,
+        _options: &ExchangeOptions,
+    ) -> BoxFuture<'static, Result<BoxFuture<'static, Result<()>>>> {
+        Box::pin(async move {
+            let native = args
+                .native
+                .unwrap_or_else(|| BinanceFuturesOrderBook::native_symbol(args.symbol));
+            let mut book = BinanceFuturesOrderBook::new_native(
+                args.http,
+                args.endpoints,
+                args.symbol,
+                native,
+                args.price_range,
+            )
+            .await?
+            .with_snapshot_depth(args.snapshot_depth)
+            .with_recorder(args.recorder);
+            if let Some(stats) = args.stats {
+                book = book.with_stats(stats);
+            }
+            book.orderbook().write().await.set_max_depth(args.depth);
+            let (levels, tx_levels) = (args.levels, args.tx_levels);
+            let started: BoxFuture<'static, Result<()>> =
+                Box::pin(async move { book.start(levels, tx_levels).await });
+            Ok(started)
+        })
+    }
+
+    fn stream_trades(
+        &self,
+        args: StreamArgs,
+        _options: &ExchangeOptions,
+        tx_trades: mpsc::Sender<Trade>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(stream_trades(
+            args.http,
+            args.endpoints,
+            args.symbol,
+            args.stats,
+            tx_trades,
+        ))
+    }
+
+    fn stream_mark_prices(
+        &self,
+        args: StreamArgs,
+        tx_info: mpsc::Sender<DerivativeInfo>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(stream_mark_prices(
+            args.http,
+            args.endpoints,
+            args.symbol,
+            args.stats,
+            tx_info,
+        ))
+    }
+
+    fn replay(&self, replay: Replay, _options: &ExchangeOptions) -> BoxFuture<'static, Result<()>> {
+        Box::pin(replay_exchange::<Snapshot, BookUpdate>(replay))
+    }
+
+    fn feed_diffs(
+        &self,
+        orderbook: OrderBook,
+        stats: Arc<ExchangeStats>,
+        levels: u32,
+        frames: Frames,
+        tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(feed::<Snapshot, BookUpdate>(
+            orderbook, stats, levels, frames, tx_levels,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+    use crate::core::http::HttpConfig;
+
+    /// Answers the futures REST calls with BTCUSDT as the only perpetual and returns the
+    /// base url and the paths requested from it. Snapshots after the first are at 118.
+    async fn mock_rest() -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let paths = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = paths.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let paths = paths.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0; 4096];
+                    while let Ok(n) = socket.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let request = String::from_utf8_lossy(&buf[..n]);
+                        let path = request.split(' ').nth(1).unwrap_or_default();
+                        paths.lock().unwrap().push(path.to_string());
+                        let exchange_info = std::fs::read_to_string(
+                            "../tests/fixtures/exchange_info_binance_futures.json",
+                        )
+                        .unwrap();
+                        let snapshots = paths
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|path| path.starts_with("/fapi/v1/depth"))
+                            .count();
+                        let body = if path.starts_with("/fapi/v1/exchangeInfo") {
+                            exchange_info
+                        } else if path.starts_with("/fapi/v1/ticker/bookTicker") {
+                            r#"{"symbol":"BTCUSDT","bidPrice":"30000.00","bidQty":"1.000","askPrice":"30000.10","askQty":"1.000","time":1688515101262}"#.to_string()
+                        } else if path.starts_with("/fapi/v1/depth") && snapshots > 1 {
+                            r#"{"lastUpdateId":118,"E":1688515101262,"T":1688515101259,"bids":[["30000.00","1.500"]],"asks":[["30000.10","2.000"]]}"#.to_string()
+                        } else if path.starts_with("/fapi/v1/depth") {
+                            r#"{"lastUpdateId":100,"E":1688515101262,"T":1688515101259,"bids":[["30000.00","1.000"]],"asks":[["30000.10","2.000"]]}"#.to_string()
+                        } else {
+                            "{}".to_string()
+                        };
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (format!("http://{}/fapi/v1", addr), recorded)
+    }
+
+    /// Sends an update spanning the mock snapshot, one after a gap that drops 106..=109 and
+    /// one following on from that, then returns the base url.
+    async fn mock_stream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    for (first, last, previous, price) in [
+                        (95, 105, 90, "29999.90"),
+                        (110, 120, 115, "29999.80"),
+                        (121, 125, 120, "29999.70"),
+                    ] {
+                        let update = serde_json::json!({
+                            "e": "depthUpdate",
+                            "E": 1688515101262u64,
+                            "T": 1688515101259u64,
+                            "s": "BTCUSDT",
+                            "U": first,
+                            "u": last,
+                            "pu": previous,
+                            "b": [[price, "3.000"]],
+                            "a": [],
+                        });
+                        ws.send(Message::Text(update.to_string())).await.unwrap();
+                    }
+                    futures::future::pending::<()>().await;
+                });
+            }
+        });
+        format!("ws://{}/ws", addr)
+    }
+
+    #[tokio::test]
+    async fn it_resyncs_the_book_after_a_dropped_update() {
+        let (rest, paths) = mock_rest().await;
+        let endpoints = Endpoints::new(&rest, &mock_stream().await).unwrap();
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+
+        let book =
+            BinanceFuturesOrderBook::new(http.clone(), endpoints.clone(), Symbol::BTCUSDT, 10)
+                .await
+                .unwrap();
+        let stats = book.stats();
+        let (tx, mut rx) = mpsc::channel(10);
+        tokio::spawn(async move { book.start(5, tx).await });
+
+        let snapshot_levels = rx.recv().await.unwrap();
+        assert_eq!(snapshot_levels.exchange, Exchange::BINANCE_FUTURES);
+        assert_eq!(snapshot_levels.bids[0].price, 30000.0);
+        let spanning = rx.recv().await.unwrap();
+        assert_eq!(spanning.last_update_id, 105);
+        assert_eq!(spanning.bids[1].price, 29999.9);
+        // The update after the gap doesn't follow on by pu, so the book is rebuilt from a
+        // fresh snapshot, which the update then spans.
+        let resynced = rx.recv().await.unwrap();
+        assert_eq!(resynced.last_update_id, 118);
+        assert_eq!(resynced.bids.len(), 1);
+        assert_eq!(resynced.bids[0].quantity, 1.5);
+        let spanning = rx.recv().await.unwrap();
+        assert_eq!(spanning.last_update_id, 120);
+        assert_eq!(spanning.bids[1].price, 29999.8);
+        let following = rx.recv().await.unwrap();
+        assert_eq!(following.last_update_id, 125);
+        assert_eq!(following.bids[2].price, 29999.7);
+        assert_eq!((stats.resyncs(), stats.update_errors()), (1, 0));
+        let snapshots = paths
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|path| path.starts_with("/fapi/v1/depth"))
+            .count();
+        assert_eq!(snapshots, 2);
+
+        let err = BinanceFuturesOrderBook::new(http, endpoints, Symbol::ETHBTC, 10)
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).contains("ETHBTC has no perpetual contract"));
+    }
+
+    #[tokio::test]
+    async fn it_rounds_the_snapshot_depth_up_to_a_limit_the_api_takes() {
+        let (rest, paths) = mock_rest().await;
+        let endpoints = Endpoints::new(&rest, "ws://127.0.0.1:1/ws").unwrap();
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        let mut book = BinanceFuturesOrderBook::new(http, endpoints, Symbol::BTCUSDT, 10)
+            .await
+            .unwrap();
+        for (depth, limit) in [
+            (None, 1000),
+            (Some(15), 20),
+            (Some(100), 100),
+            (Some(2000), 1000),
+        ] {
+            book = book.with_snapshot_depth(depth);
+            book.fetch_snapshot().await.unwrap();
+            let path = paths.lock().unwrap().last().cloned().unwrap();
+            assert!(path.ends_with(&format!("limit={}", limit)), "{}", path);
+        }
+    }
+}
@@ -1,36 +1,27 @@
 use anyhow::{bail, Context, Result};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Deserializer, Serialize};
-use serde_aux::field_attributes::deserialize_number_from_string;
-use tokio_tungstenite::tungstenite::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use url::Url;
 
 use crate::{
-    core::{num_types::DisplayAmount, order_book::Update},
-    Symbol,
+    book_summary::Trade,
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        http::HttpClient,
+        num_types::{deserialize_levels, deserialize_orders, deserialize_u64, DisplayAmount},
+        order_book::Update,
+        trades::{trade, IntoTrade, Side},
+    },
 };
 
-fn from_str<'de, D>(deserializer: D) -> Result<Vec<[Decimal; 2]>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let v: Vec<[&str; 2]> = Deserialize::deserialize(deserializer)?;
-    Ok(v.into_iter()
-        .map(|s| (s[0].parse::<Decimal>(), s[1].parse::<Decimal>()))
-        .filter_map(|p| Some([p.0.ok()?, p.1.ok()?]))
-        .collect::<Vec<[Decimal; 2]>>())
-}
-
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Snapshot {
-    #[serde(
-        alias = "microtimestamp",
-        deserialize_with = "deserialize_number_from_string"
-    )]
+    #[serde(alias = "microtimestamp", deserialize_with = "deserialize_u64")]
     pub last_update_id: u64,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub bids: Vec<[Decimal; 2]>,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub asks: Vec<[Decimal; 2]>,
 }
 
@@ -51,10 +42,26 @@ impl Update for Snapshot {
 }
 
 impl Snapshot {
-    pub(crate) async fn fetch(url: Url) -> Result<Self> {
-        let snapshot = reqwest::get(url)
+    /// Keeps the best `depth` levels of each side.
+    pub fn truncate(&mut self, depth: usize) {
+        if self.bids.len() > depth || self.asks.len() > depth {
+            tracing::debug!(
+                "keeping {} of the {} bids and {} asks in the snapshot",
+                depth,
+                self.bids.len(),
+                self.asks.len()
+            );
+        }
+        self.bids.truncate(depth);
+        self.asks.truncate(depth);
+    }
+
+    pub(crate) async fn fetch(http: &HttpClient, url: Url) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let snapshot = http
+            .get(url)
             .await
-            .context("Failed to get snapshot")?
+            .with_context(|| format!("Failed to get snapshot from {}", host))?
             .json::<Self>()
             .await
             .context("Failed to deserialize snapshot")?;
@@ -64,14 +71,11 @@ impl Snapshot {
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct BookUpdateData {
-    #[serde(
-        alias = "microtimestamp",
-        deserialize_with = "deserialize_number_from_string"
-    )]
+    #[serde(alias = "microtimestamp", deserialize_with = "deserialize_u64")]
     pub last_update_id: u64,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub bids: Vec<[DisplayAmount; 2]>,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(deserialize_with = "deserialize_levels")]
     pub asks: Vec<[DisplayAmount; 2]>,
 }
 
@@ -96,16 +100,40 @@ impl Update for BookUpdate {
     }
 }
 
-impl TryFrom<Message> for BookUpdate {
-    type Error = anyhow::Error;
-    fn try_from(item: Message) -> Result<Self> {
-        match serde_json::from_slice::<Self>(&item.into_data()) {
-            Ok(update) => Ok(update),
-            Err(e) => {
-                tracing::error!("Failed to deserialize update: {}", e);
-                bail!("failed to deserialize update")
-            }
-        }
+/// Any websocket message, used to pick out the control events like
+/// `bts:subscription_succeeded` that don't carry order book data.
+#[derive(Debug, Deserialize)]
+struct ControlMessage {
+    event: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// Parses an update of type `U`, falling back to the control events shared by every channel.
+fn from_message<U: DeserializeOwned>(data: &[u8]) -> Result<StreamMessage<U>> {
+    let update_err = match serde_json::from_slice::<U>(data) {
+        Ok(update) => return Ok(StreamMessage::Update(update)),
+        Err(err) => err,
+    };
+    let control = serde_json::from_slice::<ControlMessage>(data)
+        .map_err(|_| update_err)
+        .context("Failed to deserialize update")?;
+    match control.event.as_str() {
+        "bts:subscription_succeeded" => Ok(StreamMessage::Subscribed),
+        "bts:request_reconnect" => Ok(StreamMessage::Reconnect),
+        "bts:error" => Ok(StreamMessage::Error(
+            control.data["message"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_string(),
+        )),
+        event => bail!("Unexpected event: {}", event),
+    }
+}
+
+impl FromMessage for BookUpdate {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
     }
 }
 
@@ -121,6 +149,101 @@ impl From<Snapshot> for BookUpdate {
     }
 }
 
+/// Top 100 levels from the `order_book` channel, replacing the book with each message
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct FullBook {
+    data: BookUpdateData,
+}
+
+impl Update for FullBook {
+    fn validate(&self, _: u64) -> Result<()> {
+        Ok(())
+    }
+    fn last_update_id(&self) -> u64 {
+        self.data.last_update_id
+    }
+    fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.data.bids
+    }
+    fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.data.asks
+    }
+    fn replaces_book(&self) -> bool {
+        true
+    }
+}
+
+impl FromMessage for FullBook {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl From<Snapshot> for FullBook {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            data: BookUpdateData {
+                last_update_id: snapshot.last_update_id,
+                bids: snapshot.bids,
+                asks: snapshot.asks,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DetailBookData {
+    #[serde(alias = "microtimestamp", deserialize_with = "deserialize_u64")]
+    pub last_update_id: u64,
+    #[serde(deserialize_with = "deserialize_orders")]
+    pub bids: Vec<[DisplayAmount; 2]>,
+    #[serde(deserialize_with = "deserialize_orders")]
+    pub asks: Vec<[DisplayAmount; 2]>,
+}
+
+/// Top 100 orders from the `detail_order_book` channel, summed into levels by price and
+/// replacing the book with each message. The order ids aren't kept.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DetailBook {
+    data: DetailBookData,
+}
+
+impl Update for DetailBook {
+    fn validate(&self, _: u64) -> Result<()> {
+        Ok(())
+    }
+    fn last_update_id(&self) -> u64 {
+        self.data.last_update_id
+    }
+    fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.data.bids
+    }
+    fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+        &mut self.data.asks
+    }
+    fn replaces_book(&self) -> bool {
+        true
+    }
+}
+
+impl FromMessage for DetailBook {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl From<Snapshot> for DetailBook {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            data: DetailBookData {
+                last_update_id: snapshot.last_update_id,
+                bids: snapshot.bids,
+                asks: snapshot.asks,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) struct BestPrice {
     pub bid: Decimal,
@@ -128,10 +251,12 @@ pub(super) struct BestPrice {
 }
 
 impl BestPrice {
-    pub(super) async fn fetch(url: Url) -> Result<Self> {
-        let price = reqwest::get(url)
+    pub(super) async fn fetch(http: &HttpClient, url: Url) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let price = http
+            .get(url)
             .await
-            .context("Failed to get price")?
+            .with_context(|| format!("Failed to get price from {}", host))?
             .json::<Self>()
             .await
             .context("Failed to deserialize best price")?;
@@ -157,25 +282,35 @@ impl ExchangeInfoBitstamp {
         Ok(&self.symbol)
     }
 
-    pub async fn fetch(url: Url, symbol: &Symbol) -> Result<Self> {
+    /// Every pair listed, trading pairs info can't be asked for one
+    async fn fetch_all(http: &HttpClient, url: Url) -> Result<Vec<SymbolData>> {
+        let host = url.origin().ascii_serialization();
         let endpoint = url.join("trading-pairs-info").unwrap();
-
-        let symbols = reqwest::get(endpoint)
+        http.get(endpoint)
             .await
-            .context("Failed to get exchange info")?
+            .with_context(|| format!("Failed to get exchange info from {}", host))?
             .json::<Vec<SymbolData>>()
             .await
-            .context("Failed to deserialize exchange info to json")?;
+            .context("Failed to deserialize exchange info to json")
+    }
 
-        let symbol = symbols
+    pub async fn fetch(http: &HttpClient, url: Url, native: &str) -> Result<Self> {
+        let host = url.origin().ascii_serialization();
+        let symbol = Self::fetch_all(http, url)
+            .await?
             .into_iter()
-            .filter(|s| s.url_symbol == symbol.to_string().to_lowercase())
-            .next()
-            .context("Failed to get symbol")?;
+            .find(|s| s.url_symbol == native)
+            .with_context(|| format!("{} is not listed on {}", native, host))?;
 
         Ok(ExchangeInfoBitstamp { symbol })
     }
 
+    /// Whether `native` is still listed
+    pub async fn fetch_listed(http: &HttpClient, url: Url, native: &str) -> Result<bool> {
+        let symbols = Self::fetch_all(http, url).await?;
+        Ok(symbols.iter().any(|s| s.url_symbol == native))
+    }
+
     fn scale_price(&self) -> Result<u32> {
         let scale_price = self.symbol()?.counter_decimals;
         Ok(scale_price)
@@ -187,10 +322,160 @@ impl ExchangeInfoBitstamp {
         Ok(scale_quantity)
     }
 
-    pub async fn fetch_scales(url: Url, symbol: &Symbol) -> Result<(u32, u32)> {
-        let exinfo = Self::fetch(url, symbol).await?;
+    pub async fn fetch_scales(http: &HttpClient, url: Url, native: &str) -> Result<(u32, u32)> {
+        let exinfo = Self::fetch(http, url, native).await?;
         let scale_price = exinfo.scale_price()?;
         let scale_quantity = exinfo.scale_quantity()?;
         Ok((scale_price, scale_quantity))
     }
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiveTradeData {
+    pub id: u64,
+    pub amount_str: Decimal,
+    pub price_str: Decimal,
+    /// 0 when the taker bought and 1 when they sold
+    #[serde(rename = "type")]
+    pub side: u8,
+    #[serde(deserialize_with = "deserialize_u64")]
+    pub microtimestamp: u64,
+}
+
+/// A trade from the `live_trades` channel
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiveTrade {
+    data: LiveTradeData,
+}
+
+impl FromMessage for LiveTrade {
+    fn from_message(data: &[u8]) -> Result<StreamMessage<Self>> {
+        from_message(data)
+    }
+}
+
+impl IntoTrade for LiveTrade {
+    fn into_trade(self) -> Trade {
+        let side = match self.data.side {
+            0 => Some(Side::Buy),
+            1 => Some(Side::Sell),
+            _ => None,
+        };
+        trade(
+            self.data.id,
+            self.data.price_str,
+            self.data.amount_str,
+            side,
+            self.data.microtimestamp / 1000,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("../tests/fixtures/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn it_parses_control_messages() {
+        let message =
+            BookUpdate::from_message(&fixture("control_bitstamp_subscription_succeeded.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+
+        let message = BookUpdate::from_message(&fixture("control_bitstamp_request_reconnect.json"));
+        assert!(matches!(message, Ok(StreamMessage::Reconnect)));
+
+        match BookUpdate::from_message(&fixture("control_bitstamp_error.json")) {
+            Ok(StreamMessage::Error(message)) => assert_eq!(message, "Bad subscription string."),
+            other => panic!("expected error message, got {:?}", other),
+        }
+
+        match BookUpdate::from_message(&fixture("update_bitstamp.json")) {
+            Ok(StreamMessage::Update(mut update)) => {
+                assert_eq!(update.last_update_id(), 1688515101262376);
+                assert_eq!(update.bids_mut().len(), 1);
+                assert_eq!(update.asks_mut().len(), 1);
+            }
+            other => panic!("expected update, got {:?}", other),
+        }
+
+        assert!(BookUpdate::from_message(b"{\"event\": \"bts:unknown\"}").is_err());
+        assert!(BookUpdate::from_message(b"not json").is_err());
+    }
+
+    #[test]
+    fn it_parses_full_book_channels() {
+        match FullBook::from_message(&fixture("order_book_bitstamp.json")) {
+            Ok(StreamMessage::Update(mut book)) => {
+                assert_eq!(book.last_update_id(), 1688515101262376);
+                assert!(book.replaces_book());
+                assert_eq!(book.bids_mut().len(), 3);
+                assert_eq!(
+                    book.asks_mut()[0],
+                    [Decimal::new(30769, 0), Decimal::new(25, 2)]
+                );
+            }
+            other => panic!("expected full book, got {:?}", other),
+        }
+
+        match DetailBook::from_message(&fixture("detail_order_book_bitstamp.json")) {
+            Ok(StreamMessage::Update(mut book)) => {
+                assert_eq!(book.last_update_id(), 1688515101262376);
+                assert!(book.replaces_book());
+                // Two orders at 30766 are summed into one level.
+                assert_eq!(
+                    book.bids_mut().as_slice(),
+                    [
+                        [Decimal::new(30766, 0), Decimal::new(15, 2)],
+                        [Decimal::new(30765, 0), Decimal::new(2, 1)],
+                    ]
+                );
+                assert_eq!(book.asks_mut().len(), 2);
+            }
+            other => panic!("expected detail book, got {:?}", other),
+        }
+
+        let message =
+            DetailBook::from_message(&fixture("control_bitstamp_subscription_succeeded.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        assert!(DetailBook::from_message(&fixture("order_book_bitstamp.json")).is_err());
+    }
+
+    #[test]
+    fn it_keeps_the_best_levels_of_deep_snapshots() {
+        let mut snapshot: Snapshot =
+            serde_json::from_slice(&fixture("snapshot_bitstamp.json")).unwrap();
+        snapshot.truncate(2);
+        assert_eq!(
+            snapshot.bids.iter().map(|l| l[0]).collect::<Vec<_>>(),
+            [Decimal::new(30766, 0), Decimal::new(30763, 0)]
+        );
+        assert_eq!(
+            snapshot.asks.iter().map(|l| l[0]).collect::<Vec<_>>(),
+            [Decimal::new(30769, 0), Decimal::new(30770, 0)]
+        );
+    }
+
+    #[test]
+    fn it_parses_live_trades() {
+        match LiveTrade::from_message(&fixture("trade_bitstamp.json")) {
+            Ok(StreamMessage::Update(trade)) => {
+                let trade = trade.into_trade();
+                assert_eq!(trade.trade_id, 287341367);
+                assert_eq!(trade.price, 30766.0);
+                assert_eq!(trade.quantity, 0.0125);
+                assert_eq!(trade.side, "sell");
+                assert_eq!(trade.exchange_timestamp_ms, 1688515101262);
+            }
+            other => panic!("expected trade, got {:?}", other),
+        }
+
+        let message =
+            LiveTrade::from_message(&fixture("control_bitstamp_subscription_succeeded.json"));
+        assert!(matches!(message, Ok(StreamMessage::Subscribed)));
+        assert!(LiveTrade::from_message(&fixture("update_bitstamp.json")).is_err());
+    }
+}
@@ -1,27 +1,116 @@
 use std::sync::Arc;
 
 use crate::{
+    book_summary::Trade,
     core::{
-        exchange_book::ExchangeBook,
+        exchange_book::{process_updates, Endpoints, ExchangeBook, FromMessage},
+        http::HttpClient,
         num_types::DisplayAmount,
-        order_book::{OrderBook, OrderBookArgs},
+        order_book::{BookLevels, OrderBook, OrderBookArgs, Update},
+        recorder::Recorder,
+        stats::ExchangeStats,
+        trades::process_trades,
     },
+    exchanges::registry::{BookArgs, ExchangeConnector, StreamArgs},
+    replay::{feed, replay_exchange, Frames, Replay},
+    service::ExchangeOptions,
     Exchange, Symbol,
 };
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use data::{BestPrice, BookUpdate, Snapshot};
-use futures::SinkExt;
+use data::{BestPrice, BookUpdate, DetailBook, FullBook, LiveTrade, Snapshot};
+use futures::{future::BoxFuture, SinkExt, StreamExt};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio::sync::{mpsc, RwLock};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::Instrument;
 
 use self::data::ExchangeInfoBitstamp;
 
 pub mod data;
 
+pub use super::options::BitstampChannel;
+
 pub struct BitstampOrderBook {
-    pub orderbook: Arc<Mutex<OrderBook>>,
+    pub orderbook: Arc<RwLock<OrderBook>>,
+    pub stats: Arc<ExchangeStats>,
+    http: HttpClient,
+    endpoints: Endpoints,
+    channel: BitstampChannel,
+    snapshot_depth: Option<u32>,
+    recorder: Option<Recorder>,
+    /// The listing streamed, see [ExchangeBook::new_native]
+    native: String,
+}
+
+impl BitstampOrderBook {
+    pub fn with_channel(mut self, channel: BitstampChannel) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Levels of each side to keep from snapshots. The api only gives the whole book, so
+    /// the depth can't make snapshots any faster to fetch.
+    pub fn with_snapshot_depth(mut self, snapshot_depth: Option<u32>) -> Self {
+        self.snapshot_depth = snapshot_depth;
+        self
+    }
+
+    /// Records the frames received on the update stream
+    pub fn with_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Counts into `stats`, which outlive the book when shared with the next one
+    pub fn with_stats(mut self, stats: Arc<ExchangeStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    async fn subscribe(
+        &self,
+        channel: BitstampChannel,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let subscribe_msg = serde_json::json!({
+            "event": "bts:subscribe",
+            "data": {
+                "channel": format!("{}_{}", channel.name(), self.native)
+            }
+        });
+
+        let mut stream = self.connect_wss("").await?;
+
+        stream
+            .start_send_unpin(Message::Text(subscribe_msg.to_string()))
+            .context("Failed to send subscribe message to bitstamp")?;
+
+        Ok(stream)
+    }
+
+    /// Streams a channel carrying the whole top of the book, so there's no snapshot to
+    /// start from.
+    async fn process_full_books<U>(
+        &self,
+        levels: u32,
+        tx_summary: mpsc::Sender<Arc<BookLevels>>,
+    ) -> Result<()>
+    where
+        U: std::fmt::Debug + Update + From<Snapshot> + FromMessage + Send + Sync + 'static,
+    {
+        let (sink, stream) = self.split_stream(self.subscribe(self.channel).await?).await;
+        process_updates::<Snapshot, U, _, _>(
+            self.orderbook(),
+            self.stats(),
+            levels,
+            Snapshot::default(),
+            None,
+            stream,
+            sink,
+            tx_summary,
+        )
+        .await
+    }
 }
 
 #[async_trait]
@@ -30,28 +119,66 @@ impl ExchangeBook<Snapshot, BookUpdate> for BitstampOrderBook {
     const BASE_URL_HTTPS: &'static str = "https://www.bitstamp.net/api/v2/";
     const BASE_URL_WSS: &'static str = "wss://ws.bitstamp.net/";
 
-    async fn new(symbol: Symbol, price_range: u8) -> Result<Self>
+    fn native_symbol(symbol: Symbol) -> String {
+        symbol.to_string().to_lowercase()
+    }
+
+    async fn new_native(
+        http: HttpClient,
+        endpoints: Endpoints,
+        symbol: Symbol,
+        native: String,
+        price_range: u8,
+    ) -> Result<Self>
     where
         Self: Sized,
     {
         let exchange = Exchange::BITSTAMP;
-        let orderbook = Self::new_orderbook(exchange, symbol, price_range).await?;
+        let orderbook =
+            Self::new_orderbook(&http, &endpoints, exchange, symbol, &native, price_range).await?;
         let exchange_orderbook = Self {
-            orderbook: Arc::new(Mutex::new(orderbook)),
+            orderbook: Arc::new(RwLock::new(orderbook)),
+            stats: Arc::new(ExchangeStats::default()),
+            http,
+            endpoints,
+            channel: BitstampChannel::default(),
+            snapshot_depth: None,
+            recorder: None,
+            native,
         };
         Ok(exchange_orderbook)
     }
 
-    fn orderbook(&self) -> Arc<Mutex<OrderBook>> {
+    fn orderbook(&self) -> Arc<RwLock<OrderBook>> {
         self.orderbook.clone()
     }
 
-    async fn fetch_orderbook_args(symbol: &Symbol, price_range: u8) -> Result<OrderBookArgs> {
-        let (best_price, _) = Self::fetch_prices(symbol).await?;
+    fn stats(&self) -> Arc<ExchangeStats> {
+        self.stats.clone()
+    }
+
+    fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
+    fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    async fn fetch_orderbook_args(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+        price_range: u8,
+    ) -> Result<OrderBookArgs> {
+        let (best_price, _) = Self::fetch_prices(http, endpoints, native).await?;
 
-        println!("base_url_https: {}", Self::base_url_https());
         let (scale_price, scale_quantity) =
-            ExchangeInfoBitstamp::fetch_scales(Self::base_url_https(), symbol).await?;
+            ExchangeInfoBitstamp::fetch_scales(http, endpoints.https.clone(), native).await?;
         let (storage_price_min, storage_price_max) =
             OrderBookArgs::get_min_max(best_price, price_range, scale_price)?;
 
@@ -67,50 +194,190 @@ impl ExchangeBook<Snapshot, BookUpdate> for BitstampOrderBook {
         Ok(args)
     }
 
-    async fn fetch_prices(symbol: &Symbol) -> Result<(DisplayAmount, DisplayAmount)> {
-        let url = Self::base_url_https()
-            .join(format!("ticker/{}", symbol.to_string().to_lowercase()).as_str())?;
-        let price = BestPrice::fetch(url).await?;
+    async fn fetch_listed(http: &HttpClient, endpoints: &Endpoints, native: &str) -> Result<bool> {
+        ExchangeInfoBitstamp::fetch_listed(http, endpoints.https.clone(), native).await
+    }
+
+    async fn fetch_prices(
+        http: &HttpClient,
+        endpoints: &Endpoints,
+        native: &str,
+    ) -> Result<(DisplayAmount, DisplayAmount)> {
+        let url = endpoints
+            .https
+            .join(format!("ticker/{}", native).as_str())?;
+        let price = BestPrice::fetch(http, url).await?;
         Ok((price.bid, price.ask))
     }
 
     async fn fetch_snapshot(&self) -> Result<Snapshot> {
-        let symbol = self
-            .orderbook()
-            .lock()
-            .await
-            .symbol
-            .to_string()
-            .to_lowercase();
-        let url = Self::base_url_https().join(format!("order_book/{}", symbol).as_str())?;
-        Snapshot::fetch(url).await
+        let url = self
+            .endpoints()
+            .https
+            .join(format!("order_book/{}", self.native).as_str())?;
+        let mut snapshot = Snapshot::fetch(self.http(), url).await?;
+        if let Some(depth) = self.snapshot_depth {
+            snapshot.truncate(depth as usize);
+        }
+        Ok(snapshot)
     }
 
     async fn fetch_update_stream(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
-        let symbol = self
-            .orderbook()
-            .lock()
-            .await
-            .symbol
-            .to_string()
-            .to_lowercase();
+        self.subscribe(BitstampChannel::Diff).await
+    }
 
-        let subscribe_msg = serde_json::json!({
-            "event": "bts:subscribe",
-            "data": {
-                "channel": format!("diff_order_book_{}", symbol)
+    async fn start(&self, levels: u32, tx_summary: mpsc::Sender<Arc<BookLevels>>) -> Result<()> {
+        let symbol = self.orderbook().read().await.symbol;
+        tracing::info!("BITSTAMP {} streaming {}", symbol, self.channel.name());
+        match self.channel {
+            BitstampChannel::Diff => {
+                let (sink, stream) = self.split_stream(self.fetch_update_stream().await?).await;
+                let snapshot = self
+                    .fetch_snapshot()
+                    .instrument(tracing::info_span!("fetch_snapshot"))
+                    .await?;
+                process_updates::<Snapshot, BookUpdate, _, _>(
+                    self.orderbook(),
+                    self.stats(),
+                    levels,
+                    snapshot,
+                    Some(Box::new(|| self.fetch_snapshot())),
+                    stream,
+                    sink,
+                    tx_summary,
+                )
+                .await
             }
-        });
+            BitstampChannel::OrderBook => {
+                self.process_full_books::<FullBook>(levels, tx_summary)
+                    .await
+            }
+            BitstampChannel::Detail => {
+                self.process_full_books::<DetailBook>(levels, tx_summary)
+                    .await
+            }
+        }
+    }
+}
 
-        let (mut stream, _) = connect_async(&Self::base_url_wss())
-            .await
-            .context("Failed to connect to bit stamp wss endpoint")?;
+/// Sends the trades of `symbol` from the `live_trades` channel on `tx_trades` until the
+/// stream ends, leaving the order books alone.
+pub async fn stream_trades(
+    http: HttpClient,
+    endpoints: Endpoints,
+    symbol: Symbol,
+    stats: Arc<ExchangeStats>,
+    tx_trades: mpsc::Sender<Trade>,
+) -> Result<()> {
+    let subscribe_msg = serde_json::json!({
+        "event": "bts:subscribe",
+        "data": {
+            "channel": format!("live_trades_{}", symbol.to_string().to_lowercase())
+        }
+    });
+    let (index, mut stream) = endpoints.connect_wss(&http, "").await?;
+    stats.set_wss_endpoint(index);
+    stream
+        .start_send_unpin(Message::Text(subscribe_msg.to_string()))
+        .context("Failed to send subscribe message to bitstamp")?;
+    let (sink, stream) = stream.split();
+    process_trades::<LiveTrade, _, _>(Exchange::BITSTAMP, symbol, stats, stream, sink, tx_trades)
+        .await
+}
 
-        stream
-            .start_send_unpin(Message::Text(subscribe_msg.to_string()))
-            .context("Failed to send subscribe message to bitstamp")?;
+/// Bitstamp's entry in the [registry](crate::exchanges::registry)
+pub struct BitstampConnector;
 
-        Ok(stream)
+impl ExchangeConnector for BitstampConnector {
+    fn default_endpoints(&self) -> Endpoints {
+        BitstampOrderBook::default_endpoints()
+    }
+
+    fn native_symbol(&self, symbol: Symbol) -> String {
+        BitstampOrderBook::native_symbol(symbol)
+    }
+
+    /// Depends on the [channel](ExchangeOptions::bitstamp_channel) the book is kept up to
+    /// date from
+    fn max_depth(&self, options: &ExchangeOptions) -> Option<u32> {
+        options.bitstamp_channel.max_depth()
+    }
+
+    fn fetch_listed<'a>(
+        &self,
+        http: &'a HttpClient,
+        endpoints: &'a Endpoints,
+        native: &'a str,
+    ) -> BoxFuture<'a, Result<bool>> {
+        BitstampOrderBook::fetch_listed(http, endpoints, native)
+    }
+
+    fn book(
+        &self,
+        args: BookArgs,
+        options: &ExchangeOptions,
+    ) -> BoxFuture<'static, Result<BoxFuture<'static, Result<()>>>> {
+        let channel = options.bitstamp_channel;
+        Box::pin(async move {
+            let native = args
+                .native
+                .unwrap_or_else(|| BitstampOrderBook::native_symbol(args.symbol));
+            let mut book = BitstampOrderBook::new_native(
+                args.http,
+                args.endpoints,
+                args.symbol,
+                native,
+                args.price_range,
+            )
+            .await?
+            .with_channel(channel)
+            .with_snapshot_depth(args.snapshot_depth)
+            .with_recorder(args.recorder);
+            if let Some(stats) = args.stats {
+                book = book.with_stats(stats);
+            }
+            book.orderbook().write().await.set_max_depth(args.depth);
+            let (levels, tx_levels) = (args.levels, args.tx_levels);
+            let started: BoxFuture<'static, Result<()>> =
+                Box::pin(async move { book.start(levels, tx_levels).await });
+            Ok(started)
+        })
+    }
+
+    fn stream_trades(
+        &self,
+        args: StreamArgs,
+        _options: &ExchangeOptions,
+        tx_trades: mpsc::Sender<Trade>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(stream_trades(
+            args.http,
+            args.endpoints,
+            args.symbol,
+            args.stats,
+            tx_trades,
+        ))
+    }
+
+    fn replay(&self, replay: Replay, options: &ExchangeOptions) -> BoxFuture<'static, Result<()>> {
+        match options.bitstamp_channel {
+            BitstampChannel::Diff => Box::pin(replay_exchange::<Snapshot, BookUpdate>(replay)),
+            BitstampChannel::OrderBook => Box::pin(replay_exchange::<Snapshot, FullBook>(replay)),
+            BitstampChannel::Detail => Box::pin(replay_exchange::<Snapshot, DetailBook>(replay)),
+        }
+    }
+
+    fn feed_diffs(
+        &self,
+        orderbook: OrderBook,
+        stats: Arc<ExchangeStats>,
+        levels: u32,
+        frames: Frames,
+        tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(feed::<Snapshot, BookUpdate>(
+            orderbook, stats, levels, frames, tx_levels,
+        ))
     }
 }
 
@@ -124,7 +391,10 @@ mod tests {
             num_types::{ToDisplay, ToStorage},
             order_book::{OrderBook, OrderBookArgs},
         },
-        exchanges::bitstamp::data::Snapshot,
+        exchanges::bitstamp::{
+            data::{FullBook, Snapshot},
+            BitstampChannel,
+        },
         Exchange, Symbol,
     };
 
@@ -182,14 +452,14 @@ mod tests {
             .iter()
             .max_by(|a, b| a[0].partial_cmp(&b[0]).unwrap())
             .unwrap()
-            .clone();
+            .to_owned();
 
         let best_ask = snapshot
             .asks
             .iter()
             .max_by(|a, b| b[0].partial_cmp(&a[0]).unwrap())
             .unwrap()
-            .clone();
+            .to_owned();
 
         let spread = best_ask[0] - best_bid[0];
         let bids_count = snapshot.bids.len();
@@ -223,7 +493,7 @@ mod tests {
             {
                 acc + 1
             } else {
-                return acc;
+                acc
             }
         });
 
@@ -234,34 +504,28 @@ mod tests {
             {
                 acc + 1
             } else {
-                return acc;
+                acc
             }
         });
 
         orderbook.update(&mut snapshot).unwrap();
 
-        let ob_bids_count = orderbook.bids.iter().fold(0u64, |acc, x| {
-            if x > &0 {
-                acc + 1
-            } else {
-                return acc;
-            }
-        });
+        let ob_bids_count = orderbook
+            .bids
+            .iter()
+            .fold(0u64, |acc, x| if x > &0 { acc + 1 } else { acc });
 
-        let ob_asks_count = orderbook.asks.iter().fold(0u64, |acc, x| {
-            if x > &0 {
-                acc + 1
-            } else {
-                return acc;
-            }
-        });
+        let ob_asks_count = orderbook
+            .asks
+            .iter()
+            .fold(0u64, |acc, x| if x > &0 { acc + 1 } else { acc });
 
         assert_eq!(
-            bid_count_in_range as u64, ob_bids_count as u64,
+            bid_count_in_range, ob_bids_count,
             "bid count in snapshot not equal to bid count in updated orderbookd"
         );
         assert_eq!(
-            ask_count_in_range as u64, ob_asks_count as u64,
+            ask_count_in_range, ob_asks_count,
             "ask count in snapshot not equal to bid count in updated orderbookd"
         );
 
@@ -309,4 +573,27 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn it_replaces_the_snapshot_with_full_books() {
+        let snapshot_bytes = read("../tests/fixtures/snapshot_bitstamp.json").await;
+        let mut snapshot: Snapshot = serde_json::from_slice(&snapshot_bytes.unwrap()).unwrap();
+        let mut orderbook = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 29301, 32304, 0, 8);
+        orderbook.update(&mut snapshot).unwrap();
+
+        let full_book_bytes = read("../tests/fixtures/order_book_bitstamp.json").await;
+        let mut full_book: FullBook = serde_json::from_slice(&full_book_bytes.unwrap()).unwrap();
+        orderbook.update(&mut full_book).unwrap();
+
+        let book_levels = orderbook.get_book_levels(10).unwrap();
+        assert_eq!(book_levels.bids.len(), 3);
+        assert_eq!(book_levels.asks.len(), 2);
+        assert_eq!(book_levels.bids[0].price, 30766.0);
+        assert_eq!(book_levels.asks[1].price, 30770.0);
+        assert_eq!(
+            BitstampChannel::from_name("order_book"),
+            Some(BitstampChannel::OrderBook)
+        );
+        assert_eq!(BitstampChannel::default().name(), "diff_order_book");
+    }
 }
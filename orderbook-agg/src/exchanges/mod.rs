@@ -1,4 +1,28 @@
 //! [OrderBook](crate::core::order_book::OrderBook) wrapper struct and
 //! [ExchangeBook](crate::core::exchange_book::ExchangeBook) implementations
+//!
+//! Each connector is compiled in with its cargo feature, `exchange-binance`,
+//! `exchange-binance-futures` or `exchange-bitstamp`, all of them by default. Binance
+//! futures builds on the Binance connector, so it brings that in too. The rest of the
+//! server only reaches the connectors through the [registry], so leaving one out takes
+//! nothing more than its module and its entry there.
+#[cfg(feature = "exchange-binance")]
 pub mod binance;
+#[cfg(feature = "exchange-binance-futures")]
+pub mod binance_futures;
+#[cfg(feature = "exchange-bitstamp")]
 pub mod bitstamp;
+pub mod options;
+pub mod registry;
+
+pub use registry::{compiled, connector, BookArgs, ExchangeConnector, StreamArgs};
+
+#[cfg(not(any(
+    feature = "exchange-binance",
+    feature = "exchange-binance-futures",
+    feature = "exchange-bitstamp"
+)))]
+compile_error!(
+    "no exchange connectors are compiled in, enable at least one of the exchange-binance, \
+     exchange-binance-futures and exchange-bitstamp features"
+);
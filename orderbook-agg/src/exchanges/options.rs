@@ -0,0 +1,130 @@
+//! The settings of each connector, kept apart from the connectors so the server's flags
+//! and config file parse the same whichever of them are compiled in.
+
+/// Update speed of Binance's diff depth stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DepthSpeed {
+    #[default]
+    Ms100,
+    Ms1000,
+}
+
+impl DepthSpeed {
+    pub fn from_millis(millis: u32) -> Option<Self> {
+        match millis {
+            100 => Some(Self::Ms100),
+            1000 => Some(Self::Ms1000),
+            _ => None,
+        }
+    }
+
+    pub fn millis(self) -> u32 {
+        match self {
+            Self::Ms100 => 100,
+            Self::Ms1000 => 1000,
+        }
+    }
+
+    /// Name of the stream for `symbol` in `mode`, 1000ms being the speed without a suffix
+    pub fn stream_name(self, symbol: &str, mode: DepthMode) -> String {
+        let depth = match mode {
+            DepthMode::Diff => String::new(),
+            DepthMode::Partial(levels) => levels.to_string(),
+        };
+        match self {
+            Self::Ms100 => format!("{}@depth{}@100ms", symbol, depth),
+            Self::Ms1000 => format!("{}@depth{}", symbol, depth),
+        }
+    }
+}
+
+/// Which of Binance's trade streams trades are read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TradeStream {
+    /// Each fill
+    Trade,
+    /// The fills of each taker order at one price summed into one trade
+    #[default]
+    AggTrade,
+}
+
+impl TradeStream {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "trade" => Some(Self::Trade),
+            "aggTrade" => Some(Self::AggTrade),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Trade => "trade",
+            Self::AggTrade => "aggTrade",
+        }
+    }
+}
+
+/// How Binance's order book is kept up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthMode {
+    /// Changes from the diff depth stream applied on top of a REST snapshot
+    Diff,
+    /// The top levels from a partial book depth stream, replacing the book each message
+    Partial(u8),
+}
+
+impl DepthMode {
+    /// Uses the smallest partial book holding `levels` when partial books are allowed,
+    /// and the diff stream for more levels than the largest one holds.
+    pub fn select(levels: u32, allow_partial: bool) -> Self {
+        match [5, 10, 20]
+            .into_iter()
+            .find(|depth| levels <= *depth as u32)
+        {
+            Some(depth) if allow_partial => Self::Partial(depth),
+            _ => Self::Diff,
+        }
+    }
+}
+
+/// Websocket channel Bitstamp's order book is kept up to date from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitstampChannel {
+    /// Changes applied on top of a REST snapshot, deleting levels with zero quantity
+    #[default]
+    Diff,
+    /// The top 100 levels, replacing the book with each message
+    OrderBook,
+    /// The top 100 orders with their ids, summed into levels and replacing the book with
+    /// each message
+    Detail,
+}
+
+impl BitstampChannel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "diff_order_book" => Some(Self::Diff),
+            "order_book" => Some(Self::OrderBook),
+            "detail_order_book" => Some(Self::Detail),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Diff => "diff_order_book",
+            Self::OrderBook => "order_book",
+            Self::Detail => "detail_order_book",
+        }
+    }
+
+    /// The most levels of each side the channel gives, none for the diffs applied on top of
+    /// a snapshot of the whole book
+    pub fn max_depth(self) -> Option<u32> {
+        match self {
+            Self::Diff => None,
+            Self::OrderBook | Self::Detail => Some(100),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! The connectors compiled into the server, found by their [Exchange] with [connector].
+//!
+//! Each connector's module registers a unit struct implementing [ExchangeConnector] in
+//! [connector], behind the same feature as the module. Everything else is written against
+//! the trait, so nothing outside the registry changes with the exchanges compiled in.
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::{
+    book_summary::{DerivativeInfo, Trade},
+    core::{
+        exchange_book::Endpoints, http::HttpClient, order_book::BookLevels, order_book::OrderBook,
+        recorder::Recorder, stats::ExchangeStats,
+    },
+    replay::{Frames, Replay},
+    service::ExchangeOptions,
+    Exchange, Symbol,
+};
+
+/// Every exchange there's a connector for, whether or not it's compiled in
+const EXCHANGES: [Exchange; 3] = [
+    Exchange::BINANCE,
+    Exchange::BITSTAMP,
+    Exchange::BINANCE_FUTURES,
+];
+
+/// What an order book is made from by [ExchangeConnector::book]
+pub struct BookArgs {
+    pub http: HttpClient,
+    pub endpoints: Endpoints,
+    pub symbol: Symbol,
+    /// The listing streamed when it isn't the exchange's
+    /// [native symbol](ExchangeConnector::native_symbol)
+    pub native: Option<String>,
+    pub price_range: u8,
+    pub levels: u32,
+    /// The most levels of each side kept, every level within the price range when not set
+    pub depth: Option<u32>,
+    pub snapshot_depth: Option<u32>,
+    pub recorder: Option<Recorder>,
+    /// Where the book counts what it does, its own when not set
+    pub stats: Option<Arc<ExchangeStats>>,
+    pub tx_levels: mpsc::Sender<Arc<BookLevels>>,
+}
+
+/// Where a stream that leaves the order books alone connects
+pub struct StreamArgs {
+    pub http: HttpClient,
+    pub endpoints: Endpoints,
+    pub symbol: Symbol,
+    pub stats: Arc<ExchangeStats>,
+}
+
+/// An exchange's connector. Settings the connector has in [ExchangeOptions] are read from
+/// the options it's given, before any future it returns is first polled.
+pub trait ExchangeConnector: Send + Sync {
+    /// The urls connected to unless others are configured
+    fn default_endpoints(&self) -> Endpoints;
+
+    /// The exchange's own name for `symbol`, which its order books stream unless told
+    /// otherwise
+    fn native_symbol(&self, symbol: Symbol) -> String;
+
+    /// The most levels of each side the exchange gives with `options`, none when its whole
+    /// book can be had
+    fn max_depth(&self, options: &ExchangeOptions) -> Option<u32>;
+
+    /// Whether the exchange still lists `native` at `endpoints`
+    fn fetch_listed<'a>(
+        &self,
+        http: &'a HttpClient,
+        endpoints: &'a Endpoints,
+        native: &'a str,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    /// Makes the order book of `args`, failing when the exchange doesn't list it, and
+    /// returns the future that keeps it up to date until its stream ends.
+    fn book(
+        &self,
+        args: BookArgs,
+        options: &ExchangeOptions,
+    ) -> BoxFuture<'static, Result<BoxFuture<'static, Result<()>>>>;
+
+    /// Sends the exchange's trades of the symbol on `tx_trades` until the stream ends.
+    fn stream_trades(
+        &self,
+        args: StreamArgs,
+        options: &ExchangeOptions,
+        tx_trades: mpsc::Sender<Trade>,
+    ) -> BoxFuture<'static, Result<()>>;
+
+    /// Sends the mark price and funding of the symbol on `tx_info` until the stream ends,
+    /// failing straight away for exchanges without either.
+    fn stream_mark_prices(
+        &self,
+        args: StreamArgs,
+        tx_info: mpsc::Sender<DerivativeInfo>,
+    ) -> BoxFuture<'static, Result<()>> {
+        let _ = (args, tx_info);
+        Box::pin(async { bail!("the exchange has no mark prices") })
+    }
+
+    /// Replays recordings into an order book, parsed as the channel `options` would have
+    /// subscribed to, see [replay_symbol](crate::replay::replay_symbol).
+    fn replay(&self, replay: Replay, options: &ExchangeOptions) -> BoxFuture<'static, Result<()>>;
+
+    /// Applies `frames` of the exchange's diff channel to `orderbook`, see
+    /// [synthetic_symbol](crate::synthetic::synthetic_symbol).
+    fn feed_diffs(
+        &self,
+        orderbook: OrderBook,
+        stats: Arc<ExchangeStats>,
+        levels: u32,
+        frames: Frames,
+        tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    ) -> BoxFuture<'static, Result<()>>;
+}
+
+/// The connector of `exchange`, failing when it isn't compiled in.
+pub fn connector(exchange: Exchange) -> Result<&'static dyn ExchangeConnector> {
+    match find(exchange) {
+        Some(connector) => Ok(connector),
+        None => bail!(
+            "{} isn't compiled into this server, which only has {}. Build it with the {} \
+             feature to connect to it",
+            exchange,
+            compiled()
+                .iter()
+                .map(|exchange| exchange.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            feature(exchange)
+        ),
+    }
+}
+
+/// The exchanges with a connector compiled in
+pub fn compiled() -> Vec<Exchange> {
+    EXCHANGES
+        .into_iter()
+        .filter(|&exchange| find(exchange).is_some())
+        .collect()
+}
+
+fn find(exchange: Exchange) -> Option<&'static dyn ExchangeConnector> {
+    match exchange {
+        #[cfg(feature = "exchange-binance")]
+        Exchange::BINANCE => Some(&super::binance::BinanceConnector),
+        #[cfg(feature = "exchange-binance-futures")]
+        Exchange::BINANCE_FUTURES => Some(&super::binance_futures::BinanceFuturesConnector),
+        #[cfg(feature = "exchange-bitstamp")]
+        Exchange::BITSTAMP => Some(&super::bitstamp::BitstampConnector),
+        // Only reachable with some of the connectors left out.
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// The cargo feature compiling in the connector of `exchange`
+fn feature(exchange: Exchange) -> &'static str {
+    match exchange {
+        Exchange::BINANCE => "exchange-binance",
+        Exchange::BINANCE_FUTURES => "exchange-binance-futures",
+        Exchange::BITSTAMP => "exchange-bitstamp",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_the_connector_of_each_exchange_compiled_in() {
+        assert!(!compiled().is_empty());
+        for exchange in EXCHANGES {
+            match connector(exchange) {
+                Ok(connector) => {
+                    assert!(compiled().contains(&exchange));
+                    assert!(!connector.native_symbol(Symbol::BTCUSDT).is_empty());
+                }
+                Err(err) => assert!(err.to_string().contains(feature(exchange)), "{}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Writes summaries out for other tools to read, as json, csv or a table for people.
+use anyhow::{bail, Result};
+use std::io::{self, Write};
+
+use crate::book_summary::{Level, Summary};
+
+/// How summaries are written by a [SummaryWriter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One json object a line, with the proto's field names
+    Json,
+    /// One row a level under a header written once, see [CSV_HEADER]
+    Csv,
+    /// Asks above bids with prices to a number of decimals
+    Table,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "table" => Ok(Self::Table),
+            _ => bail!("unknown format {}, expected json, csv or table", format),
+        }
+    }
+}
+
+/// Columns of the csv format. New columns only ever go on the end.
+pub const CSV_HEADER: &str = "sequence,side,exchange,price,quantity";
+
+/// Writes each summary to `out` in a [Format], flushing after each one so summaries
+/// streamed to a pipe arrive as they're received.
+pub struct SummaryWriter<W: Write> {
+    out: W,
+    format: Format,
+    /// Decimals prices and quantities are shown with in tables
+    decimals: usize,
+    wrote_header: bool,
+}
+
+impl<W: Write> SummaryWriter<W> {
+    pub fn new(out: W, format: Format, decimals: usize) -> Self {
+        Self {
+            out,
+            format,
+            decimals,
+            wrote_header: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn write(&mut self, summary: &Summary) -> io::Result<()> {
+        match self.format {
+            Format::Json => {
+                serde_json::to_writer(&mut self.out, summary)?;
+                writeln!(self.out)?;
+            }
+            Format::Csv => self.write_csv(summary)?,
+            Format::Table => self.write_table(summary)?,
+        }
+        self.out.flush()
+    }
+
+    fn write_csv(&mut self, summary: &Summary) -> io::Result<()> {
+        if !self.wrote_header {
+            writeln!(self.out, "{}", CSV_HEADER)?;
+            self.wrote_header = true;
+        }
+        for (side, levels) in [("bid", &summary.bids), ("ask", &summary.asks)] {
+            for level in levels {
+                writeln!(
+                    self.out,
+                    "{},{},{},{},{}",
+                    summary.sequence,
+                    side,
+                    csv_field(&level.exchange),
+                    level.price,
+                    level.quantity
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_table(&mut self, summary: &Summary) -> io::Result<()> {
+        let decimals = self.decimals;
+        writeln!(
+            self.out,
+            "{} spread {:.2$}",
+            summary.symbol, summary.spread, decimals
+        )?;
+        if summary.degraded {
+            writeln!(
+                self.out,
+                "unavailable: {}",
+                summary.unavailable_exchanges.join(", ")
+            )?;
+        }
+        let asks = summary.asks.iter().rev().collect::<Vec<&Level>>();
+        for (side, levels) in [("asks", asks), ("bids", summary.bids.iter().collect())] {
+            writeln!(self.out, "{}", side)?;
+            for level in levels {
+                writeln!(
+                    self.out,
+                    "{:>14.3$} {:>14.3$} {}",
+                    level.price, level.quantity, level.exchange, decimals
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quotes a csv field if it has a comma, quote or line break in it.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::MarketType;
+
+    fn summary(sequence: u64) -> Summary {
+        let level = |exchange: &str, price: f64, quantity: f64| Level {
+            exchange: exchange.to_string(),
+            price,
+            quantity,
+            updated_at: None,
+            market_type: MarketType::Spot.into(),
+        };
+        Summary {
+            symbol: "BTCUSDT".to_string(),
+            spread: 0.5,
+            timestamp: 1688515101,
+            bids: vec![
+                level("BINANCE", 30000.25, 1.5),
+                level("BITSTAMP", 30000.0, 0.125),
+            ],
+            asks: vec![level("BITSTAMP", 30000.75, 2.0)],
+            degraded: true,
+            unavailable_exchanges: vec!["BINANCE_FUTURES".to_string()],
+            sequence,
+            liquidity: Vec::new(),
+            ofi: Some(-1.5),
+            stale_exchanges: Vec::new(),
+            native_symbols: Default::default(),
+            delisted_exchanges: Vec::new(),
+            exchange_depths: Default::default(),
+            derivatives: Default::default(),
+            quote_currency: String::new(),
+            fx_rates: Default::default(),
+            metrics: None,
+            smoothed_metrics: None,
+            checksum: 0,
+            is_backfill: false,
+            notes: Vec::new(),
+        }
+    }
+
+    fn written(format: Format, summaries: &[Summary]) -> String {
+        let mut writer = SummaryWriter::new(Vec::new(), format, 2);
+        for summary in summaries {
+            writer.write(summary).unwrap();
+        }
+        String::from_utf8(writer.out).unwrap()
+    }
+
+    #[test]
+    fn it_writes_json_lines_with_the_proto_field_names() {
+        let expected = concat!(
+            r#"{"symbol":"BTCUSDT","spread":0.5,"timestamp":1688515101,"#,
+            r#""bids":[{"exchange":"BINANCE","price":30000.25,"quantity":1.5,"updated_at":null,"#,
+            r#""market_type":0},"#,
+            r#"{"exchange":"BITSTAMP","price":30000.0,"quantity":0.125,"updated_at":null,"#,
+            r#""market_type":0}],"#,
+            r#""asks":[{"exchange":"BITSTAMP","price":30000.75,"quantity":2.0,"updated_at":null,"#,
+            r#""market_type":0}],"#,
+            r#""degraded":true,"unavailable_exchanges":["BINANCE_FUTURES"],"sequence":7,"#,
+            r#""liquidity":[],"ofi":-1.5,"stale_exchanges":[],"native_symbols":{},"#,
+            r#""delisted_exchanges":[],"exchange_depths":{},"derivatives":{},"#,
+            r#""quote_currency":"","fx_rates":{},"metrics":null,"smoothed_metrics":null,"#,
+            r#""checksum":0,"is_backfill":false,"notes":[]}"#,
+            "\n",
+        );
+        assert_eq!(written(Format::Json, &[summary(7)]), expected);
+    }
+
+    #[test]
+    fn it_writes_csv_rows_under_one_header() {
+        let expected = "\
+sequence,side,exchange,price,quantity
+7,bid,BINANCE,30000.25,1.5
+7,bid,BITSTAMP,30000,0.125
+7,ask,BITSTAMP,30000.75,2
+8,bid,BINANCE,30000.25,1.5
+8,bid,BITSTAMP,30000,0.125
+8,ask,BITSTAMP,30000.75,2
+";
+        assert_eq!(written(Format::Csv, &[summary(7), summary(8)]), expected);
+
+        assert_eq!(csv_field("BINANCE"), "BINANCE");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn it_writes_tables_asks_above_bids() {
+        let expected = "\
+BTCUSDT spread 0.50
+unavailable: BINANCE_FUTURES
+asks
+      30000.75           2.00 BITSTAMP
+bids
+      30000.25           1.50 BINANCE
+      30000.00           0.12 BITSTAMP
+";
+        assert_eq!(written(Format::Table, &[summary(7)]), expected);
+    }
+
+    #[test]
+    fn it_parses_formats() {
+        assert_eq!("csv".parse::<Format>().unwrap(), Format::Csv);
+        assert!("yaml".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Converts the prices of exchanges quoting a symbol in different currencies, for
+//! `GetSummary` requests naming a `quote_currency`.
+//!
+//! Each exchange's levels are multiplied by the rate from its listing's quote currency
+//! before they're merged, quantities are left as they are. Converted bids are rounded down
+//! and asks up to the book's price scale, so no exchange's best bid can reach its best ask
+//! however the rate falls. Levels rounded to the same price are combined. The converted
+//! levels have no [Depth](crate::core::order_book::Depth), so the summary's liquidity only
+//! counts their top levels.
+//!
+//! Rates come from a [RateSource], only [StaticRates] set with `--fx-rates` in this build.
+use anyhow::{ensure, Context, Result};
+use std::collections::HashMap;
+
+use crate::{book_summary::FxRate, book_summary::Level, core::order_book::BookLevels};
+
+/// The price scale of converted books that don't report their own
+const DEFAULT_PRICE_SCALE: u32 = 8;
+
+/// The quote currencies listings are recognised by, longest first so USDT isn't taken for
+/// USD.
+const QUOTE_CURRENCIES: [&str; 9] = [
+    "USDT", "USDC", "BUSD", "USD", "EUR", "GBP", "JPY", "BTC", "ETH",
+];
+
+/// Where the rates between quote currencies come from
+pub trait RateSource: std::fmt::Debug + Send + Sync {
+    /// The rate prices quoted in `from` are multiplied by to be quoted in `to`, both upper
+    /// case, or `None` without one
+    fn rate(&self, from: &str, to: &str) -> Option<FxRate>;
+}
+
+/// Rates set when the server starts, timestamped then
+#[derive(Debug, Clone, Default)]
+pub struct StaticRates {
+    rates: HashMap<(String, String), f64>,
+    timestamp_ms: u64,
+}
+
+impl StaticRates {
+    pub fn new(rates: HashMap<(String, String), f64>) -> Self {
+        Self {
+            rates,
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
+
+impl RateSource for StaticRates {
+    /// The rate set from `from` to `to`, or the inverse of the one set the other way
+    fn rate(&self, from: &str, to: &str) -> Option<FxRate> {
+        let key = |from: &str, to: &str| (from.to_string(), to.to_string());
+        let rate = match self.rates.get(&key(from, to)) {
+            Some(&rate) => rate,
+            None => 1.0 / *self.rates.get(&key(to, from))?,
+        };
+        Some(FxRate {
+            from: from.to_string(),
+            to: to.to_string(),
+            rate,
+            timestamp_ms: self.timestamp_ms,
+        })
+    }
+}
+
+/// Parses `FROM/TO=rate` pairs given to the server, e.g. EUR/USD=1.09, into the rate of
+/// each pair of currencies
+pub fn parse_rates<S: AsRef<str>>(pairs: &[S]) -> Result<HashMap<(String, String), f64>> {
+    let mut rates = HashMap::with_capacity(pairs.len());
+    for pair in pairs.iter().map(|pair| pair.as_ref().trim()) {
+        let (currencies, rate) = pair
+            .split_once('=')
+            .with_context(|| format!("expected FROM/TO=rate, got {}", pair))?;
+        let (from, to) = currencies
+            .split_once('/')
+            .with_context(|| format!("expected FROM/TO=rate, got {}", pair))?;
+        let (from, to) = (from.trim().to_uppercase(), to.trim().to_uppercase());
+        ensure!(
+            !from.is_empty() && !to.is_empty() && from != to,
+            "expected two different currencies, got {}",
+            pair
+        );
+        let rate = rate
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("invalid rate for {}", currencies))?;
+        ensure!(
+            rate.is_finite() && rate > 0.0,
+            "rates must be above 0, got {}",
+            pair
+        );
+        rates.insert((from, to), rate);
+    }
+    Ok(rates)
+}
+
+/// The currency a listing such as btcusdt is quoted in, upper case, or `None` when it
+/// doesn't end with one of the currencies known
+pub fn quote_currency(native: &str) -> Option<&'static str> {
+    let native = native.to_uppercase();
+    QUOTE_CURRENCIES
+        .into_iter()
+        .find(|&currency| native.len() > currency.len() && native.ends_with(currency))
+}
+
+/// `levels` with each price multiplied by `rate`, bids rounded down and asks up
+pub fn convert(levels: &BookLevels, rate: f64) -> BookLevels {
+    let scale = levels
+        .depth
+        .as_ref()
+        .map_or(DEFAULT_PRICE_SCALE, |depth| depth.scale_price);
+    let factor = 10f64.powi(scale as i32);
+    // Prices that convert to within float error of a tick are taken as on it.
+    let round = |price: f64, side: fn(f64) -> f64| {
+        let ticks = price * rate * factor;
+        let nearest = ticks.round();
+        let ticks = if (ticks - nearest).abs() < 1e-6 {
+            nearest
+        } else {
+            side(ticks)
+        };
+        ticks / factor
+    };
+    BookLevels {
+        bids: convert_side(&levels.bids, |price| round(price, f64::floor)),
+        asks: convert_side(&levels.asks, |price| round(price, f64::ceil)),
+        depth: None,
+        ..levels.clone()
+    }
+}
+
+/// The levels at their converted prices, best first, adding up levels that convert to the
+/// same price, which take the latest time any of them was updated at
+fn convert_side(levels: &[Level], price: impl Fn(f64) -> f64) -> Vec<Level> {
+    let mut converted: Vec<Level> = Vec::with_capacity(levels.len());
+    for level in levels {
+        let price = price(level.price);
+        match converted.last_mut() {
+            Some(last) if last.price == price => {
+                last.quantity += level.quantity;
+                last.updated_at = last.updated_at.max(level.updated_at);
+            }
+            _ => converted.push(Level {
+                price,
+                ..level.clone()
+            }),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::order_book::Depth, Exchange};
+    use std::sync::Arc;
+
+    fn levels(bids: &[[f64; 2]], asks: &[[f64; 2]]) -> BookLevels {
+        let side = |amounts: &[[f64; 2]]| {
+            amounts
+                .iter()
+                .map(|&[price, quantity]| Level {
+                    exchange: Exchange::BITSTAMP.to_string(),
+                    price,
+                    quantity,
+                    updated_at: None,
+                    market_type: Exchange::BITSTAMP.market_type().into(),
+                })
+                .collect()
+        };
+        BookLevels {
+            exchange: Exchange::BITSTAMP,
+            bids: side(bids),
+            asks: side(asks),
+            ..Default::default()
+        }
+    }
+
+    fn amounts(levels: &[Level]) -> Vec<[f64; 2]> {
+        levels.iter().map(|l| [l.price, l.quantity]).collect()
+    }
+
+    #[test]
+    fn it_rounds_converted_bids_down_and_asks_up() {
+        let book = BookLevels {
+            depth: Some(Arc::new(Depth {
+                scale_price: 2,
+                ..Default::default()
+            })),
+            ..levels(
+                &[[28000.0, 1.0], [27999.99, 2.0], [27999.98, 0.5]],
+                &[[28000.01, 1.5]],
+            )
+        };
+        let converted = convert(&book, 0.5);
+        // 27999.99 and 27999.98 both round down to 13999.99.
+        assert_eq!(amounts(&converted.bids), [[14000.0, 1.0], [13999.99, 2.5]]);
+        assert_eq!(amounts(&converted.asks), [[14000.01, 1.5]]);
+        assert!(converted.depth.is_none());
+
+        // Without a scale of their own, converted books keep 8 decimals.
+        let converted = convert(&levels(&[[27999.99, 2.0]], &[]), 0.5);
+        assert_eq!(amounts(&converted.bids), [[13999.995, 2.0]]);
+    }
+
+    #[test]
+    fn it_never_crosses_a_book_it_converts() {
+        let book = levels(
+            &[[100.0, 1.0], [99.99, 1.0]],
+            &[[100.01, 1.0], [100.02, 1.0]],
+        );
+        for rate in [0.0001, 0.3333, 0.9215, 1.0, 1.0857, 7.25, 149.61, 31000.0] {
+            let converted = convert(&book, rate);
+            let (bid, ask) = (converted.bids[0].price, converted.asks[0].price);
+            assert!(bid < ask, "crossed at {}: {} >= {}", rate, bid, ask);
+        }
+    }
+
+    #[test]
+    fn it_takes_the_inverse_of_rates_set_the_other_way() {
+        let rates = StaticRates::new(parse_rates(&["eur/usd=1.25", " GBP/USD = 1.5 "]).unwrap());
+        assert_eq!(rates.rate("EUR", "USD").unwrap().rate, 1.25);
+        assert_eq!(rates.rate("USD", "EUR").unwrap().rate, 0.8);
+        assert_eq!(rates.rate("GBP", "EUR"), None);
+
+        for invalid in [
+            "EUR/USD",
+            "EURUSD=1.1",
+            "EUR/EUR=1",
+            "EUR/USD=0",
+            "EUR/USD=x",
+        ] {
+            assert!(parse_rates(&[invalid]).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn it_finds_the_quote_currency_of_listings() {
+        assert_eq!(quote_currency("btcusdt"), Some("USDT"));
+        assert_eq!(quote_currency("BTCUSD"), Some("USD"));
+        assert_eq!(quote_currency("btceur"), Some("EUR"));
+        assert_eq!(quote_currency("ETHBTC"), Some("BTC"));
+        assert_eq!(quote_currency("usd"), None);
+        assert_eq!(quote_currency("btcxyz"), None);
+    }
+}
@@ -3,15 +3,18 @@
 //! ## Server
 //! Connects to two exchanges, Bitstamp and Binance, and aggregates the order book data from
 //! both into a single stream. The server can be started with:
-//! ```
+//! ```sh
 //! cargo run --bin server
 //! ```
 //!
 //! ## Client
-//! There is a client that can be used to connect to the server and receive the summary stream.
-//! ```
-//! cargo run --bin client
+//! `obagg` is a command line client for the server, printing the symbols, exchanges and
+//! summaries it serves. `watch` streams summaries as lines of json.
+//! ```sh
+//! cargo run --bin obagg -- watch btcusdt --duration 30s
 //! ```
+//! Other Rust programs can use [OrderbookClient](crate::client::OrderbookClient), which
+//! `obagg` is built on, see `examples/watch_summaries.rs`.
 //!
 //! ## Order Book
 //! Order books for each exchange are maintained in separate processes with a summary streamed
@@ -26,20 +29,60 @@
 //! Additional exchanges can be added by creating a wrapper struct around an [OrderBook](crate::core::order_book::OrderBook) instance
 //! and implementing the [ExchangeBook](crate::core::exchange_book::ExchangeBook) trait. See [BitstampOrderBook](crate::exchanges::bitstamp::BitstampOrderBook)
 //! and [BinanceOrderBook](crate::exchanges::binance::BinanceOrderBook) for example implementations.
+//! The server reaches each exchange through its [ExchangeConnector](crate::exchanges::ExchangeConnector)
+//! in the [registry](crate::exchanges::registry), compiled in with the exchange's cargo feature.
 use crate::core::order_book::BookLevels;
 
-use book_summary::{Level, Summary};
+use anyhow::{ensure, Context, Result};
+use book_summary::{Level, MarketType, Summary};
 use serde::{Deserialize, Serialize};
 
 /// Module built from protobuf definitions.
 pub mod book_summary {
     tonic::include_proto!("booksummary");
 }
+pub mod access;
+pub mod admin;
+pub mod alerts;
+pub mod backfill;
+pub mod batching;
+pub mod book;
+pub mod candles;
+pub mod checksum;
+pub mod client;
+pub mod config;
 pub mod core;
+pub mod derivatives;
+pub mod divergence;
 pub mod exchanges;
+pub mod format;
+pub mod fx;
+pub mod liquidity;
+pub mod listen;
+pub mod listings;
+pub mod logging;
+pub mod metrics;
+pub mod ofi;
+pub mod pipelines;
+pub mod reload;
+pub mod replay;
+pub mod service;
+pub mod sinks;
+pub mod smoothing;
+pub mod spread_stats;
+pub mod streams;
+pub mod summary_cache;
+pub mod synthetic;
+pub mod ticker;
+pub mod tls;
+pub mod trades;
+#[cfg(unix)]
+pub mod uds;
+pub mod warm;
+pub mod warmup;
 
 /// The symbol the order book data is for
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
 pub enum Symbol {
     #[default]
     BTCUSDT,
@@ -57,12 +100,32 @@ impl std::fmt::Display for Symbol {
     }
 }
 
+impl std::str::FromStr for Symbol {
+    type Err = anyhow::Error;
+
+    /// Parses the name the symbol is displayed with, ignoring case.
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_uppercase().as_str() {
+            "BTCUSDT" => Ok(Symbol::BTCUSDT),
+            "BTCUSD" => Ok(Symbol::BTCUSD),
+            "ETHBTC" => Ok(Symbol::ETHBTC),
+            _ => anyhow::bail!(
+                "unknown symbol {}, expected btcusdt, btcusd or ethbtc",
+                name
+            ),
+        }
+    }
+}
+
 /// The exchange the order book data is for
+#[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Hash, Eq)]
 pub enum Exchange {
     #[default]
     BINANCE,
     BITSTAMP,
+    /// Binance USDⓈ-M perpetual futures
+    BINANCE_FUTURES,
 }
 
 impl std::fmt::Display for Exchange {
@@ -70,6 +133,34 @@ impl std::fmt::Display for Exchange {
         match self {
             Exchange::BINANCE => write!(f, "BINANCE"),
             Exchange::BITSTAMP => write!(f, "BITSTAMP"),
+            Exchange::BINANCE_FUTURES => write!(f, "BINANCE_FUTURES"),
+        }
+    }
+}
+
+impl Exchange {
+    /// The market the exchange's listings are of, which its levels are labeled with
+    pub fn market_type(&self) -> MarketType {
+        match self {
+            Exchange::BINANCE | Exchange::BITSTAMP => MarketType::Spot,
+            Exchange::BINANCE_FUTURES => MarketType::Futures,
+        }
+    }
+}
+
+impl std::str::FromStr for Exchange {
+    type Err = anyhow::Error;
+
+    /// Parses the name the exchange is displayed with, ignoring case.
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_uppercase().as_str() {
+            "BINANCE" => Ok(Exchange::BINANCE),
+            "BITSTAMP" => Ok(Exchange::BITSTAMP),
+            "BINANCE_FUTURES" => Ok(Exchange::BINANCE_FUTURES),
+            _ => anyhow::bail!(
+                "unknown exchange {}, expected binance, bitstamp or binance_futures",
+                name
+            ),
         }
     }
 }
@@ -78,30 +169,61 @@ impl std::fmt::Display for Exchange {
 ///
 /// # Arguments
 ///
-/// * `book_levels_vec` - A vector of [BookLevels] structs from multiple exchanges
+/// * `book_levels` - The latest [BookLevels] from each exchange, best bids and asks first
+///   as returned by [OrderBook](crate::core::order_book::OrderBook)
 /// * `symbol` - The symbol the order book data is for
 // TODO: Remove symbol argument and get from BookLevels
-pub fn make_summary(mut book_levels_vec: Vec<BookLevels>, symbol: Symbol) -> Summary {
-    let levels_count = book_levels_vec[0].bids.len();
-    let exchange_count = book_levels_vec.len();
+pub fn make_summary(book_levels: &[&BookLevels], symbol: Symbol) -> Result<Summary> {
+    ensure!(!book_levels.is_empty(), "no book levels to summarize");
+    // Books kept to a shallower depth than others don't cut the summary short.
+    let bids_count = book_levels.iter().map(|l| l.bids.len()).max();
+    let asks_count = book_levels.iter().map(|l| l.asks.len()).max();
 
-    let mut bids = Vec::<Level>::with_capacity(levels_count);
-    let mut asks = Vec::<Level>::with_capacity(levels_count);
-    for i in 0..exchange_count {
-        bids.append(&mut book_levels_vec[i].bids);
-        asks.append(&mut book_levels_vec[i].asks);
-    }
-    bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-    asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-
-    let take_bids = bids.into_iter().take(levels_count).collect::<Vec<Level>>();
-    let take_asks = asks.into_iter().take(levels_count).collect::<Vec<Level>>();
-    let summary = Summary {
+    let take_bids = merge_levels(
+        book_levels.iter().map(|l| l.bids.as_slice()),
+        bids_count.unwrap_or_default(),
+        |a, b| a > b,
+    );
+    let take_asks = merge_levels(
+        book_levels.iter().map(|l| l.asks.as_slice()),
+        asks_count.unwrap_or_default(),
+        |a, b| a < b,
+    );
+    let best_bid = take_bids.first().context("no bids to summarize")?.price;
+    let best_ask = take_asks.first().context("no asks to summarize")?.price;
+    Ok(Summary {
         symbol: symbol.to_string(),
-        spread: take_asks[0].price - take_bids[0].price,
+        spread: best_ask - best_bid,
         timestamp: chrono::Utc::now().timestamp() as u64,
         bids: take_bids,
         asks: take_asks,
-    };
-    summary
+        ..Default::default()
+    })
+}
+
+/// Takes the best `count` levels from lists that are each sorted best first, only cloning
+/// the levels that are taken. Ties go to the list that comes first.
+fn merge_levels<'a>(
+    lists: impl Iterator<Item = &'a [Level]>,
+    count: usize,
+    better: fn(f64, f64) -> bool,
+) -> Vec<Level> {
+    let mut lists = lists.collect::<Vec<&[Level]>>();
+    let mut merged = Vec::with_capacity(count);
+    while merged.len() < count {
+        let mut best: Option<usize> = None;
+        for (i, list) in lists.iter().enumerate() {
+            if let Some(level) = list.first() {
+                if best.is_none_or(|b| better(level.price, lists[b][0].price)) {
+                    best = Some(i);
+                }
+            }
+        }
+        let Some(i) = best else {
+            break;
+        };
+        merged.push(lists[i][0].clone());
+        lists[i] = &lists[i][1..];
+    }
+    merged
 }
@@ -0,0 +1,302 @@
+//! The quantity resting near the mid of a symbol, reported on each summary for the bands
+//! clients ask for.
+//!
+//! The summary task works it out from the [Depth](crate::core::order_book::Depth) each
+//! exchange's book reports rather than the summary's levels, which only reach as far as the
+//! top few. The bands of each `WatchSummary` client are registered on [LiquidityBands]
+//! while its stream is open, on top of [DEFAULT_BPS] which are always reported.
+use anyhow::{ensure, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    book_summary::{Level, Liquidity},
+    core::order_book::BookLevels,
+};
+
+/// The bands reported to clients that don't ask for any, in basis points from the mid
+pub const DEFAULT_BPS: [f64; 3] = [5.0, 10.0, 25.0];
+
+/// The widest band a client can ask for. The books' depth reaches further from their own
+/// best prices, which are half the spread or more from the mid.
+pub const MAX_BPS: f64 = 50.0;
+
+/// Fails unless each of `bps` is above 0 and at most [MAX_BPS]
+pub fn validate_bps(bps: &[f64]) -> Result<()> {
+    for &bps in bps {
+        ensure!(
+            bps > 0.0 && bps <= MAX_BPS,
+            "liquidity_bps must be above 0 and at most {}",
+            MAX_BPS
+        );
+    }
+    Ok(())
+}
+
+/// The quantity within each of `bps` of `mid` on each side of `book_levels`. Books without
+/// depth only count their top levels, and are truncated past the last of them.
+pub fn liquidity(book_levels: &[&BookLevels], mid: f64, bps: &[f64]) -> Vec<Liquidity> {
+    bps.iter()
+        .map(|&bps| {
+            let bid_edge = mid - mid * bps / 10_000.0;
+            let ask_edge = mid + mid * bps / 10_000.0;
+            let mut liquidity = Liquidity {
+                bps,
+                ..Default::default()
+            };
+            for levels in book_levels {
+                let (bid_quantity, bid_floor, ask_quantity, ask_ceiling) = match &levels.depth {
+                    Some(depth) => (
+                        within(depth.bids(), |price| price >= bid_edge),
+                        depth.bid_floor(),
+                        within(depth.asks(), |price| price <= ask_edge),
+                        depth.ask_ceiling(),
+                    ),
+                    None => {
+                        let amounts = |level: &Level| [level.price, level.quantity];
+                        (
+                            within(levels.bids.iter().map(amounts), |price| price >= bid_edge),
+                            levels
+                                .bids
+                                .last()
+                                .map_or(f64::INFINITY, |level| level.price),
+                            within(levels.asks.iter().map(amounts), |price| price <= ask_edge),
+                            levels.asks.last().map_or(0.0, |level| level.price),
+                        )
+                    }
+                };
+                liquidity.bid_quantity += bid_quantity;
+                liquidity.ask_quantity += ask_quantity;
+                liquidity.truncated |= bid_floor > bid_edge || ask_ceiling < ask_edge;
+            }
+            liquidity
+        })
+        .collect()
+}
+
+/// The quantity of the levels, best first, until the first price outside the band
+fn within(levels: impl Iterator<Item = [f64; 2]>, inside: impl Fn(f64) -> bool) -> f64 {
+    levels
+        .take_while(|&[price, _]| inside(price))
+        .map(|[_, quantity]| quantity)
+        .sum()
+}
+
+/// The bands reported on each summary, [DEFAULT_BPS] and those of the clients watching now.
+/// Clones share the same bands.
+#[derive(Debug, Clone, Default)]
+pub struct LiquidityBands {
+    // Clients watching each band, keyed by its bits, which sort like the bands do as
+    // they're all positive.
+    watched: Arc<Mutex<BTreeMap<u64, usize>>>,
+}
+
+impl LiquidityBands {
+    /// Reports `bps` on the summaries made until the guard returned is dropped
+    pub fn watch(&self, bps: &[f64]) -> WatchedBands {
+        let bits = bps.iter().map(|bps| bps.to_bits()).collect::<Vec<_>>();
+        let mut watched = self.watched.lock().unwrap();
+        for &bits in bits.iter() {
+            *watched.entry(bits).or_default() += 1;
+        }
+        WatchedBands {
+            bands: self.clone(),
+            bits,
+        }
+    }
+
+    /// The bands to report, narrowest first
+    pub fn bps(&self) -> Vec<f64> {
+        let watched = self.watched.lock().unwrap();
+        DEFAULT_BPS
+            .iter()
+            .map(|bps| bps.to_bits())
+            .chain(watched.keys().copied())
+            .collect::<BTreeSet<u64>>()
+            .into_iter()
+            .map(f64::from_bits)
+            .collect()
+    }
+}
+
+/// Stops reporting a client's bands once dropped, unless other clients watch them too
+#[derive(Debug)]
+pub struct WatchedBands {
+    bands: LiquidityBands,
+    bits: Vec<u64>,
+}
+
+impl Drop for WatchedBands {
+    fn drop(&mut self) {
+        let mut watched = self.bands.watched.lock().unwrap();
+        for bits in self.bits.iter() {
+            if let Some(count) = watched.get_mut(bits) {
+                *count -= 1;
+                if *count == 0 {
+                    watched.remove(bits);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            num_types::DisplayAmount,
+            order_book::{OrderBook, Update},
+        },
+        Exchange, Symbol,
+    };
+    use rust_decimal::Decimal;
+
+    #[derive(Debug)]
+    struct Snapshot {
+        bids: Vec<[Decimal; 2]>,
+        asks: Vec<[Decimal; 2]>,
+    }
+
+    impl Update for Snapshot {
+        fn validate(&self, _: u64) -> Result<()> {
+            Ok(())
+        }
+        fn last_update_id(&self) -> u64 {
+            1
+        }
+        fn bids_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.bids
+        }
+        fn asks_mut(&mut self) -> &mut Vec<[DisplayAmount; 2]> {
+            &mut self.asks
+        }
+    }
+
+    /// A book priced in whole units between `min` and `max` holding `bids` and `asks`
+    fn book(min: u64, max: u64, bids: &[(i64, i64)], asks: &[(i64, i64)]) -> BookLevels {
+        let levels = |levels: &[(i64, i64)]| {
+            levels
+                .iter()
+                .map(|&(price, quantity)| [Decimal::new(price, 0), Decimal::new(quantity, 0)])
+                .collect()
+        };
+        let mut ob = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, min, max, 0, 0);
+        ob.update(&mut Snapshot {
+            bids: levels(bids),
+            asks: levels(asks),
+        })
+        .unwrap();
+        ob.get_book_levels(2).unwrap()
+    }
+
+    /// The bps, quantities and truncation of each band
+    fn sums(liquidity: &[Liquidity]) -> Vec<(f64, f64, f64, bool)> {
+        liquidity
+            .iter()
+            .map(|l| (l.bps, l.bid_quantity, l.ask_quantity, l.truncated))
+            .collect()
+    }
+
+    #[test]
+    fn it_sums_the_quantity_within_each_band() {
+        // With a mid of 10000 the bands reach 9995/10005, 9990/10010 and 9975/10025.
+        let levels = book(
+            9000,
+            11000,
+            &[
+                (9999, 1),
+                (9995, 2),
+                (9991, 3),
+                (9990, 4),
+                (9980, 5),
+                (9960, 6),
+            ],
+            &[(10001, 1), (10004, 2), (10006, 3), (10025, 4), (10026, 5)],
+        );
+        // The depth reaches past the two top levels.
+        assert_eq!(levels.bids.len(), 2);
+        assert_eq!(
+            sums(&liquidity(&[&levels], 10000.0, &DEFAULT_BPS)),
+            [
+                (5.0, 3.0, 3.0, false),
+                (10.0, 10.0, 6.0, false),
+                (25.0, 15.0, 10.0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_adds_up_the_exchanges() {
+        let bitstamp = book(9000, 11000, &[(9999, 1), (9990, 2)], &[(10001, 1)]);
+        let mut binance = book(9000, 11000, &[(9998, 3)], &[(10002, 2), (10008, 4)]);
+        binance.exchange = Exchange::BINANCE;
+        assert_eq!(
+            sums(&liquidity(&[&bitstamp, &binance], 10000.0, &[5.0, 10.0])),
+            [(5.0, 4.0, 3.0, false), (10.0, 6.0, 7.0, false)]
+        );
+    }
+
+    #[test]
+    fn it_flags_bands_past_the_price_range_as_truncated() {
+        // The book stops at 9980 below and 10010 above, inside the 25bps band.
+        let levels = book(
+            9980,
+            10010,
+            &[(9999, 1), (9985, 2)],
+            &[(10001, 1), (10009, 2)],
+        );
+        assert_eq!(
+            sums(&liquidity(&[&levels], 10000.0, &DEFAULT_BPS)),
+            [
+                (5.0, 1.0, 1.0, false),
+                (10.0, 1.0, 3.0, false),
+                (25.0, 3.0, 3.0, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_only_counts_the_top_levels_of_books_without_depth() {
+        let levels = BookLevels {
+            depth: None,
+            ..book(
+                9000,
+                11000,
+                &[(9999, 1), (9995, 2), (9980, 5)],
+                &[(10001, 1), (10005, 2), (10008, 3)],
+            )
+        };
+        // The top two levels reach 9995 and 10005, so only the 5bps band is known in full.
+        assert_eq!(
+            sums(&liquidity(&[&levels], 10000.0, &[5.0, 10.0])),
+            [(5.0, 3.0, 3.0, false), (10.0, 3.0, 3.0, true)]
+        );
+    }
+
+    #[test]
+    fn it_reports_the_bands_clients_watch() {
+        let bands = LiquidityBands::default();
+        assert_eq!(bands.bps(), DEFAULT_BPS);
+        let first = bands.watch(&[2.5, 10.0]);
+        let second = bands.watch(&[2.5, 50.0]);
+        assert_eq!(bands.bps(), [2.5, 5.0, 10.0, 25.0, 50.0]);
+        drop(first);
+        assert_eq!(bands.bps(), [2.5, 5.0, 10.0, 25.0, 50.0]);
+        drop(second);
+        assert_eq!(bands.bps(), DEFAULT_BPS);
+    }
+
+    #[test]
+    fn it_validates_the_bands() {
+        assert!(validate_bps(&[0.5, 50.0]).is_ok());
+        for bps in [0.0, -5.0, 50.5, f64::NAN] {
+            assert_eq!(
+                validate_bps(&[bps]).unwrap_err().to_string(),
+                "liquidity_bps must be above 0 and at most 50"
+            );
+        }
+    }
+}
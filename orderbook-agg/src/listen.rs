@@ -0,0 +1,80 @@
+//! Binds every address given with `--listen`, so the same server can be reached on an
+//! IPv6 and an IPv4 address at once.
+//!
+//! All of them are bound before anything is served, and the first that can't be fails
+//! startup naming it. An IPv6 wildcard like `[::]:9001` takes IPv4 clients too where the
+//! host allows it, unless an IPv4 address on the same port is listed as well, in which case
+//! it's left to that one.
+use anyhow::{anyhow, Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+
+/// Connections waiting to be accepted before the host refuses more
+const BACKLOG: i32 = 1024;
+
+/// Binds each of `addrs`, in order
+pub fn bind(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            let only_v6 = addr.is_ipv6()
+                && addrs
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == addr.port());
+            bind_one(*addr, only_v6).with_context(|| format!("failed to listen on {}", addr))
+        })
+        .collect()
+}
+
+fn bind_one(addr: SocketAddr, only_v6: bool) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    // As tokio's own bind does, so a restarted server can take the port straight back.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// The connections accepted on `listener`, for
+/// [Server::serve_with_incoming](tonic::transport::Server)
+pub fn incoming(listener: TcpListener) -> Result<TcpIncoming> {
+    TcpIncoming::from_listener(listener, false, None).map_err(|err| anyhow!(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_names_the_address_it_cant_bind() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let free = "127.0.0.1:0".parse().unwrap();
+        let err = bind(&[free, addr]).unwrap_err();
+        assert_eq!(err.to_string(), format!("failed to listen on {}", addr));
+    }
+
+    #[tokio::test]
+    async fn it_leaves_ipv4_clients_to_an_ipv4_address_on_the_same_port() {
+        // Hosts without IPv6 can't run this.
+        if std::net::TcpListener::bind("[::]:0").is_err() {
+            return;
+        }
+        let v4 = bind(&["0.0.0.0:0".parse().unwrap()]).unwrap();
+        let port = v4[0].local_addr().unwrap().port();
+        drop(v4);
+        let addrs = [
+            format!("0.0.0.0:{}", port).parse().unwrap(),
+            format!("[::]:{}", port).parse().unwrap(),
+        ];
+        let listeners = bind(&addrs).unwrap();
+        assert_eq!(listeners.len(), 2);
+    }
+}
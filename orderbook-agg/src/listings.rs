@@ -0,0 +1,194 @@
+//! Checks now and then that each exchange still lists the symbols streamed from it.
+//!
+//! An exchange delisting a pair mid-stream goes quiet or fails every reconnect, which
+//! would leave clients watching the symbol waiting forever. While an exchange is enabled
+//! its listing is fetched again every
+//! [listing_refresh](crate::service::ExchangeOptions::listing_refresh). One that has
+//! dropped the symbol stops streaming it for good and is reported
+//! [delisted](crate::service::ExchangeClosed::delisted) to the summary task, which leaves
+//! it out of the summaries and lists it on their `delisted_exchanges`. Once every enabled
+//! exchange has delisted the symbol, its summaries end with [Status::not_found] and
+//! requests for it are turned away.
+//!
+//! A listing that can't be fetched is only logged, the next refresh tries again.
+use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tonic::Status;
+use tracing::Instrument;
+
+use crate::{service::ExchangeClosed, Exchange, Symbol};
+
+/// How often the exchanges' listings are fetched again unless set otherwise
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The status summaries of a symbol every enabled exchange has delisted end with, and
+/// requests for it are refused with
+pub fn delisted() -> Status {
+    Status::not_found("symbol delisted")
+}
+
+/// The exchanges that have delisted each symbol, and the symbols no enabled exchange
+/// lists any more. Clones share the same listings.
+#[derive(Debug, Clone, Default)]
+pub struct Listings {
+    inner: Arc<Mutex<Delisted>>,
+}
+
+#[derive(Debug, Default)]
+struct Delisted {
+    exchanges: HashMap<Symbol, HashSet<Exchange>>,
+    symbols: HashSet<Symbol>,
+}
+
+impl Listings {
+    pub fn delist(&self, exchange: Exchange, symbol: Symbol) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.exchanges.entry(symbol).or_default().insert(exchange);
+    }
+
+    /// The exchanges that have stopped listing `symbol`
+    pub fn delisted_exchanges(&self, symbol: Symbol) -> HashSet<Exchange> {
+        let inner = self.inner.lock().unwrap();
+        inner.exchanges.get(&symbol).cloned().unwrap_or_default()
+    }
+
+    /// Refuses requests for `symbol` from now on
+    pub fn withdraw(&self, symbol: Symbol) {
+        self.inner.lock().unwrap().symbols.insert(symbol);
+    }
+
+    pub fn is_withdrawn(&self, symbol: Symbol) -> bool {
+        self.inner.lock().unwrap().symbols.contains(&symbol)
+    }
+}
+
+/// Spawns the task that calls `fetch_listed` every `interval` while `rx_enabled` is true,
+/// so disabled exchanges aren't contacted for it either. Once it returns false, `feed`,
+/// the task streaming the exchange's book, is aborted and the exchange reported delisted
+/// on `tx_closed`. Stops then, or once the summary task has gone away.
+pub fn spawn_listing_check<F, Fut>(
+    exchange: Exchange,
+    symbol: Symbol,
+    interval: Duration,
+    rx_enabled: watch::Receiver<bool>,
+    feed: JoinHandle<()>,
+    tx_closed: mpsc::Sender<ExchangeClosed>,
+    fetch_listed: F,
+) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<bool>> + Send,
+{
+    tokio::spawn(
+        async move {
+            let start = tokio::time::Instant::now() + interval;
+            let mut ticks = tokio::time::interval_at(start, interval);
+            loop {
+                select! {
+                    _ = ticks.tick() => {},
+                    _ = tx_closed.closed() => return,
+                }
+                if !*rx_enabled.borrow() {
+                    continue;
+                }
+                match fetch_listed().await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        tracing::warn!("{} no longer lists {}", exchange, symbol);
+                        feed.abort();
+                        let closed = ExchangeClosed {
+                            exchange,
+                            reason: "delisted".to_string(),
+                            lost: true,
+                            disabled: false,
+                            delisted: true,
+                        };
+                        let _ = tx_closed.send(closed).await;
+                        return;
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed to refresh {} listing: {:#}", exchange, err)
+                    }
+                }
+            }
+        }
+        .instrument(tracing::info_span!("listing", %exchange, %symbol)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn it_stops_the_feed_once_the_symbol_is_delisted() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let (tx_enabled, rx_enabled) = watch::channel(false);
+        let (tx_closed, mut rx_closed) = mpsc::channel(1);
+        let streaming = Arc::new(());
+        let held = streaming.clone();
+        let feed = tokio::spawn(async move {
+            let _held = held;
+            futures::future::pending::<()>().await
+        });
+        let checked = checks.clone();
+        let check = spawn_listing_check(
+            Exchange::BITSTAMP,
+            Symbol::BTCUSDT,
+            Duration::from_millis(10),
+            rx_enabled,
+            feed,
+            tx_closed,
+            move || {
+                let checks = checked.clone();
+                // Listed for the first two checks, failing on the third.
+                async move {
+                    match checks.fetch_add(1, Ordering::SeqCst) {
+                        0 | 1 => Ok(true),
+                        2 => anyhow::bail!("connection refused"),
+                        _ => Ok(false),
+                    }
+                }
+            },
+        );
+
+        // Disabled exchanges aren't checked.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(checks.load(Ordering::SeqCst), 0);
+
+        tx_enabled.send_replace(true);
+        let closed = rx_closed.recv().await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 4);
+        assert!(closed.delisted && closed.lost && !closed.disabled);
+        assert_eq!(closed.reason, "delisted");
+        check.await.unwrap();
+        // The feed was aborted, dropping what it held.
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&streaming), 1);
+    }
+
+    #[test]
+    fn it_records_the_delisted_exchanges_and_symbols() {
+        let listings = Listings::default();
+        listings.delist(Exchange::BINANCE, Symbol::BTCUSDT);
+        assert_eq!(
+            listings.delisted_exchanges(Symbol::BTCUSDT),
+            HashSet::from([Exchange::BINANCE])
+        );
+        assert!(listings.delisted_exchanges(Symbol::ETHBTC).is_empty());
+        assert!(!listings.clone().is_withdrawn(Symbol::BTCUSDT));
+        listings.clone().withdraw(Symbol::BTCUSDT);
+        assert!(listings.is_withdrawn(Symbol::BTCUSDT));
+    }
+}
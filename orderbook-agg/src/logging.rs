@@ -0,0 +1,397 @@
+//! Sets up logging for the binaries, filtered by `RUST_LOG` style directives and written
+//! pretty for people or as json lines for log aggregation.
+//!
+//! Json lines always have `timestamp`, `level`, `target` and `message`, along with the
+//! fields of the event and of each span it's in. The exchange tasks are in spans with
+//! `exchange` and `symbol` fields, the summary tasks in spans with `symbol`, and each
+//! `watch_summary` stream in a span with a `request_id`, and the `trace_id` and
+//! `parent_id` of the client's `traceparent` when it sends one.
+//!
+//! With the `otel` feature, the spans can also be exported to an OpenTelemetry collector
+//! over OTLP, the streams' spans as children of the client's spans. Without it, nothing
+//! is added to the subscriber.
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::io::Write;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::{Context as LayerContext, SubscriberExt},
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown log format {}, expected json or pretty", format),
+        }
+    }
+}
+
+/// The filter from `directives`, or `RUST_LOG` when not given, or info level for
+/// everything when neither is set.
+pub fn filter(directives: Option<&str>) -> Result<EnvFilter> {
+    match directives {
+        Some(directives) => EnvFilter::try_new(directives)
+            .with_context(|| format!("invalid log filter {}", directives)),
+        None => match std::env::var(EnvFilter::DEFAULT_ENV) {
+            Ok(directives) => EnvFilter::try_new(&directives)
+                .with_context(|| format!("invalid RUST_LOG {}", directives)),
+            Err(_) => Ok(EnvFilter::new("info")),
+        },
+    }
+}
+
+/// Installs the global subscriber, writing to stdout and exporting spans to the OTLP
+/// collector at `otlp_endpoint` when given, e.g. `http://localhost:4317`. The filter can be
+/// changed afterwards with the handle returned.
+pub fn init(
+    format: LogFormat,
+    directives: Option<&str>,
+    otlp_endpoint: Option<&str>,
+) -> Result<LogHandle> {
+    let (filter, handle) = reload::Layer::new(filter(directives)?);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(otlp_layer(otlp_endpoint)?);
+    match format {
+        LogFormat::Pretty => registry
+            .with(tracing_subscriber::fmt::layer().with_line_number(true))
+            .try_init(),
+        LogFormat::Json => registry.with(JsonLayer::new(std::io::stdout)).try_init(),
+    }
+    .context("failed to set the global subscriber")?;
+    Ok(LogHandle(handle))
+}
+
+/// Exports spans to an OTLP collector
+#[cfg(feature = "otel")]
+type OtlpLayer<S> = tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>;
+
+#[cfg(feature = "otel")]
+fn otlp_layer<S>(endpoint: Option<&str>) -> Result<Option<OtlpLayer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry_otlp::WithExportConfig;
+
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+    );
+    let resource = opentelemetry_sdk::Resource::new([opentelemetry::KeyValue::new(
+        "service.name",
+        env!("CARGO_PKG_NAME"),
+    )]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(resource))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .with_context(|| format!("failed to export spans to {}", endpoint))?;
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+#[cfg(not(feature = "otel"))]
+fn otlp_layer(endpoint: Option<&str>) -> Result<Option<tracing_subscriber::layer::Identity>> {
+    if endpoint.is_some() {
+        bail!("--otlp-endpoint needs the server built with the otel feature");
+    }
+    Ok(None)
+}
+
+/// Makes `span` a child of the client's span in the exported traces
+#[cfg(feature = "otel")]
+pub fn follow(span: &tracing::Span, parent: &TraceParent) {
+    use opentelemetry::propagation::TextMapPropagator;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let headers =
+        std::collections::HashMap::from([("traceparent".to_string(), parent.to_string())]);
+    let context =
+        opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&headers));
+    span.set_parent(context);
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn follow(_: &tracing::Span, _: &TraceParent) {}
+
+/// Exports the spans not yet sent, before the process exits
+#[cfg(feature = "otel")]
+pub fn flush_spans() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn flush_spans() {}
+
+/// Changes the filter of the subscriber [init] installed
+#[derive(Debug, Clone)]
+pub struct LogHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogHandle {
+    pub fn set_filter(&self, filter: EnvFilter) -> Result<()> {
+        self.0
+            .reload(filter)
+            .context("failed to change the log filter")
+    }
+}
+
+/// The W3C `traceparent` a client sent with a request, recorded on the rpc's span so its
+/// logs can be matched up with the client's trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub sampled: bool,
+}
+
+impl std::str::FromStr for TraceParent {
+    type Err = anyhow::Error;
+
+    /// Parses `version-trace_id-parent_id-flags`, e.g.
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    fn from_str(header: &str) -> Result<Self> {
+        let parts = header.trim().split('-').collect::<Vec<_>>();
+        let hex = |part: &str, len: usize| {
+            part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        let zero = |part: &str| part.bytes().all(|b| b == b'0');
+        match parts[..] {
+            [version, trace_id, parent_id, flags]
+                if hex(version, 2)
+                    && version != "ff"
+                    && hex(trace_id, 32)
+                    && !zero(trace_id)
+                    && hex(parent_id, 16)
+                    && !zero(parent_id)
+                    && hex(flags, 2) =>
+            {
+                Ok(Self {
+                    trace_id: trace_id.to_string(),
+                    parent_id: parent_id.to_string(),
+                    sampled: u8::from_str_radix(flags, 16)? & 1 == 1,
+                })
+            }
+            _ => bail!("invalid traceparent {}", header),
+        }
+    }
+}
+
+impl std::fmt::Display for TraceParent {
+    /// The `traceparent` header, at version 00
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.parent_id, self.sampled as u8
+        )
+    }
+}
+
+/// Writes each event as a line of json
+pub struct JsonLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W>
+where
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    pub fn new(make_writer: W) -> Self {
+        Self { make_writer }
+    }
+}
+
+/// The fields recorded on a span or event
+#[derive(Debug, Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: LayerContext<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        let mut line = Map::new();
+        // Inner spans' fields replace outer ones of the same name, and the event's
+        // replace them all.
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<Fields>() {
+                    line.extend(fields.0.clone());
+                }
+            }
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        line.extend(fields.0);
+        let metadata = event.metadata();
+        line.insert(
+            "timestamp".to_string(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".to_string(), metadata.level().as_str().into());
+        line.insert("target".to_string(), metadata.target().into());
+
+        let Ok(mut bytes) = serde_json::to_vec(&line) else {
+            return;
+        };
+        bytes.push(b'\n');
+        let _ = self.make_writer.make_writer_for(metadata).write_all(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_writes_events_with_their_spans_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(EnvFilter::new("debug,noisy=warn"))
+            .with(JsonLayer::new(move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("exchange", exchange = "BITSTAMP", symbol = "BTCUSDT");
+            let _entered = span.enter();
+            let inner = tracing::info_span!("request", request_id = 7u64);
+            let _entered = inner.enter();
+            tracing::info!(levels = 2, "sent {} levels", 2);
+            tracing::info!(target: "noisy", "left out");
+        });
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1, "{}", written);
+        let line: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "orderbook_agg::logging::tests");
+        assert_eq!(line["message"], "sent 2 levels");
+        assert_eq!(line["levels"], 2);
+        assert_eq!(line["exchange"], "BITSTAMP");
+        assert_eq!(line["symbol"], "BTCUSDT");
+        assert_eq!(line["request_id"], 7);
+        let timestamp = line["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    }
+
+    #[test]
+    fn it_parses_trace_parents() {
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse::<TraceParent>()
+            .unwrap();
+        assert_eq!(parent.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parent.parent_id, "00f067aa0ba902b7");
+        assert!(parent.sampled);
+        assert_eq!(
+            parent.to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        for header in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            assert!(header.parse::<TraceParent>().is_err(), "{}", header);
+        }
+    }
+
+    #[test]
+    fn it_parses_filters_and_formats() {
+        assert!(filter(Some("info,orderbook_agg::exchanges::bitstamp=debug")).is_ok());
+        assert!(filter(Some("info,=nonsense=")).is_err());
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+        #[cfg(not(feature = "otel"))]
+        assert!(otlp_layer(Some("http://localhost:4317")).is_err());
+    }
+}
@@ -1,12 +1,9 @@
 use anyhow::Result;
+use orderbook_agg::logging::{self, LogFormat};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let subscriber = tracing_subscriber::fmt()
-        .with_line_number(true)
-        .with_max_level(tracing::Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    logging::init(LogFormat::Pretty, None, None)?;
 
     tracing::info!("done");
     Ok(())
@@ -0,0 +1,740 @@
+//! Counters from each stage of the pipeline, from the messages each exchange sends to the
+//! summaries sent to clients, read by the `GetStats` rpc and served to Prometheus.
+//!
+//! The counters are atomics updated where the work is done. Taking a snapshot only locks
+//! the maps of feeds and symbols, which are added to when a book or summary task starts,
+//! never while updates are applied.
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::Instant,
+};
+use tonic::Status;
+
+use crate::{
+    backfill::Backfill,
+    book_summary::{ExchangeStatus, FeedStats, Stats, SymbolStats, TappedUpdate},
+    core::stats::{counters, state_name, CounterKind, CounterValue, ExchangeStats, FeedCounters},
+    service::SummaryReceiver,
+    sinks::Fanout,
+    Exchange, Symbol,
+};
+
+counters! {
+    /// Counted from the summaries published for a symbol
+    pub struct SymbolCounters {
+        /// Summaries published
+        counter summaries,
+        /// Times the summary became unavailable because every exchange was
+        counter unavailable,
+        /// Milliseconds since the unix epoch the last summary was published at
+        gauge last_summary_ms,
+        /// Exchanges missing from the last summary
+        gauge exchanges_unavailable,
+        /// Bid levels in the last summary
+        gauge bid_levels,
+        /// Ask levels in the last summary
+        gauge ask_levels,
+        /// Client streams and sinks subscribed to the summaries now
+        gauge subscribers,
+    }
+}
+
+counters! {
+    /// Counted across the whole server
+    pub struct ServerCounters {
+        /// Summary streams opened by clients
+        counter streams_opened,
+        /// Summary streams open now
+        gauge streams_active,
+        /// Summaries sent to clients
+        counter summaries_sent,
+        /// Summaries clients didn't get because they were behind, coalesced into the next
+        counter summaries_coalesced,
+        /// Batches the summaries of `WatchSummaryBatches` streams were sent in
+        counter summary_batches_sent,
+        /// Summary tasks started for the first client to watch their symbol
+        counter pipelines_started,
+        /// Summary tasks stopped once nothing had watched their symbol for the linger
+        counter pipelines_stopped,
+        /// Updates `TapUpdates` streams were behind on and dropped
+        counter tapped_updates_dropped,
+    }
+}
+
+/// Changes of state kept for status streams that are behind, more than every connection
+/// makes in a reconnect
+const TRANSITIONS_CAPACITY: usize = 256;
+
+/// Updates kept for taps that are behind, before the oldest are dropped
+const TAPS_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+struct Inner {
+    started: Instant,
+    feeds: Mutex<HashMap<(Exchange, Symbol), Arc<ExchangeStats>>>,
+    /// Each change of state of the feeds' connections
+    transitions: broadcast::Sender<ExchangeStatus>,
+    /// Each update applied to the feeds' order books while anything taps them
+    taps: broadcast::Sender<Arc<TappedUpdate>>,
+    /// How many taps want each symbol's updates, shared with the symbol's feeds
+    tappers: Mutex<HashMap<Symbol, Arc<AtomicUsize>>>,
+    symbols: Mutex<HashMap<Symbol, Arc<SymbolCounters>>>,
+    server: ServerCounters,
+    sinks: Mutex<Option<Fanout>>,
+    backfill: Mutex<Option<Backfill>>,
+    /// When the server's TLS certificate expires, in seconds since the unix epoch
+    tls_not_after: Mutex<Option<u64>>,
+}
+
+/// The counters of every feed and symbol on the server. Clones share the same counters.
+#[derive(Debug, Clone)]
+pub struct PipelineStats {
+    inner: Arc<Inner>,
+}
+
+impl Default for PipelineStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineStats {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                started: Instant::now(),
+                feeds: Mutex::default(),
+                transitions: broadcast::channel(TRANSITIONS_CAPACITY).0,
+                taps: broadcast::channel(TAPS_CAPACITY).0,
+                tappers: Mutex::default(),
+                symbols: Mutex::default(),
+                server: ServerCounters::default(),
+                sinks: Mutex::default(),
+                backfill: Mutex::default(),
+                tls_not_after: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The stats for the order books of `exchange` and `symbol`, the same each time so they
+    /// add up across reconnects. Their connection's changes of state are published to
+    /// [exchange_statuses](Self::exchange_statuses), and their updates to
+    /// [taps](Self::taps).
+    pub fn feed(&self, exchange: Exchange, symbol: Symbol) -> Arc<ExchangeStats> {
+        let mut feeds = self.inner.feeds.lock().unwrap();
+        feeds
+            .entry((exchange, symbol))
+            .or_insert_with(|| {
+                let stats = ExchangeStats::new(exchange, symbol)
+                    .with_transitions(self.inner.transitions.clone())
+                    .with_taps(self.inner.taps.clone(), self.tappers(symbol));
+                Arc::new(stats)
+            })
+            .clone()
+    }
+
+    /// The status of each feed's connection for `symbol`, or every symbol when it's
+    /// `None`, with a receiver of every change of state from just before they were read.
+    pub fn exchange_statuses(
+        &self,
+        symbol: Option<Symbol>,
+    ) -> (Vec<ExchangeStatus>, broadcast::Receiver<ExchangeStatus>) {
+        let rx_transitions = self.inner.transitions.subscribe();
+        let statuses = self
+            .feeds()
+            .into_iter()
+            .filter(|(_, feed_symbol, _)| symbol.is_none_or(|symbol| symbol == *feed_symbol))
+            .map(|(_, _, stats)| stats.status())
+            .collect();
+        (statuses, rx_transitions)
+    }
+
+    /// A tap of the updates applied to the order books of `symbol` from now on. The feeds
+    /// of the symbol only copy their updates out while it's held.
+    pub fn tap(&self, symbol: Symbol) -> Tap {
+        let tappers = self.tappers(symbol);
+        tappers.fetch_add(1, Ordering::Relaxed);
+        Tap {
+            rx_taps: self.inner.taps.subscribe(),
+            tappers,
+        }
+    }
+
+    /// The times the feeds of `symbol` have rebuilt their books from a fresh snapshot
+    pub fn resyncs(&self, symbol: Symbol) -> u64 {
+        self.feeds()
+            .into_iter()
+            .filter(|(_, feed_symbol, _)| *feed_symbol == symbol)
+            .map(|(_, _, stats)| stats.resyncs())
+            .sum()
+    }
+
+    fn tappers(&self, symbol: Symbol) -> Arc<AtomicUsize> {
+        let mut tappers = self.inner.tappers.lock().unwrap();
+        tappers.entry(symbol).or_default().clone()
+    }
+
+    pub fn symbol(&self, symbol: Symbol) -> Arc<SymbolCounters> {
+        let mut symbols = self.inner.symbols.lock().unwrap();
+        symbols.entry(symbol).or_default().clone()
+    }
+
+    pub fn server(&self) -> &ServerCounters {
+        &self.inner.server
+    }
+
+    /// Reports the summaries and status changes the sinks of `fanout` drop.
+    pub fn set_sinks(&self, fanout: Fanout) {
+        *self.inner.sinks.lock().unwrap() = Some(fanout);
+    }
+
+    /// Reports the summaries `backfill` keeps across every symbol.
+    pub fn set_backfill(&self, backfill: Backfill) {
+        *self.inner.backfill.lock().unwrap() = Some(backfill);
+    }
+
+    /// Reports the days left before the server's TLS certificate expires, at `not_after`
+    /// seconds since the unix epoch.
+    pub fn set_tls_cert_expiry(&self, not_after: u64) {
+        *self.inner.tls_not_after.lock().unwrap() = Some(not_after);
+    }
+
+    /// Counts the summaries published on `rx_summary` for `symbol` until it closes.
+    pub fn watch_symbol(&self, symbol: Symbol, mut rx_summary: SummaryReceiver) -> JoinHandle<()> {
+        let counters = self.symbol(symbol);
+        tokio::spawn(async move {
+            loop {
+                let summary = rx_summary.borrow_and_update().clone();
+                match summary {
+                    // The placeholder published before the first summary isn't counted.
+                    Ok(summary) if summary.sequence == 0 => {}
+                    Ok(summary) => {
+                        counters.summaries.set(summary.sequence);
+                        counters.last_summary_ms.set(now_ms());
+                        counters
+                            .exchanges_unavailable
+                            .set(summary.unavailable_exchanges.len() as u64);
+                        counters.bid_levels.set(summary.bids.len() as u64);
+                        counters.ask_levels.set(summary.asks.len() as u64);
+                    }
+                    Err(_) => counters.unavailable.incr(),
+                }
+                if rx_summary.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// The server wide values, adding to the counters those read from elsewhere
+    fn server_values(&self) -> Vec<CounterValue> {
+        let mut values = self.inner.server.values();
+        if let Some(fanout) = &*self.inner.sinks.lock().unwrap() {
+            values.push(CounterValue {
+                name: "sink_drops",
+                help: "Summaries and status changes dropped by sinks that were behind",
+                kind: CounterKind::Counter,
+                value: fanout.dropped(),
+            });
+        }
+        if let Some(backfill) = &*self.inner.backfill.lock().unwrap() {
+            values.push(CounterValue {
+                name: "backfill_summaries",
+                help: "Summaries kept across the symbols to backfill new summary streams with",
+                kind: CounterKind::Gauge,
+                value: backfill.kept() as u64,
+            });
+        }
+        if let Some(not_after) = *self.inner.tls_not_after.lock().unwrap() {
+            values.push(CounterValue {
+                name: "tls_cert_expiry_days",
+                help: "Whole days until the server's TLS certificate expires, 0 once it has",
+                kind: CounterKind::Gauge,
+                value: not_after.saturating_sub(now_ms() / 1000) / 86_400,
+            });
+        }
+        values
+    }
+
+    fn feeds(&self) -> Vec<(Exchange, Symbol, Arc<ExchangeStats>)> {
+        let mut feeds = self
+            .inner
+            .feeds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(exchange, symbol), stats)| (exchange, symbol, stats.clone()))
+            .collect::<Vec<_>>();
+        feeds.sort_by_key(|(exchange, symbol, _)| (exchange.to_string(), symbol.to_string()));
+        feeds
+    }
+
+    fn symbols(&self) -> Vec<(Symbol, Arc<SymbolCounters>)> {
+        let mut symbols = self
+            .inner
+            .symbols
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&symbol, counters)| (symbol, counters.clone()))
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|(symbol, _)| symbol.to_string());
+        symbols
+    }
+
+    /// Reads every counter, as returned by `GetStats`
+    pub fn snapshot(&self) -> Stats {
+        let now = now_ms();
+        let by_name = |values: Vec<CounterValue>| {
+            values
+                .into_iter()
+                .map(|value| (value.name.to_string(), value.value))
+                .collect::<HashMap<_, _>>()
+        };
+        let age = |at: u64| if at == 0 { 0 } else { now.saturating_sub(at) };
+        Stats {
+            feeds: self
+                .feeds()
+                .into_iter()
+                .map(|(exchange, symbol, stats)| FeedStats {
+                    exchange: exchange.to_string(),
+                    symbol: symbol.to_string(),
+                    last_message_age_ms: age(stats.counters.last_message_ms.get()),
+                    state: state_name(stats.state()).to_string(),
+                    counters: by_name(stats.counters.values()),
+                })
+                .collect(),
+            symbols: self
+                .symbols()
+                .into_iter()
+                .map(|(symbol, counters)| SymbolStats {
+                    symbol: symbol.to_string(),
+                    last_summary_age_ms: age(counters.last_summary_ms.get()),
+                    counters: by_name(counters.values()),
+                })
+                .collect(),
+            server: by_name(self.server_values()),
+            uptime_seconds: self.inner.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Every counter in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        let feeds = self
+            .feeds()
+            .into_iter()
+            .map(|(exchange, symbol, stats)| {
+                let labels = format!("exchange=\"{}\",symbol=\"{}\"", exchange, symbol);
+                (labels, stats.counters.values())
+            })
+            .collect::<Vec<_>>();
+        write_metrics(
+            &mut text,
+            "orderbook_feed",
+            FeedCounters::default().values(),
+            &feeds,
+        );
+        let symbols = self
+            .symbols()
+            .into_iter()
+            .map(|(symbol, counters)| (format!("symbol=\"{}\"", symbol), counters.values()))
+            .collect::<Vec<_>>();
+        write_metrics(
+            &mut text,
+            "orderbook_symbol",
+            SymbolCounters::default().values(),
+            &symbols,
+        );
+        let server = self.server_values();
+        write_metrics(
+            &mut text,
+            "orderbook_server",
+            server.clone(),
+            &[(String::new(), server)],
+        );
+        let _ = writeln!(
+            text,
+            "# HELP orderbook_server_uptime_seconds Seconds since the server started\n\
+             # TYPE orderbook_server_uptime_seconds gauge\n\
+             orderbook_server_uptime_seconds {}",
+            self.inner.started.elapsed().as_secs()
+        );
+        text
+    }
+
+    /// Serves the counters to Prometheus at `/metrics` on `addr` until the task is aborted.
+    pub async fn serve(self, addr: SocketAddr) -> Result<JoinHandle<()>> {
+        use hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Method, Response, StatusCode,
+        };
+        let server = hyper::Server::try_bind(&addr)
+            .with_context(|| format!("failed to listen on {} for metrics", addr))?;
+        let make_service = make_service_fn(move |_| {
+            let stats = self.clone();
+            async move {
+                Ok::<_, std::convert::Infallible>(service_fn(move |request| {
+                    let response = match (request.method(), request.uri().path()) {
+                        (&Method::GET, "/metrics") => Response::builder()
+                            .header("content-type", "text/plain; version=0.0.4")
+                            .body(Body::from(stats.prometheus())),
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    };
+                    async move { response }
+                }))
+            }
+        });
+        let server = server.serve(make_service);
+        Ok(tokio::spawn(async move {
+            if let Err(err) = server.await {
+                tracing::error!("metrics server failed: {}", err);
+            }
+        }))
+    }
+}
+
+/// Writes each metric with its help and type, then a line for each set of labels.
+fn write_metrics(
+    text: &mut String,
+    prefix: &str,
+    metrics: Vec<CounterValue>,
+    rows: &[(String, Vec<CounterValue>)],
+) {
+    if rows.is_empty() {
+        return;
+    }
+    for (i, metric) in metrics.iter().enumerate() {
+        let suffix = match metric.kind {
+            CounterKind::Counter => "_total",
+            CounterKind::Gauge => "",
+        };
+        let name = format!("{}_{}{}", prefix, metric.name, suffix);
+        let _ = writeln!(text, "# HELP {} {}", name, metric.help);
+        let _ = writeln!(text, "# TYPE {} {}", name, metric.kind.as_str());
+        for (labels, values) in rows {
+            let value = values[i].value;
+            let _ = match labels.is_empty() {
+                true => writeln!(text, "{} {}", name, value),
+                false => writeln!(text, "{}{{{}}} {}", name, labels, value),
+            };
+        }
+    }
+}
+
+/// Sends the status of each connection of `symbol`, or every symbol when it's `None`,
+/// marked [initial](ExchangeStatus::initial), then each change of their state on `tx` until
+/// the client goes away. Changes a status already sent includes are skipped, and if the
+/// stream falls behind the statuses are read again, so none goes back to an older state.
+pub async fn watch_exchange_status(
+    stats: PipelineStats,
+    symbol: Option<Symbol>,
+    tx: mpsc::Sender<Result<ExchangeStatus, Status>>,
+) {
+    let (statuses, mut rx_transitions) = stats.exchange_statuses(symbol);
+    let name = symbol.map(|symbol| symbol.to_string());
+    // The sequence of the latest status sent of each connection
+    let mut sent = HashMap::<(String, String), u64>::new();
+    for status in statuses {
+        let status = ExchangeStatus {
+            initial: true,
+            ..status
+        };
+        if !send_newer(&tx, &mut sent, status).await {
+            return;
+        }
+    }
+    loop {
+        let transition = select! {
+            transition = rx_transitions.recv() => transition,
+            _ = tx.closed() => return,
+        };
+        match transition {
+            Ok(status) => {
+                if name.as_ref().is_some_and(|name| *name != status.symbol) {
+                    continue;
+                }
+                if !send_newer(&tx, &mut sent, status).await {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("exchange status stream skipped {} changes", skipped);
+                for status in stats.exchange_statuses(symbol).0 {
+                    if !send_newer(&tx, &mut sent, status).await {
+                        return;
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Receives the updates applied to the order books of a symbol while it's held, see
+/// [PipelineStats::tap]
+#[derive(Debug)]
+pub struct Tap {
+    /// Shared by the taps of every symbol, so the updates of others still have to be skipped
+    rx_taps: broadcast::Receiver<Arc<TappedUpdate>>,
+    tappers: Arc<AtomicUsize>,
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        self.tappers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Sends the updates `tap` receives applied to the order books of `symbol` on `tx` until
+/// it closes, only those of `exchanges` unless it's empty. The updates the tap is behind
+/// on are dropped, counted in the server's counters and reported with the next update sent.
+pub async fn tap_updates(
+    stats: PipelineStats,
+    mut tap: Tap,
+    symbol: Symbol,
+    exchanges: Vec<Exchange>,
+    tx: mpsc::Sender<Result<TappedUpdate, Status>>,
+) {
+    let symbol = symbol.to_string();
+    let exchanges = exchanges
+        .iter()
+        .map(|exchange| exchange.to_string())
+        .collect::<Vec<_>>();
+    let mut dropped = 0;
+    loop {
+        let tapped = select! {
+            tapped = tap.rx_taps.recv() => tapped,
+            _ = tx.closed() => return,
+        };
+        match tapped {
+            Ok(update) => {
+                if update.symbol != symbol
+                    || !(exchanges.is_empty() || exchanges.contains(&update.exchange))
+                {
+                    continue;
+                }
+                let update = TappedUpdate {
+                    dropped: std::mem::take(&mut dropped),
+                    ..(*update).clone()
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("update tap dropped {} updates", skipped);
+                stats.server().tapped_updates_dropped.add(skipped);
+                dropped += skipped;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Sends `status` unless one as new was already sent for its connection, returning
+/// false once the client has gone away.
+async fn send_newer(
+    tx: &mpsc::Sender<Result<ExchangeStatus, Status>>,
+    sent: &mut HashMap<(String, String), u64>,
+    status: ExchangeStatus,
+) -> bool {
+    let latest = sent
+        .entry((status.exchange.clone(), status.symbol.clone()))
+        .or_default();
+    if status.sequence <= *latest && *latest > 0 {
+        return true;
+    }
+    *latest = status.sequence;
+    tx.send(Ok(status)).await.is_ok()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists_the_counters_by_name() {
+        let stats = PipelineStats::new();
+        let feed = stats.feed(Exchange::BINANCE, Symbol::BTCUSDT);
+        feed.received();
+        feed.applied(3, 4);
+        feed.incr_parse_errors();
+        // The same stats are handed out again, so they carry on after a reconnect.
+        stats.feed(Exchange::BINANCE, Symbol::BTCUSDT).received();
+        stats.server().summaries_sent.add(5);
+
+        let snapshot = stats.snapshot();
+        let feed = &snapshot.feeds[0];
+        assert_eq!(feed.exchange, "BINANCE");
+        assert_eq!(feed.state, "connecting");
+        assert_eq!(feed.counters["messages"], 2);
+        assert_eq!(feed.counters["parse_errors"], 1);
+        assert_eq!(feed.counters["updates_applied"], 1);
+        assert_eq!(feed.counters["bid_levels"], 3);
+        assert_eq!(feed.counters["ask_levels"], 4);
+        assert!(feed.last_message_age_ms < 10_000);
+        assert_eq!(snapshot.server["summaries_sent"], 5);
+        assert_eq!(snapshot.server["streams_active"], 0);
+        assert!(snapshot.symbols.is_empty());
+    }
+
+    #[test]
+    fn it_only_has_the_feeds_of_a_tapped_symbol_copy_their_updates_out() {
+        let stats = PipelineStats::new();
+        let btcusdt = stats.feed(Exchange::BINANCE, Symbol::BTCUSDT);
+        let ethbtc = stats.feed(Exchange::BINANCE, Symbol::ETHBTC);
+        assert!(!btcusdt.tapping() && !ethbtc.tapping());
+
+        let tap = stats.tap(Symbol::BTCUSDT);
+        let second = stats.tap(Symbol::BTCUSDT);
+        // Feeds started after the tap want their updates tapped too.
+        let bitstamp = stats.feed(Exchange::BITSTAMP, Symbol::BTCUSDT);
+        assert!(btcusdt.tapping() && bitstamp.tapping());
+        assert!(!ethbtc.tapping());
+
+        drop(tap);
+        assert!(btcusdt.tapping());
+        drop(second);
+        assert!(!btcusdt.tapping() && !bitstamp.tapping());
+    }
+
+    #[test]
+    fn it_writes_the_prometheus_text_format() {
+        let stats = PipelineStats::new();
+        stats
+            .feed(Exchange::BITSTAMP, Symbol::BTCUSDT)
+            .applied(7, 8);
+        stats.feed(Exchange::BINANCE, Symbol::BTCUSDT).received();
+        stats.server().streams_active.incr();
+
+        let text = stats.prometheus();
+        let lines = text.lines().collect::<Vec<_>>();
+        let at = |line: &str| lines.iter().position(|l| *l == line).unwrap();
+        let help = at("# HELP orderbook_feed_messages_total Websocket messages received");
+        assert_eq!(
+            lines[help + 1],
+            "# TYPE orderbook_feed_messages_total counter"
+        );
+        assert_eq!(
+            lines[help + 2],
+            "orderbook_feed_messages_total{exchange=\"BINANCE\",symbol=\"BTCUSDT\"} 1"
+        );
+        assert_eq!(
+            lines[help + 3],
+            "orderbook_feed_messages_total{exchange=\"BITSTAMP\",symbol=\"BTCUSDT\"} 0"
+        );
+        at("# TYPE orderbook_feed_bid_levels gauge");
+        at("orderbook_feed_bid_levels{exchange=\"BITSTAMP\",symbol=\"BTCUSDT\"} 7");
+        at("orderbook_server_streams_active 1");
+        at("# TYPE orderbook_server_uptime_seconds gauge");
+        assert!(!text.contains("tls_cert_expiry_days"));
+        // Symbols aren't listed until one is watched.
+        assert!(!text.contains("orderbook_symbol_"));
+    }
+
+    #[tokio::test]
+    async fn it_streams_the_status_of_each_connection_then_its_changes() {
+        use crate::core::stats::ConnectionState;
+        let stats = PipelineStats::new();
+        let feed = stats.feed(Exchange::BINANCE, Symbol::BTCUSDT);
+        feed.set_state(ConnectionState::Connecting);
+        let (tx, mut rx) = mpsc::channel(10);
+        tokio::spawn(watch_exchange_status(
+            stats.clone(),
+            Some(Symbol::BTCUSDT),
+            tx,
+        ));
+
+        let status = rx.recv().await.unwrap().unwrap();
+        assert_eq!(
+            (status.exchange.as_str(), status.state(), status.sequence),
+            ("BINANCE", ConnectionState::Connecting, 1)
+        );
+        assert!(status.initial);
+        // Other symbols' connections are left out.
+        stats.feed(Exchange::BINANCE, Symbol::ETHBTC).receiving();
+        feed.receiving();
+        feed.receiving();
+        feed.reconnecting(1, 5_000, "stream ended".to_string());
+        let mut changes = Vec::new();
+        for _ in 0..3 {
+            let status = rx.recv().await.unwrap().unwrap();
+            assert!(!status.initial);
+            changes.push((status.state(), status.attempt, status.reason));
+        }
+        assert_eq!(
+            changes,
+            [
+                (ConnectionState::Subscribed, 0, String::new()),
+                (ConnectionState::Receiving, 0, String::new()),
+                (ConnectionState::Reconnecting, 1, "stream ended".to_string()),
+            ]
+        );
+        assert!(rx.try_recv().is_err());
+
+        let snapshot = stats.snapshot();
+        let feed = &snapshot.feeds[0];
+        assert_eq!(feed.state, "reconnecting");
+        assert_eq!(feed.counters["state_changes"], 4);
+        assert_eq!(feed.counters["reconnect_attempt"], 1);
+    }
+
+    #[test]
+    fn it_reports_the_days_until_the_tls_certificate_expires() {
+        let stats = PipelineStats::new();
+        let now = now_ms() / 1000;
+        stats.set_tls_cert_expiry(now + 30 * 86_400 + 60);
+        assert_eq!(stats.snapshot().server["tls_cert_expiry_days"], 30);
+        assert!(stats
+            .prometheus()
+            .contains("orderbook_server_tls_cert_expiry_days 30\n"));
+        stats.set_tls_cert_expiry(now - 86_400);
+        assert_eq!(stats.snapshot().server["tls_cert_expiry_days"], 0);
+    }
+
+    #[tokio::test]
+    async fn it_counts_the_summaries_published_for_a_symbol() {
+        use crate::book_summary::Summary;
+        let (tx, rx) = tokio::sync::watch::channel(Ok(Arc::new(Summary::default())));
+        let stats = PipelineStats::new();
+        let handle = stats.watch_symbol(Symbol::ETHBTC, rx);
+        let _ = tx.send_replace(Ok(Arc::new(Summary {
+            sequence: 4,
+            unavailable_exchanges: vec!["BINANCE".to_string()],
+            ..Default::default()
+        })));
+        tokio::task::yield_now().await;
+        let _ = tx.send_replace(Err(tonic::Status::unavailable("gone")));
+        drop(tx);
+        handle.await.unwrap();
+
+        let symbol = &stats.snapshot().symbols[0];
+        assert_eq!(symbol.symbol, "ETHBTC");
+        assert_eq!(symbol.counters["summaries"], 4);
+        assert_eq!(symbol.counters["exchanges_unavailable"], 1);
+        assert_eq!(symbol.counters["unavailable"], 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! `obagg`, a command line client for scripts and quick checks of a running server.
+//!
+//! ```sh
+//! obagg symbols
+//! obagg exchanges
+//! obagg info
+//! obagg summary btcusdt --levels 10 --decimals 2
+//! obagg watch btcusdt --duration 30s | jq .spread
+//! ```
+//!
+//! `summary` prints a table and `watch` prints each summary as a line of json with the
+//! proto's field names, or either as csv with `--format`, see [Format]. Errors, including
+//! the server ending the stream with a status, exit with a non-zero code.
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+use orderbook_agg::{
+    client::{OrderbookClient, SummaryOptions},
+    exchanges::options::DepthSpeed,
+    format::{Format, SummaryWriter},
+    service::parse_weights,
+    Exchange,
+};
+
+#[derive(Debug, Parser)]
+#[clap(name = "obagg")]
+struct Options {
+    /// Server to connect to
+    #[clap(long, env = "OBAGG_ENDPOINT", default_value = "http://127.0.0.1:9001")]
+    endpoint: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Parser)]
+enum Command {
+    /// Lists the symbols the server aggregates
+    Symbols,
+    /// Lists the exchanges the server aggregates
+    Exchanges,
+    /// Prints the server's version, build and limits as json
+    Info,
+    /// Prints the latest summary for a symbol
+    Summary {
+        symbol: String,
+        #[clap(flatten)]
+        options: SummaryArgs,
+        /// json, csv or table
+        #[clap(long, default_value = "table")]
+        format: Format,
+        /// Decimal places prices and quantities are shown with in tables
+        #[clap(long, default_value_t = 2)]
+        decimals: usize,
+    },
+    /// Streams summaries for a symbol, one line of json each
+    Watch {
+        symbol: String,
+        #[clap(flatten)]
+        options: SummaryArgs,
+        /// Minimum milliseconds between summaries, 0 receives every summary
+        #[clap(long, default_value_t = 0)]
+        min_interval_ms: u64,
+        /// Binance depth update speed wanted in milliseconds, 100 or 1000, 0 for the server's
+        #[clap(long, default_value_t = 0)]
+        update_speed_ms: u32,
+        /// Stop after this long, e.g. 30s, 500ms or 5m, streams until interrupted when not set
+        #[clap(long, value_parser = parse_duration)]
+        duration: Option<Duration>,
+        /// json, csv or table
+        #[clap(long, default_value = "json")]
+        format: Format,
+        /// Decimal places prices and quantities are shown with in tables
+        #[clap(long, default_value_t = 2)]
+        decimals: usize,
+    },
+}
+
+#[derive(Debug, Parser)]
+struct SummaryArgs {
+    /// Number of bid and ask levels to receive, 0 receives all of them
+    #[clap(long, default_value_t = 0)]
+    levels: u32,
+    /// Only receive levels from these exchanges, e.g. binance,bitstamp, all when not set
+    #[clap(long, value_delimiter = ',')]
+    exchanges: Vec<Exchange>,
+    /// Weights to multiply exchanges' quantities by, e.g. bitstamp=0.8, 0 leaves an
+    /// exchange out
+    #[clap(long, value_delimiter = ',')]
+    exchange_weights: Vec<String>,
+}
+
+impl SummaryArgs {
+    fn options(&self) -> Result<SummaryOptions> {
+        let weights =
+            parse_weights(&self.exchange_weights).context("invalid --exchange-weights")?;
+        let options = weights.into_iter().fold(
+            SummaryOptions::new()
+                .with_levels(self.levels)
+                .with_exchanges(self.exchanges.clone()),
+            |options, (exchange, weight)| options.with_weight(exchange, weight),
+        );
+        Ok(options)
+    }
+}
+
+/// Parses a number of `ms`, `s`, `m` or `h`.
+fn parse_duration(duration: &str) -> Result<Duration> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("{} has no unit, e.g. 30s", duration))?;
+    let (count, unit) = duration.split_at(split);
+    let count: u64 = count
+        .parse()
+        .with_context(|| format!("invalid duration {}", duration))?;
+    Ok(match unit {
+        "ms" => Duration::from_millis(count),
+        "s" => Duration::from_secs(count),
+        "m" => Duration::from_secs(count * 60),
+        "h" => Duration::from_secs(count * 3600),
+        _ => bail!("unknown unit in {}, expected ms, s, m or h", duration),
+    })
+}
+
+/// Prints summaries until the stream ends, `duration` passes or the process is interrupted.
+async fn watch(
+    client: OrderbookClient,
+    symbol: &str,
+    options: SummaryOptions,
+    duration: Option<Duration>,
+    mut writer: SummaryWriter<impl std::io::Write>,
+) -> Result<()> {
+    // Scripts are told the server went away rather than left waiting for it to come back.
+    let mut stream = client.with_reconnect(None).watch(symbol, options);
+    let deadline = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => futures::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = &mut deadline => break,
+            summary = stream.next() => match summary {
+                Some(summary) => writer.write(&summary?)?,
+                // Without reconnecting, a stream the server ends yields an error first.
+                None => break,
+            },
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Options::parse();
+    let client = OrderbookClient::connect(&opts.endpoint)
+        .await
+        .with_context(|| format!("Failed to connect to {}", opts.endpoint))?;
+
+    match opts.command {
+        Command::Symbols => println!("{}", client.symbols().await?.join("\n")),
+        Command::Exchanges => println!("{}", client.exchanges().await?.join("\n")),
+        Command::Info => println!(
+            "{}",
+            serde_json::to_string_pretty(&client.server_info().await?)?
+        ),
+        Command::Summary {
+            symbol,
+            options,
+            format,
+            decimals,
+        } => {
+            let summary = client.summary(&symbol, &options.options()?).await?;
+            SummaryWriter::new(std::io::stdout().lock(), format, decimals).write(&summary)?;
+        }
+        Command::Watch {
+            symbol,
+            options,
+            min_interval_ms,
+            update_speed_ms,
+            duration,
+            format,
+            decimals,
+        } => {
+            let mut options = options
+                .options()?
+                .with_min_interval(Duration::from_millis(min_interval_ms));
+            if update_speed_ms > 0 {
+                let speed = DepthSpeed::from_millis(update_speed_ms)
+                    .context("--update-speed-ms must be 100 or 1000")?;
+                options = options.with_update_speed(speed);
+            }
+            let writer = SummaryWriter::new(std::io::stdout().lock(), format, decimals);
+            watch(client, &symbol, options, duration, writer).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Order flow imbalance of a symbol's best bids and asks over a rolling window, reported on
+//! each summary.
+//!
+//! Each change of an exchange's best bid or ask is scored as in Cont, Kukanov and Stoikov,
+//! "The Price Impact of Order Book Events" (2014). With `b` and `qb` the best bid and its
+//! quantity before the change, `a` and `qa` the best ask and its quantity, and the same
+//! primed after it:
+//!
+//! ```text
+//! e = 1{b' >= b} qb' - 1{b' <= b} qb - 1{a' <= a} qa' + 1{a' >= a} qa
+//! ```
+//!
+//! Quantity joining the best bid or the bid moving up adds to `e`, as does quantity
+//! leaving the best ask or the ask moving up, and the reverse takes away from it. The
+//! imbalance is the sum of `e` over every exchange's changes within the window, in the
+//! symbol's base quantity, positive when the pressure is to buy.
+//!
+//! An exchange's first quote after its book is rebuilt is only the baseline for the next,
+//! so the snapshot a reconnect starts from never counts as flow.
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::{divergence::Quote, Exchange};
+
+/// How far back the imbalance on summaries reaches
+pub const WINDOW: Duration = Duration::from_secs(5);
+
+/// The flow is summed in buckets this long, so the window moves on in steps of it
+const BUCKET_MS: u64 = 100;
+
+/// The score `e` of the best bid and ask moving from `before` to `after`
+pub fn flow(before: &Quote, after: &Quote) -> f64 {
+    let mut e = 0.0;
+    if after.bid >= before.bid {
+        e += after.bid_quantity;
+    }
+    if after.bid <= before.bid {
+        e -= before.bid_quantity;
+    }
+    if after.ask <= before.ask {
+        e -= after.ask_quantity;
+    }
+    if after.ask >= before.ask {
+        e += before.ask_quantity;
+    }
+    e
+}
+
+/// The last quote of each exchange and the flow scored from their changes
+#[derive(Debug)]
+pub struct OrderFlow {
+    window_ms: u64,
+    quotes: HashMap<Exchange, Quote>,
+    /// The flow of each bucket with any, by its start in milliseconds, oldest first
+    buckets: VecDeque<(u64, f64)>,
+    scored: bool,
+}
+
+impl OrderFlow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window_ms: window.as_millis() as u64,
+            quotes: HashMap::new(),
+            buckets: VecDeque::new(),
+            scored: false,
+        }
+    }
+
+    /// Scores the change from the exchange's last quote to `quote` at `now_ms`,
+    /// milliseconds since the unix epoch. The first quote of an exchange is kept without
+    /// scoring it.
+    pub fn update(&mut self, quote: Quote, now_ms: u64) {
+        let Some(before) = self.quotes.insert(quote.exchange, quote) else {
+            return;
+        };
+        if before == quote {
+            return;
+        }
+        let start = now_ms - now_ms % BUCKET_MS;
+        match self.buckets.back_mut() {
+            Some((last, e)) if *last == start => *e += flow(&before, &quote),
+            _ => self.buckets.push_back((start, flow(&before, &quote))),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|&(start, _)| start + self.window_ms <= now_ms)
+        {
+            self.buckets.pop_front();
+        }
+        self.scored = true;
+    }
+
+    /// Forgets the exchange's last quote, for when its book is rebuilt or one side of it
+    /// empties, so the next is a new baseline.
+    pub fn reset(&mut self, exchange: Exchange) {
+        self.quotes.remove(&exchange);
+    }
+
+    /// The imbalance of the buckets started within the window ending at `now_ms`, or
+    /// `None` until a change has been scored.
+    pub fn imbalance(&self, now_ms: u64) -> Option<f64> {
+        self.scored.then(|| {
+            self.buckets
+                .iter()
+                .filter(|&&(start, _)| start + self.window_ms > now_ms)
+                .map(|&(_, e)| e)
+                .sum()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(exchange: Exchange, bid: [f64; 2], ask: [f64; 2]) -> Quote {
+        Quote {
+            exchange,
+            bid: bid[0],
+            bid_quantity: bid[1],
+            ask: ask[0],
+            ask_quantity: ask[1],
+        }
+    }
+
+    #[test]
+    fn it_scores_the_changes_of_the_best_bid_and_ask() {
+        let mut flow = OrderFlow::new(WINDOW);
+        let steps = [
+            // The baseline.
+            (0, [100.0, 2.0], [101.0, 3.0]),
+            // 3 joins the bid: 5 - 2 - 3 + 3 = 3
+            (100, [100.0, 5.0], [101.0, 3.0]),
+            // Both sides move up: 1 - 0 - 0 + 3 = 4
+            (200, [101.0, 1.0], [102.0, 4.0]),
+            // Both sides move down: 0 - 1 - 2 + 0 = -3
+            (300, [100.0, 6.0], [101.0, 2.0]),
+            // 2 leaves the bid and 5 joins the ask: 4 - 6 - 7 + 2 = -7
+            (450, [100.0, 4.0], [101.0, 7.0]),
+        ];
+        let mut imbalances = Vec::new();
+        for (now_ms, bid, ask) in steps {
+            flow.update(quote(Exchange::BINANCE, bid, ask), now_ms);
+            imbalances.push(flow.imbalance(now_ms));
+        }
+        assert_eq!(
+            imbalances,
+            [None, Some(3.0), Some(7.0), Some(4.0), Some(-3.0)]
+        );
+
+        // Changes leave the window five seconds after their bucket starts.
+        assert_eq!(flow.imbalance(5_150), Some(-6.0));
+        assert_eq!(flow.imbalance(5_350), Some(-7.0));
+        assert_eq!(flow.imbalance(5_400), Some(0.0));
+    }
+
+    #[test]
+    fn it_sums_the_exchanges_without_counting_resyncs() {
+        let mut flow = OrderFlow::new(WINDOW);
+        flow.update(quote(Exchange::BINANCE, [100.0, 1.0], [101.0, 1.0]), 0);
+        flow.update(quote(Exchange::BITSTAMP, [100.0, 1.0], [101.0, 1.0]), 0);
+        // 2 joins binance's bid and half leaves bitstamp's ask, 2 + 0.5.
+        flow.update(quote(Exchange::BINANCE, [100.0, 3.0], [101.0, 1.0]), 10);
+        flow.update(quote(Exchange::BITSTAMP, [100.0, 1.0], [101.0, 0.5]), 20);
+        assert_eq!(flow.imbalance(20), Some(2.5));
+
+        // Bitstamp's book is rebuilt with a much bigger bid, which is the new baseline.
+        flow.reset(Exchange::BITSTAMP);
+        flow.update(quote(Exchange::BITSTAMP, [100.5, 40.0], [101.0, 0.5]), 30);
+        assert_eq!(flow.imbalance(30), Some(2.5));
+        flow.update(quote(Exchange::BITSTAMP, [100.5, 41.0], [101.0, 0.5]), 40);
+        assert_eq!(flow.imbalance(40), Some(3.5));
+    }
+}
@@ -0,0 +1,445 @@
+//! Summary tasks of the symbols started when a client first asks for them, rather than
+//! with the server, and stopped once nothing has watched them for a while.
+//!
+//! Each subscriber to a symbol's summaries, whether a client stream or a sink, holds a
+//! [Lease] counting it until it's dropped. Symbols running with the server, its own and
+//! its warm ones, are counted the same way but never stopped. Once the last lease of an
+//! on demand symbol is dropped its summary task runs on for the linger, so a client
+//! reconnecting straight away finds its books live, then the task is dropped. That stops
+//! it, closing the exchanges' connections and dropping their order books.
+//!
+//! Starting, sharing and dropping summary tasks all happen under one lock, so a subscriber
+//! arriving as the linger ends either keeps the task running or starts a new one, never
+//! getting one that's stopping.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{metrics::PipelineStats, service::SummarySubscriber, Symbol};
+
+/// How long an on demand symbol's summary task runs on with nothing watching it, unless
+/// set otherwise
+pub const DEFAULT_LINGER: Duration = Duration::from_secs(30);
+
+type Start = dyn Fn(Symbol) -> SummarySubscriber + Send + Sync;
+
+/// The subscribers of each symbol and the summary tasks started on demand. Clones share the
+/// same tasks and counts.
+#[derive(Clone)]
+pub struct Pipelines {
+    symbols: Vec<Symbol>,
+    start: Option<Arc<Start>>,
+    linger: Duration,
+    stats: PipelineStats,
+    entries: Arc<Mutex<HashMap<Symbol, Entry>>>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    subscribers: usize,
+    /// The summary task started on demand, none while it isn't running or for symbols
+    /// running with the server
+    tx_summary: Option<SummarySubscriber>,
+    /// Times the last lease was dropped, so a linger ending after the symbol was watched
+    /// again in the meantime leaves the task running
+    idled: u64,
+}
+
+impl std::fmt::Debug for Pipelines {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Pipelines")
+            .field("symbols", &self.symbols)
+            .field("linger", &self.linger)
+            .finish()
+    }
+}
+
+impl Default for Pipelines {
+    /// Counts subscribers without starting any symbol on demand
+    fn default() -> Self {
+        Self {
+            symbols: Vec::new(),
+            start: None,
+            linger: DEFAULT_LINGER,
+            stats: PipelineStats::default(),
+            entries: Arc::default(),
+        }
+    }
+}
+
+impl Pipelines {
+    /// Starts the summary task of each of `symbols` with `start` when it's first asked for
+    pub fn new<F>(symbols: Vec<Symbol>, start: F) -> Self
+    where
+        F: Fn(Symbol) -> SummarySubscriber + Send + Sync + 'static,
+    {
+        Self {
+            symbols,
+            start: Some(Arc::new(start)),
+            ..Default::default()
+        }
+    }
+
+    /// Runs summary tasks on for `linger` once nothing watches them, stopping them as soon
+    /// as that happens when 0
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// Reports the subscribers of each symbol, and the summary tasks started and stopped,
+    /// in `stats`
+    pub fn with_stats(mut self, stats: PipelineStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// The symbols started on demand
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Counts a subscriber of `symbol` until the lease is dropped, without starting it
+    pub fn lease(&self, symbol: Symbol) -> Lease {
+        let mut entries = self.entries.lock().unwrap();
+        self.add_subscriber(symbol, entries.entry(symbol).or_default());
+        Lease {
+            pipelines: self.clone(),
+            symbol,
+        }
+    }
+
+    /// The subscriber of the summary task of `symbol`, starting it unless it's running,
+    /// and a lease keeping it running. None when `symbol` isn't started on demand.
+    pub fn attach(&self, symbol: Symbol) -> Option<(SummarySubscriber, Lease)> {
+        let start = self
+            .start
+            .as_ref()
+            .filter(|_| self.symbols.contains(&symbol))?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(symbol).or_default();
+        // A task that stopped by itself, like one whose symbol was delisted, is replaced.
+        let tx_summary = match &entry.tx_summary {
+            Some(tx_summary) if !tx_summary.is_closed() => tx_summary.clone(),
+            _ => {
+                tracing::info!("Starting {} for its first subscriber", symbol);
+                self.stats.server().pipelines_started.incr();
+                entry.tx_summary.insert(start(symbol)).clone()
+            }
+        };
+        self.add_subscriber(symbol, entry);
+        let lease = Lease {
+            pipelines: self.clone(),
+            symbol,
+        };
+        Some((tx_summary, lease))
+    }
+
+    /// The subscribers `symbol` has now
+    pub fn subscribers(&self, symbol: Symbol) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&symbol).map_or(0, |entry| entry.subscribers)
+    }
+
+    /// The symbols started on demand whose summary tasks are running
+    pub fn running(&self) -> Vec<Symbol> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .tx_summary
+                    .as_ref()
+                    .is_some_and(|tx_summary| !tx_summary.is_closed())
+            })
+            .map(|(&symbol, _)| symbol)
+            .collect()
+    }
+
+    /// Whether the summary task of `symbol` was started on demand and hasn't been stopped
+    pub fn is_running(&self, symbol: Symbol) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&symbol)
+            .and_then(|entry| entry.tx_summary.as_ref())
+            .is_some_and(|tx_summary| !tx_summary.is_closed())
+    }
+
+    fn add_subscriber(&self, symbol: Symbol, entry: &mut Entry) {
+        entry.subscribers += 1;
+        let subscribers = entry.subscribers as u64;
+        self.stats.symbol(symbol).subscribers.set(subscribers);
+    }
+
+    /// Counts the subscriber of a dropped lease out, starting the linger of a task started
+    /// on demand once it was the last. The symbols only counted are forgotten once they
+    /// have no subscribers.
+    fn release(&self, symbol: Symbol) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&symbol) else {
+            return;
+        };
+        entry.subscribers -= 1;
+        self.stats
+            .symbol(symbol)
+            .subscribers
+            .set(entry.subscribers as u64);
+        if entry.subscribers > 0 {
+            return;
+        }
+        if entry.tx_summary.is_none() {
+            entries.remove(&symbol);
+            return;
+        }
+        entry.idled += 1;
+        let idled = entry.idled;
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) if !self.linger.is_zero() => {
+                let pipelines = self.clone();
+                runtime.spawn(async move {
+                    tokio::time::sleep(pipelines.linger).await;
+                    pipelines.stop_idle(symbol, idled);
+                });
+            }
+            _ => self.stop(symbol, &mut entries),
+        }
+    }
+
+    /// Stops the summary task of `symbol` unless it's been watched since it was left idle
+    /// for the `idled`th time
+    fn stop_idle(&self, symbol: Symbol, idled: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        let idle = entries.get(&symbol).is_some_and(|entry| {
+            entry.subscribers == 0 && entry.idled == idled && entry.tx_summary.is_some()
+        });
+        if idle {
+            self.stop(symbol, &mut entries);
+        }
+    }
+
+    /// Drops the subscriber of the summary task, which stops it once the last of its
+    /// receivers is dropped too, see [spawn_summary](crate::service::spawn_summary), and
+    /// removes the symbol's entry. Lingers still waiting on it are all from before it was
+    /// last left idle, so they end first and find the entry as it was.
+    fn stop(&self, symbol: Symbol, entries: &mut HashMap<Symbol, Entry>) {
+        tracing::info!("Stopping {}, unwatched for {:?}", symbol, self.linger);
+        entries.remove(&symbol);
+        self.stats.server().pipelines_stopped.incr();
+    }
+}
+
+/// Counts a subscriber of a symbol for as long as it's held
+#[derive(Debug)]
+pub struct Lease {
+    pipelines: Pipelines,
+    symbol: Symbol,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.pipelines.release(self.symbol);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        book_summary::Level,
+        core::order_book::BookLevels,
+        service::{spawn_exchange, spawn_summary, Backoff, ExchangeSwitches},
+        sinks::subscribe,
+        Exchange,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::{mpsc, watch};
+
+    /// Counts a connection to an exchange while it's held
+    struct Connection(Arc<AtomicUsize>);
+
+    impl Connection {
+        fn open(connected: &Arc<AtomicUsize>) -> Self {
+            connected.fetch_add(1, Ordering::SeqCst);
+            Self(connected.clone())
+        }
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn book_levels(symbol: Symbol, id: u64) -> Arc<BookLevels> {
+        let level = |price: f64| Level {
+            exchange: Exchange::BITSTAMP.to_string(),
+            price,
+            quantity: 1.0,
+            updated_at: None,
+            market_type: Exchange::BITSTAMP.market_type().into(),
+        };
+        Arc::new(BookLevels {
+            exchange: Exchange::BITSTAMP,
+            symbol,
+            last_update_id: id,
+            bids: vec![level(99.0)],
+            asks: vec![level(101.0)],
+            depth: None,
+            restored: false,
+        })
+    }
+
+    /// Pipelines of a summary task fed by a book sending levels every millisecond over a
+    /// connection counted in `connected`, with the tasks started counted in `started`
+    fn pipelines(connected: &Arc<AtomicUsize>, started: &Arc<AtomicUsize>) -> Pipelines {
+        let connected = connected.clone();
+        let started = started.clone();
+        Pipelines::new(vec![Symbol::ETHBTC], move |symbol| {
+            started.fetch_add(1, Ordering::SeqCst);
+            let (tx_levels, rx_levels) = mpsc::channel(100);
+            let (tx_closed, rx_closed) = mpsc::channel(10);
+            let connected = connected.clone();
+            spawn_exchange(
+                Exchange::BITSTAMP,
+                symbol,
+                Backoff::default(),
+                ExchangeSwitches::default().subscribe(Exchange::BITSTAMP),
+                tx_closed,
+                move || {
+                    let tx_levels = tx_levels.clone();
+                    let connection = Connection::open(&connected);
+                    async move {
+                        let _connection = connection;
+                        for id in 1.. {
+                            if tx_levels.send(book_levels(symbol, id)).await.is_err() {
+                                break;
+                            }
+                            tokio::time::sleep(Duration::from_millis(1)).await;
+                        }
+                        Ok(())
+                    }
+                },
+            );
+            spawn_summary(
+                symbol,
+                vec![Exchange::BITSTAMP],
+                rx_levels,
+                rx_closed,
+                watch::channel(true).0,
+            )
+        })
+    }
+
+    /// Waits for every connection to close
+    async fn disconnected(connected: &AtomicUsize) {
+        while connected.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_stops_symbols_left_unwatched_for_the_linger() {
+        let connected = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+        let stats = PipelineStats::new();
+        let pipelines = pipelines(&connected, &started)
+            .with_linger(Duration::from_secs(30))
+            .with_stats(stats.clone());
+        assert!(pipelines.attach(Symbol::BTCUSDT).is_none());
+
+        let (tx_summary, first) = pipelines.attach(Symbol::ETHBTC).unwrap();
+        let (_, second) = pipelines.attach(Symbol::ETHBTC).unwrap();
+        let mut rx_summary = subscribe(&tx_summary).await.unwrap();
+        drop(tx_summary);
+        rx_summary
+            .wait_for(|summary| summary.as_ref().is_ok_and(|s| s.sequence > 0))
+            .await
+            .unwrap();
+        assert_eq!(pipelines.subscribers(Symbol::ETHBTC), 2);
+        assert_eq!(connected.load(Ordering::SeqCst), 1);
+        drop((rx_summary, first, second));
+        assert_eq!(pipelines.subscribers(Symbol::ETHBTC), 0);
+
+        // Watching it again within the linger keeps it running, and starts the linger over.
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        drop(pipelines.attach(Symbol::ETHBTC).unwrap());
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert!(pipelines.is_running(Symbol::ETHBTC));
+        assert_eq!(connected.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert!(!pipelines.is_running(Symbol::ETHBTC));
+        assert!(pipelines.entries.lock().unwrap().is_empty());
+        disconnected(&connected).await;
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        // The next subscriber gets a new one.
+        let (tx_summary, _lease) = pipelines.attach(Symbol::ETHBTC).unwrap();
+        assert!(subscribe(&tx_summary).await.is_ok());
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert_eq!(pipelines.running(), [Symbol::ETHBTC]);
+
+        // Symbols running with the server are only counted.
+        let lease = pipelines.lease(Symbol::BTCUSDT);
+        assert_eq!(pipelines.subscribers(Symbol::BTCUSDT), 1);
+        let snapshot = stats.snapshot();
+        let counters = |symbol: &str| {
+            let stats = snapshot.symbols.iter().find(|s| s.symbol == symbol);
+            stats.unwrap().counters.clone()
+        };
+        assert_eq!(counters("BTCUSDT")["subscribers"], 1);
+        assert_eq!(counters("ETHBTC")["subscribers"], 1);
+        assert_eq!(snapshot.server["pipelines_started"], 2);
+        assert_eq!(snapshot.server["pipelines_stopped"], 1);
+        drop(lease);
+        assert_eq!(pipelines.subscribers(Symbol::BTCUSDT), 0);
+        // Only the symbol still watched is left.
+        assert_eq!(pipelines.entries.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn it_never_hands_out_a_stopping_symbol_to_subscribers_racing_the_linger() {
+        let connected = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+        let pipelines = pipelines(&connected, &started).with_linger(Duration::from_millis(2));
+        let clients = (0..8u64).map(|client| {
+            let pipelines = pipelines.clone();
+            tokio::spawn(async move {
+                for round in 0..40u64 {
+                    let (tx_summary, lease) = pipelines.attach(Symbol::ETHBTC).unwrap();
+                    let mut rx_summary = subscribe(&tx_summary).await.unwrap();
+                    drop(tx_summary);
+                    // Summaries keep coming whichever task the subscriber got.
+                    let mut sequence = 0;
+                    for _ in 0..2 {
+                        let summary = tokio::time::timeout(
+                            Duration::from_secs(5),
+                            rx_summary.wait_for(|summary| {
+                                summary.as_ref().is_ok_and(|s| s.sequence > sequence)
+                            }),
+                        )
+                        .await
+                        .expect("no summary within 5s")
+                        .unwrap();
+                        sequence = summary.as_ref().unwrap().sequence;
+                    }
+                    drop((rx_summary, lease));
+                    // Gaps around the linger, so some subscribers find the task idle and
+                    // others find it stopped.
+                    let gap = (client * 7 + round * 3) % 5;
+                    tokio::time::sleep(Duration::from_millis(gap)).await;
+                }
+            })
+        });
+        for client in futures::future::join_all(clients).await {
+            client.unwrap();
+        }
+
+        assert_eq!(pipelines.subscribers(Symbol::ETHBTC), 0);
+        tokio::time::timeout(Duration::from_secs(5), disconnected(&connected))
+            .await
+            .expect("connections still open at idle");
+        assert!(!pipelines.is_running(Symbol::ETHBTC));
+    }
+}
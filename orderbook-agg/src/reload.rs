@@ -0,0 +1,344 @@
+//! Reloads the config file while the server runs, on SIGHUP or the admin `ReloadConfig`
+//! rpc, without dropping client streams.
+//!
+//! Only `server.log_filter`, the peer ranges `server.allow_peers` and `server.deny_peers`
+//! with `server.unknown_peers`, and the `weight` of each exchange are reloaded. Every other
+//! key needs a restart, and changes to them are logged and left as they were. Keys given
+//! by a flag or environment variable keep taking precedence over the file, so reloading
+//! leaves them too. A file that fails to parse or validate is rejected whole.
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Mutex,
+};
+use tokio::sync::watch;
+
+use crate::{
+    access::{parse_nets, PeerFilter, UnknownPeers},
+    config::Config,
+    logging::{self, LogHandle},
+    service::parse_weights,
+    Exchange,
+};
+
+/// Applies the reloadable settings of the config file to the running server
+#[derive(Debug)]
+pub struct Reloader {
+    path: PathBuf,
+    running: Mutex<Config>,
+    /// Ids of the flags set on the command line or by environment variables
+    overridden: HashSet<String>,
+    log: Option<LogHandle>,
+    peers: Option<PeerFilter>,
+    tx_weights: watch::Sender<HashMap<Exchange, f64>>,
+}
+
+impl Reloader {
+    /// Reloads `path`, which the server was started with as `config`. New weights are
+    /// sent on `tx_weights`.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        config: Config,
+        tx_weights: watch::Sender<HashMap<Exchange, f64>>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            running: Mutex::new(config),
+            overridden: HashSet::new(),
+            log: None,
+            peers: None,
+            tx_weights,
+        }
+    }
+
+    /// Sets the handle for changing the log filter.
+    pub fn with_log(mut self, log: LogHandle) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Sets the filter of the peer addresses served.
+    pub fn with_peers(mut self, peers: PeerFilter) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// Sets the ids of the flags given on the command line or by environment variables,
+    /// whose values the file doesn't change.
+    pub fn with_overridden<I, S>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.overridden = ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Reads the file again and applies the reloadable settings that changed, returning
+    /// a description of each change. Nothing is changed if the file is invalid.
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let result = self.try_reload();
+        match &result {
+            Ok(changes) if changes.is_empty() => {
+                tracing::info!("Reloaded {}, nothing changed", self.path.display())
+            }
+            Ok(changes) => tracing::info!(
+                "Reloaded {}, changed {}",
+                self.path.display(),
+                changes.join(", ")
+            ),
+            Err(err) => tracing::error!("Rejected reload: {:#}", err),
+        }
+        result
+    }
+
+    fn try_reload(&self) -> Result<Vec<String>> {
+        let config = Config::load(&self.path)?;
+        let mut running = self.running.lock().unwrap();
+        let mut changes = Vec::new();
+
+        // Everything is checked before anything is applied.
+        let old_filter = &running.server.log_filter;
+        let new_filter = &config.server.log_filter;
+        let filter = if old_filter != new_filter && self.kept("log_filter", "server.log_filter") {
+            let filter =
+                logging::filter(new_filter.as_deref()).context("invalid server.log_filter")?;
+            changes.push(format!(
+                "server.log_filter from {} to {}",
+                old_filter.as_deref().unwrap_or("unset"),
+                new_filter.as_deref().unwrap_or("unset")
+            ));
+            Some(filter)
+        } else {
+            None
+        };
+        let old_weights = parse_weights(&running.exchange_weights())?;
+        let new_weights = parse_weights(&config.exchange_weights())?;
+        let weights =
+            if old_weights != new_weights && self.kept("exchange_weights", "exchange weights") {
+                changes.push(format!(
+                    "exchange weights from {} to {}",
+                    describe(&old_weights),
+                    describe(&new_weights)
+                ));
+                Some(new_weights)
+            } else {
+                None
+            };
+        let peers = match &self.peers {
+            Some(filter) => {
+                let old_rules = filter.rules();
+                let mut rules = old_rules.clone();
+                let (old, new) = (&running.server, &config.server);
+                if old.allow_peers != new.allow_peers
+                    && self.kept("allow_peers", "server.allow_peers")
+                {
+                    rules.allow = parse_nets(new.allow_peers.as_deref().unwrap_or_default())
+                        .context("invalid server.allow_peers")?;
+                }
+                if old.deny_peers != new.deny_peers && self.kept("deny_peers", "server.deny_peers")
+                {
+                    rules.deny = parse_nets(new.deny_peers.as_deref().unwrap_or_default())
+                        .context("invalid server.deny_peers")?;
+                }
+                if old.unknown_peers != new.unknown_peers
+                    && self.kept("unknown_peers", "server.unknown_peers")
+                {
+                    rules.unknown = match &new.unknown_peers {
+                        Some(unknown) => unknown.parse().context("invalid server.unknown_peers")?,
+                        None => UnknownPeers::default(),
+                    };
+                }
+                if rules != old_rules {
+                    changes.push(format!("peers from {} to {}", old_rules, rules));
+                    Some((filter, rules))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        for section in restart_only_changes(&running, &config) {
+            tracing::warn!("{} changed in the config file, restart to apply", section);
+        }
+
+        if let (Some(filter), Some(log)) = (filter, &self.log) {
+            log.set_filter(filter)?;
+        }
+        if let Some(weights) = weights {
+            self.tx_weights.send_replace(weights);
+        }
+        if let Some((filter, rules)) = peers {
+            filter.set(rules);
+        }
+        *running = config;
+        Ok(changes)
+    }
+
+    /// Whether the value of the flag `id` comes from the file rather than a flag,
+    /// logging that the change to `key` is ignored when it doesn't.
+    fn kept(&self, id: &str, key: &str) -> bool {
+        let from_file = !self.overridden.contains(id);
+        if !from_file {
+            tracing::info!("{} changed in the config file but is set by a flag", key);
+        }
+        from_file
+    }
+}
+
+fn describe(weights: &HashMap<Exchange, f64>) -> String {
+    if weights.is_empty() {
+        return "none".to_string();
+    }
+    let mut pairs = weights
+        .iter()
+        .map(|(exchange, weight)| format!("{}={}", exchange, weight))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs.join(",")
+}
+
+/// The sections of the file changed other than by the reloadable keys
+fn restart_only_changes(old: &Config, new: &Config) -> Vec<&'static str> {
+    let reloadable = |config: &Config| {
+        let mut config = config.clone();
+        config.server.log_filter = None;
+        config.server.allow_peers = None;
+        config.server.deny_peers = None;
+        config.server.unknown_peers = None;
+        let exchanges = &mut config.exchanges;
+        if let Some(binance) = exchanges.binance.as_mut() {
+            binance.weight = None;
+        }
+        if let Some(futures) = exchanges.binance_futures.as_mut() {
+            futures.weight = None;
+        }
+        if let Some(bitstamp) = exchanges.bitstamp.as_mut() {
+            bitstamp.weight = None;
+        }
+        config
+    };
+    let (old, new) = (reloadable(old), reloadable(new));
+    let mut sections = Vec::new();
+    if old.server != new.server {
+        sections.push("[server]");
+    }
+    if old.exchanges != new.exchanges {
+        sections.push("[exchanges]");
+    }
+    if old.limits != new.limits {
+        sections.push("[limits]");
+    }
+    if old.fx != new.fx {
+        sections.push("[fx]");
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::PeerRules;
+
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("obagg-reload-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn it_reloads_the_weights() {
+        let text = "[exchanges.bitstamp]\nweight = 0.5\n";
+        let path = config_file("weights", text);
+        let (tx_weights, rx_weights) = watch::channel(HashMap::from([(Exchange::BITSTAMP, 0.5)]));
+        let reloader = Reloader::new(&path, Config::parse(text).unwrap(), tx_weights);
+
+        assert!(reloader.reload().unwrap().is_empty());
+        std::fs::write(
+            &path,
+            "[server]\nlisten = \"127.0.0.1:9100\"\n[exchanges.bitstamp]\nweight = 0.8\n[exchanges.binance]\nweight = 0\n",
+        )
+        .unwrap();
+        let changes = reloader.reload().unwrap();
+        assert_eq!(
+            changes,
+            ["exchange weights from BITSTAMP=0.5 to BINANCE=0,BITSTAMP=0.8"]
+        );
+        assert_eq!(
+            *rx_weights.borrow(),
+            HashMap::from([(Exchange::BITSTAMP, 0.8), (Exchange::BINANCE, 0.0)])
+        );
+
+        // An invalid file changes nothing.
+        std::fs::write(&path, "[exchanges.bitstamp]\nweight = -1\n").unwrap();
+        let err = reloader.reload().unwrap_err();
+        assert!(format!("{:#}", err).contains("weight"), "{:#}", err);
+        assert_eq!(rx_weights.borrow()[&Exchange::BITSTAMP], 0.8);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn it_reloads_the_peer_ranges() {
+        let text = "[server]\nallow_peers = [\"10.0.0.0/8\"]\n";
+        let path = config_file("peers", text);
+        let config = Config::parse(text).unwrap();
+        let peers = PeerFilter::new(PeerRules {
+            allow: parse_nets(&["10.0.0.0/8"]).unwrap(),
+            ..Default::default()
+        });
+        let reloader = Reloader::new(&path, config, watch::channel(HashMap::new()).0)
+            .with_peers(peers.clone())
+            .with_overridden(["unknown_peers"]);
+        std::fs::write(
+            &path,
+            "[server]\nallow_peers = [\"fd00::/8\"]\ndeny_peers = [\"fd00::1\"]\nunknown_peers = \"deny\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            reloader.reload().unwrap(),
+            ["peers from allow 10.0.0.0/8, deny none, unknown allowed to allow fd00::/8, deny fd00::1/128, unknown allowed"]
+        );
+        assert!(!peers.rules().permits(Some("10.0.0.1".parse().unwrap())));
+        assert!(peers.rules().permits(Some("fd00::2".parse().unwrap())));
+
+        std::fs::write(&path, "[server]\nallow_peers = [\"10.0.0.0/33\"]\n").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(peers.rules().deny.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn it_leaves_values_set_by_flags() {
+        let path = config_file("flags", "[server]\nlog_filter = \"info\"\n");
+        let (tx_weights, rx_weights) = watch::channel(HashMap::new());
+        let reloader = Reloader::new(&path, Config::load(&path).unwrap(), tx_weights)
+            .with_overridden(["exchange_weights"]);
+        std::fs::write(
+            &path,
+            "[server]\nlog_filter = \"debug\"\n[exchanges.bitstamp]\nweight = 0.8\n",
+        )
+        .unwrap();
+        assert_eq!(
+            reloader.reload().unwrap(),
+            ["server.log_filter from info to debug"]
+        );
+        assert!(rx_weights.borrow().is_empty());
+
+        std::fs::write(&path, "[server]\nlog_filter = \"info,=nonsense=\"\n").unwrap();
+        assert!(reloader.reload().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn it_finds_restart_only_changes() {
+        let old = Config::parse("[server]\nlog_filter = \"info\"\n").unwrap();
+        let new = Config::parse(
+            "[server]\nlog_filter = \"debug\"\n[exchanges.binance]\nweight = 2\ndepth_speed_ms = 1000\n",
+        )
+        .unwrap();
+        assert_eq!(restart_only_changes(&old, &new), ["[exchanges]"]);
+        assert!(restart_only_changes(&old, &old).is_empty());
+    }
+}
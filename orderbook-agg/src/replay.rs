@@ -0,0 +1,389 @@
+//! Drives the server from the files written by the [recorder](crate::core::recorder)
+//! instead of live exchanges, for reproducing what a server saw from a capture of it.
+//!
+//! Recorded frames are fed to [process_updates] in place of the websocket stream, so they
+//! are parsed and applied exactly as they were live. Recordings hold no REST snapshots or
+//! exchange info, so each book starts empty and its price and quantity scales and price
+//! range are taken from the levels in the first frames recorded. Diff channels replayed
+//! from the start of a recording only hold the levels that changed since it started.
+use anyhow::{bail, ensure, Context, Result};
+use futures::{SinkExt, Stream};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, watch, RwLock},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::{
+    core::{
+        exchange_book::{process_updates, FromMessage, StreamMessage},
+        num_types::DisplayAmount,
+        order_book::{BookLevels, OrderBook, OrderBookArgs, Update},
+        recorder::{Record, RecordReader},
+        stats::ExchangeStats,
+    },
+    exchanges::connector,
+    service::{spawn_summary_with_feeds, ExchangeOptions, SummarySubscriber},
+    Exchange, Symbol,
+};
+
+/// Frames read from the start of a recording to size its order book from
+const SIZING_FRAMES: usize = 1000;
+
+/// How fast recordings are replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// The time between frames divided by a factor, 1 for the pace they were received at
+    Scaled(f64),
+    /// Each frame as soon as the one before has been read
+    Max,
+}
+
+impl std::str::FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    /// Parses `max` or a factor like `10x`.
+    fn from_str(speed: &str) -> Result<Self> {
+        if speed == "max" {
+            return Ok(Self::Max);
+        }
+        let factor = speed
+            .strip_suffix('x')
+            .and_then(|factor| factor.parse::<f64>().ok())
+            .with_context(|| format!("invalid speed {}, expected e.g. 10x or max", speed))?;
+        ensure!(
+            factor.is_finite() && factor > 0.0,
+            "speed must be more than 0x, got {}",
+            speed
+        );
+        Ok(Self::Scaled(factor))
+    }
+}
+
+/// The recordings in `dir` for each exchange and symbol, in the order they were written.
+pub fn recordings(dir: &Path) -> Result<HashMap<(Exchange, Symbol), Vec<PathBuf>>> {
+    let mut recordings = HashMap::<_, Vec<(u64, PathBuf)>>::new();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read recordings from {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "obrec") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let parsed = match name.split('-').collect::<Vec<_>>()[..] {
+            [exchange, symbol, index] => exchange
+                .parse::<Exchange>()
+                .and_then(|exchange| Ok((exchange, symbol.parse::<Symbol>()?)))
+                .and_then(|key| Ok((key, index.parse::<u64>()?))),
+            _ => bail!("{} isn't named exchange-symbol-index", path.display()),
+        };
+        let (key, index) = parsed.with_context(|| format!("invalid name {}", path.display()))?;
+        recordings.entry(key).or_default().push((index, path));
+    }
+    Ok(recordings
+        .into_iter()
+        .map(|(key, mut files)| {
+            files.sort();
+            (key, files.into_iter().map(|(_, path)| path).collect())
+        })
+        .collect())
+}
+
+fn read(files: &[PathBuf]) -> impl Iterator<Item = Result<Record>> + '_ {
+    files.iter().flat_map(|path| {
+        let records = File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))
+            .and_then(|file| RecordReader::new(BufReader::new(file)));
+        match records {
+            Ok(records) => Box::new(records) as Box<dyn Iterator<Item = Result<Record>>>,
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    })
+}
+
+/// Sizes an order book to hold the levels of the first frames in `files`, with the price
+/// range around the first price seen.
+fn orderbook_args<U: Update + FromMessage>(
+    files: &[PathBuf],
+    price_range: u8,
+) -> Result<OrderBookArgs> {
+    let (mut scale_price, mut scale_quantity) = (0, 0);
+    let mut first_price: Option<DisplayAmount> = None;
+    for record in read(files).take(SIZING_FRAMES) {
+        let data = match record?.message {
+            Message::Binary(data) => U::decode_binary(data)?,
+            message => message.into_data(),
+        };
+        if let Ok(StreamMessage::Update(mut update)) = U::from_message(&data) {
+            let mut levels = update.bids_mut().clone();
+            levels.extend_from_slice(update.asks_mut());
+            for [price, quantity] in levels {
+                scale_price = scale_price.max(price.scale());
+                scale_quantity = scale_quantity.max(quantity.scale());
+                first_price.get_or_insert(price);
+            }
+        }
+    }
+    let price = first_price.context("no levels in the recording to size the book from")?;
+    let (storage_price_min, storage_price_max) =
+        OrderBookArgs::get_min_max(price, price_range, scale_price)?;
+    Ok(OrderBookArgs {
+        storage_price_min,
+        storage_price_max,
+        scale_price,
+        scale_quantity,
+    })
+}
+
+/// Frames as if received on an exchange's update stream
+pub type Frames = Pin<Box<dyn Stream<Item = Result<Message, WsError>> + Send>>;
+
+/// Reads the frames of `files` on their own thread, paced by when they were received.
+fn frames(files: Vec<PathBuf>, speed: ReplaySpeed) -> Frames {
+    let (tx, rx) = mpsc::channel::<Record>(1000);
+    tokio::task::spawn_blocking(move || {
+        for record in read(&files) {
+            match record {
+                Ok(record) => {
+                    if tx.blocking_send(record).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("failed to read recording: {:#}", err);
+                    return;
+                }
+            }
+        }
+    });
+    Box::pin(futures::stream::unfold(
+        (rx, None::<(u64, Instant)>),
+        move |(mut rx, mut origin)| async move {
+            let record = rx.recv().await?;
+            if let ReplaySpeed::Scaled(factor) = speed {
+                let (first, started) = *origin.get_or_insert((record.received, Instant::now()));
+                let since = Duration::from_micros(record.received.saturating_sub(first));
+                tokio::time::sleep_until(started + since.div_f64(factor)).await;
+            }
+            Some((Ok(record.message), (rx, origin)))
+        },
+    ))
+}
+
+/// One exchange's recordings for a symbol, replayed by its
+/// [connector](crate::exchanges::ExchangeConnector::replay)
+pub struct Replay {
+    pub(crate) exchange: Exchange,
+    pub(crate) symbol: Symbol,
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) speed: ReplaySpeed,
+    pub(crate) price_range: u8,
+    pub(crate) levels: u32,
+    pub(crate) stats: Arc<ExchangeStats>,
+    pub(crate) tx_levels: mpsc::Sender<Arc<BookLevels>>,
+}
+
+/// Replays an exchange's recordings into an order book, returning once they've all been
+/// applied.
+#[tracing::instrument(name = "exchange", skip_all, fields(exchange = %replay.exchange, symbol = %replay.symbol))]
+pub(crate) async fn replay_exchange<S, U>(replay: Replay) -> Result<()>
+where
+    S: Update + Default + Send,
+    U: std::fmt::Debug + Update + From<S> + FromMessage + Send + Sync + 'static,
+{
+    let Replay {
+        exchange,
+        symbol,
+        files,
+        speed,
+        price_range,
+        levels,
+        stats,
+        tx_levels,
+    } = replay;
+    let sizing = files.clone();
+    let args = tokio::task::spawn_blocking(move || orderbook_args::<U>(&sizing, price_range))
+        .await?
+        .with_context(|| format!("failed to size the {} {} book", exchange, symbol))?;
+    let orderbook = OrderBook::new(
+        exchange,
+        symbol,
+        args.storage_price_min,
+        args.storage_price_max,
+        args.scale_price,
+        args.scale_quantity,
+    );
+    feed::<S, U>(orderbook, stats, levels, frames(files, speed), tx_levels).await
+}
+
+/// Applies `frames` to an empty `orderbook` as if they'd been received on its update
+/// stream, counting in `stats`, returning once they end.
+pub(crate) async fn feed<S, U>(
+    orderbook: OrderBook,
+    stats: Arc<ExchangeStats>,
+    levels: u32,
+    frames: Frames,
+    tx_levels: mpsc::Sender<Arc<BookLevels>>,
+) -> Result<()>
+where
+    S: Update + Default + Send,
+    U: std::fmt::Debug + Update + From<S> + FromMessage + Send + Sync + 'static,
+{
+    // Nothing is sent back to the frames, pongs included.
+    let sink =
+        futures::sink::drain().sink_map_err(|never: Infallible| -> WsError { match never {} });
+    process_updates::<S, U, _, _>(
+        Arc::new(RwLock::new(orderbook)),
+        stats,
+        levels,
+        S::default(),
+        // Recordings can't be resynced, so updates after a gap are dropped.
+        None,
+        frames,
+        sink,
+        tx_levels,
+    )
+    .await
+}
+
+/// Replays the recordings in `dir` of each of `options.exchanges` for `symbol`, parsed as
+/// the channels in `options`. Returns the subscriber for the summary task like
+/// [start_symbol](crate::service::start_symbol), and a handle that finishes once every
+/// recording has been replayed.
+pub fn replay_symbol(
+    dir: &Path,
+    options: &ExchangeOptions,
+    symbol: Symbol,
+    speed: ReplaySpeed,
+    price_range: u8,
+    levels: u32,
+    tx_serving: watch::Sender<bool>,
+) -> Result<(SummarySubscriber, JoinHandle<()>)> {
+    let mut recordings = recordings(dir)?;
+    let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+    // Recordings ending leaves each exchange's last levels in the summary.
+    let (_, rx_closed) = mpsc::channel(1);
+
+    let mut exchanges = Vec::new();
+    let mut replays = Vec::new();
+    for &exchange in options.exchanges.iter() {
+        let Some(files) = recordings.remove(&(exchange, symbol)) else {
+            tracing::warn!("no {} {} recordings in {}", exchange, symbol, dir.display());
+            continue;
+        };
+        tracing::info!(
+            "replaying {} {} from {} files",
+            exchange,
+            symbol,
+            files.len()
+        );
+        exchanges.push(exchange);
+        let replay = Replay {
+            exchange,
+            symbol,
+            files,
+            speed,
+            price_range,
+            levels,
+            stats: options.stats.feed(exchange, symbol),
+            tx_levels: tx_levels.clone(),
+        };
+        // The updates are parsed as the channel the server would have subscribed to.
+        let replay = tokio::spawn(connector(exchange)?.replay(replay, options));
+        replays.push((exchange, replay));
+    }
+    ensure!(
+        !exchanges.is_empty(),
+        "no {} recordings in {} for the exchanges enabled",
+        symbol,
+        dir.display()
+    );
+
+    let replayed = tokio::spawn(async move {
+        for (exchange, replay) in replays {
+            match replay.await {
+                Ok(Ok(())) => tracing::info!("{} {} replayed", exchange, symbol),
+                Ok(Err(err)) => tracing::error!("{} {} replay failed: {:#}", exchange, symbol, err),
+                Err(err) => tracing::error!("{} {} replay failed: {}", exchange, symbol, err),
+            }
+        }
+    });
+    let tx_summary = spawn_summary_with_feeds(
+        symbol,
+        exchanges,
+        rx_levels,
+        rx_closed,
+        tx_serving,
+        options.summary_feeds(),
+    );
+    Ok((tx_summary, replayed))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    const FIXTURES: &str = "../tests/fixtures/replay";
+
+    #[test]
+    fn it_parses_speeds() {
+        assert_eq!("max".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Max);
+        assert_eq!(
+            "2.5x".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Scaled(2.5)
+        );
+        for speed in ["10", "0x", "-1x", "fast"] {
+            assert!(speed.parse::<ReplaySpeed>().is_err(), "{}", speed);
+        }
+    }
+
+    #[test]
+    fn it_sizes_books_from_the_recorded_levels() {
+        let recordings = recordings(Path::new(FIXTURES)).unwrap();
+        let files = &recordings[&(Exchange::BINANCE, Symbol::BTCUSDT)];
+        let args = orderbook_args::<BinanceUpdate>(files, 5).unwrap();
+        assert_eq!((args.scale_price, args.scale_quantity), (2, 8));
+        // 5% either side of the first price, 30000.00.
+        assert_eq!(args.storage_price_min, 2857143);
+        assert_eq!(args.storage_price_max, 3150000);
+
+        let files = &recordings[&(Exchange::BITSTAMP, Symbol::BTCUSDT)];
+        let args = orderbook_args::<BitstampUpdate>(files, 5).unwrap();
+        assert_eq!((args.scale_price, args.scale_quantity), (0, 8));
+    }
+
+    #[tokio::test]
+    async fn it_paces_frames_by_when_they_were_received() {
+        let recordings = recordings(Path::new(FIXTURES)).unwrap();
+        let files = recordings[&(Exchange::BINANCE, Symbol::BTCUSDT)].clone();
+
+        let started = Instant::now();
+        let count = frames(files.clone(), ReplaySpeed::Max).count().await;
+        assert_eq!(count, 4);
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        // The frames were received 100ms apart, so 300ms from first to last at 1x.
+        let started = Instant::now();
+        assert_eq!(frames(files, ReplaySpeed::Scaled(3.0)).count().await, 4);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(250), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,5220 @@
+//! [OrderbookAggregator] gRPC service that streams summaries aggregated from the
+//! order books of each [Exchange].
+use anyhow::{ensure, Context, Result};
+use futures::{future::BoxFuture, Future, Stream};
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{select, sync::mpsc, sync::oneshot, sync::watch, task::JoinHandle, time::Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tonic_health::server::HealthReporter;
+use tracing::Instrument;
+
+use crate::{
+    alerts,
+    backfill::Backfill,
+    batching::Batcher,
+    book::{watch_book, BookChanges, Books, PublishedBook},
+    book_summary::{
+        orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer},
+        Alert, BookUpdate, Candle, DerivativeInfo, Divergence, Empty, ExchangeStatus, Exchanges,
+        FxRate, MarketType, ReplaySummariesRequest, ServerInfo, ServerLimits, Spread, SpreadStats,
+        SpreadStatsRequest, Stats, Summary, SummaryBatch, SummaryRequest, Symbols, Ticker,
+        TickerRequest, Trade, WatchAlertsRequest, WatchBookRequest, WatchCandlesRequest,
+        WatchDivergenceRequest, WatchExchangeStatusRequest, WatchSpreadRequest,
+        WatchSummaryRequest, WatchTradesRequest,
+    },
+    candles::{watch_candles, CandleBuilder},
+    checksum,
+    core::{
+        exchange_book::{Endpoints, SnapshotDepth},
+        http::HttpClient,
+        order_book::BookLevels,
+        rate_limit::Banned,
+        recorder::Recorder,
+        stats::{ConnectionState, ExchangeStats},
+    },
+    derivatives::Derivatives,
+    divergence::{watch_divergence, Divergences, Quote, Quotes},
+    exchanges::{
+        connector,
+        options::{BitstampChannel, DepthSpeed, TradeStream},
+        BookArgs,
+    },
+    fx::{self, RateSource},
+    liquidity::{self, LiquidityBands},
+    listings::{self, spawn_listing_check, Listings},
+    logging::{self, TraceParent},
+    make_summary,
+    metrics::{self, PipelineStats, ServerCounters},
+    ofi::{self, OrderFlow},
+    pipelines::{Lease, Pipelines},
+    sinks::history::{Rows, SummaryHistory},
+    smoothing::{self, Ema},
+    spread_stats::SpreadSamples,
+    streams::{StreamHandle, StreamRegistry},
+    summary_cache::{CallKey, SummaryCache},
+    ticker::{ticker, watch_spread, watch_ticker},
+    trades::{forward_trades, TradeFeed},
+    Exchange, Symbol,
+};
+
+/// Receives the latest summary, or the status the summary stream ended with.
+pub type SummaryReceiver = watch::Receiver<Result<Arc<Summary>, Status>>;
+
+/// Sends a one shot sender to the summary task, which sends back a [SummaryReceiver].
+pub type SummarySubscriber = mpsc::Sender<oneshot::Sender<SummaryReceiver>>;
+
+/// Sent by an exchange task each time its connection ends.
+#[derive(Debug)]
+pub struct ExchangeClosed {
+    pub exchange: Exchange,
+    pub reason: String,
+    /// Set once the exchange has been without a working connection for longer than
+    /// its [Backoff::budget], or as soon as it has [Banned] this IP.
+    pub lost: bool,
+    /// Set when the connection was closed because the exchange was disabled, see
+    /// [ExchangeSwitches]
+    pub disabled: bool,
+    /// Set when the exchange no longer lists the symbol, see [listings]. It never
+    /// connects again.
+    pub delisted: bool,
+}
+
+/// Delays between attempts to reconnect to an exchange. The delay doubles after each
+/// failed attempt up to `max`, and goes back to `initial` after a connection stays up
+/// for longer than `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// How long the exchange can be down before it's reported as lost.
+    pub budget: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            budget: Duration::from_secs(300),
+        }
+    }
+}
+
+/// How often each exchange's connection is checked for having gone stale
+const STALE_CHECK: Duration = Duration::from_secs(1);
+
+/// How the order books for each exchange connect
+#[derive(Debug, Clone)]
+pub struct ExchangeOptions {
+    /// The exchanges to aggregate, no others are ever contacted
+    pub exchanges: Vec<Exchange>,
+    /// Base urls for each exchange. Exchanges missing use their
+    /// [default endpoints](crate::exchanges::ExchangeConnector::default_endpoints).
+    pub endpoints: HashMap<Exchange, Endpoints>,
+    pub binance_depth_speed: DepthSpeed,
+    /// Streams Binance partial books instead of diffs when the levels fit in one, see
+    /// [DepthMode::select](crate::exchanges::options::DepthMode::select).
+    pub binance_partial_depth: bool,
+    /// Where binance trades are read from with --trades
+    pub binance_trade_stream: TradeStream,
+    pub bitstamp_channel: BitstampChannel,
+    pub snapshot_depth: SnapshotDepth,
+    /// The most levels of each side kept of each exchange's book, which its snapshots ask
+    /// for in place of [snapshot_depth](Self::snapshot_depth). Exchanges missing keep every
+    /// level within the price range. See [clamp_depths].
+    pub depths: HashMap<Exchange, u32>,
+    /// Records the raw frames received from every exchange when set
+    pub recorder: Option<Recorder>,
+    /// Turns the exchanges' connections off and on while the server runs
+    pub switches: ExchangeSwitches,
+    /// Where the order books count what they do
+    pub stats: PipelineStats,
+    /// Where the summary task publishes each exchange's best bid and ask
+    pub quotes: Quotes,
+    /// The bands the summary task reports the liquidity within
+    pub liquidity: LiquidityBands,
+    /// Where the summary task publishes every level near each exchange's best prices
+    pub books: Books,
+    /// Levels to serve as stale until each exchange sends its own, see [warm](crate::warm).
+    /// Only live connections start from them.
+    pub restored: Vec<Arc<BookLevels>>,
+    /// How often each exchange's listing is checked for the symbol, see [listings]
+    pub listing_refresh: Duration,
+    /// Where the summary tasks record the symbols the exchanges have delisted
+    pub listings: Listings,
+    /// Where the summary tasks keep the latest summaries of each symbol to backfill new
+    /// streams with
+    pub backfill: Backfill,
+}
+
+impl Default for ExchangeOptions {
+    fn default() -> Self {
+        Self {
+            exchanges: DEFAULT_EXCHANGES.to_vec(),
+            endpoints: HashMap::new(),
+            binance_depth_speed: DepthSpeed::default(),
+            binance_partial_depth: false,
+            binance_trade_stream: TradeStream::default(),
+            bitstamp_channel: BitstampChannel::default(),
+            snapshot_depth: SnapshotDepth::default(),
+            depths: HashMap::new(),
+            recorder: None,
+            switches: ExchangeSwitches::default(),
+            stats: PipelineStats::default(),
+            quotes: Quotes::default(),
+            liquidity: LiquidityBands::default(),
+            books: Books::default(),
+            restored: Vec::new(),
+            listing_refresh: listings::REFRESH_INTERVAL,
+            listings: Listings::default(),
+            backfill: Backfill::default(),
+        }
+    }
+}
+
+impl ExchangeOptions {
+    /// What the summary tasks of these options publish on
+    pub fn summary_feeds(&self) -> SummaryFeeds {
+        SummaryFeeds {
+            quotes: self.quotes.clone(),
+            liquidity: self.liquidity.clone(),
+            books: self.books.clone(),
+            listings: self.listings.clone(),
+            depths: self.depths.clone(),
+            backfill: self.backfill.clone(),
+        }
+    }
+
+    /// The depth of `exchange`'s book and of its snapshots when serving `levels`
+    fn depths(&self, exchange: Exchange, levels: u32) -> (Option<u32>, Option<u32>) {
+        let depth = self.depths.get(&exchange).copied();
+        (depth, depth.or(self.snapshot_depth.resolve(levels)))
+    }
+}
+
+/// How long a book fetched for `SummaryRequest.symbol_overrides` has to arrive
+const OVERRIDE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The exchange's own name for `symbol`, which its order books stream unless told
+/// otherwise. Exchanges that aren't compiled in get the symbol's own name.
+pub fn native_symbol(exchange: Exchange, symbol: Symbol) -> String {
+    match connector(exchange) {
+        Ok(connector) => connector.native_symbol(symbol),
+        Err(_) => symbol.to_string(),
+    }
+}
+
+/// Whether `exchange` still lists `native` at `endpoints`, or at its default endpoints
+/// without them
+pub async fn fetch_listed(
+    http: &HttpClient,
+    exchange: Exchange,
+    endpoints: Option<Endpoints>,
+    native: &str,
+) -> Result<bool> {
+    let connector = connector(exchange)?;
+    let endpoints = endpoints.unwrap_or_else(|| connector.default_endpoints());
+    connector.fetch_listed(http, &endpoints, native).await
+}
+
+/// Connects to the exchanges outside the summary tasks, for the books of the listings
+/// clients name with `SummaryRequest.symbol_overrides` and the derivatives they ask for
+/// with `SummaryRequest.derivatives`.
+#[derive(Debug, Clone)]
+pub struct Connector {
+    http: HttpClient,
+    options: ExchangeOptions,
+    price_range: u8,
+    levels: u32,
+    derivatives: Derivatives,
+}
+
+impl Connector {
+    /// Connects with the urls and settings of `options`, keeping `price_range` and
+    /// `levels` like [start_symbol]
+    pub fn new(http: HttpClient, options: &ExchangeOptions, price_range: u8, levels: u32) -> Self {
+        Self {
+            derivatives: Derivatives::new(http.clone(), options),
+            http,
+            options: options.clone(),
+            price_range,
+            levels,
+        }
+    }
+
+    /// The mark price and funding of each derivative exchange of `exchanges`, see
+    /// [Derivatives::latest]
+    pub async fn derivatives(
+        &self,
+        symbol: Symbol,
+        exchanges: &[Exchange],
+    ) -> HashMap<String, DerivativeInfo> {
+        self.derivatives.latest(symbol, exchanges).await
+    }
+
+    /// The first levels of `exchange`'s book of its `native` listing, as the levels of
+    /// `symbol`. Fails with [Status::invalid_argument] when the exchange doesn't list it,
+    /// and [Status::unavailable] when its book doesn't arrive.
+    pub async fn fetch_levels(
+        &self,
+        exchange: Exchange,
+        symbol: Symbol,
+        native: &str,
+    ) -> Result<Arc<BookLevels>, Status> {
+        let unlisted = |err: anyhow::Error| {
+            Status::invalid_argument(format!("{} doesn't list {}: {:#}", exchange, native, err))
+        };
+        let connector = connector(exchange).map_err(unlisted)?;
+        let endpoints = self
+            .options
+            .endpoints
+            .get(&exchange)
+            .cloned()
+            .unwrap_or_else(|| connector.default_endpoints());
+        let levels = self.levels;
+        let (depth, snapshot_depth) = self.options.depths(exchange, levels);
+        let (tx_levels, mut rx_levels) = mpsc::channel::<Arc<BookLevels>>(1);
+        let args = BookArgs {
+            http: self.http.clone(),
+            endpoints,
+            symbol,
+            native: Some(native.to_string()),
+            price_range: self.price_range,
+            levels,
+            depth,
+            snapshot_depth,
+            recorder: None,
+            stats: None,
+            tx_levels,
+        };
+        let started = connector
+            .book(args, &self.options)
+            .await
+            .map_err(unlisted)?;
+        let mut book = tokio::spawn(started.in_current_span());
+        let received = tokio::time::timeout(OVERRIDE_TIMEOUT, async {
+            select! {
+                Some(book_levels) = rx_levels.recv() => Ok(book_levels),
+                ended = &mut book => Err(match ended {
+                    Ok(Ok(())) => "the stream ended".to_string(),
+                    Ok(Err(err)) => format!("{:#}", err),
+                    Err(err) => err.to_string(),
+                }),
+            }
+        })
+        .await;
+        book.abort();
+        match received {
+            Ok(Ok(book_levels)) => Ok(book_levels),
+            Ok(Err(reason)) => Err(Status::unavailable(format!(
+                "{} {} sent no book: {}",
+                exchange, native, reason
+            ))),
+            Err(_) => Err(Status::unavailable(format!(
+                "{} {} sent no book within {}s",
+                exchange,
+                native,
+                OVERRIDE_TIMEOUT.as_secs()
+            ))),
+        }
+    }
+}
+
+/// Whether each exchange is enabled, which can be changed while the server runs to pull a
+/// misbehaving exchange out of the summaries without restarting. Clones share the same
+/// switches. Exchanges without a switch are always enabled.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeSwitches {
+    switches: Arc<HashMap<Exchange, watch::Sender<bool>>>,
+    changes: Arc<AtomicU64>,
+}
+
+impl ExchangeSwitches {
+    /// Switches for `exchanges`, all enabled
+    pub fn new(exchanges: &[Exchange]) -> Self {
+        Self {
+            switches: Arc::new(
+                exchanges
+                    .iter()
+                    .map(|&exchange| (exchange, watch::channel(true).0))
+                    .collect(),
+            ),
+            changes: Arc::default(),
+        }
+    }
+
+    /// Enables or disables `exchange`, returning whether that changed it.
+    pub fn set(&self, exchange: Exchange, enabled: bool) -> Result<bool> {
+        let switch = self
+            .switches
+            .get(&exchange)
+            .with_context(|| format!("{} can't be switched on this server", exchange))?;
+        let changed = switch.send_replace(enabled) != enabled;
+        if changed {
+            self.changes.fetch_add(1, Ordering::Relaxed);
+            let state = if enabled { "enabled" } else { "disabled" };
+            tracing::warn!("{} {} at runtime", exchange, state);
+        }
+        Ok(changed)
+    }
+
+    pub fn is_enabled(&self, exchange: Exchange) -> bool {
+        self.switches
+            .get(&exchange)
+            .is_none_or(|switch| *switch.borrow())
+    }
+
+    /// Number of times an exchange has been enabled or disabled
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    /// Follows whether `exchange` is enabled
+    pub fn subscribe(&self, exchange: Exchange) -> watch::Receiver<bool> {
+        match self.switches.get(&exchange) {
+            Some(switch) => switch.subscribe(),
+            None => watch::channel(true).1,
+        }
+    }
+}
+
+/// The spot exchanges, aggregated unless the server is configured otherwise
+pub const DEFAULT_EXCHANGES: [Exchange; 2] = [Exchange::BITSTAMP, Exchange::BINANCE];
+
+/// Parses a list of exchange names given to the server, failing on an empty list or any
+/// name that isn't an exchange so a misconfigured server doesn't start.
+pub fn parse_exchanges<S: AsRef<str>>(names: &[S]) -> Result<Vec<Exchange>> {
+    let mut exchanges = Vec::with_capacity(names.len());
+    for name in names.iter().map(|name| name.as_ref().trim()) {
+        let exchange = name.parse::<Exchange>()?;
+        // Fails for exchanges left out of the build, rather than once they're connected to.
+        connector(exchange)?;
+        if !exchanges.contains(&exchange) {
+            exchanges.push(exchange);
+        }
+    }
+    ensure!(!exchanges.is_empty(), "no exchanges enabled");
+    Ok(exchanges)
+}
+
+/// Parses `exchange=weight` pairs given to the server into the weights of each exchange,
+/// see [OrderbookSummary::with_weights].
+pub fn parse_weights<S: AsRef<str>>(pairs: &[S]) -> Result<HashMap<Exchange, f64>> {
+    let mut weights = HashMap::with_capacity(pairs.len());
+    for pair in pairs.iter().map(|pair| pair.as_ref().trim()) {
+        let (name, weight) = pair
+            .split_once('=')
+            .with_context(|| format!("expected exchange=weight, got {}", pair))?;
+        let weight = weight
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("invalid weight for {}", name))?;
+        check_weight(weight)?;
+        weights.insert(name.trim().parse::<Exchange>()?, weight);
+    }
+    Ok(weights)
+}
+
+/// Parses `exchange=levels` pairs given to the server into the depth of each exchange's
+/// book, see [ExchangeOptions::depths].
+pub fn parse_depths<S: AsRef<str>>(pairs: &[S]) -> Result<HashMap<Exchange, u32>> {
+    let mut depths = HashMap::with_capacity(pairs.len());
+    for pair in pairs.iter().map(|pair| pair.as_ref().trim()) {
+        let (name, depth) = pair
+            .split_once('=')
+            .with_context(|| format!("expected exchange=levels, got {}", pair))?;
+        let depth = depth
+            .trim()
+            .parse::<u32>()
+            .with_context(|| format!("invalid depth for {}", name))?;
+        ensure!(depth > 0, "depths must be above 0, got {}", pair);
+        depths.insert(name.trim().parse::<Exchange>()?, depth);
+    }
+    Ok(depths)
+}
+
+/// The most levels of each side `exchange` gives with `options`, none when its whole book
+/// can be had or it isn't compiled in. Bitstamp's depends on the
+/// [channel](ExchangeOptions::bitstamp_channel) its book is kept up to date from.
+pub fn max_depth(exchange: Exchange, options: &ExchangeOptions) -> Option<u32> {
+    connector(exchange).ok()?.max_depth(options)
+}
+
+/// `depths` with each clamped to the [max_depth] of its exchange
+pub fn clamp_depths(
+    depths: &HashMap<Exchange, u32>,
+    options: &ExchangeOptions,
+) -> HashMap<Exchange, u32> {
+    depths
+        .iter()
+        .map(|(&exchange, &depth)| match max_depth(exchange, options) {
+            Some(max) if depth > max => {
+                tracing::debug!("{} depth {} clamped to {}", exchange, depth, max);
+                (exchange, max)
+            }
+            _ => (exchange, depth),
+        })
+        .collect()
+}
+
+fn check_weight(weight: f64) -> Result<()> {
+    ensure!(
+        weight.is_finite() && weight >= 0.0,
+        "weights must be 0 or more, got {}",
+        weight
+    );
+    Ok(())
+}
+
+/// Starts the order books for each enabled exchange, each retried in the background if it
+/// fails, and returns the subscriber for the summary task that aggregates them. Each
+/// exchange's listing is checked every [ExchangeOptions::listing_refresh], its book
+/// stopping for good once the symbol is delisted.
+/// `tx_serving` is set to false while every exchange is lost, see [spawn_summary].
+pub fn start_symbol(
+    http: HttpClient,
+    options: &ExchangeOptions,
+    symbol: Symbol,
+    price_range: u8,
+    levels: u32,
+    tx_serving: watch::Sender<bool>,
+) -> SummarySubscriber {
+    // The levels senders go into each of the order books to send back the book levels.
+    // The receiver goes to the summary task to create summaries from the book levels.
+    let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+    let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+
+    for book_levels in options.restored.iter() {
+        if book_levels.symbol == symbol && options.exchanges.contains(&book_levels.exchange) {
+            let _ = tx_levels.try_send(book_levels.clone());
+        }
+    }
+
+    for &exchange in options.exchanges.iter() {
+        let connector = match connector(exchange) {
+            Ok(connector) => connector,
+            Err(err) => {
+                tracing::error!("{} {} not started: {:#}", exchange, symbol, err);
+                continue;
+            }
+        };
+        let (depth, snapshot_depth) = options.depths(exchange, levels);
+        let tx_levels = tx_levels.clone();
+        let http = http.clone();
+        let endpoints = options
+            .endpoints
+            .get(&exchange)
+            .cloned()
+            .unwrap_or_else(|| connector.default_endpoints());
+        let tx_closed = tx_closed.clone();
+        let recorder = options.recorder.clone();
+        let rx_enabled = options.switches.subscribe(exchange);
+        let stats = options.stats.feed(exchange, symbol);
+        let tx_delisted = tx_closed.clone();
+        let listed = {
+            let http = http.clone();
+            let endpoints = endpoints.clone();
+            let native = connector.native_symbol(symbol);
+            move || {
+                let http = http.clone();
+                let endpoints = endpoints.clone();
+                let native = native.clone();
+                async move { connector.fetch_listed(&http, &endpoints, &native).await }
+            }
+        };
+        let book_options = options.clone();
+        let feed = spawn_exchange_with_stats(
+            exchange,
+            symbol,
+            Backoff::default(),
+            rx_enabled,
+            tx_closed,
+            stats.clone(),
+            move || {
+                let args = BookArgs {
+                    http: http.clone(),
+                    endpoints: endpoints.clone(),
+                    symbol,
+                    native: None,
+                    price_range,
+                    levels,
+                    depth,
+                    snapshot_depth,
+                    recorder: recorder.clone(),
+                    stats: Some(stats.clone()),
+                    tx_levels: tx_levels.clone(),
+                };
+                let book = connector.book(args, &book_options);
+                async move { book.await?.await }
+            },
+        );
+        spawn_listing_check(
+            exchange,
+            symbol,
+            options.listing_refresh,
+            options.switches.subscribe(exchange),
+            feed,
+            tx_delisted,
+            listed,
+        );
+    }
+
+    spawn_summary_with_feeds(
+        symbol,
+        options.exchanges.clone(),
+        rx_levels,
+        rx_closed,
+        tx_serving,
+        options.summary_feeds(),
+    )
+}
+
+/// Spawns the task running an exchange's order book. Each time the future returned by
+/// `start` finishes, the result is logged and reported to the summary task, so it isn't
+/// lost with the detached task, and `start` is called again after the backoff delay.
+/// While `rx_enabled` is false the future is dropped, closing its connection, the summary
+/// task is told the exchange is [disabled](ExchangeClosed::disabled), and `start` isn't
+/// called again until it's enabled. Stops once the summary task has gone away, dropping
+/// the future and its connection straight away.
+pub fn spawn_exchange<F, Fut>(
+    exchange: Exchange,
+    symbol: Symbol,
+    backoff: Backoff,
+    rx_enabled: watch::Receiver<bool>,
+    tx_closed: mpsc::Sender<ExchangeClosed>,
+    start: F,
+) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    spawn_exchange_with_stats(
+        exchange,
+        symbol,
+        backoff,
+        rx_enabled,
+        tx_closed,
+        Arc::new(ExchangeStats::new(exchange, symbol)),
+        start,
+    )
+}
+
+/// [spawn_exchange], moving the connection of `stats` through each of its states as it
+/// connects, is disabled, closes and waits to reconnect, and checking while it's up
+/// whether it has gone [stale](ConnectionState::Stale). The order book `start` runs
+/// should count what it receives in the same `stats`.
+pub fn spawn_exchange_with_stats<F, Fut>(
+    exchange: Exchange,
+    symbol: Symbol,
+    backoff: Backoff,
+    mut rx_enabled: watch::Receiver<bool>,
+    tx_closed: mpsc::Sender<ExchangeClosed>,
+    stats: Arc<ExchangeStats>,
+    start: F,
+) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    tokio::spawn(
+        async move {
+            let mut delay = backoff.initial;
+            let mut down_since: Option<Instant> = None;
+            // Attempts to reconnect since the connection last stayed up
+            let mut attempt = 0;
+            'connect: loop {
+                if !*rx_enabled.borrow_and_update() {
+                    stats.set_state(ConnectionState::Disabled);
+                    let disabled = ExchangeClosed {
+                        exchange,
+                        reason: "disabled".to_string(),
+                        lost: false,
+                        disabled: true,
+                        delisted: false,
+                    };
+                    if tx_closed.send(disabled).await.is_err() {
+                        break;
+                    }
+                    select! {
+                        _ = switched(&mut rx_enabled, true) => {},
+                        _ = tx_closed.closed() => break,
+                    }
+                    tracing::info!("{} {} enabled", exchange, symbol);
+                    delay = backoff.initial;
+                    down_since = None;
+                    attempt = 0;
+                }
+                let started = Instant::now();
+                let mut banned = false;
+                stats.set_state(ConnectionState::Connecting);
+                let connection = start();
+                tokio::pin!(connection);
+                let mut checks = tokio::time::interval(STALE_CHECK);
+                // Disabling drops the connection along with the order book.
+                let result = loop {
+                    select! {
+                        result = &mut connection => break result,
+                        _ = switched(&mut rx_enabled, false) => continue 'connect,
+                        _ = tx_closed.closed() => break 'connect,
+                        _ = checks.tick() => stats.check_stale(),
+                    }
+                };
+                stats.set_state(ConnectionState::Disconnected);
+                let reason = match result {
+                    Ok(()) => {
+                        tracing::warn!("{} {} stream ended", exchange, symbol);
+                        "stream ended".to_string()
+                    }
+                    Err(err) => {
+                        tracing::error!("{} {} stream failed: {:#}", exchange, symbol, err);
+                        banned = err.chain().any(|cause| cause.is::<Banned>());
+                        format!("{:#}", err)
+                    }
+                };
+
+                // A connection that stayed up for a while starts a new outage, otherwise the
+                // outage goes back to the first attempt that failed.
+                let connected = started.elapsed() > backoff.max;
+                if connected {
+                    delay = backoff.initial;
+                    down_since = None;
+                    attempt = 0;
+                }
+                let down_since =
+                    *down_since.get_or_insert(if connected { Instant::now() } else { started });
+                // A ban lasts for minutes, so the feed counts as lost straight away.
+                let lost = banned || down_since.elapsed() >= backoff.budget;
+                if tx_closed
+                    .send(ExchangeClosed {
+                        exchange,
+                        reason: reason.clone(),
+                        lost,
+                        disabled: false,
+                        delisted: false,
+                    })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+
+                attempt += 1;
+                stats.reconnecting(attempt, now_ms() + delay.as_millis() as u64, reason);
+                select! {
+                    _ = tokio::time::sleep(delay) => {},
+                    _ = switched(&mut rx_enabled, false) => {},
+                    _ = tx_closed.closed() => break,
+                }
+                delay = (delay * 2).min(backoff.max);
+            }
+            stats.set_state(ConnectionState::Disconnected);
+        }
+        .instrument(tracing::info_span!("exchange", %exchange, %symbol)),
+    )
+}
+
+/// Completes once `rx_enabled` is `enabled`, or never if it isn't and can't change.
+async fn switched(rx_enabled: &mut watch::Receiver<bool>, enabled: bool) {
+    if rx_enabled.wait_for(|&on| on == enabled).await.is_err() {
+        futures::future::pending::<()>().await;
+    }
+}
+
+/// Spawns the task that creates a new summary from the latest book levels of each
+/// exchange every time one of them sends an update.
+///
+/// Summaries are made from the exchanges that are currently sending levels, with the
+/// rest of `exchanges` listed as unavailable and the summary marked as degraded. When an
+/// exchange connection closes its levels are dropped until it sends new ones. If every
+/// exchange is unavailable, client streams end with [Status::unavailable] naming each
+/// exchange and the reason it closed, and new clients are refused until an exchange
+/// recovers. Once every exchange is also [lost](ExchangeClosed::lost), `tx_serving` is
+/// set to false, and back to true as soon as any exchange sends levels again.
+///
+/// Each summary is built from the levels the order books sent after releasing their locks,
+/// then published whole by replacing the [Arc] in the watch channel, so clients never see
+/// one part way through being built. While nothing is subscribed to the summaries they
+/// aren't built at all, the next subscriber getting one made from the latest levels.
+///
+/// The task stops once the [SummarySubscriber] returned and its clones are dropped and
+/// nothing is watching the summaries either, which stops the exchange tasks sending it
+/// levels, see [Pipelines](crate::pipelines::Pipelines).
+pub fn spawn_summary(
+    symbol: Symbol,
+    exchanges: Vec<Exchange>,
+    rx_levels: mpsc::Receiver<Arc<BookLevels>>,
+    rx_closed: mpsc::Receiver<ExchangeClosed>,
+    tx_serving: watch::Sender<bool>,
+) -> SummarySubscriber {
+    spawn_summary_with_feeds(
+        symbol,
+        exchanges,
+        rx_levels,
+        rx_closed,
+        tx_serving,
+        SummaryFeeds::default(),
+    )
+}
+
+/// Where the summary task publishes what it sees besides the summaries, and the liquidity
+/// bands it reports on them. Clones share the same feeds.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryFeeds {
+    /// The best bid and ask of each exchange with levels, each time they change
+    pub quotes: Quotes,
+    pub liquidity: LiquidityBands,
+    /// The levels of each exchange's book, each time they change
+    pub books: Books,
+    pub listings: Listings,
+    /// The depth of each exchange's book, reported on the summaries
+    pub depths: HashMap<Exchange, u32>,
+    /// The latest summaries, added before each is published
+    pub backfill: Backfill,
+}
+
+/// [spawn_summary], also publishing on `feeds` and reporting the liquidity within each of
+/// its bands on the summaries.
+pub fn spawn_summary_with_feeds(
+    symbol: Symbol,
+    exchanges: Vec<Exchange>,
+    mut rx_levels: mpsc::Receiver<Arc<BookLevels>>,
+    mut rx_closed: mpsc::Receiver<ExchangeClosed>,
+    tx_serving: watch::Sender<bool>,
+    feeds: SummaryFeeds,
+) -> SummarySubscriber {
+    let SummaryFeeds {
+        quotes,
+        liquidity,
+        books,
+        listings,
+        depths,
+        backfill,
+    } = feeds;
+    // send summary back to tonic server to be consumed by client.
+    let (tx, rx) = watch::channel(Ok(Arc::new(Summary::default())));
+    drop(rx);
+
+    // Receive one shot sender that sends a summary receiver back to the server.
+    let (tx_subscriber, mut rx_subscriber) = mpsc::channel::<oneshot::Sender<SummaryReceiver>>(100);
+
+    tokio::spawn(async move {
+        let mut levels_map = HashMap::<Exchange, Arc<BookLevels>>::new();
+        let mut closed = HashMap::<Exchange, ExchangeClosed>::new();
+        // Disabled exchanges are left out of summaries without marking them degraded.
+        let mut disabled = HashSet::<Exchange>::new();
+        // As are delisted ones, which never come back.
+        let mut delisted = HashSet::<Exchange>::new();
+        let mut flow = OrderFlow::new(ofi::WINDOW);
+        // The times each exchange's connection has closed, telling its rebuilt books apart.
+        let mut connections = HashMap::<Exchange, u64>::new();
+        let mut summary_count = 0;
+        // Summaries aren't built while nothing is watching them, so the next watcher to
+        // subscribe gets one built from the latest levels first.
+        let mut stale = false;
+        // Cleared once every subscriber is dropped, so nothing can watch the summaries anew.
+        let mut subscribable = true;
+        let publish = |count: &mut u64, summary: Result<Summary>, ofi: Option<f64>| match summary {
+            Ok(mut summary) => {
+                *count += 1;
+                summary.sequence = *count;
+                summary.ofi = ofi;
+                // Kept before it's published, so streams starting now find it in the backfill.
+                let summary = Arc::new(summary);
+                backfill.push(symbol, summary.clone());
+                let _ = tx.send_replace(Ok(summary));
+            }
+            Err(err) => tracing::debug!("skipping summary for {}: {:#}", symbol, err),
+        };
+        loop {
+            select! {
+                // Levels are taken before close notices so those sent by an exchange
+                // before it closed aren't applied after it.
+                biased;
+                // Receive a one shot sender that sends a summary receiver back to the server.
+                subscriber = rx_subscriber.recv(), if subscribable => {
+                    let Some(oneshot_sender) = subscriber else {
+                        subscribable = false;
+                        continue;
+                    };
+                    if std::mem::take(&mut stale) {
+                        let summary = summarize(symbol, &exchanges, &levels_map, &disabled, &delisted, &depths, &liquidity.bps());
+                        publish(&mut summary_count, summary, flow.imbalance(now_ms()));
+                    }
+                    if oneshot_sender.send(tx.subscribe()).is_err() {
+                        tracing::debug!("client went away before receiving summary stream");
+                    }
+                    tracing::info!("summary_count: {}, rx count: {}", summary_count, tx.receiver_count());
+                },
+                // Receive book levels from the order books.
+                Some(book_levels) = rx_levels.recv() => {
+                    // Sent before the exchange's book was stopped.
+                    if delisted.contains(&book_levels.exchange) {
+                        continue;
+                    }
+                    if closed.remove(&book_levels.exchange).is_some() {
+                        tracing::info!("{} {} recovered", book_levels.exchange, symbol);
+                    }
+                    disabled.remove(&book_levels.exchange);
+                    if !*tx_serving.borrow() {
+                        tracing::info!("{} summary serving again", symbol);
+                        tx_serving.send_replace(true);
+                    }
+                    let now_ms = now_ms();
+                    // Restored levels are long before the exchange's next, so they're no
+                    // baseline to score its flow from.
+                    match Quote::new(&book_levels).filter(|_| !book_levels.restored) {
+                        Some(quote) => flow.update(quote, now_ms),
+                        None => flow.reset(book_levels.exchange),
+                    }
+                    levels_map.insert(book_levels.exchange, book_levels);
+                    quotes.publish(symbol, levels_map.values().filter_map(|l| Quote::new(l)).collect());
+                    books.publish(symbol, published_books(&levels_map, &connections));
+
+                    // Book levels are stored in the hashmap above and a new summary created from all exchanges
+                    // every time an update is received from any of them.
+                    if tx.receiver_count() == 0 {
+                        stale = true;
+                    } else {
+                        let summary = summarize(symbol, &exchanges, &levels_map, &disabled, &delisted, &depths, &liquidity.bps());
+                        publish(&mut summary_count, summary, flow.imbalance(now_ms));
+                    }
+                },
+                // Receive the reason an exchange connection closed.
+                Some(exchange_closed) = rx_closed.recv() => {
+                    let exchange = exchange_closed.exchange;
+                    // The book is rebuilt from a snapshot when it reconnects.
+                    flow.reset(exchange);
+                    *connections.entry(exchange).or_default() += 1;
+                    // Restored levels are served until the exchange sends its own, however
+                    // many times it fails to connect first, unless it's disabled.
+                    let restored = levels_map.get(&exchange).is_some_and(|l| l.restored);
+                    let gone = exchange_closed.disabled || exchange_closed.delisted;
+                    if (!restored || gone) && levels_map.remove(&exchange).is_some() {
+                        quotes.publish(symbol, levels_map.values().filter_map(|l| Quote::new(l)).collect());
+                        books.publish(symbol, published_books(&levels_map, &connections));
+                    }
+                    if exchange_closed.disabled {
+                        disabled.insert(exchange);
+                    }
+                    if exchange_closed.delisted {
+                        tracing::warn!("{} delisted by {}", symbol, exchange);
+                        delisted.insert(exchange);
+                        listings.delist(exchange, symbol);
+                    }
+                    closed.insert(exchange, exchange_closed);
+                    if delisted.contains(&exchange)
+                        && exchanges.iter().all(|e| delisted.contains(e) || disabled.contains(e))
+                    {
+                        tracing::error!("{} delisted by every enabled exchange", symbol);
+                        listings.withdraw(symbol);
+                        let _ = tx.send_replace(Err(listings::delisted()));
+                        quotes.remove(symbol);
+                        books.remove(symbol);
+                        backfill.remove(symbol);
+                        break;
+                    }
+                    if levels_map.is_empty() && closed.len() == exchanges.len() {
+                        let reasons = exchanges
+                            .iter()
+                            .map(|exchange| format!("{}: {}", exchange, closed[exchange].reason))
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        let message = format!("{} summary unavailable, {}", symbol, reasons);
+                        tracing::error!("{}", message);
+                        let _ = tx.send_replace(Err(Status::unavailable(message)));
+                        stale = false;
+
+                        if closed.values().all(|c| c.lost) && *tx_serving.borrow() {
+                            tracing::error!("{} summary not serving, all exchange feeds lost", symbol);
+                            tx_serving.send_replace(false);
+                        }
+                    } else if disabled.contains(&exchange) || delisted.contains(&exchange) {
+                        // Clients see the exchange leave straight away rather than at the
+                        // next update from another.
+                        if tx.receiver_count() == 0 {
+                            stale = true;
+                        } else {
+                            let summary = summarize(symbol, &exchanges, &levels_map, &disabled, &delisted, &depths, &liquidity.bps());
+                            publish(&mut summary_count, summary, flow.imbalance(now_ms()));
+                        }
+                    } else {
+                        tracing::warn!(
+                            "{} summary degraded, {} closed: {}",
+                            symbol, exchange, closed[&exchange].reason
+                        );
+                    }
+                },
+                // Nothing watches the summaries or can subscribe to them any more.
+                _ = tx.closed(), if !subscribable => {
+                    tracing::info!("{} summary stopped, nothing is watching it", symbol);
+                    quotes.remove(symbol);
+                    books.remove(symbol);
+                    backfill.remove(symbol);
+                    break;
+                },
+                else => break,
+            }
+        }
+    }
+    .instrument(tracing::info_span!("summary", %symbol)));
+    tx_subscriber
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+fn published_books(
+    levels_map: &HashMap<Exchange, Arc<BookLevels>>,
+    connections: &HashMap<Exchange, u64>,
+) -> HashMap<Exchange, PublishedBook> {
+    levels_map
+        .iter()
+        .map(|(&exchange, levels)| {
+            let connection = connections.get(&exchange).copied().unwrap_or_default();
+            (
+                exchange,
+                PublishedBook {
+                    connection,
+                    levels: levels.clone(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// A summary of the latest levels from each exchange, listing the exchanges without
+/// levels that aren't disabled as unavailable, with the liquidity within each of `bps`.
+fn summarize(
+    symbol: Symbol,
+    exchanges: &[Exchange],
+    levels_map: &HashMap<Exchange, Arc<BookLevels>>,
+    disabled: &HashSet<Exchange>,
+    delisted: &HashSet<Exchange>,
+    depths: &HashMap<Exchange, u32>,
+    bps: &[f64],
+) -> Result<Summary> {
+    let current_levels = levels_map
+        .values()
+        .map(|l| l.as_ref())
+        .collect::<Vec<&BookLevels>>();
+    let mut summary = make_summary(&current_levels, symbol)?;
+    let mid = (summary.bids[0].price + summary.asks[0].price) / 2.0;
+    summary.liquidity = liquidity::liquidity(&current_levels, mid, bps);
+    summary.unavailable_exchanges = exchanges
+        .iter()
+        .filter(|exchange| {
+            !levels_map.contains_key(exchange)
+                && !disabled.contains(exchange)
+                && !delisted.contains(exchange)
+        })
+        .map(|exchange| exchange.to_string())
+        .collect();
+    summary.delisted_exchanges = exchanges
+        .iter()
+        .filter(|exchange| delisted.contains(exchange))
+        .map(|exchange| exchange.to_string())
+        .collect();
+    summary.stale_exchanges = exchanges
+        .iter()
+        .filter(|exchange| levels_map.get(exchange).is_some_and(|l| l.restored))
+        .map(|exchange| exchange.to_string())
+        .collect();
+    summary.exchange_depths = exchanges
+        .iter()
+        .filter(|exchange| !delisted.contains(exchange))
+        .filter_map(|exchange| Some((exchange.to_string(), *depths.get(exchange)?)))
+        .collect();
+    summary.degraded =
+        !summary.unavailable_exchanges.is_empty() || !summary.stale_exchanges.is_empty();
+    Ok(summary)
+}
+
+/// Spawns the task that reports the [OrderbookAggregator] service as serving or not
+/// serving on the gRPC health service as `rx_serving` changes.
+pub fn spawn_health_monitor(
+    mut rx_serving: watch::Receiver<bool>,
+    mut reporter: HealthReporter,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let serving = *rx_serving.borrow_and_update();
+            if serving {
+                reporter
+                    .set_serving::<OrderbookAggregatorServer<OrderbookSummary>>()
+                    .await;
+            } else {
+                reporter
+                    .set_not_serving::<OrderbookAggregatorServer<OrderbookSummary>>()
+                    .await;
+            }
+            if rx_serving.changed().await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// What a summary stream makes of each summary before sending it, as its request asks
+struct Shaper {
+    options: WatchSummaryRequest,
+    weights: HashMap<String, f64>,
+    ema: Option<Ema>,
+    started: Instant,
+}
+
+impl Shaper {
+    fn new(options: &WatchSummaryRequest, weights: HashMap<String, f64>) -> Self {
+        Self {
+            options: options.clone(),
+            weights,
+            ema: (options.smoothing_half_life_ms > 0)
+                .then(|| Ema::new(options.smoothing_half_life_ms)),
+            started: Instant::now(),
+        }
+    }
+
+    /// `summary` with the exchanges, weights, levels and liquidity bands asked for, its
+    /// metrics smoothed when asked and its checksum set
+    fn shape(&mut self, summary: &Summary) -> Summary {
+        let options = &self.options;
+        let mut summary = summary.clone();
+        if !options.exchanges.is_empty() {
+            filter_exchanges(&mut summary, &options.exchanges);
+        }
+        if !self.weights.is_empty() {
+            weigh_exchanges(&mut summary, &self.weights);
+        }
+        if !options.exchanges.is_empty() || !self.weights.is_empty() {
+            flag_every_exchange(&mut summary);
+        }
+        cut_levels(
+            &mut summary,
+            SideLevels::of(options.levels, options.bid_levels, options.ask_levels),
+            options.depth_notional,
+        );
+        clear_level_ages(&mut summary);
+        let bps = match options.liquidity_bps.as_slice() {
+            [] => &liquidity::DEFAULT_BPS[..],
+            bps => bps,
+        };
+        summary.liquidity.retain(|band| bps.contains(&band.bps));
+        if let Some(ema) = &mut self.ema {
+            ema.smooth(self.started.elapsed().as_millis() as u64, &mut summary);
+        }
+        checksum::seal(&mut summary);
+        summary
+    }
+}
+
+/// How often a summary stream logs how many summaries it has sent
+const STREAM_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sends the latest summary to a client each time it changes, waiting at least
+/// `min_interval_ms`, or the `update_speed_ms` asked for if it's longer, between sends. A
+/// client that is slow to read skips straight to the latest summary instead of queueing
+/// the ones in between. Ends after sending an error or once the client goes away. Each
+/// summary sent is recorded on the stream's `handle`. Only the liquidity of the bands
+/// asked for is sent, which covers every exchange however the levels were filtered and
+/// is flagged when they were.
+///
+/// The `backfill` asked for is sent first, from the summaries of `symbol` kept in `backfill`
+/// numbered before the first one sent live. Every one of those is kept before it's
+/// published, so the backfill runs straight into the live summaries.
+#[allow(clippy::too_many_arguments)]
+async fn forward_summaries(
+    mut rx_summary: SummaryReceiver,
+    options: WatchSummaryRequest,
+    weights: HashMap<String, f64>,
+    symbol: Symbol,
+    backfill: Backfill,
+    tx: mpsc::Sender<Result<Summary, Status>>,
+    handle: &StreamHandle,
+    counters: &ServerCounters,
+) {
+    let interval =
+        Duration::from_millis(options.min_interval_ms.max(options.update_speed_ms) as u64);
+    let mut sent = 0u64;
+    let mut last_sequence = None;
+    let mut reported = Instant::now();
+    let mut shaper = Shaper::new(&options, weights);
+    let mut backfilling = options.backfill > 0;
+    loop {
+        // Only the pointer is copied while the channel is borrowed, so the summary task never
+        // waits on a client copying a summary to publish the next one.
+        let latest = rx_summary.borrow_and_update().clone();
+        let result = match latest {
+            Ok(summary) => {
+                // The first summary sent follows those kept from before it, as many as asked.
+                if std::mem::take(&mut backfilling) {
+                    let count = options.backfill as usize;
+                    for kept in backfill.before(symbol, summary.sequence, count) {
+                        let mut kept = shaper.shape(&kept);
+                        kept.is_backfill = true;
+                        let sequence = kept.sequence;
+                        if tx.send(Ok(kept)).await.is_err() {
+                            return;
+                        }
+                        handle.sent(sequence);
+                        counters.summaries_sent.incr();
+                    }
+                }
+                Ok(shaper.shape(&summary))
+            }
+            Err(status) => Err(status),
+        };
+        let sequence = result.as_ref().map(|summary| summary.sequence).ok();
+        if tx.send(result).await.is_err() {
+            break;
+        }
+        let Some(sequence) = sequence else {
+            break;
+        };
+        handle.sent(sequence);
+        counters.summaries_sent.incr();
+        // The summaries published while the client was being sent the last one are those
+        // the watch channel coalesced for it.
+        if let Some(last) = last_sequence.replace(sequence) {
+            counters
+                .summaries_coalesced
+                .add(sequence.saturating_sub(last + 1));
+        }
+        // Progress goes on the stream's span now and then rather than once a summary.
+        sent += 1;
+        if reported.elapsed() >= STREAM_REPORT_INTERVAL {
+            tracing::debug!(sent, "forwarding summaries");
+            reported = Instant::now();
+        }
+        if !interval.is_zero() {
+            tokio::time::sleep(interval).await;
+        }
+        if rx_summary.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends every summary to a client in the batches `batcher` makes of them, each once it's
+/// full or its first summary has waited the delay asked for. Summaries the watch channel
+/// coalesced while the client was behind are taken from those of `symbol` kept in
+/// `backfill`, so batches only break where it no longer has them. The `backfill` asked
+/// for is sent first, as by [forward_summaries]. Ends after sending an error, once the
+/// summaries end or once the client goes away.
+#[allow(clippy::too_many_arguments)]
+async fn forward_batches(
+    mut rx_summary: SummaryReceiver,
+    options: WatchSummaryRequest,
+    weights: HashMap<String, f64>,
+    symbol: Symbol,
+    backfill: Backfill,
+    mut batcher: Batcher,
+    tx: mpsc::Sender<Result<SummaryBatch, Status>>,
+    handle: &StreamHandle,
+    counters: &ServerCounters,
+) {
+    let mut shaper = Shaper::new(&options, weights);
+    let mut last_sequence: Option<u64> = None;
+    let mut changed = true;
+    loop {
+        if std::mem::take(&mut changed) {
+            let latest = rx_summary.borrow_and_update().clone();
+            let ready = match latest {
+                // Nothing has been made to send yet.
+                Ok(summary) if summary.sequence == 0 => Vec::new(),
+                Ok(summary) => {
+                    // The first summary follows as many kept from before it as asked, and
+                    // the rest follow the ones the client skipped since the last.
+                    let (count, is_backfill) = match last_sequence {
+                        None => (options.backfill as u64, true),
+                        Some(last) => (summary.sequence.saturating_sub(last + 1), false),
+                    };
+                    let kept = backfill.before(symbol, summary.sequence, count as usize);
+                    if !is_backfill {
+                        counters.summaries_coalesced.add(count - kept.len() as u64);
+                    }
+                    last_sequence = Some(summary.sequence);
+                    let mut ready = kept
+                        .iter()
+                        .map(|kept| Summary {
+                            is_backfill,
+                            ..shaper.shape(kept)
+                        })
+                        .collect::<Vec<_>>();
+                    ready.push(shaper.shape(&summary));
+                    ready
+                }
+                Err(status) => {
+                    if let Some(batch) = batcher.take() {
+                        if !send_batch(&tx, batch, handle, counters).await {
+                            return;
+                        }
+                    }
+                    let _ = tx.send(Err(status)).await;
+                    return;
+                }
+            };
+            let now = Instant::now();
+            for summary in ready {
+                for batch in batcher.push(summary, now) {
+                    if !send_batch(&tx, batch, handle, counters).await {
+                        return;
+                    }
+                }
+            }
+        }
+        let deadline = batcher.deadline();
+        let due = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            result = rx_summary.changed() => {
+                if result.is_err() {
+                    if let Some(batch) = batcher.take() {
+                        send_batch(&tx, batch, handle, counters).await;
+                    }
+                    return;
+                }
+                changed = true;
+            }
+            _ = due => {
+                if let Some(batch) = batcher.take() {
+                    if !send_batch(&tx, batch, handle, counters).await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends `batch` to the client, recording each of its summaries on the stream's `handle`.
+/// Returns whether the client is still there.
+async fn send_batch(
+    tx: &mpsc::Sender<Result<SummaryBatch, Status>>,
+    batch: SummaryBatch,
+    handle: &StreamHandle,
+    counters: &ServerCounters,
+) -> bool {
+    let sequences = batch
+        .summaries
+        .iter()
+        .map(|summary| summary.sequence)
+        .collect::<Vec<_>>();
+    if tx.send(Ok(batch)).await.is_err() {
+        return false;
+    }
+    for sequence in sequences.iter() {
+        handle.sent(*sequence);
+    }
+    counters.summaries_sent.add(sequences.len() as u64);
+    counters.summary_batches_sent.incr();
+    true
+}
+
+/// How many of the best levels of each side a client asked for, none for every level
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SideLevels {
+    bids: Option<u32>,
+    asks: Option<u32>,
+}
+
+impl SideLevels {
+    /// `levels` of each side, every level when 0
+    fn both(levels: u32) -> Self {
+        let levels = (levels > 0).then_some(levels);
+        Self {
+            bids: levels,
+            asks: levels,
+        }
+    }
+
+    /// `levels` of each side, or the side's own count in place of it when set. A side asked
+    /// for 0 levels is left out.
+    fn of(levels: u32, bids: Option<u32>, asks: Option<u32>) -> Self {
+        let both = Self::both(levels);
+        Self {
+            bids: bids.or(both.bids),
+            asks: asks.or(both.asks),
+        }
+    }
+}
+
+/// Cuts each side of `summary` to its best `levels`, and to the levels it takes for the
+/// price times quantity to add up to `depth_notional`, keeping the level that gets there.
+/// The notional is left alone when 0.
+fn cut_levels(summary: &mut Summary, levels: SideLevels, depth_notional: f64) {
+    for (side, levels) in [
+        (&mut summary.bids, levels.bids),
+        (&mut summary.asks, levels.asks),
+    ] {
+        if let Some(levels) = levels {
+            side.truncate(levels as usize);
+        }
+        if depth_notional > 0.0 {
+            let mut notional = 0.0;
+            if let Some(last) = side.iter().position(|level| {
+                notional += level.price * level.quantity;
+                notional >= depth_notional
+            }) {
+                side.truncate(last + 1);
+            }
+        }
+    }
+}
+
+/// Drops when each level was last updated, which only `GetSummary` sends, and only when
+/// asked for with `include_level_ages`
+pub(crate) fn clear_level_ages(summary: &mut Summary) {
+    for level in summary.bids.iter_mut().chain(summary.asks.iter_mut()) {
+        level.updated_at = None;
+    }
+}
+
+/// Keeps the best `depths` levels of each side of each exchange, keyed by its name, noting
+/// them on the summary's `exchange_depths`. The best level is always kept, so the spread
+/// doesn't change.
+fn limit_depths(summary: &mut Summary, depths: &HashMap<String, u32>) {
+    if depths.is_empty() {
+        return;
+    }
+    for side in [&mut summary.bids, &mut summary.asks] {
+        let mut counts = HashMap::<String, u32>::new();
+        side.retain(|level| match depths.get(&level.exchange) {
+            Some(&depth) => {
+                let count = counts.entry(level.exchange.clone()).or_default();
+                *count += 1;
+                *count <= depth
+            }
+            None => true,
+        });
+    }
+    for (exchange, &depth) in depths.iter() {
+        summary.exchange_depths.insert(exchange.clone(), depth);
+    }
+}
+
+fn notional_problem(depth_notional: f64) -> Option<Status> {
+    (!depth_notional.is_finite() || depth_notional < 0.0)
+        .then(|| Status::invalid_argument("depth_notional must be 0 or more"))
+}
+
+/// Drops the levels from exchanges other than `exchanges`, along with them from the
+/// unavailable and stale exchanges, and works the spread out again from the levels left.
+fn filter_exchanges(summary: &mut Summary, exchanges: &[String]) {
+    retain_exchanges(summary, |exchange| {
+        exchanges.iter().any(|e| e.eq_ignore_ascii_case(exchange))
+    });
+}
+
+/// Multiplies the quantities of each exchange's levels by its weight, keyed by the
+/// exchange's name, leaving out exchanges weighted 0. Prices and so the order of the
+/// levels are unchanged.
+fn weigh_exchanges(summary: &mut Summary, weights: &HashMap<String, f64>) {
+    retain_exchanges(summary, |exchange| weights.get(exchange) != Some(&0.0));
+    for level in summary.bids.iter_mut().chain(summary.asks.iter_mut()) {
+        if let Some(weight) = weights.get(&level.exchange) {
+            level.quantity *= weight;
+        }
+    }
+}
+
+/// Flags the liquidity bands as counting every exchange in full, once some of the levels
+/// have been left out or weighed.
+fn flag_every_exchange(summary: &mut Summary) {
+    for band in &mut summary.liquidity {
+        band.every_exchange = true;
+    }
+}
+
+fn retain_exchanges(summary: &mut Summary, wanted: impl Fn(&str) -> bool) {
+    summary.bids.retain(|level| wanted(&level.exchange));
+    summary.asks.retain(|level| wanted(&level.exchange));
+    summary
+        .unavailable_exchanges
+        .retain(|exchange| wanted(exchange));
+    summary.stale_exchanges.retain(|exchange| wanted(exchange));
+    summary
+        .delisted_exchanges
+        .retain(|exchange| wanted(exchange));
+    summary
+        .exchange_depths
+        .retain(|exchange, _| wanted(exchange));
+    summary
+        .native_symbols
+        .retain(|exchange, _| wanted(exchange));
+    summary.degraded =
+        !summary.unavailable_exchanges.is_empty() || !summary.stale_exchanges.is_empty();
+    summary.spread = match (summary.bids.first(), summary.asks.first()) {
+        (Some(bid), Some(ask)) => ask.price - bid.price,
+        _ => 0.0,
+    };
+}
+
+#[derive(Debug)]
+pub struct OrderbookSummary {
+    tx_summary: SummarySubscriber,
+    warm: HashMap<Symbol, SummarySubscriber>,
+    shutdown: watch::Receiver<bool>,
+    exchanges: Vec<Exchange>,
+    weights: watch::Receiver<HashMap<Exchange, f64>>,
+    symbols: Vec<Symbol>,
+    limits: ServerLimits,
+    listen_addrs: Vec<String>,
+    started: Instant,
+    streams: StreamRegistry,
+    switches: ExchangeSwitches,
+    history: Option<Arc<dyn SummaryHistory>>,
+    spread_samples: Vec<SpreadSamples>,
+    stats: PipelineStats,
+    quotes: Quotes,
+    trades: Vec<TradeFeed>,
+    liquidity: LiquidityBands,
+    books: Books,
+    connector: Option<Connector>,
+    listings: Listings,
+    depths: HashMap<Exchange, u32>,
+    rates: Option<Arc<dyn RateSource>>,
+    backfill: Backfill,
+    pipelines: Pipelines,
+    calls: SummaryCache,
+}
+
+impl OrderbookSummary {
+    /// Creates the service. Streams to clients complete once `true` is sent on the
+    /// `shutdown` channel.
+    pub fn new(tx_summary: SummarySubscriber, shutdown: watch::Receiver<bool>) -> Self {
+        Self {
+            tx_summary,
+            warm: HashMap::new(),
+            shutdown,
+            exchanges: DEFAULT_EXCHANGES.to_vec(),
+            weights: watch::channel(HashMap::new()).1,
+            symbols: vec![Symbol::default()],
+            limits: ServerLimits::default(),
+            listen_addrs: Vec::new(),
+            started: Instant::now(),
+            streams: StreamRegistry::new(),
+            switches: ExchangeSwitches::default(),
+            history: None,
+            spread_samples: Vec::new(),
+            stats: PipelineStats::default(),
+            quotes: Quotes::default(),
+            trades: Vec::new(),
+            liquidity: LiquidityBands::default(),
+            books: Books::default(),
+            connector: None,
+            listings: Listings::default(),
+            depths: HashMap::new(),
+            rates: None,
+            backfill: Backfill::new(0),
+            pipelines: Pipelines::default(),
+            calls: SummaryCache::default(),
+        }
+    }
+
+    /// Sets the symbols reported to clients, which should be the ones summaries are
+    /// published for.
+    pub fn with_symbols(mut self, symbols: Vec<Symbol>) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Serves summaries of each symbol of `warm` from its summary task as well as those
+    /// of the server's own symbols, see [warmup](crate::warmup).
+    pub fn with_warm_symbols(mut self, warm: HashMap<Symbol, SummarySubscriber>) -> Self {
+        self.warm = warm;
+        self
+    }
+
+    /// Serves summaries of the symbols of `pipelines` too, starting the summary task of each
+    /// when a client first asks for it. Subscribers of every symbol are counted on it.
+    pub fn with_pipelines(mut self, pipelines: Pipelines) -> Self {
+        self.pipelines = pipelines;
+        self
+    }
+
+    /// Sets the exchanges reported to clients and that they can filter summaries to,
+    /// which should be the ones the summaries are aggregated from.
+    pub fn with_exchanges(mut self, exchanges: Vec<Exchange>) -> Self {
+        self.exchanges = exchanges;
+        self
+    }
+
+    /// Sets the weights exchanges' quantities are multiplied by for clients that don't
+    /// give their own, 1 for exchanges missing. Exchanges weighted 0 are left out.
+    pub fn with_weights(mut self, weights: HashMap<Exchange, f64>) -> Self {
+        self.weights = watch::channel(weights).1;
+        self
+    }
+
+    /// Like [with_weights](Self::with_weights), with the weights changed by sending new
+    /// ones. Streams opened after a change use the new weights.
+    pub fn with_weight_updates(mut self, weights: watch::Receiver<HashMap<Exchange, f64>>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Sets the registry client streams are added to while they're open, to share it
+    /// with the [admin service](crate::admin::AdminService).
+    pub fn with_streams(mut self, streams: StreamRegistry) -> Self {
+        self.streams = streams;
+        self
+    }
+
+    /// Sets the switches the exchanges were started with, for reporting which are
+    /// disabled.
+    pub fn with_switches(mut self, switches: ExchangeSwitches) -> Self {
+        self.switches = switches;
+        self
+    }
+
+    /// Sets the limits reported in [ServerInfo], which should be the ones the summaries
+    /// are made with.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reports `addrs` as those the server is listening on in `GetServerInfo`
+    pub fn with_listen_addrs(mut self, addrs: Vec<String>) -> Self {
+        self.listen_addrs = addrs;
+        self
+    }
+
+    /// Serves `ReplaySummaries` from `history`, which the rpc fails without.
+    pub fn with_history(mut self, history: Arc<dyn SummaryHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Counts the streams and summaries sent in `stats`, which `GetStats` returns. They
+    /// should be the stats the order books count in, see [ExchangeOptions::stats].
+    pub fn with_stats(mut self, stats: PipelineStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Serves `WatchDivergence` from `quotes`, which should be the quotes the summary
+    /// tasks publish on, see [ExchangeOptions::quotes].
+    pub fn with_quotes(mut self, quotes: Quotes) -> Self {
+        self.quotes = quotes;
+        self
+    }
+
+    /// Registers the liquidity bands clients ask for on `bands`, which should be the bands
+    /// the summary tasks report, see [ExchangeOptions::liquidity].
+    pub fn with_liquidity_bands(mut self, bands: LiquidityBands) -> Self {
+        self.liquidity = bands;
+        self
+    }
+
+    /// Serves `WatchBook` from `books`, which should be the books the summary tasks
+    /// publish on, see [ExchangeOptions::books].
+    pub fn with_books(mut self, books: Books) -> Self {
+        self.books = books;
+        self
+    }
+
+    /// Refuses requests for the symbols the summary tasks record as delisted on `listings`,
+    /// which should be [ExchangeOptions::listings].
+    pub fn with_listings(mut self, listings: Listings) -> Self {
+        self.listings = listings;
+        self
+    }
+
+    /// Clamps the depths `GetSummary` clients ask for to `depths`, which should be
+    /// [ExchangeOptions::depths].
+    pub fn with_depths(mut self, depths: HashMap<Exchange, u32>) -> Self {
+        self.depths = depths;
+        self
+    }
+
+    /// Fetches the books of the listings `GetSummary` clients name with `connector`,
+    /// which the rpc refuses them without. The rest of each summary comes from the books,
+    /// see [with_books](Self::with_books).
+    pub fn with_connector(mut self, connector: Connector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// Shares the summaries `GetSummary` builds for the calls it can't answer with the
+    /// published summary between calls asking for the same one, see
+    /// [summary_cache](crate::summary_cache).
+    pub fn with_summary_cache(mut self, cache: SummaryCache) -> Self {
+        self.calls = cache;
+        self
+    }
+
+    /// Converts the prices of `GetSummary` clients naming a quote currency with `rates`,
+    /// which the rpc refuses them without unless no exchange needs converting.
+    pub fn with_rates(mut self, rates: Arc<dyn RateSource>) -> Self {
+        self.rates = Some(rates);
+        self
+    }
+
+    /// Serves `WatchTrades` for the symbol of `feed`.
+    pub fn with_trades(mut self, feed: TradeFeed) -> Self {
+        self.trades.push(feed);
+        self
+    }
+
+    /// Backfills the streams asking for it from the summaries the summary tasks keep in
+    /// `backfill`, which should be those of [ExchangeOptions::backfill]. Streams start
+    /// live without it.
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    /// Serves `GetSpreadStats` for the symbol of `samples`.
+    pub fn with_spread_samples(mut self, samples: SpreadSamples) -> Self {
+        self.spread_samples.push(samples);
+        self
+    }
+
+    /// What the server is running, with the build info embedded by the build script
+    pub fn server_info(&self) -> ServerInfo {
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: env!("ORDERBOOK_GIT_COMMIT").to_string(),
+            build_timestamp: env!("ORDERBOOK_BUILD_TIMESTAMP")
+                .parse()
+                .unwrap_or_default(),
+            features: env!("ORDERBOOK_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(|feature| feature.to_string())
+                .collect(),
+            exchanges: self.exchanges.iter().map(|e| e.to_string()).collect(),
+            symbols: self.summarized().map(|s| s.to_string()).collect(),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            limits: Some(self.limits.clone()),
+            listen_addrs: self.listen_addrs.clone(),
+        }
+    }
+
+    /// The server's weights overridden by those a client asked for, leaving out those of 1.
+    // Statuses are only returned while handling a request, never stored.
+    #[allow(clippy::result_large_err)]
+    fn client_weights(
+        &self,
+        requested: &HashMap<String, f64>,
+    ) -> Result<HashMap<String, f64>, Status> {
+        let mut weights = self.weights.borrow().clone();
+        for (name, &weight) in requested.iter() {
+            let exchange = self.enabled_exchange(name)?;
+            check_weight(weight).map_err(|err| {
+                Status::invalid_argument(format!("invalid weight for {}: {}", exchange, err))
+            })?;
+            weights.insert(exchange, weight);
+        }
+        Ok(weights
+            .into_iter()
+            .filter(|(_, weight)| *weight != 1.0)
+            .map(|(exchange, weight)| (exchange.to_string(), weight))
+            .collect())
+    }
+
+    /// Checks every field of a `WatchSummary` request, returning the symbol it watches and
+    /// the weights it asks for on top of the server's. See [reject] for the status of a
+    /// request with problems.
+    #[allow(clippy::result_large_err)]
+    fn validate_watch_request(
+        &self,
+        options: &WatchSummaryRequest,
+    ) -> Result<(Symbol, HashMap<String, f64>), Status> {
+        let mut problems = Vec::new();
+        let symbol = match options.symbol.as_str() {
+            "" => self.symbols.first().copied().unwrap_or_default(),
+            name => self.summary_symbol(name, &mut problems),
+        };
+        problems.extend(self.check_listed(symbol).err());
+        for name in options.exchanges.iter() {
+            problems.extend(self.enabled_exchange(name).err());
+        }
+        let weights = self
+            .client_weights(&options.exchange_weights)
+            .map_err(|status| problems.push(status))
+            .ok();
+        if options.update_speed_ms != 0 {
+            match DepthSpeed::from_millis(options.update_speed_ms) {
+                None => problems.push(Status::invalid_argument(
+                    "update_speed_ms must be 0, 100 or 1000",
+                )),
+                // Unthrottled 100ms updates would send a client every message of a busy
+                // market.
+                Some(DepthSpeed::Ms100) if options.min_interval_ms == 0 => {
+                    problems.push(Status::invalid_argument(
+                        "update_speed_ms of 100 needs min_interval_ms to be set",
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        if let Err(err) = liquidity::validate_bps(&options.liquidity_bps) {
+            problems.push(Status::invalid_argument(err.to_string()));
+        }
+        problems.extend(self.levels_problem("levels", options.levels));
+        for (name, levels) in [
+            ("bid_levels", options.bid_levels),
+            ("ask_levels", options.ask_levels),
+        ] {
+            problems.extend(levels.and_then(|levels| self.levels_problem(name, levels)));
+        }
+        problems.extend(notional_problem(options.depth_notional));
+        if options.smoothing_half_life_ms > smoothing::MAX_HALF_LIFE_MS {
+            problems.push(Status::invalid_argument(format!(
+                "smoothing_half_life_ms must be at most {}",
+                smoothing::MAX_HALF_LIFE_MS
+            )));
+        }
+        reject(problems)?;
+        Ok((symbol, weights.unwrap_or_default()))
+    }
+
+    /// The symbol a summary request names, adding a problem when it isn't one or the
+    /// server doesn't summarize it
+    fn summary_symbol(&self, name: &str, problems: &mut Vec<Status>) -> Symbol {
+        match name.parse::<Symbol>() {
+            Ok(symbol) if !self.summarized().any(|s| *s == symbol) => {
+                problems.push(Status::failed_precondition(format!(
+                    "{} isn't being watched, summaries are only made for the symbols the server aggregates",
+                    symbol
+                )));
+                symbol
+            }
+            Ok(symbol) => symbol,
+            Err(err) => {
+                problems.push(Status::invalid_argument(err.to_string()));
+                Symbol::default()
+            }
+        }
+    }
+
+    /// Summaries never have more levels than [ServerLimits::levels], so asking for more
+    /// is a mistake rather than a reason to send fewer. No limit is set by default.
+    /// `field` names the levels asked for in the problem.
+    fn levels_problem(&self, field: &str, levels: u32) -> Option<Status> {
+        let max = self.limits.levels;
+        (max > 0 && levels > max).then(|| {
+            Status::invalid_argument(format!(
+                "{} must be at most {}, the levels summaries are made with",
+                field, max
+            ))
+        })
+    }
+
+    /// The listings a `GetSummary` request names by exchange, adding a problem for each
+    /// exchange that's unknown, not aggregated or disabled and each listing left empty.
+    fn symbol_overrides(
+        &self,
+        requested: &HashMap<String, String>,
+        problems: &mut Vec<Status>,
+    ) -> HashMap<Exchange, String> {
+        let mut overrides = HashMap::with_capacity(requested.len());
+        for (name, native) in requested.iter() {
+            let exchange = match name.parse::<Exchange>() {
+                Ok(exchange) => exchange,
+                Err(err) => {
+                    problems.push(Status::invalid_argument(err.to_string()));
+                    continue;
+                }
+            };
+            let problem = if !self.exchanges.contains(&exchange) {
+                Some("isn't aggregated by this server")
+            } else if !self.switches.is_enabled(exchange) {
+                Some("is disabled")
+            } else if native.trim().is_empty() {
+                Some("has an empty symbol")
+            } else {
+                None
+            };
+            match problem {
+                Some(problem) => problems.push(Status::invalid_argument(format!(
+                    "symbol_overrides for {} {}",
+                    exchange, problem
+                ))),
+                None => {
+                    overrides.insert(exchange, native.trim().to_string());
+                }
+            }
+        }
+        overrides
+    }
+
+    /// The depths a `GetSummary` request asks for by exchange name, each clamped to the
+    /// depth kept of the exchange's book
+    fn request_depths(
+        &self,
+        requested: &HashMap<String, u32>,
+        problems: &mut Vec<Status>,
+    ) -> HashMap<String, u32> {
+        let mut depths = HashMap::with_capacity(requested.len());
+        for (name, &depth) in requested.iter() {
+            let exchange = match name.parse::<Exchange>() {
+                Ok(exchange) => exchange,
+                Err(err) => {
+                    problems.push(Status::invalid_argument(err.to_string()));
+                    continue;
+                }
+            };
+            if !self.exchanges.contains(&exchange) {
+                problems.push(Status::invalid_argument(format!(
+                    "exchange_depths for {} isn't aggregated by this server",
+                    exchange
+                )));
+            } else if depth == 0 {
+                problems.push(Status::invalid_argument(format!(
+                    "exchange_depths for {} must be above 0",
+                    exchange
+                )));
+            } else {
+                let kept = self.depths.get(&exchange).copied().unwrap_or(u32::MAX);
+                if depth > kept {
+                    tracing::debug!("{} depth {} clamped to {}", exchange, depth, kept);
+                }
+                depths.insert(exchange.to_string(), depth.min(kept));
+            }
+        }
+        depths
+    }
+
+    /// The market types a `GetSummary` request names, adding a problem for each unknown one
+    fn request_market_types(
+        &self,
+        requested: &[i32],
+        problems: &mut Vec<Status>,
+    ) -> Vec<MarketType> {
+        let mut market_types = Vec::with_capacity(requested.len());
+        for &value in requested {
+            match MarketType::from_i32(value) {
+                Some(market_type) => market_types.push(market_type),
+                None => problems.push(Status::invalid_argument(format!(
+                    "unknown market type {}, expected spot or futures",
+                    value
+                ))),
+            }
+        }
+        market_types
+    }
+
+    /// The exchanges of `market_types` to summarize `symbol` from, every one when none or
+    /// both are named. Futures asked for when no futures exchange has a book of the symbol,
+    /// because none lists it or none is connected, fall back to spot, noting it in `notes`.
+    fn market_exchanges(
+        &self,
+        symbol: Symbol,
+        market_types: &[MarketType],
+        notes: &mut Vec<String>,
+    ) -> Vec<Exchange> {
+        let of = |market_type: MarketType| {
+            self.exchanges
+                .iter()
+                .copied()
+                .filter(move |exchange| exchange.market_type() == market_type)
+        };
+        let with_spot = market_types.is_empty() || market_types.contains(&MarketType::Spot);
+        let with_futures = market_types.is_empty() || market_types.contains(&MarketType::Futures);
+        if with_futures && !market_types.is_empty() {
+            let published = self.books.subscribe(symbol).borrow().clone();
+            let delisted = self.listings.delisted_exchanges(symbol);
+            let live = of(MarketType::Futures).any(|exchange| {
+                published.contains_key(&exchange)
+                    && self.switches.is_enabled(exchange)
+                    && !delisted.contains(&exchange)
+            });
+            if !live {
+                notes.push(format!(
+                    "no futures book of {} is available, showing spot only",
+                    symbol
+                ));
+                return of(MarketType::Spot).collect();
+            }
+        }
+        match (with_spot, with_futures) {
+            (true, true) => self.exchanges.clone(),
+            (true, false) => of(MarketType::Spot).collect(),
+            _ => of(MarketType::Futures).collect(),
+        }
+    }
+
+    /// The rate to convert each exchange's prices by for a `GetSummary` request asking for
+    /// `quote_currency`, leaving out exchanges already quoted in it. Fails with
+    /// [Status::failed_precondition] for an exchange without a rate.
+    #[allow(clippy::result_large_err)]
+    fn fx_rates(
+        &self,
+        symbol: Symbol,
+        overrides: &HashMap<Exchange, String>,
+        quote_currency: &str,
+    ) -> Result<HashMap<Exchange, FxRate>, Status> {
+        let mut rates = HashMap::new();
+        if quote_currency.is_empty() {
+            return Ok(rates);
+        }
+        let delisted = self.listings.delisted_exchanges(symbol);
+        for &exchange in self.exchanges.iter() {
+            if !self.switches.is_enabled(exchange)
+                || (delisted.contains(&exchange) && !overrides.contains_key(&exchange))
+            {
+                continue;
+            }
+            let native = overrides
+                .get(&exchange)
+                .cloned()
+                .unwrap_or_else(|| native_symbol(exchange, symbol));
+            let from = fx::quote_currency(&native).ok_or_else(|| {
+                Status::failed_precondition(format!(
+                    "the quote currency of {} on {} isn't known",
+                    native, exchange
+                ))
+            })?;
+            if from == quote_currency {
+                continue;
+            }
+            let rate = self
+                .rates
+                .as_ref()
+                .and_then(|rates| rates.rate(from, quote_currency))
+                .ok_or_else(|| {
+                    Status::failed_precondition(format!(
+                        "no fx rate from {} to {} is configured for {}",
+                        from, quote_currency, exchange
+                    ))
+                })?;
+            rates.insert(exchange, rate);
+        }
+        Ok(rates)
+    }
+
+    /// A summary of the books of `exchanges`, fetched for those in `overrides` and the
+    /// latest of the others, each converted by its rate of `rates`, with the liquidity
+    /// within each of `bps`. It's made for one call alone, so it has no sequence.
+    async fn summarize_call(
+        &self,
+        symbol: Symbol,
+        exchanges: &[Exchange],
+        overrides: &HashMap<Exchange, String>,
+        rates: &HashMap<Exchange, FxRate>,
+        bps: &[f64],
+    ) -> Result<Summary, Status> {
+        let fetched =
+            match &self.connector {
+                _ if overrides.is_empty() => Vec::new(),
+                Some(connector) => {
+                    futures::future::try_join_all(overrides.iter().map(|(&exchange, native)| {
+                        connector.fetch_levels(exchange, symbol, native)
+                    }))
+                    .await?
+                }
+                None => {
+                    return Err(Status::failed_precondition(
+                        "symbol_overrides need a server connected to the exchanges",
+                    ))
+                }
+            };
+        let published = self.books.subscribe(symbol).borrow().clone();
+        let mut levels_map = published
+            .iter()
+            .filter(|(exchange, _)| exchanges.contains(exchange))
+            .filter(|(exchange, _)| !overrides.contains_key(exchange))
+            .map(|(&exchange, book)| (exchange, book.levels.clone()))
+            .collect::<HashMap<_, _>>();
+        levels_map.extend(
+            fetched
+                .into_iter()
+                .filter(|levels| exchanges.contains(&levels.exchange))
+                .map(|levels| (levels.exchange, levels)),
+        );
+        for (exchange, levels) in levels_map.iter_mut() {
+            if let Some(rate) = rates.get(exchange) {
+                *levels = Arc::new(fx::convert(levels, rate.rate));
+            }
+        }
+        let disabled = exchanges
+            .iter()
+            .copied()
+            .filter(|&exchange| !self.switches.is_enabled(exchange))
+            .collect();
+        // An exchange that has delisted the symbol may list it under the name overriding it.
+        let mut delisted = self.listings.delisted_exchanges(symbol);
+        delisted.retain(|exchange| !overrides.contains_key(exchange));
+        summarize(
+            symbol,
+            exchanges,
+            &levels_map,
+            &disabled,
+            &delisted,
+            &self.depths,
+            bps,
+        )
+        .map(|mut summary| {
+            summary.fx_rates = rates
+                .iter()
+                .filter(|(exchange, _)| levels_map.contains_key(exchange))
+                .map(|(exchange, rate)| (exchange.to_string(), rate.clone()))
+                .collect();
+            summary
+        })
+        .map_err(|err| Status::unavailable(format!("{:#}", err)))
+    }
+
+    /// The server's own symbols followed by its warm ones, each with a summary task, and
+    /// those started on demand
+    fn summarized(&self) -> impl Iterator<Item = &Symbol> {
+        let warm = self.warm.keys().filter(|s| !self.symbols.contains(s));
+        let on_demand = self.pipelines.symbols().iter();
+        self.symbols.iter().chain(warm).chain(on_demand)
+    }
+
+    /// A receiver of the summaries published by the summary task of `symbol`, started when
+    /// it's on demand and not running, and the lease counting the caller as a subscriber
+    /// until it's dropped
+    async fn subscribe(&self, symbol: Symbol) -> Result<(SummaryReceiver, Lease), Status> {
+        let running = self
+            .warm
+            .get(&symbol)
+            .or_else(|| self.symbols.contains(&symbol).then_some(&self.tx_summary));
+        let (tx_summary, lease) = match running {
+            Some(tx_summary) => (tx_summary.clone(), self.pipelines.lease(symbol)),
+            None => self
+                .pipelines
+                .attach(symbol)
+                .unwrap_or_else(|| (self.tx_summary.clone(), self.pipelines.lease(symbol))),
+        };
+        let (tx1, rx1) = oneshot::channel::<SummaryReceiver>();
+        tx_summary
+            .send(tx1)
+            .await
+            .map_err(|_| Status::unavailable("summary stream is not running"))?;
+        let rx_summary = rx1
+            .await
+            .map_err(|_| Status::unavailable("summary stream is not running"))?;
+        Ok((rx_summary, lease))
+    }
+
+    /// Fails with [listings::delisted] once every enabled exchange has delisted `symbol`
+    #[allow(clippy::result_large_err)]
+    fn check_listed(&self, symbol: Symbol) -> Result<(), Status> {
+        match self.listings.is_withdrawn(symbol) {
+            true => Err(listings::delisted()),
+            false => Ok(()),
+        }
+    }
+
+    /// The symbol `name`s, which must be one of the [summarized](Self::summarized) ones
+    /// and still listed. `made` says what's only made for those, for the error otherwise.
+    #[allow(clippy::result_large_err)]
+    fn summarized_symbol(&self, name: &str, made: &str) -> Result<Symbol, Status> {
+        let symbol = name
+            .parse::<Symbol>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        if !self.summarized().any(|s| *s == symbol) {
+            return Err(Status::failed_precondition(format!(
+                "{} isn't being watched, {} for the symbols the server publishes summaries for",
+                symbol, made
+            )));
+        }
+        self.check_listed(symbol)?;
+        Ok(symbol)
+    }
+
+    /// Opens a stream of the summaries a `WatchSummary` style request asks for, logged in
+    /// `span`: subscribes to the symbol, registers the stream and runs `forward` on a task
+    /// that holds the lease until it returns, the server shuts down or an admin disconnects
+    /// the stream, which the client is told of.
+    #[allow(clippy::result_large_err)]
+    async fn open_summary_stream<T, F>(
+        &self,
+        span: tracing::Span,
+        request: tonic::Request<WatchSummaryRequest>,
+        forward: F,
+    ) -> Result<tonic::Response<Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>>, Status>
+    where
+        T: Send + 'static,
+        F: for<'a> FnOnce(
+                SummaryReceiver,
+                WatchSummaryRequest,
+                HashMap<String, f64>,
+                Symbol,
+                Backfill,
+                mpsc::Sender<Result<T, Status>>,
+                &'a StreamHandle,
+                &'a ServerCounters,
+            ) -> BoxFuture<'a, ()>
+            + Send
+            + 'static,
+    {
+        if let Some(parent) = trace_parent(&request) {
+            span.record("trace_id", parent.trace_id.as_str());
+            span.record("parent_id", parent.parent_id.as_str());
+            logging::follow(&span, &parent);
+        }
+        let peer = crate::tls::remote_addr(&request);
+        match crate::tls::client_identity(&request) {
+            Some(client) => {
+                span.in_scope(|| tracing::info!("Got a request from {:?} ({})", peer, client))
+            }
+            None => span.in_scope(|| tracing::info!("Got a request from {:?}", peer)),
+        }
+        let options = request.into_inner();
+        let (symbol, weights) = self.validate_watch_request(&options)?;
+        let (rx_summary, lease) = self.subscribe(symbol).await?;
+        if let Err(status) = &*rx_summary.borrow() {
+            return Err(status.clone());
+        }
+
+        let handle = self
+            .streams
+            .register(peer, symbol, options.clone(), rx_summary.clone());
+        span.record("stream_id", handle.id);
+        let (tx, rx) = mpsc::channel(1);
+        let mut shutdown = self.shutdown.clone();
+        let stats = self.stats.clone();
+        let backfill = self.backfill.clone();
+        stats.server().streams_opened.incr();
+        stats.server().streams_active.incr();
+        // Reported from the next summary on, so the first one sent may be missing them.
+        let bands = self.liquidity.watch(&options.liquidity_bps);
+        tokio::spawn(
+            async move {
+                let _bands = bands;
+                let _lease = lease;
+                let tx_cancelled = tx.clone();
+                let counters = stats.server();
+                let forward = forward(
+                    rx_summary, options, weights, symbol, backfill, tx, &handle, counters,
+                );
+                let cancelled = select! {
+                    _ = shutdown.wait_for(|stop| *stop) => false,
+                    _ = forward => false,
+                    _ = handle.cancelled() => true,
+                };
+                counters.streams_active.decr();
+                if cancelled {
+                    tracing::info!("summary stream disconnected by an admin");
+                    let status = Status::cancelled("stream disconnected by an admin");
+                    let _ = tx_cancelled.send(Err(status)).await;
+                }
+                tracing::info!("summary stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn enabled_exchange(&self, name: &str) -> Result<Exchange, Status> {
+        let exchange = name
+            .parse::<Exchange>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        if !self.exchanges.contains(&exchange) {
+            return Err(Status::failed_precondition(format!(
+                "{} is not enabled on this server",
+                exchange
+            )));
+        }
+        Ok(exchange)
+    }
+}
+
+/// Fails with the problems found with a request, if any. Invalid arguments come first and
+/// are listed together, separated by semicolons, so a client can fix them all at once.
+/// Otherwise the first problem is returned as it is.
+#[allow(clippy::result_large_err)]
+fn reject(problems: Vec<Status>) -> Result<(), Status> {
+    let invalid = problems
+        .iter()
+        .filter(|status| status.code() == tonic::Code::InvalidArgument)
+        .map(|status| status.message())
+        .collect::<Vec<_>>();
+    if !invalid.is_empty() {
+        return Err(Status::invalid_argument(invalid.join("; ")));
+    }
+    match problems.into_iter().next() {
+        Some(status) => Err(status),
+        None => Ok(()),
+    }
+}
+
+/// Sends the summaries read from `rows`, spaced out by the time between them divided by
+/// `speed`, or as fast as they're read when it's 0. Returns once the rows run out or the
+/// client goes away.
+async fn replay(mut rows: Rows, speed: f64, tx: mpsc::Sender<Result<Summary, Status>>) {
+    use futures::StreamExt;
+    let mut previous = None;
+    while let Some(row) = select! {
+        row = rows.next() => row,
+        _ = tx.closed() => return,
+    } {
+        let mut summary = match row.and_then(|row| row.to_summary()) {
+            Ok(summary) => summary,
+            Err(err) => {
+                tracing::warn!("replay failed: {:#}", err);
+                let _ = tx.send(Err(Status::internal(format!("{:#}", err)))).await;
+                return;
+            }
+        };
+        if let Some(previous) = previous.filter(|_| speed > 0.0) {
+            let gap = summary.timestamp.saturating_sub(previous) as f64 / speed;
+            select! {
+                _ = tokio::time::sleep(Duration::from_secs_f64(gap)) => {}
+                _ = tx.closed() => return,
+            }
+        }
+        previous = Some(summary.timestamp);
+        checksum::seal(&mut summary);
+        if tx.send(Ok(summary)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Identifies a request in the logs, using the client's `x-request-id` when it sends one.
+fn request_id<T>(request: &tonic::Request<T>) -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    match request
+        .metadata()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+    {
+        Some(id) => id.to_string(),
+        None => NEXT.fetch_add(1, Ordering::Relaxed).to_string(),
+    }
+}
+
+/// The W3C trace context the client sent, if it's valid
+fn trace_parent<T>(request: &tonic::Request<T>) -> Option<TraceParent> {
+    let header = request.metadata().get("traceparent")?.to_str().ok()?;
+    match header.parse() {
+        Ok(parent) => Some(parent),
+        Err(err) => {
+            tracing::debug!("ignoring {:#}", err);
+            None
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OrderbookAggregator for OrderbookSummary {
+    type WatchSummaryStream = Pin<Box<dyn Stream<Item = Result<Summary, Status>> + Send>>;
+    async fn watch_summary(
+        &self,
+        request: tonic::Request<WatchSummaryRequest>,
+    ) -> Result<tonic::Response<Self::WatchSummaryStream>, Status> {
+        let span = tracing::info_span!(
+            "watch_summary",
+            request_id = %request_id(&request),
+            stream_id = tracing::field::Empty,
+            trace_id = tracing::field::Empty,
+            parent_id = tracing::field::Empty,
+        );
+        self.open_summary_stream(
+            span,
+            request,
+            |rx_summary, options, weights, symbol, backfill, tx, handle, counters| {
+                Box::pin(forward_summaries(
+                    rx_summary, options, weights, symbol, backfill, tx, handle, counters,
+                ))
+            },
+        )
+        .await
+    }
+
+    type WatchSummaryBatchesStream =
+        Pin<Box<dyn Stream<Item = Result<SummaryBatch, Status>> + Send>>;
+    async fn watch_summary_batches(
+        &self,
+        request: tonic::Request<WatchSummaryRequest>,
+    ) -> Result<tonic::Response<Self::WatchSummaryBatchesStream>, Status> {
+        let span = tracing::info_span!(
+            "watch_summary_batches",
+            request_id = %request_id(&request),
+            stream_id = tracing::field::Empty,
+            trace_id = tracing::field::Empty,
+            parent_id = tracing::field::Empty,
+        );
+        let options = request.get_ref();
+        let batcher = Batcher::new(options.max_batch, options.max_batch_delay_ms)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        self.open_summary_stream(
+            span,
+            request,
+            move |rx_summary, options, weights, symbol, backfill, tx, handle, counters| {
+                Box::pin(forward_batches(
+                    rx_summary, options, weights, symbol, backfill, batcher, tx, handle, counters,
+                ))
+            },
+        )
+        .await
+    }
+
+    async fn get_summary(
+        &self,
+        request: tonic::Request<SummaryRequest>,
+    ) -> Result<tonic::Response<Summary>, Status> {
+        let options = request.into_inner();
+        let mut problems = Vec::new();
+        let symbol = self.summary_symbol(&options.symbol, &mut problems);
+        if problems.is_empty() {
+            problems.extend(self.check_listed(symbol).err());
+        }
+        problems.extend(self.levels_problem("levels", options.levels));
+        for (name, levels) in [
+            ("bid_levels", options.bid_levels),
+            ("ask_levels", options.ask_levels),
+        ] {
+            problems.extend(levels.and_then(|levels| self.levels_problem(name, levels)));
+        }
+        problems.extend(notional_problem(options.depth_notional));
+        let overrides = self.symbol_overrides(&options.symbol_overrides, &mut problems);
+        let depths = self.request_depths(&options.exchange_depths, &mut problems);
+        let market_types = self.request_market_types(&options.market_types, &mut problems);
+        let weights = self
+            .client_weights(&options.exchange_weights)
+            .map_err(|status| problems.push(status))
+            .ok();
+        if let Err(err) = liquidity::validate_bps(&options.liquidity_bps) {
+            problems.push(Status::invalid_argument(err.to_string()));
+        }
+        reject(problems)?;
+        let bps = match options.liquidity_bps.as_slice() {
+            [] => &liquidity::DEFAULT_BPS[..],
+            bps => bps,
+        };
+        let quote_currency = options.quote_currency.trim().to_uppercase();
+        let rates = self.fx_rates(symbol, &overrides, &quote_currency)?;
+        let mut notes = Vec::new();
+        let exchanges = self.market_exchanges(symbol, &market_types, &mut notes);
+        // Calls asking for the summary as published share the summary task's, which has the
+        // bands of the streams open and the default ones.
+        let registered = self.liquidity.bps();
+        let published = overrides.is_empty()
+            && rates.is_empty()
+            && exchanges == self.exchanges
+            && bps.iter().all(|bps| registered.contains(bps));
+        let mut summary = if published {
+            // Subscribing has the summary task build a summary when nothing was watching,
+            // which the calls coming in meanwhile all share.
+            let (rx_summary, _lease) = self.subscribe(symbol).await?;
+            let summary = rx_summary.borrow().clone()?;
+            if summary.sequence == 0 {
+                return Err(Status::unavailable(format!(
+                    "no summary of {} has been made yet",
+                    symbol
+                )));
+            }
+            Summary::clone(&summary)
+        } else {
+            // Calls asking for the same summary at once, or shortly after, share one.
+            let key = CallKey::new(symbol, &exchanges, &overrides, &quote_currency).with_bps(bps);
+            let summary = self
+                .calls
+                .get_or_build(key, self.stats.resyncs(symbol), || {
+                    self.summarize_call(symbol, &exchanges, &overrides, &rates, bps)
+                })
+                .await?;
+            Summary::clone(&summary)
+        };
+        summary.quote_currency = quote_currency;
+        summary.notes = notes;
+        summary.native_symbols = exchanges
+            .iter()
+            .filter(|exchange| {
+                let name = exchange.to_string();
+                self.switches.is_enabled(**exchange)
+                    && !summary.unavailable_exchanges.contains(&name)
+                    && !summary.delisted_exchanges.contains(&name)
+            })
+            .map(|&exchange| {
+                let native = overrides
+                    .get(&exchange)
+                    .cloned()
+                    .unwrap_or_else(|| native_symbol(exchange, symbol));
+                (exchange.to_string(), native)
+            })
+            .collect();
+        if options.derivatives {
+            let connector = self.connector.as_ref().ok_or_else(|| {
+                Status::failed_precondition("derivatives need a server connected to the exchanges")
+            })?;
+            let exchanges = self
+                .exchanges
+                .iter()
+                .copied()
+                .filter(|exchange| {
+                    self.switches.is_enabled(*exchange)
+                        && !overrides.contains_key(exchange)
+                        && !summary.delisted_exchanges.contains(&exchange.to_string())
+                })
+                .collect::<Vec<_>>();
+            summary.derivatives = connector.derivatives(symbol, &exchanges).await;
+        }
+        limit_depths(&mut summary, &depths);
+        if let Some(weights) = weights.filter(|weights| !weights.is_empty()) {
+            weigh_exchanges(&mut summary, &weights);
+            flag_every_exchange(&mut summary);
+        }
+        summary.liquidity.retain(|band| bps.contains(&band.bps));
+        cut_levels(
+            &mut summary,
+            SideLevels::of(options.levels, options.bid_levels, options.ask_levels),
+            options.depth_notional,
+        );
+        if !options.include_level_ages {
+            clear_level_ages(&mut summary);
+        }
+        checksum::seal(&mut summary);
+        Ok(tonic::Response::new(summary))
+    }
+
+    type ReplaySummariesStream = Pin<Box<dyn Stream<Item = Result<Summary, Status>> + Send>>;
+    async fn replay_summaries(
+        &self,
+        request: tonic::Request<ReplaySummariesRequest>,
+    ) -> Result<tonic::Response<Self::ReplaySummariesStream>, Status> {
+        let span = tracing::info_span!("replay_summaries", request_id = %request_id(&request));
+        let history = self.history.clone().ok_or_else(|| {
+            Status::failed_precondition("the server isn't keeping a summary history")
+        })?;
+        let options = request.into_inner();
+        let symbol = options
+            .symbol
+            .parse::<Symbol>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        if options.start_time > options.end_time {
+            return Err(Status::invalid_argument("start_time is after end_time"));
+        }
+        if !options.speed.is_finite() || options.speed < 0.0 {
+            return Err(Status::invalid_argument("speed must be 0 or more"));
+        }
+        let (coverage, rows) = history
+            .range(&symbol.to_string(), options.start_time, options.end_time)
+            .await
+            .map_err(|err| {
+                tracing::warn!("reading the summary history failed: {:#}", err);
+                Status::unavailable("the summary history can't be read")
+            })?;
+        span.in_scope(|| tracing::info!("replaying {:?} at speed {}", coverage, options.speed));
+
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(replay(rows, options.speed, tx).instrument(span));
+        let mut response =
+            tonic::Response::new(Box::pin(ReceiverStream::new(rx)) as Self::ReplaySummariesStream);
+        if let Some(coverage) = coverage {
+            let metadata = response.metadata_mut();
+            metadata.insert("x-covered-start", coverage.start.into());
+            metadata.insert("x-covered-end", coverage.end.into());
+        }
+        Ok(response)
+    }
+
+    async fn get_spread_stats(
+        &self,
+        request: tonic::Request<SpreadStatsRequest>,
+    ) -> Result<tonic::Response<SpreadStats>, Status> {
+        let options = request.into_inner();
+        let symbol = options
+            .symbol
+            .parse::<Symbol>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let samples = self
+            .spread_samples
+            .iter()
+            .find(|samples| samples.symbol() == symbol)
+            .ok_or_else(|| {
+                Status::failed_precondition(format!(
+                    "{} isn't being watched, stats are only kept for the symbols the server publishes summaries for",
+                    symbol
+                ))
+            })?;
+        let window = Duration::from_secs(options.window_minutes as u64 * 60);
+        let max_window = samples.max_window();
+        if window.is_zero() || window > max_window {
+            return Err(Status::invalid_argument(format!(
+                "window_minutes must be from 1 to {}",
+                max_window.as_secs() / 60
+            )));
+        }
+        Ok(tonic::Response::new(samples.stats(window)))
+    }
+
+    type WatchAlertsStream = Pin<Box<dyn Stream<Item = Result<Alert, Status>> + Send>>;
+    async fn watch_alerts(
+        &self,
+        request: tonic::Request<WatchAlertsRequest>,
+    ) -> Result<tonic::Response<Self::WatchAlertsStream>, Status> {
+        let span = tracing::info_span!("watch_alerts", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = self.summarized_symbol(&options.symbol, "alerts are only raised")?;
+        let rules = alerts::parse_rules(options.rules)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let (rx_summary, lease) = self.subscribe(symbol).await?;
+        span.in_scope(|| tracing::info!("watching {} alert rules for {}", rules.len(), symbol));
+
+        let (tx, rx) = mpsc::channel(rules.len());
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = alerts::watch_alerts(rx_summary, rules, tx) => {}
+                }
+                tracing::info!("alert stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchAlertsStream
+        ))
+    }
+
+    type WatchDivergenceStream = Pin<Box<dyn Stream<Item = Result<Divergence, Status>> + Send>>;
+    async fn watch_divergence(
+        &self,
+        request: tonic::Request<WatchDivergenceRequest>,
+    ) -> Result<tonic::Response<Self::WatchDivergenceStream>, Status> {
+        let span = tracing::info_span!("watch_divergence", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = self.summarized_symbol(&options.symbol, "divergence is only watched")?;
+        let divergences = Divergences::new(options.threshold_bps, options.hysteresis_bps)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        // Keeps a symbol started on demand running while it's watched.
+        let (_, lease) = self.subscribe(symbol).await?;
+        span.in_scope(|| {
+            tracing::info!(
+                "watching {} for divergence over {}bps",
+                symbol,
+                options.threshold_bps
+            )
+        });
+
+        let (tx, rx) = mpsc::channel(1);
+        let rx_quotes = self.quotes.subscribe(symbol);
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = watch_divergence(rx_quotes, divergences, tx) => {}
+                }
+                tracing::info!("divergence stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchDivergenceStream
+        ))
+    }
+
+    type WatchCandlesStream = Pin<Box<dyn Stream<Item = Result<Candle, Status>> + Send>>;
+    async fn watch_candles(
+        &self,
+        request: tonic::Request<WatchCandlesRequest>,
+    ) -> Result<tonic::Response<Self::WatchCandlesStream>, Status> {
+        let span = tracing::info_span!("watch_candles", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = self.summarized_symbol(&options.symbol, "candles are only built")?;
+        let builder = CandleBuilder::new(symbol, options.interval_ms)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let throttle = Duration::from_millis(options.throttle_ms as u64);
+        let (rx_summary, lease) = self.subscribe(symbol).await?;
+        span.in_scope(|| {
+            tracing::info!("watching {} candles of {}ms", symbol, options.interval_ms)
+        });
+
+        let (tx, rx) = mpsc::channel(10);
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = watch_candles(rx_summary, builder, throttle, tx) => {}
+                }
+                tracing::info!("candle stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchCandlesStream
+        ))
+    }
+
+    async fn get_ticker(
+        &self,
+        request: tonic::Request<TickerRequest>,
+    ) -> Result<tonic::Response<Ticker>, Status> {
+        let symbol = self.summarized_symbol(&request.get_ref().symbol, "tickers are only made")?;
+        // Starts a symbol on demand, for the next call to find it quoted if this one doesn't.
+        let (_, _lease) = self.subscribe(symbol).await?;
+        let quotes = self.quotes.subscribe(symbol).borrow().clone();
+        let ticker = ticker(symbol, &quotes)
+            .ok_or_else(|| Status::unavailable(format!("no exchange is quoting {} yet", symbol)))?;
+        Ok(tonic::Response::new(ticker))
+    }
+
+    type WatchTickerStream = Pin<Box<dyn Stream<Item = Result<Ticker, Status>> + Send>>;
+    async fn watch_ticker(
+        &self,
+        request: tonic::Request<TickerRequest>,
+    ) -> Result<tonic::Response<Self::WatchTickerStream>, Status> {
+        let span = tracing::info_span!("watch_ticker", request_id = %request_id(&request));
+        let symbol = self.summarized_symbol(&request.get_ref().symbol, "tickers are only made")?;
+        let (_, lease) = self.subscribe(symbol).await?;
+        let rx_quotes = self.quotes.subscribe(symbol);
+        span.in_scope(|| tracing::info!("watching the {} ticker", symbol));
+
+        let (tx, rx) = mpsc::channel(10);
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = watch_ticker(rx_quotes, symbol, tx) => {}
+                }
+                tracing::info!("ticker stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchTickerStream
+        ))
+    }
+
+    type WatchSpreadStream = Pin<Box<dyn Stream<Item = Result<Spread, Status>> + Send>>;
+    async fn watch_spread(
+        &self,
+        request: tonic::Request<WatchSpreadRequest>,
+    ) -> Result<tonic::Response<Self::WatchSpreadStream>, Status> {
+        let span = tracing::info_span!("watch_spread", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = self.summarized_symbol(&options.symbol, "tickers are only made")?;
+        let min_change_bps = options.min_change_bps;
+        if !min_change_bps.is_finite() || min_change_bps < 0.0 {
+            return Err(Status::invalid_argument(format!(
+                "min_change_bps of {} isn't a finite number of bps or more",
+                min_change_bps
+            )));
+        }
+        let max_interval = Duration::from_millis(options.max_interval_ms as u64);
+        let (_, lease) = self.subscribe(symbol).await?;
+        let rx_quotes = self.quotes.subscribe(symbol);
+        span.in_scope(|| {
+            tracing::info!(
+                "watching the {} spread for moves of {}bps",
+                symbol,
+                min_change_bps
+            )
+        });
+
+        let (tx, rx) = mpsc::channel(10);
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = watch_spread(rx_quotes, symbol, min_change_bps, max_interval, tx) => {}
+                }
+                tracing::info!("spread stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchSpreadStream
+        ))
+    }
+
+    type WatchBookStream = Pin<Box<dyn Stream<Item = Result<BookUpdate, Status>> + Send>>;
+    async fn watch_book(
+        &self,
+        request: tonic::Request<WatchBookRequest>,
+    ) -> Result<tonic::Response<Self::WatchBookStream>, Status> {
+        let span = tracing::info_span!("watch_book", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = self.summarized_symbol(&options.symbol, "books are only kept")?;
+        let mut exchanges = Vec::new();
+        for name in options.exchanges.iter() {
+            exchanges.push(self.enabled_exchange(name)?);
+        }
+        let (_, lease) = self.subscribe(symbol).await?;
+        let rx_books = self.books.subscribe(symbol);
+        span.in_scope(|| tracing::info!("watching the {} book", symbol));
+
+        let (tx, rx) = mpsc::channel(10);
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = watch_book(rx_books, BookChanges::new(symbol, exchanges), tx) => {}
+                }
+                tracing::info!("book stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchBookStream
+        ))
+    }
+
+    type WatchExchangeStatusStream =
+        Pin<Box<dyn Stream<Item = Result<ExchangeStatus, Status>> + Send>>;
+    async fn watch_exchange_status(
+        &self,
+        request: tonic::Request<WatchExchangeStatusRequest>,
+    ) -> Result<tonic::Response<Self::WatchExchangeStatusStream>, Status> {
+        let span = tracing::info_span!("watch_exchange_status", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let (symbol, lease) = match options.symbol.as_str() {
+            "" => (None, None),
+            name => {
+                let symbol = self.summarized_symbol(name, "connections are only made")?;
+                let (_, lease) = self.subscribe(symbol).await?;
+                (Some(symbol), Some(lease))
+            }
+        };
+        span.in_scope(|| match symbol {
+            Some(symbol) => tracing::info!("watching the {} exchange connections", symbol),
+            None => tracing::info!("watching every exchange connection"),
+        });
+
+        let (tx, rx) = mpsc::channel(10);
+        let stats = self.stats.clone();
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                let _lease = lease;
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = metrics::watch_exchange_status(stats, symbol, tx) => {}
+                }
+                tracing::info!("exchange status stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchExchangeStatusStream
+        ))
+    }
+
+    type WatchTradesStream = Pin<Box<dyn Stream<Item = Result<Trade, Status>> + Send>>;
+    async fn watch_trades(
+        &self,
+        request: tonic::Request<WatchTradesRequest>,
+    ) -> Result<tonic::Response<Self::WatchTradesStream>, Status> {
+        let span = tracing::info_span!("watch_trades", request_id = %request_id(&request));
+        let options = request.into_inner();
+        let symbol = options
+            .symbol
+            .parse::<Symbol>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        if self.trades.is_empty() {
+            return Err(Status::failed_precondition(
+                "the server isn't streaming trades, start it with --trades",
+            ));
+        }
+        let feed = self
+            .trades
+            .iter()
+            .find(|feed| feed.symbol == symbol)
+            .ok_or_else(|| {
+                Status::failed_precondition(format!("{} trades aren't being streamed", symbol))
+            })?;
+        let mut exchanges = Vec::new();
+        for name in options.exchanges.iter() {
+            exchanges.push(self.enabled_exchange(name)?);
+        }
+        if !options.min_quantity.is_finite() || options.min_quantity < 0.0 {
+            return Err(Status::invalid_argument("min_quantity must be 0 or more"));
+        }
+        span.in_scope(|| tracing::info!("watching {} trades", symbol));
+
+        let (tx, rx) = mpsc::channel(100);
+        let rx_trades = feed.subscribe();
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(
+            async move {
+                select! {
+                    _ = shutdown.wait_for(|stop| *stop) => {}
+                    _ = forward_trades(rx_trades, exchanges, options.min_quantity, tx) => {}
+                }
+                tracing::info!("trade stream ended");
+            }
+            .instrument(span),
+        );
+        Ok(tonic::Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchTradesStream
+        ))
+    }
+
+    async fn get_stats(&self, _: tonic::Request<Empty>) -> Result<tonic::Response<Stats>, Status> {
+        Ok(tonic::Response::new(self.stats.snapshot()))
+    }
+
+    async fn get_exchanges(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<Exchanges>, Status> {
+        Ok(tonic::Response::new(Exchanges {
+            exchanges: self.exchanges.iter().map(|e| e.to_string()).collect(),
+            disabled: self
+                .exchanges
+                .iter()
+                .filter(|&&e| !self.switches.is_enabled(e))
+                .map(|e| e.to_string())
+                .collect(),
+        }))
+    }
+
+    async fn get_symbols(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<Symbols>, Status> {
+        Ok(tonic::Response::new(Symbols {
+            symbols: self.summarized().map(|s| s.to_string()).collect(),
+        }))
+    }
+
+    async fn get_server_info(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<ServerInfo>, Status> {
+        Ok(tonic::Response::new(self.server_info()))
+    }
+}
+
+#[cfg(all(test, feature = "exchange-binance", feature = "exchange-bitstamp"))]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use rust_decimal::Decimal;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::{net::TcpListener, sync::RwLock, time::timeout};
+    use tokio_tungstenite::tungstenite::Message;
+    use tonic_health::pb::{
+        health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+    };
+
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        book::LocalBook,
+        book_summary::{alert_rule::Condition, AlertRule, Level},
+        core::{
+            exchange_book::process_updates,
+            order_book::{Depth, OrderBook},
+            stats::ExchangeStats,
+        },
+        exchanges::bitstamp::data::{BookUpdate, Snapshot},
+    };
+
+    /// Starts a websocket server that sends `count` bitstamp updates to each client.
+    /// The connection is then dropped, or held open if `hold` is set.
+    async fn mock_upstream(count: u64, hold: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    for id in 2..count + 2 {
+                        let update = serde_json::json!({
+                            "data": {
+                                "microtimestamp": id.to_string(),
+                                "bids": [[(29990 + id).to_string(), "1.0"]],
+                                "asks": [["30010", "1.0"]],
+                            }
+                        });
+                        ws.send(Message::Text(update.to_string())).await.unwrap();
+                    }
+                    if hold {
+                        futures::future::pending::<()>().await;
+                    }
+                });
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    /// Runs a bitstamp order book against the mock upstream.
+    async fn run_exchange(
+        exchange: Exchange,
+        url: String,
+        tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    ) -> Result<()> {
+        let (ws, _) = tokio_tungstenite::connect_async(url).await?;
+        let (sink, stream) = ws.split();
+        let orderbook = OrderBook::new(exchange, Symbol::BTCUSDT, 29000, 31000, 0, 8);
+        let snapshot = Snapshot {
+            last_update_id: 1,
+            bids: vec![[Decimal::new(29990, 0), Decimal::ONE]],
+            asks: vec![[Decimal::new(30010, 0), Decimal::ONE]],
+        };
+        process_updates::<Snapshot, BookUpdate, _, _>(
+            Arc::new(RwLock::new(orderbook)),
+            Arc::new(ExchangeStats::default()),
+            5,
+            snapshot,
+            None,
+            stream,
+            sink,
+            tx_levels,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn it_ends_client_streams_with_unavailable_when_upstream_closes() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+
+        // Subscribe before the upstream starts so the client sees the whole stream.
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Long enough that the exchange isn't retried during the test.
+        let backoff = Backoff {
+            initial: Duration::from_secs(60),
+            max: Duration::from_secs(60),
+            budget: Duration::from_secs(60),
+        };
+        let url = mock_upstream(3, false).await;
+        spawn_exchange(
+            Exchange::BITSTAMP,
+            Symbol::BTCUSDT,
+            backoff,
+            watch::channel(true).1,
+            tx_closed,
+            move || run_exchange(Exchange::BITSTAMP, url.clone(), tx_levels.clone()),
+        );
+
+        let status = timeout(Duration::from_secs(5), async {
+            while let Some(result) = stream.next().await {
+                if let Err(status) = result {
+                    return status;
+                }
+            }
+            panic!("stream ended without a status");
+        })
+        .await
+        .expect("client did not observe the upstream failure");
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(
+            status.message().contains("BITSTAMP"),
+            "{}",
+            status.message()
+        );
+
+        // New clients are refused while every exchange is unavailable.
+        let status = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn it_completes_client_streams_on_shutdown() {
+        let (_tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // The initial value is sent straight away, then nothing until shutdown.
+        assert!(stream.next().await.unwrap().is_ok());
+        tx_shutdown.send(true).unwrap();
+        let next = timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap();
+        assert!(next.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_serves_restored_levels_as_stale_until_the_exchange_sends_its_own() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP, Exchange::BINANCE],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let book = |exchange: Exchange, price: f64, restored: bool| {
+            let level = |price| Level {
+                exchange: exchange.to_string(),
+                price,
+                quantity: 1.0,
+                updated_at: None,
+                market_type: exchange.market_type().into(),
+            };
+            Arc::new(BookLevels {
+                exchange,
+                symbol: Symbol::BTCUSDT,
+                bids: vec![level(price - 1.0)],
+                asks: vec![level(price + 1.0)],
+                restored,
+                ..Default::default()
+            })
+        };
+        // Summaries published quicker than they're read are skipped.
+        async fn until(
+            stream: &mut (impl Stream<Item = Result<Summary, Status>> + Unpin),
+            done: impl Fn(&Summary) -> bool,
+        ) -> Summary {
+            loop {
+                let next = timeout(Duration::from_secs(5), stream.next());
+                let summary = next.await.unwrap().unwrap().unwrap();
+                if done(&summary) {
+                    return summary;
+                }
+            }
+        }
+        tx_levels
+            .send(book(Exchange::BITSTAMP, 30000.0, true))
+            .await
+            .unwrap();
+        tx_levels
+            .send(book(Exchange::BINANCE, 30010.0, true))
+            .await
+            .unwrap();
+        let summary = until(&mut stream, |summary| summary.stale_exchanges.len() == 2).await;
+        assert!(summary.degraded);
+        assert_eq!(summary.stale_exchanges, ["BITSTAMP", "BINANCE"]);
+        assert_eq!(summary.bids[0].price, 30009.0);
+
+        // A failed connection leaves the restored levels in place.
+        tx_closed
+            .send(ExchangeClosed {
+                exchange: Exchange::BINANCE,
+                reason: "connection refused".to_string(),
+                lost: false,
+                disabled: false,
+                delisted: false,
+            })
+            .await
+            .unwrap();
+        tx_levels
+            .send(book(Exchange::BITSTAMP, 30005.0, false))
+            .await
+            .unwrap();
+        let summary = until(&mut stream, |summary| summary.stale_exchanges.len() < 2).await;
+        assert_eq!(summary.stale_exchanges, ["BINANCE"]);
+        assert!(summary.degraded);
+        assert_eq!(summary.bids[0].price, 30009.0);
+
+        tx_levels
+            .send(book(Exchange::BINANCE, 30006.0, false))
+            .await
+            .unwrap();
+        let summary = until(&mut stream, |summary| summary.stale_exchanges.is_empty()).await;
+        assert!(!summary.degraded);
+        assert_eq!(summary.asks[0].price, 30006.0);
+    }
+
+    #[tokio::test]
+    async fn it_serves_degraded_summaries_until_an_exchange_recovers() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP, Exchange::BINANCE],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let backoff = Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(1),
+            budget: Duration::from_secs(60),
+        };
+        let url_bs = mock_upstream(3, true).await;
+        let tx_levels_bs = tx_levels.clone();
+        spawn_exchange(
+            Exchange::BITSTAMP,
+            Symbol::BTCUSDT,
+            backoff,
+            watch::channel(true).1,
+            tx_closed.clone(),
+            move || run_exchange(Exchange::BITSTAMP, url_bs.clone(), tx_levels_bs.clone()),
+        );
+
+        // Binance fails to start the first time it's tried.
+        let url_bn = mock_upstream(3, true).await;
+        let attempts = Arc::new(AtomicUsize::new(0));
+        spawn_exchange(
+            Exchange::BINANCE,
+            Symbol::BTCUSDT,
+            backoff,
+            watch::channel(true).1,
+            tx_closed,
+            move || {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                let url = url_bn.clone();
+                let tx_levels = tx_levels.clone();
+                async move {
+                    if attempt == 0 {
+                        anyhow::bail!("connection refused");
+                    }
+                    run_exchange(Exchange::BINANCE, url, tx_levels).await
+                }
+            },
+        );
+
+        timeout(Duration::from_secs(5), async {
+            let mut saw_degraded = false;
+            while let Some(result) = stream.next().await {
+                let summary = result.expect("summary should not fail with one exchange up");
+                if summary.bids.is_empty() {
+                    continue;
+                }
+                if summary.degraded {
+                    assert_eq!(summary.unavailable_exchanges, vec!["BINANCE".to_string()]);
+                    assert!(summary.bids.iter().all(|l| l.exchange == "BITSTAMP"));
+                    saw_degraded = true;
+                } else if saw_degraded {
+                    assert!(summary.unavailable_exchanges.is_empty());
+                    return;
+                }
+            }
+            panic!("stream ended before binance recovered");
+        })
+        .await
+        .expect("summary did not recover");
+    }
+
+    /// Starts a websocket server that sends bitstamp updates to each client and holds the
+    /// connection while `up` is set. Connections are dropped, and new ones refused, while
+    /// it isn't.
+    async fn switchable_upstream(up: Arc<AtomicBool>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                if !up.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let up = up.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    let update = serde_json::json!({
+                        "data": {
+                            "microtimestamp": "2",
+                            "bids": [["29995", "1.0"]],
+                            "asks": [["30010", "1.0"]],
+                        }
+                    });
+                    ws.send(Message::Text(update.to_string())).await.unwrap();
+                    while up.load(Ordering::SeqCst) {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                });
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn it_stops_serving_when_all_feeds_are_lost_until_one_recovers() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let (tx_serving, mut rx_serving) = watch::channel(true);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP, Exchange::BINANCE],
+            rx_levels,
+            rx_closed,
+            tx_serving,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            budget: Duration::from_millis(200),
+        };
+        let up = Arc::new(AtomicBool::new(true));
+        for exchange in [Exchange::BITSTAMP, Exchange::BINANCE] {
+            let url = switchable_upstream(up.clone()).await;
+            let tx_levels = tx_levels.clone();
+            spawn_exchange(
+                exchange,
+                Symbol::BTCUSDT,
+                backoff,
+                watch::channel(true).1,
+                tx_closed.clone(),
+                move || run_exchange(exchange, url.clone(), tx_levels.clone()),
+            );
+        }
+
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        up.store(false, Ordering::SeqCst);
+
+        // Active streams end with unavailable, then the server stops serving once the
+        // budgets run out.
+        let status = timeout(Duration::from_secs(5), async {
+            while let Some(result) = stream.next().await {
+                if let Err(status) = result {
+                    return status;
+                }
+            }
+            panic!("stream ended without a status");
+        })
+        .await
+        .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        timeout(
+            Duration::from_secs(5),
+            rx_serving.wait_for(|serving| !serving),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let status = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        up.store(true, Ordering::SeqCst);
+        timeout(
+            Duration::from_secs(5),
+            rx_serving.wait_for(|serving| *serving),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(stream.next().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_reports_health_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health_service)
+                .serve_with_incoming(
+                    tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+                        .unwrap(),
+                ),
+        );
+
+        let (tx_serving, rx_serving) = watch::channel(true);
+        spawn_health_monitor(rx_serving, reporter);
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+
+        let status = |serving: bool| {
+            if serving {
+                ServingStatus::Serving as i32
+            } else {
+                ServingStatus::NotServing as i32
+            }
+        };
+        for serving in [true, false, true] {
+            tx_serving.send_replace(serving);
+            timeout(Duration::from_secs(5), async {
+                loop {
+                    let response = client
+                        .check(HealthCheckRequest {
+                            service: "booksummary.OrderbookAggregator".to_string(),
+                        })
+                        .await;
+                    if matches!(response, Ok(r) if r.get_ref().status == status(serving)) {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+    }
+
+    fn book_levels(id: u64, levels: usize) -> Arc<BookLevels> {
+        let level = |price: f64| Level {
+            exchange: Exchange::BITSTAMP.to_string(),
+            price,
+            quantity: 1.0,
+            updated_at: None,
+            market_type: Exchange::BITSTAMP.market_type().into(),
+        };
+        Arc::new(BookLevels {
+            exchange: Exchange::BITSTAMP,
+            symbol: Symbol::BTCUSDT,
+            last_update_id: id,
+            bids: (0..levels).map(|i| level(29990.0 - i as f64)).collect(),
+            asks: (0..levels).map(|i| level(30010.0 + i as f64)).collect(),
+            depth: None,
+            restored: false,
+        })
+    }
+
+    /// Reads summaries until `last` is received, waiting `delay` between reads, and
+    /// returns their sequence numbers.
+    async fn read_sequences(
+        mut stream: <OrderbookSummary as OrderbookAggregator>::WatchSummaryStream,
+        last: u64,
+        delay: Duration,
+    ) -> Vec<u64> {
+        let mut sequences = Vec::new();
+        while let Some(result) = stream.next().await {
+            let sequence = result.unwrap().sequence;
+            sequences.push(sequence);
+            if sequence == last {
+                break;
+            }
+            tokio::time::sleep(delay).await;
+        }
+        sequences
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn it_lets_slow_clients_skip_summaries_without_holding_up_others() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+
+        let updates = 500;
+        let mut readers = Vec::new();
+        for delay in [0, 0, 0, 20] {
+            let stream = service
+                .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+                .await
+                .unwrap()
+                .into_inner();
+            readers.push(tokio::spawn(read_sequences(
+                stream,
+                updates,
+                Duration::from_millis(delay),
+            )));
+        }
+
+        for id in 0..updates {
+            tx_levels.send(book_levels(id, 5)).await.unwrap();
+            tokio::time::sleep(Duration::from_micros(200)).await;
+        }
+
+        let mut sequences = Vec::new();
+        for reader in readers {
+            let reader_sequences = timeout(Duration::from_secs(10), reader)
+                .await
+                .expect("client did not receive the latest summary")
+                .unwrap();
+            assert!(reader_sequences.windows(2).all(|w| w[0] < w[1]));
+            sequences.push(reader_sequences);
+        }
+        // The slow client only sees a fraction of the summaries, with the skips showing
+        // as gaps in the sequence numbers.
+        let slow = sequences.pop().unwrap();
+        assert!(slow.len() < updates as usize / 2, "{}", slow.len());
+        let fast = sequences.iter().map(|s| s.len()).min().unwrap();
+        assert!(fast > slow.len(), "fast: {} slow: {}", fast, slow.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn it_backfills_streams_up_to_their_first_live_summary() {
+        /// The sequence numbers of the first `count` summaries of `stream`, and whether
+        /// each was backfilled
+        async fn read(
+            mut stream: <OrderbookSummary as OrderbookAggregator>::WatchSummaryStream,
+            count: usize,
+        ) -> Vec<(u64, bool)> {
+            let mut sent = Vec::new();
+            while sent.len() < count {
+                let summary = stream.next().await.unwrap().unwrap();
+                sent.push((summary.sequence, summary.is_backfill));
+            }
+            sent
+        }
+
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let backfill = Backfill::new(50);
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                backfill: backfill.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_backfill(backfill);
+        let watch = |backfill| {
+            service.watch_summary(tonic::Request::new(WatchSummaryRequest {
+                backfill,
+                ..Default::default()
+            }))
+        };
+
+        // Nothing is kept before the first summary, so the first stream starts live.
+        let mut first = watch(10).await.unwrap().into_inner();
+        let feed = tokio::spawn(async move {
+            for id in 0..500 {
+                tx_levels.send(book_levels(id, 5)).await.unwrap();
+                tokio::time::sleep(Duration::from_micros(200)).await;
+            }
+        });
+        loop {
+            let summary = first.next().await.unwrap().unwrap();
+            assert!(!summary.is_backfill);
+            if summary.sequence >= 60 {
+                break;
+            }
+        }
+
+        // Summaries are still being made as these start.
+        let few = watch(20).await.unwrap().into_inner();
+        let many = watch(100).await.unwrap().into_inner();
+        let (few, many) = timeout(
+            Duration::from_secs(10),
+            futures::future::join(read(few, 50), read(many, 80)),
+        )
+        .await
+        .expect("streams did not receive their summaries");
+        // Those asking for more than are kept get all of them.
+        for (sent, backfilled) in [(few, 20), (many, 50)] {
+            let live = sent.iter().position(|(_, backfill)| !backfill).unwrap();
+            assert_eq!(live, backfilled, "{:?}", sent);
+            assert!(sent[live..].iter().all(|(_, backfill)| !backfill));
+            // None is missed or sent twice where the backfill meets the live summaries.
+            assert!(
+                sent[..=live].windows(2).all(|w| w[1].0 == w[0].0 + 1),
+                "{:?}",
+                sent
+            );
+            assert!(sent.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+        feed.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_batches_contiguous_summaries_within_the_delay() {
+        /// The sequence numbers of each batch of `stream` up to the one ending with `last`
+        async fn read(
+            stream: &mut <OrderbookSummary as OrderbookAggregator>::WatchSummaryBatchesStream,
+            last: u64,
+        ) -> Vec<Vec<u64>> {
+            let mut batches = Vec::new();
+            while batches.last().and_then(|batch: &Vec<u64>| batch.last()) != Some(&last) {
+                let batch = stream.next().await.unwrap().unwrap();
+                assert!(batch.summaries.iter().all(|summary| !summary.is_backfill));
+                batches.push(
+                    batch
+                        .summaries
+                        .iter()
+                        .map(|summary| summary.sequence)
+                        .collect(),
+                );
+            }
+            batches
+        }
+
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let backfill = Backfill::new(100);
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                backfill: backfill.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_backfill(backfill);
+        let watch = |max_batch| {
+            service.watch_summary_batches(tonic::Request::new(WatchSummaryRequest {
+                max_batch,
+                max_batch_delay_ms: 20,
+                ..Default::default()
+            }))
+        };
+        let mut fast = watch(10).await.unwrap().into_inner();
+        // Not read until the end, so the watch channel coalesces most of its summaries.
+        let mut slow = watch(5).await.unwrap().into_inner();
+
+        // A lone summary waits out the delay for others to join it, and no longer.
+        let sent = Instant::now();
+        tx_levels.send(book_levels(0, 5)).await.unwrap();
+        assert_eq!(read(&mut fast, 1).await, [vec![1]]);
+        assert_eq!(sent.elapsed(), Duration::from_millis(20));
+
+        // Summaries made faster than that share batches until they're full.
+        for id in 1..=25 {
+            tx_levels.send(book_levels(id, 5)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(
+            read(&mut fast, 26).await,
+            [
+                (2..=11).collect::<Vec<_>>(),
+                (12..=21).collect(),
+                (22..=26).collect()
+            ]
+        );
+
+        // The slow stream is sent those it fell behind on from the backfill, in order.
+        let batches = read(&mut slow, 26).await;
+        assert!(
+            batches.iter().all(|batch| batch.len() <= 5),
+            "{:?}",
+            batches
+        );
+        assert_eq!(batches.concat(), (1..=26).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn it_applies_client_options() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest {
+                min_interval_ms: 100,
+                levels: 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().sequence, 0);
+
+        // Summaries published within the interval are skipped for the latest one.
+        for id in 0..10 {
+            tx_levels.send(book_levels(id, 5)).await.unwrap();
+        }
+        let summary = stream.next().await.unwrap().unwrap();
+        assert_eq!(summary.sequence, 10);
+        assert_eq!(summary.bids.len(), 2);
+        assert_eq!(summary.asks.len(), 2);
+        assert_eq!(summary.bids[0].price, 29990.0);
+        assert_eq!(summary.asks[0].price, 30010.0);
+    }
+
+    #[tokio::test]
+    async fn it_sends_each_side_the_levels_asked_for_it() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_limits(ServerLimits {
+            levels: 5,
+            ..Default::default()
+        });
+        let cases = [
+            (2, None, None, (2, 2)),
+            (0, None, None, (5, 5)),
+            (2, Some(4), None, (4, 2)),
+            (2, None, Some(1), (2, 1)),
+            (0, Some(3), Some(1), (3, 1)),
+            (3, Some(0), None, (0, 3)),
+            (0, None, Some(0), (5, 0)),
+            (2, Some(0), Some(0), (0, 0)),
+        ];
+        let request = |levels, bid_levels, ask_levels| {
+            tonic::Request::new(WatchSummaryRequest {
+                levels,
+                bid_levels,
+                ask_levels,
+                max_batch_delay_ms: 1,
+                ..Default::default()
+            })
+        };
+        let mut streams = Vec::new();
+        let mut batch_streams = Vec::new();
+        for (levels, bid_levels, ask_levels, _) in cases {
+            let request = request(levels, bid_levels, ask_levels);
+            streams.push(service.watch_summary(request).await.unwrap().into_inner());
+            let request = request(levels, bid_levels, ask_levels);
+            let batches = service.watch_summary_batches(request).await.unwrap();
+            batch_streams.push(batches.into_inner());
+        }
+        tx_levels.send(book_levels(0, 5)).await.unwrap();
+
+        for ((levels, bid_levels, ask_levels, sent), (stream, batches)) in cases
+            .into_iter()
+            .zip(streams.iter_mut().zip(batch_streams.iter_mut()))
+        {
+            let case = (levels, bid_levels, ask_levels);
+            let mut summary = stream.next().await.unwrap().unwrap();
+            while summary.sequence == 0 {
+                summary = stream.next().await.unwrap().unwrap();
+            }
+            assert_eq!((summary.bids.len(), summary.asks.len()), sent, "{:?}", case);
+            let summary = loop {
+                let batch = batches.next().await.unwrap().unwrap();
+                match batch.summaries.into_iter().find(|s| s.sequence > 0) {
+                    Some(summary) => break summary,
+                    None => continue,
+                }
+            };
+            assert_eq!((summary.bids.len(), summary.asks.len()), sent, "{:?}", case);
+        }
+
+        // A side can't ask for more levels than summaries are made with, no more than levels can.
+        let status = service
+            .watch_summary(request(0, Some(6), None))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "bid_levels must be at most 5, the levels summaries are made with"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_liquidity_bands_each_client_asks_for() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let bands = LiquidityBands::default();
+        let books = Books::default();
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                liquidity: bands.clone(),
+                books: books.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+            .with_exchanges(vec![Exchange::BITSTAMP])
+            .with_liquidity_bands(bands)
+            .with_books(books);
+        let watch = |liquidity_bps: Vec<f64>, exchanges: Vec<String>| {
+            service.watch_summary(tonic::Request::new(WatchSummaryRequest {
+                liquidity_bps,
+                exchanges,
+                ..Default::default()
+            }))
+        };
+        let status = watch(vec![10.0, 0.0], Vec::new()).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut defaults = watch(Vec::new(), Vec::new()).await.unwrap().into_inner();
+        let mut custom = watch(vec![4.0, 10.0], Vec::new())
+            .await
+            .unwrap()
+            .into_inner();
+        let mut filtered = watch(Vec::new(), vec!["bitstamp".to_string()])
+            .await
+            .unwrap()
+            .into_inner();
+        for stream in [&mut defaults, &mut custom, &mut filtered] {
+            assert_eq!(stream.next().await.unwrap().unwrap().sequence, 0);
+        }
+        // The mid is 30000 with a level at each price from 29990 down and 30010 up.
+        tx_levels.send(book_levels(1, 5)).await.unwrap();
+        let bands = |summary: Summary| {
+            summary
+                .liquidity
+                .iter()
+                .map(|l| (l.bps, l.bid_quantity, l.ask_quantity, l.truncated))
+                .collect::<Vec<_>>()
+        };
+        let summary = defaults.next().await.unwrap().unwrap();
+        assert_eq!(
+            bands(summary),
+            [
+                (5.0, 5.0, 5.0, true),
+                (10.0, 5.0, 5.0, true),
+                (25.0, 5.0, 5.0, true),
+            ]
+        );
+        let summary = custom.next().await.unwrap().unwrap();
+        assert!(summary.liquidity.iter().all(|l| !l.every_exchange));
+        assert_eq!(
+            bands(summary),
+            [(4.0, 3.0, 3.0, false), (10.0, 5.0, 5.0, true)]
+        );
+        // The bands of a client leaving out exchanges still count them, and say so.
+        let summary = filtered.next().await.unwrap().unwrap();
+        assert_eq!(summary.liquidity.len(), 3);
+        assert!(summary.liquidity.iter().all(|l| l.every_exchange));
+
+        // GetSummary reports the bands it asks for, working out those no stream asked for.
+        let get = |liquidity_bps: Vec<f64>, weights: &[(&str, f64)]| {
+            service.get_summary(tonic::Request::new(SummaryRequest {
+                symbol: "BTCUSDT".to_string(),
+                liquidity_bps,
+                exchange_weights: weights.iter().map(|(e, w)| (e.to_string(), *w)).collect(),
+                ..Default::default()
+            }))
+        };
+        let summary = get(Vec::new(), &[]).await.unwrap().into_inner();
+        let reported = bands(summary).iter().map(|band| band.0).collect::<Vec<_>>();
+        assert_eq!(reported, liquidity::DEFAULT_BPS);
+        let summary = get(vec![4.0], &[]).await.unwrap().into_inner();
+        assert_eq!(bands(summary), [(4.0, 3.0, 3.0, false)]);
+        let summary = get(vec![3.5, 10.0], &[]).await.unwrap().into_inner();
+        assert_eq!(
+            bands(summary),
+            [(3.5, 1.0, 1.0, false), (10.0, 5.0, 5.0, true)]
+        );
+        let summary = get(vec![4.0], &[("bitstamp", 0.5)])
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(summary.liquidity[0].every_exchange);
+        assert_eq!(bands(summary), [(4.0, 3.0, 3.0, false)]);
+        let status = get(vec![60.0], &[]).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "liquidity_bps must be above 0 and at most 50"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_order_flow_imbalance_without_counting_reconnects() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP, Exchange::BINANCE],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let mut stream = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().ofi, None);
+        let top = |exchange: Exchange, bid_quantity: f64| {
+            let level = |price, quantity| Level {
+                exchange: exchange.to_string(),
+                price,
+                quantity,
+                updated_at: None,
+                market_type: exchange.market_type().into(),
+            };
+            Arc::new(BookLevels {
+                exchange,
+                symbol: Symbol::BTCUSDT,
+                bids: vec![level(29999.0, bid_quantity)],
+                asks: vec![level(30001.0, 1.0)],
+                ..Default::default()
+            })
+        };
+
+        let mut ofi = Vec::new();
+        for levels in [
+            top(Exchange::BITSTAMP, 1.0),
+            top(Exchange::BITSTAMP, 3.0),
+            top(Exchange::BINANCE, 1.0),
+        ] {
+            tx_levels.send(levels).await.unwrap();
+            ofi.push(stream.next().await.unwrap().unwrap().ofi);
+        }
+        // Bitstamp is switched off and back on with a bigger bid, only the next baseline.
+        tx_closed
+            .send(ExchangeClosed {
+                exchange: Exchange::BITSTAMP,
+                reason: "disabled".to_string(),
+                lost: false,
+                disabled: true,
+                delisted: false,
+            })
+            .await
+            .unwrap();
+        ofi.push(stream.next().await.unwrap().unwrap().ofi);
+        for levels in [top(Exchange::BITSTAMP, 10.0), top(Exchange::BITSTAMP, 11.0)] {
+            tx_levels.send(levels).await.unwrap();
+            ofi.push(stream.next().await.unwrap().unwrap().ofi);
+        }
+        assert_eq!(
+            ofi,
+            [None, Some(2.0), Some(2.0), Some(2.0), Some(2.0), Some(3.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_sends_each_alert_watcher_the_changes_of_its_rules() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let watch = |condition, hysteresis| {
+            service.watch_alerts(tonic::Request::new(WatchAlertsRequest {
+                symbol: "btcusdt".to_string(),
+                rules: vec![AlertRule {
+                    id: "rule".to_string(),
+                    condition: Some(condition),
+                    hysteresis,
+                    ..Default::default()
+                }],
+            }))
+        };
+        // Reads whether each of the first `count` alerts triggered or cleared.
+        let changes = |response: tonic::Response<_>, count| {
+            let stream: <OrderbookSummary as OrderbookAggregator>::WatchAlertsStream =
+                response.into_inner();
+            tokio::spawn(timeout(
+                Duration::from_secs(5),
+                stream
+                    .take(count)
+                    .map(|alert| alert.unwrap().triggered)
+                    .collect::<Vec<_>>(),
+            ))
+        };
+        let wide = changes(
+            watch(Condition::SpreadBpsAbove(10.0), 2.0).await.unwrap(),
+            4,
+        );
+        let thin = changes(watch(Condition::TopSizeBelow(0.5), 0.0).await.unwrap(), 2);
+
+        // The spread starts at about 6.7bps, goes out to 20 twice, flickering down to 9.5
+        // within its hysteresis the first time, and the size thins out in between.
+        let mut id = 0;
+        for (ask, quantity) in [
+            (30010.0, 1.0),
+            (30050.0, 1.0),
+            (30018.5, 1.0),
+            (30050.0, 1.0),
+            (30010.0, 1.0),
+            (30010.0, 0.1),
+            (30010.0, 1.0),
+            (30050.0, 1.0),
+            (30010.0, 1.0),
+        ] {
+            for _ in 0..5 {
+                let mut levels = Arc::unwrap_or_clone(book_levels(id, 5));
+                levels.asks[0].price = ask;
+                levels.asks[0].quantity = quantity;
+                tx_levels.send(Arc::new(levels)).await.unwrap();
+                id += 1;
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+        let wide = wide.await.unwrap().expect("alerts were not sent");
+        assert_eq!(wide, [true, false, true, false]);
+        let thin = thin.await.unwrap().expect("alerts were not sent");
+        assert_eq!(thin, [true, false]);
+
+        let status = service
+            .watch_alerts(tonic::Request::new(WatchAlertsRequest {
+                symbol: "ethbtc".to_string(),
+                rules: vec![],
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = watch(Condition::TopSizeBelow(-1.0), 0.0)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    /// Feeds levels to a summary task quoting `exchanges` and returns the divergence
+    /// events for a threshold of 10bps, with 2bps of hysteresis. Each step gives the mid
+    /// price of each exchange.
+    async fn divergence_events(exchanges: &[Exchange], steps: &[&[f64]]) -> Vec<Divergence> {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let quotes = Quotes::default();
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            exchanges.to_vec(),
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                quotes: quotes.clone(),
+                ..Default::default()
+            },
+        );
+        let (tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_quotes(quotes);
+        let stream = service
+            .watch_divergence(tonic::Request::new(WatchDivergenceRequest {
+                symbol: "btcusdt".to_string(),
+                threshold_bps: 10.0,
+                hysteresis_bps: 2.0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let events = tokio::spawn(stream.map(Result::unwrap).collect::<Vec<_>>());
+
+        for (id, mids) in steps.iter().enumerate() {
+            for (&exchange, &mid) in exchanges.iter().zip(mids.iter()) {
+                let level = |price| Level {
+                    exchange: exchange.to_string(),
+                    price,
+                    quantity: 1.0,
+                    updated_at: None,
+                    market_type: exchange.market_type().into(),
+                };
+                let levels = BookLevels {
+                    exchange,
+                    symbol: Symbol::BTCUSDT,
+                    last_update_id: id as u64,
+                    bids: vec![level(mid - 1.0)],
+                    asks: vec![level(mid + 1.0)],
+                    depth: None,
+                    restored: false,
+                };
+                tx_levels.send(Arc::new(levels)).await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let _ = tx_shutdown.send(true);
+        timeout(Duration::from_secs(5), events)
+            .await
+            .expect("divergence stream did not end")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_streams_divergence_between_two_exchanges() {
+        let exchanges = [Exchange::BINANCE, Exchange::BITSTAMP];
+        // Bitstamp starts 5bps above binance and moves out to 15, back within the
+        // hysteresis to 9, out to 12 and back to 5, then goes 24 below.
+        let events = divergence_events(
+            &exchanges,
+            &[
+                &[30000.0, 30015.0],
+                &[30000.0, 30045.0],
+                &[30000.0, 30027.0],
+                &[30000.0, 30036.0],
+                &[30000.0, 30015.0],
+                &[30000.0, 29928.0],
+            ],
+        )
+        .await;
+        let diverged = events.iter().map(|e| e.diverged).collect::<Vec<_>>();
+        assert_eq!(diverged, [true, false, true]);
+        assert!(events
+            .iter()
+            .all(|e| (e.exchange_a.as_str(), e.exchange_b.as_str()) == ("BINANCE", "BITSTAMP")));
+        assert_eq!(events[0].difference_bps.round(), 15.0);
+        assert_eq!(events[2].difference_bps.round(), -24.0);
+        // Bitstamp's bid of 30044 was above binance's ask of 30001, and then binance's bid
+        // of 29999 above bitstamp's ask of 29929.
+        assert_eq!(events[0].crossed_quantity, 1.0);
+        assert_eq!(events[2].crossed_quantity, 1.0);
+    }
+
+    #[tokio::test]
+    async fn it_streams_the_ticker_only_when_the_best_prices_change() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let quotes = Quotes::default();
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                quotes: quotes.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_quotes(quotes);
+        let request = |symbol: &str| {
+            tonic::Request::new(TickerRequest {
+                symbol: symbol.to_string(),
+            })
+        };
+        let status = service.get_ticker(request("ETHBTC")).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = service.get_ticker(request("BTCUSDT")).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        let mut stream = service
+            .watch_ticker(request("BTCUSDT"))
+            .await
+            .unwrap()
+            .into_inner();
+        tx_levels.send(book_levels(1, 5)).await.unwrap();
+        let ticker = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            (ticker.bid, ticker.ask, ticker.spread),
+            (29990.0, 30010.0, 20.0)
+        );
+        assert_eq!(ticker.bid_exchange, "BITSTAMP");
+
+        // Levels below the best ones change without sending a ticker.
+        let mut deep = BookLevels::clone(&book_levels(2, 5));
+        deep.bids[3].quantity = 4.0;
+        deep.asks[1].quantity = 0.5;
+        tx_levels.send(Arc::new(deep.clone())).await.unwrap();
+        assert!(timeout(Duration::from_millis(100), stream.next())
+            .await
+            .is_err());
+        deep.bids[0].quantity = 2.0;
+        tx_levels.send(Arc::new(deep)).await.unwrap();
+        let ticker = stream.next().await.unwrap().unwrap();
+        assert_eq!(ticker.bid_quantity, 2.0);
+        let got = service.get_ticker(request("BTCUSDT")).await.unwrap();
+        assert_eq!(got.get_ref().bid_quantity, 2.0);
+
+        // Nothing watched the summaries, so only the one a watcher subscribes to is built.
+        let mut summaries = service
+            .watch_summary(tonic::Request::new(WatchSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        let summary = summaries.next().await.unwrap().unwrap();
+        assert_eq!(summary.sequence, 1);
+        assert_eq!(summary.bids[0].quantity, 2.0);
+    }
+
+    #[tokio::test]
+    async fn it_streams_the_spread_of_the_ticker() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let quotes = Quotes::default();
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                quotes: quotes.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_quotes(quotes);
+        let request = |symbol: &str, min_change_bps| {
+            tonic::Request::new(WatchSpreadRequest {
+                symbol: symbol.to_string(),
+                min_change_bps,
+                max_interval_ms: 0,
+            })
+        };
+        let status = service
+            .watch_spread(request("ETHBTC", 1.0))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        for min_change_bps in [-1.0, f64::NAN] {
+            let status = service
+                .watch_spread(request("BTCUSDT", min_change_bps))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+
+        let mut stream = service
+            .watch_spread(request("BTCUSDT", 1.0))
+            .await
+            .unwrap()
+            .into_inner();
+        tx_levels.send(book_levels(1, 5)).await.unwrap();
+        let spread = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            (spread.symbol.as_str(), spread.spread, spread.mid),
+            ("BTCUSDT", 20.0, 30000.0)
+        );
+        assert!((spread.spread_bps - 20.0 / 3.0).abs() < 1e-9);
+        // Quantities changing leave the spread where it was.
+        let mut levels = BookLevels::clone(&book_levels(2, 5));
+        levels.bids[0].quantity = 2.0;
+        tx_levels.send(Arc::new(levels)).await.unwrap();
+        assert!(timeout(Duration::from_millis(100), stream.next())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_serves_bursts_of_get_summary_from_one_summary() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let request = |symbol: &str, levels| {
+            tonic::Request::new(SummaryRequest {
+                symbol: symbol.to_string(),
+                levels,
+                ..Default::default()
+            })
+        };
+        let status = service
+            .get_summary(request("ETHBTC", 0))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = service
+            .get_summary(request("BTCUSDT", 0))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        // Nothing is watching, so the levels are only summarized once asked for.
+        tx_levels.send(book_levels(1, 5)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let burst = (0..20).map(|_| service.get_summary(request("BTCUSDT", 2)));
+        for response in futures::future::join_all(burst).await {
+            let summary = response.unwrap().into_inner();
+            assert_eq!(summary.sequence, 1);
+            assert_eq!((summary.bids.len(), summary.asks.len()), (2, 2));
+        }
+
+        // When each level was last updated is only sent when asked for.
+        let mut levels = BookLevels::clone(&book_levels(2, 5));
+        levels.bids[0].updated_at = Some(1_700_000_000_000);
+        tx_levels.send(Arc::new(levels)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for include_level_ages in [false, true] {
+            let summary = service
+                .get_summary(tonic::Request::new(SummaryRequest {
+                    symbol: "BTCUSDT".to_string(),
+                    include_level_ages,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                summary.bids[0].updated_at,
+                include_level_ages.then_some(1_700_000_000_000)
+            );
+            assert_eq!(summary.asks[0].updated_at, None);
+        }
+
+        // Each side can be asked for its own levels, or left out.
+        for (bid_levels, ask_levels, sent) in [
+            (Some(4), None, (4, 2)),
+            (None, Some(0), (2, 0)),
+            (Some(5), Some(1), (5, 1)),
+        ] {
+            let summary = service
+                .get_summary(tonic::Request::new(SummaryRequest {
+                    bid_levels,
+                    ask_levels,
+                    ..request("BTCUSDT", 2).into_inner()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!((summary.bids.len(), summary.asks.len()), sent);
+        }
+    }
+
+    /// A summary of three Bitstamp levels a side to cut
+    fn levels_to_cut() -> Summary {
+        let level = |price: f64, quantity: f64| Level {
+            exchange: Exchange::BITSTAMP.to_string(),
+            price,
+            quantity,
+            updated_at: None,
+            market_type: Exchange::BITSTAMP.market_type().into(),
+        };
+        Summary {
+            // Adding up to 1000, 2980 and 7880 of notional.
+            bids: vec![level(100.0, 10.0), level(99.0, 20.0), level(98.0, 50.0)],
+            // 1010, 3040 and 8140.
+            asks: vec![level(101.0, 10.0), level(101.5, 20.0), level(102.0, 50.0)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_cuts_each_side_to_the_levels_asked_for_it() {
+        let summary = levels_to_cut();
+        let cut = |levels, bid_levels, ask_levels, notional| {
+            let mut summary = summary.clone();
+            let levels = SideLevels::of(levels, bid_levels, ask_levels);
+            cut_levels(&mut summary, levels, notional);
+            (summary.bids.len(), summary.asks.len())
+        };
+        // Without either side set, levels cuts both, 0 keeping every level.
+        assert_eq!(cut(2, None, None, 0.0), (2, 2));
+        assert_eq!(cut(0, None, None, 0.0), (3, 3));
+        // A side set takes the place of levels for that side alone.
+        assert_eq!(cut(1, Some(3), None, 0.0), (3, 1));
+        assert_eq!(cut(2, None, Some(1), 0.0), (2, 1));
+        assert_eq!(cut(0, Some(1), None, 0.0), (1, 3));
+        assert_eq!(cut(0, Some(1), Some(2), 0.0), (1, 2));
+        assert_eq!(cut(3, Some(1), Some(2), 0.0), (1, 2));
+        // 0 leaves a side out, unlike levels.
+        assert_eq!(cut(2, Some(0), None, 0.0), (0, 2));
+        assert_eq!(cut(0, None, Some(0), 0.0), (3, 0));
+        assert_eq!(cut(0, Some(0), Some(0), 0.0), (0, 0));
+        // The notional is added up over each side's own levels.
+        assert_eq!(cut(0, Some(3), Some(1), 3000.0), (3, 1));
+        assert_eq!(cut(0, Some(1), Some(3), 3000.0), (1, 3));
+    }
+
+    #[test]
+    fn it_cuts_each_side_where_the_notional_is_reached() {
+        let summary = levels_to_cut();
+        let cut = |levels, notional| {
+            let mut summary = summary.clone();
+            cut_levels(&mut summary, SideLevels::both(levels), notional);
+            (summary.bids.len(), summary.asks.len())
+        };
+        // The level that reaches the notional is kept.
+        assert_eq!(cut(0, 2980.0), (2, 2));
+        assert_eq!(cut(0, 3000.0), (3, 2));
+        assert_eq!(cut(0, 1000.0), (1, 1));
+        // Sides holding less keep every level.
+        assert_eq!(cut(0, 1_000_000.0), (3, 3));
+        // Whichever of levels and the notional keeps fewer wins.
+        assert_eq!(cut(1, 3000.0), (1, 1));
+        assert_eq!(cut(3, 1000.0), (1, 1));
+        assert_eq!(cut(0, 0.0), (3, 3));
+    }
+
+    /// The levels of an exchange's book with depth, priced and sized in whole units
+    fn depth_levels(exchange: Exchange, [bids, asks]: &[BTreeMap<u64, u64>; 2]) -> Arc<BookLevels> {
+        let depth = Depth {
+            bids: bids
+                .iter()
+                .rev()
+                .map(|(&price, &quantity)| [price, quantity])
+                .collect(),
+            asks: asks
+                .iter()
+                .map(|(&price, &quantity)| [price, quantity])
+                .collect(),
+            ..Default::default()
+        };
+        let top = |levels: &[[u64; 2]]| {
+            levels
+                .iter()
+                .take(5)
+                .map(|&[price, quantity]| Level {
+                    exchange: exchange.to_string(),
+                    price: price as f64,
+                    quantity: quantity as f64,
+                    updated_at: None,
+                    market_type: exchange.market_type().into(),
+                })
+                .collect()
+        };
+        Arc::new(BookLevels {
+            exchange,
+            symbol: Symbol::BTCUSDT,
+            last_update_id: 0,
+            bids: top(&depth.bids),
+            asks: top(&depth.asks),
+            depth: Some(Arc::new(depth)),
+            restored: false,
+        })
+    }
+
+    /// Every bid and ask of `books`, best first and then by exchange
+    fn merged(books: &HashMap<Exchange, [BTreeMap<u64, u64>; 2]>) -> (Vec<Level>, Vec<Level>) {
+        let mut merged = [Vec::new(), Vec::new()];
+        for (exchange, sides) in books {
+            for (levels, side) in merged.iter_mut().zip(sides) {
+                levels.extend(side.iter().map(|(&price, &quantity)| Level {
+                    exchange: exchange.to_string(),
+                    price: price as f64,
+                    quantity: quantity as f64,
+                    updated_at: None,
+                    market_type: exchange.market_type().into(),
+                }));
+            }
+        }
+        let [mut bids, mut asks] = merged;
+        bids.sort_by(|a, b| {
+            b.price
+                .total_cmp(&a.price)
+                .then_with(|| a.exchange.cmp(&b.exchange))
+        });
+        asks.sort_by(|a, b| {
+            a.price
+                .total_cmp(&b.price)
+                .then_with(|| a.exchange.cmp(&b.exchange))
+        });
+        (bids, asks)
+    }
+
+    #[tokio::test]
+    async fn it_streams_a_book_clients_can_rebuild_at_every_sequence() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let books = Books::default();
+        let tx_summary = spawn_summary_with_feeds(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP, Exchange::BINANCE],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+            SummaryFeeds {
+                books: books.clone(),
+                ..Default::default()
+            },
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+            .with_exchanges(vec![Exchange::BITSTAMP, Exchange::BINANCE])
+            .with_books(books);
+        let request = |symbol: &str| {
+            tonic::Request::new(WatchBookRequest {
+                symbol: symbol.to_string(),
+                exchanges: vec![],
+            })
+        };
+        let status = service.watch_book(request("ETHBTC")).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let mut stream = service
+            .watch_book(request("BTCUSDT"))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut local = LocalBook::new();
+        let first = stream.next().await.unwrap().unwrap();
+        assert!(first.snapshot && first.changes.is_empty());
+        local.apply(&first).unwrap();
+
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let mut expected = HashMap::<Exchange, [BTreeMap<u64, u64>; 2]>::new();
+        let mut last = merged(&expected);
+        let mut snapshots = 0;
+        for step in 1..=300 {
+            let exchange = if rng.gen_bool(0.5) {
+                Exchange::BITSTAMP
+            } else {
+                Exchange::BINANCE
+            };
+            if step % 40 == 0 {
+                // The exchange reconnects, its book rebuilt from nothing.
+                expected.remove(&exchange);
+                let closed = ExchangeClosed {
+                    exchange,
+                    reason: "connection reset".to_string(),
+                    lost: false,
+                    disabled: false,
+                    delisted: false,
+                };
+                tx_closed.send(closed).await.unwrap();
+            } else {
+                // Each step adds, changes or deletes one level.
+                let book = expected.entry(exchange).or_default();
+                let side = rng.gen_range(0..2);
+                let price = match side {
+                    0 => rng.gen_range(990..1000),
+                    _ => rng.gen_range(1001..1011),
+                };
+                match book[side].get(&price).copied() {
+                    Some(_) if rng.gen_bool(0.5) => {
+                        book[side].remove(&price);
+                    }
+                    Some(quantity) => {
+                        book[side].insert(price, quantity % 3 + 1);
+                    }
+                    None => {
+                        book[side].insert(price, rng.gen_range(1..4));
+                    }
+                }
+                tx_levels.send(depth_levels(exchange, book)).await.unwrap();
+            }
+
+            let now = merged(&expected);
+            if now == last {
+                continue;
+            }
+            let update = timeout(Duration::from_secs(1), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            snapshots += update.snapshot as usize;
+            local.apply(&update).unwrap();
+            assert_eq!(
+                (local.bids(), local.asks()),
+                now,
+                "book differs at sequence {}",
+                update.sequence
+            );
+            last = now;
+        }
+        // The books rebuilt after reconnecting were sent whole.
+        assert!(snapshots > 1, "only {} snapshots", snapshots);
+    }
+
+    #[tokio::test]
+    async fn it_streams_candles_of_the_mid_price() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let watch = |symbol: &str, interval_ms| {
+            service.watch_candles(tonic::Request::new(WatchCandlesRequest {
+                symbol: symbol.to_string(),
+                interval_ms,
+                throttle_ms: 0,
+            }))
+        };
+        let status = watch("ethbtc", 1000).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = watch("btcusdt", 2000).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut stream = watch("btcusdt", 1000).await.unwrap().into_inner();
+        let level = |price| Level {
+            exchange: Exchange::BITSTAMP.to_string(),
+            price,
+            quantity: 1.0,
+            updated_at: None,
+            market_type: Exchange::BITSTAMP.market_type().into(),
+        };
+        let levels = BookLevels {
+            exchange: Exchange::BITSTAMP,
+            symbol: Symbol::BTCUSDT,
+            last_update_id: 1,
+            bids: vec![level(29999.0)],
+            asks: vec![level(30001.0)],
+            depth: None,
+            restored: false,
+        };
+        tx_levels.send(Arc::new(levels)).await.unwrap();
+
+        let candle = stream.next().await.unwrap().unwrap();
+        assert_eq!(candle.open, 30000.0);
+        assert_eq!(candle.spread_close, 2.0);
+        assert!(!candle.finalized);
+        assert_eq!(candle.open_time_ms % 1000, 0);
+        // The candle is sent again once its second is up, without another summary.
+        let closed = timeout(Duration::from_secs(2), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(closed.finalized);
+        assert_eq!(closed.open_time_ms, candle.open_time_ms);
+        assert_eq!(closed.close, 30000.0);
+    }
+
+    #[tokio::test]
+    async fn it_streams_the_trades_each_client_asks_for() {
+        let (_tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BINANCE, Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let status = service
+            .watch_trades(tonic::Request::new(WatchTradesRequest {
+                symbol: "btcusdt".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let (tx_trades, rx_trades) = mpsc::channel(10);
+        let feed = TradeFeed::new(
+            Symbol::BTCUSDT,
+            vec![Exchange::BINANCE, Exchange::BITSTAMP],
+            rx_trades,
+        );
+        let service = service.with_trades(feed);
+        let watch = |exchanges: &[&str], min_quantity: f64| {
+            service.watch_trades(tonic::Request::new(WatchTradesRequest {
+                symbol: "btcusdt".to_string(),
+                exchanges: exchanges.iter().map(|e| e.to_string()).collect(),
+                min_quantity,
+            }))
+        };
+        let status = watch(&["binance_futures"], 0.0).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = watch(&[], -1.0).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let all = watch(&[], 0.0).await.unwrap().into_inner();
+        let bitstamp = watch(&["bitstamp"], 1.0).await.unwrap().into_inner();
+        let all = tokio::spawn(all.map(Result::unwrap).collect::<Vec<_>>());
+        let bitstamp = tokio::spawn(bitstamp.map(Result::unwrap).collect::<Vec<_>>());
+        for (exchange, quantity) in [
+            (Exchange::BITSTAMP, 0.5),
+            (Exchange::BINANCE, 2.0),
+            (Exchange::BITSTAMP, 1.5),
+        ] {
+            let trade = Trade {
+                exchange: exchange.to_string(),
+                quantity,
+                ..Default::default()
+            };
+            tx_trades.send(trade).await.unwrap();
+        }
+        // Both clients have had every trade once the one taking all of them has.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tx_shutdown.send(true).unwrap();
+
+        let sequences = |trades: Vec<Trade>| trades.iter().map(|t| t.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences(all.await.unwrap()), [1, 2, 3]);
+        assert_eq!(sequences(bitstamp.await.unwrap()), [3]);
+    }
+
+    #[tokio::test]
+    async fn it_streams_divergence_of_only_the_pair_that_diverges() {
+        let exchanges = [
+            Exchange::BINANCE,
+            Exchange::BITSTAMP,
+            Exchange::BINANCE_FUTURES,
+        ];
+        // Bitstamp stays halfway between the two binance books, which go 12bps apart.
+        let events = divergence_events(
+            &exchanges,
+            &[
+                &[30000.0, 30009.0, 30018.0],
+                &[30000.0, 30018.0, 30036.0],
+                &[30000.0, 30012.0, 30024.0],
+            ],
+        )
+        .await;
+        let pairs = events
+            .iter()
+            .map(|e| (e.exchange_a.as_str(), e.exchange_b.as_str(), e.diverged))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [
+                ("BINANCE", "BINANCE_FUTURES", true),
+                ("BINANCE", "BINANCE_FUTURES", false)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_validates_the_update_speed() {
+        let (_tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+        let watch = |min_interval_ms, update_speed_ms| {
+            service.watch_summary(tonic::Request::new(WatchSummaryRequest {
+                min_interval_ms,
+                update_speed_ms,
+                ..Default::default()
+            }))
+        };
+
+        for (min_interval_ms, update_speed_ms) in [(0, 100), (0, 250)] {
+            let status = watch(min_interval_ms, update_speed_ms).await.err().unwrap();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        for (min_interval_ms, update_speed_ms) in [(50, 100), (0, 1000), (0, 0)] {
+            assert!(watch(min_interval_ms, update_speed_ms).await.is_ok());
+        }
+    }
+
+    #[test]
+    fn it_parses_the_enabled_exchanges() {
+        assert_eq!(
+            parse_exchanges(&["binance", " BITSTAMP", "binance"]).unwrap(),
+            [Exchange::BINANCE, Exchange::BITSTAMP]
+        );
+        assert_eq!(
+            parse_exchanges(&["binance_futures"]).unwrap(),
+            [Exchange::BINANCE_FUTURES]
+        );
+        assert!(parse_exchanges::<&str>(&[]).is_err());
+        let err = parse_exchanges(&["kraken"]).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown exchange kraken"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn it_parses_and_clamps_exchange_depths() {
+        let depths = parse_depths(&["binance=10000", " bitstamp = 500"]).unwrap();
+        assert!(parse_depths(&["binance=0"]).is_err());
+        assert!(parse_depths(&["binance=deep"]).is_err());
+        let mut options = ExchangeOptions {
+            bitstamp_channel: BitstampChannel::Diff,
+            ..Default::default()
+        };
+        let clamped = clamp_depths(&depths, &options);
+        assert_eq!(clamped[&Exchange::BINANCE], 5000);
+        assert_eq!(clamped[&Exchange::BITSTAMP], 500);
+        options.bitstamp_channel = BitstampChannel::OrderBook;
+        let clamped = clamp_depths(&depths, &options);
+        assert_eq!(clamped[&Exchange::BITSTAMP], 100);
+    }
+
+    #[test]
+    fn it_parses_exchange_weights() {
+        let weights = parse_weights(&["bitstamp=0.8", " binance = 0"]).unwrap();
+        assert_eq!(weights[&Exchange::BITSTAMP], 0.8);
+        assert_eq!(weights[&Exchange::BINANCE], 0.0);
+        assert!(parse_weights::<&str>(&[]).unwrap().is_empty());
+        assert!(parse_weights(&["bitstamp=-1"]).is_err());
+        assert!(parse_weights(&["bitstamp"]).is_err());
+        assert!(parse_weights(&["kraken=1"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_weighs_each_exchanges_quantities() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let exchanges = vec![Exchange::BITSTAMP, Exchange::BINANCE];
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            exchanges.clone(),
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+            .with_exchanges(exchanges)
+            .with_weights(HashMap::from([(Exchange::BITSTAMP, 0.8)]));
+        let watch = |weights: &[(&str, f64)]| {
+            service.watch_summary(tonic::Request::new(WatchSummaryRequest {
+                exchange_weights: weights.iter().map(|(e, w)| (e.to_string(), *w)).collect(),
+                ..Default::default()
+            }))
+        };
+        let status = watch(&[("bitstamp", -0.5)]).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = watch(&[("binance_futures", 1.0)]).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        // Binance levels sit half way between Bitstamp's.
+        tx_levels.send(book_levels(1, 3)).await.unwrap();
+        let mut binance = BookLevels::clone(&book_levels(1, 3));
+        binance.exchange = Exchange::BINANCE;
+        for level in binance.bids.iter_mut().chain(binance.asks.iter_mut()) {
+            level.exchange = Exchange::BINANCE.to_string();
+            level.price += if level.price < 30000.0 { -0.5 } else { 0.5 };
+        }
+        tx_levels.send(Arc::new(binance)).await.unwrap();
+        let latest = |mut stream: <OrderbookSummary as OrderbookAggregator>::WatchSummaryStream| async move {
+            loop {
+                let summary = stream.next().await.unwrap().unwrap();
+                if summary.sequence == 2 {
+                    break summary;
+                }
+            }
+        };
+        let vwap = |levels: &[Level]| {
+            let quantity = levels.iter().map(|l| l.quantity).sum::<f64>();
+            levels.iter().map(|l| l.price * l.quantity).sum::<f64>() / quantity
+        };
+
+        let summary = latest(watch(&[]).await.unwrap().into_inner()).await;
+        let quantities = summary.bids.iter().map(|l| l.quantity).collect::<Vec<_>>();
+        assert_eq!(quantities, [0.8, 1.0, 0.8]);
+        assert_eq!(summary.bids[1].exchange, "BINANCE");
+        assert!(
+            (vwap(&summary.bids) - (29990.0 * 0.8 + 29989.5 + 29989.0 * 0.8) / 2.6).abs() < 1e-9
+        );
+
+        // The client's weights replace the server's, and Bitstamp's 0 leaves Binance only.
+        let summary = latest(
+            watch(&[("bitstamp", 0.0), ("binance", 2.0)])
+                .await
+                .unwrap()
+                .into_inner(),
+        )
+        .await;
+        assert_eq!(summary.bids.len(), 1);
+        assert_eq!(summary.bids[0].exchange, "BINANCE");
+        assert_eq!(summary.bids[0].quantity, 2.0);
+        assert_eq!(summary.spread, 30010.5 - 29989.5);
+        assert_eq!(vwap(&summary.asks), 30010.5);
+
+        // GetSummary weighs them the same way.
+        let get = |weights: &[(&str, f64)]| {
+            service.get_summary(tonic::Request::new(SummaryRequest {
+                symbol: "BTCUSDT".to_string(),
+                exchange_weights: weights.iter().map(|(e, w)| (e.to_string(), *w)).collect(),
+                ..Default::default()
+            }))
+        };
+        let summary = get(&[]).await.unwrap().into_inner();
+        let quantities = summary.bids.iter().map(|l| l.quantity).collect::<Vec<_>>();
+        assert_eq!(quantities, [0.8, 1.0, 0.8]);
+        let summary = get(&[("bitstamp", 0.0)]).await.unwrap().into_inner();
+        assert!(summary.bids.iter().all(|l| l.exchange == "BINANCE"));
+        assert!(!summary.native_symbols.contains_key("BITSTAMP"));
+        let status = get(&[("bitstamp", -0.5)]).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    /// Counts the connections made to it, closing each straight away, and returns the
+    /// endpoints to reach it by.
+    async fn counting_server() -> (Endpoints, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                drop(socket);
+            }
+        });
+        let endpoints =
+            Endpoints::new(&format!("http://{}/", addr), &format!("ws://{}/", addr)).unwrap();
+        (endpoints, connections)
+    }
+
+    #[tokio::test]
+    async fn it_never_contacts_disabled_exchanges() {
+        let (bitstamp, bitstamp_connections) = counting_server().await;
+        let (binance, binance_connections) = counting_server().await;
+        let (futures, futures_connections) = counting_server().await;
+        let options = ExchangeOptions {
+            exchanges: vec![Exchange::BITSTAMP],
+            endpoints: HashMap::from([
+                (Exchange::BITSTAMP, bitstamp),
+                (Exchange::BINANCE, binance),
+                (Exchange::BINANCE_FUTURES, futures),
+            ]),
+            ..Default::default()
+        };
+        let http = HttpClient::new(&Default::default()).unwrap();
+        let _tx_summary = start_symbol(
+            http,
+            &options,
+            Symbol::BTCUSDT,
+            5,
+            5,
+            watch::channel(true).0,
+        );
+
+        timeout(Duration::from_secs(5), async {
+            while bitstamp_connections.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the enabled exchange was never contacted");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(binance_connections.load(Ordering::SeqCst), 0);
+        assert_eq!(futures_connections.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn it_filters_summaries_to_enabled_exchanges() {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let exchanges = vec![Exchange::BITSTAMP, Exchange::BINANCE];
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            exchanges.clone(),
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_exchanges(exchanges);
+        let enabled = service
+            .get_exchanges(tonic::Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(enabled.exchanges, ["BITSTAMP", "BINANCE"]);
+        let symbols = service
+            .get_symbols(tonic::Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(symbols.symbols, ["BTCUSDT"]);
+
+        let watch = |exchanges: &[&str]| {
+            service.watch_summary(tonic::Request::new(WatchSummaryRequest {
+                exchanges: exchanges.iter().map(|e| e.to_string()).collect(),
+                ..Default::default()
+            }))
+        };
+        let status = watch(&["binance_futures"]).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = watch(&["bitstamp", "kraken"]).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut stream = watch(&["binance"]).await.unwrap().into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().sequence, 0);
+        // Binance sits inside Bitstamp's best prices, so the spread narrows to its own.
+        tx_levels.send(book_levels(1, 3)).await.unwrap();
+        let mut binance = BookLevels::clone(&book_levels(1, 3));
+        binance.exchange = Exchange::BINANCE;
+        for level in binance.bids.iter_mut().chain(binance.asks.iter_mut()) {
+            level.exchange = Exchange::BINANCE.to_string();
+            level.price += if level.price < 30000.0 { 5.0 } else { -5.0 };
+        }
+        tx_levels.send(Arc::new(binance)).await.unwrap();
+
+        let summary = loop {
+            let summary = stream.next().await.unwrap().unwrap();
+            if summary.sequence == 2 {
+                break summary;
+            }
+            // Only Bitstamp has sent levels, and none of them are wanted.
+            assert!(summary.bids.is_empty());
+            assert_eq!(summary.unavailable_exchanges, ["BINANCE"]);
+        };
+        assert!(summary
+            .bids
+            .iter()
+            .chain(summary.asks.iter())
+            .all(|level| level.exchange == "BINANCE"));
+        assert_eq!(summary.bids.len(), 3);
+        assert_eq!(summary.spread, 10.0);
+        assert!(!summary.degraded);
+    }
+
+    /// Applies `count` synthetic bitstamp updates through an order book while `readers`
+    /// clients watch the summaries, checking each summary a client receives is complete.
+    /// Returns how long the updates took to apply.
+    async fn run_synthetic_feed(count: u64, readers: usize) -> Duration {
+        let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+        let (_tx_closed, rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+        let tx_summary = spawn_summary(
+            Symbol::BTCUSDT,
+            vec![Exchange::BITSTAMP],
+            rx_levels,
+            rx_closed,
+            watch::channel(true).0,
+        );
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown);
+
+        // Every update touches the top five levels, so each one publishes a summary after
+        // the one for the snapshot.
+        let last = count + 1;
+        let mut clients = Vec::new();
+        for _ in 0..readers {
+            let request = WatchSummaryRequest {
+                min_interval_ms: 5,
+                ..Default::default()
+            };
+            let mut stream = service
+                .watch_summary(tonic::Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            clients.push(tokio::spawn(async move {
+                let mut previous = 0;
+                while let Some(result) = stream.next().await {
+                    let summary = result.unwrap();
+                    if summary.sequence == 0 {
+                        continue;
+                    }
+                    assert!(summary.sequence > previous);
+                    previous = summary.sequence;
+                    assert_eq!(summary.bids.len(), 5);
+                    assert_eq!(summary.asks.len(), 5);
+                    assert!(summary.bids.windows(2).all(|w| w[0].price > w[1].price));
+                    assert!(summary.asks.windows(2).all(|w| w[0].price < w[1].price));
+                    assert_eq!(
+                        summary.spread,
+                        summary.asks[0].price - summary.bids[0].price
+                    );
+                    if summary.sequence == last {
+                        break;
+                    }
+                }
+                previous
+            }));
+        }
+
+        let messages = (2..count + 2)
+            .map(|id| {
+                let update = serde_json::json!({
+                    "data": {
+                        "microtimestamp": id.to_string(),
+                        "bids": [[(29985 + id % 5).to_string(), (1 + id % 3).to_string()]],
+                        "asks": [[(30011 + id % 5).to_string(), (1 + id % 3).to_string()]],
+                    }
+                });
+                Message::Text(update.to_string())
+            })
+            .collect::<Vec<_>>();
+        let orderbook = OrderBook::new(Exchange::BITSTAMP, Symbol::BTCUSDT, 29000, 31000, 0, 8);
+        let snapshot = Snapshot {
+            last_update_id: 1,
+            bids: (29980..29990)
+                .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+                .collect(),
+            asks: (30011..30021)
+                .map(|p| [Decimal::new(p, 0), Decimal::ONE])
+                .collect(),
+        };
+
+        let started = Instant::now();
+        process_updates::<Snapshot, BookUpdate, _, _>(
+            Arc::new(RwLock::new(orderbook)),
+            Arc::new(ExchangeStats::default()),
+            5,
+            snapshot,
+            None,
+            futures::stream::iter(messages.into_iter().map(Ok)),
+            futures::sink::drain().sink_map_err(|never| match never {}),
+            tx_levels,
+        )
+        .await
+        .unwrap();
+        let elapsed = started.elapsed();
+
+        for client in clients {
+            let previous = timeout(Duration::from_secs(10), client)
+                .await
+                .expect("client did not receive the latest summary")
+                .unwrap();
+            assert_eq!(previous, last);
+        }
+        elapsed
+    }
+
+    #[tokio::test]
+    async fn it_keeps_symbols_started_on_demand_running_for_each_stream_of_them() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counted = started.clone();
+        let pipelines = Pipelines::new(vec![Symbol::ETHBTC], move |symbol| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            let (_, rx_levels) = mpsc::channel(1);
+            let (_, rx_closed) = mpsc::channel(1);
+            spawn_summary(
+                symbol,
+                vec![Exchange::BITSTAMP],
+                rx_levels,
+                rx_closed,
+                watch::channel(true).0,
+            )
+        });
+        let (tx_summary, _) = mpsc::channel(1);
+        let (_tx_shutdown, rx_shutdown) = watch::channel(false);
+        let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+            .with_symbols(vec![Symbol::BTCUSDT])
+            .with_pipelines(pipelines.clone());
+        let symbols = service
+            .get_symbols(tonic::Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(symbols.symbols, ["BTCUSDT", "ETHBTC"]);
+
+        let symbol = "ethbtc".to_string();
+        let _book = service
+            .watch_book(tonic::Request::new(WatchBookRequest {
+                symbol: symbol.clone(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let _ticker = service
+            .watch_ticker(tonic::Request::new(TickerRequest {
+                symbol: symbol.clone(),
+            }))
+            .await
+            .unwrap();
+        let _spread = service
+            .watch_spread(tonic::Request::new(WatchSpreadRequest {
+                symbol: symbol.clone(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let _divergence = service
+            .watch_divergence(tonic::Request::new(WatchDivergenceRequest {
+                symbol: symbol.clone(),
+                threshold_bps: 10.0,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let _statuses = service
+            .watch_exchange_status(tonic::Request::new(WatchExchangeStatusRequest {
+                symbol: symbol.clone(),
+            }))
+            .await
+            .unwrap();
+        // Each stream holds a lease on the one summary task started for them.
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert_eq!(pipelines.subscribers(Symbol::ETHBTC), 5);
+        let status = service
+            .get_ticker(tonic::Request::new(TickerRequest { symbol }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(pipelines.subscribers(Symbol::ETHBTC), 5);
+
+        let status = service
+            .watch_book(tonic::Request::new(WatchBookRequest {
+                symbol: "btcusd".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(status.message().contains("publishes summaries for"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn it_publishes_whole_summaries_without_readers_slowing_updates() {
+        let count = 20_000;
+        let alone = run_synthetic_feed(count, 0).await;
+        let watched = run_synthetic_feed(count, 64).await;
+        // Readers only ever copy the pointer to the latest summary, so the updates take
+        // about as long with them as without.
+        assert!(
+            watched < alone * 3 + Duration::from_millis(250),
+            "alone: {:?} watched: {:?}",
+            alone,
+            watched
+        );
+    }
+}
@@ -0,0 +1,719 @@
+//! Writes summaries to files for offline research, one a symbol an hour by the time in
+//! the summary, with the rows of the client's [csv format](crate::format::CSV_HEADER), or
+//! as Parquet with the server built with the `parquet` feature, a row a summary with each
+//! side's levels in a list column.
+//!
+//! A file is written as `{SYMBOL}-{YYYYMMDDTHH}-{index}.csv.partial`, or `.parquet.partial`,
+//! and renamed without the `.partial` once the hour is over or it grows past
+//! [FileSinkConfig::max_file_bytes], so jobs picking up `*.csv` never read one part way
+//! through. The oldest finished files are removed once they add up to more than
+//! [FileSinkConfig::max_total_bytes]. Files are written on the blocking thread pool, in
+//! batches of the summaries waiting in the sink's queue.
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::SummarySink;
+use crate::{
+    book_summary::Summary,
+    format::{Format, SummaryWriter},
+};
+
+const PARTIAL: &str = "partial";
+/// Summaries buffered before they're written without waiting for the queue to empty
+const BATCH: usize = 256;
+
+/// What the files are written as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    #[default]
+    Csv,
+    /// A row a summary, with the symbol, sequence, timestamp and spread and each side's
+    /// exchange, price and quantity of each level in a list column
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(Self::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => bail!("parquet files need the server built with the parquet feature"),
+            _ => bail!("unknown file format {}, expected csv or parquet", format),
+        }
+    }
+}
+
+/// Where and how much to write
+#[derive(Debug, Clone)]
+pub struct FileSinkConfig {
+    pub dir: PathBuf,
+    pub format: FileFormat,
+    /// Files are finished before the end of their hour once they're bigger than this
+    pub max_file_bytes: u64,
+    /// The oldest finished files are removed once the files in `dir` add up to more
+    /// than this
+    pub max_total_bytes: u64,
+}
+
+impl FileSinkConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            format: FileFormat::default(),
+            max_file_bytes: 64 * 1024 * 1024,
+            max_total_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Writes summaries to hourly files
+#[derive(Debug)]
+pub struct FileSink {
+    files: Arc<Mutex<Files>>,
+    pending: Vec<Summary>,
+}
+
+impl FileSink {
+    /// Creates `config.dir` if needed, counting the files already in it towards
+    /// [FileSinkConfig::max_total_bytes].
+    pub fn new(config: FileSinkConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("failed to create {}", config.dir.display()))?;
+        Ok(Self {
+            files: Arc::new(Mutex::new(Files::new(config)?)),
+            pending: Vec::new(),
+        })
+    }
+
+    async fn write_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let files = self.files.clone();
+        tokio::task::spawn_blocking(move || files.lock().unwrap().write(&pending)).await?
+    }
+}
+
+#[async_trait::async_trait]
+impl SummarySink for FileSink {
+    fn name(&self) -> String {
+        format!(
+            "files in {}",
+            self.files.lock().unwrap().config.dir.display()
+        )
+    }
+
+    async fn send(&mut self, summary: &Summary) -> Result<()> {
+        self.pending.push(summary.clone());
+        if self.pending.len() >= BATCH {
+            self.write_pending().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.write_pending().await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts the bytes written through it, in a count that can be read while a writer owns it
+#[derive(Debug)]
+struct Counted<W> {
+    inner: W,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the rows of a file in its format
+enum Writer {
+    Csv(SummaryWriter<Counted<BufWriter<File>>>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_file::ParquetWriter<Counted<BufWriter<File>>>),
+}
+
+impl Writer {
+    fn write(&mut self, summary: &Summary) -> Result<()> {
+        match self {
+            Self::Csv(writer) => Ok(writer.write(summary)?),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => {
+                writer.write(summary);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes out the summaries written since the last batch. Csv rows are written as
+    /// they come.
+    fn end_batch(&mut self) -> Result<()> {
+        match self {
+            Self::Csv(_) => Ok(()),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => writer.end_batch(),
+        }
+    }
+
+    /// Writes out the rest of the file and closes it.
+    fn close(self) -> Result<()> {
+        match self {
+            // Dropping the writer flushes and closes the file.
+            Self::Csv(_) => Ok(()),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => writer.close(),
+        }
+    }
+}
+
+struct OpenFile {
+    writer: Writer,
+    bytes: Arc<AtomicU64>,
+    path: PathBuf,
+    hour: u64,
+}
+
+impl std::fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl OpenFile {
+    fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// The open file of each symbol and the finished files, oldest first
+#[derive(Debug)]
+struct Files {
+    config: FileSinkConfig,
+    open: HashMap<String, OpenFile>,
+    finished: VecDeque<(PathBuf, u64)>,
+    /// Index of the next file of each symbol and hour
+    indices: HashMap<(String, u64), usize>,
+}
+
+impl Files {
+    fn new(config: FileSinkConfig) -> Result<Self> {
+        let mut finished = Vec::new();
+        for entry in fs::read_dir(&config.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == config.format.extension())
+            {
+                finished.push((path, entry.metadata()?.len()));
+            }
+        }
+        // Names are the symbol then the hour and index, so sorting on the part after the
+        // symbol puts the oldest first.
+        finished.sort_by_key(|(path, _)| hour_of(path));
+        Ok(Self {
+            config,
+            open: HashMap::new(),
+            finished: finished.into(),
+            indices: HashMap::new(),
+        })
+    }
+
+    fn write(&mut self, summaries: &[Summary]) -> Result<()> {
+        for summary in summaries {
+            let hour = summary.timestamp / 3600;
+            let finish = self.open.get(&summary.symbol).is_some_and(|file| {
+                file.hour != hour || file.bytes() >= self.config.max_file_bytes
+            });
+            if finish {
+                self.finish(&summary.symbol)?;
+            }
+            if !self.open.contains_key(&summary.symbol) {
+                let file = self.create(&summary.symbol, hour)?;
+                self.open.insert(summary.symbol.clone(), file);
+            }
+            let file = self.open.get_mut(&summary.symbol).unwrap();
+            file.writer
+                .write(summary)
+                .with_context(|| format!("failed to write {}", file.path.display()))?;
+        }
+        for file in self.open.values_mut() {
+            file.writer
+                .end_batch()
+                .with_context(|| format!("failed to write {}", file.path.display()))?;
+        }
+        self.remove_oldest();
+        Ok(())
+    }
+
+    fn create(&mut self, symbol: &str, hour: u64) -> Result<OpenFile> {
+        let extension = self.config.format.extension();
+        let name = |index: usize| {
+            let start = chrono::NaiveDateTime::from_timestamp_opt(hour as i64 * 3600, 0)
+                .unwrap_or_default();
+            format!(
+                "{}-{}-{:03}.{}",
+                symbol,
+                start.format("%Y%m%dT%H"),
+                index,
+                extension
+            )
+        };
+        // After any files of the same symbol and hour, from earlier in the hour or an
+        // earlier run, even once they've been removed.
+        let index = match self.indices.get(&(symbol.to_string(), hour)) {
+            Some(&index) => index,
+            None => (0..)
+                .find(|&index| !self.config.dir.join(name(index)).exists())
+                .unwrap(),
+        };
+        self.indices
+            .retain(|(other, other_hour), _| other != symbol || *other_hour == hour);
+        self.indices.insert((symbol.to_string(), hour), index + 1);
+        let path = self.config.dir.join(name(index));
+        let partial = path.with_extension(format!("{}.{}", extension, PARTIAL));
+        let file = File::create(&partial)
+            .with_context(|| format!("failed to create {}", partial.display()))?;
+        let bytes = Arc::new(AtomicU64::new(0));
+        let out = Counted {
+            inner: BufWriter::new(file),
+            bytes: bytes.clone(),
+        };
+        let writer = match self.config.format {
+            FileFormat::Csv => Writer::Csv(SummaryWriter::new(out, Format::Csv, 0)),
+            #[cfg(feature = "parquet")]
+            FileFormat::Parquet => Writer::Parquet(parquet_file::ParquetWriter::new(out)?),
+        };
+        Ok(OpenFile {
+            writer,
+            bytes,
+            path,
+            hour,
+        })
+    }
+
+    /// Renames the open file of `symbol` to its finished name.
+    fn finish(&mut self, symbol: &str) -> Result<()> {
+        let Some(file) = self.open.remove(symbol) else {
+            return Ok(());
+        };
+        let OpenFile {
+            writer,
+            bytes,
+            path,
+            ..
+        } = file;
+        let extension = self.config.format.extension();
+        let partial = path.with_extension(format!("{}.{}", extension, PARTIAL));
+        // The file is written out and closed before it's renamed.
+        writer
+            .close()
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        let bytes = bytes.load(Ordering::Relaxed);
+        fs::rename(&partial, &path)
+            .with_context(|| format!("failed to rename {}", partial.display()))?;
+        self.finished.push_back((path, bytes));
+        Ok(())
+    }
+
+    /// Removes the oldest finished files until the total is under the cap.
+    fn remove_oldest(&mut self) {
+        let open = self.open.values().map(OpenFile::bytes).sum::<u64>();
+        let mut total = open + self.finished.iter().map(|(_, bytes)| bytes).sum::<u64>();
+        while total > self.config.max_total_bytes {
+            let Some((path, bytes)) = self.finished.pop_front() else {
+                break;
+            };
+            if let Err(err) = fs::remove_file(&path) {
+                tracing::warn!("failed to remove {}: {}", path.display(), err);
+            }
+            total -= bytes;
+        }
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let symbols = self.open.keys().cloned().collect::<Vec<_>>();
+        for symbol in symbols {
+            if let Err(err) = self.finish(&symbol) {
+                tracing::error!("failed to finish {} file: {:#}", symbol, err);
+            }
+        }
+    }
+}
+
+/// The hour and index part of a file's name, for sorting files oldest first
+fn hour_of(path: &std::path::Path) -> String {
+    let name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    name.split_once('-')
+        .map_or("", |(_, hour)| hour)
+        .to_string()
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_file {
+    use anyhow::Result;
+    use arrow::{
+        array::{
+            ArrayBuilder, ArrayRef, BooleanArray, Float64Array, Float64Builder, ListBuilder,
+            StringArray, StringBuilder, StructBuilder, UInt64Array,
+        },
+        datatypes::{DataType, Field, Fields, Schema, SchemaRef},
+        record_batch::RecordBatch,
+    };
+    use parquet::arrow::ArrowWriter;
+    use std::{io::Write, sync::Arc};
+
+    use crate::book_summary::{Level, Summary};
+
+    /// The columns of a level in the `bids` and `asks` lists
+    fn level_fields() -> Fields {
+        Fields::from(vec![
+            Field::new("exchange", DataType::Utf8, false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("quantity", DataType::Float64, false),
+        ])
+    }
+
+    /// The columns of the parquet files, the same in every file. New columns only ever go
+    /// on the end.
+    pub fn schema() -> SchemaRef {
+        let levels = DataType::List(Arc::new(Field::new(
+            "item",
+            DataType::Struct(level_fields()),
+            true,
+        )));
+        Arc::new(Schema::new(vec![
+            Field::new("symbol", DataType::Utf8, false),
+            Field::new("sequence", DataType::UInt64, false),
+            Field::new("timestamp", DataType::UInt64, false),
+            Field::new("spread", DataType::Float64, false),
+            Field::new("degraded", DataType::Boolean, false),
+            Field::new("bids", levels.clone(), false),
+            Field::new("asks", levels, false),
+        ]))
+    }
+
+    /// Writes the summaries of a batch as a row group
+    pub struct ParquetWriter<W: Write + Send> {
+        writer: ArrowWriter<W>,
+        pending: Vec<Summary>,
+    }
+
+    impl<W: Write + Send> ParquetWriter<W> {
+        pub fn new(out: W) -> Result<Self> {
+            Ok(Self {
+                writer: ArrowWriter::try_new(out, schema(), None)?,
+                pending: Vec::new(),
+            })
+        }
+
+        pub fn write(&mut self, summary: &Summary) {
+            self.pending.push(summary.clone());
+        }
+
+        /// Writes the summaries written since the last batch as a row group, so the bytes
+        /// of the file count them.
+        pub fn end_batch(&mut self) -> Result<()> {
+            if self.pending.is_empty() {
+                return Ok(());
+            }
+            let batch = record_batch(&std::mem::take(&mut self.pending))?;
+            self.writer.write(&batch)?;
+            self.writer.flush()?;
+            Ok(())
+        }
+
+        /// Writes the file's footer, without which it can't be read.
+        pub fn close(mut self) -> Result<()> {
+            self.end_batch()?;
+            self.writer.close()?;
+            Ok(())
+        }
+    }
+
+    /// A list column of the levels of a side of each summary
+    fn levels<'a>(sides: impl Iterator<Item = &'a Vec<Level>>) -> ArrayRef {
+        let fields = level_fields();
+        let builders: Vec<Box<dyn ArrayBuilder>> = vec![
+            Box::new(StringBuilder::new()),
+            Box::new(Float64Builder::new()),
+            Box::new(Float64Builder::new()),
+        ];
+        let mut list = ListBuilder::new(StructBuilder::new(fields, builders));
+        for side in sides {
+            let values = list.values();
+            for level in side {
+                let exchange = values.field_builder::<StringBuilder>(0).unwrap();
+                exchange.append_value(&level.exchange);
+                let price = values.field_builder::<Float64Builder>(1).unwrap();
+                price.append_value(level.price);
+                let quantity = values.field_builder::<Float64Builder>(2).unwrap();
+                quantity.append_value(level.quantity);
+                values.append(true);
+            }
+            list.append(true);
+        }
+        Arc::new(list.finish())
+    }
+
+    fn record_batch(summaries: &[Summary]) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                summaries.iter().map(|summary| summary.symbol.as_str()),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                summaries.iter().map(|summary| summary.sequence),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                summaries.iter().map(|summary| summary.timestamp),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                summaries.iter().map(|summary| summary.spread),
+            )),
+            Arc::new(BooleanArray::from(
+                summaries
+                    .iter()
+                    .map(|summary| summary.degraded)
+                    .collect::<Vec<_>>(),
+            )),
+            levels(summaries.iter().map(|summary| &summary.bids)),
+            levels(summaries.iter().map(|summary| &summary.asks)),
+        ];
+        Ok(RecordBatch::try_new(schema(), columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::{Level, MarketType};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("obagg-files-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn names(dir: &PathBuf) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn summary(symbol: &str, sequence: u64, timestamp: u64) -> Summary {
+        let level = |price| Level {
+            exchange: "BINANCE".to_string(),
+            price,
+            quantity: 0.5,
+            updated_at: None,
+            market_type: MarketType::Spot.into(),
+        };
+        Summary {
+            symbol: symbol.to_string(),
+            sequence,
+            timestamp,
+            bids: vec![level(99.0), level(98.0)],
+            asks: vec![level(101.0)],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_writes_a_file_a_symbol_an_hour() {
+        let dir = dir("hourly");
+        let mut sink = FileSink::new(FileSinkConfig::new(&dir)).unwrap();
+        // 2023-07-04 23:59:59, then into the next hour
+        for (symbol, sequence, timestamp) in [
+            ("BTCUSDT", 1, 1688515199),
+            ("ETHBTC", 1, 1688515199),
+            ("BTCUSDT", 2, 1688515199),
+            ("BTCUSDT", 3, 1688515200),
+        ] {
+            sink.send(&summary(symbol, sequence, timestamp))
+                .await
+                .unwrap();
+        }
+        sink.flush().await.unwrap();
+        // The file of the hour that's over is finished, the others are still written.
+        assert_eq!(
+            names(&dir),
+            [
+                "BTCUSDT-20230704T23-000.csv",
+                "BTCUSDT-20230705T00-000.csv.partial",
+                "ETHBTC-20230704T23-000.csv.partial",
+            ]
+        );
+        drop(sink);
+        assert_eq!(
+            names(&dir),
+            [
+                "BTCUSDT-20230704T23-000.csv",
+                "BTCUSDT-20230705T00-000.csv",
+                "ETHBTC-20230704T23-000.csv",
+            ]
+        );
+
+        let text = fs::read_to_string(dir.join("BTCUSDT-20230704T23-000.csv")).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], crate::format::CSV_HEADER);
+        // Three levels for each of the two summaries in the hour
+        assert_eq!(lines.len(), 1 + 2 * 3);
+        assert_eq!(lines[1], "1,bid,BINANCE,99,0.5");
+        assert_eq!(lines[6], "2,ask,BINANCE,101,0.5");
+        let text = fs::read_to_string(dir.join("BTCUSDT-20230705T00-000.csv")).unwrap();
+        assert!(text.lines().skip(1).all(|line| line.starts_with("3,")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_rotates_big_files_and_removes_the_oldest() {
+        let dir = dir("rotate");
+        let config = FileSinkConfig {
+            max_file_bytes: 100,
+            max_total_bytes: 400,
+            ..FileSinkConfig::new(&dir)
+        };
+        let mut sink = FileSink::new(config).unwrap();
+        for sequence in 1..=20 {
+            sink.send(&summary("BTCUSDT", sequence, 1688515200))
+                .await
+                .unwrap();
+        }
+        sink.flush().await.unwrap();
+        drop(sink);
+
+        let names = names(&dir);
+        assert!(
+            names.iter().all(|name| name.ends_with(".csv")),
+            "{:?}",
+            names
+        );
+        let total = names
+            .iter()
+            .map(|name| fs::metadata(dir.join(name)).unwrap().len())
+            .sum::<u64>();
+        assert!(total <= 400 + 100, "{}", total);
+        // The oldest were removed, so the last summary is still there.
+        let last = fs::read_to_string(dir.join(names.last().unwrap())).unwrap();
+        assert!(last.lines().last().unwrap().starts_with("20,"));
+        assert!(!names.contains(&"BTCUSDT-20230705T00-000.csv".to_string()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn it_writes_a_parquet_row_a_summary() {
+        use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use arrow::array::{Array, ListArray, UInt64Array};
+
+        let dir = dir("parquet");
+        let config = FileSinkConfig {
+            format: FileFormat::Parquet,
+            ..FileSinkConfig::new(&dir)
+        };
+        let mut sink = FileSink::new(config).unwrap();
+        for sequence in 1..=3 {
+            sink.send(&summary("BTCUSDT", sequence, 1688515200))
+                .await
+                .unwrap();
+            // Each flush is a row group of its own.
+            sink.flush().await.unwrap();
+        }
+        drop(sink);
+        assert_eq!(names(&dir), ["BTCUSDT-20230705T00-000.parquet"]);
+
+        let file = File::open(dir.join("BTCUSDT-20230705T00-000.parquet")).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(reader.schema(), &parquet_file::schema());
+        assert_eq!(reader.metadata().num_row_groups(), 3);
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let batch = arrow::compute::concat_batches(&parquet_file::schema(), &batches).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        let sequences = batch
+            .column_by_name("sequence")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(sequences.values(), &[1, 2, 3]);
+        for (side, levels) in [("bids", 2), ("asks", 1)] {
+            let lists = batch
+                .column_by_name(side)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<ListArray>()
+                .unwrap();
+            let lengths = (0..3).map(|row| lists.value(row).len()).collect::<Vec<_>>();
+            assert_eq!(lengths, [levels; 3], "{}", side);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_parses_formats() {
+        assert_eq!("csv".parse::<FileFormat>().unwrap(), FileFormat::Csv);
+        #[cfg(feature = "parquet")]
+        assert_eq!(
+            "parquet".parse::<FileFormat>().unwrap(),
+            FileFormat::Parquet
+        );
+        #[cfg(not(feature = "parquet"))]
+        {
+            let err = "parquet".parse::<FileFormat>().unwrap_err();
+            assert!(err.to_string().contains("parquet feature"), "{}", err);
+        }
+        assert!("xlsx".parse::<FileFormat>().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Publishes summaries to a Kafka topic, keyed by symbol so each symbol's summaries land
+//! on one partition in the order they were emitted.
+//!
+//! The broker client sits behind [Producer], which [KafkaProducer] implements with rdkafka
+//! when the server is built with the `kafka` feature, and tests mock, so the sink can be
+//! tested without a broker.
+use anyhow::Result;
+
+use super::{Encoding, SummarySink};
+use crate::book_summary::Summary;
+
+#[cfg(feature = "kafka")]
+pub use self::rdkafka_producer::KafkaProducer;
+
+/// Sends records to the brokers
+#[async_trait::async_trait]
+pub trait Producer: Send + 'static {
+    /// Sends `payload` to `topic` with `key`, returning once the brokers have it.
+    async fn send(&mut self, topic: &str, key: &str, payload: Vec<u8>) -> Result<()>;
+
+    /// Connects to the brokers again after a failed send.
+    async fn reconnect(&mut self) -> Result<()>;
+}
+
+/// Sends each summary to `topic` with a [Producer]
+#[derive(Debug)]
+pub struct KafkaSink<P> {
+    producer: P,
+    topic: String,
+    encoding: Encoding,
+}
+
+impl<P: Producer> KafkaSink<P> {
+    pub fn new(producer: P, topic: impl Into<String>) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+            encoding: Encoding::default(),
+        }
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: Producer> SummarySink for KafkaSink<P> {
+    fn name(&self) -> String {
+        format!("kafka topic {}", self.topic)
+    }
+
+    async fn send(&mut self, summary: &Summary) -> Result<()> {
+        let payload = self.encoding.encode(summary)?;
+        self.producer
+            .send(&self.topic, &summary.symbol, payload)
+            .await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.producer.reconnect().await
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod rdkafka_producer {
+    use anyhow::{Context, Result};
+    use rdkafka::{
+        producer::{FutureProducer, FutureRecord},
+        ClientConfig,
+    };
+    use std::time::Duration;
+
+    use super::Producer;
+
+    /// How long a record waits in the producer's queue for room before it fails
+    const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A [Producer] sending to Kafka brokers with rdkafka, waiting for each record to be
+    /// acknowledged by the brokers.
+    pub struct KafkaProducer {
+        brokers: String,
+        producer: FutureProducer,
+    }
+
+    impl std::fmt::Debug for KafkaProducer {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("KafkaProducer")
+                .field("brokers", &self.brokers)
+                .finish()
+        }
+    }
+
+    impl KafkaProducer {
+        /// Sends to `brokers`, a comma separated list of `host:port`. The brokers aren't
+        /// reached until the first send.
+        pub fn new(brokers: &str) -> Result<Self> {
+            Ok(Self {
+                brokers: brokers.to_string(),
+                producer: connect(brokers)?,
+            })
+        }
+    }
+
+    fn connect(brokers: &str) -> Result<FutureProducer> {
+        ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "10000")
+            .set("acks", "all")
+            .create()
+            .with_context(|| format!("failed to create a kafka producer for {}", brokers))
+    }
+
+    #[async_trait::async_trait]
+    impl Producer for KafkaProducer {
+        async fn send(&mut self, topic: &str, key: &str, payload: Vec<u8>) -> Result<()> {
+            let record = FutureRecord::to(topic).key(key).payload(&payload);
+            self.producer
+                .send(record, QUEUE_TIMEOUT)
+                .await
+                .map(|_| ())
+                .map_err(|(err, _)| err)
+                .with_context(|| format!("failed to send to kafka topic {}", topic))
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            self.producer = connect(&self.brokers)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sinks::{
+            tests::{settle, summary},
+            SinkConfig, SinkQueue,
+        },
+        Symbol,
+    };
+    use prost::Message;
+    use std::sync::{Arc, Mutex};
+
+    /// Topic, key and payload of a record sent
+    type Sent = (String, String, Vec<u8>);
+
+    #[derive(Clone, Default)]
+    struct MockProducer {
+        sent: Arc<Mutex<Vec<Sent>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Producer for MockProducer {
+        async fn send(&mut self, topic: &str, key: &str, payload: Vec<u8>) -> Result<()> {
+            let record = (topic.to_string(), key.to_string(), payload);
+            self.sent.lock().unwrap().push(record);
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_publishes_summaries_keyed_by_symbol() {
+        let producer = MockProducer::default();
+        let sink = KafkaSink::new(producer.clone(), "summaries").with_encoding(Encoding::Proto);
+        let queue = SinkQueue::spawn(sink, SinkConfig::default());
+        queue.publish(summary(Symbol::BTCUSDT, 1));
+        queue.publish(summary(Symbol::ETHBTC, 1));
+        queue.publish(summary(Symbol::BTCUSDT, 2));
+        settle().await;
+
+        let sent = producer.sent.lock().unwrap();
+        let records = sent
+            .iter()
+            .map(|(topic, key, payload)| {
+                assert_eq!(topic, "summaries");
+                let sequence = Summary::decode(payload.as_slice()).unwrap().sequence;
+                (key.as_str(), sequence)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(records, [("BTCUSDT", 1), ("ETHBTC", 1), ("BTCUSDT", 2)]);
+        assert_eq!(queue.published(), 3);
+    }
+}
@@ -0,0 +1,544 @@
+//! Publishes the summaries the server emits to systems outside it, like a data platform
+//! keeping the book's history. Publishing is decoupled from serving: summaries are handed
+//! over through a bounded queue without waiting, and dropped and counted when the sink
+//! can't keep up, so a slow or unreachable sink never holds up the order books or clients.
+//!
+//! Each destination implements [SummarySink], and [Fanout] publishes each summary into the
+//! queue of every one through a [SinkQueue].
+use anyhow::{bail, Result};
+use prost::Message;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    book_summary::Summary,
+    service::{clear_level_ages, SummaryReceiver, SummarySubscriber},
+    Exchange, Symbol,
+};
+
+pub mod files;
+pub mod history;
+pub mod kafka;
+pub mod nats;
+pub mod redis;
+
+/// A destination for summaries, sent to one at a time from its [SinkQueue]'s task
+#[async_trait::async_trait]
+pub trait SummarySink: Send + 'static {
+    /// What the sink is called in logs
+    fn name(&self) -> String;
+
+    /// Sends `summary`, returning once the destination has it.
+    async fn send(&mut self, summary: &Summary) -> Result<()>;
+
+    /// Sends a change in an exchange's connection. Sinks only interested in summaries
+    /// leave this out.
+    async fn send_status(&mut self, _status: &ExchangeStatus) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes out anything the sink has buffered, called each time its queue empties.
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Connects again after a failed send.
+    async fn reconnect(&mut self) -> Result<()>;
+}
+
+/// An exchange's connection for a symbol going down or coming back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExchangeStatus {
+    pub exchange: String,
+    pub symbol: String,
+    pub connected: bool,
+    /// Why the exchange is unavailable, empty when it isn't or when unknown
+    pub reason: String,
+}
+
+/// How summaries are encoded for a sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// A json object with the proto's field names
+    #[default]
+    Json,
+    /// The protobuf encoding of [Summary]
+    Proto,
+}
+
+impl Encoding {
+    pub fn encode(&self, summary: &Summary) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(summary)?,
+            Self::Proto => summary.encode_to_vec(),
+        })
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        match encoding {
+            "json" => Ok(Self::Json),
+            "proto" => Ok(Self::Proto),
+            _ => bail!("unknown encoding {}, expected json or proto", encoding),
+        }
+    }
+}
+
+/// What each sink is sent and how it's queued
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    /// Symbols to publish, every symbol when empty
+    pub symbols: Vec<Symbol>,
+    /// Summaries waiting to be sent before more are dropped
+    pub capacity: usize,
+    /// Delay before the first attempt to reconnect, doubling up to `max_backoff`
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self {
+            symbols: Vec::new(),
+            capacity: 1_000,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Item {
+    Summary(Arc<Summary>),
+    Status(ExchangeStatus),
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    published: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Hands summaries to a [SummarySink] on its own task. Publishing never waits, summaries
+/// that arrive while the sink is behind by [SinkConfig::capacity] are dropped and counted,
+/// as are those it fails to deliver. Clones publish to the same sink.
+#[derive(Debug, Clone)]
+pub struct SinkQueue {
+    name: Arc<str>,
+    tx: mpsc::Sender<Item>,
+    symbols: Arc<Vec<String>>,
+    counts: Arc<Counts>,
+}
+
+impl SinkQueue {
+    /// Starts the task sending to `sink`, which runs until every clone of the queue is
+    /// dropped.
+    pub fn spawn<S: SummarySink>(mut sink: S, config: SinkConfig) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Item>(config.capacity);
+        let counts = Arc::new(Counts::default());
+        let name: Arc<str> = sink.name().into();
+        let queue = Self {
+            name: name.clone(),
+            tx,
+            symbols: Arc::new(config.symbols.iter().map(|s| s.to_string()).collect()),
+            counts: counts.clone(),
+        };
+        tokio::spawn(async move {
+            while let Some(received) = rx.recv().await {
+                // Everything waiting is sent before flushing.
+                let mut next = Some(received);
+                while let Some(item) = next {
+                    let sent = match &item {
+                        Item::Summary(summary) => sink.send(summary).await,
+                        Item::Status(status) => sink.send_status(status).await,
+                    };
+                    match sent {
+                        Ok(()) => {
+                            counts.published.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(err) => failed(&mut sink, &name, &config, &counts, err).await,
+                    }
+                    next = rx.try_recv().ok();
+                }
+                if let Err(err) = sink.flush().await {
+                    failed(&mut sink, &name, &config, &counts, err).await;
+                }
+            }
+        });
+        queue
+    }
+
+    /// Queues `summary` to be sent, returning false if it was dropped because the queue
+    /// is full. Summaries of symbols not configured are left out.
+    pub fn publish(&self, summary: Arc<Summary>) -> bool {
+        self.queue(&summary.symbol.clone(), Item::Summary(summary))
+    }
+
+    /// Queues a change in an exchange's connection, like [publish](Self::publish).
+    pub fn publish_status(&self, status: ExchangeStatus) -> bool {
+        self.queue(&status.symbol.clone(), Item::Status(status))
+    }
+
+    fn queue(&self, symbol: &str, item: Item) -> bool {
+        if !self.symbols.is_empty() && !self.symbols.iter().any(|s| s == symbol) {
+            return true;
+        }
+        if self.tx.try_send(item).is_err() {
+            let dropped = self.counts.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                tracing::warn!("{} is behind, {} dropped", self.name, dropped);
+            }
+            return false;
+        }
+        true
+    }
+
+    /// Summaries and status changes the sink has received
+    pub fn published(&self) -> u64 {
+        self.counts.published.load(Ordering::Relaxed)
+    }
+
+    /// Summaries and status changes dropped because the sink was behind
+    pub fn dropped(&self) -> u64 {
+        self.counts.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Summaries and status changes that failed to be delivered
+    pub fn failed(&self) -> u64 {
+        self.counts.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts and logs a failed send, then tries to reconnect until it works, doubling the
+/// delay between attempts.
+async fn failed<S: SummarySink>(
+    sink: &mut S,
+    name: &str,
+    config: &SinkConfig,
+    counts: &Counts,
+    err: anyhow::Error,
+) {
+    let failed = counts.failed.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::error!(
+        "failed to publish to {}, {} failed so far: {:#}",
+        name,
+        failed,
+        err
+    );
+    let mut delay = config.initial_backoff;
+    loop {
+        tokio::time::sleep(delay).await;
+        match sink.reconnect().await {
+            Ok(()) => {
+                tracing::info!("{} reconnected", name);
+                return;
+            }
+            Err(err) => {
+                tracing::warn!("failed to reconnect {}: {:#}", name, err);
+                delay = (delay * 2).min(config.max_backoff);
+            }
+        }
+    }
+}
+
+/// Publishes the summaries of a summary task, and the changes in its exchanges'
+/// connections, to every sink.
+#[derive(Debug, Clone, Default)]
+pub struct Fanout {
+    queues: Vec<SinkQueue>,
+}
+
+impl Fanout {
+    pub fn new(queues: Vec<SinkQueue>) -> Self {
+        Self { queues }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Summaries and status changes dropped by all the sinks
+    pub fn dropped(&self) -> u64 {
+        self.queues.iter().map(|queue| queue.dropped()).sum()
+    }
+
+    pub fn publish(&self, summary: Arc<Summary>) {
+        for queue in self.queues.iter() {
+            queue.publish(summary.clone());
+        }
+    }
+
+    pub fn publish_status(&self, status: ExchangeStatus) {
+        for queue in self.queues.iter() {
+            queue.publish_status(status.clone());
+        }
+    }
+
+    /// Spawns a task publishing each summary emitted on `rx_summary`, aggregated from
+    /// `exchanges`, until the summary task ends. Summaries the task skips because it fell
+    /// behind count as dropped. An exchange is reported disconnected when it's listed as
+    /// unavailable, or when the summaries end in an error, and connected once it's no
+    /// longer listed.
+    pub fn watch(&self, mut rx_summary: SummaryReceiver, exchanges: &[Exchange]) -> JoinHandle<()> {
+        let fanout = self.clone();
+        let exchanges = exchanges.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        tokio::spawn(async move {
+            let mut last = None;
+            let mut symbol = String::new();
+            let mut down = HashSet::<String>::new();
+            loop {
+                let latest = rx_summary.borrow_and_update().clone();
+                match latest {
+                    // The summary sent before any levels arrive has sequence 0.
+                    Ok(summary) if summary.sequence == 0 => {}
+                    Ok(summary) => {
+                        if let Some(last) = last {
+                            let skipped = summary.sequence.saturating_sub(last + 1);
+                            for queue in fanout.queues.iter() {
+                                queue.counts.dropped.fetch_add(skipped, Ordering::Relaxed);
+                            }
+                        }
+                        last = Some(summary.sequence);
+                        symbol.clone_from(&summary.symbol);
+                        let unavailable = summary
+                            .unavailable_exchanges
+                            .iter()
+                            .cloned()
+                            .collect::<HashSet<_>>();
+                        // When each level was last updated is only sent to GetSummary.
+                        let mut summary = Summary::clone(&summary);
+                        clear_level_ages(&mut summary);
+                        fanout.publish(Arc::new(summary));
+                        for exchange in down.difference(&unavailable) {
+                            fanout.publish_status(ExchangeStatus {
+                                exchange: exchange.clone(),
+                                symbol: symbol.clone(),
+                                connected: true,
+                                reason: String::new(),
+                            });
+                        }
+                        for exchange in unavailable.difference(&down) {
+                            fanout.publish_status(ExchangeStatus {
+                                exchange: exchange.clone(),
+                                symbol: symbol.clone(),
+                                connected: false,
+                                reason: String::new(),
+                            });
+                        }
+                        down = unavailable;
+                    }
+                    Err(status) => {
+                        for exchange in exchanges.iter() {
+                            if down.insert(exchange.clone()) {
+                                fanout.publish_status(ExchangeStatus {
+                                    exchange: exchange.clone(),
+                                    symbol: symbol.clone(),
+                                    connected: false,
+                                    reason: status.message().to_string(),
+                                });
+                            }
+                        }
+                    }
+                }
+                if rx_summary.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// A receiver of the summaries of the summary task behind `tx_summary`, the same way
+/// client streams get theirs.
+pub async fn subscribe(tx_summary: &SummarySubscriber) -> Result<SummaryReceiver> {
+    let (tx, rx) = oneshot::channel();
+    if tx_summary.send(tx).await.is_err() {
+        bail!("summary task is not running");
+    }
+    Ok(rx.await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::{watch, Semaphore};
+    use tonic::Status;
+
+    /// Records the sequences and statuses it's sent, failing the sends and reconnects
+    /// it's told to
+    #[derive(Clone, Default)]
+    struct MockSink {
+        sent: Arc<Mutex<Vec<u64>>>,
+        statuses: Arc<Mutex<Vec<ExchangeStatus>>>,
+        failing_sends: Arc<AtomicU64>,
+        failing_reconnects: Arc<AtomicU64>,
+        reconnects: Arc<AtomicU64>,
+        /// Each send waits for a permit when set, to hold the sink up
+        permits: Option<Arc<Semaphore>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SummarySink for MockSink {
+        fn name(&self) -> String {
+            "mock sink".to_string()
+        }
+
+        async fn send(&mut self, summary: &Summary) -> Result<()> {
+            if let Some(permits) = &self.permits {
+                permits.acquire().await?.forget();
+            }
+            if take(&self.failing_sends) {
+                bail!("unreachable");
+            }
+            self.sent.lock().unwrap().push(summary.sequence);
+            Ok(())
+        }
+
+        async fn send_status(&mut self, status: &ExchangeStatus) -> Result<()> {
+            self.statuses.lock().unwrap().push(status.clone());
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+            if take(&self.failing_reconnects) {
+                bail!("unreachable");
+            }
+            Ok(())
+        }
+    }
+
+    /// Takes one from `count`, returning false if it was already 0.
+    fn take(count: &AtomicU64) -> bool {
+        count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    pub(super) fn summary(symbol: Symbol, sequence: u64) -> Arc<Summary> {
+        Arc::new(Summary {
+            symbol: symbol.to_string(),
+            sequence,
+            spread: 1.5,
+            ..Default::default()
+        })
+    }
+
+    pub(super) async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn it_drops_summaries_instead_of_waiting_on_a_slow_sink() {
+        let permits = Arc::new(Semaphore::new(0));
+        let sink = MockSink {
+            permits: Some(permits.clone()),
+            ..Default::default()
+        };
+        let queue = SinkQueue::spawn(
+            sink.clone(),
+            SinkConfig {
+                capacity: 2,
+                ..Default::default()
+            },
+        );
+        // One summary is taken by the stuck sink and two wait in the queue.
+        settle().await;
+        let queued = (1..=10)
+            .filter(|&sequence| queue.publish(summary(Symbol::BTCUSDT, sequence)))
+            .count();
+        assert!(queued <= 3, "{}", queued);
+        assert_eq!(queue.dropped(), 10 - queued as u64);
+
+        permits.add_permits(10);
+        settle().await;
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), queued);
+        assert_eq!(sent[0], 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_counts_failed_deliveries_and_reconnects() {
+        let sink = MockSink {
+            failing_sends: Arc::new(AtomicU64::new(1)),
+            failing_reconnects: Arc::new(AtomicU64::new(2)),
+            ..Default::default()
+        };
+        let queue = SinkQueue::spawn(sink.clone(), SinkConfig::default());
+        for sequence in 1..=3 {
+            queue.publish(summary(Symbol::BTCUSDT, sequence));
+        }
+        // Waits out the 1s and 2s delays before the reconnect that works.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(sink.reconnects.load(Ordering::Relaxed), 3);
+        assert_eq!((queue.published(), queue.failed()), (2, 1));
+        assert_eq!(*sink.sent.lock().unwrap(), [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn it_fans_out_summaries_and_exchange_status_changes() {
+        let (first, second) = (MockSink::default(), MockSink::default());
+        let btcusdt = SinkConfig {
+            symbols: vec![Symbol::BTCUSDT],
+            ..Default::default()
+        };
+        let fanout = Fanout::new(vec![
+            SinkQueue::spawn(first.clone(), btcusdt),
+            SinkQueue::spawn(second.clone(), SinkConfig::default()),
+        ]);
+        let (tx_summary, rx_summary) = watch::channel(Ok(Arc::new(Summary::default())));
+        let watching = fanout.watch(rx_summary, &[Exchange::BINANCE, Exchange::BITSTAMP]);
+        let degraded = |sequence| {
+            let mut summary = Summary::clone(&summary(Symbol::BTCUSDT, sequence));
+            summary.unavailable_exchanges = vec!["BITSTAMP".to_string()];
+            summary.degraded = true;
+            Arc::new(summary)
+        };
+        for summary in [
+            summary(Symbol::BTCUSDT, 1),
+            degraded(2),
+            summary(Symbol::BTCUSDT, 3),
+        ] {
+            let _ = tx_summary.send_replace(Ok(summary));
+            settle().await;
+        }
+        let _ = tx_summary.send_replace(Err(Status::unavailable("all exchanges closed")));
+        settle().await;
+        fanout.publish(summary(Symbol::ETHBTC, 1));
+        drop(tx_summary);
+        watching.await.unwrap();
+        settle().await;
+
+        assert_eq!(*first.sent.lock().unwrap(), [1, 2, 3]);
+        assert_eq!(*second.sent.lock().unwrap(), [1, 2, 3, 1]);
+        let statuses = first.statuses.lock().unwrap();
+        let changes = statuses
+            .iter()
+            .map(|status| (status.exchange.as_str(), status.connected))
+            .collect::<Vec<_>>();
+        assert_eq!(&changes[..2], [("BITSTAMP", false), ("BITSTAMP", true)]);
+        let mut closed = changes[2..].to_vec();
+        closed.sort();
+        assert_eq!(closed, [("BINANCE", false), ("BITSTAMP", false)]);
+        assert_eq!(statuses[3].reason, "all exchanges closed");
+        assert_eq!(statuses[3].symbol, "BTCUSDT");
+    }
+}
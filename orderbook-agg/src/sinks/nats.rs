@@ -0,0 +1,226 @@
+//! Publishes summaries to NATS on `{prefix}.summary.{symbol}`, and changes in the
+//! exchanges' connections as json on `{prefix}.status.{exchange}`.
+//!
+//! The connection sits behind [NatsClient], which [NatsConnection] implements with
+//! async-nats when the server is built with the `nats` feature, with its credentials, TLS
+//! and whether to publish through JetStream for persistence, and tests mock.
+use anyhow::Result;
+
+use super::{Encoding, ExchangeStatus, SummarySink};
+use crate::book_summary::Summary;
+
+#[cfg(feature = "nats")]
+pub use self::connection::{NatsConfig, NatsConnection};
+
+/// Publishes messages to subjects
+#[async_trait::async_trait]
+pub trait NatsClient: Send + 'static {
+    /// Publishes `payload` to `subject`, returning once the server has it.
+    async fn publish(&mut self, subject: String, payload: Vec<u8>) -> Result<()>;
+
+    /// Connects to the server again after a failed publish.
+    async fn reconnect(&mut self) -> Result<()>;
+}
+
+/// Publishes each summary and status change with a [NatsClient]
+#[derive(Debug)]
+pub struct NatsSink<C> {
+    client: C,
+    prefix: String,
+    encoding: Encoding,
+}
+
+impl<C: NatsClient> NatsSink<C> {
+    /// Publishes to subjects under `orderbook`.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            prefix: "orderbook".to_string(),
+            encoding: Encoding::default(),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: NatsClient> SummarySink for NatsSink<C> {
+    fn name(&self) -> String {
+        format!("nats subjects {}.*", self.prefix)
+    }
+
+    async fn send(&mut self, summary: &Summary) -> Result<()> {
+        let subject = format!("{}.summary.{}", self.prefix, summary.symbol);
+        let payload = self.encoding.encode(summary)?;
+        self.client.publish(subject, payload).await
+    }
+
+    async fn send_status(&mut self, status: &ExchangeStatus) -> Result<()> {
+        let subject = format!("{}.status.{}", self.prefix, status.exchange);
+        self.client
+            .publish(subject, serde_json::to_vec(status)?)
+            .await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.client.reconnect().await
+    }
+}
+
+#[cfg(feature = "nats")]
+mod connection {
+    use anyhow::{Context, Result};
+    use async_nats::{jetstream, Client, ConnectOptions};
+    use std::path::PathBuf;
+
+    use super::NatsClient;
+
+    /// How to reach the NATS server and publish to it
+    #[derive(Debug, Clone, Default)]
+    pub struct NatsConfig {
+        /// The server's url, e.g. `nats://localhost:4222`
+        pub url: String,
+        /// A `.creds` file with the user's JWT and nkey seed
+        pub credentials: Option<PathBuf>,
+        /// Connects over TLS, even when the server doesn't ask for it
+        pub tls: bool,
+        /// The CA the server's certificate is checked against, the system's when not set
+        pub tls_ca: Option<PathBuf>,
+        /// Publishes through JetStream, waiting for a stream to acknowledge each message,
+        /// which fails for subjects no stream captures
+        pub jetstream: bool,
+    }
+
+    /// A [NatsClient] connected with async-nats
+    #[derive(Debug)]
+    pub struct NatsConnection {
+        config: NatsConfig,
+        client: Client,
+        jetstream: Option<jetstream::Context>,
+    }
+
+    impl NatsConnection {
+        pub async fn connect(config: NatsConfig) -> Result<Self> {
+            let client = connect(&config).await?;
+            Ok(Self {
+                jetstream: config.jetstream.then(|| jetstream::new(client.clone())),
+                client,
+                config,
+            })
+        }
+    }
+
+    async fn connect(config: &NatsConfig) -> Result<Client> {
+        let mut options = ConnectOptions::new();
+        if let Some(credentials) = &config.credentials {
+            options = options
+                .credentials_file(credentials)
+                .await
+                .with_context(|| format!("failed to read {}", credentials.display()))?;
+        }
+        if config.tls {
+            options = options.require_tls(true);
+        }
+        if let Some(ca) = &config.tls_ca {
+            options = options.add_root_certificates(ca.clone());
+        }
+        options
+            .connect(config.url.as_str())
+            .await
+            .with_context(|| format!("failed to connect to nats at {}", config.url))
+    }
+
+    #[async_trait::async_trait]
+    impl NatsClient for NatsConnection {
+        async fn publish(&mut self, subject: String, payload: Vec<u8>) -> Result<()> {
+            let failed = || format!("failed to publish to {}", subject);
+            match &self.jetstream {
+                Some(jetstream) => {
+                    let ack = jetstream
+                        .publish(subject.clone(), payload.into())
+                        .await
+                        .with_context(failed)?;
+                    ack.await.with_context(failed)?;
+                }
+                None => {
+                    self.client
+                        .publish(subject.clone(), payload.into())
+                        .await
+                        .with_context(failed)?;
+                    self.client.flush().await.with_context(failed)?;
+                }
+            }
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            *self = Self::connect(self.config.clone()).await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sinks::{
+            tests::{settle, summary},
+            SinkConfig, SinkQueue,
+        },
+        Symbol,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Subject and payload of a message published
+    type Published = (String, Vec<u8>);
+
+    #[derive(Clone, Default)]
+    struct MockClient {
+        published: Arc<Mutex<Vec<Published>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl NatsClient for MockClient {
+        async fn publish(&mut self, subject: String, payload: Vec<u8>) -> Result<()> {
+            self.published.lock().unwrap().push((subject, payload));
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_publishes_summaries_and_statuses_to_their_subjects() {
+        let client = MockClient::default();
+        let sink = NatsSink::new(client.clone()).with_prefix("md");
+        let queue = SinkQueue::spawn(sink, SinkConfig::default());
+        queue.publish(summary(Symbol::BTCUSDT, 1));
+        queue.publish_status(ExchangeStatus {
+            exchange: "BITSTAMP".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            connected: false,
+            reason: "closed".to_string(),
+        });
+        settle().await;
+
+        let published = client.published.lock().unwrap();
+        assert_eq!(published[0].0, "md.summary.BTCUSDT");
+        let summary = serde_json::from_slice::<Summary>(&published[0].1).unwrap();
+        assert_eq!(summary.sequence, 1);
+        assert_eq!(published[1].0, "md.status.BITSTAMP");
+        let status = serde_json::from_slice::<serde_json::Value>(&published[1].1).unwrap();
+        assert_eq!(status["connected"], false);
+        assert_eq!(status["reason"], "closed");
+    }
+}
@@ -0,0 +1,235 @@
+//! Pushes summaries to Redis for consumers that don't speak gRPC. Each summary is
+//! published as json on `{prefix}:{symbol}` and set on `{prefix}:latest:{symbol}` with a
+//! ttl, so the latest book is one GET away.
+//!
+//! Summaries are only emitted when a book changes, so the ttl should be longer than the
+//! quietest market goes without an update, the key then only expiring once the summaries
+//! have stopped. The connection sits behind [RedisConnection], which [RedisClient]
+//! implements with the redis crate when the server is built with the `redis` feature, and
+//! tests mock.
+use anyhow::Result;
+use std::time::Duration;
+
+use super::SummarySink;
+use crate::book_summary::Summary;
+
+#[cfg(feature = "redis")]
+pub use self::client::RedisClient;
+
+/// A command sent to Redis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `PUBLISH channel payload`
+    Publish { channel: String, payload: Vec<u8> },
+    /// `SET key value PX ttl`
+    Set {
+        key: String,
+        value: Vec<u8>,
+        ttl: Duration,
+    },
+}
+
+/// Sends commands to Redis
+#[async_trait::async_trait]
+pub trait RedisConnection: Send + 'static {
+    /// Sends `commands` together in one pipeline, returning once they've all been run.
+    async fn pipeline(&mut self, commands: Vec<Command>) -> Result<()>;
+
+    /// Connects again after a failed pipeline.
+    async fn reconnect(&mut self) -> Result<()>;
+}
+
+/// Publishes and caches each summary with a [RedisConnection]
+#[derive(Debug)]
+pub struct RedisSink<C> {
+    connection: C,
+    prefix: String,
+    ttl: Duration,
+}
+
+impl<C: RedisConnection> RedisSink<C> {
+    /// Uses keys under `orderbook` with a ttl of a minute.
+    pub fn new(connection: C) -> Self {
+        Self {
+            connection,
+            prefix: "orderbook".to_string(),
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets how long the latest summary is kept after the last one for its symbol.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn commands(&self, summary: &Summary) -> Result<Vec<Command>> {
+        let payload = serde_json::to_vec(summary)?;
+        Ok(vec![
+            Command::Publish {
+                channel: format!("{}:{}", self.prefix, summary.symbol),
+                payload: payload.clone(),
+            },
+            Command::Set {
+                key: format!("{}:latest:{}", self.prefix, summary.symbol),
+                value: payload,
+                ttl: self.ttl,
+            },
+        ])
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: RedisConnection> SummarySink for RedisSink<C> {
+    fn name(&self) -> String {
+        format!("redis keys {}:*", self.prefix)
+    }
+
+    async fn send(&mut self, summary: &Summary) -> Result<()> {
+        let commands = self.commands(summary)?;
+        self.connection.pipeline(commands).await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.connection.reconnect().await
+    }
+}
+
+#[cfg(feature = "redis")]
+mod client {
+    use ::redis::aio::ConnectionManager;
+    use anyhow::{Context, Result};
+
+    use super::{Command, RedisConnection};
+    use crate::config::redact_url;
+
+    /// A [RedisConnection] made with the redis crate, which reconnects on its own too
+    pub struct RedisClient {
+        url: String,
+        connection: ConnectionManager,
+    }
+
+    impl std::fmt::Debug for RedisClient {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("RedisClient")
+                .field("url", &redact_url(&self.url))
+                .finish()
+        }
+    }
+
+    impl RedisClient {
+        /// Connects to `url`, e.g. `redis://:password@localhost:6379/0`
+        pub async fn connect(url: &str) -> Result<Self> {
+            Ok(Self {
+                url: url.to_string(),
+                connection: connect(url).await?,
+            })
+        }
+    }
+
+    async fn connect(url: &str) -> Result<ConnectionManager> {
+        let client = ::redis::Client::open(url)
+            .with_context(|| format!("invalid redis url {}", redact_url(url)))?;
+        ConnectionManager::new(client)
+            .await
+            .with_context(|| format!("failed to connect to redis at {}", redact_url(url)))
+    }
+
+    #[async_trait::async_trait]
+    impl RedisConnection for RedisClient {
+        async fn pipeline(&mut self, commands: Vec<Command>) -> Result<()> {
+            let mut pipe = ::redis::pipe();
+            for command in commands {
+                match command {
+                    Command::Publish { channel, payload } => {
+                        pipe.cmd("PUBLISH").arg(channel).arg(payload).ignore();
+                    }
+                    Command::Set { key, value, ttl } => {
+                        let ttl = ttl.as_millis() as u64;
+                        pipe.cmd("SET")
+                            .arg(key)
+                            .arg(value)
+                            .arg("PX")
+                            .arg(ttl)
+                            .ignore();
+                    }
+                }
+            }
+            pipe.query_async::<_, ()>(&mut self.connection)
+                .await
+                .context("failed to send the redis pipeline")
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            self.connection = connect(&self.url).await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sinks::{
+            tests::{settle, summary},
+            SinkConfig, SinkQueue,
+        },
+        Symbol,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct MockConnection {
+        pipelines: Arc<Mutex<Vec<Vec<Command>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RedisConnection for MockConnection {
+        async fn pipeline(&mut self, commands: Vec<Command>) -> Result<()> {
+            self.pipelines.lock().unwrap().push(commands);
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_publishes_and_sets_each_summary_in_one_pipeline() {
+        let connection = MockConnection::default();
+        let sink = RedisSink::new(connection.clone()).with_ttl(Duration::from_secs(30));
+        let queue = SinkQueue::spawn(sink, SinkConfig::default());
+        queue.publish(summary(Symbol::BTCUSDT, 1));
+        queue.publish(summary(Symbol::ETHBTC, 4));
+        settle().await;
+
+        let pipelines = connection.pipelines.lock().unwrap();
+        assert_eq!(pipelines.len(), 2);
+        let payload = serde_json::to_vec(&*summary(Symbol::BTCUSDT, 1)).unwrap();
+        assert_eq!(
+            pipelines[0],
+            [
+                Command::Publish {
+                    channel: "orderbook:BTCUSDT".to_string(),
+                    payload: payload.clone(),
+                },
+                Command::Set {
+                    key: "orderbook:latest:BTCUSDT".to_string(),
+                    value: payload,
+                    ttl: Duration::from_secs(30),
+                },
+            ]
+        );
+        let Command::Set { key, .. } = &pipelines[1][1] else {
+            panic!("expected a set, got {:?}", pipelines[1][1]);
+        };
+        assert_eq!(key, "orderbook:latest:ETHBTC");
+    }
+}
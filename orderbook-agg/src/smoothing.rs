@@ -0,0 +1,196 @@
+//! Exponential smoothing of the mid, spread and imbalance of the summaries a WatchSummary
+//! stream sends, for requests with a `smoothing_half_life_ms`.
+//!
+//! Each stream keeps its own [Ema], as the half-life is the client's. The weight given to a
+//! summary depends on how long it's been since the last one was sent, so summaries
+//! coalesced or held back by `min_interval_ms` don't skew the average. Summary timestamps
+//! are only to the second, so the stream's own clock is used. Only the derived metrics are
+//! smoothed, the levels always go out as they are.
+use crate::book_summary::{Summary, SummaryMetrics};
+
+/// The longest half-life a client can ask for
+pub const MAX_HALF_LIFE_MS: u32 = 3_600_000;
+
+/// Half-lives between summaries after which the smoothing starts over
+const RESET_HALF_LIVES: f64 = 4.0;
+
+/// The metrics of the levels of `summary`, None while either side is empty
+pub fn metrics(summary: &Summary) -> Option<SummaryMetrics> {
+    let bid = summary.bids.first()?.price;
+    let ask = summary.asks.first()?.price;
+    let mid = (bid + ask) / 2.0;
+    let bid_quantity = summary.bids.iter().map(|level| level.quantity).sum::<f64>();
+    let ask_quantity = summary.asks.iter().map(|level| level.quantity).sum::<f64>();
+    let total = bid_quantity + ask_quantity;
+    Some(SummaryMetrics {
+        mid,
+        spread_bps: (ask - bid) / mid * 10_000.0,
+        imbalance: match total > 0.0 {
+            true => (bid_quantity - ask_quantity) / total,
+            false => 0.0,
+        },
+    })
+}
+
+/// An exponential moving average of [SummaryMetrics] over time
+#[derive(Debug, Clone)]
+pub struct Ema {
+    half_life_ms: f64,
+    /// The timestamp of the last metrics added and the average up to them
+    last: Option<(u64, SummaryMetrics)>,
+}
+
+impl Ema {
+    pub fn new(half_life_ms: u32) -> Self {
+        Self {
+            half_life_ms: half_life_ms as f64,
+            last: None,
+        }
+    }
+
+    /// Adds the metrics of a summary sent at `timestamp`, in milliseconds, returning the
+    /// average. The first metrics, those after a gap of more than a few half-lives and
+    /// those from before the last are taken as they are.
+    pub fn add(&mut self, timestamp: u64, metrics: &SummaryMetrics) -> SummaryMetrics {
+        let average = match &self.last {
+            Some((last, average)) if timestamp >= *last => {
+                let dt = (timestamp - last) as f64;
+                match dt > RESET_HALF_LIVES * self.half_life_ms {
+                    true => metrics.clone(),
+                    false => {
+                        let alpha = 1.0 - (-dt / self.half_life_ms).exp2();
+                        let toward = |average: f64, value: f64| average + alpha * (value - average);
+                        SummaryMetrics {
+                            mid: toward(average.mid, metrics.mid),
+                            spread_bps: toward(average.spread_bps, metrics.spread_bps),
+                            imbalance: toward(average.imbalance, metrics.imbalance),
+                        }
+                    }
+                }
+            }
+            _ => metrics.clone(),
+        };
+        self.last = Some((timestamp, average.clone()));
+        average
+    }
+
+    /// Sets the metrics of `summary`, sent at `timestamp`, as they are and smoothed.
+    /// Summaries without them leave the average as it was.
+    pub fn smooth(&mut self, timestamp: u64, summary: &mut Summary) {
+        if let Some(metrics) = metrics(summary) {
+            summary.smoothed_metrics = Some(self.add(timestamp, &metrics));
+            summary.metrics = Some(metrics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::{Level, MarketType};
+
+    fn level(price: f64, quantity: f64) -> Level {
+        Level {
+            exchange: "binance".to_string(),
+            price,
+            quantity,
+            updated_at: None,
+            market_type: MarketType::Spot.into(),
+        }
+    }
+
+    fn raw(mid: f64) -> SummaryMetrics {
+        SummaryMetrics {
+            mid,
+            spread_bps: mid / 10.0,
+            imbalance: mid / 1000.0,
+        }
+    }
+
+    /// The average of each value of `series` with the ones before it, the last average
+    /// decayed by the time since it, computed the long way round
+    fn reference(half_life_ms: f64, series: &[(u64, f64)]) -> Vec<f64> {
+        let mut averages: Vec<f64> = vec![];
+        for (i, &(timestamp, value)) in series.iter().enumerate() {
+            let average = match i {
+                0 => value,
+                _ => {
+                    let dt = (timestamp - series[i - 1].0) as f64;
+                    let decay = 0.5f64.powf(dt / half_life_ms);
+                    decay * averages[i - 1] + (1.0 - decay) * value
+                }
+            };
+            averages.push(average);
+        }
+        averages
+    }
+
+    #[test]
+    fn it_smooths_irregular_intervals_as_the_reference_does() {
+        let series = [
+            (1_000, 100.0),
+            (1_010, 104.0),
+            (1_250, 98.0),
+            (1_260, 99.0),
+            (1_900, 110.0),
+            (1_901, 90.0),
+            (2_400, 101.0),
+        ];
+        let mut ema = Ema::new(200);
+        let expected = reference(200.0, &series);
+        for (&(timestamp, mid), expected) in series.iter().zip(expected) {
+            let average = ema.add(timestamp, &raw(mid));
+            assert!((average.mid - expected).abs() < 1e-9, "{}", timestamp);
+            assert!((average.spread_bps - expected / 10.0).abs() < 1e-9);
+            assert!((average.imbalance - expected / 1000.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn it_moves_halfway_in_a_half_life() {
+        let mut ema = Ema::new(100);
+        ema.add(0, &raw(100.0));
+        assert_eq!(ema.add(100, &raw(200.0)).mid, 150.0);
+        // The same time taken in two steps gets as far.
+        let mut stepped = Ema::new(100);
+        stepped.add(0, &raw(100.0));
+        stepped.add(50, &raw(200.0));
+        assert!((stepped.add(100, &raw(200.0)).mid - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_starts_over_after_a_gap() {
+        let mut ema = Ema::new(100);
+        ema.add(0, &raw(100.0));
+        assert!(ema.add(400, &raw(200.0)).mid < 200.0);
+        assert_eq!(ema.add(801, &raw(300.0)).mid, 300.0);
+        // Summaries from before the last aren't averaged in either.
+        assert_eq!(ema.add(500, &raw(50.0)).mid, 50.0);
+    }
+
+    #[test]
+    fn it_derives_metrics_from_the_levels_sent() {
+        let mut summary = Summary {
+            bids: vec![level(99.0, 3.0), level(98.0, 1.0)],
+            asks: vec![level(101.0, 1.0)],
+            ..Default::default()
+        };
+        let mut ema = Ema::new(1000);
+        ema.smooth(1, &mut summary);
+        let metrics = summary.metrics.clone().unwrap();
+        assert_eq!(metrics.mid, 100.0);
+        assert_eq!(metrics.spread_bps, 200.0);
+        assert_eq!(metrics.imbalance, 0.6);
+        assert_eq!(summary.smoothed_metrics, Some(metrics));
+        // The levels themselves are left as they were.
+        assert_eq!(summary.bids[0], level(99.0, 3.0));
+
+        let mut empty = Summary {
+            bids: vec![level(99.0, 3.0)],
+            ..Default::default()
+        };
+        ema.smooth(2, &mut empty);
+        assert_eq!(empty.metrics, None);
+        assert_eq!(empty.smoothed_metrics, None);
+    }
+}
@@ -0,0 +1,396 @@
+//! Rolling statistics of the spread and the size at the top of the book, kept from the
+//! summaries published for a symbol whether or not any client is watching it.
+//!
+//! Each summary is sampled as it's published and holds until the next one, so averages
+//! are weighted by how long the book stayed that way rather than by how often it changed.
+//! While the summary is unavailable no sample holds, and that time is left out.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    book_summary::{Level, SpreadStats, Summary},
+    service::SummaryReceiver,
+    Symbol,
+};
+
+/// How many samples are kept and for how long
+#[derive(Debug, Clone, Copy)]
+pub struct SpreadSamplesConfig {
+    /// The longest window stats can be asked for
+    pub max_window: Duration,
+    /// Samples kept at most, the oldest are dropped first when a busy market fills them
+    /// before `max_window` has passed
+    pub capacity: usize,
+}
+
+impl Default for SpreadSamplesConfig {
+    fn default() -> Self {
+        Self {
+            max_window: Duration::from_secs(60 * 60),
+            capacity: 100_000,
+        }
+    }
+}
+
+/// The top of the book in a summary
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Top {
+    spread: f64,
+    bid_size: f64,
+    ask_size: f64,
+}
+
+impl Top {
+    fn new(summary: &Summary) -> Option<Self> {
+        Some(Self {
+            spread: summary.spread,
+            bid_size: best_size(&summary.bids)?,
+            ask_size: best_size(&summary.asks)?,
+        })
+    }
+}
+
+/// The quantity at the best price, summed over the exchanges quoting it
+pub(crate) fn best_size(levels: &[Level]) -> Option<f64> {
+    let best = levels.first()?.price;
+    Some(
+        levels
+            .iter()
+            .take_while(|level| level.price == best)
+            .map(|level| level.quantity)
+            .sum(),
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// Milliseconds since the unix epoch
+    at: u64,
+    /// Unset while the summary is unavailable
+    top: Option<Top>,
+}
+
+/// The samples of one symbol, oldest first
+#[derive(Debug)]
+struct Samples {
+    config: SpreadSamplesConfig,
+    samples: VecDeque<Sample>,
+}
+
+impl Samples {
+    fn push(&mut self, sample: Sample) {
+        if let Some(last) = self.samples.back_mut() {
+            // Samples are ordered even if the clock steps back.
+            if sample.at <= last.at {
+                last.top = sample.top;
+                return;
+            }
+        }
+        self.samples.push_back(sample);
+        // The newest sample before the longest window is kept for how the book started it.
+        let start = sample
+            .at
+            .saturating_sub(self.config.max_window.as_millis() as u64);
+        while self.samples.len() > self.config.capacity
+            || self.samples.get(1).is_some_and(|next| next.at <= start)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Stats over the `window` before `now`, both in milliseconds
+    fn stats(&self, now: u64, window: u64) -> SpreadStats {
+        let start = now.saturating_sub(window);
+        let ends = self
+            .samples
+            .iter()
+            .skip(1)
+            .map(|sample| sample.at)
+            .chain([now]);
+        // Each sample held from when it was taken until the next, clipped to the window.
+        let held = self
+            .samples
+            .iter()
+            .zip(ends)
+            .filter(|(sample, end)| *end > start || sample.at >= start)
+            .filter_map(|(sample, end)| {
+                let top = sample.top?;
+                let duration = end.min(now).saturating_sub(sample.at.max(start));
+                Some((top, duration))
+            })
+            .collect::<Vec<_>>();
+        if held.is_empty() {
+            return SpreadStats::default();
+        }
+        let covered = held.iter().map(|(_, duration)| duration).sum::<u64>();
+        // Samples taken right at the end of the window still count when nothing else does.
+        let weight = |duration: u64| if covered == 0 { 1.0 } else { duration as f64 };
+        let total = held
+            .iter()
+            .map(|&(_, duration)| weight(duration))
+            .sum::<f64>();
+        let average = |value: fn(&Top) -> f64| {
+            held.iter()
+                .map(|(top, duration)| value(top) * weight(*duration))
+                .sum::<f64>()
+                / total
+        };
+        let spreads = held.iter().map(|(top, _)| top.spread);
+        SpreadStats {
+            average_spread: average(|top| top.spread),
+            min_spread: spreads.clone().fold(f64::INFINITY, f64::min),
+            max_spread: spreads.fold(f64::NEG_INFINITY, f64::max),
+            average_bid_size: average(|top| top.bid_size),
+            average_ask_size: average(|top| top.ask_size),
+            crossed_percent: average(|top| if top.spread < 0.0 { 100.0 } else { 0.0 }),
+            samples: held.len() as u64,
+            covered_seconds: covered as f64 / 1000.0,
+            ..Default::default()
+        }
+    }
+}
+
+/// The samples kept for a symbol. Clones share the same samples.
+#[derive(Debug, Clone)]
+pub struct SpreadSamples {
+    symbol: Symbol,
+    samples: Arc<Mutex<Samples>>,
+}
+
+impl SpreadSamples {
+    pub fn new(symbol: Symbol, config: SpreadSamplesConfig) -> Self {
+        Self {
+            symbol,
+            samples: Arc::new(Mutex::new(Samples {
+                config,
+                samples: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.symbol
+    }
+
+    /// The longest window stats can be asked for
+    pub fn max_window(&self) -> Duration {
+        self.samples.lock().unwrap().config.max_window
+    }
+
+    /// Samples `summary` at `at` milliseconds since the unix epoch, `None` marking the
+    /// summary unavailable from then.
+    pub fn record(&self, summary: Option<&Summary>, at: u64) {
+        let top = summary.and_then(Top::new);
+        self.samples.lock().unwrap().push(Sample { at, top });
+    }
+
+    /// Samples each summary published on `rx_summary` until it closes.
+    pub fn watch(&self, mut rx_summary: SummaryReceiver) -> JoinHandle<()> {
+        let samples = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let summary = rx_summary.borrow_and_update().clone();
+                match summary {
+                    // The placeholder published before the first summary isn't sampled.
+                    Ok(summary) if summary.sequence == 0 => {}
+                    Ok(summary) => samples.record(Some(&summary), now()),
+                    Err(_) => samples.record(None, now()),
+                }
+                if rx_summary.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Stats over the last `window`
+    pub fn stats(&self, window: Duration) -> SpreadStats {
+        self.stats_at(now(), window)
+    }
+
+    /// Stats over the `window` before `now` milliseconds since the unix epoch
+    pub fn stats_at(&self, now: u64, window: Duration) -> SpreadStats {
+        let mut stats = self
+            .samples
+            .lock()
+            .unwrap()
+            .stats(now, window.as_millis() as u64);
+        stats.symbol = self.symbol.to_string();
+        stats
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::MarketType;
+
+    fn summary(spread: f64, bid_size: f64, ask_size: f64) -> Summary {
+        let level = |price, quantity| Level {
+            exchange: "BINANCE".to_string(),
+            price,
+            quantity,
+            updated_at: None,
+            market_type: MarketType::Spot.into(),
+        };
+        Summary {
+            spread,
+            bids: vec![level(100.0, bid_size), level(99.0, 5.0)],
+            asks: vec![level(100.0 + spread, ask_size)],
+            sequence: 1,
+            ..Default::default()
+        }
+    }
+
+    fn samples(capacity: usize) -> SpreadSamples {
+        SpreadSamples::new(
+            Symbol::BTCUSDT,
+            SpreadSamplesConfig {
+                max_window: Duration::from_secs(60),
+                capacity,
+            },
+        )
+    }
+
+    #[test]
+    fn it_weights_the_stats_by_how_long_each_sample_held() {
+        let samples = samples(100);
+        // A spread of 1 for 30 seconds, 3 for 10 seconds and crossed at -1 for 20.
+        samples.record(Some(&summary(1.0, 2.0, 1.0)), 0);
+        samples.record(Some(&summary(3.0, 4.0, 1.0)), 30_000);
+        samples.record(Some(&summary(-1.0, 2.0, 7.0)), 40_000);
+
+        let stats = samples.stats_at(60_000, Duration::from_secs(60));
+        assert_eq!(stats.symbol, "BTCUSDT");
+        assert_eq!(stats.average_spread, (30.0 + 30.0 - 20.0) / 60.0);
+        assert_eq!(stats.min_spread, -1.0);
+        assert_eq!(stats.max_spread, 3.0);
+        assert_eq!(stats.average_bid_size, (60.0 + 40.0 + 40.0) / 60.0);
+        assert_eq!(stats.average_ask_size, (30.0 + 10.0 + 140.0) / 60.0);
+        assert_eq!(stats.crossed_percent, 100.0 / 3.0);
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.covered_seconds, 60.0);
+    }
+
+    #[test]
+    fn it_clips_samples_to_the_window() {
+        let samples = samples(100);
+        samples.record(Some(&summary(1.0, 1.0, 1.0)), 0);
+        samples.record(Some(&summary(2.0, 1.0, 1.0)), 50_000);
+
+        // The first sample held for the first 10 of the last 20 seconds.
+        let stats = samples.stats_at(60_000, Duration::from_secs(20));
+        assert_eq!(stats.average_spread, 1.5);
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.covered_seconds, 20.0);
+
+        let stats = samples.stats_at(60_000, Duration::from_secs(5));
+        assert_eq!(stats.average_spread, 2.0);
+        assert_eq!(stats.min_spread, 2.0);
+        assert_eq!(stats.samples, 1);
+    }
+
+    #[test]
+    fn it_leaves_out_the_time_the_summary_was_unavailable() {
+        let samples = samples(100);
+        samples.record(Some(&summary(1.0, 1.0, 1.0)), 0);
+        samples.record(None, 10_000);
+        samples.record(Some(&summary(4.0, 1.0, 1.0)), 50_000);
+
+        let stats = samples.stats_at(60_000, Duration::from_secs(60));
+        assert_eq!(stats.average_spread, 2.5);
+        assert_eq!(stats.covered_seconds, 20.0);
+        assert_eq!(stats.samples, 2);
+    }
+
+    #[test]
+    fn it_sums_the_sizes_quoted_at_the_best_price() {
+        let mut summary = summary(1.0, 2.0, 1.0);
+        summary.bids.insert(
+            1,
+            Level {
+                exchange: "BITSTAMP".to_string(),
+                price: 100.0,
+                quantity: 0.5,
+                updated_at: None,
+                market_type: MarketType::Spot.into(),
+            },
+        );
+        let samples = samples(100);
+        samples.record(Some(&summary), 0);
+        assert_eq!(
+            samples
+                .stats_at(10_000, Duration::from_secs(60))
+                .average_bid_size,
+            2.5
+        );
+    }
+
+    #[test]
+    fn it_counts_samples_at_the_end_of_the_window_when_nothing_held() {
+        let samples = samples(100);
+        assert_eq!(
+            samples.stats_at(0, Duration::from_secs(60)),
+            SpreadStats {
+                symbol: "BTCUSDT".to_string(),
+                ..Default::default()
+            }
+        );
+        samples.record(Some(&summary(2.0, 1.0, 1.0)), 1_000);
+        let stats = samples.stats_at(1_000, Duration::from_secs(60));
+        assert_eq!(stats.average_spread, 2.0);
+        assert_eq!(stats.samples, 1);
+        assert_eq!(stats.covered_seconds, 0.0);
+    }
+
+    #[test]
+    fn it_bounds_the_samples_kept() {
+        let samples = samples(3);
+        for at in 0..10 {
+            samples.record(Some(&summary(at as f64, 1.0, 1.0)), at * 1000);
+        }
+        assert_eq!(samples.samples.lock().unwrap().samples.len(), 3);
+        assert_eq!(
+            samples.stats_at(10_000, Duration::from_secs(60)).min_spread,
+            7.0
+        );
+
+        // Samples older than the longest window are dropped, except the one it starts in.
+        let samples = self::samples(100);
+        for at in 0..10 {
+            samples.record(Some(&summary(at as f64, 1.0, 1.0)), at * 30_000);
+        }
+        let kept = &samples.samples.lock().unwrap().samples;
+        assert_eq!(
+            kept.iter().map(|s| s.at).collect::<Vec<_>>(),
+            [210_000, 240_000, 270_000]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_samples_the_summaries_published() {
+        let (tx, rx) = tokio::sync::watch::channel(Ok(Arc::new(Summary::default())));
+        let samples = samples(100);
+        let handle = samples.watch(rx);
+        tokio::task::yield_now().await;
+        assert!(samples.samples.lock().unwrap().samples.is_empty());
+
+        let _ = tx.send_replace(Ok(Arc::new(summary(2.0, 1.0, 1.0))));
+        tokio::task::yield_now().await;
+        let stats = samples.stats(Duration::from_secs(60));
+        assert_eq!((stats.samples, stats.average_spread), (1, 2.0));
+        drop(tx);
+        handle.await.unwrap();
+    }
+}
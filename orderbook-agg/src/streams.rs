@@ -0,0 +1,217 @@
+//! Registry of the `watch_summary` streams open on the server, so they can be listed and
+//! ended one at a time without touching the others.
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{sync::watch, time::Instant};
+
+use crate::{
+    book_summary::{StreamInfo, WatchSummaryRequest},
+    service::SummaryReceiver,
+    Symbol,
+};
+
+/// The open streams, shared by the aggregator service that adds them and the admin
+/// service that lists and ends them. Clones share the same streams.
+#[derive(Debug, Clone, Default)]
+pub struct StreamRegistry {
+    inner: Arc<Mutex<Streams>>,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    next_id: u64,
+    streams: BTreeMap<u64, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    peer: Option<SocketAddr>,
+    symbol: Symbol,
+    request: WatchSummaryRequest,
+    started: Instant,
+    progress: Arc<Progress>,
+    latest: SummaryReceiver,
+    tx_cancel: watch::Sender<bool>,
+}
+
+/// Summaries sent on a stream, updated by its task as it sends them
+#[derive(Debug, Default)]
+struct Progress {
+    sent: AtomicU64,
+    sequence: AtomicU64,
+}
+
+impl StreamRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stream, which stays listed until the handle returned is dropped.
+    /// `latest` is the summaries the stream is sent from, to work its lag out.
+    pub fn register(
+        &self,
+        peer: Option<SocketAddr>,
+        symbol: Symbol,
+        request: WatchSummaryRequest,
+        latest: SummaryReceiver,
+    ) -> StreamHandle {
+        let (tx_cancel, rx_cancel) = watch::channel(false);
+        let progress = Arc::new(Progress::default());
+        let mut streams = self.inner.lock().unwrap();
+        streams.next_id += 1;
+        let id = streams.next_id;
+        streams.streams.insert(
+            id,
+            Entry {
+                peer,
+                symbol,
+                request,
+                started: Instant::now(),
+                progress: progress.clone(),
+                latest,
+                tx_cancel,
+            },
+        );
+        StreamHandle {
+            id,
+            registry: self.clone(),
+            progress,
+            rx_cancel,
+        }
+    }
+
+    /// The open streams, oldest first
+    pub fn list(&self) -> Vec<StreamInfo> {
+        let streams = self.inner.lock().unwrap();
+        streams
+            .streams
+            .iter()
+            .map(|(&id, entry)| {
+                let latest = match &*entry.latest.borrow() {
+                    Ok(summary) => summary.sequence,
+                    Err(_) => 0,
+                };
+                let sent_sequence = entry.progress.sequence.load(Ordering::Relaxed);
+                StreamInfo {
+                    id,
+                    peer: entry.peer.map(|peer| peer.to_string()).unwrap_or_default(),
+                    symbol: entry.symbol.to_string(),
+                    request: Some(entry.request.clone()),
+                    summaries_sent: entry.progress.sent.load(Ordering::Relaxed),
+                    lag: latest.saturating_sub(sent_sequence),
+                    age_seconds: entry.started.elapsed().as_secs(),
+                }
+            })
+            .collect()
+    }
+
+    /// Tells the stream `id` to end, returning false if there's no such stream.
+    pub fn cancel(&self, id: u64) -> bool {
+        let streams = self.inner.lock().unwrap();
+        match streams.streams.get(&id) {
+            Some(entry) => {
+                entry.tx_cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of streams open
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Held by a stream's task for as long as the stream is open
+#[derive(Debug)]
+pub struct StreamHandle {
+    pub id: u64,
+    registry: StreamRegistry,
+    progress: Arc<Progress>,
+    rx_cancel: watch::Receiver<bool>,
+}
+
+impl StreamHandle {
+    /// Records a summary with `sequence` sent to the client.
+    pub fn sent(&self, sequence: u64) {
+        self.progress.sent.fetch_add(1, Ordering::Relaxed);
+        self.progress.sequence.store(sequence, Ordering::Relaxed);
+    }
+
+    /// Completes once the stream has been [cancelled](StreamRegistry::cancel).
+    pub async fn cancelled(&self) {
+        let mut rx_cancel = self.rx_cancel.clone();
+        let _ = rx_cancel.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.registry.inner.lock().unwrap().streams.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::Summary;
+
+    #[tokio::test]
+    async fn it_lists_and_cancels_streams() {
+        let (tx_summary, rx_summary) = watch::channel(Ok(Arc::new(Summary {
+            sequence: 5,
+            ..Default::default()
+        })));
+        let registry = StreamRegistry::new();
+        let first = registry.register(
+            None,
+            Symbol::BTCUSDT,
+            WatchSummaryRequest::default(),
+            rx_summary.clone(),
+        );
+        let second = registry.register(
+            Some("127.0.0.1:4000".parse().unwrap()),
+            Symbol::BTCUSDT,
+            WatchSummaryRequest {
+                levels: 3,
+                ..Default::default()
+            },
+            rx_summary,
+        );
+        second.sent(5);
+        let _ = tx_summary.send_replace(Ok(Arc::new(Summary {
+            sequence: 8,
+            ..Default::default()
+        })));
+
+        let streams = registry.list();
+        assert_eq!(
+            streams.iter().map(|s| s.id).collect::<Vec<_>>(),
+            [first.id, second.id]
+        );
+        assert_eq!(streams[1].peer, "127.0.0.1:4000");
+        assert_eq!(streams[1].request.as_ref().unwrap().levels, 3);
+        assert_eq!(streams[1].summaries_sent, 1);
+        assert_eq!(streams[1].lag, 3);
+        assert_eq!(streams[0].lag, 8);
+
+        assert!(registry.cancel(first.id));
+        first.cancelled().await;
+        assert!(!registry.cancel(u64::MAX));
+        drop(first);
+        assert_eq!(registry.len(), 1);
+        drop(second);
+        assert!(registry.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! The summaries `GetSummary` builds for calls it can't answer with the published summary,
+//! those naming listings with `symbol_overrides`, a quote currency or markets. Calls asking
+//! for the same summary while it's being built wait for it rather than fetching the books
+//! again, and those coming in shortly after are sent it too.
+//!
+//! A summary is built again once it's older than the cache's TTL, or once a feed of its
+//! symbol has resynced its book since it was built.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::OnceCell, time::Instant};
+use tonic::Status;
+
+use crate::{book_summary::Summary, Exchange, Symbol};
+
+/// How long a summary is sent to the calls asking for it again unless set otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// Summaries kept unless set otherwise
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// What a summary is built from, the same for every call that would build the same one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallKey {
+    symbol: Symbol,
+    exchanges: Vec<Exchange>,
+    overrides: Vec<(Exchange, String)>,
+    quote_currency: String,
+    /// The liquidity bands, by their bits
+    bps: Vec<u64>,
+}
+
+impl CallKey {
+    /// The summary of `symbol` aggregated from `exchanges`, with the listings of `overrides`
+    /// in place of theirs and priced in `quote_currency`, in whatever order they're given
+    pub fn new(
+        symbol: Symbol,
+        exchanges: &[Exchange],
+        overrides: &HashMap<Exchange, String>,
+        quote_currency: &str,
+    ) -> Self {
+        let mut exchanges = exchanges.to_vec();
+        exchanges.sort_by_key(|exchange| exchange.to_string());
+        exchanges.dedup();
+        let mut overrides = overrides
+            .iter()
+            .map(|(&exchange, native)| (exchange, native.clone()))
+            .collect::<Vec<_>>();
+        overrides.sort_by_key(|(exchange, native)| (exchange.to_string(), native.clone()));
+        Self {
+            symbol,
+            exchanges,
+            overrides,
+            quote_currency: quote_currency.to_string(),
+            bps: Vec::new(),
+        }
+    }
+
+    /// The summary with the liquidity within each of `bps`, in whatever order they're given
+    pub fn with_bps(mut self, bps: &[f64]) -> Self {
+        self.bps = bps.iter().map(|bps| bps.to_bits()).collect();
+        self.bps.sort_unstable();
+        self.bps.dedup();
+        self
+    }
+}
+
+/// A summary and when it was built, or why it couldn't be
+type Built = (Instant, Result<Arc<Summary>, Status>);
+
+#[derive(Debug)]
+struct Entry {
+    created: Instant,
+    /// The resyncs of the symbol's feeds when the summary started being built
+    resyncs: u64,
+    built: Arc<OnceCell<Built>>,
+}
+
+/// The summaries built for `GetSummary` calls, at most the capacity of them. Clones share
+/// the same summaries.
+#[derive(Debug, Clone)]
+pub struct SummaryCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Arc<Mutex<HashMap<CallKey, Entry>>>,
+}
+
+impl Default for SummaryCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_CAPACITY)
+    }
+}
+
+impl SummaryCache {
+    /// Keeps at most `capacity` summaries, dropping the oldest for new ones, each sent for
+    /// `ttl` after it's built. With a TTL of 0, only the calls waiting on a summary while
+    /// it's built share it.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            entries: Arc::default(),
+        }
+    }
+
+    /// The summary of `key`, built with `build` unless another call is building it, or
+    /// built it within the TTL and `resyncs`, those of the symbol's feeds so far, haven't
+    /// changed since. A failure is sent to the calls waiting on it and then dropped, so
+    /// the next call builds the summary again.
+    pub async fn get_or_build<F, Fut>(
+        &self,
+        key: CallKey,
+        resyncs: u64,
+        build: F,
+    ) -> Result<Arc<Summary>, Status>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Summary, Status>>,
+    {
+        let cell = self.cell(&key, resyncs);
+        let (_, built) = cell
+            .get_or_init(|| async {
+                let summary = build().await.map(Arc::new);
+                (Instant::now(), summary)
+            })
+            .await;
+        if built.is_err() {
+            let mut entries = self.entries.lock().unwrap();
+            if entries
+                .get(&key)
+                .is_some_and(|entry| Arc::ptr_eq(&entry.built, &cell))
+            {
+                entries.remove(&key);
+            }
+        }
+        built.clone()
+    }
+
+    /// The cell the summary of `key` is built in, a new one when the one kept is too old
+    /// or from before a resync
+    fn cell(&self, key: &CallKey, resyncs: u64) -> Arc<OnceCell<Built>> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(key) {
+            let fresh = entry
+                .built
+                .get()
+                .is_none_or(|(built, _)| built.elapsed() < self.ttl);
+            if fresh && entry.resyncs == resyncs {
+                return entry.built.clone();
+            }
+        }
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let built = Arc::new(OnceCell::new());
+        entries.insert(
+            key.clone(),
+            Entry {
+                created: Instant::now(),
+                resyncs,
+                built: built.clone(),
+            },
+        );
+        built
+    }
+
+    /// The summaries kept, including those being built
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn key(symbol: Symbol) -> CallKey {
+        CallKey::new(
+            symbol,
+            &[Exchange::BITSTAMP, Exchange::BINANCE],
+            &HashMap::from([(Exchange::BITSTAMP, "xbtusdt".to_string())]),
+            "",
+        )
+    }
+
+    /// Builds a summary numbered with the builds so far, after `delay`
+    async fn build(builds: &AtomicUsize, delay: Duration) -> Result<Summary, Status> {
+        let sequence = builds.fetch_add(1, Ordering::SeqCst) as u64 + 1;
+        tokio::time::sleep(delay).await;
+        Ok(Summary {
+            sequence,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_builds_a_summary_once_for_the_calls_asking_for_it() {
+        let cache = SummaryCache::new(Duration::from_secs(1), 10);
+        let builds = AtomicUsize::new(0);
+        let calls = (0..10).map(|_| {
+            cache.get_or_build(key(Symbol::BTCUSDT), 0, || {
+                build(&builds, Duration::from_millis(100))
+            })
+        });
+        let summaries = futures::future::join_all(calls).await;
+        assert!(summaries.iter().all(|s| s.as_ref().unwrap().sequence == 1));
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+
+        // The same listings in another order are the same summary.
+        let reordered = CallKey::new(
+            Symbol::BTCUSDT,
+            &[Exchange::BINANCE, Exchange::BITSTAMP],
+            &HashMap::from([(Exchange::BITSTAMP, "xbtusdt".to_string())]),
+            "",
+        );
+        assert_eq!(reordered, key(Symbol::BTCUSDT));
+
+        // It's built again once it's older than the TTL.
+        let get = || cache.get_or_build(key(Symbol::BTCUSDT), 0, || build(&builds, Duration::ZERO));
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        assert_eq!(get().await.unwrap().sequence, 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(get().await.unwrap().sequence, 2);
+
+        // Or as soon as a feed of the symbol resyncs.
+        let resynced = cache
+            .get_or_build(key(Symbol::BTCUSDT), 1, || build(&builds, Duration::ZERO))
+            .await;
+        assert_eq!(resynced.unwrap().sequence, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_drops_failures_and_the_oldest_summaries_past_the_capacity() {
+        let cache = SummaryCache::new(Duration::from_secs(60), 2);
+        let builds = AtomicUsize::new(0);
+        let failed = cache
+            .get_or_build(key(Symbol::BTCUSDT), 0, || async {
+                builds.fetch_add(1, Ordering::SeqCst);
+                Err(Status::unavailable("no book"))
+            })
+            .await;
+        assert_eq!(failed.unwrap_err().code(), tonic::Code::Unavailable);
+        assert!(cache.is_empty());
+
+        for symbol in [Symbol::BTCUSDT, Symbol::ETHBTC, Symbol::BTCUSD] {
+            cache
+                .get_or_build(key(symbol), 0, || build(&builds, Duration::ZERO))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(cache.len(), 2);
+        let rebuilt = cache
+            .get_or_build(key(Symbol::BTCUSDT), 0, || build(&builds, Duration::ZERO))
+            .await;
+        assert_eq!(rebuilt.unwrap().sequence, 5);
+    }
+}
@@ -0,0 +1,537 @@
+//! Seeded, deterministic market data for load testing and for running the server without
+//! exchange access.
+//!
+//! A [Generator] keeps a book on each side of a random walk mid price and produces the
+//! levels each message inserts, modifies or deletes as a [Change], which is written in
+//! the wire format of any of the exchanges. The same [SyntheticConfig] always produces
+//! the same messages, so they can be scripted into the mock exchanges in tests or fed
+//! straight into order books with [synthetic_symbol].
+use anyhow::{ensure, Result};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::Instrument;
+
+use crate::{
+    core::order_book::{BookLevels, OrderBook, OrderBookArgs},
+    exchanges::connector,
+    replay::Frames,
+    service::{spawn_summary_with_feeds, ExchangeOptions, SummarySubscriber},
+    Exchange, Symbol,
+};
+
+/// Microseconds since the unix epoch the generator's clock starts from
+const START_TIME: u64 = 1_700_000_000_000_000;
+
+/// Decimals of generated quantities
+const SCALE_QUANTITY: u32 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticConfig {
+    pub seed: u64,
+    /// Price the walk starts from and stays within about a percent of
+    pub mid: Decimal,
+    /// Smallest price step
+    pub tick: Decimal,
+    /// Levels kept on each side
+    pub depth: usize,
+    /// Messages a second on average
+    pub rate: f64,
+    /// Fraction of messages sent straight after the one before, from 0 up to 1
+    pub burstiness: f64,
+    /// Chance of the mid moving a tick with each message
+    pub volatility: f64,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            mid: dec!(30000),
+            tick: dec!(0.01),
+            depth: 50,
+            rate: 100.0,
+            burstiness: 0.2,
+            volatility: 0.3,
+        }
+    }
+}
+
+/// Levels changed by a message, best first, with a zero quantity deleting a level. From
+/// [Generator::snapshot] it's the whole book instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub update_id: u64,
+    /// Microseconds since the unix epoch on the generator's clock
+    pub time: u64,
+    pub bids: Vec<[Decimal; 2]>,
+    pub asks: Vec<[Decimal; 2]>,
+}
+
+impl Change {
+    /// The depth update message `exchange` would send for the change
+    pub fn update(&self, exchange: Exchange, symbol: Symbol) -> String {
+        let millis = self.time / 1000;
+        let message = match exchange {
+            Exchange::BINANCE => serde_json::json!({
+                "e": "depthUpdate",
+                "E": millis,
+                "s": symbol.to_string(),
+                "U": self.update_id,
+                "u": self.update_id,
+                "b": levels(&self.bids),
+                "a": levels(&self.asks),
+            }),
+            Exchange::BINANCE_FUTURES => serde_json::json!({
+                "e": "depthUpdate",
+                "E": millis,
+                "T": millis,
+                "s": symbol.to_string(),
+                "U": self.update_id,
+                "u": self.update_id,
+                "pu": self.update_id.saturating_sub(1),
+                "b": levels(&self.bids),
+                "a": levels(&self.asks),
+            }),
+            Exchange::BITSTAMP => serde_json::json!({
+                "data": {
+                    "timestamp": (self.time / 1_000_000).to_string(),
+                    "microtimestamp": self.time.to_string(),
+                    "bids": levels(&self.bids),
+                    "asks": levels(&self.asks),
+                },
+                "channel": format!("diff_order_book_{}", symbol.to_string().to_lowercase()),
+                "event": "data",
+            }),
+        };
+        message.to_string()
+    }
+
+    /// The REST snapshot `exchange` would return for the change, for a change from
+    /// [Generator::snapshot]
+    pub fn snapshot(&self, exchange: Exchange) -> String {
+        let message = match exchange {
+            Exchange::BINANCE | Exchange::BINANCE_FUTURES => serde_json::json!({
+                "lastUpdateId": self.update_id,
+                "E": self.time / 1000,
+                "T": self.time / 1000,
+                "bids": levels(&self.bids),
+                "asks": levels(&self.asks),
+            }),
+            Exchange::BITSTAMP => serde_json::json!({
+                "timestamp": (self.time / 1_000_000).to_string(),
+                "microtimestamp": self.time.to_string(),
+                "bids": levels(&self.bids),
+                "asks": levels(&self.asks),
+            }),
+        };
+        message.to_string()
+    }
+}
+
+fn levels(levels: &[[Decimal; 2]]) -> Vec<[String; 2]> {
+    levels
+        .iter()
+        .map(|[price, quantity]| [price.to_string(), quantity.to_string()])
+        .collect()
+}
+
+/// Generates the endless sequence of changes to a synthetic book, each with the time
+/// since the one before
+pub struct Generator {
+    config: SyntheticConfig,
+    rng: ChaCha8Rng,
+    /// Prices are kept in ticks
+    start: i64,
+    mid: i64,
+    bids: BTreeMap<i64, Decimal>,
+    asks: BTreeMap<i64, Decimal>,
+    update_id: u64,
+    time: u64,
+}
+
+impl Generator {
+    pub fn new(config: SyntheticConfig) -> Result<Self> {
+        ensure!(config.tick > Decimal::ZERO, "tick must be above zero");
+        ensure!(
+            (config.mid % config.tick).is_zero() && config.mid > config.tick,
+            "mid {} must be a multiple of the tick {}",
+            config.mid,
+            config.tick
+        );
+        ensure!(config.depth > 0, "depth must be above zero");
+        ensure!(config.rate > 0.0, "rate must be above zero");
+        ensure!(
+            (0.0..1.0).contains(&config.burstiness),
+            "burstiness must be from 0 up to 1"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&config.volatility),
+            "volatility must be from 0 to 1"
+        );
+        let start = (config.mid / config.tick)
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("mid {} is too many ticks", config.mid))?;
+        let mut generator = Self {
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            start,
+            mid: start,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            update_id: 1,
+            time: START_TIME,
+        };
+        // The book starts full, with gaps of up to a few ticks between levels.
+        let (mut bid, mut ask) = (start, start);
+        for _ in 0..generator.config.depth {
+            bid -= generator.rng.gen_range(1..=3);
+            ask += generator.rng.gen_range(1..=3);
+            let quantity = generator.quantity();
+            generator.bids.insert(bid, quantity);
+            let quantity = generator.quantity();
+            generator.asks.insert(ask, quantity);
+        }
+        Ok(generator)
+    }
+
+    /// The whole book as of the last change
+    pub fn snapshot(&self) -> Change {
+        Change {
+            update_id: self.update_id,
+            time: self.time,
+            bids: self.levels(self.bids.iter().rev()),
+            asks: self.levels(self.asks.iter()),
+        }
+    }
+
+    pub fn mid(&self) -> Decimal {
+        Decimal::from(self.mid) * self.config.tick
+    }
+
+    fn levels<'a>(
+        &self,
+        levels: impl Iterator<Item = (&'a i64, &'a Decimal)>,
+    ) -> Vec<[Decimal; 2]> {
+        levels
+            .map(|(&ticks, &quantity)| [Decimal::from(ticks) * self.config.tick, quantity])
+            .collect()
+    }
+
+    fn quantity(&mut self) -> Decimal {
+        Decimal::new(self.rng.gen_range(1..=500_000_000), SCALE_QUANTITY)
+    }
+
+    /// Time until the next message, none for messages in a burst and otherwise
+    /// exponentially distributed so messages average the configured rate
+    fn gap(&mut self) -> Duration {
+        if self.rng.gen_bool(self.config.burstiness) {
+            return Duration::ZERO;
+        }
+        let rate = self.config.rate * (1.0 - self.config.burstiness);
+        let uniform: f64 = self.rng.gen();
+        Duration::from_secs_f64(-(1.0 - uniform).ln() / rate)
+    }
+
+    /// Moves the mid a tick, drifting back towards where it started, and deletes the
+    /// levels it crosses.
+    fn walk(&mut self, bids: &mut BTreeMap<i64, Decimal>, asks: &mut BTreeMap<i64, Decimal>) {
+        let reach = (self.start / 100).max(1) as f64;
+        let offset = (self.mid - self.start) as f64;
+        let up = (0.5 - 0.5 * offset / reach).clamp(0.0, 1.0);
+        if self.rng.gen_bool(up) {
+            self.mid += 1;
+            while let Some((&ticks, _)) = self.asks.first_key_value() {
+                if ticks > self.mid {
+                    break;
+                }
+                self.asks.remove(&ticks);
+                asks.insert(ticks, Decimal::ZERO);
+            }
+        } else {
+            self.mid -= 1;
+            while let Some((&ticks, _)) = self.bids.last_key_value() {
+                if ticks < self.mid {
+                    break;
+                }
+                self.bids.remove(&ticks);
+                bids.insert(ticks, Decimal::ZERO);
+            }
+        }
+    }
+
+    /// Inserts, modifies or deletes a level on one side, keeping the side near its depth.
+    fn change_level(&mut self, bid: bool, changed: &mut BTreeMap<i64, Decimal>) {
+        let depth = self.config.depth;
+        let len = if bid {
+            self.bids.len()
+        } else {
+            self.asks.len()
+        };
+        let roll = self.rng.gen_range(0..10);
+        let quantity = self.quantity();
+        let distance = self.rng.gen_range(1..=depth as i64 * 2);
+        let nth = self.rng.gen_range(0..len.max(1));
+        let mid = self.mid;
+        let side = if bid { &mut self.bids } else { &mut self.asks };
+        if len < depth || (len == depth && roll < 3) {
+            let ticks = if bid { mid - distance } else { mid + distance };
+            side.insert(ticks, quantity);
+            changed.insert(ticks, quantity);
+        } else if len > depth {
+            // The level furthest from the mid goes
+            let ticks = if bid {
+                side.first_key_value()
+            } else {
+                side.last_key_value()
+            }
+            .map(|(&ticks, _)| ticks)
+            .expect("the side is over its depth");
+            side.remove(&ticks);
+            changed.insert(ticks, Decimal::ZERO);
+        } else {
+            let ticks = *side.keys().nth(nth).expect("the side has levels");
+            if roll < 8 {
+                side.insert(ticks, quantity);
+                changed.insert(ticks, quantity);
+            } else {
+                side.remove(&ticks);
+                changed.insert(ticks, Decimal::ZERO);
+            }
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = (Duration, Change);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let gap = self.gap();
+        let (mut bids, mut asks) = (BTreeMap::new(), BTreeMap::new());
+        if self.rng.gen_bool(self.config.volatility) {
+            self.walk(&mut bids, &mut asks);
+        }
+        for _ in 0..self.rng.gen_range(1..=3) {
+            if self.rng.gen_bool(0.5) {
+                self.change_level(true, &mut bids);
+            } else {
+                self.change_level(false, &mut asks);
+            }
+        }
+        self.update_id += 1;
+        self.time += gap.as_micros() as u64;
+        let change = Change {
+            update_id: self.update_id,
+            time: self.time,
+            bids: self.levels(bids.iter().rev()),
+            asks: self.levels(asks.iter()),
+        };
+        Some((gap, change))
+    }
+}
+
+/// The generator's messages for `exchange` paced at its rate, starting with its whole
+/// book as the first update
+fn frames(generator: Generator, exchange: Exchange, symbol: Symbol) -> Frames {
+    let first = generator.snapshot().update(exchange, symbol);
+    let first = futures::stream::once(async move { Ok(Message::Text(first)) });
+    let rest = futures::stream::unfold(
+        (generator, Instant::now(), Duration::ZERO),
+        move |(mut generator, started, elapsed)| async move {
+            let (gap, change) = generator.next()?;
+            let elapsed = elapsed + gap;
+            tokio::time::sleep_until(started + elapsed).await;
+            let message = Message::Text(change.update(exchange, symbol));
+            Some((Ok(message), (generator, started, elapsed)))
+        },
+    );
+    Box::pin(futures::StreamExt::chain(first, rest))
+}
+
+/// Feeds an order book for each of `options.exchanges` from its own generator and returns
+/// the subscriber for the summary task like [start_symbol](crate::service::start_symbol).
+/// Each exchange's generator is seeded from `config.seed` and its place in the exchanges,
+/// and its messages are parsed as the exchange's diff channel whatever the channels in
+/// `options`.
+pub fn synthetic_symbol(
+    options: &ExchangeOptions,
+    symbol: Symbol,
+    config: &SyntheticConfig,
+    price_range: u8,
+    levels: u32,
+    tx_serving: watch::Sender<bool>,
+) -> Result<SummarySubscriber> {
+    let (tx_levels, rx_levels) = mpsc::channel::<Arc<BookLevels>>(100);
+    let (_, rx_closed) = mpsc::channel(1);
+    let scale_price = config.tick.normalize().scale();
+    let (storage_price_min, storage_price_max) =
+        OrderBookArgs::get_min_max(config.mid, price_range, scale_price)?;
+
+    for (i, &exchange) in options.exchanges.iter().enumerate() {
+        let generator = Generator::new(SyntheticConfig {
+            seed: config.seed.wrapping_add(i as u64),
+            ..config.clone()
+        })?;
+        tracing::info!(
+            "generating {} {} at {} messages a second",
+            exchange,
+            symbol,
+            config.rate
+        );
+        let orderbook = OrderBook::new(
+            exchange,
+            symbol,
+            storage_price_min,
+            storage_price_max,
+            scale_price,
+            SCALE_QUANTITY,
+        );
+        let frames = frames(generator, exchange, symbol);
+        let stats = options.stats.feed(exchange, symbol);
+        let fed =
+            connector(exchange)?.feed_diffs(orderbook, stats, levels, frames, tx_levels.clone());
+        tokio::spawn(
+            async move {
+                if let Err(err) = fed.await {
+                    tracing::error!("{} {} generator failed: {:#}", exchange, symbol, err);
+                }
+            }
+            .instrument(tracing::info_span!("exchange", %exchange, %symbol)),
+        );
+    }
+
+    Ok(spawn_summary_with_feeds(
+        symbol,
+        options.exchanges.clone(),
+        rx_levels,
+        rx_closed,
+        tx_serving,
+        options.summary_feeds(),
+    ))
+}
+
+#[cfg(all(
+    test,
+    feature = "exchange-binance-futures",
+    feature = "exchange-bitstamp"
+))]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            exchange_book::{FromMessage, StreamMessage},
+            order_book::Update,
+        },
+        exchanges::{
+            binance::data::{BookUpdate as BinanceUpdate, Snapshot as BinanceSnapshot},
+            binance_futures::data::BookUpdate as FuturesUpdate,
+            bitstamp::data::{BookUpdate as BitstampUpdate, Snapshot},
+        },
+    };
+    use rust_decimal::prelude::ToPrimitive;
+
+    fn generator(seed: u64) -> Generator {
+        Generator::new(SyntheticConfig {
+            seed,
+            depth: 10,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn it_generates_the_same_changes_from_the_same_seed() {
+        let changes = |seed| generator(seed).take(200).collect::<Vec<_>>();
+        assert_eq!(changes(7), changes(7));
+        assert_ne!(changes(7), changes(8));
+        assert_eq!(generator(7).snapshot(), generator(7).snapshot());
+    }
+
+    #[test]
+    fn it_keeps_the_book_uncrossed_near_its_depth() {
+        let mut generator = generator(1);
+        let mut gaps = Duration::ZERO;
+        for (gap, _) in generator.by_ref().take(10_000) {
+            gaps += gap;
+        }
+        let book = generator.snapshot();
+        assert!(book.bids[0][0] < book.asks[0][0]);
+        assert!(book.bids.windows(2).all(|pair| pair[0][0] > pair[1][0]));
+        assert!(book.asks.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+        assert!((9..=11).contains(&book.bids.len()) && (9..=11).contains(&book.asks.len()));
+        assert!((generator.mid() - dec!(30000)).abs() <= dec!(300));
+        // 100 messages a second on average
+        assert!((80.0..120.0).contains(&gaps.as_secs_f64()), "{:?}", gaps);
+        assert_eq!(book.update_id, 10_001);
+    }
+
+    #[test]
+    fn it_writes_changes_the_order_books_accept() {
+        let mut generator = generator(2);
+        let scale_price = 2;
+        let (min, max) = OrderBookArgs::get_min_max(dec!(30000), 5, scale_price).unwrap();
+        let mut orderbook =
+            OrderBook::new(Exchange::BINANCE, Symbol::BTCUSDT, min, max, scale_price, 8);
+        let messages = std::iter::once(generator.snapshot())
+            .chain(generator.by_ref().take(1000).map(|(_, change)| change))
+            .map(|change| change.update(Exchange::BINANCE, Symbol::BTCUSDT))
+            .collect::<Vec<_>>();
+        for message in messages {
+            let Ok(StreamMessage::Update(mut update)) =
+                BinanceUpdate::from_message(message.as_bytes())
+            else {
+                panic!("not an update: {}", message);
+            };
+            orderbook.update(&mut update).unwrap();
+        }
+        let levels = orderbook
+            .take_top_levels(1)
+            .unwrap()
+            .to_book_levels()
+            .unwrap();
+        let best_bid = generator.snapshot().bids[0][0];
+        assert_eq!(Some(levels.bids[0].price), best_bid.to_f64());
+
+        let change = generator.snapshot();
+        for exchange in [Exchange::BITSTAMP, Exchange::BINANCE_FUTURES] {
+            let message = change.update(exchange, Symbol::BTCUSDT);
+            let parsed = match exchange {
+                Exchange::BITSTAMP => BitstampUpdate::from_message(message.as_bytes())
+                    .map(|update| matches!(update, StreamMessage::Update(_))),
+                _ => FuturesUpdate::from_message(message.as_bytes())
+                    .map(|update| matches!(update, StreamMessage::Update(_))),
+            };
+            assert!(parsed.unwrap(), "{}", message);
+        }
+        let snapshot: BinanceSnapshot =
+            serde_json::from_str(&change.snapshot(Exchange::BINANCE)).unwrap();
+        assert_eq!(snapshot.last_update_id(), 1001);
+        let snapshot: Snapshot =
+            serde_json::from_str(&change.snapshot(Exchange::BITSTAMP)).unwrap();
+        assert_eq!(snapshot.bids.len(), change.bids.len());
+    }
+
+    #[test]
+    fn it_rejects_configs_it_cant_generate_from() {
+        let config = |config: SyntheticConfig| Generator::new(config).is_err();
+        assert!(config(SyntheticConfig {
+            mid: dec!(30000.005),
+            ..Default::default()
+        }));
+        assert!(config(SyntheticConfig {
+            burstiness: 1.0,
+            ..Default::default()
+        }));
+        assert!(config(SyntheticConfig {
+            depth: 0,
+            ..Default::default()
+        }));
+    }
+}
@@ -0,0 +1,246 @@
+//! The best bid and ask of a symbol across the exchanges, served by `GetTicker`,
+//! `WatchTicker` and `WatchSpread` from the [Quotes](crate::divergence::Quotes) the summary
+//! task publishes.
+//!
+//! Tickers are made from each exchange's best bid and ask rather than from the summaries,
+//! so a client watching only the ticker never has the summary task build a summary.
+use std::time::Duration;
+use tokio::{
+    select,
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+use tonic::Status;
+
+use crate::{
+    book_summary::{Spread, Ticker},
+    divergence::{Quote, QuotesReceiver},
+    Symbol,
+};
+
+/// The ticker of `quotes`, or `None` without any. Ties on price go to the exchange
+/// quoting the most quantity.
+pub fn ticker(symbol: Symbol, quotes: &[Quote]) -> Option<Ticker> {
+    let bid = quotes.iter().max_by(|a, b| {
+        a.bid
+            .total_cmp(&b.bid)
+            .then(a.bid_quantity.total_cmp(&b.bid_quantity))
+    })?;
+    let ask = quotes.iter().min_by(|a, b| {
+        a.ask
+            .total_cmp(&b.ask)
+            .then(b.ask_quantity.total_cmp(&a.ask_quantity))
+    })?;
+    Some(Ticker {
+        symbol: symbol.to_string(),
+        bid: bid.bid,
+        bid_quantity: bid.bid_quantity,
+        bid_exchange: bid.exchange.to_string(),
+        ask: ask.ask,
+        ask_quantity: ask.ask_quantity,
+        ask_exchange: ask.exchange.to_string(),
+        mid: (bid.bid + ask.ask) / 2.0,
+        spread: ask.ask - bid.bid,
+        timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+    })
+}
+
+/// Sends the ticker of the quotes on `rx_quotes` each time any of it other than the
+/// timestamp changes, so quotes republished as deeper levels change send nothing. Returns
+/// once the quotes end or the client goes away.
+pub async fn watch_ticker(
+    rx_quotes: QuotesReceiver,
+    symbol: Symbol,
+    tx: mpsc::Sender<Result<Ticker, Status>>,
+) {
+    let mut last: Option<Ticker> = None;
+    watch_quotes(rx_quotes, None, tx, |quotes, _| {
+        let ticker = ticker(symbol, quotes)?;
+        let changed = last.as_ref().is_none_or(|last| {
+            Ticker {
+                timestamp_ms: ticker.timestamp_ms,
+                ..last.clone()
+            } != ticker
+        });
+        changed.then(|| {
+            last = Some(ticker.clone());
+            ticker
+        })
+    })
+    .await
+}
+
+/// The spread of the ticker of `quotes`, or `None` without any
+pub fn spread(symbol: Symbol, quotes: &[Quote]) -> Option<Spread> {
+    let ticker = ticker(symbol, quotes)?;
+    Some(Spread {
+        symbol: ticker.symbol,
+        spread: ticker.spread,
+        spread_bps: ticker.spread / ticker.mid * 10_000.0,
+        mid: ticker.mid,
+        timestamp_ms: ticker.timestamp_ms,
+    })
+}
+
+/// Sends the spread of the quotes on `rx_quotes` each time its `spread_bps` moves by
+/// `min_change_bps` from the last one sent, and again each `max_interval` without one
+/// when it isn't zero. Returns once the quotes end or the client goes away.
+pub async fn watch_spread(
+    rx_quotes: QuotesReceiver,
+    symbol: Symbol,
+    min_change_bps: f64,
+    max_interval: Duration,
+    tx: mpsc::Sender<Result<Spread, Status>>,
+) {
+    let heartbeat = (!max_interval.is_zero()).then_some(max_interval);
+    let mut last: Option<f64> = None;
+    watch_quotes(rx_quotes, heartbeat, tx, |quotes, due| {
+        let spread = spread(symbol, quotes)?;
+        let moved = last.is_none_or(|last| {
+            let change = (spread.spread_bps - last).abs();
+            change > 0.0 && change >= min_change_bps
+        });
+        (moved || due).then(|| {
+            last = Some(spread.spread_bps);
+            spread
+        })
+    })
+    .await
+}
+
+/// Sends what `emit` makes of the quotes on `rx_quotes` each time they change, if
+/// anything. With a `heartbeat`, `emit` is also called once that long has passed without
+/// a message, with `due` set. Returns once the quotes end or the client goes away.
+async fn watch_quotes<T>(
+    mut rx_quotes: QuotesReceiver,
+    heartbeat: Option<Duration>,
+    tx: mpsc::Sender<Result<T, Status>>,
+    mut emit: impl FnMut(&[Quote], bool) -> Option<T>,
+) {
+    let mut last_sent = Instant::now();
+    let mut due = false;
+    loop {
+        let quotes = rx_quotes.borrow_and_update().clone();
+        match emit(&quotes, due) {
+            Some(message) => {
+                if tx.send(Ok(message)).await.is_err() {
+                    return;
+                }
+                last_sent = Instant::now();
+            }
+            // Without quotes to send, the next heartbeat is a whole interval on.
+            None if due => last_sent = Instant::now(),
+            None => {}
+        }
+        due = false;
+        let beat = async {
+            match heartbeat {
+                Some(heartbeat) => sleep_until(last_sent + heartbeat).await,
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            changed = rx_quotes.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = beat => due = true,
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Exchange;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    fn quote(exchange: Exchange, bid: [f64; 2], ask: [f64; 2]) -> Quote {
+        Quote {
+            exchange,
+            bid: bid[0],
+            bid_quantity: bid[1],
+            ask: ask[0],
+            ask_quantity: ask[1],
+        }
+    }
+
+    #[test]
+    fn it_takes_each_side_from_the_best_exchange() {
+        let quotes = [
+            quote(Exchange::BITSTAMP, [99.5, 2.0], [100.5, 1.0]),
+            quote(Exchange::BINANCE, [99.75, 0.5], [100.5, 3.0]),
+        ];
+        let ticker = ticker(Symbol::BTCUSDT, &quotes).unwrap();
+        assert_eq!(
+            Ticker {
+                timestamp_ms: 0,
+                ..ticker
+            },
+            Ticker {
+                symbol: "BTCUSDT".to_string(),
+                bid: 99.75,
+                bid_quantity: 0.5,
+                bid_exchange: "BINANCE".to_string(),
+                // Tied on price, binance quotes more.
+                ask: 100.5,
+                ask_quantity: 3.0,
+                ask_exchange: "BINANCE".to_string(),
+                mid: 100.125,
+                spread: 0.75,
+                timestamp_ms: 0,
+            }
+        );
+        assert_eq!(super::ticker(Symbol::BTCUSDT, &[]), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_sends_the_spread_once_it_moves_past_the_threshold() {
+        let quotes =
+            |bid: f64, ask: f64| Arc::new(vec![quote(Exchange::BITSTAMP, [bid, 1.0], [ask, 1.0])]);
+        let (tx_quotes, rx_quotes) = watch::channel(Arc::new(Vec::new()));
+        let (tx, mut rx) = mpsc::channel(10);
+        let handle = tokio::spawn(watch_spread(
+            rx_quotes,
+            Symbol::BTCUSDT,
+            5.0,
+            Duration::from_millis(1000),
+            tx,
+        ));
+        let bps = |rx: &mut mpsc::Receiver<Result<Spread, Status>>| {
+            rx.try_recv()
+                .ok()
+                .map(|spread| spread.unwrap().spread_bps.round())
+        };
+
+        tx_quotes.send_replace(quotes(99.5, 100.5));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(bps(&mut rx), Some(100.0));
+        // 4bps wider isn't enough, 6bps from the last one sent is.
+        tx_quotes.send_replace(quotes(99.48, 100.52));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(bps(&mut rx), None);
+        tx_quotes.send_replace(quotes(99.47, 100.53));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(bps(&mut rx), Some(106.0));
+        tx_quotes.send_replace(quotes(99.46, 100.52));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(bps(&mut rx), None);
+
+        // Quiet for the interval since the last one sent, so it's sent again.
+        tokio::time::sleep(Duration::from_millis(750)).await;
+        assert_eq!(bps(&mut rx), None);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(bps(&mut rx), Some(106.0));
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(bps(&mut rx), Some(106.0));
+        assert_eq!(bps(&mut rx), None);
+
+        drop(tx_quotes);
+        handle.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+}
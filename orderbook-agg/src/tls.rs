@@ -0,0 +1,473 @@
+//! Serves the aggregator over TLS with `--tls-cert` and `--tls-key`, and verifies client
+//! certificates against `--tls-client-ca` for mutual TLS.
+//!
+//! Connections are accepted with openssl rather than tonic's own TLS, so each handshake is
+//! done here and the connection handed to tonic once it's through. A client certificate
+//! that doesn't verify fails the handshake, so the client is refused before any call is
+//! made. With [ClientAuth::Required] clients without a certificate are refused the same
+//! way, [ClientAuth::Optional] serves them without an identity.
+//!
+//! The identity of a verified client is on the [TlsConnectInfo] in the extensions of each
+//! of its requests.
+use anyhow::{Context, Result};
+use openssl::{
+    asn1::Asn1Time,
+    nid::Nid,
+    ssl::{
+        self, AlpnError, ErrorCode, Ssl, SslAcceptor, SslFiletype, SslMethod, SslStream,
+        SslVerifyMode,
+    },
+    x509::{X509Name, X509Ref},
+};
+use std::{
+    future::poll_fn,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll, Waker},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+
+/// How long a client has to finish the handshake once connected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The protocols offered by ALPN, only HTTP/2 which gRPC runs over
+const ALPN_H2: &[u8] = b"\x02h2";
+
+/// Whether clients have to present a certificate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    #[default]
+    Required,
+    Optional,
+}
+
+impl std::str::FromStr for ClientAuth {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "required" => Ok(ClientAuth::Required),
+            "optional" => Ok(ClientAuth::Optional),
+            _ => anyhow::bail!("expected required or optional, got {}", name),
+        }
+    }
+}
+
+/// The certificate the server is served with and the CAs client certificates are checked
+/// against
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, the server's certificate first
+    pub cert: PathBuf,
+    /// PEM private key of the certificate
+    pub key: PathBuf,
+    /// PEM bundle of the CAs that sign client certificates. Clients aren't asked for a
+    /// certificate without one.
+    pub client_ca: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+}
+
+/// The names a verified client certificate was issued to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub common_name: Option<String>,
+    /// The DNS names, email addresses, URIs and IP addresses of its subject alternative
+    /// names
+    pub alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+    fn new(cert: &X509Ref) -> Self {
+        let common_name = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|name| name.to_string());
+        let alt_names = cert
+            .subject_alt_names()
+            .iter()
+            .flatten()
+            .filter_map(|name| {
+                let ip = name.ipaddress().and_then(|ip| match ip.len() {
+                    4 => Some(std::net::IpAddr::from(<[u8; 4]>::try_from(ip).ok()?).to_string()),
+                    16 => Some(std::net::IpAddr::from(<[u8; 16]>::try_from(ip).ok()?).to_string()),
+                    _ => None,
+                });
+                name.dnsname()
+                    .or(name.email())
+                    .or(name.uri())
+                    .map(|name| name.to_string())
+                    .or(ip)
+            })
+            .collect();
+        Self {
+            common_name,
+            alt_names,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.common_name {
+            Some(name) => write!(f, "CN={}", name)?,
+            None => write!(f, "no CN")?,
+        }
+        if !self.alt_names.is_empty() {
+            write!(f, " SAN={}", self.alt_names.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// What's known of the client of a TLS connection, in the extensions of its requests
+#[derive(Debug, Clone, Default)]
+pub struct TlsConnectInfo {
+    pub remote_addr: Option<SocketAddr>,
+    /// Set once the client's certificate has been verified
+    pub client: Option<ClientIdentity>,
+}
+
+/// The peer address of the connection `request` came over, whether or not it's over TLS
+pub fn remote_addr<T>(request: &tonic::Request<T>) -> Option<SocketAddr> {
+    request.remote_addr().or_else(|| {
+        request
+            .extensions()
+            .get::<TlsConnectInfo>()
+            .and_then(|info| info.remote_addr)
+    })
+}
+
+/// The verified identity of the client `request` came from, if it sent a certificate
+pub fn client_identity<T>(request: &tonic::Request<T>) -> Option<&ClientIdentity> {
+    request
+        .extensions()
+        .get::<TlsConnectInfo>()
+        .and_then(|info| info.client.as_ref())
+}
+
+/// Accepts TLS connections with the server's certificate
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: SslAcceptor,
+    not_after: u64,
+}
+
+impl std::fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TlsAcceptor")
+            .field("not_after", &self.not_after)
+            .finish()
+    }
+}
+
+impl TlsAcceptor {
+    pub fn new(config: &TlsConfig) -> Result<Self> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder
+            .set_certificate_chain_file(&config.cert)
+            .with_context(|| format!("failed to load {}", config.cert.display()))?;
+        builder
+            .set_private_key_file(&config.key, SslFiletype::PEM)
+            .with_context(|| format!("failed to load {}", config.key.display()))?;
+        builder
+            .check_private_key()
+            .context("the TLS key isn't the certificate's")?;
+        builder.set_alpn_select_callback(|_, offered| {
+            ssl::select_next_proto(ALPN_H2, offered).ok_or(AlpnError::NOACK)
+        });
+        if let Some(ca) = &config.client_ca {
+            builder
+                .set_ca_file(ca)
+                .with_context(|| format!("failed to load {}", ca.display()))?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(ca)?);
+            builder.set_verify(match config.client_auth {
+                ClientAuth::Required => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+                ClientAuth::Optional => SslVerifyMode::PEER,
+            });
+        }
+        let acceptor = builder.build();
+        let cert = acceptor
+            .context()
+            .certificate()
+            .context("no TLS certificate loaded")?;
+        let not_after = unix_seconds(cert.not_after())?;
+        Ok(Self {
+            acceptor,
+            not_after,
+        })
+    }
+
+    /// When the server's certificate expires, in seconds since the unix epoch
+    pub fn not_after(&self) -> u64 {
+        self.not_after
+    }
+
+    /// Does the handshake with the client connected on `stream` from `remote_addr`
+    pub async fn accept<S>(
+        &self,
+        stream: S,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ssl = Ssl::new(self.acceptor.context())?;
+        let mut stream = TlsStream::new(ssl, stream)?;
+        poll_fn(|cx| stream.handshake(cx, SslStream::accept)).await?;
+        stream.info = TlsConnectInfo {
+            remote_addr,
+            client: stream.peer_identity(),
+        };
+        Ok(stream)
+    }
+
+    /// The connections accepted on `listener` once their handshakes are through, for
+    /// [Server::serve_with_incoming](tonic::transport::Server). Each handshake is done in
+    /// a task of its own so a slow client doesn't hold up the others, and those that fail
+    /// are logged and dropped. Stops accepting once the stream is dropped.
+    pub fn incoming(
+        self,
+        listener: TcpListener,
+    ) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+        let acceptor = Arc::new(self);
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            tracing::warn!("Failed to accept a connection: {}", err);
+                            continue;
+                        }
+                    },
+                    _ = tx.closed() => return,
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let accepted = tokio::time::timeout(
+                        HANDSHAKE_TIMEOUT,
+                        acceptor.accept(stream, Some(addr)),
+                    )
+                    .await;
+                    match accepted {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(err)) => {
+                            tracing::warn!("TLS handshake with {} failed: {:#}", addr, err)
+                        }
+                        Err(_) => tracing::warn!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
+
+/// Does the client side of the handshake on `stream`, with `ssl` set up for the server's
+/// name
+pub async fn connect<S>(ssl: Ssl, stream: S) -> Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = TlsStream::new(ssl, stream)?;
+    poll_fn(|cx| stream.handshake(cx, SslStream::connect)).await?;
+    Ok(stream)
+}
+
+/// Seconds since the unix epoch of `time`
+fn unix_seconds(time: &openssl::asn1::Asn1TimeRef) -> Result<u64> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok((diff.days as i64 * 86_400 + diff.secs as i64).max(0) as u64)
+}
+
+/// A TLS connection over `S`
+#[derive(Debug)]
+pub struct TlsStream<S> {
+    ssl: SslStream<WakerIo<S>>,
+    info: TlsConnectInfo,
+}
+
+/// Lets openssl's blocking reads and writes poll the stream, waking the task of the last
+/// poll once it's ready
+#[derive(Debug)]
+struct WakerIo<S> {
+    stream: S,
+    waker: Option<Waker>,
+}
+
+impl<S: Unpin> WakerIo<S> {
+    fn poll<R>(
+        &mut self,
+        poll: impl FnOnce(Pin<&mut S>, &mut TaskContext) -> Poll<io::Result<R>>,
+    ) -> io::Result<R> {
+        let waker = self.waker.clone().unwrap_or_else(futures::task::noop_waker);
+        let mut cx = TaskContext::from_waker(&waker);
+        match poll(Pin::new(&mut self.stream), &mut cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Read for WakerIo<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        self.poll(|stream, cx| stream.poll_read(cx, &mut buf))?;
+        Ok(buf.filled().len())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for WakerIo<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll(|stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll(|stream, cx| stream.poll_flush(cx))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+    fn new(ssl: Ssl, stream: S) -> Result<Self> {
+        let io = WakerIo {
+            stream,
+            waker: None,
+        };
+        Ok(Self {
+            ssl: SslStream::new(ssl, io)?,
+            info: TlsConnectInfo::default(),
+        })
+    }
+
+    /// The verified identity of the other side, if it sent a certificate
+    fn peer_identity(&self) -> Option<ClientIdentity> {
+        let cert = self.ssl.ssl().peer_certificate()?;
+        Some(ClientIdentity::new(&cert))
+    }
+
+    fn handshake(
+        &mut self,
+        cx: &mut TaskContext,
+        step: fn(&mut SslStream<WakerIo<S>>) -> Result<(), ssl::Error>,
+    ) -> Poll<io::Result<()>> {
+        self.ssl.get_mut().waker = Some(cx.waker().clone());
+        match step(&mut self.ssl) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(err) if matches!(err.code(), ErrorCode::WANT_READ | ErrorCode::WANT_WRITE) => {
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(io::Error::other(err))),
+        }
+    }
+
+    /// Runs `io` on the connection, pending while the stream under it would block
+    fn poll_io<R>(
+        &mut self,
+        cx: &mut TaskContext,
+        io: impl FnOnce(&mut SslStream<WakerIo<S>>) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        self.ssl.get_mut().waker = Some(cx.waker().clone());
+        match io(&mut self.ssl) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, |ssl| {
+            let read = ssl.read(buf.initialize_unfilled())?;
+            buf.advance(read);
+            Ok(())
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_io(cx, |ssl| ssl.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, |ssl| ssl.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.ssl.get_mut().waker = Some(cx.waker().clone());
+        match this.ssl.shutdown() {
+            Ok(_) => {}
+            Err(err) if err.code() == ErrorCode::ZERO_RETURN => {}
+            Err(err) if matches!(err.code(), ErrorCode::WANT_READ | ErrorCode::WANT_WRITE) => {
+                return Poll::Pending
+            }
+            // The other side may have gone already, which leaves nothing to shut down.
+            Err(_) => {}
+        }
+        Pin::new(&mut this.ssl.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected for TlsStream<TcpStream> {
+    type ConnectInfo = TlsConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.info.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_client_auth_modes() {
+        assert_eq!(
+            "optional".parse::<ClientAuth>().unwrap(),
+            ClientAuth::Optional
+        );
+        assert_eq!(
+            "required".parse::<ClientAuth>().unwrap(),
+            ClientAuth::Required
+        );
+        assert!("none".parse::<ClientAuth>().is_err());
+    }
+
+    #[test]
+    fn it_describes_client_identities() {
+        let identity = ClientIdentity {
+            common_name: Some("pricing".to_string()),
+            alt_names: vec!["pricing.internal".to_string(), "10.0.0.7".to_string()],
+        };
+        assert_eq!(
+            identity.to_string(),
+            "CN=pricing SAN=pricing.internal,10.0.0.7"
+        );
+        assert_eq!(ClientIdentity::default().to_string(), "no CN");
+    }
+}
@@ -0,0 +1,211 @@
+//! Trades of each exchange merged into one feed a symbol, served by `WatchTrades`.
+//!
+//! Each exchange's trade stream runs on its own connection next to the order book's,
+//! reconnecting with [spawn_exchange_with_stats] like the books do but never touching
+//! them. The trades are sequenced in the order they arrive and broadcast to every client,
+//! each filtering them down to what it asked for.
+use std::sync::Arc;
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+};
+use tonic::Status;
+
+use crate::{
+    book_summary::Trade,
+    core::{http::HttpClient, stats::ExchangeStats},
+    exchanges::{connector, StreamArgs},
+    service::{spawn_exchange_with_stats, Backoff, ExchangeClosed, ExchangeOptions},
+    Exchange, Symbol,
+};
+
+/// Trades kept for clients that fall behind before they start missing them
+const CAPACITY: usize = 1024;
+
+/// The merged trades of a symbol. Clones share the same feed.
+#[derive(Debug, Clone)]
+pub struct TradeFeed {
+    pub symbol: Symbol,
+    pub exchanges: Vec<Exchange>,
+    tx: broadcast::Sender<Arc<Trade>>,
+}
+
+impl TradeFeed {
+    /// Broadcasts the trades received on `rx_trades`, numbering them from 1 in the order
+    /// they arrive.
+    pub fn new(
+        symbol: Symbol,
+        exchanges: Vec<Exchange>,
+        mut rx_trades: mpsc::Receiver<Trade>,
+    ) -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        let tx_trades = tx.clone();
+        tokio::spawn(async move {
+            let mut sequence = 0;
+            while let Some(trade) = rx_trades.recv().await {
+                sequence += 1;
+                // There's no one to send to until a client subscribes.
+                let _ = tx_trades.send(Arc::new(Trade { sequence, ..trade }));
+            }
+            tracing::info!("{} trades ended", symbol);
+        });
+        Self {
+            symbol,
+            exchanges,
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Trade>> {
+        self.tx.subscribe()
+    }
+}
+
+/// Streams the trades of `symbol` from each of the exchanges in `options`, reconnecting
+/// like the order books do.
+pub fn start_trades(http: HttpClient, options: &ExchangeOptions, symbol: Symbol) -> TradeFeed {
+    let (tx_trades, rx_trades) = mpsc::channel::<Trade>(100);
+    let (tx_closed, mut rx_closed) = mpsc::channel::<ExchangeClosed>(10);
+    for &exchange in options.exchanges.iter() {
+        let connector = match connector(exchange) {
+            Ok(connector) => connector,
+            Err(err) => {
+                tracing::error!("{} {} trades not streamed: {:#}", exchange, symbol, err);
+                continue;
+            }
+        };
+        let tx_trades = tx_trades.clone();
+        let http = http.clone();
+        let endpoints = options
+            .endpoints
+            .get(&exchange)
+            .cloned()
+            .unwrap_or_else(|| connector.default_endpoints());
+        let rx_enabled = options.switches.subscribe(exchange);
+        // Counted apart from the book's stats, which report the book's connection.
+        let stats = Arc::new(ExchangeStats::new(exchange, symbol));
+        let options = options.clone();
+        spawn_exchange_with_stats(
+            exchange,
+            symbol,
+            Backoff::default(),
+            rx_enabled,
+            tx_closed.clone(),
+            stats.clone(),
+            move || {
+                let args = StreamArgs {
+                    http: http.clone(),
+                    endpoints: endpoints.clone(),
+                    symbol,
+                    stats: stats.clone(),
+                };
+                connector.stream_trades(args, &options, tx_trades.clone())
+            },
+        );
+    }
+    // Clients are told nothing of closed trade streams, the trades just stop.
+    tokio::spawn(async move { while rx_closed.recv().await.is_some() {} });
+    TradeFeed::new(symbol, options.exchanges.clone(), rx_trades)
+}
+
+/// Sends the trades on `rx` of `exchanges`, or of every exchange when empty, of at least
+/// `min_quantity`. Trades dropped while the client was behind are skipped, leaving a gap
+/// in the sequence. Returns once the feed ends or the client goes away.
+pub async fn forward_trades(
+    mut rx: broadcast::Receiver<Arc<Trade>>,
+    exchanges: Vec<Exchange>,
+    min_quantity: f64,
+    tx: mpsc::Sender<Result<Trade, Status>>,
+) {
+    let exchanges = exchanges
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    loop {
+        let trade = select! {
+            trade = rx.recv() => trade,
+            _ = tx.closed() => return,
+        };
+        let trade = match trade {
+            Ok(trade) => trade,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("client fell behind, skipped {} trades", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if (exchanges.is_empty() || exchanges.contains(&trade.exchange))
+            && trade.quantity >= min_quantity
+            && tx.send(Ok(Trade::clone(&trade))).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(exchange: Exchange, quantity: f64) -> Trade {
+        Trade {
+            exchange: exchange.to_string(),
+            symbol: Symbol::BTCUSDT.to_string(),
+            price: 30000.0,
+            quantity,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_sequences_trades_in_the_order_they_arrive() {
+        let (tx_trades, rx_trades) = mpsc::channel(10);
+        let feed = TradeFeed::new(
+            Symbol::BTCUSDT,
+            vec![Exchange::BINANCE, Exchange::BITSTAMP],
+            rx_trades,
+        );
+        let mut rx = feed.subscribe();
+        for exchange in [Exchange::BITSTAMP, Exchange::BINANCE, Exchange::BITSTAMP] {
+            tx_trades.send(trade(exchange, 1.0)).await.unwrap();
+        }
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let trade = rx.recv().await.unwrap();
+            seen.push((trade.exchange.clone(), trade.sequence));
+        }
+        assert_eq!(
+            seen,
+            [
+                ("BITSTAMP".to_string(), 1),
+                ("BINANCE".to_string(), 2),
+                ("BITSTAMP".to_string(), 3),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_forwards_the_trades_asked_for() {
+        let (tx_feed, rx_feed) = broadcast::channel(10);
+        let (tx, mut rx) = mpsc::channel(10);
+        let forward = tokio::spawn(forward_trades(rx_feed, vec![Exchange::BINANCE], 0.5, tx));
+        for (exchange, quantity) in [
+            (Exchange::BINANCE, 0.25),
+            (Exchange::BITSTAMP, 2.0),
+            (Exchange::BINANCE, 0.5),
+            (Exchange::BINANCE, 3.0),
+        ] {
+            tx_feed.send(Arc::new(trade(exchange, quantity))).unwrap();
+        }
+        drop(tx_feed);
+        forward.await.unwrap();
+
+        let mut quantities = Vec::new();
+        while let Some(trade) = rx.recv().await {
+            let trade = trade.unwrap();
+            assert_eq!(trade.exchange, "BINANCE");
+            quantities.push(trade.quantity);
+        }
+        assert_eq!(quantities, [0.5, 3.0]);
+    }
+}
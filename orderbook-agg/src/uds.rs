@@ -0,0 +1,150 @@
+//! Serves clients on a unix domain socket with `--uds-path`, for consumers on the same
+//! host that don't need TCP.
+//!
+//! A socket file left behind by a server that didn't shut down cleanly is removed before
+//! binding, while one a running server is still listening on is never taken over. The file
+//! is given `--uds-mode` permissions once bound and removed again when the [SocketFile]
+//! is dropped. Calls over the socket have no peer address, so `--unknown-peers` decides
+//! whether they're served.
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+
+/// Permissions of the socket file, parsed from octal like 660
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketMode(pub u32);
+
+impl Default for SocketMode {
+    /// Read and write for the owner and group
+    fn default() -> Self {
+        Self(0o660)
+    }
+}
+
+impl std::str::FromStr for SocketMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        let bits = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .with_context(|| format!("expected an octal mode like 660, got {}", mode))?;
+        ensure!(
+            bits <= 0o777,
+            "expected an octal mode like 660, got {}",
+            mode
+        );
+        Ok(Self(bits))
+    }
+}
+
+impl std::fmt::Display for SocketMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:03o}", self.0)
+    }
+}
+
+/// Removes the socket file when dropped
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+impl SocketFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => tracing::info!("Removed {}", self.path.display()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!("Failed to remove {}: {}", self.path.display(), err),
+        }
+    }
+}
+
+/// Listens on a socket at `path` with `mode` permissions, returning the connections for
+/// [Server::serve_with_incoming](tonic::transport::Server) and the file to remove once
+/// the server stops.
+pub fn bind(path: &Path, mode: SocketMode) -> Result<(UnixListenerStream, SocketFile)> {
+    remove_stale(path)?;
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    let file = SocketFile {
+        path: path.to_path_buf(),
+    };
+    fs::set_permissions(path, Permissions::from_mode(mode.0))
+        .with_context(|| format!("failed to set the mode of {}", path.display()))?;
+    Ok((UnixListenerStream::new(listener), file))
+}
+
+/// Removes the socket at `path` unless a server is listening on it. Anything else at the
+/// path is left alone.
+fn remove_stale(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    if !metadata.file_type().is_socket() {
+        bail!("{} exists and isn't a socket", path.display());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("a server is already listening on {}", path.display());
+    }
+    tracing::info!("Removing the stale socket {}", path.display());
+    fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("obagg-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn it_parses_octal_modes() {
+        assert_eq!("660".parse::<SocketMode>().unwrap(), SocketMode(0o660));
+        assert_eq!("0o600".parse::<SocketMode>().unwrap(), SocketMode(0o600));
+        assert_eq!(SocketMode(0o600).to_string(), "600");
+        assert!("680".parse::<SocketMode>().is_err());
+        assert!("1777".parse::<SocketMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn it_replaces_stale_sockets_and_removes_its_own() {
+        let path = socket_path("stale");
+        // A socket nothing listens on, as a server that was killed leaves behind.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let (incoming, file) = bind(&path, SocketMode(0o600)).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The socket is in use now, so it isn't taken over.
+        assert!(bind(&path, SocketMode::default()).is_err());
+        assert!(path.exists());
+
+        drop(incoming);
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn it_leaves_files_that_arent_sockets() {
+        let path = socket_path("regular");
+        fs::write(&path, "data").unwrap();
+        let err = remove_stale(&path).unwrap_err();
+        assert!(err.to_string().contains("isn't a socket"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(&path).unwrap();
+    }
+}
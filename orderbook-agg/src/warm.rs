@@ -0,0 +1,252 @@
+//! Each exchange's book saved when the server shuts down, so a server started with
+//! `--restore` has something to serve while the books are fetched from the exchanges
+//! again.
+//!
+//! The summary task serves restored levels flagged [restored](BookLevels::restored),
+//! listing their exchanges as stale on the summaries until each sends a book of its own.
+//! Every exchange's book is still rebuilt from a new snapshot. Only the top levels are
+//! saved rather than the whole book, so updates can't be applied on top of them even when
+//! they follow on from the saved `last_update_id`.
+//!
+//! Files are json with a [VERSION]. Files that can't be read, or were written by another
+//! version, are ignored with a warning.
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc};
+
+use crate::{book::Books, book_summary::Level, core::order_book::BookLevels, Exchange, Symbol};
+
+/// The version of the files written, bumped whenever their layout changes
+pub const VERSION: u32 = 1;
+
+/// The top levels of an exchange's book when it was saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmBook {
+    pub exchange: Exchange,
+    pub symbol: Symbol,
+    /// The last update applied to the book
+    pub last_update_id: u64,
+    /// The price and quantity of each level, best first
+    pub bids: Vec<[f64; 2]>,
+    pub asks: Vec<[f64; 2]>,
+}
+
+impl WarmBook {
+    fn new(levels: &BookLevels) -> Self {
+        let amounts = |levels: &[Level]| {
+            levels
+                .iter()
+                .map(|level| [level.price, level.quantity])
+                .collect()
+        };
+        Self {
+            exchange: levels.exchange,
+            symbol: levels.symbol,
+            last_update_id: levels.last_update_id,
+            bids: amounts(&levels.bids),
+            asks: amounts(&levels.asks),
+        }
+    }
+
+    /// The levels of the book, flagged as restored
+    pub fn book_levels(&self) -> BookLevels {
+        let levels = |amounts: &[[f64; 2]]| {
+            amounts
+                .iter()
+                .map(|&[price, quantity]| Level {
+                    exchange: self.exchange.to_string(),
+                    price,
+                    quantity,
+                    updated_at: None,
+                    market_type: self.exchange.market_type().into(),
+                })
+                .collect()
+        };
+        BookLevels {
+            exchange: self.exchange,
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: levels(&self.bids),
+            asks: levels(&self.asks),
+            depth: None,
+            restored: true,
+        }
+    }
+}
+
+/// The books saved by a server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmState {
+    pub version: u32,
+    /// When the books were saved, in milliseconds since the unix epoch
+    pub saved_ms: u64,
+    pub books: Vec<WarmBook>,
+}
+
+impl WarmState {
+    /// The books of `symbols` published on `books` now, restored ones included so a
+    /// server stopped before the exchanges sent new books keeps them.
+    pub fn new(books: &Books, symbols: &[Symbol]) -> Self {
+        let mut saved = Vec::new();
+        for &symbol in symbols {
+            let published = books.subscribe(symbol).borrow().clone();
+            let mut exchanges = published.keys().copied().collect::<Vec<_>>();
+            exchanges.sort_by_key(|exchange| exchange.to_string());
+            saved.extend(
+                exchanges
+                    .iter()
+                    .map(|exchange| WarmBook::new(&published[exchange].levels)),
+            );
+        }
+        Self {
+            version: VERSION,
+            saved_ms: chrono::Utc::now().timestamp_millis() as u64,
+            books: saved,
+        }
+    }
+
+    /// Writes the books to `path`, replacing it whole so a server stopped part way
+    /// through never leaves half a file behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_vec(self)?)
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        tracing::info!("Saved {} books to {}", self.books.len(), path.display());
+        Ok(())
+    }
+
+    /// The books saved to `path`, failing if it can't be read or holds another version
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let state: Self = serde_json::from_slice(&bytes)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        ensure!(
+            state.version == VERSION,
+            "{} is version {}, expected {}",
+            path.display(),
+            state.version,
+            VERSION
+        );
+        Ok(state)
+    }
+
+    /// The books saved to `path`, or none with a warning if they can't be restored
+    pub fn load(path: &Path) -> Option<Self> {
+        match Self::read(path) {
+            Ok(state) => {
+                tracing::info!(
+                    "Restoring {} books saved to {} at {}",
+                    state.books.len(),
+                    path.display(),
+                    state.saved_ms
+                );
+                Some(state)
+            }
+            Err(err) => {
+                tracing::warn!("Not restoring books: {:#}", err);
+                None
+            }
+        }
+    }
+
+    /// The levels of each book, flagged as restored
+    pub fn book_levels(&self) -> Vec<Arc<BookLevels>> {
+        self.books
+            .iter()
+            .map(|book| Arc::new(book.book_levels()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::PublishedBook;
+    use std::{collections::HashMap, path::PathBuf};
+
+    fn state_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("obagg-{}-{}.json", name, std::process::id()))
+    }
+
+    fn levels(exchange: Exchange, last_update_id: u64, bids: &[[f64; 2]]) -> BookLevels {
+        WarmBook {
+            exchange,
+            symbol: Symbol::BTCUSDT,
+            last_update_id,
+            bids: bids.to_vec(),
+            asks: vec![[30001.0, 1.5], [30002.5, 0.25]],
+        }
+        .book_levels()
+    }
+
+    #[test]
+    fn it_restores_the_books_it_saved() {
+        let books = Books::default();
+        let published = [
+            levels(Exchange::BITSTAMP, 7, &[[29999.0, 2.0], [29998.0, 0.125]]),
+            levels(Exchange::BINANCE, 912, &[[29999.5, 4.0]]),
+        ];
+        books.publish(
+            Symbol::BTCUSDT,
+            published
+                .iter()
+                .map(|levels| {
+                    let levels = BookLevels {
+                        restored: false,
+                        ..levels.clone()
+                    };
+                    let book = PublishedBook {
+                        connection: 2,
+                        levels: Arc::new(levels),
+                    };
+                    (book.levels.exchange, book)
+                })
+                .collect::<HashMap<_, _>>(),
+        );
+
+        let path = state_file("warm");
+        let saved = WarmState::new(&books, &[Symbol::BTCUSDT, Symbol::ETHBTC]);
+        saved.save(&path).unwrap();
+        let restored = WarmState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored, saved);
+
+        let mut restored = restored.book_levels();
+        restored.sort_by_key(|levels| levels.last_update_id);
+        assert_eq!(restored.len(), 2);
+        for (restored, published) in restored.iter().zip(published.iter()) {
+            assert!(restored.restored);
+            assert_eq!(restored.exchange, published.exchange);
+            assert_eq!(restored.last_update_id, published.last_update_id);
+            assert_eq!(restored.bids, published.bids);
+            assert_eq!(restored.asks, published.asks);
+        }
+    }
+
+    #[test]
+    fn it_ignores_corrupt_and_other_version_files() {
+        let path = state_file("warm-corrupt");
+        std::fs::write(&path, b"{\"version\":1,\"books\":[").unwrap();
+        assert!(WarmState::load(&path).is_none());
+
+        let other = WarmState {
+            version: VERSION + 1,
+            saved_ms: 0,
+            books: Vec::new(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&other).unwrap()).unwrap();
+        let err = WarmState::read(&path).unwrap_err();
+        assert!(
+            err.to_string().ends_with("is version 2, expected 1"),
+            "{}",
+            err
+        );
+        assert!(WarmState::load(&path).is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(WarmState::load(&path).is_none());
+    }
+}
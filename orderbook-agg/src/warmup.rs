@@ -0,0 +1,122 @@
+//! Starts the summary task of each symbol given with `--warm-symbols` before the server
+//! reports itself healthy, so the first client to watch one gets a book that's already live
+//! rather than waiting on the exchanges' snapshots and subscriptions itself.
+//!
+//! Symbols are started a few at a time with `--warm-concurrency`, the next only once one
+//! before it has its first summary, so a long list doesn't trip the exchanges' rate limits.
+//! A symbol that has no summary within [FIRST_SUMMARY] is served anyway, its books carrying
+//! on in the background. Warm symbols run for as long as the server does, whether or not
+//! anyone is watching them, and their books are resynced like any other.
+use futures::StreamExt;
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    service::{SummaryReceiver, SummarySubscriber},
+    sinks::subscribe,
+    Symbol,
+};
+
+/// How long each symbol is given for its first summary
+pub const FIRST_SUMMARY: Duration = Duration::from_secs(30);
+
+/// Starts each of `symbols` with `start`, at most `concurrency` waiting on their first
+/// summary at a time, returning their subscribers once every one has a summary or has
+/// timed out.
+pub async fn warm_up<F>(
+    symbols: &[Symbol],
+    concurrency: usize,
+    start: F,
+) -> HashMap<Symbol, SummarySubscriber>
+where
+    F: Fn(Symbol) -> SummarySubscriber,
+{
+    let start = &start;
+    futures::stream::iter(symbols.iter().copied())
+        .map(|symbol| async move {
+            let tx_summary = start(symbol);
+            match tokio::time::timeout(FIRST_SUMMARY, first_summary(&tx_summary)).await {
+                Ok(true) => tracing::info!("Warmed up {}", symbol),
+                Ok(false) => tracing::warn!("Summary task of {} stopped warming up", symbol),
+                Err(_) => tracing::warn!(
+                    "No summary of {} within {:?}, serving it anyway",
+                    symbol,
+                    FIRST_SUMMARY
+                ),
+            }
+            (symbol, tx_summary)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Waits for a summary with levels from the summary task behind `tx_summary`, false if
+/// the task stops first
+async fn first_summary(tx_summary: &SummarySubscriber) -> bool {
+    let Ok(mut rx_summary): Result<SummaryReceiver, _> = subscribe(tx_summary).await else {
+        return false;
+    };
+    let warm = rx_summary
+        .wait_for(|summary| {
+            summary
+                .as_ref()
+                .is_ok_and(|summary| !summary.bids.is_empty() || !summary.asks.is_empty())
+        })
+        .await
+        .is_ok();
+    warm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_summary::{Level, Summary};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::sync::{mpsc, oneshot, watch};
+
+    /// A summary task that publishes a summary with a level after `delay`, counting how
+    /// many are warming up at once in `running`
+    fn spawn_task(
+        delay: Duration,
+        running: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    ) -> SummarySubscriber {
+        let (tx, mut rx) = mpsc::channel::<oneshot::Sender<SummaryReceiver>>(1);
+        let (tx_summary, rx_summary) = watch::channel(Ok(Arc::new(Summary::default())));
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(now, Ordering::SeqCst);
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            let summary = Summary {
+                bids: vec![Level::default()],
+                ..Default::default()
+            };
+            let _ = tx_summary.send_replace(Ok(Arc::new(summary)));
+            while let Some(tx) = rx.recv().await {
+                let _ = tx.send(rx_summary.clone());
+            }
+        });
+        tx
+    }
+
+    #[tokio::test]
+    async fn it_warms_up_a_few_symbols_at_a_time() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let symbols = [Symbol::BTCUSDT, Symbol::BTCUSD, Symbol::ETHBTC];
+        let warm = warm_up(&symbols, 2, |_| {
+            spawn_task(Duration::from_millis(50), running.clone(), most.clone())
+        })
+        .await;
+        assert_eq!(warm.len(), 3);
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        for tx_summary in warm.values() {
+            let rx_summary = subscribe(tx_summary).await.unwrap();
+            assert_eq!(rx_summary.borrow().as_ref().unwrap().bids.len(), 1);
+        }
+    }
+}
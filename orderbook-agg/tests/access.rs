@@ -0,0 +1,107 @@
+//! Serves the aggregator behind the peer filter and calls it from allowed and refused
+//! addresses.
+use std::net::SocketAddr;
+
+use orderbook_agg::{
+    access::{parse_nets, PeerFilter, PeerRules, UnknownPeers},
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, Empty,
+    },
+    service::OrderbookSummary,
+    Exchange,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+use tonic::{
+    service::interceptor::InterceptedService,
+    transport::{server::TcpIncoming, Server},
+    Code,
+};
+
+mod support;
+use support::duplex_channel;
+
+fn rules(allow: &[&str], deny: &[&str], unknown: UnknownPeers) -> PeerRules {
+    PeerRules {
+        allow: parse_nets(allow).unwrap(),
+        deny: parse_nets(deny).unwrap(),
+        unknown,
+    }
+}
+
+/// An aggregator whose summary task isn't running, for the rpcs that don't need one
+fn stopped() -> OrderbookAggregatorServer<OrderbookSummary> {
+    let (tx_summary, _) = mpsc::channel(1);
+    let service = OrderbookSummary::new(tx_summary, watch::channel(false).1)
+        .with_exchanges(vec![Exchange::BINANCE]);
+    OrderbookAggregatorServer::new(service)
+}
+
+/// Serves the aggregator behind `peers` on a free port of `ip`, returning its url.
+async fn serve(ip: &str, peers: PeerFilter) -> String {
+    let listener = TcpListener::bind((ip, 0)).await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .layer(tonic::service::interceptor(peers))
+            .add_service(stopped())
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap()),
+    );
+    format!("http://{}", addr)
+}
+
+async fn get_exchanges(url: String) -> Result<(), Code> {
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    client
+        .get_exchanges(Empty {})
+        .await
+        .map(|_| ())
+        .map_err(|status| status.code())
+}
+
+#[tokio::test]
+async fn it_serves_allowed_peers_and_refuses_the_rest() {
+    let peers = PeerFilter::new(rules(&["127.0.0.0/8"], &[], UnknownPeers::Deny));
+    let url = serve("127.0.0.1", peers.clone()).await;
+    assert_eq!(get_exchanges(url.clone()).await, Ok(()));
+
+    // Changing the rules, as a reload does, applies from the next call.
+    peers.set(rules(&["127.0.0.0/8"], &["127.0.0.1"], UnknownPeers::Deny));
+    assert_eq!(
+        get_exchanges(url.clone()).await,
+        Err(Code::PermissionDenied)
+    );
+    peers.set(rules(&["10.0.0.0/8"], &[], UnknownPeers::Allow));
+    assert_eq!(get_exchanges(url).await, Err(Code::PermissionDenied));
+}
+
+#[tokio::test]
+async fn it_checks_ipv6_peers_against_ipv6_ranges() {
+    // Hosts without IPv6 loopback can't run this.
+    if std::net::TcpListener::bind("[::1]:0").is_err() {
+        return;
+    }
+    let peers = PeerFilter::new(rules(&["::1/128"], &[], UnknownPeers::Deny));
+    let url = serve("::1", peers.clone()).await;
+    assert_eq!(get_exchanges(url.clone()).await, Ok(()));
+    peers.set(rules(&["fd00::/8"], &[], UnknownPeers::Allow));
+    assert_eq!(get_exchanges(url).await, Err(Code::PermissionDenied));
+}
+
+#[tokio::test]
+async fn it_serves_peers_without_an_address_by_the_policy() {
+    for (unknown, expected) in [
+        (UnknownPeers::Allow, Ok(())),
+        (UnknownPeers::Deny, Err(Code::PermissionDenied)),
+    ] {
+        // Calls over in-memory pipes have no peer address.
+        let peers = PeerFilter::new(rules(&["127.0.0.0/8"], &[], unknown));
+        let router = Server::builder().add_service(InterceptedService::new(stopped(), peers));
+        let mut client = OrderbookAggregatorClient::new(duplex_channel(router).await);
+        let result = client.get_exchanges(Empty {}).await;
+        assert_eq!(result.map(|_| ()).map_err(|status| status.code()), expected);
+    }
+}
@@ -0,0 +1,396 @@
+//! Lists and disconnects client streams, switches exchanges and taps updates through the
+//! admin service.
+use std::time::Duration;
+
+use orderbook_agg::{
+    admin::AdminService,
+    book_summary::{
+        orderbook_admin_client::OrderbookAdminClient,
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, DisconnectStreamRequest, Empty,
+        SetExchangeEnabledRequest, Summary, TapUpdatesRequest, TappedUpdate, UpdateOutcome,
+        WatchSummaryRequest,
+    },
+    service::ExchangeOptions,
+    streams::StreamRegistry,
+    Exchange, Symbol,
+};
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+use tonic::{transport::Server, Code, Request, Status, Streaming};
+
+mod support;
+use support::{duplex_channel, MockExchange, Step};
+
+const TOKEN: &str = "admin-secret";
+
+fn admin_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", TOKEN).parse().unwrap(),
+    );
+    request
+}
+
+async fn next(stream: &mut Streaming<Summary>) -> Option<Result<Summary, Status>> {
+    timeout(Duration::from_secs(10), stream.next())
+        .await
+        .expect("no summary in time")
+}
+
+#[tokio::test]
+async fn it_disconnects_one_stream_and_leaves_the_other() {
+    // The book changes after the disconnect, for the stream kept to be sent.
+    let bitstamp = MockExchange::bitstamp(vec![vec![
+        Step::Wait(Duration::from_secs(2)),
+        Step::json(serde_json::json!({
+            "data": {
+                "microtimestamp": "2",
+                "bids": [["30000.50", "0.50000000"]],
+                "asks": [],
+            },
+            "event": "data",
+        })),
+        Step::Hold,
+    ]])
+    .await;
+    let streams = StreamRegistry::new();
+    let service =
+        support::aggregate(&[(Exchange::BITSTAMP, &bitstamp)]).with_streams(streams.clone());
+    let channel = duplex_channel(
+        Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .add_service(AdminService::new(streams).with_token(TOKEN)),
+    )
+    .await;
+    let mut client = OrderbookAggregatorClient::new(channel.clone());
+    let mut admin = OrderbookAdminClient::new(channel);
+
+    let mut kept = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut kicked = client
+        .watch_summary(WatchSummaryRequest {
+            levels: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    next(&mut kept).await.unwrap().unwrap();
+    next(&mut kicked).await.unwrap().unwrap();
+
+    let status = admin.list_streams(Empty {}).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let listed = admin
+        .list_streams(admin_request(Empty {}))
+        .await
+        .unwrap()
+        .into_inner()
+        .streams;
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed[0].symbol, "BTCUSDT");
+    let id = listed
+        .iter()
+        .find(|stream| stream.request.as_ref().unwrap().levels == 1)
+        .unwrap()
+        .id;
+    admin
+        .disconnect_stream(admin_request(DisconnectStreamRequest { id }))
+        .await
+        .unwrap();
+
+    // The summary already waiting may come first.
+    let status = loop {
+        match next(&mut kicked)
+            .await
+            .expect("stream ended without a status")
+        {
+            Ok(_) => continue,
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), Code::Cancelled);
+    assert!(next(&mut kicked).await.is_none());
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let summary = next(&mut kept).await.unwrap().unwrap();
+            if summary.bids[0].price == 30000.5 {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("the stream kept stopped getting summaries");
+    let listed = timeout(Duration::from_secs(5), async {
+        loop {
+            let listed = admin
+                .list_streams(admin_request(Empty {}))
+                .await
+                .unwrap()
+                .into_inner()
+                .streams;
+            if listed.len() == 1 {
+                return listed;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_ne!(listed[0].id, id);
+    let status = admin
+        .disconnect_stream(admin_request(DisconnectStreamRequest { id }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+fn exchanges(summary: &Summary) -> Vec<&str> {
+    let mut exchanges = summary
+        .bids
+        .iter()
+        .chain(summary.asks.iter())
+        .map(|level| level.exchange.as_str())
+        .collect::<Vec<_>>();
+    exchanges.sort();
+    exchanges.dedup();
+    exchanges
+}
+
+/// Reads summaries until one has levels from just `expected`.
+async fn until_exchanges(stream: &mut Streaming<Summary>, expected: &[&str]) -> Summary {
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let summary = next(stream).await.unwrap().unwrap();
+            if exchanges(&summary) == expected {
+                return summary;
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("no summary from just {:?}", expected))
+}
+
+#[tokio::test]
+async fn it_switches_an_exchange_off_and_on_mid_stream() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    let (service, switches) = support::aggregate_switched(&[
+        (Exchange::BINANCE, &binance),
+        (Exchange::BITSTAMP, &bitstamp),
+    ]);
+    let channel = duplex_channel(
+        Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .add_service(
+                AdminService::new(StreamRegistry::new())
+                    .with_switches(switches)
+                    .with_token(TOKEN),
+            ),
+    )
+    .await;
+    let mut client = OrderbookAggregatorClient::new(channel.clone());
+    let mut admin = OrderbookAdminClient::new(channel);
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    until_exchanges(&mut stream, &["BINANCE", "BITSTAMP"]).await;
+
+    let set = |exchange: &str, enabled| {
+        admin_request(SetExchangeEnabledRequest {
+            exchange: exchange.to_string(),
+            enabled,
+        })
+    };
+    let response = admin
+        .set_exchange_enabled(set("binance", false))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.changed);
+    assert_eq!(response.changes, 1);
+    let summary = until_exchanges(&mut stream, &["BITSTAMP"]).await;
+    assert!(!summary.degraded, "{:?}", summary.unavailable_exchanges);
+    let listed = client.get_exchanges(Empty {}).await.unwrap().into_inner();
+    assert_eq!(listed.disabled, ["BINANCE"]);
+    let again = admin
+        .set_exchange_enabled(set("binance", false))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!again.changed);
+
+    admin
+        .set_exchange_enabled(set("binance", true))
+        .await
+        .unwrap();
+    until_exchanges(&mut stream, &["BINANCE", "BITSTAMP"]).await;
+    // Enabling took a new snapshot and subscription.
+    assert_eq!(binance.subscriptions().len(), 2);
+    let listed = client.get_exchanges(Empty {}).await.unwrap().into_inner();
+    assert!(listed.disabled.is_empty());
+
+    let status = admin
+        .set_exchange_enabled(set("binance_futures", false))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = admin
+        .set_exchange_enabled(set("kraken", false))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+fn binance_update(first: u64, last: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Step {
+    Step::json(serde_json::json!({
+        "e": "depthUpdate",
+        "E": 1688515101262u64,
+        "s": "BTCUSDT",
+        "U": first,
+        "u": last,
+        "b": bids,
+        "a": asks,
+    }))
+}
+
+#[tokio::test]
+async fn it_taps_each_update_as_it_is_applied_to_the_book() {
+    let binance = MockExchange::binance(vec![vec![
+        binance_update(101, 101, &[("30000.50000000", "0.50000000")], &[]),
+        // Time for the snapshot to move on before the gap is found.
+        Step::Wait(Duration::from_secs(1)),
+        // After a gap from the update before, so the book is resynced from a fresh
+        // snapshot, which it follows on from.
+        binance_update(105, 106, &[("29990.00000000", "1.00000000")], &[]),
+        // Already in the book, so it's skipped.
+        binance_update(103, 103, &[("29980.00000000", "1.00000000")], &[]),
+        binance_update(107, 107, &[], &[("30001.00000000", "0.00000000")]),
+        Step::Hold,
+    ]])
+    .await;
+    let options = ExchangeOptions::default();
+    let stats = options.stats.clone();
+    // Tapped before the book is started, for the snapshot to be tapped too.
+    let mut admin = OrderbookAdminClient::new(
+        duplex_channel(
+            Server::builder().add_service(
+                AdminService::new(StreamRegistry::new())
+                    .with_stats(stats.clone())
+                    .with_token(TOKEN),
+            ),
+        )
+        .await,
+    );
+    let mut taps = admin
+        .tap_updates(admin_request(TapUpdatesRequest {
+            symbol: "btcusdt".to_string(),
+            exchanges: vec!["binance".to_string()],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let (service, _) = support::aggregate_with(&[(Exchange::BINANCE, &binance)], options);
+    let url = support::serve(service).await;
+
+    let mut tapped = Vec::<TappedUpdate>::new();
+    while tapped.len() < 6 {
+        if tapped.len() == 2 {
+            binance.set_rest(
+                "depth",
+                r#"{"lastUpdateId":104,"bids":[["30000.00000000","1.00000000"]],"asks":[["30001.00000000","1.00000000"],["30002.00000000","2.00000000"]]}"#,
+            );
+        }
+        let update = timeout(Duration::from_secs(10), taps.next())
+            .await
+            .expect("no update tapped in time")
+            .unwrap()
+            .unwrap();
+        tapped.push(update);
+    }
+    let ids = tapped
+        .iter()
+        .map(|update| {
+            (
+                update.outcome(),
+                update.previous_update_id,
+                update.update_id,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        [
+            (UpdateOutcome::Resync, 0, 100),
+            (UpdateOutcome::Accepted, 100, 101),
+            (UpdateOutcome::Resync, 0, 104),
+            (UpdateOutcome::Accepted, 104, 106),
+            (UpdateOutcome::OutOfOrder, 106, 103),
+            (UpdateOutcome::Accepted, 106, 107),
+        ]
+    );
+    assert!(tapped
+        .iter()
+        .all(|update| update.exchange == "BINANCE" && update.symbol == "BTCUSDT"));
+    assert!(tapped.iter().all(|update| update.dropped == 0));
+    assert_eq!((tapped[0].bids.len(), tapped[0].asks.len()), (2, 2));
+    assert_eq!(
+        (tapped[1].bids[0].price, tapped[1].bids[0].quantity),
+        (30000.5, 0.5)
+    );
+    // The fresh snapshot says which gap it was fetched for.
+    assert!(tapped[2].reason.contains("first_update_id: 105"));
+    assert_eq!((tapped[2].bids.len(), tapped[2].asks.len()), (1, 2));
+    assert!(!tapped[4].reason.is_empty());
+    assert_eq!(
+        (tapped[5].asks[0].price, tapped[5].asks[0].quantity),
+        (30001.0, 0.0)
+    );
+
+    // The tapped updates applied are the ones the book counts and the summary shows.
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let summary = timeout(Duration::from_secs(10), async {
+        loop {
+            let summary = next(&mut stream).await.unwrap().unwrap();
+            if summary.asks.iter().all(|level| level.price != 30001.0) {
+                return summary;
+            }
+        }
+    })
+    .await
+    .expect("no summary without the removed ask");
+    // The book was rebuilt, so the bid from before the gap is gone and the update after
+    // it was applied, but not the one skipped.
+    assert!(summary.bids.iter().all(|level| level.price != 30000.5));
+    assert!(summary.bids.iter().any(|level| level.price == 29990.0));
+    assert!(summary.bids.iter().all(|level| level.price != 29980.0));
+    let feed = stats.feed(Exchange::BINANCE, Symbol::BTCUSDT);
+    let applied = tapped
+        .iter()
+        .filter(|update| update.outcome() != UpdateOutcome::OutOfOrder)
+        .count() as u64;
+    assert_eq!(feed.counters.updates_applied.get(), applied);
+    assert_eq!((feed.resyncs(), feed.update_errors()), (1, 0));
+    assert_eq!(stats.snapshot().server["tapped_updates_dropped"], 0);
+
+    let status = admin
+        .tap_updates(admin_request(TapUpdatesRequest {
+            symbol: "btcusdt".to_string(),
+            exchanges: vec!["kraken".to_string()],
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
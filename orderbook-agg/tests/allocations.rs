@@ -0,0 +1,139 @@
+//! Counts heap allocations on the path from raw exchange messages to summaries, so that
+//! regressions in the hot path show up as a failing test. Lives in its own test binary
+//! because it installs dhat as the global allocator.
+use std::sync::Arc;
+
+use orderbook_agg::{
+    core::{
+        exchange_book::{FromMessage, StreamMessage},
+        order_book::{BookLevels, OrderBook, Update},
+    },
+    exchanges::{binance, bitstamp},
+    make_summary, Exchange, Symbol,
+};
+
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+const MESSAGES: u64 = 1000;
+const LEVELS: u32 = 15;
+/// Allocations allowed per 1k messages, about a fifth above the count measured when the
+/// budget was last tightened.
+const BUDGET: u64 = 94_000;
+
+/// Raw depth updates alternating between Binance and Bitstamp, with prices walking over
+/// the top of the book.
+fn messages() -> Vec<(Exchange, Vec<u8>)> {
+    (0..MESSAGES)
+        .map(|i| {
+            let bid = 30750 + i * 7 % 17;
+            let ask = 30769 + i * 5 % 17;
+            let quantity = if i % 4 == 0 {
+                "0.00000000"
+            } else {
+                "0.12345678"
+            };
+            if i % 2 == 0 {
+                let id = 2 + i / 2;
+                let message = serde_json::json!({
+                    "e": "depthUpdate",
+                    "E": 1688515101262u64 + i,
+                    "s": "BTCUSDT",
+                    "U": id,
+                    "u": id,
+                    "b": [[format!("{}.00000000", bid), quantity]],
+                    "a": [[format!("{}.00000000", ask), quantity]],
+                });
+                (Exchange::BINANCE, message.to_string().into_bytes())
+            } else {
+                let message = serde_json::json!({
+                    "data": {
+                        "timestamp": "1688515101",
+                        "microtimestamp": (1688515101262376 + i).to_string(),
+                        "bids": [[bid.to_string(), quantity]],
+                        "asks": [[ask.to_string(), quantity]],
+                    },
+                    "channel": "diff_order_book_btcusdt",
+                    "event": "data",
+                });
+                (Exchange::BITSTAMP, message.to_string().into_bytes())
+            }
+        })
+        .collect()
+}
+
+fn seeded_book(exchange: Exchange, scale_price: u32) -> OrderBook {
+    let snapshot = std::fs::read("../tests/fixtures/snapshot_bitstamp.json").unwrap();
+    let mut snapshot: bitstamp::data::Snapshot = serde_json::from_slice(&snapshot).unwrap();
+    for level in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+        level[0].rescale(scale_price);
+    }
+    snapshot.last_update_id = 1;
+    let mut orderbook = OrderBook::new(
+        exchange,
+        Symbol::BTCUSDT,
+        29301 * 10u64.pow(scale_price),
+        32304 * 10u64.pow(scale_price),
+        scale_price,
+        8,
+    );
+    orderbook.update(&mut snapshot).unwrap();
+    orderbook
+}
+
+fn apply<U: FromMessage + Update + std::fmt::Debug>(
+    orderbook: &mut OrderBook,
+    data: &[u8],
+) -> Option<Arc<BookLevels>> {
+    let StreamMessage::Update(mut update) = U::from_message(data).unwrap() else {
+        panic!("expected an update");
+    };
+    if orderbook.update(&mut update).unwrap() {
+        orderbook.cached_book_levels(LEVELS)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn it_stays_within_the_allocation_budget() {
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let messages = messages();
+    let mut binance_book = seeded_book(Exchange::BINANCE, 2);
+    let mut bitstamp_book = seeded_book(Exchange::BITSTAMP, 0);
+    let mut latest = [
+        binance_book.cached_book_levels(LEVELS).unwrap(),
+        bitstamp_book.cached_book_levels(LEVELS).unwrap(),
+    ];
+
+    let before = dhat::HeapStats::get();
+    let mut summaries = 0;
+    for (exchange, data) in messages.iter() {
+        let (book_levels, idx) = match exchange {
+            Exchange::BINANCE => (
+                apply::<binance::data::BookUpdate>(&mut binance_book, data),
+                0,
+            ),
+            _ => (
+                apply::<bitstamp::data::BookUpdate>(&mut bitstamp_book, data),
+                1,
+            ),
+        };
+        if let Some(book_levels) = book_levels {
+            latest[idx] = book_levels;
+            let summary =
+                make_summary(&[latest[0].as_ref(), latest[1].as_ref()], Symbol::BTCUSDT).unwrap();
+            assert_eq!(summary.bids.len(), LEVELS as usize);
+            summaries += 1;
+        }
+    }
+    let after = dhat::HeapStats::get();
+
+    let allocations = after.total_blocks - before.total_blocks;
+    println!(
+        "{} allocations for {} messages and {} summaries",
+        allocations, MESSAGES, summaries
+    );
+    dhat::assert!(summaries > 0);
+    dhat::assert!(allocations <= BUDGET, "{} allocations", allocations);
+}
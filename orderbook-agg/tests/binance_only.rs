@@ -0,0 +1,69 @@
+//! Runs a server built with only the Binance connector, the smallest build there is:
+//! ```sh
+//! cargo test --no-default-features --features exchange-binance --test binance_only
+//! ```
+//! It passes with every connector compiled in too.
+use std::time::Duration;
+
+use orderbook_agg::{
+    book_summary::{orderbook_aggregator_client::OrderbookAggregatorClient, WatchSummaryRequest},
+    exchanges,
+    service::parse_exchanges,
+    Exchange,
+};
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+
+mod support;
+use support::{MockExchange, Step};
+
+#[test]
+fn it_compiles_in_binance_and_rejects_the_exchanges_left_out() {
+    assert!(exchanges::compiled().contains(&Exchange::BINANCE));
+    assert_eq!(parse_exchanges(&["binance"]).unwrap(), [Exchange::BINANCE]);
+    for exchange in [Exchange::BITSTAMP, Exchange::BINANCE_FUTURES] {
+        if exchanges::compiled().contains(&exchange) {
+            continue;
+        }
+        let err = parse_exchanges(&[exchange.to_string()]).unwrap_err();
+        assert!(err.to_string().contains("isn't compiled"), "{}", err);
+    }
+}
+
+#[tokio::test]
+async fn it_streams_summaries_from_binance_alone() {
+    let binance = MockExchange::binance(vec![vec![
+        Step::json(serde_json::json!({
+            "e": "depthUpdate",
+            "E": 1688515101262u64,
+            "s": "BTCUSDT",
+            "U": 101,
+            "u": 101,
+            "b": [["30000.50000000", "0.50000000"]],
+            "a": [],
+        })),
+        Step::Hold,
+    ]])
+    .await;
+    let url = support::serve(support::aggregate(&[(Exchange::BINANCE, &binance)])).await;
+
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let summary = timeout(Duration::from_secs(10), async {
+        while let Some(summary) = stream.next().await {
+            let summary = summary.unwrap();
+            if summary.bids.iter().any(|level| level.price == 30000.5) {
+                return summary;
+            }
+        }
+        panic!("stream ended");
+    })
+    .await
+    .expect("no summary with the update's bid");
+    assert!(summary.bids.iter().all(|level| level.exchange == "BINANCE"));
+    assert_eq!(binance.subscriptions(), [["/ws/btcusdt@depth@100ms"]]);
+}
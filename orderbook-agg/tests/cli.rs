@@ -0,0 +1,200 @@
+//! Runs the `obagg` binary against a server in the test process, fed with book levels by
+//! the test instead of exchanges.
+use std::{process::Output, sync::Arc, time::Duration};
+
+use orderbook_agg::{
+    book_summary::{orderbook_aggregator_server::OrderbookAggregatorServer, Level, Summary},
+    core::order_book::BookLevels,
+    service::{spawn_summary, ExchangeClosed, OrderbookSummary},
+    Exchange, Symbol,
+};
+use tokio::{
+    net::TcpListener,
+    process::Command,
+    sync::{mpsc, watch},
+};
+
+struct TestServer {
+    endpoint: String,
+    tx_levels: mpsc::Sender<Arc<BookLevels>>,
+    tx_closed: mpsc::Sender<ExchangeClosed>,
+    _tx_shutdown: watch::Sender<bool>,
+}
+
+async fn serve() -> TestServer {
+    let (tx_levels, rx_levels) = mpsc::channel(100);
+    let (tx_closed, rx_closed) = mpsc::channel(10);
+    let exchanges = vec![Exchange::BITSTAMP, Exchange::BINANCE];
+    let tx_summary = spawn_summary(
+        Symbol::BTCUSDT,
+        exchanges.clone(),
+        rx_levels,
+        rx_closed,
+        watch::channel(true).0,
+    );
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let service = OrderbookSummary::new(tx_summary, rx_shutdown).with_exchanges(exchanges);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .serve_with_incoming(
+                tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap(),
+            ),
+    );
+    TestServer {
+        endpoint: addr.to_string(),
+        tx_levels,
+        tx_closed,
+        _tx_shutdown: tx_shutdown,
+    }
+}
+
+fn book_levels(exchange: Exchange, id: u64) -> Arc<BookLevels> {
+    let level = |price: f64| Level {
+        exchange: exchange.to_string(),
+        price,
+        quantity: 1.5,
+        updated_at: None,
+        market_type: exchange.market_type().into(),
+    };
+    Arc::new(BookLevels {
+        exchange,
+        symbol: Symbol::BTCUSDT,
+        last_update_id: id,
+        bids: (0..3).map(|i| level(29990.0 - i as f64)).collect(),
+        asks: (0..3).map(|i| level(30010.0 + i as f64)).collect(),
+        depth: None,
+        restored: false,
+    })
+}
+
+async fn obagg(endpoint: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_obagg"))
+        .arg("--endpoint")
+        .arg(endpoint)
+        .args(args)
+        .output()
+        .await
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[tokio::test]
+async fn it_lists_symbols_and_exchanges() {
+    let server = serve().await;
+    let output = obagg(&server.endpoint, &["symbols"]).await;
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "BTCUSDT\n");
+
+    let output = obagg(&server.endpoint, &["exchanges"]).await;
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "BITSTAMP\nBINANCE\n");
+}
+
+#[tokio::test]
+async fn it_prints_the_latest_summary() {
+    let server = serve().await;
+    server
+        .tx_levels
+        .send(book_levels(Exchange::BITSTAMP, 1))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let args = ["summary", "btcusdt", "--levels", "2", "--decimals", "1"];
+    let output = obagg(&server.endpoint, &args).await;
+    assert!(output.status.success(), "{:?}", output);
+    let lines = stdout(&output);
+    let lines = lines.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "BTCUSDT spread 20.0");
+    assert_eq!(lines[1], "unavailable: BINANCE");
+    assert_eq!(lines[2], "asks");
+    assert_eq!(
+        lines[3].split_whitespace().collect::<Vec<_>>(),
+        ["30011.0", "1.5", "BITSTAMP"]
+    );
+    assert_eq!(lines[5], "bids");
+    assert_eq!(lines.len(), 8);
+
+    let output = obagg(&server.endpoint, &["summary", "ethbtc"]).await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ethbtc is not served"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn it_streams_summaries_as_json_lines_for_a_duration() {
+    let server = serve().await;
+    let tx_levels = server.tx_levels.clone();
+    tokio::spawn(async move {
+        for id in 1.. {
+            if tx_levels
+                .send(book_levels(Exchange::BINANCE, id))
+                .await
+                .is_err()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+
+    let args = ["watch", "btcusdt", "--duration", "1s", "--levels", "1"];
+    let output = obagg(&server.endpoint, &args).await;
+    assert!(output.status.success(), "{:?}", output);
+    let lines = stdout(&output);
+    assert!(lines.lines().count() > 2, "{}", lines);
+    for line in lines.lines() {
+        let summary: Summary = serde_json::from_str(line).unwrap();
+        assert_eq!(summary.symbol, "BTCUSDT");
+        assert_eq!(summary.bids.len(), 1);
+    }
+    // Field names are the proto's.
+    assert!(
+        lines.contains(r#""unavailable_exchanges":["BITSTAMP"]"#),
+        "{}",
+        lines
+    );
+
+    let args = ["watch", "btcusdt", "--duration", "500ms", "--format", "csv"];
+    let output = obagg(&server.endpoint, &args).await;
+    assert!(output.status.success(), "{:?}", output);
+    let lines = stdout(&output);
+    let mut lines = lines.lines();
+    assert_eq!(lines.next(), Some("sequence,side,exchange,price,quantity"));
+    assert!(lines.all(|line| line.contains(",BINANCE,")));
+
+    let output = obagg(&server.endpoint, &["watch", "btcusdt", "--duration", "1"]).await;
+    assert!(!output.status.success());
+}
+
+#[tokio::test]
+async fn it_exits_with_an_error_when_summaries_are_unavailable() {
+    let server = serve().await;
+    for exchange in [Exchange::BITSTAMP, Exchange::BINANCE] {
+        server
+            .tx_closed
+            .send(ExchangeClosed {
+                exchange,
+                reason: "down".to_string(),
+                lost: false,
+                disabled: false,
+                delisted: false,
+            })
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let output = obagg(&server.endpoint, &["watch", "btcusdt"]).await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("summary unavailable"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
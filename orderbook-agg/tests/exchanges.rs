@@ -0,0 +1,885 @@
+//! Runs the real connectors against the mock exchanges in [support] and checks what a
+//! client streams from the server.
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use orderbook_agg::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient, ConnectionState, DerivativeInfo,
+        Empty, ExchangeStatus, Level, MarketType, Summary, SummaryRequest,
+        WatchExchangeStatusRequest, WatchSummaryRequest,
+    },
+    fx::{self, StaticRates},
+    service::ExchangeOptions,
+    synthetic::{Generator, SyntheticConfig},
+    Exchange, Symbol,
+};
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+use tonic::{Code, Status, Streaming};
+
+mod support;
+use support::{MockExchange, Step};
+
+fn binance_update(first: u64, last: u64, bid: (&str, &str)) -> Step {
+    Step::json(serde_json::json!({
+        "e": "depthUpdate",
+        "E": 1688515101262u64,
+        "s": "BTCUSDT",
+        "U": first,
+        "u": last,
+        "b": [[bid.0, bid.1]],
+        "a": [],
+    }))
+}
+
+fn bitstamp_update(microtimestamp: u64, bid: (&str, &str)) -> Step {
+    Step::json(serde_json::json!({
+        "data": {
+            "timestamp": "0",
+            "microtimestamp": microtimestamp.to_string(),
+            "bids": [[bid.0, bid.1]],
+            "asks": [],
+        },
+        "channel": "diff_order_book_btcusdt",
+        "event": "data",
+    }))
+}
+
+/// Serves BTCUSDT from the mocks, returning the server's url.
+async fn serve(mocks: &[(Exchange, &MockExchange)]) -> String {
+    support::serve(support::aggregate(mocks)).await
+}
+
+/// Waits for a summary `until` is true of.
+async fn watch_until(url: String, until: impl Fn(&Summary) -> bool) -> Summary {
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut last = None;
+    let found = timeout(Duration::from_secs(10), async {
+        while let Some(summary) = stream.next().await {
+            let summary = summary.unwrap();
+            if until(&summary) {
+                return summary;
+            }
+            last = Some(summary);
+        }
+        panic!("stream ended");
+    })
+    .await;
+    found.unwrap_or_else(|_| panic!("no matching summary, last was {:?}", last))
+}
+
+/// GetSummary of BTCUSDT aggregated from the exchanges of `market_types`
+async fn summarize_markets(url: &str, market_types: &[MarketType]) -> Summary {
+    let mut client = OrderbookAggregatorClient::connect(url.to_string())
+        .await
+        .unwrap();
+    let request = SummaryRequest {
+        symbol: "btcusdt".to_string(),
+        market_types: market_types.iter().map(|&m| m.into()).collect(),
+        ..Default::default()
+    };
+    client.get_summary(request).await.unwrap().into_inner()
+}
+
+fn best_bid(summary: &Summary) -> Option<(&str, f64, f64)> {
+    summary
+        .bids
+        .first()
+        .map(|level| (level.exchange.as_str(), level.price, level.quantity))
+}
+
+fn has_bid(summary: &Summary, exchange: &str, price: f64, quantity: f64) -> bool {
+    summary.bids.iter().any(|level| {
+        level.exchange == exchange && level.price == price && level.quantity == quantity
+    })
+}
+
+#[tokio::test]
+async fn it_streams_summaries_merged_from_both_exchanges() {
+    let binance = MockExchange::binance(vec![vec![
+        binance_update(101, 101, ("30000.50000000", "0.50000000")),
+        Step::Hold,
+    ]])
+    .await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![
+        Step::fixture("control_bitstamp_subscription_succeeded.json"),
+        bitstamp_update(2, ("30000.75", "0.25000000")),
+        Step::Hold,
+    ]])
+    .await;
+    let url = serve(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE, &binance),
+    ])
+    .await;
+
+    let summary = watch_until(url, |summary| {
+        has_bid(summary, "BITSTAMP", 30000.75, 0.25) && has_bid(summary, "BINANCE", 30000.5, 0.5)
+    })
+    .await;
+    assert_eq!(best_bid(&summary), Some(("BITSTAMP", 30000.75, 0.25)));
+    assert_eq!(summary.spread, 0.25);
+    assert!(!summary.degraded);
+
+    assert_eq!(binance.subscriptions(), [["/ws/btcusdt@depth@100ms"]]);
+    let bitstamp = bitstamp.subscriptions();
+    assert_eq!(bitstamp.len(), 1);
+    assert_eq!(bitstamp[0][0], "/ws/");
+    let subscribe: serde_json::Value = serde_json::from_str(&bitstamp[0][1]).unwrap();
+    assert_eq!(subscribe["event"], "bts:subscribe");
+    assert_eq!(subscribe["data"]["channel"], "diff_order_book_btcusdt");
+}
+
+#[tokio::test]
+async fn it_summarizes_the_listings_clients_name() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    // Bitstamp lists the symbol under a second name too, with a better bid.
+    bitstamp.set_rest(
+        "trading-pairs-info",
+        r#"[{"url_symbol":"btcusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2},{"url_symbol":"xbtusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2}]"#,
+    );
+    bitstamp.set_rest("ticker/xbtusdt", r#"{"bid":"30000.50","ask":"30001.50"}"#);
+    bitstamp.set_rest(
+        "order_book/xbtusdt",
+        r#"{"timestamp":"0","microtimestamp":"1","bids":[["30000.50","3.00000000"],["29999.50","1.00000000"]],"asks":[["30001.50","1.00000000"],["30002.50","1.00000000"]]}"#,
+    );
+    let (service, switches) = support::aggregate_switched(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE, &binance),
+    ]);
+    let url = support::serve(service).await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BITSTAMP", 30000.0, 1.0) && has_bid(summary, "BINANCE", 30000.0, 1.0)
+    })
+    .await;
+
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let request = |exchange: &str, native: &str| SummaryRequest {
+        symbol: "btcusdt".to_string(),
+        symbol_overrides: HashMap::from([(exchange.to_string(), native.to_string())]),
+        ..Default::default()
+    };
+    let summary = client
+        .get_summary(request("bitstamp", "xbtusdt"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(best_bid(&summary), Some(("BITSTAMP", 30000.5, 3.0)));
+    assert!(has_bid(&summary, "BINANCE", 30000.0, 1.0));
+    assert!(!has_bid(&summary, "BITSTAMP", 30000.0, 1.0));
+    assert_eq!(
+        summary.native_symbols,
+        HashMap::from([
+            ("BITSTAMP".to_string(), "xbtusdt".to_string()),
+            ("BINANCE".to_string(), "BTCUSDT".to_string()),
+        ])
+    );
+    let channels = bitstamp
+        .subscriptions()
+        .iter()
+        .map(|connection| {
+            let subscribe: serde_json::Value = serde_json::from_str(&connection[1]).unwrap();
+            subscribe["data"]["channel"].as_str().unwrap().to_string()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        channels,
+        ["diff_order_book_btcusdt", "diff_order_book_xbtusdt"]
+    );
+
+    let status = client
+        .get_summary(request("bitstamp", "dogeusdt"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(
+        status
+            .message()
+            .starts_with("BITSTAMP doesn't list dogeusdt"),
+        "{}",
+        status.message()
+    );
+    switches.set(Exchange::BINANCE, false).unwrap();
+    let status = client
+        .get_summary(request("binance", "BTCUSDC"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "symbol_overrides for BINANCE is disabled");
+}
+
+#[tokio::test]
+async fn it_fetches_a_listing_once_for_the_calls_naming_it_at_once() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    bitstamp.set_rest(
+        "trading-pairs-info",
+        r#"[{"url_symbol":"btcusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2},{"url_symbol":"xbtusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2}]"#,
+    );
+    bitstamp.set_rest("ticker/xbtusdt", r#"{"bid":"30000.50","ask":"30001.50"}"#);
+    bitstamp.set_rest(
+        "order_book/xbtusdt",
+        r#"{"timestamp":"0","microtimestamp":"1","bids":[["30000.50","3.00000000"]],"asks":[["30001.50","1.00000000"]]}"#,
+    );
+    let url = serve(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE, &binance),
+    ])
+    .await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BITSTAMP", 30000.0, 1.0) && has_bid(summary, "BINANCE", 30000.0, 1.0)
+    })
+    .await;
+
+    let client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let overrides = HashMap::from([("bitstamp".to_string(), "xbtusdt".to_string())]);
+    let calls = (0..8).map(|levels| {
+        let mut client = client.clone();
+        let request = SummaryRequest {
+            symbol: "btcusdt".to_string(),
+            levels,
+            symbol_overrides: overrides.clone(),
+            ..Default::default()
+        };
+        async move { client.get_summary(request).await.unwrap().into_inner() }
+    });
+    // Calls cutting the summary to their own levels share it all the same.
+    let summaries = futures::future::join_all(calls).await;
+    for summary in summaries.iter() {
+        assert_eq!(best_bid(summary), Some(("BITSTAMP", 30000.5, 3.0)));
+    }
+    assert_eq!(bitstamp.requests("order_book/xbtusdt"), 1);
+    assert_eq!(bitstamp.subscriptions().len(), 2);
+}
+
+#[tokio::test]
+async fn it_keeps_each_exchange_to_its_depth() {
+    let binance = MockExchange::binance(vec![vec![
+        binance_update(101, 101, ("30000.50000000", "0.50000000")),
+        Step::Hold,
+    ]])
+    .await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    bitstamp.set_rest(
+        "order_book/btcusdt",
+        r#"{"timestamp":"0","microtimestamp":"1","bids":[["30000.00","1.00000000"],["29999.00","2.00000000"],["29998.00","1.00000000"],["29997.00","1.00000000"]],"asks":[["30001.00","1.00000000"],["30002.00","2.00000000"],["30003.00","1.00000000"],["30004.00","1.00000000"]]}"#,
+    );
+    let options = ExchangeOptions {
+        depths: HashMap::from([(Exchange::BINANCE, 1)]),
+        ..Default::default()
+    };
+    let (service, _) = support::aggregate_with(
+        &[
+            (Exchange::BITSTAMP, &bitstamp),
+            (Exchange::BINANCE, &binance),
+        ],
+        options,
+    );
+    let url = support::serve(service).await;
+    let levels = |levels: &[Level], exchange: &str| {
+        levels
+            .iter()
+            .filter(|level| level.exchange == exchange)
+            .map(|level| level.price)
+            .collect::<Vec<_>>()
+    };
+
+    // The better bid pushes binance's others out of its book.
+    let summary = watch_until(url.clone(), |summary| {
+        has_bid(summary, "BINANCE", 30000.5, 0.5) && has_bid(summary, "BITSTAMP", 29998.0, 1.0)
+    })
+    .await;
+    assert_eq!(levels(&summary.bids, "BINANCE"), [30000.5]);
+    assert_eq!(levels(&summary.asks, "BINANCE"), [30001.0]);
+    assert_eq!(
+        levels(&summary.bids, "BITSTAMP"),
+        [30000.0, 29999.0, 29998.0]
+    );
+    assert_eq!(
+        summary.exchange_depths,
+        HashMap::from([("BINANCE".to_string(), 1)])
+    );
+
+    // Clients can take fewer levels from an exchange, but no more than its book keeps.
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let request = |depths: &[(&str, u32)]| SummaryRequest {
+        symbol: "btcusdt".to_string(),
+        exchange_depths: depths
+            .iter()
+            .map(|(exchange, depth)| (exchange.to_string(), *depth))
+            .collect(),
+        ..Default::default()
+    };
+    let summary = client
+        .get_summary(request(&[("bitstamp", 1), ("binance", 5)]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(levels(&summary.bids, "BITSTAMP"), [30000.0]);
+    assert_eq!(levels(&summary.asks, "BITSTAMP"), [30001.0]);
+    assert_eq!(levels(&summary.bids, "BINANCE"), [30000.5]);
+    assert_eq!(
+        summary.exchange_depths,
+        HashMap::from([("BINANCE".to_string(), 1), ("BITSTAMP".to_string(), 1)])
+    );
+
+    let status = client
+        .get_summary(request(&[("bitstamp", 0)]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "exchange_depths for BITSTAMP must be above 0"
+    );
+}
+
+#[tokio::test]
+async fn it_converts_listings_quoted_in_other_currencies() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    bitstamp.set_rest(
+        "trading-pairs-info",
+        r#"[{"url_symbol":"btcusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2},{"url_symbol":"btceur","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2}]"#,
+    );
+    bitstamp.set_rest("ticker/btceur", r#"{"bid":"27272.72","ask":"27273.64"}"#);
+    bitstamp.set_rest(
+        "order_book/btceur",
+        r#"{"timestamp":"0","microtimestamp":"1","bids":[["27272.72","2.00000000"],["27272.71","1.00000000"]],"asks":[["27273.64","1.00000000"]]}"#,
+    );
+    let service = support::aggregate(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE, &binance),
+    ])
+    .with_rates(Arc::new(StaticRates::new(
+        fx::parse_rates(&["EUR/USDT=1.1"]).unwrap(),
+    )));
+    let url = support::serve(service).await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BITSTAMP", 30000.0, 1.0) && has_bid(summary, "BINANCE", 30000.0, 1.0)
+    })
+    .await;
+
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let request = |quote_currency: &str| SummaryRequest {
+        symbol: "btcusdt".to_string(),
+        symbol_overrides: HashMap::from([("bitstamp".to_string(), "btceur".to_string())]),
+        quote_currency: quote_currency.to_string(),
+        ..Default::default()
+    };
+    let summary = client
+        .get_summary(request("usdt"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(summary.quote_currency, "USDT");
+    // Bids round down and asks up, so 30001.004 is asked at 30001.01.
+    let levels = |levels: &[Level], exchange: &str| {
+        levels
+            .iter()
+            .filter(|level| level.exchange == exchange)
+            .map(|level| [level.price, level.quantity])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(levels(&summary.bids, "BITSTAMP"), [[29999.99, 2.0]]);
+    assert_eq!(levels(&summary.bids, "BINANCE"), [[30000.0, 1.0]]);
+    assert_eq!(levels(&summary.asks, "BITSTAMP"), [[30001.01, 1.0]]);
+    let rate = &summary.fx_rates["BITSTAMP"];
+    assert_eq!((rate.from.as_str(), rate.to.as_str()), ("EUR", "USDT"));
+    assert_eq!(rate.rate, 1.1);
+    assert!(rate.timestamp_ms > 0);
+    assert_eq!(summary.fx_rates.len(), 1);
+
+    // Converted the other way at the inverse rate, but there's none to GBP.
+    let summary = client
+        .get_summary(request("EUR"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(levels(&summary.bids, "BITSTAMP")[0], [27272.72, 2.0]);
+    assert_eq!(levels(&summary.bids, "BINANCE")[0], [27272.72, 1.0]);
+    let status = client.get_summary(request("gbp")).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    assert!(
+        status
+            .message()
+            .ends_with("to GBP is configured for BITSTAMP"),
+        "{}",
+        status.message()
+    );
+}
+
+#[tokio::test]
+async fn it_adds_mark_prices_for_derivative_exchanges_only() {
+    let futures = MockExchange::binance_futures(vec![
+        vec![Step::Hold],
+        vec![Step::fixture("mark_price_binance_futures.json"), Step::Hold],
+    ])
+    .await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    let url = serve(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE_FUTURES, &futures),
+    ])
+    .await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BITSTAMP", 30000.0, 1.0)
+            && has_bid(summary, "BINANCE_FUTURES", 30000.0, 1.0)
+    })
+    .await;
+
+    // Clients that don't ask cost no subscription.
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let request = |derivatives| SummaryRequest {
+        symbol: "btcusdt".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+    let summary = client
+        .get_summary(request(false))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(summary.derivatives.is_empty());
+    assert_eq!(futures.connections(), 1);
+
+    let summary = client
+        .get_summary(request(true))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        summary.derivatives,
+        HashMap::from([(
+            "BINANCE_FUTURES".to_string(),
+            DerivativeInfo {
+                mark_price: 30768.41234567,
+                funding_rate: 0.0001,
+                next_funding_time_ms: 1688515200000,
+            }
+        )])
+    );
+    assert_eq!(futures.subscriptions()[1], ["/ws/btcusdt@markPrice@1s"]);
+    assert_eq!(bitstamp.connections(), 1);
+
+    // Later calls share the stream.
+    let summary = client
+        .get_summary(request(true))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(summary.derivatives.contains_key("BINANCE_FUTURES"));
+    assert_eq!(futures.connections(), 2);
+}
+
+#[tokio::test]
+async fn it_labels_the_spot_and_futures_levels_and_summarizes_either() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let futures = MockExchange::binance_futures(vec![vec![
+        Step::json(serde_json::json!({
+            "e": "depthUpdate",
+            "E": 1688515101262u64,
+            "s": "BTCUSDT",
+            "U": 101,
+            "u": 101,
+            "pu": 100,
+            "b": [["30000.50", "0.500"]],
+            "a": [],
+        })),
+        Step::Hold,
+    ]])
+    .await;
+    let url = serve(&[
+        (Exchange::BINANCE, &binance),
+        (Exchange::BINANCE_FUTURES, &futures),
+    ])
+    .await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BINANCE", 30000.0, 1.0)
+            && has_bid(summary, "BINANCE_FUTURES", 30000.5, 0.5)
+    })
+    .await;
+
+    let labels = |summary: &Summary| {
+        let levels = summary.bids.iter().chain(summary.asks.iter());
+        let mut labels = levels
+            .map(|level| (level.exchange.clone(), level.market_type()))
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels.dedup();
+        labels
+    };
+    let both = vec![
+        ("BINANCE".to_string(), MarketType::Spot),
+        ("BINANCE_FUTURES".to_string(), MarketType::Futures),
+    ];
+
+    // Both books merge into one ladder, the perpetual's best bid first, each level
+    // labeled with its market.
+    let summary = summarize_markets(&url, &[]).await;
+    assert_eq!(
+        summary.bids[0],
+        Level {
+            exchange: "BINANCE_FUTURES".to_string(),
+            price: 30000.5,
+            quantity: 0.5,
+            updated_at: None,
+            market_type: MarketType::Futures.into(),
+        }
+    );
+    assert_eq!(labels(&summary), both);
+    assert!(summary.notes.is_empty());
+    let summary = summarize_markets(&url, &[MarketType::Spot, MarketType::Futures]).await;
+    assert_eq!(labels(&summary), both);
+
+    let summary = summarize_markets(&url, &[MarketType::Futures]).await;
+    assert_eq!(labels(&summary), &both[1..]);
+    assert_eq!(summary.spread, 0.5);
+    assert_eq!(
+        summary.native_symbols,
+        HashMap::from([("BINANCE_FUTURES".to_string(), "BTCUSDT".to_string())])
+    );
+    assert!(summary.notes.is_empty());
+    let summary = summarize_markets(&url, &[MarketType::Spot]).await;
+    assert_eq!(labels(&summary), &both[..1]);
+    assert_eq!(summary.spread, 1.0);
+    assert!(!summary.degraded);
+}
+
+#[tokio::test]
+async fn it_falls_back_to_spot_for_symbols_without_futures() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    // No perpetual of BTCUSDT is listed, so the futures book never starts.
+    let futures = MockExchange::binance_futures(vec![vec![Step::Hold]]).await;
+    futures.set_rest("exchangeInfo", r#"{"symbols":[]}"#);
+    let url = serve(&[
+        (Exchange::BINANCE, &binance),
+        (Exchange::BINANCE_FUTURES, &futures),
+    ])
+    .await;
+    watch_until(url.clone(), |summary| {
+        has_bid(summary, "BINANCE", 30000.0, 1.0)
+    })
+    .await;
+
+    let summary = summarize_markets(&url, &[MarketType::Futures]).await;
+    assert!(summary
+        .bids
+        .iter()
+        .chain(summary.asks.iter())
+        .all(|level| level.exchange == "BINANCE" && level.market_type() == MarketType::Spot));
+    assert_eq!(
+        summary.notes,
+        ["no futures book of BTCUSDT is available, showing spot only"]
+    );
+    assert!(!summary.degraded);
+}
+
+#[tokio::test]
+async fn it_drops_exchanges_that_delist_the_symbol() {
+    let binance = MockExchange::binance(vec![vec![Step::Hold]]).await;
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    let options = ExchangeOptions {
+        listing_refresh: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let (service, _) = support::aggregate_with(
+        &[
+            (Exchange::BITSTAMP, &bitstamp),
+            (Exchange::BINANCE, &binance),
+        ],
+        options,
+    );
+    let url = support::serve(service).await;
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    async fn next(stream: &mut Streaming<Summary>) -> Option<Result<Summary, Status>> {
+        timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("no message within 10s")
+    }
+    loop {
+        let summary = next(&mut stream).await.unwrap().unwrap();
+        if has_bid(&summary, "BITSTAMP", 30000.0, 1.0) && has_bid(&summary, "BINANCE", 30000.0, 1.0)
+        {
+            break;
+        }
+    }
+
+    // Bitstamp stops listing btcusdt, and is left out for good without degrading the
+    // summaries.
+    bitstamp.set_rest(
+        "trading-pairs-info",
+        r#"[{"url_symbol":"ethbtc","base_decimals":8,"counter_decimals":6,"instant_order_counter_decimals":6}]"#,
+    );
+    let summary = loop {
+        let summary = next(&mut stream).await.unwrap().unwrap();
+        if !summary.delisted_exchanges.is_empty() {
+            break summary;
+        }
+    };
+    assert_eq!(summary.delisted_exchanges, ["BITSTAMP"]);
+    assert!(summary.unavailable_exchanges.is_empty());
+    assert!(!summary.degraded);
+    assert!(summary
+        .bids
+        .iter()
+        .chain(summary.asks.iter())
+        .all(|level| level.exchange == "BINANCE"));
+    let summary = client
+        .get_summary(SummaryRequest {
+            symbol: "btcusdt".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        summary.native_symbols,
+        HashMap::from([("BINANCE".to_string(), "BTCUSDT".to_string())])
+    );
+
+    // Once binance stops listing it too, streams end and new requests are refused.
+    binance.set_rest("exchangeInfo", r#"{"symbols":[]}"#);
+    let status = loop {
+        if let Err(status) = next(&mut stream).await.unwrap() {
+            break status;
+        }
+    };
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(status.message(), "symbol delisted");
+    assert!(next(&mut stream).await.is_none());
+    let status = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    let status = client
+        .get_summary(SummaryRequest {
+            symbol: "btcusdt".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(status.message(), "symbol delisted");
+}
+
+#[tokio::test]
+async fn it_resubscribes_when_exchanges_disconnect() {
+    let binance = MockExchange::binance(vec![
+        vec![
+            binance_update(101, 101, ("30000.25000000", "0.10000000")),
+            // Streams end if every exchange is down at once, so this waits for Bitstamp
+            // to have reconnected, a second after it was asked to.
+            Step::Wait(Duration::from_secs(2)),
+            Step::Disconnect,
+        ],
+        vec![
+            binance_update(101, 101, ("30000.50000000", "0.50000000")),
+            Step::Hold,
+        ],
+    ])
+    .await;
+    let bitstamp = MockExchange::bitstamp(vec![
+        vec![
+            Step::Wait(Duration::from_millis(100)),
+            Step::fixture("control_bitstamp_request_reconnect.json"),
+            Step::Hold,
+        ],
+        vec![bitstamp_update(2, ("30000.75", "0.25000000")), Step::Hold],
+    ])
+    .await;
+    let url = serve(&[
+        (Exchange::BITSTAMP, &bitstamp),
+        (Exchange::BINANCE, &binance),
+    ])
+    .await;
+
+    watch_until(url, |summary| {
+        has_bid(summary, "BITSTAMP", 30000.75, 0.25) && has_bid(summary, "BINANCE", 30000.5, 0.5)
+    })
+    .await;
+    assert_eq!(binance.connections(), 2);
+    assert_eq!(bitstamp.connections(), 2);
+}
+
+#[tokio::test]
+async fn it_streams_each_change_of_state_of_a_connection_through_a_reconnect() {
+    let binance = MockExchange::binance(vec![
+        vec![
+            binance_update(101, 101, ("30000.25000000", "0.10000000")),
+            Step::Wait(Duration::from_millis(500)),
+            Step::Disconnect,
+        ],
+        vec![
+            binance_update(101, 101, ("30000.50000000", "0.50000000")),
+            Step::Hold,
+        ],
+    ])
+    .await;
+    let url = serve(&[(Exchange::BINANCE, &binance)]).await;
+
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let mut stream = client
+        .watch_exchange_status(WatchExchangeStatusRequest {
+            symbol: "btcusdt".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut statuses = Vec::<ExchangeStatus>::new();
+    let mut reconnected = false;
+    while !reconnected {
+        let status = timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("no status within 10s")
+            .unwrap()
+            .unwrap();
+        reconnected = status.state() == ConnectionState::Receiving
+            && statuses
+                .iter()
+                .any(|status| status.state() == ConnectionState::Reconnecting);
+        statuses.push(status);
+    }
+
+    // The connection as it was first, then every change after it with none missed.
+    assert!(statuses[0].initial);
+    assert!(statuses[1..].iter().all(|status| !status.initial));
+    for (before, after) in statuses.iter().zip(&statuses[1..]) {
+        assert_eq!(after.sequence, before.sequence + 1);
+        assert_eq!(
+            (after.exchange.as_str(), after.symbol.as_str()),
+            ("BINANCE", "BTCUSDT")
+        );
+    }
+    let closed = statuses
+        .iter()
+        .position(|status| status.state() == ConnectionState::Disconnected)
+        .unwrap();
+    let states = statuses[closed..]
+        .iter()
+        .map(|status| status.state())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        states,
+        [
+            ConnectionState::Disconnected,
+            ConnectionState::Reconnecting,
+            ConnectionState::Connecting,
+            ConnectionState::Subscribed,
+            ConnectionState::Receiving,
+        ]
+    );
+    let reconnecting = &statuses[closed + 1];
+    assert_eq!(reconnecting.attempt, 1);
+    assert_eq!(reconnecting.reason, "stream ended");
+    assert!(reconnecting.next_retry_ms > reconnecting.changed_at_ms);
+    assert_eq!(binance.connections(), 2);
+
+    // The counters tell the same story.
+    let stats = client.get_stats(Empty {}).await.unwrap().into_inner();
+    let feed = &stats.feeds[0];
+    assert_eq!(feed.state, "receiving");
+    assert_eq!(
+        feed.counters["state_changes"],
+        statuses.last().unwrap().sequence
+    );
+    assert_eq!(feed.counters["reconnect_attempt"], 0);
+}
+
+#[tokio::test]
+async fn it_skips_malformed_frames_and_gapped_updates() {
+    let binance = MockExchange::binance(vec![vec![
+        Step::text("{not json"),
+        Step::Send(tokio_tungstenite::tungstenite::Message::Binary(vec![
+            0xff, 0x00,
+        ])),
+        // Starts after a gap from the snapshot's update id, so the book is resynced from a
+        // fresh snapshot, which the mock leaves at the same id, and the update dropped.
+        binance_update(105, 105, ("30000.25000000", "9.00000000")),
+        binance_update(101, 101, ("30000.50000000", "0.50000000")),
+        Step::Hold,
+    ]])
+    .await;
+    let url = serve(&[(Exchange::BINANCE, &binance)]).await;
+
+    let summary = watch_until(url.clone(), |summary| {
+        has_bid(summary, "BINANCE", 30000.5, 0.5)
+    })
+    .await;
+    assert!(!has_bid(&summary, "BINANCE", 30000.25, 9.0));
+    assert_eq!(binance.connections(), 1);
+
+    // Each frame was counted before the update after it could reach the summary.
+    let mut client = OrderbookAggregatorClient::connect(url).await.unwrap();
+    let stats = client.get_stats(Empty {}).await.unwrap().into_inner();
+    let feed = &stats.feeds[0];
+    assert_eq!(
+        (feed.exchange.as_str(), feed.symbol.as_str()),
+        ("BINANCE", "BTCUSDT")
+    );
+    assert_eq!(feed.state, "receiving");
+    let counters = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| feed.counters[*name])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        counters(&["messages", "parse_errors", "update_errors", "connections"]),
+        [4, 2, 1, 1]
+    );
+    // Both snapshots and the update that followed them
+    assert_eq!(feed.counters["updates_applied"], 3);
+    assert_eq!(feed.counters["resyncs"], 1);
+    assert_eq!(binance.requests("depth"), 2);
+    assert!(feed.counters["bid_levels"] > 0 && feed.counters["ask_levels"] > 0);
+    assert!(
+        feed.last_message_age_ms < 10_000,
+        "{}",
+        feed.last_message_age_ms
+    );
+    assert_eq!(stats.server["streams_opened"], 1);
+    assert!(stats.server["summaries_sent"] >= 1);
+}
+
+#[tokio::test]
+async fn it_follows_a_synthetic_book() {
+    let mut generator = Generator::new(SyntheticConfig {
+        seed: 3,
+        depth: 20,
+        ..Default::default()
+    })
+    .unwrap();
+    let snapshot = generator.snapshot().snapshot(Exchange::BINANCE);
+    let mut script = generator
+        .by_ref()
+        .take(500)
+        .map(|(_, change)| Step::text(change.update(Exchange::BINANCE, Symbol::BTCUSDT)))
+        .collect::<Vec<_>>();
+    script.push(Step::Hold);
+    let binance = MockExchange::binance(vec![script]).await;
+    binance.set_rest("depth", &snapshot);
+    let url = serve(&[(Exchange::BINANCE, &binance)]).await;
+
+    let book = generator.snapshot();
+    let price = |level: &[rust_decimal::Decimal; 2]| level[0].to_string().parse::<f64>().unwrap();
+    let (best_bid, best_ask) = (price(&book.bids[0]), price(&book.asks[0]));
+    let summary = watch_until(url, |summary| {
+        summary.bids.first().map(|level| level.price) == Some(best_bid)
+            && summary.asks.first().map(|level| level.price) == Some(best_ask)
+    })
+    .await;
+    assert_eq!(summary.bids.len(), 10);
+}
@@ -0,0 +1,73 @@
+//! Serves the aggregator on an IPv4 and an IPv6 address at once and calls it on both.
+use std::{net::SocketAddr, time::Duration};
+
+use futures::future::try_join_all;
+use orderbook_agg::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, Empty,
+    },
+    listen,
+    service::OrderbookSummary,
+    Exchange,
+};
+use tokio::sync::{mpsc, watch};
+use tonic::transport::Server;
+
+#[tokio::test]
+async fn it_serves_every_address_and_drains_them_all_on_shutdown() {
+    // Hosts without IPv6 loopback only get the IPv4 address.
+    let mut addrs: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap()];
+    if std::net::TcpListener::bind("[::1]:0").is_ok() {
+        addrs.push("[::1]:0".parse().unwrap());
+    }
+    let listeners = listen::bind(&addrs).unwrap();
+    let bound = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect::<Vec<_>>();
+
+    let (tx_summary, _) = mpsc::channel(1);
+    let service = OrderbookSummary::new(tx_summary, watch::channel(false).1)
+        .with_exchanges(vec![Exchange::BINANCE])
+        .with_listen_addrs(bound.iter().map(|addr| addr.to_string()).collect());
+    let aggregator = OrderbookAggregatorServer::new(service);
+    let (tx_stopped, rx_stopped) = watch::channel(false);
+    let servers = listeners
+        .into_iter()
+        .map(|listener| {
+            let mut rx_stopped = rx_stopped.clone();
+            Server::builder()
+                .add_service(aggregator.clone())
+                .serve_with_incoming_shutdown(listen::incoming(listener).unwrap(), async move {
+                    let _ = rx_stopped.wait_for(|stopped| *stopped).await;
+                })
+        })
+        .collect::<Vec<_>>();
+    let servers = tokio::spawn(try_join_all(servers));
+
+    let mut clients = vec![];
+    for addr in &bound {
+        let mut client = OrderbookAggregatorClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let info = client.get_server_info(Empty {}).await.unwrap().into_inner();
+        let listening = bound
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(info.listen_addrs, listening);
+        clients.push(client);
+    }
+
+    // The clients' connections are still open, and every listener stops anyway.
+    tx_stopped.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), servers)
+        .await
+        .expect("listeners still serving")
+        .unwrap()
+        .unwrap();
+    for client in &mut clients {
+        assert!(client.get_server_info(Empty {}).await.is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Checks the json log lines written while a client watches summaries from a mock
+//! exchange.
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use orderbook_agg::{book_summary::WatchSummaryRequest, logging::JsonLayer, Exchange};
+use serde_json::Value;
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
+
+mod support;
+use support::{duplex_client, MockExchange, Step};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// The subscriber is only set on this thread, so every task has to run on it.
+#[tokio::test(flavor = "current_thread")]
+async fn it_logs_json_lines_with_request_and_exchange_fields() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::new("info"))
+        .with(JsonLayer::new(move || writer.clone()));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    let mut client = duplex_client(support::aggregate(&[(Exchange::BITSTAMP, &bitstamp)])).await;
+    let mut request = tonic::Request::new(WatchSummaryRequest {
+        levels: 2,
+        ..Default::default()
+    });
+    request
+        .metadata_mut()
+        .insert("x-request-id", "test-request".parse().unwrap());
+    request.metadata_mut().insert(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse()
+            .unwrap(),
+    );
+    let mut stream = client.watch_summary(request).await.unwrap().into_inner();
+    timeout(Duration::from_secs(10), async {
+        while stream.next().await.unwrap().unwrap().bids.is_empty() {}
+    })
+    .await
+    .unwrap();
+    drop(stream);
+    drop(client);
+
+    let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines = written
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    for line in lines.iter() {
+        for field in ["timestamp", "level", "target", "message"] {
+            assert!(line[field].is_string(), "no {} in {}", field, line);
+        }
+    }
+    let request = lines
+        .iter()
+        .find(|line| {
+            line["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("Got a request")
+        })
+        .expect("the request is logged");
+    assert_eq!(request["request_id"], "test-request");
+    assert_eq!(request["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(request["parent_id"], "00f067aa0ba902b7");
+    assert_eq!(request["level"], "INFO");
+    assert_eq!(request["target"], "orderbook_agg::service");
+    assert!(
+        lines
+            .iter()
+            .any(|line| line["exchange"] == "BITSTAMP" && line["symbol"] == "BTCUSDT"),
+        "no exchange fields in {}",
+        written
+    );
+}
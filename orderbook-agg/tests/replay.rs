@@ -0,0 +1,92 @@
+//! Replays the recordings in `tests/fixtures/replay` through a server in the test process
+//! and checks what a client streams from it.
+use std::{path::Path, time::Duration};
+
+use orderbook_agg::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, Level, WatchSummaryRequest,
+    },
+    replay::{replay_symbol, ReplaySpeed},
+    service::{ExchangeOptions, OrderbookSummary},
+    Symbol,
+};
+use tokio::{net::TcpListener, sync::watch, time::timeout};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn it_streams_summaries_from_recordings_until_they_end() {
+    let options = ExchangeOptions::default();
+    let (tx_summary, replayed) = replay_symbol(
+        Path::new("../tests/fixtures/replay"),
+        &options,
+        Symbol::BTCUSDT,
+        ReplaySpeed::Scaled(2.0),
+        5,
+        10,
+        watch::channel(true).0,
+    )
+    .unwrap();
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let service =
+        OrderbookSummary::new(tx_summary, rx_shutdown).with_exchanges(options.exchanges.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .serve_with_incoming(
+                tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap(),
+            ),
+    );
+
+    let mut client = OrderbookAggregatorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    // Stop the server the way it stops once a replay finishes.
+    tokio::spawn(async move {
+        replayed.await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        tx_shutdown.send(true).unwrap();
+    });
+
+    let mut last = None;
+    let ended = timeout(Duration::from_secs(10), async {
+        while let Some(summary) = stream.next().await {
+            last = Some(summary.unwrap());
+        }
+    })
+    .await;
+    assert!(ended.is_ok(), "stream didn't end with the replay");
+
+    // Summaries have as many levels as the books have, two of each side after the last
+    // Binance update removed 29999.50.
+    let summary = last.unwrap();
+    assert!(!summary.degraded);
+    let levels = |levels: &[Level]| {
+        levels
+            .iter()
+            .map(|level| (level.exchange.clone(), level.price, level.quantity))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        levels(&summary.bids),
+        [
+            ("BITSTAMP".to_string(), 30001.0, 0.75),
+            ("BINANCE".to_string(), 30000.5, 0.5),
+        ]
+    );
+    assert_eq!(
+        levels(&summary.asks),
+        [
+            ("BINANCE".to_string(), 30001.5, 0.25),
+            ("BINANCE".to_string(), 30002.0, 3.0),
+        ]
+    );
+    assert_eq!(summary.spread, 0.5);
+}
@@ -0,0 +1,702 @@
+//! Calls the aggregator through a client connected over in-memory pipes, checking what
+//! each rpc returns and the statuses requests fail with.
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use orderbook_agg::{
+    book_summary::{
+        alert_rule::Condition, AlertRule, Empty, Level, MarketType, ReplaySummariesRequest,
+        ServerLimits, SpreadStatsRequest, Summary, SummaryRequest, WatchAlertsRequest,
+        WatchDivergenceRequest, WatchSummaryRequest, WatchTradesRequest,
+    },
+    checksum,
+    service::OrderbookSummary,
+    sinks::history::{Coverage, MemoryHistory, Rows, SummaryHistory, SummaryRow, SummaryStore},
+    spread_stats::{SpreadSamples, SpreadSamplesConfig},
+    trades::TradeFeed,
+    Exchange, Symbol,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{timeout, Instant},
+};
+use tokio_stream::StreamExt;
+use tonic::Code;
+
+mod support;
+use support::{duplex_client, MockExchange, Step};
+
+/// An aggregator whose summary task isn't running, for the rpcs that don't need one
+fn stopped(exchanges: Vec<Exchange>) -> OrderbookSummary {
+    let (tx_summary, _) = mpsc::channel(1);
+    OrderbookSummary::new(tx_summary, watch::channel(false).1).with_exchanges(exchanges)
+}
+
+#[tokio::test]
+async fn it_lists_the_symbols_and_exchanges_served() {
+    let mut client = duplex_client(stopped(vec![Exchange::BINANCE])).await;
+    let symbols = client.get_symbols(Empty {}).await.unwrap().into_inner();
+    assert_eq!(symbols.symbols, ["BTCUSDT"]);
+    let exchanges = client.get_exchanges(Empty {}).await.unwrap().into_inner();
+    assert_eq!(exchanges.exchanges, ["BINANCE"]);
+}
+
+#[tokio::test]
+async fn it_reports_the_server_info() {
+    let service = stopped(vec![Exchange::BITSTAMP]).with_limits(ServerLimits {
+        levels: 15,
+        price_range: 5,
+        binance_depth_speed_ms: 100,
+        snapshot_depth: "default".to_string(),
+        max_liquidity_bps: 50.0,
+    });
+    let mut client = duplex_client(service).await;
+    let info = client.get_server_info(Empty {}).await.unwrap().into_inner();
+    assert!(!info.version.is_empty());
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert!(!info.git_commit.is_empty());
+    assert!(info.build_timestamp > 0);
+    assert_eq!(info.exchanges, ["BITSTAMP"]);
+    assert_eq!(info.symbols, ["BTCUSDT"]);
+    assert_eq!(info.limits.unwrap().levels, 15);
+}
+
+#[tokio::test]
+async fn it_rejects_invalid_summary_requests() {
+    let service = stopped(vec![Exchange::BITSTAMP]).with_limits(ServerLimits {
+        levels: 10,
+        ..Default::default()
+    });
+    let mut client = duplex_client(service).await;
+    let request = |symbol: &str, levels| SummaryRequest {
+        symbol: symbol.to_string(),
+        levels,
+        ..Default::default()
+    };
+    let overrides = |overrides: &[(&str, &str)]| SummaryRequest {
+        symbol_overrides: overrides
+            .iter()
+            .map(|(exchange, native)| (exchange.to_string(), native.to_string()))
+            .collect(),
+        ..request("BTCUSDT", 10)
+    };
+    let cases = [
+        (
+            request("DOGEUSDT", 5),
+            Code::InvalidArgument,
+            "unknown symbol DOGEUSDT",
+        ),
+        (
+            request("ETHBTC", 5),
+            Code::FailedPrecondition,
+            "ETHBTC isn't being watched",
+        ),
+        (
+            request("DOGEUSDT", 20),
+            Code::InvalidArgument,
+            "unknown symbol DOGEUSDT, expected btcusdt, btcusd or ethbtc; levels must be at most 10",
+        ),
+        (
+            request("ETHBTC", 20),
+            Code::InvalidArgument,
+            "levels must be at most 10",
+        ),
+        (
+            SummaryRequest {
+                depth_notional: -1.0,
+                ..request("BTCUSDT", 10)
+            },
+            Code::InvalidArgument,
+            "depth_notional must be 0 or more",
+        ),
+        (
+            SummaryRequest {
+                bid_levels: Some(11),
+                ask_levels: Some(0),
+                ..request("BTCUSDT", 5)
+            },
+            Code::InvalidArgument,
+            "bid_levels must be at most 10, the levels summaries are made with",
+        ),
+        (
+            SummaryRequest {
+                bid_levels: Some(10),
+                ask_levels: Some(11),
+                ..request("BTCUSDT", 5)
+            },
+            Code::InvalidArgument,
+            "ask_levels must be at most 10, the levels summaries are made with",
+        ),
+        (
+            SummaryRequest {
+                market_types: vec![7],
+                ..request("BTCUSDT", 10)
+            },
+            Code::InvalidArgument,
+            "unknown market type 7, expected spot or futures",
+        ),
+        (
+            overrides(&[("kraken", "xbtusd")]),
+            Code::InvalidArgument,
+            "unknown exchange kraken",
+        ),
+        (
+            overrides(&[("binance", "BTCUSDT")]),
+            Code::InvalidArgument,
+            "symbol_overrides for BINANCE isn't aggregated by this server",
+        ),
+        (
+            overrides(&[("bitstamp", " ")]),
+            Code::InvalidArgument,
+            "symbol_overrides for BITSTAMP has an empty symbol",
+        ),
+        (
+            overrides(&[("bitstamp", "btcusdc")]),
+            Code::FailedPrecondition,
+            "symbol_overrides need a server connected to the exchanges",
+        ),
+        (
+            request("BTCUSDT", 10),
+            Code::Unavailable,
+            "summary stream is not running",
+        ),
+    ];
+    for (request, code, message) in cases {
+        let status = client.get_summary(request).await.unwrap_err();
+        assert_eq!(status.code(), code, "{}", status.message());
+        assert!(
+            status.message().starts_with(message),
+            "{:?} doesn't start with {:?}",
+            status.message(),
+            message
+        );
+    }
+}
+
+#[tokio::test]
+async fn it_watches_summaries_from_the_mock_exchanges() {
+    let bitstamp = MockExchange::bitstamp(vec![vec![
+        Step::json(serde_json::json!({
+            "data": {
+                "microtimestamp": "2",
+                "bids": [["30000.50", "0.50000000"]],
+                "asks": [],
+            },
+            "event": "data",
+        })),
+        Step::Hold,
+    ]])
+    .await;
+    let mut client = duplex_client(support::aggregate(&[(Exchange::BITSTAMP, &bitstamp)])).await;
+    let mut stream = client
+        .watch_summary(WatchSummaryRequest {
+            levels: 2,
+            smoothing_half_life_ms: 1000,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let summary = timeout(Duration::from_secs(10), async {
+        loop {
+            let summary = stream.next().await.unwrap().unwrap();
+            if summary.bids.first().map(|level| level.price) == Some(30000.5) {
+                return summary;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(summary.symbol, "BTCUSDT");
+    let bids = summary
+        .bids
+        .iter()
+        .map(|level| (level.exchange.as_str(), level.price, level.quantity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        bids,
+        [("BITSTAMP", 30000.5, 0.5), ("BITSTAMP", 30000.0, 1.0)]
+    );
+    assert_eq!(summary.spread, 0.5);
+    // The metrics are of the two levels of each side sent.
+    let metrics = summary.metrics.unwrap();
+    assert_eq!(metrics.mid, 30000.75);
+    assert_eq!(metrics.imbalance, (1.5 - 3.0) / 4.5);
+    let smoothed = summary.smoothed_metrics.unwrap();
+    assert!(smoothed.mid > 30000.0 && smoothed.mid <= 30000.75);
+    // The checksum is of the levels as this client received them.
+    checksum::verify(&summary).unwrap();
+    let mut cut = summary.clone();
+    cut.bids.pop();
+    assert!(checksum::verify(&cut).is_err());
+}
+
+#[tokio::test]
+async fn it_rejects_invalid_watch_requests() {
+    let service = stopped(vec![Exchange::BITSTAMP]).with_limits(ServerLimits {
+        levels: 10,
+        ..Default::default()
+    });
+    let mut client = duplex_client(service).await;
+    let cases = [
+        (
+            WatchSummaryRequest {
+                exchanges: vec!["KRAKEN".to_string()],
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "unknown exchange KRAKEN",
+        ),
+        (
+            WatchSummaryRequest {
+                exchanges: vec!["binance".to_string()],
+                ..Default::default()
+            },
+            Code::FailedPrecondition,
+            "BINANCE is not enabled on this server",
+        ),
+        (
+            WatchSummaryRequest {
+                exchange_weights: HashMap::from([("BITSTAMP".to_string(), -1.0)]),
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "invalid weight for BITSTAMP: weights must be 0 or more, got -1",
+        ),
+        (
+            WatchSummaryRequest {
+                update_speed_ms: 250,
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "update_speed_ms must be 0, 100 or 1000",
+        ),
+        (
+            WatchSummaryRequest {
+                update_speed_ms: 100,
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "update_speed_ms of 100 needs min_interval_ms to be set",
+        ),
+        (
+            WatchSummaryRequest {
+                levels: 11,
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "levels must be at most 10, the levels summaries are made with",
+        ),
+        (
+            WatchSummaryRequest {
+                liquidity_bps: vec![75.0],
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "liquidity_bps must be above 0 and at most 50",
+        ),
+        (
+            WatchSummaryRequest {
+                depth_notional: -1.0,
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "depth_notional must be 0 or more",
+        ),
+        (
+            SummaryRequest {
+                market_types: vec![7],
+                ..request("BTCUSDT", 10)
+            },
+            Code::InvalidArgument,
+            "unknown market type 7, expected spot or futures",
+        ),
+        (
+            WatchSummaryRequest {
+                smoothing_half_life_ms: 3_600_001,
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "smoothing_half_life_ms must be at most 3600000",
+        ),
+        // Every invalid field is listed, ahead of exchanges that aren't enabled.
+        (
+            WatchSummaryRequest {
+                exchanges: vec!["BINANCE".to_string(), "KRAKEN".to_string()],
+                update_speed_ms: 250,
+                levels: u32::MAX,
+                liquidity_bps: vec![0.0],
+                ..Default::default()
+            },
+            Code::InvalidArgument,
+            "unknown exchange KRAKEN, expected binance, bitstamp or binance_futures; \
+             update_speed_ms must be 0, 100 or 1000; \
+             liquidity_bps must be above 0 and at most 50; \
+             levels must be at most 10, the levels summaries are made with",
+        ),
+        (
+            WatchSummaryRequest {
+                levels: 10,
+                ..Default::default()
+            },
+            Code::Unavailable,
+            "summary stream is not running",
+        ),
+    ];
+    for (request, code, message) in cases {
+        let status = client.watch_summary(request).await.unwrap_err();
+        assert_eq!(status.code(), code, "{}", status.message());
+        assert!(
+            status.message().starts_with(message),
+            "{:?} doesn't start with {:?}",
+            status.message(),
+            message
+        );
+    }
+}
+
+#[tokio::test]
+async fn it_rejects_nan_and_infinite_values() {
+    let (_tx_trades, rx_trades) = mpsc::channel(1);
+    let feed = TradeFeed::new(Symbol::BTCUSDT, vec![Exchange::BITSTAMP], rx_trades);
+    let mut client = duplex_client(stopped(vec![Exchange::BITSTAMP]).with_trades(feed)).await;
+    let mut codes = Vec::new();
+    for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let status = client
+            .watch_summary(WatchSummaryRequest {
+                exchange_weights: HashMap::from([("BITSTAMP".to_string(), value)]),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        codes.push(("exchange_weights", value, status.code()));
+        let status = client
+            .watch_summary(WatchSummaryRequest {
+                liquidity_bps: vec![value],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        codes.push(("liquidity_bps", value, status.code()));
+        let status = client
+            .watch_summary(WatchSummaryRequest {
+                depth_notional: value,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        codes.push(("depth_notional", value, status.code()));
+        let status = client
+            .watch_divergence(WatchDivergenceRequest {
+                symbol: "BTCUSDT".to_string(),
+                threshold_bps: value,
+                hysteresis_bps: 0.0,
+            })
+            .await
+            .unwrap_err();
+        codes.push(("threshold_bps", value, status.code()));
+        let status = client
+            .watch_divergence(WatchDivergenceRequest {
+                symbol: "BTCUSDT".to_string(),
+                threshold_bps: 10.0,
+                hysteresis_bps: value,
+            })
+            .await
+            .unwrap_err();
+        codes.push(("hysteresis_bps", value, status.code()));
+        for condition in [
+            Condition::SpreadBpsAbove(value),
+            Condition::TopSizeBelow(value),
+        ] {
+            let status = client
+                .watch_alerts(WatchAlertsRequest {
+                    symbol: "BTCUSDT".to_string(),
+                    rules: vec![AlertRule {
+                        id: "rule".to_string(),
+                        condition: Some(condition),
+                        ..Default::default()
+                    }],
+                })
+                .await
+                .unwrap_err();
+            codes.push(("rules", value, status.code()));
+        }
+        let status = client
+            .watch_trades(WatchTradesRequest {
+                symbol: "BTCUSDT".to_string(),
+                min_quantity: value,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        codes.push(("min_quantity", value, status.code()));
+    }
+    for (field, value, code) in codes {
+        assert_eq!(code, Code::InvalidArgument, "{} of {}", field, value);
+    }
+}
+
+/// An aggregator replaying from a history of BTCUSDT summaries made at `timestamps`
+async fn replaying(timestamps: &[u64]) -> OrderbookSummary {
+    let mut history = MemoryHistory::new();
+    let rows = timestamps
+        .iter()
+        .enumerate()
+        .map(|(sequence, &timestamp)| {
+            let summary = Summary {
+                symbol: "BTCUSDT".to_string(),
+                sequence: sequence as u64 + 1,
+                timestamp,
+                spread: 1.0,
+                ..Default::default()
+            };
+            SummaryRow::new(&summary, 0).unwrap()
+        })
+        .collect::<Vec<_>>();
+    history.insert(&rows).await.unwrap();
+    stopped(vec![Exchange::BINANCE]).with_history(Arc::new(history))
+}
+
+#[tokio::test]
+async fn it_replays_the_summaries_kept_in_a_range() {
+    let mut client = duplex_client(replaying(&[100, 110, 120, 130]).await).await;
+    let response = client
+        .replay_summaries(ReplaySummariesRequest {
+            symbol: "btcusdt".to_string(),
+            start_time: 105,
+            end_time: 1000,
+            speed: 0.0,
+        })
+        .await
+        .unwrap();
+    let header = |name| response.metadata().get(name).unwrap().to_str().unwrap();
+    assert_eq!(header("x-covered-start"), "110");
+    assert_eq!(header("x-covered-end"), "130");
+    let summaries = response
+        .into_inner()
+        .map(|summary| summary.unwrap().sequence)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(summaries, [2, 3, 4]);
+
+    let response = client
+        .replay_summaries(ReplaySummariesRequest {
+            symbol: "BTCUSDT".to_string(),
+            start_time: 200,
+            end_time: 300,
+            speed: 0.0,
+        })
+        .await
+        .unwrap();
+    assert!(response.metadata().get("x-covered-start").is_none());
+    assert_eq!(response.into_inner().collect::<Vec<_>>().await.len(), 0);
+}
+
+#[tokio::test]
+async fn it_paces_replays_by_the_speed_asked_for() {
+    let mut client = duplex_client(replaying(&[100, 102, 104]).await).await;
+    let started = Instant::now();
+    let stream = client
+        .replay_summaries(ReplaySummariesRequest {
+            symbol: "BTCUSDT".to_string(),
+            start_time: 0,
+            end_time: 1000,
+            speed: 20.0,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 3);
+    // 4 seconds of summaries at 20 times the speed
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn it_rejects_invalid_replay_requests() {
+    let request = |symbol: &str, start_time, end_time, speed| ReplaySummariesRequest {
+        symbol: symbol.to_string(),
+        start_time,
+        end_time,
+        speed,
+    };
+    let mut client = duplex_client(stopped(vec![Exchange::BINANCE])).await;
+    let status = client
+        .replay_summaries(request("BTCUSDT", 0, 10, 0.0))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    assert_eq!(
+        status.message(),
+        "the server isn't keeping a summary history"
+    );
+
+    let mut client = duplex_client(replaying(&[100]).await).await;
+    let cases = [
+        (request("DOGEUSDT", 0, 10, 0.0), "unknown symbol DOGEUSDT"),
+        (
+            request("BTCUSDT", 10, 0, 0.0),
+            "start_time is after end_time",
+        ),
+        (request("BTCUSDT", 0, 10, -1.0), "speed must be 0 or more"),
+        (
+            request("BTCUSDT", 0, 10, f64::NAN),
+            "speed must be 0 or more",
+        ),
+    ];
+    for (request, message) in cases {
+        let status = client.replay_summaries(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(
+            status.message().starts_with(message),
+            "{}",
+            status.message()
+        );
+    }
+}
+
+/// A history whose rows never run out, recording when they're dropped
+#[derive(Debug, Default)]
+struct EndlessHistory {
+    dropped: Arc<AtomicBool>,
+}
+
+/// Sets the flag when the rows holding it are dropped
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl SummaryHistory for EndlessHistory {
+    async fn range(&self, symbol: &str, start: u64, _: u64) -> Result<(Option<Coverage>, Rows)> {
+        let flag = DropFlag(self.dropped.clone());
+        let symbol = symbol.to_string();
+        let rows = futures::stream::iter(0..).map(move |sequence| {
+            let _ = &flag;
+            let summary = Summary {
+                symbol: symbol.clone(),
+                sequence,
+                timestamp: start + sequence * 10,
+                ..Default::default()
+            };
+            SummaryRow::new(&summary, 0)
+        });
+        Ok((None, Box::pin(rows)))
+    }
+}
+
+#[tokio::test]
+async fn it_stops_reading_the_history_when_a_replay_is_cancelled() {
+    let history = Arc::new(EndlessHistory::default());
+    let dropped = history.dropped.clone();
+    let service = stopped(vec![Exchange::BINANCE]).with_history(history);
+    let mut client = duplex_client(service).await;
+    let mut stream = client
+        .replay_summaries(ReplaySummariesRequest {
+            symbol: "BTCUSDT".to_string(),
+            start_time: 0,
+            end_time: 1000,
+            speed: 1.0,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(stream.next().await.unwrap().unwrap().sequence, 0);
+    assert!(!dropped.load(Ordering::SeqCst));
+    drop(stream);
+    // The next row is 10 seconds away, the replay has to notice the client leaving first.
+    timeout(Duration::from_secs(2), async {
+        while !dropped.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn it_serves_spread_stats_for_the_symbols_watched() {
+    let samples = SpreadSamples::new(Symbol::BTCUSDT, SpreadSamplesConfig::default());
+    let level = |price| Level {
+        exchange: "BINANCE".to_string(),
+        price,
+        quantity: 2.0,
+        updated_at: None,
+        market_type: MarketType::Spot.into(),
+    };
+    let summary = Summary {
+        symbol: "BTCUSDT".to_string(),
+        spread: 0.5,
+        bids: vec![level(100.0)],
+        asks: vec![level(100.5)],
+        sequence: 1,
+        ..Default::default()
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    samples.record(Some(&summary), now - 30_000);
+    let service = stopped(vec![Exchange::BINANCE]).with_spread_samples(samples);
+    let mut client = duplex_client(service).await;
+
+    let request = |symbol: &str, window_minutes| SpreadStatsRequest {
+        symbol: symbol.to_string(),
+        window_minutes,
+    };
+    let stats = client
+        .get_spread_stats(request("btcusdt", 5))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(stats.symbol, "BTCUSDT");
+    assert_eq!(stats.average_spread, 0.5);
+    assert_eq!(stats.average_bid_size, 2.0);
+    assert_eq!(stats.samples, 1);
+    assert!(stats.covered_seconds >= 30.0, "{}", stats.covered_seconds);
+
+    let cases = [
+        (
+            request("ETHBTC", 5),
+            Code::FailedPrecondition,
+            "ETHBTC isn't being watched",
+        ),
+        (
+            request("DOGEUSDT", 5),
+            Code::InvalidArgument,
+            "unknown symbol DOGEUSDT",
+        ),
+        (
+            request("BTCUSDT", 0),
+            Code::InvalidArgument,
+            "window_minutes must be from 1 to 60",
+        ),
+        (
+            request("BTCUSDT", 61),
+            Code::InvalidArgument,
+            "window_minutes must be from 1 to 60",
+        ),
+    ];
+    for (request, code, message) in cases {
+        let status = client.get_spread_stats(request).await.unwrap_err();
+        assert_eq!(status.code(), code, "{}", status.message());
+        assert!(
+            status.message().starts_with(message),
+            "{}",
+            status.message()
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! Runs synthetic books at a high rate for a minute, checking memory stays flat and
+//! summaries keep flowing. Ignored by default, run it with
+//! `cargo test --release --test soak -- --ignored`.
+use std::time::{Duration, Instant};
+
+use orderbook_agg::{
+    service::ExchangeOptions,
+    synthetic::{synthetic_symbol, SyntheticConfig},
+    Symbol,
+};
+use tokio::sync::{oneshot, watch};
+
+const RUN: Duration = Duration::from_secs(60);
+/// Memory is measured from once the books have filled and every allocation has warmed up
+const WARM_UP: Duration = Duration::from_secs(10);
+/// Growth allowed after warming up
+const MAX_GROWTH_BYTES: usize = 16 * 1024 * 1024;
+/// Longest a client waits between summaries while the books are changing
+const MAX_GAP: Duration = Duration::from_millis(250);
+
+fn physical_mem() -> usize {
+    memory_stats::memory_stats()
+        .expect("memory stats are available")
+        .physical_mem
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn it_stays_bounded_under_a_minute_of_synthetic_load() {
+    let config = SyntheticConfig {
+        seed: 42,
+        depth: 200,
+        rate: 5000.0,
+        burstiness: 0.5,
+        ..Default::default()
+    };
+    let tx_summary = synthetic_symbol(
+        &ExchangeOptions::default(),
+        Symbol::BTCUSDT,
+        &config,
+        5,
+        15,
+        watch::channel(true).0,
+    )
+    .unwrap();
+    let (tx, rx) = oneshot::channel();
+    tx_summary.send(tx).await.unwrap();
+    let mut rx_summary = rx.await.unwrap();
+
+    let started = Instant::now();
+    let mut warm = None;
+    let mut last = Instant::now();
+    let mut max_gap = Duration::ZERO;
+    let mut summaries = 0u64;
+    while started.elapsed() < RUN {
+        tokio::time::timeout(MAX_GAP * 4, rx_summary.changed())
+            .await
+            .expect("summaries stopped")
+            .unwrap();
+        rx_summary.borrow_and_update().as_ref().unwrap();
+        summaries += 1;
+        if started.elapsed() > WARM_UP {
+            max_gap = max_gap.max(last.elapsed());
+            warm.get_or_insert_with(physical_mem);
+        }
+        last = Instant::now();
+    }
+
+    let growth = physical_mem().saturating_sub(warm.unwrap());
+    println!(
+        "{} summaries, longest gap {:?}, grew {} KiB",
+        summaries,
+        max_gap,
+        growth / 1024
+    );
+    assert!(
+        max_gap < MAX_GAP,
+        "longest gap between summaries {:?}",
+        max_gap
+    );
+    assert!(growth < MAX_GROWTH_BYTES, "grew {} bytes", growth);
+}
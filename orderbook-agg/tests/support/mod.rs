@@ -0,0 +1,410 @@
+//! Mock Binance and Bitstamp servers the real connectors can be pointed at through their
+//! [Endpoints], so the whole server can be tested without reaching an exchange.
+//!
+//! Each mock answers the REST calls the connectors make with canned bodies and plays a
+//! script of [Step]s to each websocket connection, the first script to the first
+//! connection and so on, with the last script played to any connections after that.
+//! [duplex_client] serves the aggregator over in-memory pipes instead, for tests that
+//! don't need a port.
+// Each integration test builds its own copy of this module and uses only part of it.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{SinkExt, StreamExt};
+use orderbook_agg::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer,
+    },
+    core::{
+        exchange_book::Endpoints,
+        http::{HttpClient, HttpConfig},
+    },
+    service::{start_symbol, Connector, ExchangeOptions, ExchangeSwitches, OrderbookSummary},
+    Exchange, Symbol,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_tungstenite::tungstenite::{handshake::server, Message};
+use tonic::transport::{server::Router, Channel, Endpoint, Server, Uri};
+
+/// What a mock websocket does next on a connection
+#[derive(Debug, Clone)]
+pub enum Step {
+    Send(Message),
+    Wait(Duration),
+    /// Closes the connection
+    Disconnect,
+    /// Keeps the connection open without sending anything else
+    Hold,
+}
+
+impl Step {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::Send(Message::Text(text.into()))
+    }
+
+    pub fn json(value: serde_json::Value) -> Self {
+        Self::text(value.to_string())
+    }
+
+    /// Sends a fixture from `tests/fixtures`
+    pub fn fixture(name: &str) -> Self {
+        Self::text(fixture(name))
+    }
+}
+
+pub fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!("../tests/fixtures/{}", name)).unwrap()
+}
+
+type Routes = Arc<Mutex<Vec<(String, String)>>>;
+
+pub struct MockExchange {
+    pub rest_addr: SocketAddr,
+    pub wss_addr: SocketAddr,
+    rest_path: &'static str,
+    routes: Routes,
+    /// The path of each websocket connection followed by the messages sent on it
+    subscriptions: Arc<Mutex<Vec<Vec<String>>>>,
+    /// The path of each REST request, without its query
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockExchange {
+    /// A Binance spot exchange listing BTCUSDT with a snapshot at update id 100, bids from
+    /// 30000 and asks from 30001.
+    pub async fn binance(scripts: Vec<Vec<Step>>) -> Self {
+        let routes = vec![
+            (
+                "exchangeInfo",
+                r#"{"symbols":[{"symbol":"BTCUSDT","baseAssetPrecision":8,"quoteAssetPrecision":8,"filters":[{"filterType":"PRICE_FILTER","tickSize":"0.01000000"}]}]}"#,
+            ),
+            (
+                "ticker/bookTicker",
+                r#"{"symbol":"BTCUSDT","bidPrice":"30000.00000000","bidQty":"1.00000000","askPrice":"30001.00000000","askQty":"1.00000000"}"#,
+            ),
+            (
+                "depth",
+                r#"{"lastUpdateId":100,"bids":[["30000.00000000","1.00000000"],["29999.00000000","2.00000000"]],"asks":[["30001.00000000","1.00000000"],["30002.00000000","2.00000000"]]}"#,
+            ),
+        ];
+        Self::start("/api/v3/", routes, scripts).await
+    }
+
+    /// A Binance futures exchange listing the BTCUSDT perpetual with a snapshot at update
+    /// id 100, bids from 30000 and asks from 30001.
+    pub async fn binance_futures(scripts: Vec<Vec<Step>>) -> Self {
+        let routes = vec![
+            (
+                "exchangeInfo",
+                r#"{"symbols":[{"symbol":"BTCUSDT","contractType":"PERPETUAL","quantityPrecision":3,"filters":[{"filterType":"PRICE_FILTER","tickSize":"0.10"}]}]}"#,
+            ),
+            (
+                "ticker/bookTicker",
+                r#"{"symbol":"BTCUSDT","bidPrice":"30000.00","bidQty":"1.000","askPrice":"30001.00","askQty":"1.000"}"#,
+            ),
+            (
+                "depth",
+                r#"{"lastUpdateId":100,"bids":[["30000.00","1.000"],["29999.00","2.000"]],"asks":[["30001.00","1.000"],["30002.00","2.000"]]}"#,
+            ),
+        ];
+        Self::start("/fapi/v1/", routes, scripts).await
+    }
+
+    /// A Bitstamp exchange listing btcusdt with a snapshot at microtimestamp 1, bids from
+    /// 30000 and asks from 30001.
+    pub async fn bitstamp(scripts: Vec<Vec<Step>>) -> Self {
+        let routes = vec![
+            (
+                "trading-pairs-info",
+                r#"[{"url_symbol":"btcusdt","base_decimals":8,"counter_decimals":2,"instant_order_counter_decimals":2}]"#,
+            ),
+            ("ticker/btcusdt", r#"{"bid":"30000.00","ask":"30001.00"}"#),
+            (
+                "order_book/btcusdt",
+                r#"{"timestamp":"0","microtimestamp":"1","bids":[["30000.00","1.00000000"],["29999.00","2.00000000"]],"asks":[["30001.00","1.00000000"],["30002.00","2.00000000"]]}"#,
+            ),
+        ];
+        Self::start("/api/v2/", routes, scripts).await
+    }
+
+    async fn start(
+        rest_path: &'static str,
+        routes: Vec<(&str, &str)>,
+        scripts: Vec<Vec<Step>>,
+    ) -> Self {
+        let routes = routes
+            .into_iter()
+            .map(|(path, body)| (format!("{}{}", rest_path, path), body.to_string()))
+            .collect::<Vec<_>>();
+        let routes = Arc::new(Mutex::new(routes));
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let rest_addr = serve_rest(routes.clone(), requests.clone()).await;
+        let wss_addr = serve_wss(scripts, subscriptions.clone()).await;
+        Self {
+            rest_addr,
+            wss_addr,
+            rest_path,
+            routes,
+            subscriptions,
+            requests,
+        }
+    }
+
+    pub fn rest_url(&self) -> String {
+        format!("http://{}{}", self.rest_addr, self.rest_path)
+    }
+
+    pub fn wss_url(&self) -> String {
+        format!("ws://{}/ws/", self.wss_addr)
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints::new(&self.rest_url(), &self.wss_url()).unwrap()
+    }
+
+    /// Answers requests for `path` under the REST base with `body` from now on.
+    pub fn set_rest(&self, path: &str, body: &str) {
+        let path = format!("{}{}", self.rest_path, path);
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|(route, _)| *route != path);
+        routes.push((path, body.to_string()));
+    }
+
+    /// The path each websocket connection asked for and the messages sent on it, in the
+    /// order they connected
+    pub fn subscriptions(&self) -> Vec<Vec<String>> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
+    }
+
+    /// How many REST requests have been made for `path` under the REST base
+    pub fn requests(&self, path: &str) -> usize {
+        let path = format!("{}{}", self.rest_path, path);
+        let requests = self.requests.lock().unwrap();
+        requests.iter().filter(|request| **request == path).count()
+    }
+}
+
+/// Answers each request with the body of the route for its path, ignoring the query,
+/// or 404, recording each path in `requests`.
+async fn serve_rest(routes: Routes, requests: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let routes = routes.clone();
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                while let Ok(n) = socket.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let target = request.split(' ').nth(1).unwrap_or_default();
+                    let path = target.split('?').next().unwrap_or_default();
+                    requests.lock().unwrap().push(path.to_string());
+                    let body = routes
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|(route, _)| route == path)
+                        .map(|(_, body)| body.clone());
+                    let (status, body) = match body {
+                        Some(body) => ("200 OK", body),
+                        None => ("404 Not Found", "{}".to_string()),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    if socket.write_all(response.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+// The handshake callback's error type is set by tungstenite.
+#[allow(clippy::result_large_err)]
+async fn serve_wss(
+    scripts: Vec<Vec<Step>>,
+    subscriptions: Arc<Mutex<Vec<Vec<String>>>>,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let mut path = String::new();
+            let callback = |request: &server::Request, response| {
+                path = request.uri().path().to_string();
+                Ok(response)
+            };
+            let Ok(ws) = tokio_tungstenite::accept_hdr_async(socket, callback).await else {
+                continue;
+            };
+            let connection = {
+                let mut subscriptions = subscriptions.lock().unwrap();
+                subscriptions.push(vec![path]);
+                subscriptions.len() - 1
+            };
+            let script = scripts
+                .get(connection)
+                .or(scripts.last())
+                .cloned()
+                .unwrap_or_default();
+            let (mut sink, mut stream) = ws.split();
+            let received = subscriptions.clone();
+            let reader = tokio::spawn(async move {
+                while let Some(Ok(message)) = stream.next().await {
+                    if let Message::Text(text) = message {
+                        received.lock().unwrap()[connection].push(text);
+                    }
+                }
+            });
+            tokio::spawn(async move {
+                for step in script {
+                    match step {
+                        Step::Send(message) => {
+                            if sink.send(message).await.is_err() {
+                                return;
+                            }
+                        }
+                        Step::Wait(duration) => tokio::time::sleep(duration).await,
+                        Step::Disconnect => {
+                            let _ = sink.close().await;
+                            reader.abort();
+                            return;
+                        }
+                        Step::Hold => break,
+                    }
+                }
+                // Connections stay open once their script has played.
+                let _ = reader.await;
+            });
+        }
+    });
+    addr
+}
+
+/// The aggregator for BTCUSDT from the real connectors pointed at `mocks`
+pub fn aggregate(mocks: &[(Exchange, &MockExchange)]) -> OrderbookSummary {
+    aggregate_switched(mocks).0
+}
+
+/// [aggregate] along with the switches that turn the mocks' connections off and on
+pub fn aggregate_switched(
+    mocks: &[(Exchange, &MockExchange)],
+) -> (OrderbookSummary, ExchangeSwitches) {
+    aggregate_with(mocks, ExchangeOptions::default())
+}
+
+/// [aggregate_switched] with `options`, their exchanges, endpoints and switches replaced
+/// by the mocks'
+pub fn aggregate_with(
+    mocks: &[(Exchange, &MockExchange)],
+    options: ExchangeOptions,
+) -> (OrderbookSummary, ExchangeSwitches) {
+    let exchanges = mocks
+        .iter()
+        .map(|(exchange, _)| *exchange)
+        .collect::<Vec<_>>();
+    let switches = ExchangeSwitches::new(&exchanges);
+    let options = ExchangeOptions {
+        exchanges,
+        endpoints: mocks
+            .iter()
+            .map(|(exchange, mock)| (*exchange, mock.endpoints()))
+            .collect::<HashMap<_, _>>(),
+        switches: switches.clone(),
+        ..options
+    };
+    let http = HttpClient::new(&HttpConfig::default()).unwrap();
+    let connector = Connector::new(http.clone(), &options, 5, 10);
+    let tx_summary = start_symbol(
+        http,
+        &options,
+        Symbol::BTCUSDT,
+        5,
+        10,
+        watch::channel(true).0,
+    );
+    // The shutdown sender is leaked so streams run for the rest of the test.
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    std::mem::forget(tx_shutdown);
+    let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+        .with_exchanges(options.exchanges)
+        .with_switches(switches.clone())
+        .with_stats(options.stats)
+        .with_quotes(options.quotes)
+        .with_books(options.books)
+        .with_backfill(options.backfill)
+        .with_listings(options.listings)
+        .with_depths(options.depths)
+        .with_connector(connector);
+    (service, switches)
+}
+
+/// Serves the aggregator on a free port, returning its url.
+pub async fn serve(service: OrderbookSummary) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .serve_with_incoming(
+                tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap(),
+            ),
+    );
+    format!("http://{}", addr)
+}
+
+/// Serves the aggregator over in-memory pipes and returns a client connected to it. Each
+/// connection the client makes gets a new pipe, so it can reconnect like over a socket.
+pub async fn duplex_client(service: OrderbookSummary) -> OrderbookAggregatorClient<Channel> {
+    let router = Server::builder().add_service(OrderbookAggregatorServer::new(service));
+    OrderbookAggregatorClient::new(duplex_channel(router).await)
+}
+
+/// Serves `router` over in-memory pipes, returning a channel to it for any of its clients
+pub async fn duplex_channel(router: Router) -> Channel {
+    let (tx_incoming, rx_incoming) = mpsc::channel::<io::Result<DuplexStream>>(4);
+    tokio::spawn(router.serve_with_incoming(ReceiverStream::new(rx_incoming)));
+    // The uri is only used for the requests' authority, nothing is resolved.
+    Endpoint::from_static("http://duplex.test")
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let tx_incoming = tx_incoming.clone();
+            async move {
+                let (client, server) = tokio::io::duplex(64 * 1024);
+                tx_incoming
+                    .send(Ok(server))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "server stopped"))?;
+                Ok::<_, io::Error>(client)
+            }
+        }))
+        .await
+        .unwrap()
+}
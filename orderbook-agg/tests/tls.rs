@@ -0,0 +1,305 @@
+//! Serves the aggregator over mutual TLS with certificates from a test CA, and calls it
+//! with a client certificate the CA issued, one from another CA and none.
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    ssl::{SslConnector, SslMethod},
+    x509::{
+        extension::{BasicConstraints, SubjectAlternativeName},
+        X509NameBuilder, X509,
+    },
+};
+use orderbook_agg::{
+    access::{PeerFilter, PeerRules, UnknownPeers},
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, Empty, WatchSummaryRequest,
+    },
+    tls::{self, ClientAuth, TlsAcceptor, TlsConfig},
+    Exchange,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use tonic::{
+    service::interceptor::InterceptedService,
+    transport::{Channel, Endpoint, Server, Uri},
+    Request,
+};
+
+mod support;
+use support::{MockExchange, Step};
+
+struct Issued {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+/// A certificate for `name`, issued by `issuer` or self-signed without one
+fn issue(name: &str, issuer: Option<&Issued>, ca: bool, alt_names: &[&str]) -> Issued {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+    let subject = subject.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(rand::random::<u32>()).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder
+        .set_issuer_name(issuer.map_or(&subject, |issuer| issuer.cert.subject_name()))
+        .unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+        .unwrap();
+    if ca {
+        let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+        builder.append_extension(constraints).unwrap();
+    }
+    if !alt_names.is_empty() {
+        let mut san = SubjectAlternativeName::new();
+        for alt_name in alt_names {
+            match alt_name.parse::<std::net::IpAddr>() {
+                Ok(_) => san.ip(alt_name),
+                Err(_) => san.dns(alt_name),
+            };
+        }
+        let san = san
+            .build(&builder.x509v3_context(issuer.map(|issuer| &*issuer.cert), None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+    }
+    let signer = issuer.map_or(&key, |issuer| &issuer.key);
+    builder.sign(signer, MessageDigest::sha256()).unwrap();
+    Issued {
+        cert: builder.build(),
+        key,
+    }
+}
+
+/// The test CA, its server certificate, a client certificate it issued and one issued by
+/// another CA
+struct Pki {
+    ca: Issued,
+    server: Issued,
+    client: Issued,
+    untrusted: Issued,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca = issue("test ca", None, true, &[]);
+        let server = issue("localhost", Some(&ca), false, &["localhost", "127.0.0.1"]);
+        let client = issue("pricing", Some(&ca), false, &["pricing.internal"]);
+        let other_ca = issue("other ca", None, true, &[]);
+        let untrusted = issue("pricing", Some(&other_ca), false, &["pricing.internal"]);
+        Self {
+            ca,
+            server,
+            client,
+            untrusted,
+        }
+    }
+
+    /// The server's config, its files written to the temp dir
+    fn config(&self, name: &str, client_auth: ClientAuth) -> TlsConfig {
+        let write = |file: &str, pem: Vec<u8>| {
+            let path = std::env::temp_dir().join(format!(
+                "obagg-tls-{}-{}-{}",
+                name,
+                std::process::id(),
+                file
+            ));
+            std::fs::write(&path, pem).unwrap();
+            path
+        };
+        let cert: PathBuf = write("server.pem", self.server.cert.to_pem().unwrap());
+        let key = write(
+            "server.key",
+            self.server.key.private_key_to_pem_pkcs8().unwrap(),
+        );
+        let client_ca = write("ca.pem", self.ca.cert.to_pem().unwrap());
+        TlsConfig {
+            cert,
+            key,
+            client_ca: Some(client_ca),
+            client_auth,
+        }
+    }
+}
+
+/// The common names of the client certificates of the calls served, None for calls
+/// without one
+type Seen = Arc<Mutex<Vec<Option<String>>>>;
+
+/// Serves the aggregator streaming from a mock Bitstamp over TLS with `config`, only to
+/// peers on the loopback, returning its address and the client identities of its calls.
+async fn serve(config: &TlsConfig) -> (u16, Seen) {
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    // The mock is kept for the rest of the test along with the server.
+    let bitstamp = Box::leak(Box::new(bitstamp));
+    let service = support::aggregate(&[(Exchange::BITSTAMP, bitstamp)]);
+    let seen = Seen::default();
+    let record = seen.clone();
+    #[allow(clippy::result_large_err)] // The signature is tonic's.
+    let interceptor = move |request: Request<()>| {
+        let client = tls::client_identity(&request);
+        record
+            .lock()
+            .unwrap()
+            .push(client.and_then(|client| client.common_name.clone()));
+        Ok(request)
+    };
+    let service = InterceptedService::new(OrderbookAggregatorServer::new(service), interceptor);
+    // Calls only get through with the peer address taken from the TLS connection.
+    let peers = PeerFilter::new(PeerRules {
+        allow: orderbook_agg::access::parse_nets(&["127.0.0.0/8"]).unwrap(),
+        deny: vec![],
+        unknown: UnknownPeers::Deny,
+    });
+    let acceptor = TlsAcceptor::new(config).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(
+        Server::builder()
+            .layer(tonic::service::interceptor(peers))
+            .add_service(service)
+            .serve_with_incoming(acceptor.incoming(listener)),
+    );
+    (port, seen)
+}
+
+/// A channel to the server on `port` trusting the test CA, presenting `client`'s
+/// certificate when given one
+async fn connect(pki: &Pki, port: u16, client: Option<&Issued>) -> Channel {
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder
+        .cert_store_mut()
+        .add_cert(pki.ca.cert.clone())
+        .unwrap();
+    builder.set_alpn_protos(b"\x02h2").unwrap();
+    if let Some(client) = client {
+        builder.set_certificate(&client.cert).unwrap();
+        builder.set_private_key(&client.key).unwrap();
+    }
+    let connector = builder.build();
+    Endpoint::from_shared(format!("http://localhost:{}", port))
+        .unwrap()
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let ssl = connector
+                .configure()
+                .unwrap()
+                .into_ssl("localhost")
+                .unwrap();
+            async move {
+                let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+                tls::connect(ssl, stream)
+                    .await
+                    .map_err(std::io::Error::other)
+            }
+        }))
+        .await
+        .unwrap()
+}
+
+/// Calls GetExchanges and waits for the first summary of WatchSummary, returning whether
+/// each got through
+async fn call(channel: Channel) -> (bool, bool) {
+    let mut client = OrderbookAggregatorClient::new(channel);
+    let unary = client.get_exchanges(Empty {}).await.is_ok();
+    let streamed = match client.watch_summary(WatchSummaryRequest::default()).await {
+        Ok(stream) => {
+            let mut stream = stream.into_inner();
+            matches!(
+                tokio::time::timeout(Duration::from_secs(10), stream.next()).await,
+                Ok(Some(Ok(_)))
+            )
+        }
+        Err(_) => false,
+    };
+    (unary, streamed)
+}
+
+#[tokio::test]
+async fn it_serves_clients_with_certificates_the_ca_issued() {
+    let pki = Pki::new();
+    let config = pki.config("required", ClientAuth::Required);
+    let (port, seen) = serve(&config).await;
+
+    assert_eq!(
+        call(connect(&pki, port, Some(&pki.client)).await).await,
+        (true, true)
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [Some("pricing".to_string()), Some("pricing".to_string())]
+    );
+
+    // Both fail the handshake, so neither call reaches the service.
+    assert_eq!(
+        call(connect(&pki, port, Some(&pki.untrusted)).await).await,
+        (false, false)
+    );
+    assert_eq!(call(connect(&pki, port, None).await).await, (false, false));
+    assert_eq!(seen.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn it_serves_clients_without_certificates_when_optional() {
+    let pki = Pki::new();
+    let config = pki.config("optional", ClientAuth::Optional);
+    let (port, seen) = serve(&config).await;
+
+    assert_eq!(call(connect(&pki, port, None).await).await, (true, true));
+    assert_eq!(
+        call(connect(&pki, port, Some(&pki.client)).await).await,
+        (true, true)
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            None,
+            None,
+            Some("pricing".to_string()),
+            Some("pricing".to_string())
+        ]
+    );
+    // A certificate that is presented still has to verify.
+    assert_eq!(
+        call(connect(&pki, port, Some(&pki.untrusted)).await).await,
+        (false, false)
+    );
+}
+
+#[test]
+fn it_reads_when_the_server_certificate_expires() {
+    let pki = Pki::new();
+    let acceptor = TlsAcceptor::new(&pki.config("expiry", ClientAuth::Required)).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let days = (acceptor.not_after() - now) as f64 / 86_400.0;
+    assert!((29.9..=30.0).contains(&days), "{}", days);
+
+    let mut keyless = pki.config("keyless", ClientAuth::Required);
+    keyless.key = keyless.cert.clone();
+    assert!(TlsAcceptor::new(&keyless).is_err());
+}
@@ -0,0 +1,96 @@
+//! Serves the aggregator on a unix domain socket and streams summaries over it.
+#![cfg(unix)]
+use std::time::Duration;
+
+use orderbook_agg::{
+    book_summary::{
+        orderbook_aggregator_client::OrderbookAggregatorClient,
+        orderbook_aggregator_server::OrderbookAggregatorServer, WatchSummaryRequest,
+    },
+    uds::{self, SocketMode},
+    Exchange,
+};
+use tokio::{net::UnixStream, sync::watch, time::timeout};
+use tokio_stream::StreamExt;
+use tonic::transport::{Endpoint, Server, Uri};
+
+mod support;
+use support::{MockExchange, Step};
+
+fn bitstamp_update(microtimestamp: u64, bid: (&str, &str)) -> Step {
+    Step::json(serde_json::json!({
+        "data": {
+            "timestamp": "0",
+            "microtimestamp": microtimestamp.to_string(),
+            "bids": [[bid.0, bid.1]],
+            "asks": [],
+        },
+        "channel": "diff_order_book_btcusdt",
+        "event": "data",
+    }))
+}
+
+#[tokio::test]
+async fn it_streams_summaries_over_a_unix_socket() {
+    let wait = || Step::Wait(Duration::from_millis(300));
+    // Late enough for the client to have the snapshot's summary first.
+    let bitstamp = MockExchange::bitstamp(vec![vec![
+        Step::Wait(Duration::from_secs(1)),
+        bitstamp_update(2, ("30000.25", "0.25000000")),
+        wait(),
+        bitstamp_update(3, ("30000.50", "0.50000000")),
+        wait(),
+        bitstamp_update(4, ("30000.75", "0.75000000")),
+        Step::Hold,
+    ]])
+    .await;
+    let service = support::aggregate(&[(Exchange::BITSTAMP, &bitstamp)]);
+
+    let path = std::env::temp_dir().join(format!("obagg-uds-{}.sock", std::process::id()));
+    let (incoming, file) = uds::bind(&path, SocketMode::default()).unwrap();
+    let (tx_stopped, mut rx_stopped) = watch::channel(false);
+    let server = tokio::spawn(
+        Server::builder()
+            .add_service(OrderbookAggregatorServer::new(service))
+            .serve_with_incoming_shutdown(incoming, async move {
+                let _ = rx_stopped.wait_for(|stopped| *stopped).await;
+            }),
+    );
+
+    // The uri is only used for the requests' authority, the connector dials the socket.
+    let socket = path.clone();
+    let channel = Endpoint::from_static("http://uds.test")
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            UnixStream::connect(socket.clone())
+        }))
+        .await
+        .unwrap();
+    let mut stream = OrderbookAggregatorClient::new(channel)
+        .watch_summary(WatchSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut best_bids = vec![];
+    while best_bids.last() != Some(&30000.75) {
+        let summary = timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("no summary in time")
+            .unwrap()
+            .unwrap();
+        // Summaries from before the book's snapshot have no levels.
+        let Some(best_bid) = summary.bids.first().map(|level| level.price) else {
+            continue;
+        };
+        if best_bids.last() != Some(&best_bid) {
+            best_bids.push(best_bid);
+        }
+    }
+    assert_eq!(best_bids, [30000.0, 30000.25, 30000.5, 30000.75]);
+
+    // The socket file is removed once the server has stopped.
+    tx_stopped.send(true).unwrap();
+    drop(stream);
+    server.await.unwrap().unwrap();
+    drop(file);
+    assert!(!path.exists());
+}
@@ -0,0 +1,111 @@
+//! Warms up BTCUSDT next to a server aggregating another symbol, and watches it.
+use std::time::{Duration, Instant};
+
+use orderbook_agg::{
+    book_summary::{Empty, TickerRequest, WatchBookRequest, WatchSummaryRequest},
+    core::http::{HttpClient, HttpConfig},
+    service::{start_symbol, ExchangeOptions, OrderbookSummary},
+    warmup::warm_up,
+    Exchange, Symbol,
+};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::StreamExt;
+
+mod support;
+use support::{MockExchange, Step};
+
+#[tokio::test]
+async fn it_serves_a_warm_symbol_without_fetching_its_book_again() {
+    let bitstamp = MockExchange::bitstamp(vec![vec![Step::Hold]]).await;
+    let options = ExchangeOptions {
+        exchanges: vec![Exchange::BITSTAMP],
+        endpoints: [(Exchange::BITSTAMP, bitstamp.endpoints())].into(),
+        ..Default::default()
+    };
+    let http = HttpClient::new(&HttpConfig::default()).unwrap();
+    let warm = warm_up(&[Symbol::BTCUSDT], 4, |symbol| {
+        start_symbol(
+            http.clone(),
+            &options,
+            symbol,
+            5,
+            10,
+            watch::channel(true).0,
+        )
+    })
+    .await;
+    let snapshots = bitstamp.requests("order_book/btcusdt");
+    assert_eq!(snapshots, 1);
+
+    // The server's own symbol has no summary task, only the warm one is watched.
+    let (tx_summary, _) = mpsc::channel(1);
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let service = OrderbookSummary::new(tx_summary, rx_shutdown)
+        .with_exchanges(options.exchanges.clone())
+        .with_symbols(vec![Symbol::ETHBTC])
+        .with_warm_symbols(warm)
+        .with_quotes(options.quotes.clone())
+        .with_books(options.books.clone());
+    let mut client = support::duplex_client(service).await;
+    let info = client.get_server_info(Empty {}).await.unwrap().into_inner();
+    assert_eq!(info.symbols, ["ETHBTC", "BTCUSDT"]);
+    let symbols = client.get_symbols(Empty {}).await.unwrap().into_inner();
+    assert_eq!(symbols.symbols, info.symbols);
+
+    let started = Instant::now();
+    let request = WatchSummaryRequest {
+        symbol: "BTCUSDT".to_string(),
+        ..Default::default()
+    };
+    let mut stream = client.watch_summary(request).await.unwrap().into_inner();
+    let summary = tokio::time::timeout(Duration::from_secs(10), stream.next())
+        .await
+        .expect("no summary in time")
+        .unwrap()
+        .unwrap();
+    // The first summary already has the book's levels.
+    assert_eq!(summary.bids.first().map(|level| level.price), Some(30000.0));
+    assert!(
+        started.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(bitstamp.requests("order_book/btcusdt"), snapshots);
+
+    // The ticker and book are served from the warm symbol's summary task too.
+    let request = TickerRequest {
+        symbol: "BTCUSDT".to_string(),
+    };
+    let ticker = client
+        .get_ticker(request.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(ticker.bid, 30000.0);
+    let mut tickers = client.watch_ticker(request).await.unwrap().into_inner();
+    let ticker = tokio::time::timeout(Duration::from_secs(10), tickers.next())
+        .await
+        .expect("no ticker in time")
+        .unwrap()
+        .unwrap();
+    assert_eq!(ticker.symbol, "BTCUSDT");
+    let request = WatchBookRequest {
+        symbol: "BTCUSDT".to_string(),
+        ..Default::default()
+    };
+    let mut books = client.watch_book(request).await.unwrap().into_inner();
+    tokio::time::timeout(Duration::from_secs(10), books.next())
+        .await
+        .expect("no book in time")
+        .unwrap()
+        .unwrap();
+
+    // Symbols that are neither the server's nor warm are refused.
+    let request = WatchSummaryRequest {
+        symbol: "BTCUSD".to_string(),
+        ..Default::default()
+    };
+    let err = client.watch_summary(request).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    drop(tx_shutdown);
+}
@@ -11,16 +11,31 @@ pub enum Action {
     Sleep,
     IncrementDelay,
     DecrementDelay,
+    MoreLevels,
+    FewerLevels,
+    ScrollUp,
+    ScrollDown,
+    Recenter,
+    /// Shows or hides the server's exchange at this index
+    ToggleExchange(usize),
 }
 
 impl Action {
     /// All available actions
     pub fn iterator() -> Iter<'static, Action> {
-        static ACTIONS: [Action; 4] = [
+        static ACTIONS: [Action; 12] = [
             Action::Quit,
             Action::Sleep,
             Action::IncrementDelay,
             Action::DecrementDelay,
+            Action::MoreLevels,
+            Action::FewerLevels,
+            Action::ScrollUp,
+            Action::ScrollDown,
+            Action::Recenter,
+            Action::ToggleExchange(0),
+            Action::ToggleExchange(1),
+            Action::ToggleExchange(2),
         ];
         ACTIONS.iter()
     }
@@ -32,6 +47,14 @@ impl Action {
             Action::Sleep => &[Key::Char('s')],
             Action::IncrementDelay => &[Key::Char('+')],
             Action::DecrementDelay => &[Key::Char('-')],
+            Action::MoreLevels => &[Key::Char('>')],
+            Action::FewerLevels => &[Key::Char('<')],
+            Action::ScrollUp => &[Key::Up],
+            Action::ScrollDown => &[Key::Down],
+            Action::Recenter => &[Key::Char('c')],
+            Action::ToggleExchange(0) => &[Key::Char('1')],
+            Action::ToggleExchange(1) => &[Key::Char('2')],
+            Action::ToggleExchange(_) => &[Key::Char('3')],
         }
     }
 }
@@ -44,6 +67,14 @@ impl Display for Action {
             Action::Sleep => "Sleep",
             Action::IncrementDelay => "Increment delay",
             Action::DecrementDelay => "Decrement delay",
+            Action::MoreLevels => "More levels",
+            Action::FewerLevels => "Fewer levels",
+            Action::ScrollUp => "Scroll up",
+            Action::ScrollDown => "Scroll down",
+            Action::Recenter => "Recenter",
+            Action::ToggleExchange(index) => {
+                return write!(f, "Toggle exchange {}", index + 1);
+            }
         };
         write!(f, "{}", str)
     }
@@ -137,6 +168,16 @@ mod tests {
         .into();
     }
 
+    #[test]
+    fn should_find_exchange_toggles_by_index() {
+        let actions: Actions = vec![Action::ToggleExchange(0), Action::ToggleExchange(2)].into();
+        assert_eq!(
+            actions.find(Key::Char('3')),
+            Some(&Action::ToggleExchange(2))
+        );
+        assert_eq!(actions.find(Key::Char('2')), None);
+    }
+
     #[test]
     #[should_panic]
     fn should_panic_when_create_actions_conflict_key() {
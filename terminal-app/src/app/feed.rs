@@ -0,0 +1,245 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use orderbook_agg::book_summary::{Summary, WatchSummaryRequest};
+
+/// Fewest and most levels of each side shown on the ladder
+pub const MIN_LEVELS: u32 = 1;
+pub const MAX_LEVELS: u32 = 50;
+
+/// The connection to the server and what's asked of it
+#[derive(Debug, Clone)]
+pub struct Feed {
+    /// The symbol asked for on the command line, the server's first when none was
+    symbol: Option<String>,
+    symbols: Vec<String>,
+    /// The server's exchanges and whether each is shown
+    exchanges: Vec<(String, bool)>,
+    levels: u32,
+    /// Rows the ladder is scrolled up by from the spread, down when negative
+    offset: i32,
+    /// Why the stream ended while reconnecting
+    disconnected: Option<String>,
+    /// When summaries arrived over the last second
+    received: VecDeque<Instant>,
+    /// When each exchange last had levels in a summary
+    last_seen: HashMap<String, Instant>,
+}
+
+impl Feed {
+    pub fn new(symbol: Option<String>, levels: u32) -> Self {
+        Self {
+            symbol,
+            symbols: Vec::new(),
+            exchanges: Vec::new(),
+            levels: levels.clamp(MIN_LEVELS, MAX_LEVELS),
+            offset: 0,
+            disconnected: Some("connecting".to_string()),
+            received: VecDeque::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Takes the server's symbols and exchanges on each connect, keeping hidden any
+    /// exchange that was hidden before.
+    pub fn connected(&mut self, symbols: Vec<String>, exchanges: Vec<String>) {
+        self.symbols = symbols;
+        self.exchanges = exchanges
+            .into_iter()
+            .map(|exchange| {
+                let shown = self
+                    .exchanges
+                    .iter()
+                    .find(|(e, _)| *e == exchange)
+                    .is_none_or(|(_, shown)| *shown);
+                (exchange, shown)
+            })
+            .collect();
+        self.disconnected = None;
+    }
+
+    pub fn disconnected(&mut self, reason: String) {
+        self.disconnected = Some(reason);
+    }
+
+    pub fn received(&mut self, summary: &Summary, now: Instant) {
+        self.received.push_back(now);
+        while self
+            .received
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > Duration::from_secs(1))
+        {
+            self.received.pop_front();
+        }
+        for level in summary.bids.iter().chain(summary.asks.iter()) {
+            self.last_seen.insert(level.exchange.clone(), now);
+        }
+    }
+
+    /// The symbol shown, and whether the server serves it
+    pub fn symbol(&self) -> (Option<&str>, bool) {
+        match &self.symbol {
+            Some(symbol) => (
+                Some(symbol.as_str()),
+                self.symbols.is_empty()
+                    || self.symbols.iter().any(|s| s.eq_ignore_ascii_case(symbol)),
+            ),
+            None => (self.symbols.first().map(|s| s.as_str()), true),
+        }
+    }
+
+    pub fn exchanges(&self) -> &[(String, bool)] {
+        &self.exchanges
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    pub fn disconnected_reason(&self) -> Option<&str> {
+        self.disconnected.as_deref()
+    }
+
+    /// Summaries received over the last second before `now`
+    pub fn update_rate(&self, now: Instant) -> usize {
+        self.received
+            .iter()
+            .filter(|at| now.duration_since(**at) <= Duration::from_secs(1))
+            .count()
+    }
+
+    /// How long since `exchange` last had levels in a summary, none if it never has
+    pub fn staleness(&self, exchange: &str, now: Instant) -> Option<Duration> {
+        self.last_seen
+            .get(exchange)
+            .map(|at| now.duration_since(*at))
+    }
+
+    /// Shows or hides the exchange at `index`, keeping at least one shown. Returns whether
+    /// anything changed.
+    pub fn toggle_exchange(&mut self, index: usize) -> bool {
+        let shown = self.exchanges.iter().filter(|(_, shown)| *shown).count();
+        match self.exchanges.get_mut(index) {
+            Some((_, true)) if shown == 1 => false,
+            Some((_, shown)) => {
+                *shown = !*shown;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn more_levels(&mut self) {
+        self.levels = (self.levels + 1).min(MAX_LEVELS);
+    }
+
+    pub fn fewer_levels(&mut self) {
+        self.levels = (self.levels - 1).max(MIN_LEVELS);
+    }
+
+    pub fn scroll(&mut self, rows: i32) {
+        let max = self.levels as i32;
+        self.offset = (self.offset + rows).clamp(-max, max);
+    }
+
+    pub fn recenter(&mut self) {
+        self.offset = 0;
+    }
+
+    /// The request for the levels and exchanges shown. All exchanges are asked for when
+    /// none are hidden, so exchanges the server adds are shown too.
+    pub fn watch_request(&self) -> WatchSummaryRequest {
+        let exchanges = if self.exchanges.iter().all(|(_, shown)| *shown) {
+            Vec::new()
+        } else {
+            self.exchanges
+                .iter()
+                .filter(|(_, shown)| *shown)
+                .map(|(exchange, _)| exchange.clone())
+                .collect()
+        };
+        WatchSummaryRequest {
+            levels: self.levels,
+            exchanges,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orderbook_agg::book_summary::Level;
+
+    use super::*;
+
+    fn connected_feed() -> Feed {
+        let mut feed = Feed::new(None, 10);
+        feed.connected(
+            vec!["BTCUSDT".to_string()],
+            vec!["BITSTAMP".to_string(), "BINANCE".to_string()],
+        );
+        feed
+    }
+
+    #[test]
+    fn should_ask_for_the_exchanges_shown() {
+        let mut feed = connected_feed();
+        assert!(feed.watch_request().exchanges.is_empty());
+        assert!(feed.toggle_exchange(0));
+        assert_eq!(feed.watch_request().exchanges, ["BINANCE"]);
+        // The last exchange shown can't be hidden.
+        assert!(!feed.toggle_exchange(1));
+        assert!(!feed.toggle_exchange(2));
+
+        // Reconnecting keeps Bitstamp hidden.
+        feed.disconnected("stream ended".to_string());
+        feed.connected(
+            vec!["BTCUSDT".to_string()],
+            vec!["BITSTAMP".to_string(), "BINANCE".to_string()],
+        );
+        assert_eq!(feed.watch_request().exchanges, ["BINANCE"]);
+        assert_eq!(feed.disconnected_reason(), None);
+    }
+
+    #[test]
+    fn should_clamp_levels_and_scrolling() {
+        let mut feed = Feed::new(Some("ETHBTC".to_string()), MAX_LEVELS);
+        feed.more_levels();
+        assert_eq!(feed.watch_request().levels, MAX_LEVELS);
+        feed.scroll(100);
+        assert_eq!(feed.offset(), MAX_LEVELS as i32);
+        feed.recenter();
+        assert_eq!(feed.offset(), 0);
+
+        feed.connected(vec!["BTCUSDT".to_string()], Vec::new());
+        assert_eq!(feed.symbol(), (Some("ETHBTC"), false));
+    }
+
+    #[test]
+    fn should_track_update_rate_and_staleness() {
+        let mut feed = connected_feed();
+        let start = Instant::now();
+        let summary = Summary {
+            bids: vec![Level {
+                exchange: "BINANCE".to_string(),
+                price: 30000.0,
+                quantity: 1.0,
+            }],
+            ..Default::default()
+        };
+        for ms in [0, 500, 1200, 1300] {
+            feed.received(&summary, start + Duration::from_millis(ms));
+        }
+        let now = start + Duration::from_millis(1400);
+        assert_eq!(feed.update_rate(now), 3);
+        assert_eq!(
+            feed.staleness("BINANCE", now),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(feed.staleness("BITSTAMP", now), None);
+    }
+}
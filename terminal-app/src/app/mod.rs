@@ -1,14 +1,20 @@
 use anyhow::Result;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
+
+use std::time::Instant;
+
+use tokio::sync::watch;
 
 use self::actions::Actions;
+use self::feed::Feed;
 use self::state::AppState;
 use crate::app::actions::Action;
 use crate::inputs::key::Key;
 use crate::io::IoEvent;
-use orderbook_agg::book_summary::Summary;
+use orderbook_agg::book_summary::{Summary, WatchSummaryRequest};
 
 pub mod actions;
+pub mod feed;
 pub mod state;
 pub mod ui;
 
@@ -27,20 +33,30 @@ pub struct App {
     /// State
     is_loading: bool,
     state: AppState,
+    feed: Feed,
+    /// The summaries asked of the server, resubscribed to each time it changes
+    tx_request: watch::Sender<WatchSummaryRequest>,
 }
 
 impl App {
-    pub fn new(io_tx: tokio::sync::mpsc::Sender<IoEvent>) -> Self {
+    pub fn new(
+        io_tx: tokio::sync::mpsc::Sender<IoEvent>,
+        feed: Feed,
+    ) -> (Self, watch::Receiver<WatchSummaryRequest>) {
         let actions = vec![Action::Quit].into();
         let is_loading = false;
         let state = AppState::default();
+        let (tx_request, rx_request) = watch::channel(feed.watch_request());
 
-        Self {
+        let app = Self {
             io_tx,
             actions,
             is_loading,
             state,
-        }
+            feed,
+            tx_request,
+        };
+        (app, rx_request)
     }
 
     /// Handle a user action
@@ -66,6 +82,36 @@ impl App {
                     self.state.decrement_delay();
                     AppReturn::Continue
                 }
+                Action::MoreLevels => {
+                    self.feed.more_levels();
+                    self.resubscribe();
+                    AppReturn::Continue
+                }
+                Action::FewerLevels => {
+                    self.feed.fewer_levels();
+                    self.resubscribe();
+                    AppReturn::Continue
+                }
+                Action::ScrollUp => {
+                    self.feed.scroll(1);
+                    AppReturn::Continue
+                }
+                Action::ScrollDown => {
+                    self.feed.scroll(-1);
+                    AppReturn::Continue
+                }
+                Action::Recenter => {
+                    self.feed.recenter();
+                    AppReturn::Continue
+                }
+                Action::ToggleExchange(index) => {
+                    if self.feed.toggle_exchange(*index) {
+                        self.resubscribe();
+                    } else {
+                        warn!("Can't toggle exchange {}", index + 1);
+                    }
+                    AppReturn::Continue
+                }
             }
         } else {
             warn!("No action accociated to {}", key);
@@ -108,6 +154,14 @@ impl App {
             Action::Sleep,
             Action::IncrementDelay,
             Action::DecrementDelay,
+            Action::MoreLevels,
+            Action::FewerLevels,
+            Action::ScrollUp,
+            Action::ScrollDown,
+            Action::Recenter,
+            Action::ToggleExchange(0),
+            Action::ToggleExchange(1),
+            Action::ToggleExchange(2),
         ]
         .into();
         self.state = AppState::initialized().await?;
@@ -122,8 +176,35 @@ impl App {
         self.state.incr_sleep();
     }
 
+    pub fn feed(&self) -> &Feed {
+        &self.feed
+    }
+
     pub async fn update_summary(&mut self, summary: Summary) -> AppReturn {
+        self.feed.received(&summary, Instant::now());
         self.state.update_summary(summary);
         AppReturn::Continue
     }
+
+    pub fn connected(&mut self, symbols: Vec<String>, exchanges: Vec<String>) -> AppReturn {
+        info!("Connected, exchanges: {}", exchanges.join(", "));
+        self.feed.connected(symbols, exchanges);
+        // Exchanges hidden before a reconnect might not be served any more.
+        self.resubscribe();
+        AppReturn::Continue
+    }
+
+    pub fn disconnected(&mut self, reason: String) -> AppReturn {
+        self.feed.disconnected(reason);
+        AppReturn::Continue
+    }
+
+    fn resubscribe(&self) {
+        let request = self.feed.watch_request();
+        self.tx_request.send_if_modified(|current| {
+            let changed = *current != request;
+            *current = request;
+            changed
+        });
+    }
 }
@@ -3,14 +3,15 @@ use std::time::Duration;
 
 use orderbook_agg::book_summary::Summary;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub enum AppState {
+    #[default]
     Init,
     Initialized {
         duration: Duration,
         counter_sleep: u32,
         counter_tick: u64,
-        summary: Summary,
+        summary: Box<Summary>,
         datapoints_bid: Vec<(f64, f64)>,
         datapoints_ask: Vec<(f64, f64)>,
         datapoints_spread: Vec<(f64, f64)>,
@@ -22,7 +23,7 @@ impl AppState {
         let duration = Duration::from_secs(1);
         let counter_sleep = 0;
         let counter_tick = 0;
-        let summary = Summary::default();
+        let summary = Box::default();
         let datapoints_bid = Vec::new();
         let datapoints_ask = Vec::new();
         let datapoints_spread = Vec::new();
@@ -72,7 +73,7 @@ impl AppState {
 
     pub fn get_summary(&self) -> Option<&Summary> {
         if let Self::Initialized { summary, .. } = self {
-            Some(&summary)
+            Some(summary.as_ref())
         } else {
             None
         }
@@ -125,17 +126,15 @@ impl AppState {
             ..
         } = self
         {
-            datapoints_bid.push((summary_new.timestamp as f64, summary_new.bids[0].price));
-            datapoints_ask.push((summary_new.timestamp as f64, summary_new.asks[0].price));
-            datapoints_spread.push((summary_new.timestamp as f64, summary_new.spread));
-
-            *summary = summary_new;
+            // Summaries filtered to exchanges that haven't sent levels yet are empty.
+            if let (Some(bid), Some(ask)) = (summary_new.bids.first(), summary_new.asks.first()) {
+                let timestamp = summary_new.timestamp as f64;
+                datapoints_bid.push((timestamp, bid.price));
+                datapoints_ask.push((timestamp, ask.price));
+                datapoints_spread.push((timestamp, summary_new.spread));
+            }
+
+            **summary = summary_new;
         }
     }
 }
-
-impl Default for AppState {
-    fn default() -> Self {
-        Self::Init
-    }
-}
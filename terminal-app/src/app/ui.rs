@@ -1,5 +1,6 @@
 use chrono::{prelude::DateTime, Utc};
-use std::time::{Duration, UNIX_EPOCH};
+use orderbook_agg::book_summary::Level;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use symbols::line;
 use tui::backend::Backend;
 use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
@@ -13,10 +14,11 @@ use tui::{symbols, Frame};
 use tui_logger::TuiLoggerWidget;
 
 use super::actions::Actions;
+use super::feed::Feed;
 use super::state::AppState;
 use crate::app::App;
 
-pub fn draw<B>(rect: &mut Frame<B>, app: &App, symbol: &String, decimals: u32)
+pub fn draw<B>(rect: &mut Frame<B>, app: &App, decimals: u32)
 where
     B: Backend,
 {
@@ -30,6 +32,7 @@ where
             [
                 Constraint::Length(3),
                 Constraint::Min(10),
+                Constraint::Length(3),
                 Constraint::Length(12),
             ]
             .as_ref(),
@@ -37,7 +40,7 @@ where
         .split(size);
 
     // Title
-    let title = draw_title(symbol.clone());
+    let title = draw_title(app.feed());
     rect.render_widget(title, chunks[0]);
 
     // Body & Help
@@ -54,11 +57,13 @@ where
         )
         .split(chunks[1]);
 
-    let summary = draw_summary(app.state(), decimals);
+    // The ladder's borders and header take 3 rows.
+    let ladder_rows = body_chunks[0].height.saturating_sub(3) as usize;
+    let summary = draw_summary(app.state(), app.feed(), decimals, ladder_rows);
     rect.render_widget(summary, body_chunks[0]);
 
     if let Some(datapoints) = app.state.get_datapoints() {
-        if datapoints.into_iter().all(|d| d.len() > 0) {
+        if datapoints.into_iter().all(|d| !d.is_empty()) {
             let chart = draw_chart(datapoints, decimals);
             rect.render_widget(chart, body_chunks[1]);
         }
@@ -76,13 +81,21 @@ where
     //     rect.render_widget(duration_block, chunks[2]);
     // }
 
+    let status = draw_status(app.feed(), Instant::now());
+    rect.render_widget(status, chunks[2]);
+
     // Logs
     let logs = draw_logs();
-    rect.render_widget(logs, chunks[2]);
+    rect.render_widget(logs, chunks[3]);
 }
 
-fn draw_title<'a>(symbol: String) -> Paragraph<'a> {
-    Paragraph::new(format!("Orderbook Summary: {}", symbol))
+fn draw_title<'a>(feed: &Feed) -> Paragraph<'a> {
+    let title = match feed.symbol() {
+        (Some(symbol), true) => format!("Orderbook Summary: {}", symbol),
+        (Some(symbol), false) => format!("Orderbook Summary: {} (not served)", symbol),
+        (None, _) => "Orderbook Summary".to_string(),
+    };
+    Paragraph::new(title)
         .style(Style::default().fg(Color::White))
         .alignment(Alignment::Center)
         .block(
@@ -97,8 +110,8 @@ fn check_size(rect: &Rect) {
     if rect.width < 52 {
         panic!("Require width >= 52, (got {})", rect.width);
     }
-    if rect.height < 28 {
-        panic!("Require height >= 28, (got {})", rect.height);
+    if rect.height < 31 {
+        panic!("Require height >= 31, (got {})", rect.height);
     }
 }
 
@@ -147,17 +160,17 @@ fn get_date_str_format(timestamp: f64) -> String {
 fn draw_chart<'a>(datapoints: [&'a Vec<(f64, f64)>; 3], decimals: u32) -> Chart<'a> {
     let max_min_decimals = 8;
     let x_min = datapoints[0]
-        .into_iter()
+        .iter()
         .min_by(|x, y| (x.0 as u32).cmp(&(y.0 as u32)))
         .unwrap()
         .0;
     let x_max = datapoints[0]
-        .into_iter()
+        .iter()
         .max_by(|x, y| (x.0 as u32).cmp(&(y.0 as u32)))
         .unwrap()
         .0;
     let y_min = datapoints[2]
-        .into_iter()
+        .iter()
         .min_by(|x, y| {
             ((x.1 * 10u32.pow(max_min_decimals) as f64) as i32)
                 .cmp(&((y.1 * 10u32.pow(max_min_decimals) as f64) as i32))
@@ -165,7 +178,7 @@ fn draw_chart<'a>(datapoints: [&'a Vec<(f64, f64)>; 3], decimals: u32) -> Chart<
         .unwrap()
         .1;
     let y_max = datapoints[2]
-        .into_iter()
+        .iter()
         .max_by(|x, y| {
             ((x.1 * 10u32.pow(max_min_decimals) as f64) as i32)
                 .cmp(&((y.1 * 10u32.pow(max_min_decimals) as f64) as i32))
@@ -239,7 +252,7 @@ fn draw_chart<'a>(datapoints: [&'a Vec<(f64, f64)>; 3], decimals: u32) -> Chart<
 }
 
 #[allow(dead_code)]
-fn draw_duration(duration: &Duration) -> LineGauge {
+fn draw_duration(duration: &Duration) -> LineGauge<'_> {
     let sec = duration.as_secs();
     let label = format!("{}s", sec);
     let ratio = sec as f64 / 10.0;
@@ -260,62 +273,80 @@ fn draw_duration(duration: &Duration) -> LineGauge {
         .ratio(ratio)
 }
 
-fn draw_summary(state: &AppState, decimals: u32) -> Table {
+/// Text colour for each exchange's levels
+fn exchange_color(exchange: &str) -> Color {
+    match exchange {
+        "BINANCE" => Color::Yellow,
+        "BITSTAMP" => Color::LightBlue,
+        "BINANCE_FUTURES" => Color::LightMagenta,
+        _ => Color::White,
+    }
+}
+
+/// The depth ladder, asks above bids with the spread and mid between them. The `rows`
+/// shown are centred on the spread, scrolled by the feed's offset.
+fn draw_summary<'a>(state: &'a AppState, feed: &Feed, decimals: u32, rows: usize) -> Table<'a> {
     let help_style = Style::default().fg(Color::Gray);
+    let decimals = decimals as usize;
 
-    let mut rows = vec![];
-    rows.push(Row::new(vec![
-        Cell::from(Span::styled("Ask/Bid".to_string(), help_style)),
-        Cell::from(Span::styled(format!("{:>10}", "Quantity"), help_style)),
-        Cell::from(Span::styled("Exchange".to_string(), help_style)),
-    ]));
+    let level_row = |level: &'a Level, side_color: Color| {
+        let exchange_style = Style::default().fg(exchange_color(&level.exchange));
+        Row::new(vec![
+            Cell::from(Span::styled(
+                format!("{:>8.1$}", level.price, decimals),
+                Style::default().fg(side_color),
+            )),
+            Cell::from(Span::styled(
+                format!("{:>10.5}", level.quantity),
+                exchange_style,
+            )),
+            Cell::from(Span::styled(level.exchange.as_str(), exchange_style)),
+        ])
+    };
+
+    let mut ladder = vec![];
+    let mut center = 0;
     if let Some(summary) = state.get_summary() {
-        for level in summary.asks.iter().rev() {
-            let row = Row::new(vec![
-                Cell::from(Span::styled(
-                    format!("{:>8.1$}", level.price, decimals as usize),
-                    Style::default().fg(Color::LightRed),
-                )),
-                Cell::from(Span::styled(
-                    format!("{:>10.5}", level.quantity),
-                    help_style,
-                )),
-                Cell::from(Span::styled(&level.exchange, help_style)),
-            ]);
-            rows.push(row);
-        }
-        rows.push(Row::new(vec![Span::styled("".to_string(), help_style); 3]));
-        rows.push(Row::new(vec![
+        ladder.extend(
+            summary
+                .asks
+                .iter()
+                .rev()
+                .map(|l| level_row(l, Color::LightRed)),
+        );
+        center = ladder.len();
+        let mid = match (summary.bids.first(), summary.asks.first()) {
+            (Some(bid), Some(ask)) => {
+                format!("mid {:.1$}", (bid.price + ask.price) / 2.0, decimals)
+            }
+            _ => String::new(),
+        };
+        ladder.push(Row::new(vec![
             Cell::from(Span::styled(
-                format!("{:>8.1$}", summary.spread, decimals as usize),
+                format!("{:>8.1$}", summary.spread, decimals),
                 Style::default().fg(Color::LightYellow),
             )),
-            Cell::from(Span::styled("".to_string(), help_style)),
-            Cell::from(Span::styled("".to_string(), help_style)),
+            Cell::from(Span::styled(mid, Style::default().fg(Color::LightYellow))),
+            Cell::from(Span::styled("spread", help_style)),
         ]));
-        rows.push(Row::new(vec![Span::styled("".to_string(), help_style); 3]));
-        for level in summary.bids.iter() {
-            let row = Row::new(vec![
-                Cell::from(Span::styled(
-                    format!("{:>8.1$}", level.price, decimals as usize),
-                    Style::default().fg(Color::LightGreen),
-                )),
-                Cell::from(Span::styled(
-                    format!("{:>10.5}", level.quantity),
-                    help_style,
-                )),
-                Cell::from(Span::styled(&level.exchange, help_style)),
-            ]);
-            rows.push(row);
-        }
+        ladder.extend(summary.bids.iter().map(|l| level_row(l, Color::LightGreen)));
     };
+    // Scrolling up shows more asks, so the window starts higher up the ladder.
+    let start = (center as i64 - rows as i64 / 2 - feed.offset() as i64)
+        .clamp(0, ladder.len().saturating_sub(rows) as i64) as usize;
 
-    Table::new(rows)
+    let header = Row::new(vec![
+        Cell::from(Span::styled("Ask/Bid".to_string(), help_style)),
+        Cell::from(Span::styled(format!("{:>10}", "Quantity"), help_style)),
+        Cell::from(Span::styled("Exchange".to_string(), help_style)),
+    ]);
+    Table::new(ladder.into_iter().skip(start).take(rows))
+        .header(header)
         .block(
             Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Plain)
-                .title("Orderbook Summary"),
+                .title(format!("Orderbook Summary ({} levels)", feed.levels())),
         )
         .widths(&[
             Constraint::Ratio(1, 3),
@@ -325,12 +356,49 @@ fn draw_summary(state: &AppState, decimals: u32) -> Table {
         .column_spacing(1)
 }
 
-fn draw_help(actions: &Actions) -> Table {
+/// Summaries a second, each exchange's time since it last had levels in a summary with
+/// its toggle key, or why the stream is reconnecting.
+fn draw_status<'a>(feed: &Feed, now: Instant) -> Paragraph<'a> {
+    let mut spans = vec![];
+    match feed.disconnected_reason() {
+        Some(reason) => spans.push(Span::styled(
+            format!("reconnecting: {}", reason),
+            Style::default().fg(Color::Red),
+        )),
+        None => spans.push(Span::styled(
+            format!("{} updates/s", feed.update_rate(now)),
+            Style::default().fg(Color::LightCyan),
+        )),
+    }
+    for (i, (exchange, shown)) in feed.exchanges().iter().enumerate() {
+        let staleness = match feed.staleness(exchange, now) {
+            Some(staleness) => format!("{:.1}s", staleness.as_secs_f64()),
+            None => "-".to_string(),
+        };
+        let style = if *shown {
+            Style::default().fg(exchange_color(exchange))
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        spans.push(Span::raw(" | "));
+        spans.push(Span::styled(
+            format!("{} {} {}", i + 1, exchange, staleness),
+            style,
+        ));
+    }
+    Paragraph::new(Spans::from(spans)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain)
+            .title("Status"),
+    )
+}
+
+fn draw_help(actions: &Actions) -> Table<'_> {
     let key_style = Style::default().fg(Color::LightCyan);
     let help_style = Style::default().fg(Color::Gray);
 
     let mut rows = vec![];
-    let mut counter = 0;
     for action in actions.actions().iter() {
         let mut first = true;
         for key in action.keys() {
@@ -344,10 +412,7 @@ fn draw_help(actions: &Actions) -> Table {
                 Cell::from(Span::styled(key.to_string(), key_style)),
                 Cell::from(Span::styled(help, help_style)),
             ]);
-            if counter < 2 {
-                rows.push(row);
-            }
-            counter += 1;
+            rows.push(row);
         }
     }
 
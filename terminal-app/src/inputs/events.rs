@@ -2,9 +2,12 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use log::error;
+use anyhow::{bail, Result};
+use log::{error, warn};
 use orderbook_agg::book_summary::orderbook_aggregator_client::OrderbookAggregatorClient;
-use orderbook_agg::book_summary::{Empty, SummaryRequest};
+use orderbook_agg::book_summary::{Empty, WatchSummaryRequest};
+use orderbook_agg::service::Backoff;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::StreamExt;
 
 use super::key::Key;
@@ -21,11 +24,13 @@ pub struct Events {
 }
 
 impl Events {
-    /// Constructs an new instance of `Events` with the default config.
+    /// Constructs an new instance of `Events` with the default config. Summaries are
+    /// streamed from the server at `endpoint` as asked for on `rx_request`, reconnecting
+    /// with a backoff whenever the stream ends.
     pub fn new(
         tick_rate: Duration,
-        mut client: OrderbookAggregatorClient<tonic::transport::Channel>,
-        _: SummaryRequest,
+        endpoint: String,
+        mut rx_request: watch::Receiver<WatchSummaryRequest>,
     ) -> Events {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         let stop_capture = Arc::new(AtomicBool::new(false));
@@ -35,24 +40,31 @@ impl Events {
         let event_stop_capture = stop_capture.clone();
 
         tokio::spawn(async move {
-            let request = tonic::Request::new(Empty {});
-            let mut stream = client.watch_summary(request).await.unwrap().into_inner();
+            let backoff = Backoff::default();
+            let mut delay = backoff.initial;
             loop {
-                if let Some(summary) = stream.next().await {
-                    match summary {
-                        Ok(summary) => {
-                            if let Err(err) = client_tx.send(InputEvent::Update(summary)).await {
-                                error!("Oops!, {}", err);
-                            }
-                        }
-                        Err(err) => {
-                            error!("Oops!, {}", err);
-                        }
-                    };
-                }
-                if let Err(err) = client_tx.send(InputEvent::Tick).await {
-                    error!("Oops!, {}", err);
+                let result = stream_summaries(&endpoint, &mut rx_request, &client_tx, || {
+                    delay = backoff.initial;
+                })
+                .await;
+                // Returns without an error once the app has closed.
+                let Err(err) = result else {
+                    break;
+                };
+                let reason = format!("{:#}", err);
+                warn!(
+                    "Summary stream ended: {}, reconnecting in {:?}",
+                    reason, delay
+                );
+                if client_tx
+                    .send(InputEvent::Disconnected(reason))
+                    .await
+                    .is_err()
+                {
+                    break;
                 }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(backoff.max);
             }
         });
 
@@ -91,3 +103,48 @@ impl Events {
         self.stop_capture.store(true, Ordering::Relaxed)
     }
 }
+
+/// Connects to the server and streams summaries to `tx`, subscribing again each time the
+/// request changes. Calls `connected` once the server has answered. Returns once the app
+/// has gone away, and errors when the stream ends.
+async fn stream_summaries(
+    endpoint: &str,
+    rx_request: &mut watch::Receiver<WatchSummaryRequest>,
+    tx: &mpsc::Sender<InputEvent>,
+    connected: impl FnOnce(),
+) -> Result<()> {
+    let mut client = OrderbookAggregatorClient::connect(endpoint.to_string()).await?;
+    let symbols = client.get_symbols(Empty {}).await?.into_inner().symbols;
+    let exchanges = client.get_exchanges(Empty {}).await?.into_inner().exchanges;
+    if tx
+        .send(InputEvent::Connected { symbols, exchanges })
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+    connected();
+    loop {
+        let request = rx_request.borrow_and_update().clone();
+        let mut stream = client.watch_summary(request).await?.into_inner();
+        loop {
+            tokio::select! {
+                changed = rx_request.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    break;
+                }
+                summary = stream.next() => match summary {
+                    Some(Ok(summary)) => {
+                        if tx.send(InputEvent::Update(Box::new(summary))).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Some(Err(status)) => bail!("{}", status.message()),
+                    None => bail!("server closed the stream"),
+                },
+            }
+        }
+    }
+}
@@ -9,5 +9,12 @@ pub enum InputEvent {
     Input(Key),
     /// An tick event occurred.
     Tick,
-    Update(Summary),
+    Update(Box<Summary>),
+    /// Connected to the server, which serves these symbols and exchanges
+    Connected {
+        symbols: Vec<String>,
+        exchanges: Vec<String>,
+    },
+    /// The summary stream ended, it's reconnected to after a backoff
+    Disconnected(String),
 }
@@ -23,7 +23,7 @@ impl IoAsyncHandler {
         let result = match io_event {
             IoEvent::Initialize => self.do_initialize().await,
             IoEvent::Sleep(duration) => self.do_sleep(duration).await,
-            IoEvent::Update(summary) => self.do_update(summary).await,
+            IoEvent::Update(summary) => self.do_update(*summary).await,
         };
 
         if let Err(err) = result {
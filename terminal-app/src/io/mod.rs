@@ -6,7 +6,7 @@ pub mod handler;
 // For this dummy application we only need two IO event
 #[derive(Debug, Clone)]
 pub enum IoEvent {
-    Initialize,      // Launch to initialize the application
-    Sleep(Duration), // Just take a little break
-    Update(Summary), // Update the summary
+    Initialize,           // Launch to initialize the application
+    Sleep(Duration),      // Just take a little break
+    Update(Box<Summary>), // Update the summary
 }
@@ -7,8 +7,8 @@ use app::{App, AppReturn};
 use inputs::events::Events;
 use inputs::InputEvent;
 use io::IoEvent;
-use orderbook_agg::book_summary::orderbook_aggregator_client::OrderbookAggregatorClient;
-use orderbook_agg::book_summary::SummaryRequest;
+use orderbook_agg::book_summary::WatchSummaryRequest;
+use tokio::sync::watch;
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 
@@ -18,9 +18,13 @@ pub mod app;
 pub mod inputs;
 pub mod io;
 
+/// Runs the app until it's quit, streaming summaries from the server at `endpoint` as asked
+/// for on `rx_request` and showing prices with `decimals` places.
 pub async fn start_ui(
     app: &Arc<tokio::sync::Mutex<App>>,
-    summary_request: SummaryRequest,
+    endpoint: String,
+    rx_request: watch::Receiver<WatchSummaryRequest>,
+    decimals: u32,
 ) -> Result<()> {
     // Configure Crossterm backend for tui
     let stdout = stdout();
@@ -32,11 +36,7 @@ pub async fn start_ui(
 
     // User event handler
     let tick_rate = Duration::from_millis(100);
-    let client: OrderbookAggregatorClient<tonic::transport::Channel> =
-        OrderbookAggregatorClient::connect("http://127.0.0.1:9001").await?;
-    // let decimals = summary_request.decimals;
-    // let symbol = summary_request.symbol.clone();
-    let mut events = Events::new(tick_rate, client, summary_request);
+    let mut events = Events::new(tick_rate, endpoint, rx_request);
 
     // Trigger state change from Init to Initialized
     {
@@ -49,13 +49,15 @@ pub async fn start_ui(
         let mut app = app.lock().await;
 
         // Render
-        terminal.draw(|rect| ui::draw(rect, &app, &"BTCUSDT".to_string(), 4))?;
+        terminal.draw(|rect| ui::draw(rect, &app, decimals))?;
 
         // Handle inputs
         let result = match events.next().await {
             InputEvent::Input(key) => app.do_action(key).await,
             InputEvent::Tick => app.update_on_tick().await,
-            InputEvent::Update(summary) => app.update_summary(summary).await,
+            InputEvent::Update(summary) => app.update_summary(*summary).await,
+            InputEvent::Connected { symbols, exchanges } => app.connected(symbols, exchanges),
+            InputEvent::Disconnected(reason) => app.disconnected(reason),
         };
         // Check if we should exit
         if result == AppReturn::Exit {
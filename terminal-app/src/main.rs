@@ -1,9 +1,9 @@
 use clap::Parser;
-use orderbook_agg::book_summary::SummaryRequest;
 use std::sync::Arc;
 
 use anyhow::Result;
 use log::LevelFilter;
+use terminal_app::app::feed::Feed;
 use terminal_app::app::App;
 use terminal_app::io::handler::IoAsyncHandler;
 use terminal_app::io::IoEvent;
@@ -11,10 +11,15 @@ use terminal_app::start_ui;
 
 #[derive(Debug, Parser)]
 struct SummaryOptions {
+    /// Server to stream summaries from
+    #[clap(long, default_value = "http://127.0.0.1:9001")]
+    endpoint: String,
+    /// Symbol to show, the first the server serves when not set
     #[clap(long)]
-    symbol: String,
-    #[clap(long)]
-    levels: Option<u32>,
+    symbol: Option<String>,
+    /// Levels of each side to show, changed with < and >
+    #[clap(long, default_value_t = 10)]
+    levels: u32,
     #[clap(long)]
     price_range: Option<f64>,
     #[clap(long)]
@@ -23,10 +28,13 @@ struct SummaryOptions {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let opts = SummaryOptions::parse();
     let (sync_io_tx, mut sync_io_rx) = tokio::sync::mpsc::channel::<IoEvent>(100);
 
     // We need to share the App between thread
-    let app = Arc::new(tokio::sync::Mutex::new(App::new(sync_io_tx.clone())));
+    let feed = Feed::new(opts.symbol.clone(), opts.levels);
+    let (app, rx_request) = App::new(sync_io_tx.clone(), feed);
+    let app = Arc::new(tokio::sync::Mutex::new(app));
     let app_ui = Arc::clone(&app);
 
     // Configure log
@@ -41,12 +49,8 @@ async fn main() -> Result<()> {
         }
     });
 
-    let opts = SummaryOptions::parse();
-    let summary_request = SummaryRequest {
-        symbol: opts.symbol,
-    };
-
-    start_ui(&app_ui, summary_request).await?;
+    let decimals = opts.decimals.unwrap_or(4);
+    start_ui(&app_ui, opts.endpoint, rx_request, decimals).await?;
 
     Ok(())
 }